@@ -255,6 +255,17 @@ fn main() {
 
     let o = PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
+    // Std bundle embedded in the binary, if any (see `std_bundle`)
+    println!("cargo:rerun-if-env-changed=EDGE_RUNTIME_STD_BUNDLE");
+    let std_bundle_path = o.join("STD_BUNDLE.eszip");
+    match env::var_os("EDGE_RUNTIME_STD_BUNDLE") {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", PathBuf::from(&path).display());
+            std::fs::copy(&path, &std_bundle_path).expect("failed to embed the std bundle");
+        }
+        None => std::fs::write(&std_bundle_path, []).unwrap(),
+    }
+
     // Main snapshot
     let runtime_snapshot_path = o.join("RUNTIME_SNAPSHOT.bin");
 
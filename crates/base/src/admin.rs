@@ -0,0 +1,685 @@
+use crate::connections::connection_metrics;
+use crate::load_shedding::concurrency_limiter;
+use crate::manifest_update::{diff_manifests, parse_manifest_update, ManifestDiff};
+use crate::preload::Preloads;
+use crate::rt_worker::events_supervisor::EventsMetrics;
+use crate::rt_worker::idempotency::idempotency_store;
+use crate::rt_worker::memory_pressure::memory_coordinator;
+use crate::rt_worker::worker_pool::apply_version;
+use crate::std_bundle::std_bundle;
+use crate::timeline::timelines;
+use crate::utils::send_event_if_event_worker_available;
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json;
+use deno_core::url::form_urlencoded;
+use event_worker::events::{
+    EventMetadata, ServiceStateChangedEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use hyper::header::HeaderValue;
+use hyper::{server::conn::Http, service::service_fn, Body, Method, Request, Response, StatusCode};
+use log::{error, info};
+use module_fetcher::cache::module_cache_metrics;
+use module_fetcher::registry_mirrors::registry_mirrors;
+use sb_core::problem::{Problem, RuntimeErrorCode, PROBLEM_CONTENT_TYPE};
+use sb_worker_context::essentials::{
+    CanaryVersion, CreateUserWorkerResult, ServiceVersion, ServiceVersions, UserWorkerMsgs,
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use sb_worker_context::manifest::{Manifest, SharedManifest};
+use sb_worker_context::paused_services::{
+    pause_service, paused_services, resume_service, ServicePause,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Clone)]
+pub struct AdminState {
+    pub manifest: Option<SharedManifest>,
+    pub manifest_path: Option<String>,
+    pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub events_metrics: EventsMetrics,
+    pub preloads: Preloads,
+    // for the events of services paused and resumed
+    pub events_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    json_response(status, serde_json::json!({ "msg": msg }).to_string())
+}
+
+// Re-reads the manifest from disk and retires running workers so that new
+// requests are served with the updated service definitions.
+fn reload_manifest(state: &AdminState) -> Result<(), Error> {
+    let (Some(manifest), Some(path)) = (&state.manifest, &state.manifest_path) else {
+        return Err(anyhow!("no manifest configured"));
+    };
+
+    let updated = Manifest::load(Path::new(path))?;
+    *manifest.write().unwrap() = updated;
+    state.worker_pool_tx.send(UserWorkerMsgs::RetireAll)?;
+    // boots the preloaded services again, the runtime stays ready meanwhile
+    Preloads::start(&manifest.read().unwrap(), state.worker_pool_tx.clone());
+
+    info!("reloaded manifest from {}", path);
+    Ok(())
+}
+
+// Replaces the manifest with the one in the body once it's validated, retiring
+// the workers of the services it changes or removes (the others keep serving).
+// With `?dryRun=true`, only reports how it differs from the current one. The
+// update lasts until the manifest is reloaded from disk.
+async fn update_manifest(state: &AdminState, req: Request<Body>) -> Result<Response<Body>, Error> {
+    let Some(manifest) = &state.manifest else {
+        return Ok(error_response(
+            StatusCode::NOT_FOUND,
+            "no manifest configured",
+        ));
+    };
+    let dry_run = query_params(&req)
+        .get("dryRun")
+        .is_some_and(|value| value == "true" || value == "1");
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let base_dir = state
+        .manifest_path
+        .as_ref()
+        .and_then(|path| Path::new(path).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let updated = match parse_manifest_update(&body, &base_dir) {
+        Ok(updated) => updated,
+        Err(errors) => {
+            let body = serde_json::json!({ "msg": "invalid manifest", "errors": errors });
+            return Ok(json_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                body.to_string(),
+            ));
+        }
+    };
+    let diff_response = |diff: &ManifestDiff, applied: bool| {
+        let body = serde_json::json!({ "dryRun": dry_run, "applied": applied, "diff": diff });
+        json_response(StatusCode::OK, body.to_string())
+    };
+    if dry_run {
+        let diff = diff_manifests(&manifest.read().unwrap(), &updated);
+        return Ok(diff_response(&diff, false));
+    }
+
+    // diffed and swapped under the same lock, so concurrent updates can't interleave
+    let (diff, retired) = {
+        let mut current = manifest.write().unwrap();
+        let diff = diff_manifests(&current, &updated);
+        let retired: Vec<String> = diff
+            .changed
+            .keys()
+            .chain(diff.removed.iter())
+            .filter_map(|name| current.get(name))
+            .map(|service| service.worker_options.service_path)
+            .collect();
+        if !diff.is_empty() {
+            *current = updated;
+        }
+        (diff, retired)
+    };
+    if diff.is_empty() {
+        return Ok(diff_response(&diff, false));
+    }
+    if !retired.is_empty() {
+        state
+            .worker_pool_tx
+            .send(UserWorkerMsgs::RetireServices(retired))?;
+    }
+    let manifest = manifest.read().unwrap();
+    let preloads_changed = diff.preload_changed
+        || manifest
+            .preload
+            .iter()
+            .any(|name| diff.added.contains(name) || diff.changed.contains_key(name));
+    if preloads_changed {
+        // the runtime stays ready meanwhile
+        Preloads::start(&manifest, state.worker_pool_tx.clone());
+    }
+
+    info!(
+        "updated manifest ({} added, {} changed, {} removed)",
+        diff.added.len(),
+        diff.changed.len(),
+        diff.removed.len()
+    );
+    Ok(diff_response(&diff, true))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct DeployRequest {
+    service_path: String,
+    entrypoint: Option<String>,
+    // eszip already present on the host
+    eszip_path: Option<String>,
+    import_map_path: Option<String>,
+    #[serde(default)]
+    env_vars: Vec<(String, String)>,
+    // path requested on the new version before switching to it, must return a 2xx
+    health_check_path: Option<String>,
+    // route this percentage of traffic to the new version instead of switching over
+    canary_weight: Option<u8>,
+    canary_max_error_rate: Option<f64>,
+    canary_min_requests: Option<u64>,
+}
+
+const DEFAULT_CANARY_MAX_ERROR_RATE: f64 = 0.05;
+const DEFAULT_CANARY_MIN_REQUESTS: u64 = 20;
+
+// Checked before the new version is staged
+fn validate_canary(deploy: &DeployRequest) -> Result<(), Error> {
+    if deploy.canary_weight.is_some_and(|weight| weight > 100) {
+        bail!("canaryWeight must be between 0 and 100");
+    }
+    if deploy
+        .canary_max_error_rate
+        .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+    {
+        bail!("canaryMaxErrorRate must be between 0 and 1");
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ServicePathRequest {
+    service_path: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PauseRequest {
+    service_path: String,
+    // 503 by default
+    status: Option<u16>,
+    // a `SERVICE_PAUSED` problem response by default
+    body: Option<String>,
+    content_type: Option<String>,
+    // eg: `abuse` or `billing`, only reported to operators
+    reason: Option<String>,
+}
+
+fn query_params(req: &Request<Body>) -> HashMap<String, String> {
+    form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn describe_version(version: &ServiceVersion) -> serde_json::Value {
+    serde_json::json!({
+        "id": version.id.to_string(),
+        "entrypoint": version.maybe_entrypoint,
+        "eszip": version.maybe_eszip.is_some(),
+        "requests": version.stats.requests.load(Ordering::Relaxed),
+        "errors": version.stats.errors.load(Ordering::Relaxed),
+        "errorRate": version.stats.error_rate(),
+    })
+}
+
+fn versions_json(service_path: &str, versions: &ServiceVersions) -> String {
+    serde_json::json!({
+        "servicePath": service_path,
+        "active": versions.active.as_ref().map(describe_version),
+        "previous": versions.previous.as_ref().map(describe_version),
+        "canary": versions.canary.as_ref().map(|canary| {
+            let mut value = describe_version(&canary.version);
+            value["weight"] = canary.weight.into();
+            value["maxErrorRate"] = canary.max_error_rate.into();
+            value["minRequests"] = canary.min_requests.into();
+            value
+        }),
+    })
+    .to_string()
+}
+
+// Accepts either a JSON `DeployRequest`, or an uploaded eszip
+// (`application/octet-stream`) with the remaining options given as query params.
+async fn read_deploy_request(
+    req: Request<Body>,
+) -> Result<(DeployRequest, Option<Vec<u8>>), Error> {
+    let is_upload = req
+        .headers()
+        .get("content-type")
+        .map(|v| v.as_bytes() == b"application/octet-stream")
+        .unwrap_or(false);
+
+    if is_upload {
+        let mut params = query_params(&req);
+        let deploy = DeployRequest {
+            service_path: params
+                .remove("servicePath")
+                .ok_or_else(|| anyhow!("servicePath is required"))?,
+            entrypoint: params.remove("entrypoint"),
+            import_map_path: params.remove("importMapPath"),
+            health_check_path: params.remove("healthCheckPath"),
+            canary_weight: params
+                .remove("canaryWeight")
+                .map(|v| v.parse())
+                .transpose()?,
+            canary_max_error_rate: params
+                .remove("canaryMaxErrorRate")
+                .map(|v| v.parse())
+                .transpose()?,
+            canary_min_requests: params
+                .remove("canaryMinRequests")
+                .map(|v| v.parse())
+                .transpose()?,
+            ..Default::default()
+        };
+        let eszip = hyper::body::to_bytes(req.into_body()).await?;
+        return Ok((deploy, Some(eszip.to_vec())));
+    }
+
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let deploy: DeployRequest = serde_json::from_slice(&body)?;
+    let eszip = match &deploy.eszip_path {
+        Some(path) => Some(tokio::fs::read(path).await?),
+        None => None,
+    };
+    Ok((deploy, eszip))
+}
+
+async fn health_check(state: &AdminState, key: uuid::Uuid, path: &str) -> Result<(), Error> {
+    let req = Request::builder()
+        .uri(format!("http://localhost{}", path))
+        .body(Body::empty())?;
+    let (res_tx, res_rx) = oneshot::channel();
+    state
+        .worker_pool_tx
+        .send(UserWorkerMsgs::SendRequest(key, req, res_tx))?;
+
+    let res = res_rx.await??;
+    if !res.status().is_success() {
+        bail!("health check returned {}", res.status());
+    }
+    Ok(())
+}
+
+// Boots the new version next to the current one, health-checks it and only then
+// switches routing over. The replaced version is kept for rollbacks.
+async fn deploy_version(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let (deploy, maybe_eszip) = read_deploy_request(req).await?;
+    if deploy.entrypoint.is_none() && maybe_eszip.is_none() {
+        bail!("either an entrypoint or an eszip is required");
+    }
+    validate_canary(&deploy)?;
+
+    let version = ServiceVersion {
+        id: uuid::Uuid::new_v4(),
+        maybe_entrypoint: deploy.entrypoint,
+        maybe_eszip: maybe_eszip.map(Arc::new),
+        import_map_path: deploy.import_map_path,
+        stats: Default::default(),
+    };
+
+    let mut worker_options = WorkerContextInitOpts {
+        service_path: PathBuf::from(&deploy.service_path),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: deploy.env_vars.into_iter().collect(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_module_code: None,
+        maybe_entrypoint: None,
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts::default()),
+    };
+    apply_version(&mut worker_options, &version);
+
+    let (tx, rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+    state
+        .worker_pool_tx
+        .send(UserWorkerMsgs::Stage(worker_options, tx))?;
+    let CreateUserWorkerResult { key } = rx.await??;
+
+    if let Some(path) = &deploy.health_check_path {
+        if let Err(err) = health_check(state, key, path).await {
+            state.worker_pool_tx.send(UserWorkerMsgs::Shutdown(key))?;
+            bail!("version {} failed health check: {}", version.id, err);
+        }
+    }
+
+    let id = version.id;
+    if let Some(weight) = deploy.canary_weight {
+        let canary = CanaryVersion {
+            version,
+            key,
+            weight,
+            max_error_rate: deploy
+                .canary_max_error_rate
+                .unwrap_or(DEFAULT_CANARY_MAX_ERROR_RATE),
+            min_requests: deploy
+                .canary_min_requests
+                .unwrap_or(DEFAULT_CANARY_MIN_REQUESTS),
+        };
+        state.worker_pool_tx.send(UserWorkerMsgs::StartCanary(
+            deploy.service_path.clone(),
+            canary,
+        ))?;
+
+        info!(
+            "started canary {} of {} ({}% of traffic)",
+            id, deploy.service_path, weight
+        );
+        return Ok(serde_json::json!({ "version": id.to_string() }).to_string());
+    }
+
+    state.worker_pool_tx.send(UserWorkerMsgs::Activate(
+        deploy.service_path.clone(),
+        version,
+        key,
+    ))?;
+
+    info!("activated version {} of {}", id, deploy.service_path);
+    Ok(serde_json::json!({ "version": id.to_string() }).to_string())
+}
+
+async fn rollback_version(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let rollback: ServicePathRequest = serde_json::from_slice(&body)?;
+
+    let (tx, rx) = oneshot::channel();
+    state
+        .worker_pool_tx
+        .send(UserWorkerMsgs::Rollback(rollback.service_path.clone(), tx))?;
+    let versions = rx.await??;
+
+    info!("rolled back {}", rollback.service_path);
+    Ok(versions_json(&rollback.service_path, &versions))
+}
+
+async fn promote_canary(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let promote: ServicePathRequest = serde_json::from_slice(&body)?;
+
+    let (tx, rx) = oneshot::channel();
+    state.worker_pool_tx.send(UserWorkerMsgs::PromoteCanary(
+        promote.service_path.clone(),
+        tx,
+    ))?;
+    let versions = rx.await??;
+
+    info!("promoted canary of {}", promote.service_path);
+    Ok(versions_json(&promote.service_path, &versions))
+}
+
+async fn get_versions(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let service_path = query_params(&req)
+        .remove("servicePath")
+        .ok_or_else(|| anyhow!("servicePath is required"))?;
+
+    let (tx, rx) = oneshot::channel();
+    state
+        .worker_pool_tx
+        .send(UserWorkerMsgs::GetVersions(service_path.clone(), tx))?;
+    let versions = rx.await?;
+
+    Ok(versions_json(&service_path, &versions))
+}
+
+fn service_state_changed(state: &AdminState, service_path: &str, event: ServiceStateChangedEvent) {
+    send_event_if_event_worker_available(
+        state.events_tx.clone(),
+        WorkerEvents::ServiceStateChanged(event),
+        EventMetadata {
+            service_path: Some(service_path.to_string()),
+            ..Default::default()
+        },
+    );
+}
+
+// Sheds the traffic of a service right away: its requests are answered with the
+// pause response, by its current workers as well, and no worker is booted for it
+// until it's resumed. Pausing a paused service replaces its response.
+async fn pause(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let pause: PauseRequest = serde_json::from_slice(&body)?;
+    let status = pause
+        .status
+        .unwrap_or(RuntimeErrorCode::ServicePaused.status());
+    // the response has a body
+    if !(200..=599).contains(&status) || matches!(status, 204 | 205 | 304) {
+        bail!("status must be between 200 and 599, and not 204, 205 or 304");
+    }
+    if let Some(content_type) = &pause.content_type {
+        if HeaderValue::from_str(content_type).is_err() {
+            bail!("contentType must be a valid header value");
+        }
+    }
+    let (content_type, body) = match pause.body {
+        Some(body) => (
+            pause
+                .content_type
+                .unwrap_or_else(|| "text/plain;charset=UTF-8".to_string()),
+            body,
+        ),
+        None => {
+            let mut problem = Problem::new(RuntimeErrorCode::ServicePaused, None);
+            problem.status = status;
+            (
+                PROBLEM_CONTENT_TYPE.to_string(),
+                serde_json::to_string(&problem)?,
+            )
+        }
+    };
+    let paused = ServicePause {
+        status,
+        content_type,
+        body,
+        reason: pause.reason,
+        paused_at_ms: now_ms(),
+    };
+
+    pause_service(&pause.service_path, paused.clone());
+    // not routed to anymore, workers holding requests answer with the pause response
+    let retired = vec![pause.service_path.clone()];
+    state
+        .worker_pool_tx
+        .send(UserWorkerMsgs::RetireServices(retired))?;
+    service_state_changed(
+        state,
+        &pause.service_path,
+        ServiceStateChangedEvent {
+            state: "paused".to_string(),
+            reason: paused.reason.clone(),
+            status: Some(status),
+        },
+    );
+
+    info!(
+        "paused {} ({})",
+        pause.service_path,
+        paused.reason.as_deref().unwrap_or("no reason given")
+    );
+    Ok(serde_json::json!({ "servicePath": pause.service_path, "pause": paused }).to_string())
+}
+
+async fn resume(state: &AdminState, req: Request<Body>) -> Result<String, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let resume: ServicePathRequest = serde_json::from_slice(&body)?;
+    let paused = resume_service(&resume.service_path)
+        .ok_or_else(|| anyhow!("{} is not paused", resume.service_path))?;
+    service_state_changed(
+        state,
+        &resume.service_path,
+        ServiceStateChangedEvent {
+            state: "resumed".to_string(),
+            reason: paused.reason.clone(),
+            status: None,
+        },
+    );
+
+    info!("resumed {}", resume.service_path);
+    Ok(serde_json::json!({ "servicePath": resume.service_path, "pause": paused }).to_string())
+}
+
+fn to_response(result: Result<String, Error>) -> Response<Body> {
+    match result {
+        Ok(body) => json_response(StatusCode::OK, body),
+        Err(err) => error_response(StatusCode::BAD_REQUEST, &err.to_string()),
+    }
+}
+
+async fn handle_admin_request(
+    state: AdminState,
+    req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/_admin/manifest") => match &state.manifest {
+            Some(manifest) => {
+                let body = serde_json::to_string(&*manifest.read().unwrap())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "no manifest configured"),
+        },
+        (&Method::PUT, "/_admin/manifest") => update_manifest(&state, req).await?,
+        (&Method::POST, "/_admin/manifest/reload") => match reload_manifest(&state) {
+            Ok(()) => json_response(StatusCode::OK, "{}".to_string()),
+            Err(err) => error_response(StatusCode::BAD_REQUEST, &err.to_string()),
+        },
+        (&Method::GET, "/_admin/events/metrics") => {
+            let body = serde_json::to_string(&state.events_metrics.snapshot())?;
+            json_response(StatusCode::OK, body)
+        }
+        (&Method::GET, "/_admin/module-cache/metrics") => {
+            let body = serde_json::to_string(&module_cache_metrics().snapshot())?;
+            json_response(StatusCode::OK, body)
+        }
+        (&Method::GET, "/_admin/ready") => {
+            let report = state.preloads.report();
+            let status = if report.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            json_response(status, serde_json::to_string(&report)?)
+        }
+        (&Method::GET, "/_admin/concurrency") => match concurrency_limiter() {
+            Some(limiter) => {
+                let body = serde_json::to_string(&limiter.snapshot())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "adaptive concurrency is not enabled"),
+        },
+        (&Method::GET, "/_admin/idempotency") => match idempotency_store() {
+            Some(store) => {
+                let body = serde_json::to_string(&store.snapshot())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "idempotency keys are not enabled"),
+        },
+        (&Method::GET, "/_admin/connections") => {
+            let body = serde_json::to_string(&connection_metrics().snapshot())?;
+            json_response(StatusCode::OK, body)
+        }
+        (&Method::GET, "/_admin/registries") => match registry_mirrors() {
+            Some(mirrors) => {
+                let body = serde_json::to_string(&mirrors.status())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "no registry mirrors are configured"),
+        },
+        (&Method::GET, "/_admin/std-bundle") => match std_bundle() {
+            Some(bundle) => {
+                let body = serde_json::to_string(&bundle.snapshot())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "the std bundle is not enabled"),
+        },
+        (&Method::GET, "/_admin/memory") => match memory_coordinator() {
+            Some(coordinator) => {
+                let body = serde_json::to_string(&coordinator.snapshot())?;
+                json_response(StatusCode::OK, body)
+            }
+            None => error_response(
+                StatusCode::NOT_FOUND,
+                "the memory coordinator is not enabled",
+            ),
+        },
+        (&Method::GET, "/_admin/timelines") => match timelines() {
+            Some(timelines) => json_response(StatusCode::OK, timelines.to_chrome_trace()),
+            None => error_response(StatusCode::NOT_FOUND, "timelines are not enabled"),
+        },
+        (&Method::GET, "/_admin/services/versions") => to_response(get_versions(&state, req).await),
+        (&Method::POST, "/_admin/services/versions") => {
+            to_response(deploy_version(&state, req).await)
+        }
+        (&Method::POST, "/_admin/services/rollback") => {
+            to_response(rollback_version(&state, req).await)
+        }
+        (&Method::POST, "/_admin/services/canary/promote") => {
+            to_response(promote_canary(&state, req).await)
+        }
+        (&Method::GET, "/_admin/services/paused") => {
+            let body = serde_json::to_string(&paused_services())?;
+            json_response(StatusCode::OK, body)
+        }
+        (&Method::POST, "/_admin/services/pause") => to_response(pause(&state, req).await),
+        (&Method::POST, "/_admin/services/resume") => to_response(resume(&state, req).await),
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    Ok(res)
+}
+
+pub async fn serve_admin(listener: TcpListener, state: AdminState) {
+    loop {
+        match listener.accept().await {
+            Ok((conn, _)) => {
+                let state = state.clone();
+                tokio::task::spawn(async move {
+                    let service = service_fn(move |req| handle_admin_request(state.clone(), req));
+                    if let Err(e) = Http::new().serve_connection(conn, service).await {
+                        error!("admin connection error ({:?})", e);
+                    }
+                });
+            }
+            Err(e) => error!("admin socket error: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate_canary, DeployRequest};
+
+    #[test]
+    fn test_validate_canary() {
+        let deploy = |weight, rate| DeployRequest {
+            service_path: "./functions/hello".to_string(),
+            canary_weight: Some(weight),
+            canary_max_error_rate: rate,
+            ..Default::default()
+        };
+        assert!(validate_canary(&deploy(5, None)).is_ok());
+        assert!(validate_canary(&deploy(5, Some(0.1))).is_ok());
+        assert!(validate_canary(&deploy(101, None)).is_err());
+        for rate in [-0.1, 1.5, f64::NAN, f64::INFINITY] {
+            assert!(validate_canary(&deploy(5, Some(rate))).is_err());
+        }
+    }
+}
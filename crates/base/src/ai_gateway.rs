@@ -0,0 +1,520 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::{FutureExt, StreamExt};
+use deno_core::serde_json::{self, Value};
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use sb_worker_context::ai::{
+    AiEndpoint, AiError, AiGateway, AiRequest, AiResponse, AiUsage, SharedAiGateway,
+};
+use sb_worker_context::service_scope::load_service_config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+// `EdgeRuntime.ai`: requests to OpenAI-compatible providers are sent by the
+// runtime, with the provider's API key injected, and streamed back to the
+// isolate. The tokens providers report are counted against per-service quotas.
+// Requests run on the server's runtime, so they outlive the worker's runtime.
+
+static AI_GATEWAY: OnceLock<SharedAiGateway> = OnceLock::new();
+
+// services without a config of their own use this one, if set
+const DEFAULT_SERVICE: &str = "*";
+const DEFAULT_WINDOW_SECS: u64 = 24 * 60 * 60;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// non-streamed responses are buffered up to this size to read their usage
+const MAX_BUFFERED_RESPONSE: usize = 16 * 1024 * 1024;
+const RESPONSE_CHUNKS_BUFFERED: usize = 16;
+
+pub fn set_ai_gateway(gateway: SharedAiGateway) {
+    if AI_GATEWAY.set(gateway).is_err() {
+        warn!("ai gateway is already set");
+    }
+}
+
+pub fn ai_gateway() -> Option<SharedAiGateway> {
+    AI_GATEWAY.get().cloned()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AiProviderConfig {
+    // eg: `https://api.openai.com/v1`
+    pub base_url: String,
+    // env var holding the API key, sent as a bearer token
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    // sent with every request (eg: an organization header)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AiServiceConfig {
+    // providers the service may use, the first one by default
+    pub providers: Vec<String>,
+    #[serde(default)]
+    pub tokens_per_window: Option<u64>,
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+}
+
+// eg: `{ "providers": { "openai": { "baseUrl": "https://api.openai.com/v1", "apiKeyEnv": "OPENAI_API_KEY" } }, "services": { "*": { "providers": ["openai"], "tokensPerWindow": 100000 } } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AiConfig {
+    pub providers: HashMap<String, AiProviderConfig>,
+    pub services: HashMap<String, AiServiceConfig>,
+}
+
+struct AiProvider {
+    base_url: String,
+    headers: HeaderMap,
+}
+
+impl AiProvider {
+    fn new(config: AiProviderConfig) -> Result<Self, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            headers.insert(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        }
+        if let Some(var) = &config.api_key_env {
+            let key = std::env::var(var)
+                .with_context(|| format!("failed to read the API key from ${}", var))?;
+            let mut value = HeaderValue::try_from(format!("Bearer {}", key))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            headers,
+        })
+    }
+}
+
+struct QuotaWindow {
+    started_at_ms: u64,
+    tokens: u64,
+}
+
+// Tokens used per service, in fixed windows
+#[derive(Default)]
+struct TokenQuotas {
+    windows: Mutex<HashMap<String, QuotaWindow>>,
+}
+
+impl TokenQuotas {
+    fn with_window<T>(
+        &self,
+        service: &str,
+        window_ms: u64,
+        now_ms: u64,
+        f: impl FnOnce(&mut QuotaWindow) -> T,
+    ) -> T {
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(service.to_string()).or_insert(QuotaWindow {
+            started_at_ms: now_ms,
+            tokens: 0,
+        });
+        if now_ms >= window.started_at_ms + window_ms {
+            *window = QuotaWindow {
+                started_at_ms: now_ms,
+                tokens: 0,
+            };
+        }
+        f(window)
+    }
+
+    fn usage(&self, service: &str, config: &AiServiceConfig, now_ms: u64) -> AiUsage {
+        let window_ms = window_ms(config);
+        self.with_window(service, window_ms, now_ms, |window| AiUsage {
+            tokens: window.tokens,
+            quota: config.tokens_per_window,
+            resets_at_ms: config
+                .tokens_per_window
+                .map(|_| window.started_at_ms + window_ms),
+        })
+    }
+
+    fn add(&self, service: &str, window_ms: u64, tokens: u64, now_ms: u64) {
+        self.with_window(service, window_ms, now_ms, |window| {
+            window.tokens += tokens;
+        });
+    }
+}
+
+fn window_ms(config: &AiServiceConfig) -> u64 {
+    config.window_secs.unwrap_or(DEFAULT_WINDOW_SECS) * 1000
+}
+
+fn usage_tokens(body: &Value) -> Option<u64> {
+    let usage = body.get("usage")?;
+    usage
+        .get("total_tokens")
+        .and_then(Value::as_u64)
+        .or_else(|| {
+            let prompt = usage.get("prompt_tokens").and_then(Value::as_u64)?;
+            let completion = usage
+                .get("completion_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(0);
+            Some(prompt + completion)
+        })
+}
+
+// Finds the usage providers report, at the end of JSON bodies or in the last
+// event of streamed responses
+struct UsageCounter {
+    streaming: bool,
+    buf: Vec<u8>,
+    tokens: Option<u64>,
+    bytes: usize,
+}
+
+impl UsageCounter {
+    fn new(streaming: bool) -> Self {
+        Self {
+            streaming,
+            buf: vec![],
+            tokens: None,
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.bytes += chunk.len();
+        if !self.streaming {
+            if self.buf.len() + chunk.len() <= MAX_BUFFERED_RESPONSE {
+                self.buf.extend_from_slice(chunk);
+            }
+            return;
+        }
+
+        self.buf.extend_from_slice(chunk);
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let Some(data) = line
+                .strip_prefix(b"data:")
+                .and_then(|data| std::str::from_utf8(data).ok())
+            else {
+                continue;
+            };
+            if let Some(tokens) = serde_json::from_str::<Value>(data.trim())
+                .ok()
+                .as_ref()
+                .and_then(usage_tokens)
+            {
+                self.tokens = Some(tokens);
+            }
+        }
+    }
+
+    // Tokens reported by the provider, or estimated from the size of the request
+    // and response (~4 bytes a token) if it reported none
+    fn finish(self, request_bytes: usize) -> u64 {
+        let reported = if self.streaming {
+            self.tokens
+        } else {
+            serde_json::from_slice::<Value>(&self.buf)
+                .ok()
+                .as_ref()
+                .and_then(usage_tokens)
+        };
+        reported.unwrap_or(((request_bytes + self.bytes) / 4) as u64)
+    }
+}
+
+// Streamed chat completions only report their usage if asked to
+fn prepare_body(endpoint: AiEndpoint, mut body: Value) -> Result<Value, Error> {
+    let Some(fields) = body.as_object_mut() else {
+        bail!("request body must be an object");
+    };
+    let streaming = fields.get("stream").and_then(Value::as_bool) == Some(true);
+    if endpoint == AiEndpoint::Chat && streaming {
+        let options = fields
+            .entry("stream_options")
+            .or_insert_with(|| Value::Object(Default::default()));
+        if let Some(options) = options.as_object_mut() {
+            options.insert("include_usage".to_string(), Value::Bool(true));
+        }
+    }
+    Ok(body)
+}
+
+pub struct SharedAi {
+    handle: Handle,
+    client: reqwest::Client,
+    providers: HashMap<String, Arc<AiProvider>>,
+    services: HashMap<String, AiServiceConfig>,
+    quotas: Arc<TokenQuotas>,
+}
+
+impl SharedAi {
+    fn service(&self, service: &str) -> Result<&AiServiceConfig, Error> {
+        self.services
+            .get(service)
+            .or_else(|| self.services.get(DEFAULT_SERVICE))
+            .ok_or_else(|| anyhow!("no AI providers are configured for {}", service))
+    }
+
+    fn provider(
+        &self,
+        config: &AiServiceConfig,
+        name: Option<&str>,
+    ) -> Result<Arc<AiProvider>, Error> {
+        let name = match name {
+            Some(name) => config
+                .providers
+                .iter()
+                .find(|provider| *provider == name)
+                .ok_or_else(|| anyhow!("provider {} isn't available to the service", name))?,
+            None => config
+                .providers
+                .first()
+                .ok_or_else(|| anyhow!("the service has no providers"))?,
+        };
+        self.providers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown provider {}", name))
+    }
+
+    fn start(
+        &self,
+        service: &str,
+        req: AiRequest,
+    ) -> Result<BoxFuture<'static, Result<AiResponse, AiError>>, AiError> {
+        let config = self.service(service)?;
+        let provider = self.provider(config, req.provider.as_deref())?;
+
+        let window_ms = window_ms(config);
+        let usage = self.quotas.usage(service, config, now_ms());
+        if let Some(quota) = usage.quota {
+            if usage.tokens >= quota {
+                return Err(AiError::QuotaExceeded(format!(
+                    "{} used its {} tokens, the quota resets at {}",
+                    service,
+                    quota,
+                    usage.resets_at_ms.unwrap_or_default()
+                )));
+            }
+        }
+
+        let body =
+            serde_json::to_vec(&prepare_body(req.endpoint, req.body)?).map_err(Error::from)?;
+        let request_bytes = body.len();
+        let request = self
+            .client
+            .post(format!("{}{}", provider.base_url, req.endpoint.path()))
+            .headers(provider.headers.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        let quotas = self.quotas.clone();
+        let service = service.to_string();
+
+        let task = self.handle.spawn(async move {
+            let res = request.send().await?;
+            let status = res.status().as_u16();
+            let content_type = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let streaming = content_type
+                .as_deref()
+                .map(|value| value.starts_with("text/event-stream"))
+                .unwrap_or(false);
+
+            let (tx, rx) = mpsc::channel(RESPONSE_CHUNKS_BUFFERED);
+            tokio::spawn(async move {
+                let mut counter = UsageCounter::new(streaming);
+                let mut stream = res.bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(err) => {
+                            let _ = tx.send(Err(err.to_string())).await;
+                            break;
+                        }
+                    };
+                    counter.push(&chunk);
+                    // keep counting if the isolate went away, the tokens are used
+                    let _ = tx.send(Ok(chunk.to_vec())).await;
+                }
+                quotas.add(&service, window_ms, counter.finish(request_bytes), now_ms());
+            });
+
+            Ok::<_, Error>(AiResponse {
+                status,
+                content_type,
+                body: rx,
+            })
+        });
+        Ok(async move {
+            let res = task.await.map_err(Error::from)??;
+            Ok(res)
+        }
+        .boxed())
+    }
+}
+
+impl AiGateway for SharedAi {
+    fn request(
+        &self,
+        service: &str,
+        req: AiRequest,
+    ) -> BoxFuture<'static, Result<AiResponse, AiError>> {
+        match self.start(service, req) {
+            Ok(res) => res,
+            Err(err) => future::ready(Err(err)).boxed(),
+        }
+    }
+
+    fn usage(&self, service: &str) -> AiUsage {
+        match self.service(service) {
+            Ok(config) => self.quotas.usage(service, config, now_ms()),
+            Err(_) => AiUsage {
+                tokens: 0,
+                quota: Some(0),
+                resets_at_ms: None,
+            },
+        }
+    }
+}
+
+impl AiConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_service_config(path, Self::parse, |config| &mut config.services)
+    }
+
+    // Requests are sent by the current runtime
+    pub fn into_gateway(self) -> Result<SharedAi, Error> {
+        let mut providers = HashMap::new();
+        for (name, config) in self.providers {
+            let provider =
+                AiProvider::new(config).with_context(|| format!("invalid AI provider {}", name))?;
+            providers.insert(name, Arc::new(provider));
+        }
+        for (service, config) in &self.services {
+            if let Some(unknown) = config
+                .providers
+                .iter()
+                .find(|name| !providers.contains_key(*name))
+            {
+                bail!("AI config of {} uses unknown provider {}", service, unknown);
+            }
+        }
+        Ok(SharedAi {
+            handle: Handle::current(),
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()?,
+            providers,
+            services: self.services,
+            quotas: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prepare_body, AiConfig, AiServiceConfig, TokenQuotas, UsageCounter};
+    use deno_core::serde_json::json;
+    use reqwest::header::AUTHORIZATION;
+    use sb_worker_context::ai::AiEndpoint;
+
+    #[tokio::test]
+    async fn test_ai_config() {
+        std::env::set_var("AI_GATEWAY_TEST_KEY", "sk-test");
+        let gateway = AiConfig::parse(
+            r#"{ "providers": { "openai": { "baseUrl": "https://api.openai.com/v1/", "apiKeyEnv": "AI_GATEWAY_TEST_KEY" }, "local": { "baseUrl": "http://ollama:11434/v1" } }, "services": { "*": { "providers": ["local"] }, "search": { "providers": ["openai", "local"], "tokensPerWindow": 1000 } } }"#,
+        )
+        .unwrap()
+        .into_gateway()
+        .unwrap();
+
+        // keys are injected, services fall back to the `*` config
+        let search = gateway.service("search").unwrap();
+        let openai = gateway.provider(search, None).unwrap();
+        assert_eq!(openai.base_url, "https://api.openai.com/v1");
+        assert_eq!(openai.headers[AUTHORIZATION], "Bearer sk-test");
+        assert!(gateway.provider(search, Some("local")).is_ok());
+
+        let other = gateway.service("media").unwrap();
+        assert!(gateway.provider(other, Some("openai")).is_err());
+
+        assert!(AiConfig::parse(
+            r#"{ "providers": {}, "services": { "*": { "providers": ["openai"] } } }"#
+        )
+        .unwrap()
+        .into_gateway()
+        .is_err());
+    }
+
+    #[test]
+    fn test_token_quotas() {
+        let config = AiServiceConfig {
+            providers: vec![],
+            tokens_per_window: Some(100),
+            window_secs: Some(60),
+        };
+        let quotas = TokenQuotas::default();
+        quotas.add("search", 60_000, 80, 1_000);
+        quotas.add("search", 60_000, 30, 2_000);
+        let usage = quotas.usage("search", &config, 3_000);
+        assert_eq!(usage.tokens, 110);
+        assert_eq!(usage.resets_at_ms, Some(61_000));
+        assert_eq!(quotas.usage("other", &config, 3_000).tokens, 0);
+
+        // a new window starts once the current one is over
+        assert_eq!(quotas.usage("search", &config, 61_000).tokens, 0);
+    }
+
+    #[test]
+    fn test_usage_counter() {
+        let mut counter = UsageCounter::new(false);
+        counter.push(br#"{ "data": [], "usage": { "prompt_tokens": 8, "#);
+        counter.push(br#""total_tokens": 8 } }"#);
+        assert_eq!(counter.finish(100), 8);
+
+        // the usage comes with the last event, possibly split across chunks
+        let mut counter = UsageCounter::new(true);
+        counter.push(b"data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n");
+        counter.push(b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,");
+        counter.push(b"\"completion_tokens\":2}}\n\ndata: [DONE]\n\n");
+        assert_eq!(counter.finish(100), 7);
+
+        // estimated if the provider reports none
+        let mut counter = UsageCounter::new(false);
+        counter.push(&[b'x'; 300]);
+        assert_eq!(counter.finish(100), 100);
+    }
+
+    #[test]
+    fn test_prepare_body() {
+        let body = prepare_body(AiEndpoint::Chat, json!({ "model": "m", "stream": true })).unwrap();
+        assert_eq!(body["stream_options"]["include_usage"], true);
+
+        let body = prepare_body(AiEndpoint::Chat, json!({ "model": "m" })).unwrap();
+        assert!(body.get("stream_options").is_none());
+
+        assert!(prepare_body(AiEndpoint::Embeddings, json!("text")).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::{anyhow, Error};
+use deno_core::serde_json;
+use hyper::{Body, Request};
+use log::{error, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use sb_worker_context::alarms::{
+    Alarm, AlarmStore, SharedAlarmStore, ALARM_ATTEMPT_HEADER, ALARM_HEADER,
+};
+use sb_worker_context::essentials::{
+    UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+
+pub const ALARM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// how long a claimed alarm has to complete before it fires again
+const ALARM_LEASE_MS: u64 = 60_000;
+const ALARM_MAX_ATTEMPTS: u32 = 5;
+const ALARM_RETRY_BASE_MS: u64 = 10_000;
+
+static ALARM_STORE: OnceLock<SharedAlarmStore> = OnceLock::new();
+
+// Store of the alarm scheduler, if alarms are enabled
+pub fn alarm_store() -> Option<SharedAlarmStore> {
+    ALARM_STORE.get().cloned()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Alarms in a local sqlite database
+pub struct SqliteAlarmStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteAlarmStore {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS alarms (
+                id TEXT PRIMARY KEY,
+                service_path TEXT NOT NULL,
+                import_map_path TEXT,
+                entrypoint TEXT,
+                path TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                fire_at_ms INTEGER NOT NULL,
+                attempt INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS alarms_fire_at_ms ON alarms (fire_at_ms);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn alarm_from_row(row: &rusqlite::Row) -> rusqlite::Result<(Alarm, String)> {
+    Ok((
+        Alarm {
+            id: row.get(0)?,
+            service_path: row.get(1)?,
+            import_map_path: row.get(2)?,
+            entrypoint: row.get(3)?,
+            path: row.get(4)?,
+            payload: serde_json::Value::Null,
+            fire_at_ms: row.get::<_, i64>(6)? as u64,
+            attempt: row.get(7)?,
+        },
+        row.get(5)?,
+    ))
+}
+
+fn query_alarms(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Alarm>, Error> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, alarm_from_row)?;
+
+    let mut alarms = vec![];
+    for row in rows {
+        let (mut alarm, payload) = row?;
+        alarm.payload = serde_json::from_str(&payload)?;
+        alarms.push(alarm);
+    }
+    Ok(alarms)
+}
+
+const ALARM_COLUMNS: &str =
+    "id, service_path, import_map_path, entrypoint, path, payload, fire_at_ms, attempt";
+
+impl AlarmStore for SqliteAlarmStore {
+    fn insert(&self, alarm: &Alarm) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            &format!(
+                "INSERT INTO alarms ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                ALARM_COLUMNS
+            ),
+            params![
+                alarm.id,
+                alarm.service_path,
+                alarm.import_map_path,
+                alarm.entrypoint,
+                alarm.path,
+                serde_json::to_string(&alarm.payload)?,
+                alarm.fire_at_ms as i64,
+                alarm.attempt,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn cancel(&self, service_path: &str, id: &str) -> Result<bool, Error> {
+        let deleted = self.conn.lock().unwrap().execute(
+            "DELETE FROM alarms WHERE id = ?1 AND service_path = ?2",
+            params![id, service_path],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn list(&self, service_path: &str) -> Result<Vec<Alarm>, Error> {
+        query_alarms(
+            &self.conn.lock().unwrap(),
+            &format!(
+                "SELECT {} FROM alarms WHERE service_path = ?1 ORDER BY fire_at_ms",
+                ALARM_COLUMNS
+            ),
+            params![service_path],
+        )
+    }
+
+    fn claim_due(&self, now_ms: u64, lease_ms: u64) -> Result<Vec<Alarm>, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let alarms = query_alarms(
+            &tx,
+            &format!(
+                "SELECT {} FROM alarms WHERE fire_at_ms <= ?1 ORDER BY fire_at_ms",
+                ALARM_COLUMNS
+            ),
+            params![now_ms as i64],
+        )?;
+        tx.execute(
+            "UPDATE alarms SET fire_at_ms = ?1 WHERE fire_at_ms <= ?2",
+            params![(now_ms + lease_ms) as i64, now_ms as i64],
+        )?;
+        tx.commit()?;
+        Ok(alarms)
+    }
+
+    fn complete(&self, id: &str) -> Result<(), Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM alarms WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn retry(&self, id: &str, fire_at_ms: u64) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        let attempt: Option<u32> = conn
+            .query_row(
+                "SELECT attempt FROM alarms WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if attempt.is_some() {
+            conn.execute(
+                "UPDATE alarms SET fire_at_ms = ?1, attempt = attempt + 1 WHERE id = ?2",
+                params![fire_at_ms as i64, id],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Sends the alarm to a user worker of its service. Errors if the request failed
+// or the service responded with a server error.
+async fn dispatch(
+    alarm: &Alarm,
+    worker_pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+) -> Result<(), Error> {
+    let opts = WorkerContextInitOpts {
+        service_path: PathBuf::from(&alarm.service_path),
+        no_module_cache: false,
+        import_map_path: alarm.import_map_path.clone(),
+        // same as the example main worker
+        env_vars: std::env::vars().collect(),
+        events_rx: None,
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            service_path: Some(alarm.service_path.clone()),
+            ..Default::default()
+        }),
+        maybe_eszip: None,
+        maybe_module_code: None,
+        maybe_entrypoint: alarm.entrypoint.clone(),
+    };
+    let (tx, rx) = oneshot::channel();
+    worker_pool_tx.send(UserWorkerMsgs::Create(opts, tx))?;
+    let worker = rx.await??;
+
+    let req = Request::builder()
+        .method("POST")
+        .uri(format!("http://localhost{}", alarm.path))
+        .header(ALARM_HEADER, &alarm.id)
+        .header(ALARM_ATTEMPT_HEADER, alarm.attempt.to_string())
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&alarm.payload)?))?;
+    let (tx, rx) = oneshot::channel();
+    worker_pool_tx.send(UserWorkerMsgs::SendRequest(worker.key, req, tx))?;
+    let res = rx.await??;
+
+    if res.status().is_server_error() {
+        return Err(anyhow!("service responded with {}", res.status()));
+    }
+    Ok(())
+}
+
+async fn fire(
+    store: SharedAlarmStore,
+    alarm: Alarm,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+) {
+    let result = match dispatch(&alarm, &worker_pool_tx).await {
+        Ok(_) => store.complete(&alarm.id),
+        Err(err) if alarm.attempt + 1 >= ALARM_MAX_ATTEMPTS => {
+            error!(
+                "alarm {} of {} failed {} times, dropping it: {}",
+                alarm.id, alarm.service_path, ALARM_MAX_ATTEMPTS, err
+            );
+            store.complete(&alarm.id)
+        }
+        Err(err) => {
+            warn!(
+                "alarm {} of {} failed (attempt {}): {}",
+                alarm.id, alarm.service_path, alarm.attempt, err
+            );
+            let backoff = ALARM_RETRY_BASE_MS << alarm.attempt;
+            store.retry(&alarm.id, now_ms() + backoff)
+        }
+    };
+    if let Err(err) = result {
+        error!("failed to update alarm {}: {}", alarm.id, err);
+    }
+}
+
+// Polls the store for due alarms and dispatches them to user workers
+pub fn start_alarm_scheduler(
+    store: SharedAlarmStore,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+) {
+    if ALARM_STORE.set(store.clone()).is_err() {
+        warn!("alarm scheduler is already running");
+        return;
+    }
+
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(ALARM_POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let alarms = match store.claim_due(now_ms(), ALARM_LEASE_MS) {
+                Ok(alarms) => alarms,
+                Err(err) => {
+                    error!("failed to poll alarms: {}", err);
+                    continue;
+                }
+            };
+            for alarm in alarms {
+                tokio::task::spawn(fire(store.clone(), alarm, worker_pool_tx.clone()));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::SqliteAlarmStore;
+    use deno_core::serde_json::json;
+    use sb_worker_context::alarms::{Alarm, AlarmStore};
+
+    fn alarm(id: &str, service_path: &str, fire_at_ms: u64) -> Alarm {
+        Alarm {
+            id: id.to_string(),
+            service_path: service_path.to_string(),
+            import_map_path: None,
+            entrypoint: None,
+            path: "/retry".to_string(),
+            payload: json!({ "webhook": 1 }),
+            fire_at_ms,
+            attempt: 0,
+        }
+    }
+
+    #[test]
+    fn test_sqlite_alarm_store() {
+        let store = SqliteAlarmStore::in_memory().unwrap();
+        store.insert(&alarm("a", "./hello", 1_000)).unwrap();
+        store.insert(&alarm("b", "./hello", 5_000)).unwrap();
+        store.insert(&alarm("c", "./other", 1_000)).unwrap();
+
+        assert_eq!(store.list("./hello").unwrap().len(), 2);
+        assert!(!store.cancel("./hello", "c").unwrap());
+        assert!(store.cancel("./other", "c").unwrap());
+
+        // claimed alarms are leased, so they aren't claimed twice
+        let due = store.claim_due(2_000, 60_000).unwrap();
+        assert_eq!(due, vec![alarm("a", "./hello", 1_000)]);
+        assert!(store.claim_due(2_000, 60_000).unwrap().is_empty());
+
+        store.retry("a", 3_000).unwrap();
+        let due = store.claim_due(3_000, 60_000).unwrap();
+        assert_eq!(due[0].attempt, 1);
+
+        store.complete("a").unwrap();
+        assert_eq!(store.list("./hello").unwrap().len(), 1);
+    }
+}
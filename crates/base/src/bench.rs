@@ -0,0 +1,263 @@
+use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use anyhow::{bail, Error};
+use deno_core::futures::future::join_all;
+use deno_core::serde_json;
+use hyper::{Body, Request};
+use sb_worker_context::essentials::{
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+pub struct BenchOpts {
+    pub service_path: PathBuf,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    // requests sent after the first one (which measures the cold start)
+    pub requests: usize,
+    // requests in flight at a time
+    pub concurrency: usize,
+    pub method: String,
+    // path and query of the requests
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl Default for BenchOpts {
+    fn default() -> Self {
+        Self {
+            service_path: PathBuf::from("."),
+            import_map_path: None,
+            no_module_cache: false,
+            requests: 1000,
+            concurrency: 10,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: vec![],
+            body: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyStats {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let ms = |d: &Duration| d.as_nanos() as f64 / 1_000_000.0;
+        // nearest rank
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+            ms(&samples[rank.clamp(1, samples.len()) - 1])
+        };
+        Self {
+            min_ms: ms(&samples[0]),
+            mean_ms: samples.iter().map(ms).sum::<f64>() / samples.len() as f64,
+            p50_ms: percentile(50.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            max_ms: ms(&samples[samples.len() - 1]),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchReport {
+    // from booting the worker to the first response
+    pub cold_start_ms: f64,
+    pub requests: usize,
+    // requests that failed or were answered with a 5xx
+    pub errors: usize,
+    pub concurrency: usize,
+    pub duration_ms: f64,
+    pub requests_per_sec: f64,
+    pub latency: LatencyStats,
+    // CPU time of the process while under load, divided by the requests
+    pub cpu_time_per_request_ms: f64,
+    // peak resident memory of the process
+    pub max_rss_mb: f64,
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "cold start:   {:.2}ms", self.cold_start_ms);
+        let _ = writeln!(
+            out,
+            "requests:     {} ({} errors) with {} in flight, {:.1} req/s",
+            self.requests, self.errors, self.concurrency, self.requests_per_sec
+        );
+        let _ = writeln!(
+            out,
+            "latency:      min {:.2}ms | mean {:.2}ms | p50 {:.2}ms | p95 {:.2}ms | p99 {:.2}ms | max {:.2}ms",
+            self.latency.min_ms,
+            self.latency.mean_ms,
+            self.latency.p50_ms,
+            self.latency.p95_ms,
+            self.latency.p99_ms,
+            self.latency.max_ms
+        );
+        let _ = writeln!(
+            out,
+            "cpu time:     {:.3}ms per request",
+            self.cpu_time_per_request_ms
+        );
+        let _ = writeln!(out, "max memory:   {:.1}MB", self.max_rss_mb);
+        out
+    }
+}
+
+// (CPU time in ms, peak resident memory in MB) of the process so far
+fn process_usage() -> (f64, f64) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let ms = |t: libc::timeval| t.tv_sec as f64 * 1000.0 + t.tv_usec as f64 / 1000.0;
+    (
+        ms(usage.ru_utime) + ms(usage.ru_stime),
+        // kilobytes on linux
+        usage.ru_maxrss as f64 / 1024.0,
+    )
+}
+
+fn bench_request(opts: &BenchOpts) -> Result<Request<Body>, Error> {
+    let mut builder = Request::builder()
+        .method(opts.method.as_str())
+        .uri(format!("http://localhost{}", opts.path));
+    for (name, value) in &opts.headers {
+        builder = builder.header(name, value);
+    }
+    let body = opts
+        .body
+        .clone()
+        .map(Body::from)
+        .unwrap_or_else(Body::empty);
+    Ok(builder.body(body)?)
+}
+
+// Sends a request and reads its response, returning whether it succeeded
+async fn send(worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>, req: Request<Body>) -> bool {
+    match send_user_worker_request(worker_req_tx, req).await {
+        Ok(res) => {
+            let status = res.status();
+            hyper::body::to_bytes(res.into_body()).await.is_ok() && !status.is_server_error()
+        }
+        Err(_) => false,
+    }
+}
+
+// Boots the service in a user worker (with its default limits) and sends it
+// `requests` requests, `concurrency` at a time
+pub async fn run_bench(opts: BenchOpts) -> Result<BenchReport, Error> {
+    if opts.concurrency == 0 {
+        bail!("concurrency must be at least 1");
+    }
+    // fails early on an invalid method, path or header
+    bench_request(&opts)?;
+
+    let started = Instant::now();
+    let worker_req_tx = create_worker(WorkerContextInitOpts {
+        service_path: opts.service_path.clone(),
+        no_module_cache: opts.no_module_cache,
+        import_map_path: opts.import_map_path.clone(),
+        env_vars: std::env::vars().collect(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            service_path: Some(opts.service_path.to_string_lossy().to_string()),
+            ..UserWorkerRuntimeOpts::default()
+        }),
+    })
+    .await?;
+    if !send(worker_req_tx.clone(), bench_request(&opts)?).await {
+        bail!("the service failed to respond to the first request");
+    }
+    let cold_start = started.elapsed();
+
+    let (cpu_before, _) = process_usage();
+    let started = Instant::now();
+    let remaining = RefCell::new(opts.requests);
+    let samples = RefCell::new(Vec::with_capacity(opts.requests));
+    let errors = RefCell::new(0);
+    let clients = (0..opts.concurrency.min(opts.requests)).map(|_| async {
+        loop {
+            {
+                let mut remaining = remaining.borrow_mut();
+                if *remaining == 0 {
+                    return;
+                }
+                *remaining -= 1;
+            }
+            let req = bench_request(&opts).unwrap();
+            let sent = Instant::now();
+            let ok = send(worker_req_tx.clone(), req).await;
+            samples.borrow_mut().push(sent.elapsed());
+            if !ok {
+                *errors.borrow_mut() += 1;
+            }
+        }
+    });
+    join_all(clients).await;
+    let duration = started.elapsed();
+    let (cpu_after, max_rss_mb) = process_usage();
+
+    Ok(BenchReport {
+        cold_start_ms: cold_start.as_secs_f64() * 1000.0,
+        requests: opts.requests,
+        errors: errors.into_inner(),
+        concurrency: opts.concurrency,
+        duration_ms: duration.as_secs_f64() * 1000.0,
+        requests_per_sec: opts.requests as f64 / duration.as_secs_f64().max(f64::EPSILON),
+        latency: LatencyStats::from_samples(samples.into_inner()),
+        cpu_time_per_request_ms: (cpu_after - cpu_before) / opts.requests.max(1) as f64,
+        max_rss_mb,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::LatencyStats;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_stats() {
+        let samples = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_samples(samples);
+        assert_eq!(
+            stats,
+            LatencyStats {
+                min_ms: 1.0,
+                mean_ms: 50.5,
+                p50_ms: 50.0,
+                p95_ms: 95.0,
+                p99_ms: 99.0,
+                max_ms: 100.0,
+            }
+        );
+        assert_eq!(LatencyStats::from_samples(vec![]), LatencyStats::default());
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use deno_broadcast_channel::{BroadcastChannel, Message};
+use deno_core::error::AnyError;
+use deno_core::futures::StreamExt;
+use deno_core::serde_json;
+use deno_core::Resource;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+// messages a subscriber can fall behind by before it starts missing them
+const CHANNEL_CAPACITY: usize = 256;
+
+const REDIS_CHANNEL_PREFIX: &str = "edge-runtime:broadcast:";
+const REDIS_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+static BROKER: OnceLock<BroadcastBroker> = OnceLock::new();
+static RELAY: OnceLock<mpsc::UnboundedSender<RelayedMessage>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+struct ScopedMessage {
+    name: Arc<String>,
+    data: Arc<Vec<u8>>,
+    // subscription the message was sent from (nil for messages from other nodes)
+    sender: Uuid,
+}
+
+// Routes `BroadcastChannel` messages between the isolates of a service. Each
+// service gets its own scope, so services can't read each other's messages.
+#[derive(Default)]
+struct BroadcastBroker {
+    scopes: Mutex<HashMap<String, broadcast::Sender<ScopedMessage>>>,
+}
+
+impl BroadcastBroker {
+    fn scope(&self, scope: &str) -> broadcast::Sender<ScopedMessage> {
+        self.scopes
+            .lock()
+            .unwrap()
+            .entry(scope.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .clone()
+    }
+}
+
+fn broker() -> &'static BroadcastBroker {
+    BROKER.get_or_init(BroadcastBroker::default)
+}
+
+// `BroadcastChannel` backend of a worker, scoped to its service
+#[derive(Clone)]
+pub struct ServiceBroadcastChannel {
+    scope: String,
+    tx: broadcast::Sender<ScopedMessage>,
+}
+
+impl ServiceBroadcastChannel {
+    pub fn new(scope: &str) -> Self {
+        Self {
+            scope: scope.to_string(),
+            tx: broker().scope(scope),
+        }
+    }
+}
+
+pub struct ServiceBroadcastChannelResource {
+    rx: tokio::sync::Mutex<(
+        broadcast::Receiver<ScopedMessage>,
+        mpsc::UnboundedReceiver<()>,
+    )>,
+    cancel_tx: mpsc::UnboundedSender<()>,
+    id: Uuid,
+}
+
+impl Resource for ServiceBroadcastChannelResource {
+    fn name(&self) -> Cow<str> {
+        "serviceBroadcastChannel".into()
+    }
+}
+
+#[async_trait]
+impl BroadcastChannel for ServiceBroadcastChannel {
+    type Resource = ServiceBroadcastChannelResource;
+
+    fn subscribe(&self) -> Result<Self::Resource, AnyError> {
+        let (cancel_tx, cancel_rx) = mpsc::unbounded_channel();
+        Ok(ServiceBroadcastChannelResource {
+            rx: tokio::sync::Mutex::new((self.tx.subscribe(), cancel_rx)),
+            cancel_tx,
+            id: Uuid::new_v4(),
+        })
+    }
+
+    fn unsubscribe(&self, resource: &Self::Resource) -> Result<(), AnyError> {
+        Ok(resource.cancel_tx.send(())?)
+    }
+
+    async fn send(
+        &self,
+        resource: &Self::Resource,
+        name: String,
+        data: Vec<u8>,
+    ) -> Result<(), AnyError> {
+        if let Some(relay) = RELAY.get() {
+            let _ = relay.send(RelayedMessage {
+                node: node_id().to_string(),
+                scope: self.scope.clone(),
+                name: name.clone(),
+                data: base64::encode(&data),
+            });
+        }
+
+        // the sender's own subscription is always there, so this can't fail
+        let _ = self.tx.send(ScopedMessage {
+            name: Arc::new(name),
+            data: Arc::new(data),
+            sender: resource.id,
+        });
+        Ok(())
+    }
+
+    async fn recv(&self, resource: &Self::Resource) -> Result<Option<Message>, AnyError> {
+        let mut guard = resource.rx.lock().await;
+        let (rx, cancel_rx) = &mut *guard;
+        loop {
+            let result = tokio::select! {
+                result = rx.recv() => result,
+                _ = cancel_rx.recv() => return Ok(None),
+            };
+            match result {
+                Err(broadcast::error::RecvError::Closed) => return Ok(None),
+                // fell behind, the oldest messages are gone
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                // channels don't receive their own messages
+                Ok(message) if message.sender == resource.id => {}
+                Ok(message) => {
+                    return Ok(Some((
+                        String::clone(&message.name),
+                        Vec::clone(&message.data),
+                    )))
+                }
+            }
+        }
+    }
+}
+
+// Message relayed between runtime instances through Redis
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct RelayedMessage {
+    node: String,
+    scope: String,
+    name: String,
+    // base64 encoded
+    data: String,
+}
+
+fn node_id() -> Uuid {
+    static NODE_ID: OnceLock<Uuid> = OnceLock::new();
+    *NODE_ID.get_or_init(Uuid::new_v4)
+}
+
+fn deliver(message: RelayedMessage) {
+    if message.node == node_id().to_string() {
+        return;
+    }
+    let data = match base64::decode(&message.data) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("dropping relayed broadcast message: {}", err);
+            return;
+        }
+    };
+    let _ = broker().scope(&message.scope).send(ScopedMessage {
+        name: Arc::new(message.name),
+        data: Arc::new(data),
+        sender: Uuid::nil(),
+    });
+}
+
+async fn publish(
+    client: &redis::Client,
+    rx: &mut mpsc::UnboundedReceiver<RelayedMessage>,
+) -> Result<(), Error> {
+    use redis::AsyncCommands;
+
+    let mut conn = client.get_async_connection().await?;
+    while let Some(message) = rx.recv().await {
+        let channel = format!("{}{}", REDIS_CHANNEL_PREFIX, message.scope);
+        conn.publish::<_, _, ()>(channel, serde_json::to_vec(&message)?)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn subscribe(client: &redis::Client) -> Result<(), Error> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub
+        .psubscribe(format!("{}*", REDIS_CHANNEL_PREFIX))
+        .await?;
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        match serde_json::from_slice::<RelayedMessage>(msg.get_payload_bytes()) {
+            Ok(message) => deliver(message),
+            Err(err) => warn!("dropping invalid broadcast message: {}", err),
+        }
+    }
+    Ok(())
+}
+
+// Relays messages of every scope to the other runtime instances connected to the
+// same Redis (and back). Services are matched by their path.
+pub fn enable_broadcast_relay(redis_url: &str) -> Result<(), Error> {
+    let client = redis::Client::open(redis_url)?;
+    let (tx, mut rx) = mpsc::unbounded_channel::<RelayedMessage>();
+    if RELAY.set(tx).is_err() {
+        warn!("broadcast relay is already enabled");
+        return Ok(());
+    }
+
+    let publish_client = client.clone();
+    tokio::task::spawn(async move {
+        loop {
+            if let Err(err) = publish(&publish_client, &mut rx).await {
+                error!("broadcast relay failed to publish: {}", err);
+            }
+            tokio::time::sleep(REDIS_RECONNECT_DELAY).await;
+        }
+    });
+    tokio::task::spawn(async move {
+        loop {
+            if let Err(err) = subscribe(&client).await {
+                error!("broadcast relay failed to subscribe: {}", err);
+            }
+            tokio::time::sleep(REDIS_RECONNECT_DELAY).await;
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deliver, node_id, RelayedMessage, ServiceBroadcastChannel};
+    use deno_broadcast_channel::BroadcastChannel;
+
+    #[tokio::test]
+    async fn test_broadcast_between_workers_of_a_service() {
+        let worker_a = ServiceBroadcastChannel::new("./services/a");
+        let worker_b = ServiceBroadcastChannel::new("./services/a");
+        let other_service = ServiceBroadcastChannel::new("./services/b");
+
+        let sub_a = worker_a.subscribe().unwrap();
+        let sub_b = worker_b.subscribe().unwrap();
+        let sub_other = other_service.subscribe().unwrap();
+
+        worker_a
+            .send(&sub_a, "cache".to_string(), b"invalidate".to_vec())
+            .await
+            .unwrap();
+
+        let (name, data) = worker_b.recv(&sub_b).await.unwrap().unwrap();
+        assert_eq!(name, "cache");
+        assert_eq!(data, b"invalidate");
+
+        other_service.unsubscribe(&sub_other).unwrap();
+        assert!(other_service.recv(&sub_other).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_relayed_message() {
+        let worker = ServiceBroadcastChannel::new("./services/relayed");
+        let sub = worker.subscribe().unwrap();
+
+        // messages this node published are ignored
+        deliver(RelayedMessage {
+            node: node_id().to_string(),
+            scope: "./services/relayed".to_string(),
+            name: "cache".to_string(),
+            data: base64::encode(b"own"),
+        });
+        deliver(RelayedMessage {
+            node: uuid::Uuid::new_v4().to_string(),
+            scope: "./services/relayed".to_string(),
+            name: "cache".to_string(),
+            data: base64::encode(b"remote"),
+        });
+
+        let (_, data) = worker.recv(&sub).await.unwrap().unwrap();
+        assert_eq!(data, b"remote");
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{anyhow, Error};
+use deno_core::serde_json;
+use log::{error, warn};
+use sb_worker_context::essentials::WorkerCreationRejected;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+
+// Cluster mode: `start --cluster <N>` runs N runtime processes (members) that
+// accept connections on the same port (SO_REUSEPORT), so a multi-core host isn't
+// limited by the lock of a single worker pool. The process started by the user
+// only supervises them, restarting the members that exit, and keeps the state
+// they share on a unix socket: the workers each service with a `maxWorkers`
+// limit runs across the cluster.
+
+// set on the members by the process supervising them
+pub const CLUSTER_MEMBER_ENV: &str = "EDGE_RUNTIME_CLUSTER_MEMBER";
+pub const CLUSTER_STATE_ENV: &str = "EDGE_RUNTIME_CLUSTER_STATE";
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+// members are killed if they didn't exit this long after being asked to
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum StateRequest {
+    #[serde(rename_all = "camelCase")]
+    Acquire {
+        service: String,
+        key: Uuid,
+        max_workers: usize,
+    },
+    Release {
+        key: Uuid,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct AcquireReply {
+    granted: bool,
+    // the workers of the service in the cluster, including the new one
+    workers: usize,
+}
+
+// The workers of the services limited by `maxWorkers`, with the service they
+// run and the member (connection to the state) that booted them
+#[derive(Default)]
+struct LeaseTable {
+    leases: HashMap<Uuid, (String, usize)>,
+}
+
+impl LeaseTable {
+    fn workers(&self, service: &str) -> usize {
+        self.leases.values().filter(|(s, _)| s == service).count()
+    }
+
+    fn handle(&mut self, owner: usize, request: StateRequest) -> Option<AcquireReply> {
+        match request {
+            StateRequest::Acquire {
+                service,
+                key,
+                max_workers,
+            } => {
+                let workers = self.workers(&service);
+                if workers >= max_workers {
+                    return Some(AcquireReply {
+                        granted: false,
+                        workers,
+                    });
+                }
+                self.leases.insert(key, (service, owner));
+                Some(AcquireReply {
+                    granted: true,
+                    workers: workers + 1,
+                })
+            }
+            StateRequest::Release { key } => {
+                self.leases.remove(&key);
+                None
+            }
+        }
+    }
+
+    // the workers of a member that exited (or lost its connection) are gone
+    fn release_owner(&mut self, owner: usize) {
+        self.leases.retain(|_, (_, o)| *o != owner);
+    }
+}
+
+struct StateConnection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+}
+
+// This process's side of the cluster, when it's one of its members
+pub struct ClusterMember {
+    pub index: usize,
+    state_path: PathBuf,
+    connection: tokio::sync::Mutex<Option<StateConnection>>,
+    // the leases this member holds, the others aren't released
+    leased: Mutex<HashSet<Uuid>>,
+}
+
+impl ClusterMember {
+    async fn call(&self, request: &StateRequest) -> Result<Option<String>, Error> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            let (read, write) = UnixStream::connect(&self.state_path).await?.into_split();
+            *connection = Some(StateConnection {
+                lines: BufReader::new(read).lines(),
+                write,
+            });
+        }
+        let conn = connection.as_mut().unwrap();
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        let result = match conn.write.write_all(&line).await {
+            Ok(()) if matches!(request, StateRequest::Acquire { .. }) => {
+                conn.lines.next_line().await.and_then(|line| {
+                    line.map(Some)
+                        .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+                })
+            }
+            Ok(()) => Ok(None),
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            // reconnected on the next call
+            *connection = None;
+        }
+        Ok(result?)
+    }
+
+    async fn acquire(&self, service: &str, key: Uuid, max_workers: usize) -> Result<bool, Error> {
+        let request = StateRequest::Acquire {
+            service: service.to_string(),
+            key,
+            max_workers,
+        };
+        let line = self
+            .call(&request)
+            .await?
+            .ok_or_else(|| anyhow!("no reply from the cluster state"))?;
+        let reply: AcquireReply = serde_json::from_str(&line)?;
+        if reply.granted {
+            self.leased.lock().unwrap().insert(key);
+        }
+        Ok(reply.granted)
+    }
+
+    async fn release(&self, key: Uuid) {
+        if !self.leased.lock().unwrap().remove(&key) {
+            return;
+        }
+        if let Err(err) = self.call(&StateRequest::Release { key }).await {
+            error!("failed to release worker {} in the cluster: {}", key, err);
+        }
+    }
+}
+
+static CLUSTER_MEMBER: OnceLock<ClusterMember> = OnceLock::new();
+// leases of the services limited by `maxWorkers` outside cluster mode
+static LOCAL_LEASES: OnceLock<Mutex<LeaseTable>> = OnceLock::new();
+
+// Joins the cluster this process was started in by the supervising process, if
+// any
+pub fn join_cluster() -> Result<Option<&'static ClusterMember>, Error> {
+    let (Some(index), Some(state_path)) = (
+        std::env::var_os(CLUSTER_MEMBER_ENV),
+        std::env::var_os(CLUSTER_STATE_ENV),
+    ) else {
+        return Ok(None);
+    };
+    let index = index
+        .to_str()
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| anyhow!("invalid {}: {:?}", CLUSTER_MEMBER_ENV, index))?;
+    let member = CLUSTER_MEMBER.get_or_init(|| ClusterMember {
+        index,
+        state_path: PathBuf::from(state_path),
+        connection: Default::default(),
+        leased: Default::default(),
+    });
+    Ok(Some(member))
+}
+
+pub fn cluster_member() -> Option<&'static ClusterMember> {
+    CLUSTER_MEMBER.get()
+}
+
+pub fn is_cluster_member() -> bool {
+    std::env::var_os(CLUSTER_MEMBER_ENV).is_some()
+}
+
+// The process runs the node's one-off duties (the admin API, the alarm scheduler,
+// the webhook dispatcher, ...): it's not in a cluster, or its first member
+pub fn is_cluster_leader() -> bool {
+    cluster_member().map_or(true, |member| member.index == 0)
+}
+
+fn acquire_local_slot(service: &str, key: Uuid, max_workers: usize) -> bool {
+    let request = StateRequest::Acquire {
+        service: service.to_string(),
+        key,
+        max_workers,
+    };
+    let mut leases = LOCAL_LEASES.get_or_init(Default::default).lock().unwrap();
+    leases.handle(0, request).is_some_and(|reply| reply.granted)
+}
+
+// Reserves a slot for a new worker of a service that may run at most
+// `max_workers` (across the cluster in cluster mode). The state of the cluster
+// being unreachable doesn't keep workers from booting.
+pub async fn acquire_worker_slot(
+    service: &str,
+    key: Uuid,
+    max_workers: usize,
+) -> Result<(), Error> {
+    let granted = match cluster_member() {
+        Some(member) => match member.acquire(service, key, max_workers).await {
+            Ok(granted) => granted,
+            Err(err) => {
+                warn!(
+                    "cluster state unavailable, not limiting the workers of {}: {}",
+                    service, err
+                );
+                true
+            }
+        },
+        None => acquire_local_slot(service, key, max_workers),
+    };
+    if !granted {
+        return Err(WorkerCreationRejected(format!(
+            "{} already runs its maximum of {} workers",
+            service, max_workers
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// Frees the slot of a worker that shut down (or failed to boot)
+pub fn release_worker_slot(key: Uuid) {
+    match cluster_member() {
+        Some(member) => {
+            tokio::task::spawn(member.release(key));
+        }
+        None => {
+            if let Some(leases) = LOCAL_LEASES.get() {
+                leases
+                    .lock()
+                    .unwrap()
+                    .handle(0, StateRequest::Release { key });
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod supervisor {
+    use super::{
+        LeaseTable, StateRequest, CLUSTER_MEMBER_ENV, CLUSTER_STATE_ENV, RESTART_DELAY,
+        STOP_TIMEOUT,
+    };
+    use anyhow::{bail, Context, Error};
+    use deno_core::futures::future::select_all;
+    use deno_core::serde_json;
+    use log::{error, info};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::process::{Child, Command};
+    use tokio::signal::unix::{signal, SignalKind};
+
+    async fn serve_member(stream: UnixStream, owner: usize, leases: Arc<Mutex<LeaseTable>>) {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let request: StateRequest = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(err) => {
+                    error!("invalid cluster state request: {}", err);
+                    break;
+                }
+            };
+            let reply = leases.lock().unwrap().handle(owner, request);
+            if let Some(reply) = reply {
+                let mut line = serde_json::to_vec(&reply).unwrap();
+                line.push(b'\n');
+                if write.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        }
+        leases.lock().unwrap().release_owner(owner);
+    }
+
+    async fn serve_state(listener: UnixListener) {
+        let leases = Arc::new(Mutex::new(LeaseTable::default()));
+        let mut next_owner = 0;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    next_owner += 1;
+                    tokio::task::spawn(serve_member(stream, next_owner, leases.clone()));
+                }
+                Err(err) => error!("cluster state socket error: {}", err),
+            }
+        }
+    }
+
+    // Execs this binary again, with the same arguments, as a member of the cluster
+    fn spawn_member(index: usize, state_path: &Path) -> Result<Child, Error> {
+        let child = Command::new(std::env::current_exe()?)
+            .args(std::env::args_os().skip(1))
+            .env(CLUSTER_MEMBER_ENV, index.to_string())
+            .env(CLUSTER_STATE_ENV, state_path)
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to start cluster member {}", index))?;
+        info!("cluster member {} started ({:?})", index, child.id());
+        Ok(child)
+    }
+
+    async fn stop_members(members: &mut [Child]) {
+        for member in members.iter() {
+            if let Some(pid) = member.id() {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) };
+            }
+        }
+        for member in members.iter_mut() {
+            if tokio::time::timeout(STOP_TIMEOUT, member.wait())
+                .await
+                .is_err()
+            {
+                let _ = member.kill().await;
+            }
+        }
+    }
+
+    // Runs the members of the cluster until a shutdown signal (SIGINT or SIGTERM),
+    // restarting those that exit
+    pub async fn run_cluster(size: usize) -> Result<(), Error> {
+        if size == 0 {
+            bail!("a cluster needs at least one member");
+        }
+        let state_path =
+            std::env::temp_dir().join(format!("edge-runtime-cluster-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&state_path);
+        let listener = UnixListener::bind(&state_path)?;
+        tokio::task::spawn(serve_state(listener));
+
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut members = (0..size)
+            .map(|index| spawn_member(index, &state_path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        loop {
+            let exited = tokio::select! {
+                (status, index, _) = select_all(members.iter_mut().map(|member| Box::pin(member.wait()))) => {
+                    Some((index, status))
+                }
+                _ = interrupt.recv() => None,
+                _ = terminate.recv() => None,
+            };
+            let Some((index, status)) = exited else {
+                info!("shutdown signal received, stopping the cluster members");
+                stop_members(&mut members).await;
+                break;
+            };
+            match status {
+                Ok(status) => error!(
+                    "cluster member {} exited ({}), restarting it",
+                    index, status
+                ),
+                Err(err) => error!("failed to wait for cluster member {}: {}", index, err),
+            }
+            tokio::time::sleep(RESTART_DELAY).await;
+            members[index] = spawn_member(index, &state_path)?;
+        }
+
+        let _ = std::fs::remove_file(&state_path);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub use supervisor::run_cluster;
+
+#[cfg(not(unix))]
+pub async fn run_cluster(_size: usize) -> Result<(), Error> {
+    anyhow::bail!("cluster mode is only supported on unix")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AcquireReply, LeaseTable, StateRequest};
+    use uuid::Uuid;
+
+    fn acquire(service: &str, key: Uuid) -> StateRequest {
+        StateRequest::Acquire {
+            service: service.to_string(),
+            key,
+            max_workers: 2,
+        }
+    }
+
+    #[test]
+    fn test_lease_table() {
+        let mut leases = LeaseTable::default();
+        let (k1, k2, k3) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let granted = |workers| {
+            Some(AcquireReply {
+                granted: true,
+                workers,
+            })
+        };
+        assert_eq!(leases.handle(1, acquire("/srv/hello", k1)), granted(1));
+        assert_eq!(leases.handle(2, acquire("/srv/hello", k2)), granted(2));
+        assert_eq!(
+            leases.handle(1, acquire("/srv/hello", k3)),
+            Some(AcquireReply {
+                granted: false,
+                workers: 2,
+            })
+        );
+        // other services have slots of their own
+        assert_eq!(leases.handle(1, acquire("/srv/other", k3)), granted(1));
+
+        assert_eq!(leases.handle(2, StateRequest::Release { key: k2 }), None);
+        assert_eq!(leases.workers("/srv/hello"), 1);
+        // a member leaving releases its workers
+        leases.release_owner(1);
+        assert_eq!(leases.workers("/srv/hello"), 0);
+        assert_eq!(leases.workers("/srv/other"), 0);
+    }
+
+    #[test]
+    fn test_state_request_format() {
+        let key = Uuid::nil();
+        let line = deno_core::serde_json::to_string(&acquire("/srv/hello", key)).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                r#"{{"op":"acquire","service":"/srv/hello","key":"{}","maxWorkers":2}}"#,
+                key
+            )
+        );
+    }
+}
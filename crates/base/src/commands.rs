@@ -1,4 +1,4 @@
-use crate::server::{Server, ServerCodes, WorkerEntrypoints};
+use crate::server::{Server, ServerCodes, ServerFlags, WorkerEntrypoints};
 use anyhow::Error;
 use tokio::sync::mpsc::Sender;
 
@@ -12,6 +12,7 @@ pub async fn start_server(
     no_module_cache: bool,
     callback_tx: Option<Sender<ServerCodes>>,
     entrypoints: WorkerEntrypoints,
+    flags: ServerFlags,
 ) -> Result<(), Error> {
     let mut server = Server::new(
         ip,
@@ -22,6 +23,7 @@ pub async fn start_server(
         no_module_cache,
         callback_tx,
         entrypoints,
+        flags,
     )
     .await?;
     server.listen().await
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+static CONNECTION_METRICS: ConnectionMetrics = ConnectionMetrics {
+    accepted: AtomicU64::new(0),
+    open: AtomicU64::new(0),
+    errors: AtomicU64::new(0),
+    requests: AtomicU64::new(0),
+    duration_ms: AtomicU64::new(0),
+};
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionSnapshot {
+    pub accepted: u64,
+    pub open: u64,
+    // connections closed with an error, eg: clients going away mid-response
+    pub errors: u64,
+    pub requests: u64,
+    pub requests_per_connection: f64,
+    // of the closed connections
+    pub avg_duration_ms: f64,
+}
+
+// Metrics of the HTTP connections served by the runtime
+pub struct ConnectionMetrics {
+    accepted: AtomicU64,
+    open: AtomicU64,
+    errors: AtomicU64,
+    requests: AtomicU64,
+    // lifetime of the closed connections, summed up
+    duration_ms: AtomicU64,
+}
+
+pub fn connection_metrics() -> &'static ConnectionMetrics {
+    &CONNECTION_METRICS
+}
+
+impl ConnectionMetrics {
+    pub fn accept(&'static self) -> ConnectionGuard {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.open.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: self,
+            opened: Instant::now(),
+        }
+    }
+
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        let accepted = self.accepted.load(Ordering::Relaxed);
+        let open = self.open.load(Ordering::Relaxed);
+        let requests = self.requests.load(Ordering::Relaxed);
+        let closed = accepted.saturating_sub(open).max(1) as f64;
+        ConnectionSnapshot {
+            accepted,
+            open,
+            errors: self.errors.load(Ordering::Relaxed),
+            requests,
+            requests_per_connection: requests as f64 / accepted.max(1) as f64,
+            avg_duration_ms: self.duration_ms.load(Ordering::Relaxed) as f64 / closed,
+        }
+    }
+}
+
+// An open connection, counted as closed once dropped
+pub struct ConnectionGuard {
+    metrics: &'static ConnectionMetrics,
+    opened: Instant,
+}
+
+impl ConnectionGuard {
+    pub fn failed(&self) {
+        self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.open.fetch_sub(1, Ordering::Relaxed);
+        self.metrics
+            .duration_ms
+            .fetch_add(self.opened.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
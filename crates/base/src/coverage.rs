@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Error};
+use deno_core::ModuleCode;
+use module_fetcher::util::text_encoding::source_map_from_code;
+use sb_core::coverage::{CoverageRange, ScriptCoverage};
+use sourcemap::SourceMap;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+#[derive(Debug, Default)]
+struct FileCoverage {
+    // hits by (1-based) line of the original file
+    lines: BTreeMap<u32, u64>,
+    // calls by function, with the line it starts on
+    functions: BTreeMap<(u32, String), u64>,
+}
+
+// A line of the emitted source, in UTF-16 offsets like V8's ranges
+struct GeneratedLine {
+    start: usize,
+    // first non whitespace character, `None` for blank lines
+    code: Option<usize>,
+}
+
+fn generated_lines(source: &str) -> Vec<GeneratedLine> {
+    let mut lines = vec![];
+    let mut line = GeneratedLine {
+        start: 0,
+        code: None,
+    };
+    let mut offset = 0;
+    for ch in source.chars() {
+        if ch == '\n' {
+            lines.push(line);
+            line = GeneratedLine {
+                start: offset + 1,
+                code: None,
+            };
+        } else if line.code.is_none() && !ch.is_whitespace() {
+            line.code = Some(offset);
+        }
+        offset += ch.len_utf16();
+    }
+    lines.push(line);
+    lines
+}
+
+// Count of the innermost range containing `offset`
+fn count_at(ranges: &[&CoverageRange], offset: usize) -> Option<u64> {
+    ranges
+        .iter()
+        .filter(|r| r.start_offset <= offset && offset < r.end_offset)
+        .min_by_key(|r| r.end_offset - r.start_offset)
+        .map(|r| r.count)
+}
+
+// Maps emitted positions back to the original file through its source map,
+// untranspiled (JavaScript) modules map to themselves
+struct LineMapper {
+    source_map: Option<SourceMap>,
+}
+
+impl LineMapper {
+    fn new(source: &str) -> Result<Self, Error> {
+        let source_map = match source_map_from_code(&ModuleCode::from(source.to_string())) {
+            Some(bytes) => Some(SourceMap::from_slice(&bytes)?),
+            None => None,
+        };
+        Ok(Self { source_map })
+    }
+
+    // 1-based line of the original file
+    fn original_line(&self, line: usize, column: usize) -> Option<u32> {
+        let Some(source_map) = &self.source_map else {
+            return Some(line as u32 + 1);
+        };
+        let token = source_map.lookup_token(line as u32, column as u32)?;
+        // the closest token can be on a previous line
+        (token.get_dst_line() == line as u32).then_some(token.get_src_line() + 1)
+    }
+}
+
+// Coverage of the modules run by the tests, merged across test files
+#[derive(Debug, Default)]
+pub struct CoverageReport {
+    files: BTreeMap<PathBuf, FileCoverage>,
+}
+
+impl CoverageReport {
+    // Only adds the files `include` accepts
+    pub fn add(
+        &mut self,
+        script: &ScriptCoverage,
+        include: impl Fn(&Path) -> bool,
+    ) -> Result<(), Error> {
+        let path = Url::parse(&script.url)?
+            .to_file_path()
+            .map_err(|_| anyhow!("not a local module: {}", script.url))?;
+        if !include(&path) {
+            return Ok(());
+        }
+
+        let mapper = LineMapper::new(&script.source)?;
+        let lines = generated_lines(&script.source);
+        let ranges: Vec<&CoverageRange> = script.functions.iter().flat_map(|f| &f.ranges).collect();
+        let file = self.files.entry(path).or_default();
+
+        // a line of the original file can be emitted on several lines, it's hit
+        // if any of them is
+        let mut hits = BTreeMap::new();
+        for (index, line) in lines.iter().enumerate() {
+            let Some(code) = line.code else {
+                continue;
+            };
+            let (Some(count), Some(original)) = (
+                count_at(&ranges, code),
+                mapper.original_line(index, code - line.start),
+            ) else {
+                continue;
+            };
+            let hit = hits.entry(original).or_insert(0);
+            *hit = count.max(*hit);
+        }
+        for (line, count) in hits {
+            *file.lines.entry(line).or_insert(0) += count;
+        }
+
+        for function in &script.functions {
+            let Some(range) = function.ranges.first() else {
+                continue;
+            };
+            // the module itself is reported as an anonymous function
+            if function.function_name.is_empty() {
+                continue;
+            }
+            let index = lines
+                .partition_point(|line| line.start <= range.start_offset)
+                .saturating_sub(1);
+            let Some(line) = mapper.original_line(index, range.start_offset - lines[index].start)
+            else {
+                continue;
+            };
+            *file
+                .functions
+                .entry((line, function.function_name.clone()))
+                .or_insert(0) += range.count;
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+        for (path, file) in &self.files {
+            writeln!(out, "SF:{}", path.display()).unwrap();
+            for (line, name) in file.functions.keys() {
+                writeln!(out, "FN:{},{}", line, name).unwrap();
+            }
+            for ((_, name), count) in &file.functions {
+                writeln!(out, "FNDA:{},{}", count, name).unwrap();
+            }
+            writeln!(out, "FNF:{}", file.functions.len()).unwrap();
+            let functions_hit = file.functions.values().filter(|c| **c > 0).count();
+            writeln!(out, "FNH:{}", functions_hit).unwrap();
+            for (line, count) in &file.lines {
+                writeln!(out, "DA:{},{}", line, count).unwrap();
+            }
+            writeln!(out, "LF:{}", file.lines.len()).unwrap();
+            let lines_hit = file.lines.values().filter(|c| **c > 0).count();
+            writeln!(out, "LH:{}", lines_hit).unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoverageReport;
+    use sb_core::coverage::{CoverageRange, FunctionCoverage, ScriptCoverage};
+
+    fn range(start_offset: usize, end_offset: usize, count: u64) -> CoverageRange {
+        CoverageRange {
+            start_offset,
+            end_offset,
+            count,
+        }
+    }
+
+    #[test]
+    fn test_lcov_report() {
+        let source = "function add(a, b) {\n  return a + b;\n}\nfunction sub(a, b) {\n  return a - b;\n}\nadd(1, 2);\nadd(3, 4);\n";
+        let script = ScriptCoverage {
+            url: "file:///app/math.js".to_string(),
+            source: source.to_string(),
+            functions: vec![
+                FunctionCoverage {
+                    function_name: String::new(),
+                    ranges: vec![range(0, source.len(), 1)],
+                },
+                FunctionCoverage {
+                    function_name: "add".to_string(),
+                    ranges: vec![range(0, 38, 2)],
+                },
+                FunctionCoverage {
+                    function_name: "sub".to_string(),
+                    ranges: vec![range(39, 77, 0)],
+                },
+            ],
+        };
+
+        let mut report = CoverageReport::default();
+        report.add(&script, |_| true).unwrap();
+        // another test file running the same module
+        report.add(&script, |_| true).unwrap();
+        assert_eq!(
+            report.to_lcov(),
+            "SF:/app/math.js\n\
+             FN:1,add\n\
+             FN:4,sub\n\
+             FNDA:4,add\n\
+             FNDA:0,sub\n\
+             FNF:2\n\
+             FNH:1\n\
+             DA:1,4\n\
+             DA:2,4\n\
+             DA:3,4\n\
+             DA:4,0\n\
+             DA:5,0\n\
+             DA:6,0\n\
+             DA:7,2\n\
+             DA:8,2\n\
+             LF:8\n\
+             LH:5\n\
+             end_of_record\n"
+        );
+
+        let mut report = CoverageReport::default();
+        report.add(&script, |_| false).unwrap();
+        assert!(report.is_empty());
+    }
+}
@@ -1,48 +1,101 @@
-use crate::utils::units::mib_to_bytes;
-
 use crate::js_worker::module_loader;
 use anyhow::{anyhow, bail, Error};
 use deno_core::error::AnyError;
+use deno_core::futures::future::LocalBoxFuture;
+use deno_core::futures::FutureExt;
 use deno_core::url::Url;
-use deno_core::{located_script_name, serde_v8, JsRuntime, ModuleCode, ModuleId, RuntimeOptions};
+use deno_core::{
+    located_script_name, serde_v8, JsRuntime, ModuleCode, ModuleId, RuntimeOptions,
+    SharedArrayBufferStore,
+};
 use deno_http::DefaultHttpPropertyExtractor;
 use deno_tls::rustls;
 use deno_tls::rustls::RootCertStore;
 use deno_tls::rustls_native_certs::load_native_certs;
 use deno_tls::RootCertStoreProvider;
 use import_map::{parse_from_json, ImportMap};
-use log::error;
+use log::{error, warn};
 use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::future::poll_fn;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{fmt, fs};
-use tokio::net::UnixStream;
 use tokio::sync::mpsc;
 use urlencoding::decode;
 
+use crate::ai_gateway::ai_gateway;
+use crate::alarms::alarm_store;
+use crate::broadcast::ServiceBroadcastChannel;
 use crate::cert::ValueRootCertStoreProvider;
+use crate::embed::{
+    custom_esm_sources, custom_extensions, custom_module_source_provider, EsmStage,
+};
+use crate::embeddings::embedding_models;
+use crate::fault_injection::inject_boot_delay;
+use crate::feature_flags::feature_flags_rx;
+use crate::gc::{gc_tuning, heap_limits, notify_idle, notify_pressure, GcNudges, IdleGc};
+use crate::images::image_limits;
 use crate::js_worker::emitter::EmitterFactory;
+use crate::key_store::key_store;
+use crate::mail::mailer;
+use crate::node::node_identity;
+use crate::onnx::model_runner;
+use crate::outbound_webhooks::webhook_store;
+use crate::redis_pool::redis_pool;
+use crate::replay::replay_seed;
+use crate::rt_worker::pending_ops::{cancel_pending_ops, CancelledOps};
+use crate::rt_worker::uncaught_errors::{handle_uncaught_error, survivable_error_kind};
+use crate::rt_worker::watchdog::EventLoopWatch;
+use crate::rt_worker::web_worker::web_workers_for;
+use crate::sequences::sequences;
+use crate::service_config::ServiceConfig;
+use crate::service_env::load_service_env;
+use crate::test_runtime::test_clock_enabled;
+use crate::timeline::{timelines, worker_track};
 use crate::{errors_rt, snapshot};
-use event_worker::events::{EventMetadata, WorkerEventWithMetadata};
+use event_worker::events::{
+    BootModule, EventMetadata, UncaughtErrorPolicy, WorkerEventWithMetadata,
+};
 use event_worker::js_interceptors::sb_events_js_interceptors;
-use event_worker::sb_user_event_worker;
+use event_worker::{sb_user_event_worker, AcceptedEvents};
+use module_fetcher::file_fetcher::CacheSetting;
 use module_fetcher::util::diagnostic::print_import_map_diagnostics;
-use module_loader::DefaultModuleLoader;
+use module_loader::{BootManifest, DefaultModuleLoader};
+use sb_core::coverage::{start_coverage, CoverageSession};
 use sb_core::http_start::sb_core_http;
+use sb_core::images::ImageLimits;
 use sb_core::net::sb_core_net;
 use sb_core::permissions::{sb_core_permissions, Permissions};
 use sb_core::runtime::sb_core_runtime;
 use sb_core::sb_core_main_js;
+use sb_core::templates::{WorkerTemplates, TEMPLATES_DIR};
+use sb_core::test_clock::TestClock;
 use sb_env::sb_env as sb_env_op;
-use sb_eszip::module_loader::EszipModuleLoader;
+use sb_eszip::module_loader::{EszipModuleLoader, ModuleLoadStats};
 use sb_node::deno_node;
+use sb_worker_context::ai::WorkerAi;
+use sb_worker_context::alarms::WorkerAlarms;
+use sb_worker_context::embeddings::SharedEmbeddingModels;
 use sb_worker_context::essentials::{UserWorkerMsgs, WorkerContextInitOpts, WorkerRuntimeOpts};
+use sb_worker_context::flags::{flag_service_name, WorkerFeatureFlags};
+use sb_worker_context::keys::WorkerKeys;
+use sb_worker_context::listen::ListenPermissions;
+use sb_worker_context::mail::WorkerMailer;
+use sb_worker_context::manifest::SharedManifest;
+use sb_worker_context::onnx::WorkerModels;
+use sb_worker_context::outbound_webhooks::WorkerWebhooks;
+use sb_worker_context::redis::WorkerRedis;
+use sb_worker_context::request_metadata::WorkerConnection;
+use sb_worker_context::sequences::WorkerSequences;
+use sb_worker_context::service_scope::service_scope;
+use sb_worker_context::web_workers::{WebWorkerPort, WebWorkers, WorkerBudget, WorkerBudgetAlarms};
 use sb_workers::sb_user_workers;
 
-fn load_import_map(maybe_path: Option<String>) -> Result<Option<ImportMap>, Error> {
+pub(crate) fn load_import_map(maybe_path: Option<String>) -> Result<Option<ImportMap>, Error> {
     if let Some(path_str) = maybe_path {
         let json_str;
         let base_url;
@@ -104,16 +157,76 @@ fn set_v8_flags() {
     );
 }
 
+// Evaluates ESM sources of extensions registered by the embedder
+async fn evaluate_custom_esm(
+    js_runtime: &mut JsRuntime,
+    sources: Vec<(String, String)>,
+) -> Result<(), Error> {
+    for (specifier, code) in sources {
+        let specifier = Url::parse(&specifier)?;
+        let mod_id = js_runtime
+            .load_side_module(&specifier, Some(ModuleCode::from(code)))
+            .await?;
+        let mod_result_rx = js_runtime.mod_evaluate(mod_id);
+        js_runtime.run_event_loop(false).await?;
+        mod_result_rx
+            .await
+            .map_err(|_| anyhow!("mod result sender dropped"))??;
+    }
+    Ok(())
+}
+
+// Polls the event loop until `fut` resolves, for inspector messages that are
+// only answered while it runs
+async fn with_event_loop<T>(js_runtime: &mut JsRuntime, mut fut: LocalBoxFuture<'_, T>) -> T {
+    loop {
+        tokio::select! {
+            biased;
+            res = &mut fut => return res,
+            _ = js_runtime.run_event_loop(false) => {}
+        }
+    }
+}
+
 pub struct DenoRuntime {
     pub js_runtime: JsRuntime,
     pub env_vars: HashMap<String, String>, // TODO: does this need to be pub?
     main_module_id: ModuleId,
     pub conf: WorkerRuntimeOpts,
+    // remote modules downloaded because they were not in the module cache
+    pub module_downloads: Arc<AtomicU64>,
+    // modules loaded from the bundle, for eszip services
+    pub module_loads: Option<Arc<ModuleLoadStats>>,
+    // modules loaded while booting, reported with the boot event
+    pub boot_modules: Vec<BootModule>,
+    pub event_loop_watch: EventLoopWatch,
+    // the ops cancelled when the worker stopped, reported with its shutdown event
+    pub cancelled_ops: Arc<Mutex<CancelledOps>>,
+    // shared by a user worker and its web workers, the alarms are received by
+    // the supervisor of the user worker
+    pub budget: Option<WorkerBudget>,
+    pub budget_alarms: Option<WorkerBudgetAlarms>,
 }
 
 impl DenoRuntime {
     #[allow(clippy::unnecessary_literal_unwrap)]
     pub async fn new(opts: WorkerContextInitOpts) -> Result<Self, Error> {
+        // shared by a user worker and its web workers
+        let shared_array_buffers = opts
+            .conf
+            .as_user_worker()
+            .and_then(|conf| conf.web_worker.as_ref())
+            .map(|port| port.shared_array_buffers.clone())
+            .unwrap_or_default();
+        let (maybe_web_workers, budget_alarms) =
+            web_workers_for(&opts, &shared_array_buffers).unzip();
+        let budget = opts
+            .conf
+            .as_user_worker()
+            .and_then(|conf| conf.web_worker.as_ref())
+            .map(|port| port.budget.clone())
+            .or_else(|| maybe_web_workers.as_ref().map(|w| w.budget().clone()));
+
         let WorkerContextInitOpts {
             service_path,
             no_module_cache,
@@ -133,10 +246,46 @@ impl DenoRuntime {
 
         set_v8_flags();
 
+        if conf.is_user_worker() {
+            if let Some(delay) = inject_boot_delay() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        let flag_service = flag_service_name(&service_path.to_string_lossy());
+        let service = service_scope(&service_path);
+        let maybe_worker_alarms = match (alarm_store(), conf.as_user_worker()) {
+            (Some(store), Some(user_conf)) => Some(WorkerAlarms {
+                store,
+                service_path: user_conf
+                    .service_path
+                    .clone()
+                    .unwrap_or_else(|| service_path.to_string_lossy().to_string()),
+                import_map_path: import_map_path.clone(),
+                entrypoint: maybe_entrypoint.clone(),
+            }),
+            _ => None,
+        };
         let user_agent = "supabase-edge-runtime".to_string();
         let base_dir_path = std::env::current_dir().map(|p| p.join(&service_path))?;
         let base_url = Url::from_directory_path(&base_dir_path).unwrap();
 
+        // templates are compiled at boot, so broken templates fail the boot
+        let templates_dir = base_dir_path.join(TEMPLATES_DIR);
+        let maybe_templates = if !conf.is_events_worker() && templates_dir.is_dir() {
+            Some(WorkerTemplates::load(&templates_dir)?)
+        } else {
+            None
+        };
+
+        // the `.env` files of a service are read each time one of its workers
+        // is created, on top of the env vars it's created with
+        let env_vars = if conf.is_user_worker() && base_dir_path.is_dir() {
+            load_service_env(&base_dir_path, env_vars)?
+        } else {
+            env_vars
+        };
+
         // TODO: check for other potential main paths (eg: index.js, index.tsx)
         let mut main_module_url = base_url.join("index.ts")?;
         if maybe_entrypoint.is_some() {
@@ -203,7 +352,7 @@ impl DenoRuntime {
             });
         }
         let fs = Arc::new(deno_fs::RealFs);
-        let extensions = vec![
+        let mut extensions = vec![
             sb_core_permissions::init_ops(net_access_disabled),
             deno_webidl::deno_webidl::init_ops(),
             deno_console::deno_console::init_ops(),
@@ -224,9 +373,10 @@ impl DenoRuntime {
             ),
             // TODO: support providing a custom seed for crypto
             deno_crypto::deno_crypto::init_ops(None),
-            deno_broadcast_channel::deno_broadcast_channel::init_ops(
-                deno_broadcast_channel::InMemoryBroadcastChannel::default(),
-            ),
+            // workers of the same service share their broadcast channels
+            deno_broadcast_channel::deno_broadcast_channel::init_ops(ServiceBroadcastChannel::new(
+                &service_path.to_string_lossy(),
+            )),
             deno_net::deno_net::init_ops::<Permissions>(Some(root_cert_store_provider), None),
             deno_tls::deno_tls::init_ops(),
             deno_http::deno_http::init_ops::<DefaultHttpPropertyExtractor>(),
@@ -243,59 +393,173 @@ impl DenoRuntime {
             deno_node::init_ops::<Permissions>(None, fs),
             sb_core_runtime::init_ops(Some(main_module_url.clone())),
         ];
+        extensions.extend(custom_extensions(&conf));
 
+        let coverage = conf.as_user_worker().is_some_and(|conf| conf.coverage);
+        // module fetches of user workers are recorded on the worker's timeline
+        let timeline_track = timelines()
+            .and(conf.as_user_worker())
+            .and_then(|conf| Some(worker_track(conf.service_path.as_deref()?, &conf.key?)));
         let mut runtime_options = RuntimeOptions {
             extensions,
             is_main: true,
-            create_params: {
-                if conf.is_user_worker() {
-                    Some(deno_core::v8::CreateParams::default().heap_limits(
-                        mib_to_bytes(0) as usize,
-                        mib_to_bytes(conf.as_user_worker().unwrap().memory_limit_mb) as usize,
-                    ))
-                } else {
-                    None
-                }
-            },
+            create_params: heap_limits(&conf).map(|(initial, max)| {
+                deno_core::v8::CreateParams::default().heap_limits(initial, max)
+            }),
             get_error_class_fn: Some(&get_error_class_name),
-            shared_array_buffer_store: None,
+            shared_array_buffer_store: Some(shared_array_buffers),
             compiled_wasm_module_store: Default::default(),
             startup_snapshot: Some(snapshot::snapshot()),
+            // only needed to collect coverage
+            inspector: coverage,
             ..Default::default()
         };
+        let module_downloads = Arc::new(AtomicU64::new(0));
+        // runtime API version of a bundle, used to enable compatibility shims
+        let mut maybe_api_version = None;
+        let mut module_loads = None;
+        let mut boot_manifest = BootManifest::default();
         if maybe_eszip.is_some() {
-            let eszip_module_loader =
+            let mut eszip_module_loader =
                 EszipModuleLoader::new(maybe_eszip.unwrap(), import_map_path).await?;
+            // modules loaded on demand land on the worker's timeline next to the
+            // requests that imported them
+            if let Some(track) = timeline_track.clone() {
+                eszip_module_loader =
+                    eszip_module_loader.with_load_hook(Rc::new(move |specifier, start, on_demand| {
+                        if let Some(timelines) = timelines() {
+                            let args = deno_core::serde_json::json!({ "specifier": specifier.as_str(), "onDemand": on_demand });
+                            timelines.span(&track, "module", "load", start, args);
+                        }
+                    }));
+            }
+            maybe_api_version = Some(eszip_module_loader.api_version());
+            module_loads = Some(eszip_module_loader.load_stats());
             runtime_options.module_loader = Some(Rc::new(eszip_module_loader));
         } else {
+            // a deno.json of the service is honored as under `deno run`, its
+            // import map unless one was given
+            let maybe_config = if base_dir_path.is_dir() {
+                ServiceConfig::discover(&base_dir_path)?
+            } else {
+                None
+            };
+            let import_map_path = import_map_path.or_else(|| {
+                maybe_config
+                    .as_ref()
+                    .and_then(|config| config.import_map_path())
+            });
             let import_map = load_import_map(import_map_path)?;
             let emitter = EmitterFactory::new();
+            let emit_options = match &maybe_config {
+                Some(config) => config.emit_options(emitter.emit_options())?,
+                None => emitter.emit_options(),
+            };
 
+            let cache_setting = match conf.as_user_worker() {
+                _ if no_module_cache => CacheSetting::ReloadAll,
+                Some(conf) if !conf.reload_modules.is_empty() => {
+                    CacheSetting::ReloadSome(conf.reload_modules.clone())
+                }
+                _ => CacheSetting::Use,
+            };
             let default_module_loader = DefaultModuleLoader::new(
                 module_root_path,
                 import_map,
-                emitter.emitter().unwrap(),
-                no_module_cache,
+                emitter.emitter_with_options(emit_options)?,
+                cache_setting,
                 allow_remote_modules,
+                Some(module_downloads.clone()),
+                custom_module_source_provider(),
             )?;
+            let default_module_loader = match conf.as_user_worker() {
+                Some(conf) => default_module_loader.with_mocks(&conf.module_mocks)?,
+                None => default_module_loader,
+            };
+            let default_module_loader = match conf.as_user_worker() {
+                Some(conf) if conf.freeze_remote_origins => {
+                    default_module_loader.with_frozen_origins()
+                }
+                _ => default_module_loader,
+            };
+            let default_module_loader = match &timeline_track {
+                Some(track) => default_module_loader.with_timeline_track(track.clone()),
+                None => default_module_loader,
+            };
+            let locked_modules = match &maybe_config {
+                Some(config) => config.locked_modules()?,
+                None => None,
+            };
+            let default_module_loader = match locked_modules {
+                Some(locked_modules) => default_module_loader.with_locked_modules(locked_modules),
+                None => default_module_loader,
+            };
+            boot_manifest = default_module_loader.boot_manifest();
             runtime_options.module_loader = Some(Rc::new(default_module_loader));
         }
         let mut js_runtime = JsRuntime::new(runtime_options);
 
         let version: Option<&str> = option_env!("GIT_V_TAG");
 
+        let test_clock =
+            test_clock_enabled() || conf.as_user_worker().is_some_and(|conf| conf.test_clock);
+        let disallow_code_generation = conf
+            .as_user_worker()
+            .is_some_and(|conf| conf.disallow_code_generation);
+        let deterministic = conf.as_user_worker().and_then(|conf| conf.deterministic);
+        let timer_policy = conf.as_user_worker().and_then(|conf| conf.timer_policy);
+        let detect_leaks = conf.as_user_worker().is_some_and(|conf| conf.detect_leaks);
+        if deterministic.is_some() {
+            warn!(
+                "worker of {} runs in deterministic mode, its random values are predictable (never use it in production)",
+                service_path.display()
+            );
+        }
+
         // Bootstrapping stage
         let script = format!(
             "globalThis.bootstrapSBEdge({}, {}, {}, '{}')",
-            deno_core::serde_json::json!({ "target": env!("TARGET") }),
+            deno_core::serde_json::json!({
+                "target": env!("TARGET"),
+                "replay": replay_seed(),
+                "testClock": test_clock,
+                "coverage": coverage,
+                "codeGeneration": !disallow_code_generation,
+                "deterministic": deterministic,
+                "timers": timer_policy,
+                "detectLeaks": detect_leaks,
+                "node": node_identity(),
+                "apiVersion": maybe_api_version,
+                "webWorker": conf
+                    .as_user_worker()
+                    .and_then(|conf| conf.web_worker.as_ref())
+                    .map(|port| deno_core::serde_json::json!({ "name": port.name })),
+            }),
             conf.is_user_worker(),
             conf.is_events_worker(),
             version.unwrap_or("0.1.0")
         );
 
+        evaluate_custom_esm(
+            &mut js_runtime,
+            custom_esm_sources(&conf, EsmStage::BeforeBootstrap),
+        )
+        .await?;
         js_runtime
             .execute_script(located_script_name!(), ModuleCode::from(script))
             .expect("Failed to execute bootstrap script");
+        evaluate_custom_esm(
+            &mut js_runtime,
+            custom_esm_sources(&conf, EsmStage::AfterBootstrap),
+        )
+        .await?;
+
+        // once booted, so the runtime's own scripts can still be evaluated
+        if disallow_code_generation {
+            let scope = &mut js_runtime.handle_scope();
+            let context = scope.get_current_context();
+            context.set_allow_generation_from_strings(false);
+        }
 
         {
             //run inside a closure, so op_state_rc is released
@@ -304,57 +568,214 @@ impl DenoRuntime {
             let mut op_state = op_state_rc.borrow_mut();
             op_state.put::<sb_env::EnvVars>(env_vars);
 
+            if !conf.is_events_worker() {
+                if let Some(limits) = image_limits() {
+                    op_state.put::<ImageLimits>(limits);
+                }
+                if test_clock {
+                    op_state.put::<TestClock>(TestClock::default());
+                }
+                op_state.put::<WorkerSequences>(WorkerSequences {
+                    sequences: sequences(),
+                    service: flag_service.clone(),
+                });
+                if let Some(store) = key_store() {
+                    op_state.put::<WorkerKeys>(WorkerKeys {
+                        store,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(mailer) = mailer() {
+                    op_state.put::<WorkerMailer>(WorkerMailer {
+                        mailer,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(pool) = redis_pool() {
+                    op_state.put::<WorkerRedis>(WorkerRedis {
+                        pool,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(gateway) = ai_gateway() {
+                    op_state.put::<WorkerAi>(WorkerAi {
+                        gateway,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(models) = embedding_models() {
+                    op_state.put::<SharedEmbeddingModels>(models);
+                }
+                if let Some(runner) = model_runner() {
+                    op_state.put::<WorkerModels>(WorkerModels {
+                        runner,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(store) = webhook_store() {
+                    op_state.put::<WorkerWebhooks>(WorkerWebhooks {
+                        store,
+                        service: service.clone(),
+                    });
+                }
+                if let Some(flags) = feature_flags_rx() {
+                    op_state.put::<WorkerFeatureFlags>(WorkerFeatureFlags {
+                        service: flag_service,
+                        flags,
+                    });
+                }
+            }
+
             if conf.is_events_worker() {
                 // if worker is an events worker, assert events_rx is to be available
                 op_state
                     .put::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>(events_rx.unwrap());
+                if let Some(accepted_events) =
+                    conf.as_events_worker().unwrap().accepted_events.clone()
+                {
+                    op_state.put::<AcceptedEvents>(accepted_events);
+                }
+            }
+
+            if let Some(templates) = maybe_templates {
+                op_state.put::<WorkerTemplates>(templates);
+            }
+
+            if let Some(worker_alarms) = maybe_worker_alarms {
+                op_state.put::<WorkerAlarms>(worker_alarms);
+            }
+
+            if let Some(web_workers) = maybe_web_workers {
+                op_state.put::<WebWorkers>(web_workers);
             }
 
             if conf.is_user_worker() {
                 let conf = conf.as_user_worker().unwrap();
+                if let Some(port) = conf.web_worker.clone() {
+                    op_state.put::<WebWorkerPort>(port);
+                }
+                if !conf.listen.is_empty() {
+                    op_state.put::<ListenPermissions>(ListenPermissions(conf.listen.clone()));
+                }
                 if let Some(events_msg_tx) = conf.events_msg_tx.clone() {
                     op_state.put::<mpsc::UnboundedSender<WorkerEventWithMetadata>>(events_msg_tx);
                     op_state.put::<EventMetadata>(EventMetadata {
                         service_path: conf.service_path.clone(),
                         execution_id: conf.key,
+                        ..Default::default()
                     });
                 }
             }
         }
 
+        if coverage {
+            let mut session = js_runtime.inspector().borrow().create_local_session();
+            with_event_loop(&mut js_runtime, start_coverage(&mut session).boxed_local()).await?;
+            js_runtime
+                .op_state()
+                .borrow_mut()
+                .put::<CoverageSession>(CoverageSession(session));
+        }
+
+        let loading = Instant::now();
         let main_module_id = js_runtime
             .load_main_module(&main_module_url, maybe_module_code)
             .await?;
+        if let (Some(timelines), Some(track)) = (timelines(), &timeline_track) {
+            let args = deno_core::serde_json::json!({ "url": main_module_url.as_str() });
+            timelines.span(track, "worker", "load main module", loading, args);
+        }
 
         Ok(Self {
             js_runtime,
             main_module_id,
             env_vars,
             conf,
+            module_downloads,
+            module_loads,
+            boot_modules: boot_manifest.take(),
+            event_loop_watch: EventLoopWatch::default(),
+            cancelled_ops: Default::default(),
+            budget,
+            budget_alarms,
         })
     }
 
     pub async fn run(
         mut self,
-        unix_stream_rx: mpsc::UnboundedReceiver<UnixStream>,
+        unix_stream_rx: mpsc::UnboundedReceiver<WorkerConnection>,
     ) -> Result<(), Error> {
         {
             let op_state_rc = self.js_runtime.op_state();
             let mut op_state = op_state_rc.borrow_mut();
-            op_state.put::<mpsc::UnboundedReceiver<UnixStream>>(unix_stream_rx);
+            op_state.put::<mpsc::UnboundedReceiver<WorkerConnection>>(unix_stream_rx);
 
             if self.conf.is_main_worker() {
-                op_state.put::<mpsc::UnboundedSender<UserWorkerMsgs>>(
-                    self.conf.as_main_worker().unwrap().worker_pool_tx.clone(),
-                );
+                let conf = self.conf.as_main_worker().unwrap();
+                op_state.put::<mpsc::UnboundedSender<UserWorkerMsgs>>(conf.worker_pool_tx.clone());
+                if let Some(manifest) = conf.manifest.clone() {
+                    op_state.put::<SharedManifest>(manifest);
+                }
             }
         }
 
         let mut js_runtime = self.js_runtime;
-
-        let future = async move {
+        let watch = self.event_loop_watch;
+        let mut idle_gc = gc_tuning(&self.conf)
+            .idle_gc_after_ms
+            .map(|ms| IdleGc::new(Duration::from_millis(ms)));
+        let mut gc_nudges = GcNudges::default();
+        // the heap of workers sharing a budget is recorded after each turn
+        let mut memory_share = self.budget.as_ref().map(|budget| budget.memory_share());
+        let uncaught_error_policy = self
+            .conf
+            .as_user_worker()
+            .map(|conf| conf.uncaught_error_policy)
+            .unwrap_or_default();
+
+        // the runtime outlives the future, to cancel the ops left once it's done
+        let future = async {
+            // top level code runs synchronously within `mod_evaluate`, so it's
+            // watched like an event loop turn
+            watch.enter();
             let mod_result_rx = js_runtime.mod_evaluate(self.main_module_id);
-            match js_runtime.run_event_loop(false).await {
+            watch.exit();
+            let result = loop {
+                // same as `run_event_loop`, but records each turn for the watchdog
+                let result = poll_fn(|cx| {
+                    watch.enter();
+                    let poll = js_runtime.poll_event_loop(cx, false);
+                    watch.exit();
+                    if gc_nudges.poll_nudge(cx) {
+                        notify_pressure(js_runtime.v8_isolate());
+                    }
+                    if let Some(share) = &mut memory_share {
+                        let mut stats = deno_core::v8::HeapStatistics::default();
+                        js_runtime.v8_isolate().get_heap_statistics(&mut stats);
+                        share.record(stats.used_heap_size() + stats.external_memory());
+                    }
+                    if let Some(idle_gc) = &mut idle_gc {
+                        if poll.is_pending() && idle_gc.poll_idle(cx) {
+                            notify_idle(js_runtime.v8_isolate());
+                        }
+                    }
+                    poll
+                })
+                .await;
+                let Err(err) = &result else {
+                    break result;
+                };
+                // the worker keeps running through uncaught errors unless its
+                // policy is to be killed
+                let terminating = js_runtime.v8_isolate().is_execution_terminating();
+                match survivable_error_kind(err, terminating) {
+                    Some(kind) if uncaught_error_policy != UncaughtErrorPolicy::KillWorker => {
+                        handle_uncaught_error(&mut js_runtime, err, kind, uncaught_error_policy);
+                    }
+                    _ => break result,
+                }
+            };
+            match result {
                 Err(err) => {
                     // usually this happens because isolate is terminated
                     error!("event loop error: {}", err);
@@ -377,10 +798,12 @@ impl DenoRuntime {
             let worker_timeout_ms = self.conf.as_user_worker().unwrap().worker_timeout_ms;
             duration = Duration::from_millis(worker_timeout_ms);
         }
-        match tokio::time::timeout(duration, future).await {
+        let result = match tokio::time::timeout(duration, future).await {
             Err(_) => Err(anyhow!("wall clock duration reached")),
             Ok(res) => res,
-        }
+        };
+        *self.cancelled_ops.lock().unwrap() = cancel_pending_ops(&mut js_runtime);
+        result
     }
 
     #[allow(clippy::wrong_self_convention)]
@@ -407,12 +830,12 @@ mod test {
     use deno_core::{ModuleCode, ModuleSpecifier};
     use sb_eszip::module_loader::EszipPayloadKind;
     use sb_worker_context::essentials::{
-        MainWorkerRuntimeOpts, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-        WorkerRuntimeOpts,
+        DeterministicMode, MainWorkerRuntimeOpts, TimerPolicy, UserWorkerMsgs,
+        UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
     };
+    use sb_worker_context::request_metadata::WorkerConnection;
     use std::collections::HashMap;
     use std::path::PathBuf;
-    use tokio::net::UnixStream;
     use tokio::sync::mpsc;
 
     #[tokio::test]
@@ -443,7 +866,12 @@ mod test {
             maybe_eszip: Some(EszipPayloadKind::VecKind(eszip_code)),
             maybe_entrypoint: None,
             maybe_module_code: None,
-            conf: { WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts { worker_pool_tx }) },
+            conf: {
+                WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
+                    worker_pool_tx,
+                    manifest: None,
+                })
+            },
         })
         .await;
 
@@ -466,6 +894,11 @@ mod test {
             .unwrap();
         let read_is_even = rt.to_value::<deno_core::serde_json::Value>(&read_is_even_global);
         assert_eq!(read_is_even.unwrap().to_string(), "true");
+
+        // only the static graph was loaded
+        let module_loads = rt.module_loads.as_ref().unwrap();
+        assert!(module_loads.loaded() > 0);
+        assert_eq!(module_loads.on_demand(), 0);
         std::mem::drop(main_mod_ev);
     }
 
@@ -489,7 +922,10 @@ mod test {
                 if let Some(uc) = user_conf {
                     uc
                 } else {
-                    WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts { worker_pool_tx })
+                    WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
+                        worker_pool_tx,
+                        manifest: None,
+                    })
                 }
             },
         })
@@ -516,7 +952,7 @@ mod test {
         }
     }
 
-    // User Runtime Should not have access to EdgeRuntime
+    // User Runtime should only have access to the restricted EdgeRuntime
     #[tokio::test]
     async fn test_user_runtime_creation() {
         let mut runtime = create_runtime(
@@ -526,18 +962,22 @@ mod test {
         )
         .await;
 
-        {
-            let scope = &mut runtime.js_runtime.handle_scope();
-            let context = scope.get_current_context();
-            let inner_scope = &mut deno_core::v8::ContextScope::new(scope, context);
-            let global = context.global(inner_scope);
-            let edge_runtime_key: deno_core::v8::Local<deno_core::v8::Value> =
-                deno_core::serde_v8::to_v8(inner_scope, "EdgeRuntime").unwrap();
-            assert!(global
-                .get(inner_scope, edge_runtime_key)
-                .unwrap()
-                .is_undefined(),);
-        }
+        let edge_runtime_apis = runtime
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                ModuleCode::from(
+                    r#"[typeof EdgeRuntime.userWorkers, typeof EdgeRuntime.remainingBudgetMs]"#
+                        .to_string(),
+                ),
+            )
+            .unwrap();
+        let edge_runtime_apis =
+            runtime.to_value::<deno_core::serde_json::Value>(&edge_runtime_apis);
+        assert_eq!(
+            edge_runtime_apis.unwrap(),
+            deno_core::serde_json::json!(["undefined", "function"])
+        );
     }
 
     #[tokio::test]
@@ -711,6 +1151,103 @@ mod test {
             .contains("Spawning subprocesses is not allowed on Supabase Edge Runtime"));
     }
 
+    #[tokio::test]
+    async fn test_code_generation_can_be_disallowed() {
+        let mut user_rt = create_runtime(
+            None,
+            None,
+            Some(WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                disallow_code_generation: true,
+                ..Default::default()
+            })),
+        )
+        .await;
+
+        let mut run = |code: &str| {
+            user_rt
+                .js_runtime
+                .execute_script("<anon>", ModuleCode::from(code.to_string()))
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert!(run("eval('1 + 1')")
+            .unwrap()
+            .contains("eval() is disallowed for this worker"));
+        assert!(run("new Function('return 1')")
+            .unwrap()
+            .contains("Code generation from strings disallowed"));
+        assert!(run("setTimeout('1 + 1', 0)").is_some());
+        assert!(run("setTimeout(() => {}, 0)").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_mode() {
+        let deterministic = |seed: u32| {
+            Some(WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                deterministic: Some(DeterministicMode {
+                    seed,
+                    time_origin_ms: Some(1_700_000_000_000),
+                    timer_resolution_ms: 60_000,
+                }),
+                ..Default::default()
+            }))
+        };
+        let run = |rt: &mut DenoRuntime| {
+            let value = rt
+                .js_runtime
+                .execute_script(
+                    "<anon>",
+                    ModuleCode::from(
+                        "[Math.random(), crypto.randomUUID(), Date.now(), new Date().getTime()]"
+                            .to_string(),
+                    ),
+                )
+                .unwrap();
+            rt.to_value::<deno_core::serde_json::Value>(&value).unwrap()
+        };
+
+        let first = run(&mut create_runtime(None, None, deterministic(42)).await);
+        let second = run(&mut create_runtime(None, None, deterministic(42)).await);
+        let other_seed = run(&mut create_runtime(None, None, deterministic(7)).await);
+        assert_eq!(first, second);
+        assert_ne!(first[0], other_seed[0]);
+        assert_ne!(first[1], other_seed[1]);
+        assert_eq!(first[2], 1_700_000_000_000u64);
+        assert_eq!(first[3], 1_700_000_000_000u64);
+    }
+
+    #[tokio::test]
+    async fn test_timer_policy_caps_pending_timers() {
+        let mut user_rt = create_runtime(
+            None,
+            None,
+            Some(WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                timer_policy: Some(TimerPolicy {
+                    max_timers: Some(2),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        )
+        .await;
+
+        let mut run = |code: &str| {
+            user_rt
+                .js_runtime
+                .execute_script("<anon>", ModuleCode::from(code.to_string()))
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert!(
+            run("globalThis.a = setTimeout(() => {}, 1000); setInterval(() => {}, 1000)").is_none()
+        );
+        assert!(run("setTimeout(() => {}, 1000)")
+            .unwrap()
+            .contains("too many pending timers"));
+        // cleared timers are no longer pending
+        assert!(run("clearTimeout(globalThis.a); setTimeout(() => {}, 1000)").is_none());
+    }
+
     #[tokio::test]
     async fn test_os_env_vars() {
         std::env::set_var("Supa_Test", "Supa_Value");
@@ -802,6 +1339,7 @@ mod test {
                 pool_msg_tx: None,
                 events_msg_tx: None,
                 service_path: None,
+                ..Default::default()
             })),
         )
         .await
@@ -810,7 +1348,7 @@ mod test {
     #[tokio::test]
     async fn test_read_file_user_rt() {
         let user_rt = create_basic_user_runtime("./test_cases/readFile", 20, 1000).await;
-        let (_tx, unix_stream_rx) = mpsc::unbounded_channel::<UnixStream>();
+        let (_tx, unix_stream_rx) = mpsc::unbounded_channel::<WorkerConnection>();
         let result = user_rt.run(unix_stream_rx).await;
         match result {
             Err(err) => {
@@ -0,0 +1,438 @@
+use crate::node::node_identity;
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::serde_json::{self, json, Value};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// Registration of this node, and the services it serves, in Consul or etcd so
+// fleets can route to it without an inventory of their own. The registration
+// expires unless it's refreshed within its TTL, and is removed on a graceful
+// shutdown.
+
+const DEFAULT_SERVICE_NAME: &str = "edge-runtime";
+const DEFAULT_TTL_SECS: u64 = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// time given to the deregistration before the process exits anyway
+const DEREGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+// failing checks of a node that stopped refreshing them are removed after this
+const CONSUL_DEREGISTER_AFTER: &str = "10m";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryBackend {
+    Consul,
+    Etcd,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub backend: DiscoveryBackend,
+    // http endpoint of the Consul agent or the etcd gateway
+    pub endpoint: String,
+    // name of the Consul service, or the etcd key prefix (`/<name>/nodes/<id>`)
+    #[serde(default)]
+    pub service_name: Option<String>,
+    // `<service name>-<address>-<port>` by default
+    #[serde(default)]
+    pub node_id: Option<String>,
+    // advertised address, the listening one by default
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    // env var holding the token sent to the backend (Consul ACL token, etcd auth
+    // token)
+    #[serde(default)]
+    pub token_env: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// What is registered about this node
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeRegistration {
+    pub id: String,
+    pub service_name: String,
+    pub address: String,
+    pub port: u16,
+    pub service_paths: Vec<String>,
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+impl DiscoveryConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let config: Self = serde_json::from_str(json)?;
+        reqwest::Url::parse(&config.endpoint)
+            .with_context(|| format!("invalid discovery endpoint {}", config.endpoint))?;
+        if config.ttl_secs == Some(0) {
+            bail!("the discovery TTL must be positive");
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs.unwrap_or(DEFAULT_TTL_SECS))
+    }
+
+    // The node listening on `ip:port` and serving `service_paths`
+    pub fn registration(
+        &self,
+        ip: Ipv4Addr,
+        port: u16,
+        mut service_paths: Vec<String>,
+    ) -> Result<NodeRegistration, Error> {
+        let address = match &self.address {
+            Some(address) => address.clone(),
+            None if !ip.is_unspecified() => ip.to_string(),
+            None => bail!(
+                "discovery needs an address to advertise when listening on {}",
+                ip
+            ),
+        };
+        let service_name = self
+            .service_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+        service_paths.sort();
+        service_paths.dedup();
+        let identity = node_identity();
+        Ok(NodeRegistration {
+            id: self
+                .node_id
+                .clone()
+                .unwrap_or_else(|| format!("{}-{}-{}", service_name, address, port)),
+            service_name,
+            address,
+            port,
+            service_paths,
+            tags: self.tags.clone(),
+            region: identity.region,
+            zone: identity.zone,
+        })
+    }
+}
+
+struct Registry {
+    config: DiscoveryConfig,
+    client: reqwest::Client,
+    token: Option<String>,
+}
+
+impl Registry {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.endpoint.trim_end_matches('/'), path)
+    }
+
+    async fn call(&self, method: reqwest::Method, path: &str, body: Value) -> Result<Value, Error> {
+        let mut req = self.client.request(method, self.url(path));
+        if !body.is_null() {
+            req = req.json(&body);
+        }
+        if let Some(token) = &self.token {
+            req = match self.config.backend {
+                DiscoveryBackend::Consul => req.header("x-consul-token", token),
+                DiscoveryBackend::Etcd => req.header(reqwest::header::AUTHORIZATION, token),
+            };
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        let text = res.text().await?;
+        if !status.is_success() {
+            bail!("{} {} ({})", status, path, text.trim());
+        }
+        Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    // Registers the node, returning the etcd lease it's attached to
+    async fn register(&self, node: &NodeRegistration) -> Result<Option<String>, Error> {
+        match self.config.backend {
+            DiscoveryBackend::Consul => {
+                let body = consul_service(node, self.config.ttl());
+                self.call(reqwest::Method::PUT, "/v1/agent/service/register", body)
+                    .await?;
+                Ok(None)
+            }
+            DiscoveryBackend::Etcd => {
+                let grant = self
+                    .call(
+                        reqwest::Method::POST,
+                        "/v3/lease/grant",
+                        json!({ "TTL": self.config.ttl().as_secs() }),
+                    )
+                    .await?;
+                let lease = etcd_lease_id(&grant)
+                    .ok_or_else(|| anyhow!("no lease in the etcd grant ({})", grant))?;
+                self.call(reqwest::Method::POST, "/v3/kv/put", etcd_put(node, &lease)?)
+                    .await?;
+                Ok(Some(lease))
+            }
+        }
+    }
+
+    async fn refresh(&self, node: &NodeRegistration, lease: Option<&str>) -> Result<(), Error> {
+        match (self.config.backend, lease) {
+            (DiscoveryBackend::Consul, _) => {
+                let path = format!("/v1/agent/check/pass/{}", consul_check_id(node));
+                self.call(reqwest::Method::PUT, &path, Value::Null).await?;
+            }
+            (DiscoveryBackend::Etcd, Some(lease)) => {
+                let res = self
+                    .call(
+                        reqwest::Method::POST,
+                        "/v3/lease/keepalive",
+                        json!({ "ID": lease }),
+                    )
+                    .await?;
+                // an expired lease is kept alive with no TTL, the key is gone
+                let ttl = res
+                    .pointer("/result/TTL")
+                    .and_then(|ttl| ttl.as_str().and_then(|t| t.parse().ok()).or(ttl.as_i64()))
+                    .unwrap_or(0);
+                if ttl <= 0 {
+                    bail!("etcd lease {} expired", lease);
+                }
+            }
+            (DiscoveryBackend::Etcd, None) => bail!("not registered"),
+        }
+        Ok(())
+    }
+
+    async fn deregister(&self, node: &NodeRegistration, lease: Option<&str>) -> Result<(), Error> {
+        match (self.config.backend, lease) {
+            (DiscoveryBackend::Consul, _) => {
+                let path = format!("/v1/agent/service/deregister/{}", node.id);
+                self.call(reqwest::Method::PUT, &path, Value::Null).await?;
+            }
+            // revoking the lease deletes the key
+            (DiscoveryBackend::Etcd, Some(lease)) => {
+                self.call(
+                    reqwest::Method::POST,
+                    "/v3/lease/revoke",
+                    json!({ "ID": lease }),
+                )
+                .await?;
+            }
+            (DiscoveryBackend::Etcd, None) => {}
+        }
+        Ok(())
+    }
+}
+
+fn consul_check_id(node: &NodeRegistration) -> String {
+    format!("service:{}", node.id)
+}
+
+fn consul_service(node: &NodeRegistration, ttl: Duration) -> Value {
+    let mut tags = node.tags.clone();
+    tags.extend(
+        node.service_paths
+            .iter()
+            .map(|path| format!("path:{}", path)),
+    );
+    let mut meta = serde_json::Map::new();
+    if let Some(region) = &node.region {
+        meta.insert("region".to_string(), json!(region));
+    }
+    if let Some(zone) = &node.zone {
+        meta.insert("zone".to_string(), json!(zone));
+    }
+    json!({
+        "ID": node.id,
+        "Name": node.service_name,
+        "Address": node.address,
+        "Port": node.port,
+        "Tags": tags,
+        "Meta": meta,
+        "Check": {
+            "CheckID": consul_check_id(node),
+            "TTL": format!("{}s", ttl.as_secs()),
+            "DeregisterCriticalServiceAfter": CONSUL_DEREGISTER_AFTER,
+        },
+    })
+}
+
+fn etcd_key(node: &NodeRegistration) -> String {
+    format!("/{}/nodes/{}", node.service_name, node.id)
+}
+
+fn etcd_put(node: &NodeRegistration, lease: &str) -> Result<Value, Error> {
+    Ok(json!({
+        "key": base64::encode(etcd_key(node)),
+        "value": base64::encode(serde_json::to_vec(node)?),
+        "lease": lease,
+    }))
+}
+
+// int64s are strings in the JSON of the etcd gateway
+fn etcd_lease_id(grant: &Value) -> Option<String> {
+    match grant.get("ID")? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+// A registration kept alive in the background
+pub struct DiscoveryRegistration {
+    stop_tx: oneshot::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl DiscoveryRegistration {
+    // Stops refreshing the registration, removing it if `deregister` (on a
+    // shutdown, not when handing the node over to a new process registering it
+    // again)
+    pub async fn stop(self, deregister: bool) {
+        let _ = self.stop_tx.send(deregister);
+        if tokio::time::timeout(DEREGISTER_TIMEOUT, self.task)
+            .await
+            .is_err()
+        {
+            warn!("gave up on deregistering the node");
+        }
+    }
+}
+
+// Registers `node` and refreshes it every third of the TTL, registering it again
+// whenever a refresh fails (eg: the backend lost it)
+pub fn start_registration(
+    config: DiscoveryConfig,
+    node: NodeRegistration,
+) -> Result<DiscoveryRegistration, Error> {
+    let token = match &config.token_env {
+        Some(name) => Some(
+            std::env::var(name)
+                .with_context(|| format!("discovery token env var {} isn't set", name))?,
+        ),
+        None => None,
+    };
+    let registry = Registry {
+        client: reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?,
+        token,
+        config,
+    };
+    let (stop_tx, mut stop_rx) = oneshot::channel::<bool>();
+
+    let task = tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(registry.config.ttl() / 3);
+        let mut registered = false;
+        let mut lease: Option<String> = None;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                deregister = &mut stop_rx => {
+                    if registered && deregister.unwrap_or(true) {
+                        match registry.deregister(&node, lease.as_deref()).await {
+                            Ok(()) => info!("deregistered {} from {:?}", node.id, registry.config.backend),
+                            Err(err) => warn!("failed to deregister {}: {:#}", node.id, err),
+                        }
+                    }
+                    return;
+                }
+            }
+            if registered {
+                match registry.refresh(&node, lease.as_deref()).await {
+                    Ok(()) => {
+                        debug!("refreshed the registration of {}", node.id);
+                        continue;
+                    }
+                    Err(err) => warn!(
+                        "failed to refresh the registration of {}: {:#}",
+                        node.id, err
+                    ),
+                }
+            }
+            match registry.register(&node).await {
+                Ok(new_lease) => {
+                    if !registered {
+                        info!("registered {} with {:?}", node.id, registry.config.backend);
+                    }
+                    registered = true;
+                    lease = new_lease;
+                }
+                Err(err) => {
+                    registered = false;
+                    warn!("failed to register {}: {:#}", node.id, err);
+                }
+            }
+        }
+    });
+    Ok(DiscoveryRegistration { stop_tx, task })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{consul_service, etcd_key, etcd_lease_id, DiscoveryConfig};
+    use deno_core::serde_json::json;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_registration() {
+        let config = DiscoveryConfig::parse(
+            r#"{ "backend": "consul", "endpoint": "http://127.0.0.1:8500", "tags": ["edge"] }"#,
+        )
+        .unwrap();
+        let node = config
+            .registration(
+                Ipv4Addr::new(10, 0, 0, 2),
+                9000,
+                vec!["./b".to_string(), "./a".to_string(), "./b".to_string()],
+            )
+            .unwrap();
+        assert_eq!(node.id, "edge-runtime-10.0.0.2-9000");
+        assert_eq!(node.service_paths, vec!["./a", "./b"]);
+        assert_eq!(
+            etcd_key(&node),
+            "/edge-runtime/nodes/edge-runtime-10.0.0.2-9000"
+        );
+
+        let service = consul_service(&node, Duration::from_secs(30));
+        assert_eq!(service["Tags"], json!(["edge", "path:./a", "path:./b"]));
+        assert_eq!(service["Check"]["TTL"], "30s");
+        assert_eq!(
+            service["Check"]["CheckID"],
+            "service:edge-runtime-10.0.0.2-9000"
+        );
+
+        // nothing to advertise
+        assert!(config
+            .registration(Ipv4Addr::UNSPECIFIED, 9000, vec![])
+            .is_err());
+        assert!(
+            DiscoveryConfig::parse(r#"{ "backend": "zookeeper", "endpoint": "http://zk" }"#)
+                .is_err()
+        );
+        assert!(DiscoveryConfig::parse(
+            r#"{ "backend": "etcd", "endpoint": "http://etcd:2379", "ttlSecs": 0 }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_etcd_lease_id() {
+        assert_eq!(
+            etcd_lease_id(&json!({ "ID": "7587862072907470356", "TTL": "30" })).as_deref(),
+            Some("7587862072907470356")
+        );
+        assert_eq!(etcd_lease_id(&json!({ "ID": 42 })).as_deref(), Some("42"));
+        assert_eq!(etcd_lease_id(&json!({ "error": "denied" })), None);
+    }
+}
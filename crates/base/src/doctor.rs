@@ -0,0 +1,418 @@
+use crate::ai_gateway::AiConfig;
+use crate::deno_runtime::load_import_map;
+use crate::discovery::DiscoveryConfig;
+use crate::fault_injection::FaultInjectionConfig;
+use crate::gc::GcConfig;
+use crate::geo::GeoIp;
+use crate::http3::Http3Config;
+use crate::key_store::KeyStoreConfig;
+use crate::mail::MailConfig;
+use crate::module_cache::{cache_info, deps_dir};
+use crate::onnx::OnnxConfig;
+use crate::redis_pool::RedisConfig;
+use crate::rt_worker::event_filters::EventFiltersConfig;
+use crate::rt_worker::sandbox::enable_worker_sandbox;
+use crate::rt_worker::worker_ctx::create_worker;
+use crate::service_config::read_lockfile;
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json;
+use event_worker::redaction::{RedactionConfig, Redactor};
+use module_fetcher::registry_mirrors::RegistryMirrors;
+use sb_worker_context::essentials::{
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use sb_worker_context::manifest::Manifest;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// a service that takes longer to boot is reported as failing
+const BOOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// What `edge-runtime start` would be run with
+#[derive(Debug, Clone, Default)]
+pub struct DoctorOpts {
+    pub ip: String,
+    pub port: u16,
+    pub main_service_path: Option<String>,
+    pub import_map_path: Option<String>,
+    pub manifest_path: Option<String>,
+    pub admin_port: Option<u16>,
+    pub http3_port: Option<u16>,
+    pub http3_cert_path: Option<String>,
+    pub http3_key_path: Option<String>,
+    pub key_store_path: Option<String>,
+    pub mail_config_path: Option<String>,
+    pub redis_config_path: Option<String>,
+    pub ai_config_path: Option<String>,
+    pub onnx_config_path: Option<String>,
+    pub fault_injection_path: Option<String>,
+    pub redaction_config_path: Option<String>,
+    pub event_filters_path: Option<String>,
+    pub gc_config_path: Option<String>,
+    pub registry_mirrors_path: Option<String>,
+    pub discovery_config_path: Option<String>,
+    pub geoip_db_paths: Vec<String>,
+    // boot the services in sandboxed workers (linux only)
+    pub sandbox_workers: bool,
+    // only run the static checks
+    pub skip_boot: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DoctorCheck {
+    // what was checked (eg: `config`, `port`, `boot`)
+    pub category: String,
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DoctorReport {
+    pub checks: Vec<DoctorCheck>,
+}
+
+impl DoctorReport {
+    fn push(&mut self, category: &str, name: &str, status: CheckStatus, detail: String) {
+        self.checks.push(DoctorCheck {
+            category: category.to_string(),
+            name: name.to_string(),
+            status,
+            detail,
+        });
+    }
+
+    fn push_result(&mut self, category: &str, name: &str, result: Result<String, Error>) {
+        match result {
+            Ok(detail) => self.push(category, name, CheckStatus::Pass, detail),
+            Err(err) => self.push(category, name, CheckStatus::Fail, format!("{:#}", err)),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // One line per check, aligned in columns
+    pub fn to_pretty_string(&self) -> String {
+        let width = |f: fn(&DoctorCheck) -> usize| self.checks.iter().map(f).max().unwrap_or(0);
+        let category_width = width(|c| c.category.len());
+        let name_width = width(|c| c.name.len());
+        let mut out = String::new();
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => "ok",
+                CheckStatus::Warn => "WARN",
+                CheckStatus::Fail => "FAIL",
+            };
+            let _ = writeln!(
+                out,
+                "{:<4}  {:<cw$}  {:<nw$}  {}",
+                status,
+                check.category,
+                check.name,
+                check.detail,
+                cw = category_width,
+                nw = name_width
+            );
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Fail)
+            .count();
+        let _ = writeln!(out, "\n{} checks, {} failed", self.checks.len(), failed);
+        out
+    }
+}
+
+fn check_config(path: &str, load: fn(&Path) -> Result<(), Error>) -> Result<String, Error> {
+    load(Path::new(path)).map(|_| path.to_string())
+}
+
+// The module cache has to be writable for remote modules to be fetched
+fn check_cache_dir() -> Result<String, Error> {
+    let dir = deps_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    std::fs::write(&probe, b"")
+        .map_err(|err| anyhow!("{} isn't writable: {}", dir.display(), err))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(dir.display().to_string())
+}
+
+fn check_tcp_port(ip: Ipv4Addr, port: u16) -> Result<String, Error> {
+    let addr = SocketAddr::from((ip, port));
+    TcpListener::bind(addr).map_err(|err| anyhow!("can't listen on {}: {}", addr, err))?;
+    Ok(format!("{} is available", addr))
+}
+
+fn check_udp_port(ip: Ipv4Addr, port: u16) -> Result<String, Error> {
+    let addr = SocketAddr::from((ip, port));
+    UdpSocket::bind(addr).map_err(|err| anyhow!("can't bind {}: {}", addr, err))?;
+    Ok(format!("{} (udp) is available", addr))
+}
+
+// Compares the checksums of `deno.lock` with the cached sources of the remote
+// modules, a mismatch means the cache (or the lockfile) was tampered with
+fn check_lockfile(path: &Path) -> Result<String, Error> {
+    let remote = read_lockfile(path)?;
+    let mut verified = 0;
+    let mut mismatched = vec![];
+    for (url, checksum) in &remote {
+        let Some(integrity) = cache_info(url)?.and_then(|info| info.integrity) else {
+            continue;
+        };
+        if integrity == *checksum {
+            verified += 1;
+        } else {
+            mismatched.push(url.as_str());
+        }
+    }
+    if !mismatched.is_empty() {
+        bail!(
+            "cached modules don't match the lockfile: {}",
+            mismatched.join(", ")
+        );
+    }
+    Ok(format!(
+        "{} of {} remote modules verified against the cache",
+        verified,
+        remote.len()
+    ))
+}
+
+struct ServiceToBoot {
+    name: String,
+    service_path: PathBuf,
+    maybe_entrypoint: Option<String>,
+    import_map_path: Option<String>,
+    env_vars: HashMap<String, String>,
+}
+
+async fn boot_service(service: &ServiceToBoot) -> Result<String, Error> {
+    if !service.service_path.exists() {
+        bail!("{} does not exist", service.service_path.display());
+    }
+    let started = Instant::now();
+    let boot = create_worker(WorkerContextInitOpts {
+        service_path: service.service_path.clone(),
+        no_module_cache: false,
+        import_map_path: service.import_map_path.clone(),
+        env_vars: service.env_vars.clone(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: service.maybe_entrypoint.clone(),
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            service_path: Some(service.service_path.to_string_lossy().to_string()),
+            ..UserWorkerRuntimeOpts::default()
+        }),
+    });
+    tokio::time::timeout(BOOT_TIMEOUT, boot)
+        .await
+        .map_err(|_| anyhow!("didn't boot within {}s", BOOT_TIMEOUT.as_secs()))??;
+    Ok(format!("booted in {}ms", started.elapsed().as_millis()))
+}
+
+// Checks what the server would be started with, then boots every service once
+pub async fn doctor(opts: DoctorOpts) -> Result<DoctorReport, Error> {
+    let mut report = DoctorReport::default();
+
+    let configs: [(&str, &Option<String>, fn(&Path) -> Result<(), Error>); 11] = [
+        ("key store", &opts.key_store_path, |p| {
+            KeyStoreConfig::load(p).map(drop)
+        }),
+        ("mail", &opts.mail_config_path, |p| {
+            MailConfig::load(p).map(drop)
+        }),
+        ("redis", &opts.redis_config_path, |p| {
+            RedisConfig::load(p).map(drop)
+        }),
+        ("ai", &opts.ai_config_path, |p| AiConfig::load(p).map(drop)),
+        ("onnx", &opts.onnx_config_path, |p| {
+            OnnxConfig::load(p).map(drop)
+        }),
+        ("fault injection", &opts.fault_injection_path, |p| {
+            FaultInjectionConfig::load(p).map(drop)
+        }),
+        ("redaction", &opts.redaction_config_path, |p| {
+            Redactor::new(&RedactionConfig::load(p)?).map(drop)
+        }),
+        ("event filters", &opts.event_filters_path, |p| {
+            EventFiltersConfig::load(p)?.into_filters().map(drop)
+        }),
+        ("gc", &opts.gc_config_path, |p| GcConfig::load(p).map(drop)),
+        ("registry mirrors", &opts.registry_mirrors_path, |p| {
+            RegistryMirrors::load(p).map(drop)
+        }),
+        ("discovery", &opts.discovery_config_path, |p| {
+            DiscoveryConfig::load(p).map(drop)
+        }),
+    ];
+    for (name, path, load) in configs {
+        if let Some(path) = path {
+            report.push_result("config", name, check_config(path, load));
+        }
+    }
+    if !opts.geoip_db_paths.is_empty() {
+        let paths: Vec<PathBuf> = opts.geoip_db_paths.iter().map(PathBuf::from).collect();
+        report.push_result(
+            "config",
+            "geoip",
+            GeoIp::open(&paths).map(|_| opts.geoip_db_paths.join(", ")),
+        );
+    }
+    let manifest = match &opts.manifest_path {
+        Some(path) => {
+            let manifest = Manifest::load(Path::new(path));
+            let result = manifest
+                .as_ref()
+                .map(|m| format!("{} services", m.services.len()))
+                .map_err(|err| anyhow!("{}", err));
+            report.push_result("config", "manifest", result);
+            manifest.ok()
+        }
+        None => None,
+    };
+
+    report.push_result("cache", "module cache", check_cache_dir());
+
+    if let (Some(cert_path), Some(key_path)) = (&opts.http3_cert_path, &opts.http3_key_path) {
+        let config = Http3Config {
+            addr: SocketAddr::from(([0, 0, 0, 0], opts.http3_port.unwrap_or(0))),
+            cert_path: PathBuf::from(cert_path),
+            key_path: PathBuf::from(key_path),
+        };
+        report.push_result(
+            "tls",
+            "http3 certificate",
+            config.server_config().map(|_| cert_path.clone()),
+        );
+    } else if opts.http3_port.is_some() {
+        report.push(
+            "tls",
+            "http3 certificate",
+            CheckStatus::Fail,
+            "--http3-port needs --http3-cert and --http3-key".to_string(),
+        );
+    }
+
+    match opts.ip.parse::<Ipv4Addr>() {
+        Ok(ip) => {
+            report.push_result("port", "http", check_tcp_port(ip, opts.port));
+            if let Some(port) = opts.http3_port {
+                report.push_result("port", "http3", check_udp_port(ip, port));
+            }
+        }
+        Err(err) => report.push("port", "http", CheckStatus::Fail, err.to_string()),
+    }
+    if let Some(port) = opts.admin_port {
+        report.push_result("port", "admin", check_tcp_port(Ipv4Addr::LOCALHOST, port));
+    }
+
+    // everything served by the node: the main service and the manifest's
+    let mut services = vec![];
+    if let Some(path) = &opts.main_service_path {
+        services.push(ServiceToBoot {
+            name: "main".to_string(),
+            service_path: PathBuf::from(path),
+            maybe_entrypoint: None,
+            import_map_path: opts.import_map_path.clone(),
+            env_vars: std::env::vars().collect(),
+        });
+    }
+    if let Some(manifest) = &manifest {
+        let mut names: Vec<&String> = manifest.services.keys().collect();
+        names.sort();
+        for name in names {
+            let options = manifest.get(name).unwrap().worker_options;
+            services.push(ServiceToBoot {
+                name: name.clone(),
+                service_path: PathBuf::from(options.service_path),
+                maybe_entrypoint: options.maybe_entrypoint,
+                import_map_path: options.import_map_path,
+                env_vars: options.env_vars.into_iter().collect(),
+            });
+        }
+    }
+
+    for service in &services {
+        if service.import_map_path.is_some() {
+            report.push_result(
+                "import map",
+                &service.name,
+                load_import_map(service.import_map_path.clone())
+                    .map(|_| service.import_map_path.clone().unwrap_or_default()),
+            );
+        }
+        let lockfile = service.service_path.join("deno.lock");
+        if lockfile.exists() {
+            report.push_result("lockfile", &service.name, check_lockfile(&lockfile));
+        }
+    }
+
+    if services.is_empty() {
+        report.push(
+            "boot",
+            "services",
+            CheckStatus::Warn,
+            "no main service or manifest to boot".to_string(),
+        );
+    }
+    if opts.skip_boot {
+        return Ok(report);
+    }
+    if opts.sandbox_workers {
+        if let Err(err) = enable_worker_sandbox(None) {
+            report.push("boot", "sandbox", CheckStatus::Fail, err.to_string());
+            return Ok(report);
+        }
+    }
+    for service in &services {
+        report.push_result("boot", &service.name, boot_service(service).await);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_lockfile;
+
+    #[test]
+    fn test_check_lockfile() {
+        let path = std::env::temp_dir().join(format!("deno-{}.lock", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{ "version": "3", "remote": { "https://example.com/not-cached.ts": "ab12" } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            check_lockfile(&path).unwrap(),
+            "0 of 1 remote modules verified against the cache"
+        );
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(check_lockfile(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
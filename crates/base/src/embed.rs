@@ -0,0 +1,457 @@
+use crate::feature_flags::{
+    start_feature_flags, FeatureFlagProvider, SharedFeatureFlagProvider,
+    DEFAULT_FEATURE_FLAGS_REFRESH_SECS,
+};
+use crate::js_worker::module_loader::{ModuleSourceProvider, SharedModuleSourceProvider};
+use crate::key_store::{set_key_store, KeyStoreConfig};
+use crate::rt_worker::hooks::{set_worker_hooks, SharedWorkerHooks, WorkerLifecycleHooks};
+use crate::server::{Server, ServerFlags, ServerOpts, WorkerEntrypoints};
+use anyhow::{bail, Error};
+use deno_core::Extension;
+use event_worker::events::WorkerEventWithMetadata;
+use hyper::{Body, Request, Response};
+use log::warn;
+use module_fetcher::module_scanner::{set_module_scanner, ModuleScanner};
+use sb_worker_context::alarms::{AlarmStore, SharedAlarmStore};
+use sb_worker_context::essentials::{UserWorkerMsgs, WorkerRuntimeOpts};
+use sb_worker_context::keys::{KeyStore, RegisteredKey, Signer};
+use sb_worker_context::manifest::{Manifest, ServiceEntry};
+use sb_worker_context::service_scope::service_scope;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+pub type ExtensionFactory = Arc<dyn Fn() -> Extension + Send + Sync>;
+pub type WorkerEventHook = Arc<dyn Fn(&WorkerEventWithMetadata) + Send + Sync>;
+pub type ListeningHook = Arc<dyn Fn(SocketAddr) + Send + Sync>;
+
+// extensions are process wide, as every worker gets its own instances
+static CUSTOM_EXTENSIONS: OnceLock<Vec<CustomExtension>> = OnceLock::new();
+
+static MODULE_SOURCE_PROVIDER: OnceLock<SharedModuleSourceProvider> = OnceLock::new();
+
+// Provider replacing the default one (disk, remote URLs and the module cache) for
+// workers not running from an eszip
+pub fn custom_module_source_provider() -> Option<SharedModuleSourceProvider> {
+    MODULE_SOURCE_PROVIDER.get().cloned()
+}
+
+// Kinds of workers an extension is added to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerKinds {
+    pub main: bool,
+    pub events: bool,
+    pub user: bool,
+}
+
+impl WorkerKinds {
+    pub const ALL: WorkerKinds = WorkerKinds {
+        main: true,
+        events: true,
+        user: true,
+    };
+    pub const MAIN: WorkerKinds = WorkerKinds {
+        main: true,
+        events: false,
+        user: false,
+    };
+    pub const USER: WorkerKinds = WorkerKinds {
+        main: false,
+        events: false,
+        user: true,
+    };
+
+    fn matches(&self, conf: &WorkerRuntimeOpts) -> bool {
+        match conf {
+            WorkerRuntimeOpts::MainWorker(_) => self.main,
+            WorkerRuntimeOpts::EventsWorker(_) => self.events,
+            WorkerRuntimeOpts::UserWorker(_) => self.user,
+        }
+    }
+}
+
+// When the ESM sources of an extension are evaluated, relative to the sb_core
+// bootstrap (which sets up `Deno`, `EdgeRuntime` and the other globals). Ops are
+// only reachable (through `Deno.core.ops`) before the bootstrap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EsmStage {
+    BeforeBootstrap,
+    AfterBootstrap,
+}
+
+// An extension added to workers by the embedder. Its ops are registered after all
+// built-in extensions (sb_core included), in registration order. ESM sources are
+// evaluated by the runtime itself, as sources of extensions outside of the
+// snapshot aren't loaded by deno_core; they can't import other modules.
+#[derive(Clone)]
+pub struct CustomExtension {
+    factory: ExtensionFactory,
+    esm: Vec<(String, String)>,
+    esm_stage: EsmStage,
+    workers: WorkerKinds,
+}
+
+impl CustomExtension {
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn() -> Extension + Send + Sync + 'static,
+    {
+        Self {
+            factory: Arc::new(factory),
+            esm: vec![],
+            esm_stage: EsmStage::AfterBootstrap,
+            workers: WorkerKinds::ALL,
+        }
+    }
+
+    // eg: `.esm("ext:my_platform/api.js", "globalThis.MyPlatform = { ... }")`
+    pub fn esm(mut self, specifier: &str, code: &str) -> Self {
+        self.esm.push((specifier.to_string(), code.to_string()));
+        self
+    }
+
+    pub fn esm_stage(mut self, stage: EsmStage) -> Self {
+        self.esm_stage = stage;
+        self
+    }
+
+    pub fn workers(mut self, workers: WorkerKinds) -> Self {
+        self.workers = workers;
+        self
+    }
+}
+
+fn registered_extensions(conf: &WorkerRuntimeOpts) -> impl Iterator<Item = &CustomExtension> {
+    CUSTOM_EXTENSIONS
+        .get()
+        .into_iter()
+        .flatten()
+        .filter(|ext| ext.workers.matches(conf))
+}
+
+// Instances of the extensions registered for this kind of worker
+pub fn custom_extensions(conf: &WorkerRuntimeOpts) -> Vec<Extension> {
+    registered_extensions(conf)
+        .map(|ext| (ext.factory)())
+        .collect()
+}
+
+// ESM sources (specifier, code) to evaluate at the given stage
+pub fn custom_esm_sources(conf: &WorkerRuntimeOpts, stage: EsmStage) -> Vec<(String, String)> {
+    registered_extensions(conf)
+        .filter(|ext| ext.esm_stage == stage)
+        .flat_map(|ext| ext.esm.iter().cloned())
+        .collect()
+}
+
+#[derive(Clone, Default)]
+struct LifecycleHooks {
+    on_listening: Option<ListeningHook>,
+    on_worker_event: Option<WorkerEventHook>,
+}
+
+// Builds an `EdgeRuntime` for embedding the runtime in another Rust program
+pub struct EdgeRuntimeBuilder {
+    main_service_path: Option<String>,
+    events_service_path: Option<String>,
+    import_map_path: Option<String>,
+    no_module_cache: bool,
+    entrypoints: WorkerEntrypoints,
+    flags: ServerFlags,
+    services: Manifest,
+    extensions: Vec<CustomExtension>,
+    module_source_provider: Option<SharedModuleSourceProvider>,
+    module_scanner: Option<Arc<dyn ModuleScanner>>,
+    worker_hooks: Option<SharedWorkerHooks>,
+    feature_flag_provider: Option<SharedFeatureFlagProvider>,
+    alarm_store: Option<SharedAlarmStore>,
+    signing_keys: KeyStore,
+    hooks: LifecycleHooks,
+}
+
+impl Default for EdgeRuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EdgeRuntimeBuilder {
+    pub fn new() -> Self {
+        Self {
+            main_service_path: None,
+            events_service_path: None,
+            import_map_path: None,
+            no_module_cache: false,
+            entrypoints: WorkerEntrypoints {
+                main: None,
+                events: None,
+            },
+            flags: ServerFlags::default(),
+            services: Manifest::default(),
+            extensions: vec![],
+            module_source_provider: None,
+            module_scanner: None,
+            worker_hooks: None,
+            feature_flag_provider: None,
+            alarm_store: None,
+            signing_keys: KeyStore::default(),
+            hooks: LifecycleHooks::default(),
+        }
+    }
+
+    // Without a main service, requests are routed to the registered services
+    pub fn main_service(mut self, path: &str) -> Self {
+        self.main_service_path = Some(path.to_string());
+        self
+    }
+
+    pub fn events_service(mut self, path: &str) -> Self {
+        self.events_service_path = Some(path.to_string());
+        self
+    }
+
+    pub fn import_map(mut self, path: &str) -> Self {
+        self.import_map_path = Some(path.to_string());
+        self
+    }
+
+    pub fn module_cache(mut self, enabled: bool) -> Self {
+        self.no_module_cache = !enabled;
+        self
+    }
+
+    pub fn entrypoints(mut self, entrypoints: WorkerEntrypoints) -> Self {
+        self.entrypoints = entrypoints;
+        self
+    }
+
+    pub fn flags(mut self, flags: ServerFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    // Registers a service, as if it was declared in the manifest
+    pub fn service(mut self, name: &str, entry: ServiceEntry) -> Self {
+        self.services.services.insert(name.to_string(), entry);
+        self
+    }
+
+    // Adds an extension (ops only) to every worker
+    pub fn extension<F>(self, factory: F) -> Self
+    where
+        F: Fn() -> Extension + Send + Sync + 'static,
+    {
+        self.register_extension(CustomExtension::new(factory))
+    }
+
+    pub fn register_extension(mut self, extension: CustomExtension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    // Loads module sources of all workers from this provider
+    pub fn module_source_provider<P>(mut self, provider: P) -> Self
+    where
+        P: ModuleSourceProvider + Send + Sync + 'static,
+    {
+        self.module_source_provider = Some(Arc::new(provider));
+        self
+    }
+
+    // Scans every module downloaded from remote registries before it's cached
+    // and executed (in place of the `module_scanner` command of the flags)
+    pub fn module_scanner<S>(mut self, scanner: S) -> Self
+    where
+        S: ModuleScanner + 'static,
+    {
+        self.module_scanner = Some(Arc::new(scanner));
+        self
+    }
+
+    // Called at boot, around every request and at shutdown of every worker
+    pub fn worker_hooks<H>(mut self, hooks: H) -> Self
+    where
+        H: WorkerLifecycleHooks + 'static,
+    {
+        self.worker_hooks = Some(Arc::new(hooks));
+        self
+    }
+
+    // Serves `EdgeRuntime.flags` from this provider (instead of the `feature_flags`
+    // source of the flags)
+    pub fn feature_flag_provider<P>(mut self, provider: P) -> Self
+    where
+        P: FeatureFlagProvider + Send + Sync + 'static,
+    {
+        self.feature_flag_provider = Some(Arc::new(provider));
+        self
+    }
+
+    // Persists alarms to this store (instead of the `alarms_db_path` of the flags)
+    pub fn alarm_store<S>(mut self, store: S) -> Self
+    where
+        S: AlarmStore + 'static,
+    {
+        self.alarm_store = Some(Arc::new(store));
+        self
+    }
+
+    // Registers a signing key (eg: backed by a KMS) for `EdgeRuntime.crypto`. Keys
+    // are usable by the listed services only, or by all if `services` has `"*"`.
+    pub fn signing_key<S>(mut self, name: &str, signer: S, services: Vec<String>) -> Self
+    where
+        S: Signer + 'static,
+    {
+        self.signing_keys.insert(
+            name,
+            RegisteredKey {
+                signer: Arc::new(signer),
+                services,
+            },
+        );
+        self
+    }
+
+    pub fn on_listening<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(SocketAddr) + Send + Sync + 'static,
+    {
+        self.hooks.on_listening = Some(Arc::new(hook));
+        self
+    }
+
+    // Called for every worker event (boot, shutdown, logs, ...)
+    pub fn on_worker_event<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&WorkerEventWithMetadata) + Send + Sync + 'static,
+    {
+        self.hooks.on_worker_event = Some(Arc::new(hook));
+        self
+    }
+
+    pub async fn build(self) -> Result<EdgeRuntime, Error> {
+        if self.main_service_path.is_none() && self.services.services.is_empty() {
+            bail!("either a main service or at least one service is required");
+        }
+
+        if !self.extensions.is_empty() && CUSTOM_EXTENSIONS.set(self.extensions).is_err() {
+            bail!("custom extensions can only be registered once per process");
+        }
+        if let Some(provider) = self.module_source_provider {
+            if MODULE_SOURCE_PROVIDER.set(provider).is_err() {
+                bail!("a module source provider can only be set once per process");
+            }
+        }
+        if let Some(scanner) = self.module_scanner {
+            set_module_scanner(scanner);
+        }
+        if let Some(hooks) = self.worker_hooks {
+            set_worker_hooks(hooks);
+        }
+
+        let mut flags = self.flags;
+        if !self.signing_keys.is_empty() {
+            let mut store = match flags.key_store_path.take() {
+                Some(path) => KeyStoreConfig::load(Path::new(&path))?.into_key_store()?,
+                None => KeyStore::default(),
+            };
+            // keys name the services of the builder
+            let mut signing_keys = self.signing_keys;
+            signing_keys.scope_services(|name| match self.services.get(name) {
+                Some(service) => service_scope(Path::new(&service.worker_options.service_path)),
+                None => name.to_string(),
+            });
+            store.extend(signing_keys);
+            set_key_store(store);
+        }
+        if let Some(provider) = self.feature_flag_provider {
+            let interval = flags
+                .feature_flags_refresh_secs
+                .unwrap_or(DEFAULT_FEATURE_FLAGS_REFRESH_SECS);
+            start_feature_flags(provider, Duration::from_secs(interval)).await?;
+            flags.feature_flags = None;
+        }
+        if let Some(hook) = self.hooks.on_worker_event.clone() {
+            flags.event_listener = Some(spawn_event_hook(hook, flags.event_listener.take()));
+        }
+
+        let server = Server::with_opts(ServerOpts {
+            // only used by `Server::listen`, embedders bring their own listener
+            ip: Ipv4Addr::LOCALHOST,
+            port: 0,
+            main_service_path: self.main_service_path,
+            events_service_path: self.events_service_path,
+            import_map_path: self.import_map_path,
+            no_module_cache: self.no_module_cache,
+            callback_tx: None,
+            entrypoints: self.entrypoints,
+            flags,
+            services: if self.services.services.is_empty() {
+                None
+            } else {
+                Some(self.services)
+            },
+            alarm_store: self.alarm_store,
+        })
+        .await?;
+
+        Ok(EdgeRuntime {
+            server,
+            hooks: self.hooks,
+        })
+    }
+}
+
+// Calls the hook for every event, then passes it on to the next listener (if any)
+fn spawn_event_hook(
+    hook: WorkerEventHook,
+    maybe_next: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            hook(&event);
+            if let Some(next) = &maybe_next {
+                let _ = next.send(event);
+            }
+        }
+    });
+    tx
+}
+
+// The edge runtime (main, events and user workers) as a library
+pub struct EdgeRuntime {
+    server: Server,
+    hooks: LifecycleHooks,
+}
+
+impl EdgeRuntime {
+    pub fn builder() -> EdgeRuntimeBuilder {
+        EdgeRuntimeBuilder::new()
+    }
+
+    // Handles a request without going through a listener, eg: from a route of
+    // another hyper server
+    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        self.server.handle(req).await
+    }
+
+    // For creating user workers and sending requests to them directly
+    pub fn worker_pool_tx(&self) -> mpsc::UnboundedSender<UserWorkerMsgs> {
+        self.server.worker_pool_tx()
+    }
+
+    // Serves requests on the listener until a shutdown signal is received
+    pub async fn serve(mut self, listener: TcpListener) -> Result<(), Error> {
+        if let Some(hook) = &self.hooks.on_listening {
+            match listener.local_addr() {
+                Ok(addr) => hook(addr),
+                Err(err) => warn!("failed to get the listener address: {}", err),
+            }
+        }
+        self.server.listen_on(listener).await
+    }
+}
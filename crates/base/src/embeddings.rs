@@ -0,0 +1,461 @@
+use crate::onnx::ort_environment;
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::FutureExt;
+use log::{error, info, warn};
+use ndarray::{Array2, ArrayView2, Axis, Ix3};
+use ort::{GraphOptimizationLevel, Session, Tensor};
+use sb_worker_context::embeddings::{EmbedOptions, EmbeddingModels, SharedEmbeddingModels};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot, OnceCell, Semaphore};
+
+// `EdgeRuntime.ai.Session`: built-in embedding models, downloaded to a cache
+// directory on first use and loaded once for all isolates. Texts embedded at the
+// same time (from any isolate) are batched into a single run of the model, and
+// a semaphore caps the runs in flight.
+//
+// Models are downloaded from a pinned revision of their repository, and each
+// file is checked against its sha256 before it's cached, so a moved branch or
+// a tampered download is never loaded.
+
+static EMBEDDING_MODELS: OnceLock<SharedEmbeddingModels> = OnceLock::new();
+
+const MAX_BATCH_SIZE: usize = 32;
+// how long the first text of a batch waits for others
+const BATCH_WINDOW: Duration = Duration::from_millis(5);
+const MAX_SEQUENCE_LENGTH: usize = 512;
+
+// A file of a model's repository, and the sha256 (hex) it must have
+struct ModelFile {
+    path: &'static str,
+    sha256: &'static str,
+}
+
+struct BuiltinModel {
+    name: &'static str,
+    // Hugging Face repository, and the commit files are downloaded from
+    repo: &'static str,
+    revision: &'static str,
+    model: ModelFile,
+    tokenizer: ModelFile,
+}
+
+impl BuiltinModel {
+    fn url(&self, file: &ModelFile) -> String {
+        format!(
+            "https://huggingface.co/{}/resolve/{}/{}",
+            self.repo, self.revision, file.path
+        )
+    }
+}
+
+// TODO: fill in the commit of https://huggingface.co/Supabase/gte-small and
+// the sha256 of its files, the model can't be downloaded until they're pinned
+const BUILTIN_MODELS: &[BuiltinModel] = &[BuiltinModel {
+    name: "gte-small",
+    repo: "Supabase/gte-small",
+    revision: "",
+    model: ModelFile {
+        path: "onnx/model_quantized.onnx",
+        sha256: "",
+    },
+    tokenizer: ModelFile {
+        path: "tokenizer.json",
+        sha256: "",
+    },
+}];
+
+pub fn set_embedding_models(models: SharedEmbeddingModels) {
+    if EMBEDDING_MODELS.set(models).is_err() {
+        warn!("embedding models are already set");
+    }
+}
+
+pub fn embedding_models() -> Option<SharedEmbeddingModels> {
+    EMBEDDING_MODELS.get().cloned()
+}
+
+fn default_max_concurrency() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| (n.get() / 2).max(1))
+        .unwrap_or(1)
+}
+
+fn check_sha256(url: &str, bytes: &[u8], sha256: &str) -> Result<(), Error> {
+    let actual = format!("{:x}", Sha256::digest(bytes));
+    if !actual.eq_ignore_ascii_case(sha256) {
+        bail!("sha256 of {} is {}, expected {}", url, actual, sha256);
+    }
+    Ok(())
+}
+
+// Writes to a temporary file first, so an interrupted (or unverified) download
+// isn't cached
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    sha256: &str,
+    path: &Path,
+) -> Result<(), Error> {
+    if sha256.is_empty() {
+        bail!("{} isn't pinned to a sha256, refusing to download it", url);
+    }
+    info!("downloading {} to {}", url, path.display());
+    let res = client.get(url).send().await?;
+    if !res.status().is_success() {
+        bail!("failed to download {}: {}", url, res.status());
+    }
+    let bytes = res.bytes().await?;
+    check_sha256(url, &bytes, sha256)?;
+    let tmp_path = path.with_extension("download");
+    tokio::fs::write(&tmp_path, &bytes).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+// Mean of the embeddings of the unmasked tokens, or the first token's embedding
+fn pool(hidden: ArrayView2<f32>, mask: &[u32], opts: EmbedOptions) -> Vec<f32> {
+    let mut embedding = if opts.mean_pool {
+        let mut sum = vec![0.0; hidden.ncols()];
+        let mut count = 0.0;
+        for (token, row) in hidden.axis_iter(Axis(0)).enumerate() {
+            if mask.get(token) != Some(&1) {
+                continue;
+            }
+            for (acc, value) in sum.iter_mut().zip(row.iter()) {
+                *acc += value;
+            }
+            count += 1.0;
+        }
+        if count > 0.0 {
+            sum.iter_mut().for_each(|value| *value /= count);
+        }
+        sum
+    } else {
+        hidden.row(0).to_vec()
+    };
+
+    if opts.normalize {
+        let norm = embedding
+            .iter()
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|value| *value /= norm);
+        }
+    }
+    embedding
+}
+
+struct EmbedRequest {
+    text: String,
+    opts: EmbedOptions,
+    result_tx: oneshot::Sender<Result<Vec<f32>, Error>>,
+}
+
+struct LoadedModel {
+    session: Session,
+    tokenizer: Tokenizer,
+}
+
+impl LoadedModel {
+    fn load(dir: &Path) -> Result<Self, Error> {
+        let mut tokenizer =
+            Tokenizer::from_file(dir.join("tokenizer.json")).map_err(|err| anyhow!(err))?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_SEQUENCE_LENGTH,
+                ..Default::default()
+            }))
+            .map_err(|err| anyhow!(err))?;
+
+        ort_environment()?;
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_intra_threads(1)?
+            .commit_from_file(dir.join("model.onnx"))?;
+        Ok(Self { session, tokenizer })
+    }
+
+    // Embeds a batch of texts with a single run of the model
+    fn run(&self, batch: &[(String, EmbedOptions)]) -> Result<Vec<Vec<f32>>, Error> {
+        let texts: Vec<&str> = batch.iter().map(|(text, _)| text.as_str()).collect();
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(|err| anyhow!(err))?;
+        let seq_len = encodings.first().map(|e| e.get_ids().len()).unwrap_or(0);
+
+        let tensor = |f: &dyn Fn(&tokenizers::Encoding) -> &[u32]| -> Result<Array2<i64>, Error> {
+            let data = encodings
+                .iter()
+                .flat_map(|encoding| f(encoding).iter().map(|id| *id as i64))
+                .collect();
+            Ok(Array2::from_shape_vec((encodings.len(), seq_len), data)?)
+        };
+        let input_ids = tensor(&|e| e.get_ids())?;
+        let attention_mask = tensor(&|e| e.get_attention_mask())?;
+        let token_type_ids = tensor(&|e| e.get_type_ids())?;
+
+        // BERT-like models take some of these, by name
+        let mut inputs = vec![];
+        for input in &self.session.inputs {
+            let array = match input.name.as_str() {
+                "input_ids" => &input_ids,
+                "attention_mask" => &attention_mask,
+                "token_type_ids" => &token_type_ids,
+                name => bail!("the model takes an unknown input {}", name),
+            };
+            inputs.push((input.name.as_str(), Tensor::from_array(array.view())?));
+        }
+        let outputs = self.session.run(inputs)?;
+        let output = self
+            .session
+            .outputs
+            .first()
+            .ok_or_else(|| anyhow!("the model has no outputs"))?;
+        let hidden = outputs[output.name.as_str()]
+            .try_extract_tensor::<f32>()?
+            .into_dimensionality::<Ix3>()?;
+
+        Ok(batch
+            .iter()
+            .zip(encodings.iter())
+            .enumerate()
+            .map(|(i, ((_, opts), encoding))| {
+                pool(
+                    hidden.index_axis(Axis(0), i),
+                    encoding.get_attention_mask(),
+                    *opts,
+                )
+            })
+            .collect())
+    }
+}
+
+// Collects the texts sent while the model is busy or within the batch window
+async fn run_batches(
+    model: Arc<LoadedModel>,
+    mut rx: mpsc::UnboundedReceiver<EmbedRequest>,
+    permits: Arc<Semaphore>,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut requests = vec![first];
+        let window = tokio::time::sleep(BATCH_WINDOW);
+        tokio::pin!(window);
+        while requests.len() < MAX_BATCH_SIZE {
+            tokio::select! {
+                req = rx.recv() => match req {
+                    Some(req) => requests.push(req),
+                    None => break,
+                },
+                _ = &mut window => break,
+            }
+        }
+
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            return;
+        };
+        let model = model.clone();
+        tokio::spawn(async move {
+            let batch: Vec<(String, EmbedOptions)> = requests
+                .iter_mut()
+                .map(|req| (std::mem::take(&mut req.text), req.opts))
+                .collect();
+            let result = tokio::task::spawn_blocking(move || model.run(&batch)).await;
+            drop(permit);
+
+            match result.map_err(Error::from).and_then(|r| r) {
+                Ok(embeddings) => {
+                    for (req, embedding) in requests.into_iter().zip(embeddings) {
+                        let _ = req.result_tx.send(Ok(embedding));
+                    }
+                }
+                Err(err) => {
+                    error!("embedding batch failed: {:#}", err);
+                    for req in requests {
+                        let _ = req.result_tx.send(Err(anyhow!("{:#}", err)));
+                    }
+                }
+            }
+        });
+    }
+}
+
+pub struct EmbeddingSessions {
+    handle: Handle,
+    client: reqwest::Client,
+    cache_dir: PathBuf,
+    permits: Arc<Semaphore>,
+    // loaded on first use
+    models: Mutex<HashMap<&'static str, Arc<OnceCell<mpsc::UnboundedSender<EmbedRequest>>>>>,
+}
+
+impl EmbeddingSessions {
+    // Models are downloaded to `cache_dir` and batched on the current runtime
+    pub fn new(cache_dir: PathBuf, max_concurrency: Option<usize>) -> Result<Self, Error> {
+        std::fs::create_dir_all(&cache_dir)
+            .with_context(|| format!("failed to create {}", cache_dir.display()))?;
+        Ok(Self {
+            handle: Handle::current(),
+            client: reqwest::Client::new(),
+            cache_dir,
+            permits: Arc::new(Semaphore::new(
+                max_concurrency
+                    .unwrap_or_else(default_max_concurrency)
+                    .max(1),
+            )),
+            models: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn model_slot(
+        &self,
+        name: &str,
+    ) -> Result<
+        (
+            &'static BuiltinModel,
+            Arc<OnceCell<mpsc::UnboundedSender<EmbedRequest>>>,
+        ),
+        Error,
+    > {
+        let model = BUILTIN_MODELS
+            .iter()
+            .find(|model| model.name == name)
+            .ok_or_else(|| anyhow!("unknown embedding model {}", name))?;
+        let slot = self
+            .models
+            .lock()
+            .unwrap()
+            .entry(model.name)
+            .or_default()
+            .clone();
+        Ok((model, slot))
+    }
+}
+
+async fn start_model(
+    model: &'static BuiltinModel,
+    dir: PathBuf,
+    client: reqwest::Client,
+    permits: Arc<Semaphore>,
+) -> Result<mpsc::UnboundedSender<EmbedRequest>, Error> {
+    tokio::fs::create_dir_all(&dir).await?;
+    for (file, cached) in [
+        (&model.model, "model.onnx"),
+        (&model.tokenizer, "tokenizer.json"),
+    ] {
+        let path = dir.join(cached);
+        if !path.exists() {
+            download(&client, &model.url(file), file.sha256, &path).await?;
+        }
+    }
+    let loaded = tokio::task::spawn_blocking(move || LoadedModel::load(&dir)).await??;
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_batches(Arc::new(loaded), rx, permits));
+    Ok(tx)
+}
+
+impl EmbeddingModels for EmbeddingSessions {
+    fn models(&self) -> Vec<String> {
+        BUILTIN_MODELS
+            .iter()
+            .map(|model| model.name.to_string())
+            .collect()
+    }
+
+    fn embed(
+        &self,
+        model: &str,
+        text: String,
+        opts: EmbedOptions,
+    ) -> BoxFuture<'static, Result<Vec<f32>, Error>> {
+        let (model, slot) = match self.model_slot(model) {
+            Ok(slot) => slot,
+            Err(err) => return future::ready(Err(err)).boxed(),
+        };
+        let dir = self.cache_dir.join(model.name);
+        let client = self.client.clone();
+        let permits = self.permits.clone();
+
+        // loading and batching happen on the server's runtime, shared by all workers
+        let task = self.handle.spawn(async move {
+            let tx = slot
+                .get_or_try_init(|| start_model(model, dir, client, permits))
+                .await?;
+            let (result_tx, result_rx) = oneshot::channel();
+            tx.send(EmbedRequest {
+                text,
+                opts,
+                result_tx,
+            })
+            .map_err(|_| anyhow!("embedding model {} is shut down", model.name))?;
+            result_rx.await?
+        });
+        async move { task.await? }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_sha256, pool, EmbeddingSessions, BUILTIN_MODELS};
+    use ndarray::array;
+    use sb_worker_context::embeddings::{EmbedOptions, EmbeddingModels};
+
+    #[test]
+    fn test_pool() {
+        let hidden = array![[1.0, 2.0], [3.0, 4.0], [100.0, 100.0]];
+        let raw = EmbedOptions {
+            mean_pool: true,
+            normalize: false,
+        };
+        // padding tokens are left out
+        assert_eq!(pool(hidden.view(), &[1, 1, 0], raw), vec![2.0, 3.0]);
+
+        let first = EmbedOptions {
+            mean_pool: false,
+            normalize: false,
+        };
+        assert_eq!(pool(hidden.view(), &[1, 1, 0], first), vec![1.0, 2.0]);
+
+        let normalized = pool(array![[3.0, 4.0]].view(), &[1], EmbedOptions::default());
+        assert_eq!(normalized, vec![0.6, 0.8]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_embedding_model() {
+        let dir = std::env::temp_dir().join("edge-runtime-embeddings-test");
+        let sessions = EmbeddingSessions::new(dir, Some(1)).unwrap();
+        assert_eq!(sessions.models(), vec!["gte-small".to_string()]);
+        assert!(sessions
+            .embed("gpt-9", "hello".to_string(), EmbedOptions::default())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_model_downloads_are_pinned() {
+        let model = &BUILTIN_MODELS[0];
+        let url = model.url(&model.tokenizer);
+        assert!(url.starts_with("https://huggingface.co/Supabase/gte-small/resolve/"));
+        assert!(!url.contains("/resolve/main/"));
+
+        // sha256 of "hello"
+        let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(check_sha256(&url, b"hello", sha256).is_ok());
+        assert!(check_sha256(&url, b"hello", &sha256.to_uppercase()).is_ok());
+        assert!(check_sha256(&url, b"tampered", sha256).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::module_reload::ModuleReload;
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use hyper::{Body, Request, Response};
+use log::error;
+use sb_core::problem::{problem_response, RuntimeErrorCode};
+use sb_worker_context::essentials::{
+    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    WorkerCreationRejected, WorkerRuntimeOpts,
+};
+use sb_worker_context::manifest::{ResolvedWorkerOptions, SharedManifest};
+use sb_worker_context::paused_services::ServicePaused;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::sync::{mpsc, oneshot};
+
+// Routes requests straight to user workers while the main worker is unavailable.
+// Services are resolved from the manifest, or else by the first path segment
+// (`/<name>/...` -> `<services_dir>/<name>`). JWTs are verified by the main
+// worker, so services requiring them are unavailable until it's back.
+#[derive(Clone)]
+pub struct FallbackRouter {
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    manifest: Option<SharedManifest>,
+    services: Option<FallbackServices>,
+}
+
+// lists the services of a fallback services directory that don't require a JWT,
+// eg: `{ "publicServices": ["status"] }`
+pub const FALLBACK_POLICY_FILE: &str = "fallback.json";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct FallbackPolicy {
+    public_services: HashSet<String>,
+}
+
+// Services of a directory, which require a JWT like those of the manifest
+// unless its policy says otherwise
+#[derive(Clone, Debug)]
+pub struct FallbackServices {
+    pub dir: PathBuf,
+    pub public_services: HashSet<String>,
+}
+
+impl FallbackServices {
+    // A directory without a policy isn't served, so its services can't be
+    // reached without a JWT by mistake
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(FALLBACK_POLICY_FILE);
+        let policy = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "fallback services need a policy listing those served without a JWT in {:?}",
+                path
+            )
+        })?;
+        let policy: FallbackPolicy = serde_json::from_str(&policy)
+            .with_context(|| format!("invalid fallback policy {:?}", path))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            public_services: policy.public_services,
+        })
+    }
+}
+
+pub(crate) fn manifest_worker_opts(options: ResolvedWorkerOptions) -> WorkerContextInitOpts {
+    let limits = options.limits;
+    let defaults = UserWorkerRuntimeOpts::default();
+    let conf = UserWorkerRuntimeOpts {
+        memory_limit_mb: limits.memory_limit_mb.unwrap_or(defaults.memory_limit_mb),
+        low_memory_multiplier: limits
+            .low_memory_multiplier
+            .unwrap_or(defaults.low_memory_multiplier),
+        worker_timeout_ms: limits
+            .worker_timeout_ms
+            .unwrap_or(defaults.worker_timeout_ms),
+        cpu_time_threshold_ms: limits
+            .cpu_time_threshold_ms
+            .unwrap_or(defaults.cpu_time_threshold_ms),
+        cpu_burst_interval_ms: limits
+            .cpu_burst_interval_ms
+            .unwrap_or(defaults.cpu_burst_interval_ms),
+        max_cpu_bursts: limits.max_cpu_bursts.unwrap_or(defaults.max_cpu_bursts),
+        hang_threshold_ms: limits
+            .hang_threshold_ms
+            .unwrap_or(defaults.hang_threshold_ms),
+        cpu_weight: limits.cpu_weight,
+        isolation: limits.isolation.unwrap_or(defaults.isolation),
+        max_web_workers: limits.max_web_workers.unwrap_or(defaults.max_web_workers),
+        max_concurrent_requests: limits.max_concurrent_requests,
+        max_workers: limits.max_workers,
+        telemetry_sample_rate: limits.telemetry_sample_rate,
+        disallow_code_generation: limits
+            .disallow_code_generation
+            .unwrap_or(defaults.disallow_code_generation),
+        timer_policy: limits.timers,
+        uncaught_error_policy: limits
+            .on_uncaught_error
+            .unwrap_or(defaults.uncaught_error_policy),
+        detect_leaks: limits.detect_leaks.unwrap_or(defaults.detect_leaks),
+        ..defaults
+    };
+
+    WorkerContextInitOpts {
+        service_path: PathBuf::from(options.service_path),
+        no_module_cache: false,
+        import_map_path: options.import_map_path,
+        env_vars: options.env_vars.into_iter().collect(),
+        events_rx: None,
+        conf: WorkerRuntimeOpts::UserWorker(conf),
+        maybe_eszip: None,
+        maybe_module_code: None,
+        maybe_entrypoint: options.maybe_entrypoint,
+    }
+}
+
+impl FallbackRouter {
+    pub fn new(
+        worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+        manifest: Option<SharedManifest>,
+        services: Option<FallbackServices>,
+    ) -> Self {
+        Self {
+            worker_pool_tx,
+            manifest,
+            services,
+        }
+    }
+
+    // the name of the service serving `path`, if it requires a JWT
+    fn requires_jwt(&self, path: &str) -> Option<String> {
+        if let Some(manifest) = &self.manifest {
+            if let Some(service) = manifest.read().unwrap().resolve(path) {
+                return service.verify_jwt.then_some(service.name);
+            }
+        }
+        let name = path.split('/').find(|p| !p.is_empty())?;
+        let services = self.services.as_ref()?;
+        (!services.public_services.contains(name)).then(|| name.to_string())
+    }
+
+    fn worker_opts(&self, path: &str) -> Option<WorkerContextInitOpts> {
+        if let Some(manifest) = &self.manifest {
+            if let Some(service) = manifest.read().unwrap().resolve(path) {
+                return Some(manifest_worker_opts(service.worker_options));
+            }
+        }
+
+        let name = path.split('/').find(|p| !p.is_empty())?;
+        if name.starts_with('.') {
+            return None;
+        }
+        let service_path = self.services.as_ref()?.dir.join(name);
+        if !service_path.is_dir() {
+            return None;
+        }
+
+        Some(WorkerContextInitOpts {
+            service_path,
+            no_module_cache: false,
+            import_map_path: None,
+            // only services of the manifest have env vars configured
+            env_vars: HashMap::new(),
+            events_rx: None,
+            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts::default()),
+            maybe_eszip: None,
+            maybe_module_code: None,
+            maybe_entrypoint: None,
+        })
+    }
+
+    async fn create_worker(
+        &self,
+        opts: WorkerContextInitOpts,
+    ) -> Result<CreateUserWorkerResult, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.worker_pool_tx.send(UserWorkerMsgs::Create(opts, tx))?;
+        rx.await?
+    }
+
+    async fn send_request(
+        &self,
+        key: uuid::Uuid,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::SendRequest(key, req, tx))?;
+        rx.await?
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let path = req.uri().path().to_string();
+        let Some(mut opts) = self.worker_opts(&path) else {
+            return problem_response(
+                RuntimeErrorCode::WorkerUnavailable,
+                Some(format!(
+                    "main worker is unavailable and no fallback service matches {}",
+                    path
+                )),
+            );
+        };
+        if let Some(name) = self.requires_jwt(&path) {
+            return problem_response(
+                RuntimeErrorCode::WorkerUnavailable,
+                Some(format!(
+                    "main worker is unavailable to verify the JWT required by {}",
+                    name
+                )),
+            );
+        }
+
+        if let Some(reload) = ModuleReload::from_headers(req.headers()) {
+            reload.apply(&mut opts);
+        }
+
+        let worker = match self.create_worker(opts).await {
+            Ok(worker) => worker,
+            Err(err) => {
+                // a paused service isn't an error of the runtime
+                if let Some(paused) = err.downcast_ref::<ServicePaused>() {
+                    return paused.pause.response();
+                }
+                error!("fallback router failed to create worker: {}", err);
+                let code = if err.is::<WorkerCreationRejected>() {
+                    RuntimeErrorCode::Overloaded
+                } else {
+                    RuntimeErrorCode::BootFailure
+                };
+                return problem_response(code, Some(err.to_string()));
+            }
+        };
+
+        match self.send_request(worker.key, req).await {
+            Ok(res) => res,
+            Err(err) => {
+                error!("fallback router failed to send request: {}", err);
+                problem_response(RuntimeErrorCode::WorkerUnavailable, Some(err.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FallbackRouter, FallbackServices};
+    use sb_worker_context::essentials::WorkerIsolation;
+    use sb_worker_context::manifest::Manifest;
+    use std::collections::{HashMap, HashSet};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, RwLock};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_fallback_worker_opts() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let manifest = Manifest::parse(
+            r#"{
+                "services": {
+                    "api": { "entrypoint": "./api", "env": { "TOKEN": "a" }, "limits": { "memoryLimitMb": 64, "isolation": "process" } },
+                    "public": { "entrypoint": "./public", "verifyJwt": false }
+                }
+            }"#,
+            Path::new("/srv"),
+        )
+        .unwrap();
+        let router = FallbackRouter::new(
+            tx,
+            Some(Arc::new(RwLock::new(manifest))),
+            Some(FallbackServices {
+                dir: PathBuf::from("./test_cases"),
+                public_services: HashSet::from(["main".to_string()]),
+            }),
+        );
+
+        let opts = router.worker_opts("/api/users").unwrap();
+        assert_eq!(opts.service_path, PathBuf::from("/srv/./api"));
+        assert_eq!(
+            opts.env_vars,
+            HashMap::from([("TOKEN".to_string(), "a".to_string())])
+        );
+        assert_eq!(opts.conf.as_user_worker().unwrap().memory_limit_mb, 64);
+        assert_eq!(
+            opts.conf.as_user_worker().unwrap().isolation,
+            WorkerIsolation::Process
+        );
+
+        let opts = router.worker_opts("/main/hello").unwrap();
+        assert_eq!(opts.service_path, PathBuf::from("./test_cases/main"));
+        assert!(opts.env_vars.is_empty());
+
+        // JWTs can't be verified without the main worker
+        assert_eq!(router.requires_jwt("/api/users").as_deref(), Some("api"));
+        assert!(router.requires_jwt("/public").is_none());
+        assert!(router.requires_jwt("/main/hello").is_none());
+        // so do services of the directory, unless its policy says otherwise
+        assert_eq!(router.requires_jwt("/hello").as_deref(), Some("hello"));
+
+        // a directory without a policy isn't served
+        assert!(FallbackServices::load(Path::new("./test_cases")).is_err());
+
+        assert!(router.worker_opts("/missing").is_none());
+        assert!(router.worker_opts("/../main").is_none());
+    }
+}
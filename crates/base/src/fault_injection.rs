@@ -0,0 +1,147 @@
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use log::warn;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+static FAULTS: OnceLock<FaultInjectionConfig> = OnceLock::new();
+
+// A fault injected in `rate` (0.0 - 1.0) of the cases, lasting `duration_ms`
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TimedFault {
+    pub rate: f64,
+    pub duration_ms: u64,
+}
+
+// Artificial failures for chaos testing. Only active when loaded with
+// `--fault-injection`; every rate defaults to 0.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FaultInjectionConfig {
+    // remote module fetches failing
+    #[serde(default)]
+    pub module_fetch_failure_rate: f64,
+    // workers taking longer to boot
+    #[serde(default)]
+    pub boot_delay: Option<TimedFault>,
+    // user workers burning CPU before handling requests
+    #[serde(default)]
+    pub cpu_exhaustion: Option<TimedFault>,
+    // runtime events not reaching the events worker
+    #[serde(default)]
+    pub dropped_events_rate: f64,
+}
+
+fn check_rate(name: &str, rate: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&rate) {
+        bail!(
+            "fault injection: {} must be between 0 and 1 (got {})",
+            name,
+            rate
+        );
+    }
+    Ok(())
+}
+
+impl FaultInjectionConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let config: FaultInjectionConfig = serde_json::from_str(json)?;
+        check_rate("moduleFetchFailureRate", config.module_fetch_failure_rate)?;
+        check_rate("droppedEventsRate", config.dropped_events_rate)?;
+        if let Some(fault) = &config.boot_delay {
+            check_rate("bootDelay.rate", fault.rate)?;
+        }
+        if let Some(fault) = &config.cpu_exhaustion {
+            check_rate("cpuExhaustion.rate", fault.rate)?;
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+pub fn enable_fault_injection(config: FaultInjectionConfig) {
+    warn!("fault injection is enabled: {:?}", config);
+    if FAULTS.set(config).is_err() {
+        warn!("fault injection is already enabled");
+    }
+}
+
+fn faults() -> Option<&'static FaultInjectionConfig> {
+    FAULTS.get()
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+fn roll_timed(fault: Option<TimedFault>) -> Option<Duration> {
+    fault
+        .filter(|f| roll(f.rate))
+        .map(|f| Duration::from_millis(f.duration_ms))
+}
+
+pub fn inject_module_fetch_failure() -> bool {
+    faults()
+        .map(|f| roll(f.module_fetch_failure_rate))
+        .unwrap_or(false)
+}
+
+pub fn inject_dropped_event() -> bool {
+    faults()
+        .map(|f| roll(f.dropped_events_rate))
+        .unwrap_or(false)
+}
+
+pub fn inject_boot_delay() -> Option<Duration> {
+    roll_timed(faults()?.boot_delay)
+}
+
+// Busy-loops on the current thread, so the time is counted as the worker's CPU time
+pub fn inject_cpu_exhaustion() {
+    let Some(duration) = faults().and_then(|f| roll_timed(f.cpu_exhaustion)) else {
+        return;
+    };
+    let start = Instant::now();
+    let mut n: u64 = 0;
+    while start.elapsed() < duration {
+        n = std::hint::black_box(n.wrapping_add(1));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{roll, FaultInjectionConfig, TimedFault};
+
+    #[test]
+    fn test_parse_fault_injection_config() {
+        let config = FaultInjectionConfig::parse(
+            r#"{ "moduleFetchFailureRate": 0.25, "bootDelay": { "rate": 1, "durationMs": 500 } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.module_fetch_failure_rate, 0.25);
+        assert_eq!(
+            config.boot_delay,
+            Some(TimedFault {
+                rate: 1.0,
+                duration_ms: 500
+            })
+        );
+        assert_eq!(config.cpu_exhaustion, None);
+        assert_eq!(config.dropped_events_rate, 0.0);
+
+        assert!(FaultInjectionConfig::parse(r#"{ "droppedEventsRate": 1.5 }"#).is_err());
+        assert!(FaultInjectionConfig::parse(r#"{ "unknownFault": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_roll() {
+        assert!(!roll(0.0));
+        assert!(roll(1.0));
+    }
+}
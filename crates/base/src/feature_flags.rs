@@ -0,0 +1,148 @@
+use anyhow::{bail, Error};
+use deno_core::futures::future::BoxFuture;
+use deno_core::futures::FutureExt;
+use log::{error, info};
+use sb_worker_context::flags::{FeatureFlags, SharedFeatureFlags};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::watch;
+
+pub const DEFAULT_FEATURE_FLAGS_REFRESH_SECS: u64 = 30;
+
+static FEATURE_FLAGS: OnceLock<watch::Sender<Arc<FeatureFlags>>> = OnceLock::new();
+
+// Where the runtime gets its feature flags from. Fetched at startup, then every
+// refresh interval.
+pub trait FeatureFlagProvider {
+    fn fetch(&self) -> BoxFuture<'static, Result<FeatureFlags, Error>>;
+}
+
+pub type SharedFeatureFlagProvider = Arc<dyn FeatureFlagProvider + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureFlagSource {
+    // JSON file (see `FeatureFlags`)
+    File(PathBuf),
+    // endpoint responding with the same JSON
+    Http(String),
+}
+
+impl FromStr for FeatureFlagSource {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Ok(FeatureFlagSource::Http(value.to_string()));
+        }
+
+        let path = value.strip_prefix("file://").unwrap_or(value);
+        if path.is_empty() {
+            bail!("invalid feature flag source: {}", value);
+        }
+        Ok(FeatureFlagSource::File(PathBuf::from(path)))
+    }
+}
+
+impl FeatureFlagProvider for FeatureFlagSource {
+    fn fetch(&self) -> BoxFuture<'static, Result<FeatureFlags, Error>> {
+        let source = self.clone();
+        async move {
+            let json = match source {
+                FeatureFlagSource::File(path) => tokio::fs::read_to_string(path).await?,
+                FeatureFlagSource::Http(url) => {
+                    reqwest::get(url).await?.error_for_status()?.text().await?
+                }
+            };
+            FeatureFlags::parse(&json)
+        }
+        .boxed()
+    }
+}
+
+// Fetches the flags, then keeps refreshing them in the background. Workers see
+// changes through `feature_flags_rx`.
+pub async fn start_feature_flags(
+    provider: SharedFeatureFlagProvider,
+    refresh_interval: Duration,
+) -> Result<(), Error> {
+    let flags = provider.fetch().await?;
+    let (tx, _) = watch::channel(Arc::new(flags));
+    if FEATURE_FLAGS.set(tx).is_err() {
+        bail!("feature flags can only be started once per process");
+    }
+
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(refresh_interval);
+        // first tick completes immediately
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            match provider.fetch().await {
+                Ok(flags) => refresh(flags),
+                // keep serving the last known flags
+                Err(err) => error!("failed to refresh feature flags: {}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn refresh(flags: FeatureFlags) {
+    let Some(tx) = FEATURE_FLAGS.get() else {
+        return;
+    };
+    let changed = tx.send_if_modified(|current| {
+        if **current == flags {
+            return false;
+        }
+        *current = Arc::new(flags);
+        true
+    });
+    if changed {
+        info!("feature flags changed");
+    }
+}
+
+pub fn feature_flags_rx() -> Option<SharedFeatureFlags> {
+    FEATURE_FLAGS.get().map(|tx| tx.subscribe())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeatureFlagProvider, FeatureFlagSource};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_feature_flag_source() {
+        assert_eq!(
+            "https://flags.local/edge"
+                .parse::<FeatureFlagSource>()
+                .unwrap(),
+            FeatureFlagSource::Http("https://flags.local/edge".to_string())
+        );
+        assert_eq!(
+            "file:///etc/flags.json"
+                .parse::<FeatureFlagSource>()
+                .unwrap(),
+            FeatureFlagSource::File(PathBuf::from("/etc/flags.json"))
+        );
+        assert!("".parse::<FeatureFlagSource>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_feature_flags_from_file() {
+        let path = std::env::temp_dir().join(format!("flags-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{ "beta": { "default": true } }"#).unwrap();
+
+        let flags = FeatureFlagSource::File(path.clone()).fetch().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            flags.evaluate("beta", "hello", None),
+            Some(deno_core::serde_json::Value::Bool(true))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::utils::units::mib_to_bytes;
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use deno_core::v8;
+use sb_worker_context::essentials::WorkerRuntimeOpts;
+use serde::Deserialize;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::Context;
+use std::time::Duration;
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+use tokio::time::{Instant, Sleep};
+
+static GC_CONFIG: OnceLock<GcConfig> = OnceLock::new();
+static GC_NUDGE: OnceLock<Notify> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct GcTuning {
+    // initial heap of the isolate, V8 sizes the young generation from it
+    pub initial_heap_mb: Option<u64>,
+    // max heap of the isolate (`--max-old-space-size`), user workers are limited
+    // by the `memoryLimitMb` of their service instead
+    pub max_heap_mb: Option<u64>,
+    // the isolate is asked to collect garbage once it's been idle this long
+    pub idle_gc_after_ms: Option<u64>,
+}
+
+// Tuning of each class of worker, eg:
+// `{ "user": { "initialHeapMb": 4, "idleGcAfterMs": 5000 }, "main": { "maxHeapMb": 1024 } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct GcConfig {
+    #[serde(default)]
+    pub main: GcTuning,
+    #[serde(default)]
+    pub events: GcTuning,
+    #[serde(default)]
+    pub user: GcTuning,
+}
+
+impl GcConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let config: Self = serde_json::from_str(json)?;
+        if config.user.max_heap_mb.is_some() {
+            bail!("the heap of user workers is limited by the memoryLimitMb of their service");
+        }
+        for (class, tuning) in [("main", &config.main), ("events", &config.events)] {
+            if tuning.initial_heap_mb.is_some() && tuning.max_heap_mb.is_none() {
+                bail!("the initial heap of the {} worker needs a maxHeapMb", class);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn tuning(&self, conf: &WorkerRuntimeOpts) -> GcTuning {
+        match conf {
+            WorkerRuntimeOpts::MainWorker(_) => self.main,
+            WorkerRuntimeOpts::EventsWorker(_) => self.events,
+            WorkerRuntimeOpts::UserWorker(_) => self.user,
+        }
+    }
+}
+
+pub fn set_gc_config(config: GcConfig) {
+    let _ = GC_CONFIG.set(config);
+}
+
+pub fn gc_tuning(conf: &WorkerRuntimeOpts) -> GcTuning {
+    GC_CONFIG
+        .get()
+        .map(|config| config.tuning(conf))
+        .unwrap_or_default()
+}
+
+// Initial and max heap (bytes) the isolate of a worker is created with, if limited
+pub fn heap_limits(conf: &WorkerRuntimeOpts) -> Option<(usize, usize)> {
+    let tuning = gc_tuning(conf);
+    let initial = mib_to_bytes(tuning.initial_heap_mb.unwrap_or(0)) as usize;
+    match conf {
+        WorkerRuntimeOpts::UserWorker(conf) => {
+            Some((initial, mib_to_bytes(conf.memory_limit_mb) as usize))
+        }
+        _ => tuning
+            .max_heap_mb
+            .map(|max| (initial, mib_to_bytes(max) as usize)),
+    }
+}
+
+// Tells when the event loop of a worker has been idle (not polled) for a while.
+// Polled along with the event loop, every other poll counts as activity.
+pub struct IdleGc {
+    after: Duration,
+    timer: Pin<Box<Sleep>>,
+    collected: bool,
+}
+
+impl IdleGc {
+    pub fn new(after: Duration) -> Self {
+        Self {
+            after,
+            timer: Box::pin(tokio::time::sleep(after)),
+            collected: false,
+        }
+    }
+
+    // Whether to collect garbage now, once per idle period
+    pub fn poll_idle(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.timer.as_mut().poll(cx).is_ready() && !self.collected {
+            self.collected = true;
+            return true;
+        }
+        self.collected = false;
+        self.timer.as_mut().reset(Instant::now() + self.after);
+        let _ = self.timer.as_mut().poll(cx);
+        false
+    }
+}
+
+// Low priority, V8 collects incrementally instead of stopping the isolate
+pub fn notify_idle(isolate: &mut v8::Isolate) {
+    isolate.memory_pressure_notification(v8::MemoryPressureLevel::Moderate);
+}
+
+fn gc_nudge() -> &'static Notify {
+    GC_NUDGE.get_or_init(Notify::new)
+}
+
+// Asks every running worker to collect garbage, eg: when the node is low on memory
+pub fn nudge_gc() {
+    gc_nudge().notify_waiters();
+}
+
+// Tells when the workers were nudged, polled along with the event loop
+pub struct GcNudges {
+    notified: Pin<Box<Notified<'static>>>,
+}
+
+impl Default for GcNudges {
+    fn default() -> Self {
+        Self {
+            notified: Box::pin(gc_nudge().notified()),
+        }
+    }
+}
+
+impl GcNudges {
+    pub fn poll_nudge(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.notified.as_mut().poll(cx).is_pending() {
+            return false;
+        }
+        // waits for the next nudge
+        self.notified = Box::pin(gc_nudge().notified());
+        let _ = self.notified.as_mut().poll(cx);
+        true
+    }
+}
+
+// Collects right away, the node is about to run out of memory
+pub fn notify_pressure(isolate: &mut v8::Isolate) {
+    isolate.memory_pressure_notification(v8::MemoryPressureLevel::Critical);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GcConfig, GcTuning};
+
+    #[test]
+    fn test_gc_config() {
+        let config = GcConfig::parse(
+            r#"{ "user": { "initialHeapMb": 4, "idleGcAfterMs": 5000 }, "main": { "maxHeapMb": 1024 } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.user,
+            GcTuning {
+                initial_heap_mb: Some(4),
+                max_heap_mb: None,
+                idle_gc_after_ms: Some(5000),
+            }
+        );
+        assert_eq!(config.main.max_heap_mb, Some(1024));
+        assert_eq!(config.events, GcTuning::default());
+
+        assert!(GcConfig::parse(r#"{ "user": { "maxHeapMb": 256 } }"#).is_err());
+        assert!(GcConfig::parse(r#"{ "events": { "initialHeapMb": 8 } }"#).is_err());
+        assert!(GcConfig::parse(r#"{ "web": {} }"#).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{bail, Context, Error};
+use hyper::header::HeaderValue;
+use hyper::{Body, Request};
+use log::{error, info};
+use maxminddb::{MaxMindDBError, Reader};
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+pub const GEO_COUNTRY_HEADER: &str = "x-geo-country";
+pub const GEO_REGION_HEADER: &str = "x-geo-region";
+pub const GEO_ASN_HEADER: &str = "x-geo-asn";
+const GEO_HEADERS: [&str; 3] = [GEO_COUNTRY_HEADER, GEO_REGION_HEADER, GEO_ASN_HEADER];
+
+pub const GEOIP_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+static GEOIP: OnceLock<GeoIp> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    // ISO 3166-1 alpha-2
+    pub country: Option<String>,
+    // ISO 3166-2 subdivision code, without the country prefix
+    pub region: Option<String>,
+    pub asn: Option<u32>,
+}
+
+impl GeoInfo {
+    fn is_empty(&self) -> bool {
+        self.country.is_none() && self.region.is_none() && self.asn.is_none()
+    }
+
+    fn merge(&mut self, other: GeoInfo) {
+        self.country = self.country.take().or(other.country);
+        self.region = self.region.take().or(other.region);
+        self.asn = self.asn.or(other.asn);
+    }
+}
+
+// Fields of the GeoIP2/GeoLite2 City, Country and ASN databases we read
+#[derive(Deserialize, Debug)]
+struct MmdbRecord<'a> {
+    #[serde(borrow)]
+    country: Option<IsoCode<'a>>,
+    #[serde(borrow)]
+    subdivisions: Option<Vec<IsoCode<'a>>>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IsoCode<'a> {
+    iso_code: Option<&'a str>,
+}
+
+struct GeoDatabase {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    reader: Reader<Vec<u8>>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl GeoDatabase {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let modified = modified(path);
+        let reader = Reader::open_readfile(path)
+            .with_context(|| format!("failed to open geoip database {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified,
+            reader,
+        })
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let record: MmdbRecord = match self.reader.lookup(ip) {
+            Ok(record) => record,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return None,
+            Err(err) => {
+                error!("geoip lookup in {} failed: {}", self.path.display(), err);
+                return None;
+            }
+        };
+        Some(GeoInfo {
+            country: record.country.and_then(|c| c.iso_code).map(str::to_string),
+            region: record
+                .subdivisions
+                .and_then(|s| s.into_iter().next())
+                .and_then(|s| s.iso_code)
+                .map(str::to_string),
+            asn: record.autonomous_system_number,
+        })
+    }
+}
+
+// MaxMind databases requests are looked up in. A city (or country) and an ASN
+// database are usually combined; the first database with a field wins.
+pub struct GeoIp {
+    databases: RwLock<Vec<Arc<GeoDatabase>>>,
+}
+
+impl GeoIp {
+    pub fn open(paths: &[PathBuf]) -> Result<Self, Error> {
+        let databases = paths
+            .iter()
+            .map(|path| GeoDatabase::open(path).map(Arc::new))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            databases: RwLock::new(databases),
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        for db in self.databases.read().unwrap().iter() {
+            if let Some(found) = db.lookup(ip) {
+                info.merge(found);
+            }
+        }
+        info
+    }
+
+    // Reopens the databases whose file changed. A database that fails to load
+    // keeps being served from the previous file.
+    pub fn reload_changed(&self) {
+        let current = self.databases.read().unwrap().clone();
+        for (i, db) in current.iter().enumerate() {
+            if modified(&db.path) == db.modified {
+                continue;
+            }
+            match GeoDatabase::open(&db.path) {
+                Ok(reloaded) => {
+                    info!("reloaded geoip database {}", db.path.display());
+                    self.databases.write().unwrap()[i] = Arc::new(reloaded);
+                }
+                Err(err) => error!("{:#}", err),
+            }
+        }
+    }
+}
+
+// Looks up requests in these databases from now on, reloading them when they're
+// replaced on disk
+pub fn enable_geoip(paths: &[PathBuf]) -> Result<(), Error> {
+    if GEOIP.set(GeoIp::open(paths)?).is_err() {
+        bail!("geoip can only be enabled once per process");
+    }
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(GEOIP_RELOAD_INTERVAL);
+        // first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Some(geoip) = GEOIP.get() {
+                let _ = tokio::task::spawn_blocking(|| geoip.reload_changed()).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+// The peer, unless it's a local proxy (or unknown, for requests handed to the
+// runtime by an embedder), in which case the client is taken from
+// `x-forwarded-for`
+fn client_ip(req: &Request<Body>, peer: Option<SocketAddr>) -> Option<IpAddr> {
+    let peer_ip = peer.map(|addr| addr.ip());
+    if matches!(peer_ip, Some(ip) if !is_local(ip)) {
+        return peer_ip;
+    }
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .or(peer_ip)
+}
+
+fn set_geo_headers(req: &mut Request<Body>, info: &GeoInfo) {
+    let headers = req.headers_mut();
+    if let Some(country) = info
+        .country
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(GEO_COUNTRY_HEADER, country);
+    }
+    if let Some(region) = info
+        .region
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(GEO_REGION_HEADER, region);
+    }
+    if let Some(asn) = info.asn {
+        headers.insert(GEO_ASN_HEADER, HeaderValue::from(asn));
+    }
+}
+
+// Attaches the geo headers of the client to the request. Any geo headers sent
+// by the client are dropped.
+pub fn apply_geo_headers(req: &mut Request<Body>, peer: Option<SocketAddr>) {
+    let Some(geoip) = GEOIP.get() else {
+        return;
+    };
+    for name in GEO_HEADERS {
+        req.headers_mut().remove(name);
+    }
+    let Some(ip) = client_ip(req, peer) else {
+        return;
+    };
+    let info = geoip.lookup(ip);
+    if !info.is_empty() {
+        set_geo_headers(req, &info);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{client_ip, set_geo_headers, GeoInfo, GEO_ASN_HEADER, GEO_COUNTRY_HEADER};
+    use hyper::{Body, Request};
+
+    #[test]
+    fn test_client_ip() {
+        let req = Request::builder()
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+
+        // a public peer is the client, whatever it claims to forward for
+        assert_eq!(
+            client_ip(&req, Some("198.51.100.1:443".parse().unwrap())),
+            Some("198.51.100.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&req, Some("10.0.0.2:443".parse().unwrap())),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(client_ip(&req, None), Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_geo_info_merge() {
+        let mut info = GeoInfo {
+            country: Some("DE".to_string()),
+            region: Some("BE".to_string()),
+            asn: None,
+        };
+        info.merge(GeoInfo {
+            country: Some("US".to_string()),
+            region: None,
+            asn: Some(3320),
+        });
+
+        let mut req = Request::builder().body(Body::empty()).unwrap();
+        set_geo_headers(&mut req, &info);
+        assert_eq!(req.headers().get(GEO_COUNTRY_HEADER).unwrap(), "DE");
+        assert_eq!(req.headers().get(GEO_ASN_HEADER).unwrap(), "3320");
+    }
+}
@@ -0,0 +1,259 @@
+use std::time::Duration;
+
+// Zero-downtime restarts: on SIGUSR2 the runtime execs a new copy of itself
+// that inherits the listening socket, waits for it to listen and then drains
+// its own connections before exiting. The socket is never closed, so no
+// connection is refused during an upgrade.
+
+// fds of the inherited listeners, set by the process handing them over
+pub const LISTEN_FD_ENV: &str = "EDGE_RUNTIME_LISTEN_FD";
+pub const ADMIN_LISTEN_FD_ENV: &str = "EDGE_RUNTIME_ADMIN_LISTEN_FD";
+// fd the successor writes a byte to once it accepts connections
+pub const READY_FD_ENV: &str = "EDGE_RUNTIME_READY_FD";
+
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{bail, Context, Error};
+    use log::debug;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::process::Command;
+    use tokio::signal::unix::{signal, Signal, SignalKind};
+
+    use super::{ADMIN_LISTEN_FD_ENV, LISTEN_FD_ENV, READY_FD_ENV};
+    use crate::systemd::{take_activated_fd, ADMIN_SOCKET_NAME, HTTP_SOCKET_NAME};
+
+    fn take_env_fd(name: &str) -> Result<Option<RawFd>, Error> {
+        let Some(value) = std::env::var_os(name) else {
+            return Ok(None);
+        };
+        // not passed on to anything this process spawns
+        std::env::remove_var(name);
+        let fd = value
+            .to_str()
+            .and_then(|v| v.parse().ok())
+            .with_context(|| format!("invalid {}: {:?}", name, value))?;
+        Ok(Some(fd))
+    }
+
+    fn set_cloexec(fd: RawFd, cloexec: bool) -> Result<(), Error> {
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let flags = if cloexec {
+                flags | libc::FD_CLOEXEC
+            } else {
+                flags & !libc::FD_CLOEXEC
+            };
+            if libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+
+    // A copy of `fd` that is inherited by exec'd processes
+    fn inheritable_dup(fd: RawFd) -> Result<OwnedFd, Error> {
+        let dup = unsafe { libc::dup(fd) };
+        if dup < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let dup = unsafe { OwnedFd::from_raw_fd(dup) };
+        set_cloexec(dup.as_raw_fd(), false)?;
+        Ok(dup)
+    }
+
+    fn pipe() -> Result<(OwnedFd, OwnedFd), Error> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        set_cloexec(read.as_raw_fd(), true)?;
+        set_cloexec(write.as_raw_fd(), true)?;
+        Ok((read, write))
+    }
+
+    fn listener_from_fd(fd: RawFd) -> Result<std::net::TcpListener, Error> {
+        set_cloexec(fd, true)?;
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        debug!("inherited listener on {:?}", listener.local_addr()?);
+        Ok(listener)
+    }
+
+    // The listener handed over by the previous process, or passed by systemd
+    pub fn inherited_listener() -> Result<Option<std::net::TcpListener>, Error> {
+        take_env_fd(LISTEN_FD_ENV)?
+            .or_else(|| take_activated_fd(HTTP_SOCKET_NAME))
+            .map(listener_from_fd)
+            .transpose()
+    }
+
+    pub fn inherited_admin_listener() -> Result<Option<std::net::TcpListener>, Error> {
+        take_env_fd(ADMIN_LISTEN_FD_ENV)?
+            .or_else(|| take_activated_fd(ADMIN_SOCKET_NAME))
+            .map(listener_from_fd)
+            .transpose()
+    }
+
+    pub fn notify_handed_over() {
+        let fd = match take_env_fd(READY_FD_ENV) {
+            Ok(Some(fd)) => fd,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return;
+            }
+        };
+        let mut ready = unsafe { File::from_raw_fd(fd) };
+        if let Err(err) = ready.write_all(&[1]) {
+            log::error!("failed to notify the previous process: {}", err);
+        }
+    }
+
+    pub struct Successor {
+        pid: u32,
+        ready: File,
+    }
+
+    impl Successor {
+        // Execs this binary again, with the same arguments, handing it the
+        // listeners
+        pub fn spawn<L: AsRawFd, A: AsRawFd>(
+            listener: &L,
+            admin_listener: Option<&A>,
+        ) -> Result<Self, Error> {
+            let listen_fd = inheritable_dup(listener.as_raw_fd())?;
+            let admin_listen_fd = admin_listener
+                .map(|l| inheritable_dup(l.as_raw_fd()))
+                .transpose()?;
+            let (ready_read, ready_write) = pipe()?;
+            let ready_write = inheritable_dup(ready_write.as_raw_fd())?;
+
+            let mut cmd = Command::new(std::env::current_exe()?);
+            cmd.args(std::env::args_os().skip(1))
+                .env(LISTEN_FD_ENV, listen_fd.as_raw_fd().to_string())
+                .env(READY_FD_ENV, ready_write.as_raw_fd().to_string())
+                // the watchdog is the child's to ping once it's the main process
+                .env_remove("WATCHDOG_PID");
+            if let Some(fd) = &admin_listen_fd {
+                cmd.env(ADMIN_LISTEN_FD_ENV, fd.as_raw_fd().to_string());
+            }
+            // our copies are closed once the child has its own
+            let child = cmd
+                .spawn()
+                .context("failed to start the new runtime process")?;
+
+            Ok(Self {
+                pid: child.id(),
+                ready: File::from(ready_read),
+            })
+        }
+
+        pub fn pid(&self) -> u32 {
+            self.pid
+        }
+
+        // Resolves once the successor accepts connections. Fails if it exits
+        // before that.
+        pub async fn ready(mut self) -> Result<(), Error> {
+            let pid = self.pid;
+            tokio::task::spawn_blocking(move || {
+                let mut buf = [0; 1];
+                match self.ready.read(&mut buf)? {
+                    1 => Ok(()),
+                    _ => bail!("new runtime process ({}) exited before listening", pid),
+                }
+            })
+            .await?
+        }
+    }
+
+    // SIGUSR2, registered once so signals between two `recv`s aren't lost
+    pub struct UpgradeSignal(Signal);
+
+    impl UpgradeSignal {
+        pub fn new() -> Result<Self, Error> {
+            Ok(Self(signal(SignalKind::user_defined2())?))
+        }
+
+        pub async fn recv(&mut self) {
+            self.0.recv().await;
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{
+    inherited_admin_listener, inherited_listener, notify_handed_over, Successor, UpgradeSignal,
+};
+
+#[cfg(not(unix))]
+pub fn inherited_listener() -> Result<Option<std::net::TcpListener>, anyhow::Error> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub fn inherited_admin_listener() -> Result<Option<std::net::TcpListener>, anyhow::Error> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub fn notify_handed_over() {}
+
+#[cfg(not(unix))]
+pub struct UpgradeSignal;
+
+#[cfg(not(unix))]
+impl UpgradeSignal {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        Ok(Self)
+    }
+
+    pub async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Successor;
+
+#[cfg(not(unix))]
+impl Successor {
+    pub fn spawn<L, A>(_listener: &L, _admin_listener: Option<&A>) -> Result<Self, anyhow::Error> {
+        anyhow::bail!("zero-downtime restarts are only supported on unix")
+    }
+
+    pub fn pid(&self) -> u32 {
+        0
+    }
+
+    pub async fn ready(self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::{inherited_listener, LISTEN_FD_ENV};
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn test_inherited_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::env::set_var(LISTEN_FD_ENV, listener.into_raw_fd().to_string());
+
+        let inherited = inherited_listener().unwrap().unwrap();
+        assert_eq!(inherited.local_addr().unwrap(), addr);
+        // the variable is consumed
+        assert!(std::env::var_os(LISTEN_FD_ENV).is_none());
+        assert!(inherited_listener().unwrap().is_none());
+    }
+}
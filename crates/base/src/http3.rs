@@ -0,0 +1,237 @@
+use anyhow::{bail, Context, Error};
+use bytes::{Buf, Bytes};
+use deno_tls::rustls;
+use h3::error::ErrorLevel;
+use h3::server::RequestStream;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, HOST};
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use log::{debug, error};
+use std::fs;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+// Experimental HTTP/3 (QUIC) listener serving the same requests as the TCP
+// listener. QUIC always runs over TLS, so it needs a certificate and key.
+//
+// WebTransport isn't supported: it needs the WebTransport extension of h3 and
+// an API handing sessions to workers, left for a follow-up. The listener
+// doesn't advertise extended CONNECT (nor WebTransport) in its settings, so
+// clients don't open sessions, and the extended CONNECT requests sent anyway
+// are refused with `501`.
+
+#[derive(Debug, Clone)]
+pub struct Http3Config {
+    pub addr: SocketAddr,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl Http3Config {
+    pub(crate) fn server_config(&self) -> Result<quinn::ServerConfig, Error> {
+        let cert_file = fs::File::open(&self.cert_path)
+            .with_context(|| format!("failed to open {}", self.cert_path.display()))?;
+        let certs = deno_tls::load_certs(&mut BufReader::new(cert_file))?;
+        let key_bytes = fs::read(&self.key_path)
+            .with_context(|| format!("failed to read {}", self.key_path.display()))?;
+        let Some(key) = deno_tls::load_private_keys(&key_bytes)?.into_iter().next() else {
+            bail!("no private key in {}", self.key_path.display());
+        };
+
+        let mut tls = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+        Ok(quinn::ServerConfig::with_crypto(Arc::new(tls)))
+    }
+}
+
+// Serves HTTP/3 until `shutdown_rx` fires. `make_service` creates the service
+// for the connection of a peer.
+pub async fn serve_http3<F, S>(
+    config: Http3Config,
+    make_service: F,
+    mut shutdown_rx: watch::Receiver<()>,
+) -> Result<(), Error>
+where
+    F: Fn(SocketAddr) -> S,
+    S: Service<Request<Body>, Response = Response<Body>, Error = Error> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    let endpoint = quinn::Endpoint::server(config.server_config()?, config.addr)?;
+    debug!(
+        "edge-runtime is listening for HTTP/3 on {:?}",
+        endpoint.local_addr()?
+    );
+
+    loop {
+        tokio::select! {
+            Some(connecting) = endpoint.accept() => {
+                let service = make_service(connecting.remote_address());
+                tokio::task::spawn(async move {
+                    if let Err(err) = serve_connection(connecting, service).await {
+                        error!("http3 connection error ({:#})", err);
+                    }
+                });
+            }
+            _ = shutdown_rx.changed() => break,
+            else => break,
+        }
+    }
+
+    endpoint.close(0u32.into(), b"shutting down");
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+async fn serve_connection<S>(connecting: quinn::Connecting, service: S) -> Result<(), Error>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Error> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    let conn = connecting.await?;
+    let mut h3_conn: h3::server::Connection<_, Bytes> =
+        h3::server::Connection::new(h3_quinn::Connection::new(conn)).await?;
+
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some((req, stream))) => {
+                let service = service.clone();
+                tokio::task::spawn(async move {
+                    if let Err(err) = serve_request(req, stream, service).await {
+                        debug!("http3 request error ({:#})", err);
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            Err(err) => match err.get_error_level() {
+                ErrorLevel::ConnectionError => return Err(err.into()),
+                ErrorLevel::StreamError => continue,
+            },
+        }
+    }
+}
+
+async fn serve_request<S>(
+    req: Request<()>,
+    stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    mut service: S,
+) -> Result<(), Error>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Error>,
+{
+    let (mut send, mut recv) = stream.split();
+
+    // not forwarded to workers, which would take them for plain CONNECT requests
+    if is_extended_connect(&req) {
+        let res = Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(())?;
+        send.send_response(res).await?;
+        send.finish().await?;
+        return Ok(());
+    }
+
+    let (body_tx, body) = Body::channel();
+    tokio::task::spawn(async move {
+        let mut body_tx = body_tx;
+        loop {
+            match recv.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    let bytes = chunk.copy_to_bytes(chunk.remaining());
+                    if body_tx.send_data(bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    debug!("http3 request body error ({})", err);
+                    body_tx.abort();
+                    return;
+                }
+            }
+        }
+    });
+
+    let res = service.call(into_hyper_request(req, body)).await?;
+    let (parts, mut body) = res.into_parts();
+    send.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(chunk) = body.data().await {
+        send.send_data(chunk?).await?;
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+// WebTransport (and other protocols) upgrade with a CONNECT carrying `:protocol`
+fn is_extended_connect(req: &Request<()>) -> bool {
+    req.method() == Method::CONNECT && req.extensions().get::<h3::ext::Protocol>().is_some()
+}
+
+// Requests are forwarded like the ones of the TCP listener: with the authority
+// in the `host` header and an origin-form URI
+fn into_hyper_request(req: Request<()>, body: Body) -> Request<Body> {
+    let (mut parts, _) = req.into_parts();
+    if let Some(authority) = parts.uri.authority() {
+        if !parts.headers.contains_key(HOST) {
+            if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+                parts.headers.insert(HOST, host);
+            }
+        }
+    }
+    if let Some(path_and_query) = parts.uri.path_and_query() {
+        if let Ok(uri) = Uri::try_from(path_and_query.as_str()) {
+            parts.uri = uri;
+        }
+    }
+    parts.version = hyper::Version::HTTP_11;
+    Request::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{into_hyper_request, is_extended_connect, Http3Config};
+    use hyper::header::HOST;
+    use hyper::{Body, Method, Request};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_requests_are_origin_form() {
+        let req = Request::get("https://example.com:4433/hello?name=bar")
+            .body(())
+            .unwrap();
+        let req = into_hyper_request(req, Body::empty());
+        assert_eq!(req.uri().to_string(), "/hello?name=bar");
+        assert_eq!(req.headers().get(HOST).unwrap(), "example.com:4433");
+        assert_eq!(req.version(), hyper::Version::HTTP_11);
+    }
+
+    #[test]
+    fn test_extended_connect_is_detected() {
+        let mut req = Request::builder()
+            .method(Method::CONNECT)
+            .uri("https://example.com/session")
+            .body(())
+            .unwrap();
+        assert!(!is_extended_connect(&req));
+        req.extensions_mut()
+            .insert(h3::ext::Protocol::WEB_TRANSPORT);
+        assert!(is_extended_connect(&req));
+    }
+
+    #[test]
+    fn test_missing_cert_is_an_error() {
+        let config = Http3Config {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            cert_path: PathBuf::from("./test_cases/missing.pem"),
+            key_path: PathBuf::from("./test_cases/missing.key"),
+        };
+        let err = config.server_config().unwrap_err();
+        assert!(err.to_string().contains("missing.pem"));
+    }
+}
@@ -0,0 +1,16 @@
+use log::warn;
+use sb_core::images::ImageLimits;
+use std::sync::OnceLock;
+
+static IMAGE_LIMITS: OnceLock<ImageLimits> = OnceLock::new();
+
+// Makes `EdgeRuntime.images` available to main and user workers
+pub fn enable_images(limits: ImageLimits) {
+    if IMAGE_LIMITS.set(limits).is_err() {
+        warn!("image transformations are already enabled");
+    }
+}
+
+pub fn image_limits() -> Option<ImageLimits> {
+    IMAGE_LIMITS.get().copied()
+}
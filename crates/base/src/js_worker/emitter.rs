@@ -57,10 +57,15 @@ impl EmitterFactory {
     }
 
     pub fn emitter(&self) -> Result<Arc<Emitter>, AnyError> {
+        self.emitter_with_options(self.emit_options())
+    }
+
+    // eg: with the JSX options of the service's deno.json
+    pub fn emitter_with_options(&self, options: EmitOptions) -> Result<Arc<Emitter>, AnyError> {
         let emitter = Arc::new(Emitter::new(
             self.emit_cache()?,
             self.parsed_source_cache()?,
-            self.emit_options(),
+            options,
         ));
 
         Ok(emitter)
@@ -72,9 +77,9 @@ impl EmitterFactory {
 
     pub fn file_fetcher(&self) -> FileFetcher {
         use module_fetcher::cache::*;
-        let global_cache_struct =
-            GlobalHttpCache::new(self.deno_dir.deps_folder_path(), RealDenoCacheEnv);
-        let global_cache: Arc<dyn HttpCache> = Arc::new(global_cache_struct);
+        let global_cache: Arc<dyn HttpCache> = Arc::new(ContentAddressedHttpCache::new(
+            self.deno_dir.deps_folder_path(),
+        ));
         let http_client = Arc::new(make_http_client().unwrap());
         let blob_store = Arc::new(deno_web::BlobStore::default());
 
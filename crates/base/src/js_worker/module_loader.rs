@@ -1,22 +1,36 @@
+use crate::fault_injection::inject_module_fetch_failure;
+use crate::std_bundle::{std_bundle, StdBundle};
+use crate::timeline::timelines;
 use anyhow::{anyhow, bail, Error};
 use deno_ast::MediaType;
-use deno_core::error::AnyError;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::futures::future::LocalBoxFuture;
 use deno_core::futures::FutureExt;
+use deno_core::serde_json::json;
 use deno_core::ModuleLoader;
 use deno_core::ModuleSource;
 use deno_core::ModuleSourceFuture;
 use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
 use deno_core::ResolutionKind;
+use event_worker::events::BootModule;
 use import_map::ImportMap;
-use module_fetcher::cache::{DenoDir, GlobalHttpCache, HttpCache};
+use module_fetcher::cache::{ContentAddressedHttpCache, DenoDir, HttpCache};
 use module_fetcher::emit::Emitter;
 use module_fetcher::file_fetcher::{CacheSetting, FileFetcher};
 use module_fetcher::http_util::HttpClient;
+use module_fetcher::util::checksum;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use url::Url;
+use std::time::Instant;
+use url::{Origin, Url};
+
+pub use event_worker::events::ModuleSourceKind;
 
 fn get_module_type(media_type: MediaType) -> Result<ModuleType, Error> {
     let module_type = match media_type {
@@ -47,11 +61,194 @@ pub fn make_http_client() -> Result<HttpClient, AnyError> {
     ))
 }
 
-pub struct DefaultModuleLoader {
+// Source of a module, as returned by a `ModuleSourceProvider`
+#[derive(Debug, Clone)]
+pub struct ModuleSourceFile {
+    // final specifier of the module (can differ from the requested one on redirects)
+    pub specifier: ModuleSpecifier,
+    pub media_type: MediaType,
+    pub source: Arc<str>,
+    // where the source came from, as reported in the boot manifest
+    pub loaded_from: ModuleSourceKind,
+}
+
+// Where workers load module sources from. By default they are fetched from disk
+// and remote URLs (through the module cache), embedders can plug in their own
+// provider (eg: to load sources from an artifact store). Custom providers are
+// responsible for restricting which modules can be loaded.
+pub trait ModuleSourceProvider {
+    fn fetch(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> LocalBoxFuture<'static, Result<ModuleSourceFile, AnyError>>;
+}
+
+pub type SharedModuleSourceProvider = Arc<dyn ModuleSourceProvider + Send + Sync>;
+
+struct FileFetcherProvider {
     file_fetcher: FileFetcher,
     permissions: module_fetcher::permissions::Permissions,
+}
+
+impl ModuleSourceProvider for FileFetcherProvider {
+    fn fetch(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> LocalBoxFuture<'static, Result<ModuleSourceFile, AnyError>> {
+        let file_fetcher = self.file_fetcher.clone();
+        let permissions = self.permissions.clone();
+        let specifier = specifier.clone();
+
+        async move {
+            let file = file_fetcher.fetch(&specifier, permissions).await?;
+            let loaded_from = match specifier.scheme() {
+                _ if file.downloaded => ModuleSourceKind::Network,
+                "http" | "https" => ModuleSourceKind::Cache,
+                _ => ModuleSourceKind::Local,
+            };
+            Ok(ModuleSourceFile {
+                specifier: file.specifier,
+                media_type: file.media_type,
+                source: file.source,
+                loaded_from,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+// Serves the modules of the std bundle without going to the network, anything
+// else is fetched from the wrapped provider
+struct StdBundleProvider {
+    bundle: &'static StdBundle,
+    inner: Arc<dyn ModuleSourceProvider>,
+}
+
+impl ModuleSourceProvider for StdBundleProvider {
+    fn fetch(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> LocalBoxFuture<'static, Result<ModuleSourceFile, AnyError>> {
+        match self.bundle.get(specifier) {
+            Some(module) => async move {
+                Ok(ModuleSourceFile {
+                    specifier: module.specifier,
+                    media_type: module.media_type,
+                    source: module.source,
+                    loaded_from: ModuleSourceKind::Bundle,
+                })
+            }
+            .boxed_local(),
+            None => self.inner.fetch(specifier),
+        }
+    }
+}
+
+// Serves local files in place of some modules (eg: to stub remote modules in
+// tests), anything else is fetched from the wrapped provider
+struct MockModuleSourceProvider {
+    mocks: HashMap<ModuleSpecifier, PathBuf>,
+    inner: Arc<dyn ModuleSourceProvider>,
+}
+
+impl ModuleSourceProvider for MockModuleSourceProvider {
+    fn fetch(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> LocalBoxFuture<'static, Result<ModuleSourceFile, AnyError>> {
+        let Some(path) = self.mocks.get(specifier).cloned() else {
+            return self.inner.fetch(specifier);
+        };
+
+        async move {
+            let source = tokio::fs::read_to_string(&path).await?;
+            // relative imports of the mock are resolved next to it
+            let specifier = ModuleSpecifier::from_file_path(&path)
+                .map_err(|_| anyhow!("invalid mock path: {}", path.display()))?;
+            Ok(ModuleSourceFile {
+                media_type: MediaType::from_specifier(&specifier),
+                specifier,
+                source: source.into(),
+                loaded_from: ModuleSourceKind::Mock,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+// Modules loaded while a worker boots. Recorded from the creation of the
+// manifest until they are taken, the default one doesn't record anything.
+#[derive(Clone, Default)]
+pub struct BootManifest(Rc<RefCell<Option<Vec<BootModule>>>>);
+
+impl BootManifest {
+    pub fn new() -> Self {
+        Self(Rc::new(RefCell::new(Some(vec![]))))
+    }
+
+    fn record(&self, module: impl FnOnce() -> BootModule) {
+        if let Some(modules) = self.0.borrow_mut().as_mut() {
+            modules.push(module());
+        }
+    }
+
+    // Stops the recording, returning the modules loaded so far
+    pub fn take(&self) -> Vec<BootModule> {
+        self.0.borrow_mut().take().unwrap_or_default()
+    }
+}
+
+pub struct DefaultModuleLoader {
+    source_provider: Arc<dyn ModuleSourceProvider>,
     emitter: Arc<Emitter>,
     maybe_import_map: Option<ImportMap>,
+    // timeline track module fetches are recorded on
+    maybe_timeline_track: Option<Arc<str>>,
+    // remote origins of the static module graph, the only ones dynamic imports
+    // can reach (see `with_frozen_origins`)
+    maybe_static_origins: Option<Rc<RefCell<HashSet<Origin>>>>,
+    boot_manifest: BootManifest,
+    // checksums of the remote modules locked by the service's lockfile
+    maybe_locked_modules: Option<Arc<HashMap<String, String>>>,
+}
+
+// Whether the source of a remote module matches its checksum in the lockfile,
+// when it's locked
+fn check_locked_module(
+    locked_modules: &HashMap<String, String>,
+    specifier: &ModuleSpecifier,
+    source: &str,
+) -> Result<(), AnyError> {
+    let Some(checksum) = locked_modules.get(specifier.as_str()) else {
+        return Ok(());
+    };
+    if checksum::gen(&[source.as_bytes()]) != *checksum {
+        bail!(
+            "The source code is invalid, as it does not match the expected hash in the lock file.\n  Specifier: {}",
+            specifier
+        );
+    }
+    Ok(())
+}
+
+// Whether a dynamic import may load `specifier`, when the remote origins are frozen
+fn check_dynamic_import(
+    static_origins: &HashSet<Origin>,
+    specifier: &ModuleSpecifier,
+) -> Result<(), AnyError> {
+    if !matches!(specifier.scheme(), "http" | "https")
+        || static_origins.contains(&specifier.origin())
+    {
+        return Ok(());
+    }
+    Err(custom_error(
+        "PermissionDenied",
+        format!(
+            "Dynamic import of {} is not allowed, the static module graph doesn't import from {}",
+            specifier,
+            specifier.origin().ascii_serialization()
+        ),
+    ))
 }
 
 impl DefaultModuleLoader {
@@ -59,40 +256,112 @@ impl DefaultModuleLoader {
         root_path: PathBuf,
         maybe_import_map: Option<ImportMap>,
         emitter: Arc<Emitter>,
-        no_cache: bool,
+        cache_setting: CacheSetting,
         allow_remote: bool,
+        maybe_download_counter: Option<Arc<AtomicU64>>,
+        maybe_source_provider: Option<SharedModuleSourceProvider>,
     ) -> Result<Self, AnyError> {
+        if let Some(source_provider) = maybe_source_provider {
+            return Ok(Self {
+                source_provider,
+                maybe_import_map,
+                emitter,
+                maybe_timeline_track: None,
+                maybe_static_origins: None,
+                boot_manifest: BootManifest::new(),
+                maybe_locked_modules: None,
+            });
+        }
+
         // Note: we are reusing Deno dependency cache path
         let deno_dir = DenoDir::new(None)?;
         let deps_cache_location = deno_dir.deps_folder_path();
 
-        let cache_setting = if no_cache {
-            CacheSetting::ReloadAll
-        } else {
-            CacheSetting::Use
-        };
         let http_client = Arc::new(make_http_client()?);
         let blob_store = Arc::new(deno_web::BlobStore::default());
 
-        let global_cache_struct =
-            GlobalHttpCache::new(deps_cache_location, module_fetcher::cache::RealDenoCacheEnv);
-        let global_cache: Arc<dyn HttpCache> = Arc::new(global_cache_struct);
-        let file_fetcher = FileFetcher::new(
+        let global_cache: Arc<dyn HttpCache> =
+            Arc::new(ContentAddressedHttpCache::new(deps_cache_location));
+        let mut file_fetcher = FileFetcher::new(
             global_cache.clone(),
             cache_setting,
             allow_remote,
             http_client,
             blob_store,
         );
+        if let Some(counter) = maybe_download_counter {
+            file_fetcher.set_download_counter(counter);
+        }
         let permissions = module_fetcher::permissions::Permissions::new(root_path);
-
-        Ok(Self {
+        let mut source_provider: Arc<dyn ModuleSourceProvider> = Arc::new(FileFetcherProvider {
             file_fetcher,
             permissions,
+        });
+        if let Some(bundle) = std_bundle() {
+            source_provider = Arc::new(StdBundleProvider {
+                bundle,
+                inner: source_provider,
+            });
+        }
+
+        Ok(Self {
+            source_provider,
             maybe_import_map,
             emitter,
+            maybe_timeline_track: None,
+            maybe_static_origins: None,
+            boot_manifest: BootManifest::new(),
+            maybe_locked_modules: None,
         })
     }
+
+    // Loads the given files in place of the modules they are mapped to, by
+    // their resolved specifier (after the import map)
+    pub fn with_mocks(mut self, mocks: &HashMap<String, PathBuf>) -> Result<Self, AnyError> {
+        if mocks.is_empty() {
+            return Ok(self);
+        }
+
+        let mut resolved = HashMap::new();
+        for (specifier, path) in mocks {
+            let specifier = ModuleSpecifier::parse(specifier)
+                .map_err(|err| anyhow!("invalid mocked specifier {:?}: {}", specifier, err))?;
+            let path = std::fs::canonicalize(path)
+                .map_err(|err| anyhow!("mock {} not found: {}", path.display(), err))?;
+            resolved.insert(specifier, path);
+        }
+        self.source_provider = Arc::new(MockModuleSourceProvider {
+            mocks: resolved,
+            inner: self.source_provider,
+        });
+        Ok(self)
+    }
+
+    pub fn with_timeline_track(mut self, track: String) -> Self {
+        self.maybe_timeline_track = Some(track.into());
+        self
+    }
+
+    // Freezes the remote origins modules can be loaded from to those of the
+    // static module graph: dynamic imports (and their own imports) of modules
+    // from other origins are rejected with a `PermissionDenied` error, so a
+    // compromised dependency can't pull in code from (or leak data to) a new
+    // origin at runtime
+    pub fn with_frozen_origins(mut self) -> Self {
+        self.maybe_static_origins = Some(Rc::default());
+        self
+    }
+
+    // Rejects remote modules whose source doesn't match their checksum in the
+    // lockfile, as `deno run` does
+    pub fn with_locked_modules(mut self, locked_modules: HashMap<String, String>) -> Self {
+        self.maybe_locked_modules = Some(Arc::new(locked_modules));
+        self
+    }
+
+    pub fn boot_manifest(&self) -> BootManifest {
+        self.boot_manifest.clone()
+    }
 }
 
 impl ModuleLoader for DefaultModuleLoader {
@@ -132,16 +401,31 @@ impl ModuleLoader for DefaultModuleLoader {
         &self,
         module_specifier: &ModuleSpecifier,
         _maybe_referrer: Option<&ModuleSpecifier>,
-        _is_dyn_import: bool,
+        is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
-        let file_fetcher = self.file_fetcher.clone();
-        let permissions = self.permissions.clone();
+        let source_provider = self.source_provider.clone();
         let module_specifier = module_specifier.clone();
         let emitter = self.emitter.clone();
+        let maybe_timeline_track = self.maybe_timeline_track.clone();
+        let maybe_static_origins = self.maybe_static_origins.clone();
+        let boot_manifest = self.boot_manifest.clone();
+        let maybe_locked_modules = self.maybe_locked_modules.clone();
 
         async move {
-            let fetched_file = file_fetcher
-                .fetch(&module_specifier, permissions)
+            let started = Instant::now();
+            let is_remote = matches!(module_specifier.scheme(), "http" | "https");
+            if is_remote && inject_module_fetch_failure() {
+                bail!(
+                    "Failed to load module: {:?} - injected fault",
+                    module_specifier.as_str()
+                );
+            }
+            if let (Some(static_origins), true) = (&maybe_static_origins, is_dyn_import) {
+                check_dynamic_import(&static_origins.borrow(), &module_specifier)?;
+            }
+
+            let fetched_file = source_provider
+                .fetch(&module_specifier)
                 .await
                 .map_err(|err| {
                     anyhow!(
@@ -150,7 +434,34 @@ impl ModuleLoader for DefaultModuleLoader {
                         err
                     )
                 })?;
+            if let Some(static_origins) = &maybe_static_origins {
+                let mut static_origins = static_origins.borrow_mut();
+                if is_dyn_import {
+                    // redirects can't lead to a new origin either
+                    check_dynamic_import(&static_origins, &fetched_file.specifier)?;
+                } else {
+                    for specifier in [&module_specifier, &fetched_file.specifier] {
+                        if matches!(specifier.scheme(), "http" | "https") {
+                            static_origins.insert(specifier.origin());
+                        }
+                    }
+                }
+            }
+            if let Some(locked_modules) = &maybe_locked_modules {
+                check_locked_module(
+                    locked_modules,
+                    &fetched_file.specifier,
+                    &fetched_file.source,
+                )?;
+            }
             let module_type = get_module_type(fetched_file.media_type)?;
+            let fetched = Instant::now();
+            boot_manifest.record(|| BootModule {
+                specifier: module_specifier.to_string(),
+                resolved: fetched_file.specifier.to_string(),
+                hash: checksum::gen(&[fetched_file.source.as_bytes()]),
+                source: fetched_file.loaded_from,
+            });
 
             let code = fetched_file.source;
             let code = match fetched_file.media_type {
@@ -172,6 +483,12 @@ impl ModuleLoader for DefaultModuleLoader {
                 }
             };
 
+            if let (Some(timelines), Some(track)) = (timelines(), &maybe_timeline_track) {
+                let args = json!({ "specifier": module_specifier.as_str() });
+                timelines.record(track, "module", "fetch", started, fetched, args.clone());
+                timelines.span(track, "module", "transpile", fetched, args);
+            }
+
             let module = ModuleSource::new_with_redirect(
                 module_type,
                 code,
@@ -184,3 +501,78 @@ impl ModuleLoader for DefaultModuleLoader {
         .boxed_local()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{check_dynamic_import, check_locked_module, BootManifest, ModuleSourceKind};
+    use deno_core::error::get_custom_error_class;
+    use deno_core::ModuleSpecifier;
+    use event_worker::events::BootModule;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn test_dynamic_imports_of_new_origins_are_blocked() {
+        let specifier = |url: &str| ModuleSpecifier::parse(url).unwrap();
+        let static_origins =
+            HashSet::from([specifier("https://deno.land/std@0.203.0/http/server.ts").origin()]);
+
+        assert!(check_dynamic_import(
+            &static_origins,
+            &specifier("https://deno.land/x/oak/mod.ts")
+        )
+        .is_ok());
+        assert!(
+            check_dynamic_import(&static_origins, &specifier("file:///srv/hello/lazy.ts")).is_ok()
+        );
+        let err = check_dynamic_import(&static_origins, &specifier("https://evil.example/x.js"))
+            .unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("PermissionDenied"));
+        assert!(err.to_string().contains("https://evil.example"));
+    }
+
+    #[test]
+    fn test_locked_modules_must_match_their_checksum() {
+        let specifier = |url: &str| ModuleSpecifier::parse(url).unwrap();
+        let source = "export const x = 1;";
+        let locked = HashMap::from([(
+            "https://deno.land/x/mod.ts".to_string(),
+            module_fetcher::util::checksum::gen(&[source.as_bytes()]),
+        )]);
+
+        assert!(
+            check_locked_module(&locked, &specifier("https://deno.land/x/mod.ts"), source).is_ok()
+        );
+        let err = check_locked_module(
+            &locked,
+            &specifier("https://deno.land/x/mod.ts"),
+            "export const x = 2;",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not match the expected hash"));
+        // not locked
+        assert!(check_locked_module(&locked, &specifier("https://esm.sh/zod"), source).is_ok());
+    }
+
+    #[test]
+    fn test_boot_manifest_stops_recording_once_taken() {
+        let module = |specifier: &str| BootModule {
+            specifier: specifier.to_string(),
+            resolved: specifier.to_string(),
+            hash: String::new(),
+            source: ModuleSourceKind::Cache,
+        };
+        let manifest = BootManifest::new();
+        manifest.record(|| module("https://deno.land/std/http/server.ts"));
+        let modules = manifest.take();
+        assert_eq!(modules.len(), 1);
+        assert_eq!(modules[0].specifier, "https://deno.land/std/http/server.ts");
+
+        // loaded after the boot (eg: dynamic imports)
+        manifest.record(|| module("https://deno.land/x/oak/mod.ts"));
+        assert!(manifest.take().is_empty());
+        // eszip services don't record a manifest
+        let none = BootManifest::default();
+        none.record(|| module("file:///srv/hello/index.ts"));
+        assert!(none.take().is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::node::NodeIdentity;
+use crate::preload::Preloads;
+use anyhow::Error;
+use deno_core::serde_json::json;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use std::convert::Infallible;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+
+// Kubernetes lifecycle: readiness and liveness probes on ports of their own, a
+// `preStop` hook taking the pod out of rotation, and connections drained on
+// SIGTERM within the pod's termination grace period.
+
+pub const DEFAULT_PRESTOP_DELAY: Duration = Duration::from_secs(5);
+pub const DEFAULT_TERMINATION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+// left of the grace period for the process to exit once connections are drained
+const EXIT_MARGIN: Duration = Duration::from_secs(2);
+
+// env vars set from the downward API (`fieldRef`) in the pod spec
+const NODE_NAME_ENV: &str = "NODE_NAME";
+const POD_NAME_ENV: &str = "POD_NAME";
+const POD_NAMESPACE_ENV: &str = "POD_NAMESPACE";
+// usually copied from the `topology.kubernetes.io` labels of the node
+const ZONE_ENV: &str = "TOPOLOGY_ZONE";
+const REGION_ENV: &str = "TOPOLOGY_REGION";
+
+static LIFECYCLE: OnceLock<Lifecycle> = OnceLock::new();
+
+pub struct Lifecycle {
+    prestop_delay: Duration,
+    termination_grace_period: Duration,
+    // when the pod started terminating (`preStop` hook or SIGTERM)
+    draining_since: Mutex<Option<Instant>>,
+}
+
+impl Lifecycle {
+    pub fn new(prestop_delay: Duration, termination_grace_period: Duration) -> Self {
+        Self {
+            prestop_delay,
+            termination_grace_period,
+            draining_since: Mutex::new(None),
+        }
+    }
+
+    // Takes the pod out of rotation (readiness fails from now on), returns when it
+    // started terminating
+    pub fn start_draining(&self) -> Instant {
+        *self
+            .draining_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining_since.lock().unwrap().is_some()
+    }
+
+    // New connections are still accepted for the `preStop` delay, the time it
+    // takes for the pod to be removed from the endpoints of its services
+    pub fn stop_accepting_at(&self) -> Instant {
+        self.start_draining() + self.prestop_delay
+    }
+
+    // The grace period counts from the `preStop` hook, so open connections get
+    // what it leaves
+    pub fn drain_deadline(&self) -> Instant {
+        self.start_draining() + self.termination_grace_period.saturating_sub(EXIT_MARGIN)
+    }
+}
+
+pub fn enable_kubernetes_lifecycle(lifecycle: Lifecycle) {
+    if LIFECYCLE.set(lifecycle).is_err() {
+        warn!("kubernetes lifecycle is already enabled");
+    }
+}
+
+pub fn kubernetes_lifecycle() -> Option<&'static Lifecycle> {
+    LIFECYCLE.get()
+}
+
+// Where the pod runs, from the env vars set with the downward API. `base` (the
+// `--region` and `--zone` of the node) takes precedence.
+pub fn downward_api_identity(base: NodeIdentity) -> NodeIdentity {
+    identity_from_env(base, |name| {
+        std::env::var(name).ok().filter(|value| !value.is_empty())
+    })
+}
+
+fn identity_from_env(base: NodeIdentity, var: impl Fn(&str) -> Option<String>) -> NodeIdentity {
+    let pod = match (var(POD_NAMESPACE_ENV), var(POD_NAME_ENV)) {
+        (Some(namespace), Some(name)) => Some(format!("{}/{}", namespace, name)),
+        (None, name) => name,
+        (Some(_), None) => None,
+    };
+    NodeIdentity {
+        region: base.region.or_else(|| var(REGION_ENV)),
+        zone: base.zone.or_else(|| var(ZONE_ENV)),
+        node: base.node.or_else(|| var(NODE_NAME_ENV)),
+        pod: base.pod.or(pod),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProbePorts {
+    pub readiness: Option<u16>,
+    pub liveness: Option<u16>,
+}
+
+impl ProbePorts {
+    pub fn is_set(&self) -> bool {
+        self.readiness.is_some() || self.liveness.is_some()
+    }
+}
+
+fn probe_response(status: StatusCode, body: deno_core::serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn handle_probe(
+    req: Request<Body>,
+    readiness: bool,
+    liveness: bool,
+    preloads: Preloads,
+) -> Result<Response<Body>, Infallible> {
+    let lifecycle = kubernetes_lifecycle();
+    let res = match (req.method(), req.uri().path(), lifecycle) {
+        // answered from the server's runtime, so it fails when the runtime hangs
+        (&Method::GET, "/livez", _) if liveness => probe_response(StatusCode::OK, json!({})),
+        (&Method::GET, "/readyz", _) if readiness => {
+            let report = preloads.report();
+            let draining = lifecycle.is_some_and(Lifecycle::is_draining);
+            let status = if report.ready && !draining {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            probe_response(
+                status,
+                json!({ "ready": report.ready, "draining": draining }),
+            )
+        }
+        // `preStop` hook, returns once the pod is out of rotation
+        (&Method::GET | &Method::POST, "/prestop", Some(lifecycle)) if readiness => {
+            info!("preStop hook called, draining");
+            tokio::time::sleep_until(lifecycle.stop_accepting_at()).await;
+            probe_response(StatusCode::OK, json!({ "draining": true }))
+        }
+        _ => probe_response(StatusCode::NOT_FOUND, json!({ "msg": "not found" })),
+    };
+    Ok(res)
+}
+
+async fn serve_probe(listener: TcpListener, readiness: bool, liveness: bool, preloads: Preloads) {
+    loop {
+        match listener.accept().await {
+            Ok((conn, _)) => {
+                let preloads = preloads.clone();
+                tokio::task::spawn(async move {
+                    let service = service_fn(move |req| {
+                        handle_probe(req, readiness, liveness, preloads.clone())
+                    });
+                    if let Err(e) = Http::new().serve_connection(conn, service).await {
+                        error!("probe connection error ({:?})", e);
+                    }
+                });
+            }
+            Err(e) => error!("probe socket error: {}", e),
+        }
+    }
+}
+
+// Serves `/readyz` (and `/prestop`) on the readiness port and `/livez` on the
+// liveness port, which may be the same
+pub async fn start_probes(
+    ip: std::net::IpAddr,
+    ports: ProbePorts,
+    preloads: Preloads,
+) -> Result<(), Error> {
+    let mut listeners: Vec<(u16, bool, bool)> = vec![];
+    if let Some(port) = ports.readiness {
+        listeners.push((port, true, ports.liveness == Some(port)));
+    }
+    if let Some(port) = ports.liveness.filter(|port| ports.readiness != Some(*port)) {
+        listeners.push((port, false, true));
+    }
+    for (port, readiness, liveness) in listeners {
+        let listener = TcpListener::bind((ip, port)).await?;
+        debug!("probes are listening on {:?}", listener.local_addr()?);
+        tokio::task::spawn(serve_probe(listener, readiness, liveness, preloads.clone()));
+    }
+    Ok(())
+}
+
+// SIGTERM, only caught in Kubernetes mode (it otherwise terminates the process
+// right away)
+#[cfg(unix)]
+pub struct TerminateSignal(Option<tokio::signal::unix::Signal>);
+
+#[cfg(unix)]
+impl TerminateSignal {
+    pub fn new() -> Result<Self, Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self(match kubernetes_lifecycle() {
+            Some(_) => Some(signal(SignalKind::terminate())?),
+            None => None,
+        }))
+    }
+
+    pub async fn recv(&mut self) {
+        match &mut self.0 {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub struct TerminateSignal;
+
+#[cfg(not(unix))]
+impl TerminateSignal {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    pub async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{identity_from_env, Lifecycle, EXIT_MARGIN};
+    use crate::node::NodeIdentity;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_identity_from_env() {
+        let env = HashMap::from([
+            ("NODE_NAME", "node-a"),
+            ("POD_NAME", "edge-runtime-7c9"),
+            ("POD_NAMESPACE", "functions"),
+            ("TOPOLOGY_ZONE", "eu-west-1b"),
+            ("TOPOLOGY_REGION", "eu-west-1"),
+        ]);
+        let var = |name: &str| env.get(name).map(|value| value.to_string());
+        let identity = identity_from_env(
+            NodeIdentity {
+                region: Some("eu".to_string()),
+                ..Default::default()
+            },
+            var,
+        );
+        assert_eq!(
+            identity,
+            NodeIdentity {
+                region: Some("eu".to_string()),
+                zone: Some("eu-west-1b".to_string()),
+                node: Some("node-a".to_string()),
+                pod: Some("functions/edge-runtime-7c9".to_string()),
+            }
+        );
+        assert!(identity_from_env(NodeIdentity::default(), |_| None).is_empty());
+    }
+
+    #[test]
+    fn test_drain_timing() {
+        let lifecycle = Lifecycle::new(Duration::from_secs(5), Duration::from_secs(30));
+        assert!(!lifecycle.is_draining());
+        let started = lifecycle.start_draining();
+        assert!(lifecycle.is_draining());
+
+        // SIGTERM comes in after the preStop hook, the grace period started with it
+        assert_eq!(lifecycle.start_draining(), started);
+        assert_eq!(
+            lifecycle.stop_accepting_at(),
+            started + Duration::from_secs(5)
+        );
+        assert_eq!(
+            lifecycle.drain_deadline(),
+            started + Duration::from_secs(30) - EXIT_MARGIN
+        );
+    }
+}
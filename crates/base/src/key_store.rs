@@ -0,0 +1,504 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::FutureExt;
+use deno_core::serde_json::{self, json, Value};
+use log::warn;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+    ECDSA_P256_SHA256_FIXED_SIGNING, ED25519,
+};
+use sb_worker_context::keys::{KeyStore, RegisteredKey, SharedKeyStore, SharedSigner, Signer};
+use sb_worker_context::service_scope::config_service_scope;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+const VAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+static KEY_STORE: OnceLock<SharedKeyStore> = OnceLock::new();
+
+pub fn set_key_store(store: KeyStore) {
+    if KEY_STORE.set(Arc::new(store)).is_err() {
+        warn!("key store is already set");
+    }
+}
+
+pub fn key_store() -> Option<SharedKeyStore> {
+    KEY_STORE.get().cloned()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAlgorithm {
+    HmacSha256,
+    Ed25519,
+    EcdsaP256,
+}
+
+impl KeyAlgorithm {
+    fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::HmacSha256 => "hmac-sha256",
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::EcdsaP256 => "ecdsa-p256",
+        }
+    }
+}
+
+fn default_vault_mount() -> String {
+    "transit".to_string()
+}
+
+fn default_vault_key_version() -> u32 {
+    1
+}
+
+// Key held by Vault's transit secrets engine, eg:
+// `{ "address": "https://vault:8200", "key": "signed-urls", "tokenEnv": "VAULT_TOKEN" }`.
+// The version is pinned, as signatures are handed to functions without Vault's
+// `vault:v1:` prefix.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VaultKeyConfig {
+    pub address: String,
+    pub key: String,
+    pub token_env: String,
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+    #[serde(default = "default_vault_key_version")]
+    pub key_version: u32,
+}
+
+// Where the key material is read from, or the KMS holding the key. HMAC secrets
+// are base64 encoded, private keys are PKCS#8 (PEM, or base64 encoded DER in env
+// vars).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KeyConfig {
+    pub algorithm: KeyAlgorithm,
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub env: Option<String>,
+    #[serde(default)]
+    pub vault: Option<VaultKeyConfig>,
+    // services allowed to use the key, `"*"` for all of them
+    pub services: Vec<String>,
+}
+
+// eg: `{ "keys": { "signed-urls": { "algorithm": "hmac-sha256", "env": "URL_SECRET", "services": ["media"] } } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KeyStoreConfig {
+    pub keys: HashMap<String, KeyConfig>,
+}
+
+enum LocalKey {
+    Hmac(hmac::Key),
+    Ed25519(Ed25519KeyPair),
+    EcdsaP256(EcdsaKeyPair),
+}
+
+// Key held in the runtime's memory
+pub struct LocalSigner {
+    algorithm: KeyAlgorithm,
+    key: Arc<LocalKey>,
+}
+
+fn decode_pem_or_base64(material: &str) -> Result<Vec<u8>, Error> {
+    let body: String = material
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    Ok(base64::decode(body)?)
+}
+
+impl LocalSigner {
+    pub fn new(algorithm: KeyAlgorithm, material: &str) -> Result<Self, Error> {
+        let bytes = decode_pem_or_base64(material.trim())?;
+        let key = match algorithm {
+            KeyAlgorithm::HmacSha256 => LocalKey::Hmac(hmac::Key::new(hmac::HMAC_SHA256, &bytes)),
+            KeyAlgorithm::Ed25519 => LocalKey::Ed25519(
+                Ed25519KeyPair::from_pkcs8_maybe_unchecked(&bytes)
+                    .map_err(|err| anyhow!("invalid ed25519 key: {}", err))?,
+            ),
+            KeyAlgorithm::EcdsaP256 => LocalKey::EcdsaP256(
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &bytes)
+                    .map_err(|err| anyhow!("invalid ecdsa-p256 key: {}", err))?,
+            ),
+        };
+        Ok(Self {
+            algorithm,
+            key: Arc::new(key),
+        })
+    }
+
+    fn sign_sync(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(match &*self.key {
+            LocalKey::Hmac(key) => hmac::sign(key, data).as_ref().to_vec(),
+            LocalKey::Ed25519(key) => key.sign(data).as_ref().to_vec(),
+            LocalKey::EcdsaP256(key) => key
+                .sign(&SystemRandom::new(), data)
+                .map_err(|_| anyhow!("signing failed"))?
+                .as_ref()
+                .to_vec(),
+        })
+    }
+
+    fn verify_sync(&self, data: &[u8], signature: &[u8]) -> bool {
+        match &*self.key {
+            LocalKey::Hmac(key) => hmac::verify(key, data, signature).is_ok(),
+            LocalKey::Ed25519(key) => UnparsedPublicKey::new(&ED25519, key.public_key().as_ref())
+                .verify(data, signature)
+                .is_ok(),
+            LocalKey::EcdsaP256(key) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.public_key().as_ref())
+                    .verify(data, signature)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+impl Signer for LocalSigner {
+    fn algorithm(&self) -> &str {
+        self.algorithm.as_str()
+    }
+
+    fn sign(&self, data: Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, Error>> {
+        future::ready(self.sign_sync(&data)).boxed()
+    }
+
+    fn verify(&self, data: Vec<u8>, signature: Vec<u8>) -> BoxFuture<'static, Result<bool, Error>> {
+        future::ready(Ok(self.verify_sync(&data, &signature))).boxed()
+    }
+}
+
+// Key held by Vault, used through the transit engine's HTTP API
+pub struct VaultSigner {
+    algorithm: KeyAlgorithm,
+    client: reqwest::Client,
+    // `{address}/v1/{mount}`
+    base_url: String,
+    key: String,
+    key_version: u32,
+    token: String,
+}
+
+impl VaultSigner {
+    pub fn new(
+        algorithm: KeyAlgorithm,
+        config: &VaultKeyConfig,
+        token: String,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            algorithm,
+            client: reqwest::Client::builder().timeout(VAULT_TIMEOUT).build()?,
+            base_url: format!(
+                "{}/v1/{}",
+                config.address.trim_end_matches('/'),
+                config.mount.trim_matches('/')
+            ),
+            key: config.key.clone(),
+            key_version: config.key_version,
+            token,
+        })
+    }
+
+    // `hmac/{key}/sha2-256`, `sign/{key}` or `verify/{key}`, and the body of the request
+    fn request(&self, action: &str, data: &[u8]) -> (String, Value) {
+        let mut body = json!({
+            "input": base64::encode(data),
+            "key_version": self.key_version,
+        });
+        let path = match self.algorithm {
+            KeyAlgorithm::HmacSha256 => format!("{}/{}/sha2-256", action, self.key),
+            KeyAlgorithm::EcdsaP256 => {
+                // raw `r || s`, as with local keys
+                body["hash_algorithm"] = json!("sha2-256");
+                body["marshaling_algorithm"] = json!("jws");
+                format!("{}/{}", action, self.key)
+            }
+            KeyAlgorithm::Ed25519 => format!("{}/{}", action, self.key),
+        };
+        (format!("{}/{}", self.base_url, path), body)
+    }
+
+    // Signatures are base64url encoded with the `jws` marshaling
+    fn base64_config(&self) -> base64::Config {
+        match self.algorithm {
+            KeyAlgorithm::EcdsaP256 => base64::URL_SAFE_NO_PAD,
+            _ => base64::STANDARD,
+        }
+    }
+
+    fn send(&self, url: String, body: Value) -> BoxFuture<'static, Result<Value, Error>> {
+        let request = self
+            .client
+            .post(url)
+            .header("X-Vault-Token", &self.token)
+            .json(&body);
+        async move {
+            let res = request.send().await?;
+            let status = res.status();
+            if !status.is_success() {
+                bail!("Vault responded with {}", status);
+            }
+            let body: Value = res.json().await?;
+            Ok(body["data"].clone())
+        }
+        .boxed()
+    }
+}
+
+impl Signer for VaultSigner {
+    fn algorithm(&self) -> &str {
+        self.algorithm.as_str()
+    }
+
+    fn sign(&self, data: Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, Error>> {
+        let action = match self.algorithm {
+            KeyAlgorithm::HmacSha256 => "hmac",
+            _ => "sign",
+        };
+        let (url, body) = self.request(action, &data);
+        let response = self.send(url, body);
+        let prefix = format!("vault:v{}:", self.key_version);
+        let config = self.base64_config();
+        async move {
+            let data = response.await?;
+            let signature = data["hmac"]
+                .as_str()
+                .or_else(|| data["signature"].as_str())
+                .ok_or_else(|| anyhow!("Vault returned no signature"))?;
+            let signature = signature
+                .strip_prefix(&prefix)
+                .ok_or_else(|| anyhow!("Vault signed with another key version"))?;
+            Ok(base64::decode_config(signature, config)?)
+        }
+        .boxed()
+    }
+
+    fn verify(&self, data: Vec<u8>, signature: Vec<u8>) -> BoxFuture<'static, Result<bool, Error>> {
+        let (url, mut body) = self.request("verify", &data);
+        let field = match self.algorithm {
+            KeyAlgorithm::HmacSha256 => "hmac",
+            _ => "signature",
+        };
+        body[field] = json!(format!(
+            "vault:v{}:{}",
+            self.key_version,
+            base64::encode_config(signature, self.base64_config())
+        ));
+        let response = self.send(url, body);
+        async move { Ok(response.await?["valid"].as_bool().unwrap_or(false)) }.boxed()
+    }
+}
+
+impl KeyStoreConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut config = Self::parse(&std::fs::read_to_string(path)?)?;
+        for key in config.keys.values_mut() {
+            for service in key.services.iter_mut() {
+                *service = config_service_scope(path, service);
+            }
+        }
+        Ok(config)
+    }
+
+    // Reads the key material of every key
+    pub fn into_key_store(self) -> Result<KeyStore, Error> {
+        let mut store = KeyStore::default();
+        for (name, config) in self.keys {
+            if config.services.is_empty() {
+                bail!(
+                    "key {}: services is empty, use \"*\" for all services",
+                    name
+                );
+            }
+            let signer: SharedSigner = match (&config.file, &config.env, &config.vault) {
+                (Some(path), None, None) => {
+                    let material = std::fs::read_to_string(path)
+                        .with_context(|| format!("failed to read key {}", name))?;
+                    Arc::new(
+                        LocalSigner::new(config.algorithm, &material)
+                            .with_context(|| format!("invalid key {}", name))?,
+                    )
+                }
+                (None, Some(var), None) => {
+                    let material = std::env::var(var)
+                        .with_context(|| format!("failed to read key {} from ${}", name, var))?;
+                    Arc::new(
+                        LocalSigner::new(config.algorithm, &material)
+                            .with_context(|| format!("invalid key {}", name))?,
+                    )
+                }
+                (None, None, Some(vault)) => {
+                    let token = std::env::var(&vault.token_env).with_context(|| {
+                        format!("failed to read the Vault token of key {}", name)
+                    })?;
+                    Arc::new(
+                        VaultSigner::new(config.algorithm, vault, token)
+                            .with_context(|| format!("invalid key {}", name))?,
+                    )
+                }
+                _ => bail!(
+                    "key {}: exactly one of file, env or vault is required",
+                    name
+                ),
+            };
+            store.insert(
+                &name,
+                RegisteredKey {
+                    signer,
+                    services: config.services,
+                },
+            );
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyAlgorithm, KeyStoreConfig, LocalSigner, VaultKeyConfig, VaultSigner};
+    use deno_core::serde_json::{self, json, Value};
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Request, Response};
+    use sb_worker_context::keys::Signer;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    // Stands in for Vault's transit engine, with an HMAC key signing by appending
+    // a marker to the input
+    async fn fake_vault(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        assert_eq!(req.headers()["x-vault-token"], "token");
+        let path = req.uri().path().to_string();
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["key_version"], 2);
+        let mut signature = base64::decode(body["input"].as_str().unwrap()).unwrap();
+        signature.push(0xff);
+        let signature = format!("vault:v2:{}", base64::encode(signature));
+        let data = match path.as_str() {
+            "/v1/transit/hmac/urls/sha2-256" => json!({ "hmac": signature }),
+            "/v1/transit/verify/urls/sha2-256" => json!({ "valid": body["hmac"] == signature }),
+            _ => panic!("unexpected path {}", path),
+        };
+        Ok(Response::new(Body::from(
+            json!({ "data": data }).to_string(),
+        )))
+    }
+
+    #[tokio::test]
+    async fn test_hmac_signer() {
+        let signer =
+            LocalSigner::new(KeyAlgorithm::HmacSha256, &base64::encode(b"secret")).unwrap();
+        let signature = signer.sign(b"/files/a.png?exp=1".to_vec()).await.unwrap();
+        assert_eq!(signature.len(), 32);
+        assert!(signer
+            .verify(b"/files/a.png?exp=1".to_vec(), signature.clone())
+            .await
+            .unwrap());
+        assert!(!signer
+            .verify(b"/files/b.png?exp=1".to_vec(), signature)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_vault_signer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                tokio::spawn(Http::new().serve_connection(conn, service_fn(fake_vault)));
+            }
+        });
+
+        let config: VaultKeyConfig = serde_json::from_value(json!({
+            "address": format!("http://{}/", addr),
+            "key": "urls",
+            "tokenEnv": "KEY_STORE_TEST_VAULT_TOKEN",
+            "keyVersion": 2,
+        }))
+        .unwrap();
+        let signer =
+            VaultSigner::new(KeyAlgorithm::HmacSha256, &config, "token".to_string()).unwrap();
+
+        let signature = signer.sign(b"/a".to_vec()).await.unwrap();
+        assert_eq!(signature, vec![b'/', b'a', 0xff]);
+        assert!(signer
+            .verify(b"/a".to_vec(), signature.clone())
+            .await
+            .unwrap());
+        assert!(!signer.verify(b"/b".to_vec(), signature).await.unwrap());
+    }
+
+    #[test]
+    fn test_key_store_config() {
+        std::env::set_var("KEY_STORE_TEST_SECRET", base64::encode(b"secret"));
+        let store = KeyStoreConfig::parse(
+            r#"{ "keys": {
+                "urls": { "algorithm": "hmac-sha256", "env": "KEY_STORE_TEST_SECRET", "services": ["media"] },
+                "shared": { "algorithm": "hmac-sha256", "env": "KEY_STORE_TEST_SECRET", "services": ["*"] }
+            } }"#,
+        )
+        .unwrap()
+        .into_key_store()
+        .unwrap();
+
+        assert!(store.get("urls", "media").is_some());
+        assert!(store.get("urls", "other").is_none());
+        assert!(store.get("shared", "other").is_some());
+        assert_eq!(
+            store.names("media"),
+            vec!["shared".to_string(), "urls".to_string()]
+        );
+        assert_eq!(store.names("other"), vec!["shared".to_string()]);
+
+        assert!(KeyStoreConfig::parse(
+            r#"{ "keys": { "urls": { "algorithm": "rsa", "env": "KEY_STORE_TEST_SECRET", "services": ["*"] } } }"#
+        )
+        .is_err());
+        // keys aren't available to every service unless they say so
+        assert!(KeyStoreConfig::parse(
+            r#"{ "keys": { "urls": { "algorithm": "hmac-sha256", "env": "KEY_STORE_TEST_SECRET" } } }"#
+        )
+        .is_err());
+        assert!(KeyStoreConfig::parse(
+            r#"{ "keys": { "urls": { "algorithm": "hmac-sha256", "env": "KEY_STORE_TEST_SECRET", "services": [] } } }"#
+        )
+        .unwrap()
+        .into_key_store()
+        .is_err());
+        assert!(KeyStoreConfig::parse(
+            r#"{ "keys": { "urls": { "algorithm": "ed25519", "services": ["*"] } } }"#
+        )
+        .unwrap()
+        .into_key_store()
+        .is_err());
+        assert!(KeyStoreConfig::parse(
+            r#"{ "keys": { "urls": {
+                "algorithm": "hmac-sha256",
+                "env": "KEY_STORE_TEST_SECRET",
+                "vault": { "address": "http://vault", "key": "urls", "tokenEnv": "KEY_STORE_TEST_VAULT_TOKEN" },
+                "services": ["*"]
+            } } }"#
+        )
+        .unwrap()
+        .into_key_store()
+        .is_err());
+    }
+}
@@ -1,12 +1,57 @@
 extern crate core;
 
+pub mod admin;
+pub mod ai_gateway;
+pub mod alarms;
+pub mod bench;
+pub mod broadcast;
 pub mod cert;
+pub mod cluster;
 pub mod commands;
+pub mod connections;
+pub mod coverage;
 pub mod deno_runtime;
+pub mod discovery;
+pub mod doctor;
+pub mod embed;
+pub mod embeddings;
 pub mod errors_rt;
+pub mod fallback;
+pub mod fault_injection;
+pub mod feature_flags;
+pub mod gc;
+pub mod geo;
+pub mod handoff;
+pub mod http3;
+pub mod images;
 pub mod js_worker;
+pub mod k8s;
+pub mod key_store;
+pub mod load_shedding;
 pub mod macros;
+pub mod mail;
+pub mod manifest_update;
+pub mod module_cache;
+pub mod module_reload;
+pub mod node;
+pub mod onnx;
+pub mod outbound_webhooks;
+pub mod preload;
+pub mod redis_pool;
+pub mod replay;
 pub mod rt_worker;
+pub mod sampling;
+pub mod sequences;
 pub mod server;
+pub mod service_config;
+pub mod service_env;
 pub mod snapshot;
+pub mod std_bundle;
+pub mod systemd;
+pub mod test_runner;
+pub mod test_runtime;
+pub mod timeline;
+pub mod type_check;
+pub mod usage;
 pub mod utils;
+pub mod vendor;
@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_CONCURRENCY: usize = 1000;
+const INITIAL_LIMIT: usize = 20;
+const MIN_LIMIT: usize = 4;
+// the limit is updated once per window, from the latencies measured in it
+const WINDOW: Duration = Duration::from_millis(100);
+const MIN_WINDOW_SAMPLES: u32 = 10;
+// latency growth tolerated before the limit shrinks
+const TOLERANCE: f64 = 1.5;
+// weight of a window in the limit, and in the long term latency
+const SMOOTHING: f64 = 0.2;
+const LONG_RTT_SMOOTHING: f64 = 0.01;
+// the limit is cut by this much when downstream reports overload
+const BACKOFF: f64 = 0.9;
+
+static CONCURRENCY_LIMITER: OnceLock<ConcurrencyLimiter> = OnceLock::new();
+
+// Sheds the requests over an adaptive concurrency limit, from now on
+pub fn enable_load_shedding(max_limit: usize) {
+    let _ = CONCURRENCY_LIMITER.set(ConcurrencyLimiter::new(max_limit));
+}
+
+pub fn concurrency_limiter() -> Option<&'static ConcurrencyLimiter> {
+    CONCURRENCY_LIMITER.get()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    rtt_sum: f64,
+    samples: u32,
+    max_inflight: usize,
+    overloaded: bool,
+}
+
+impl Window {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            rtt_sum: 0.0,
+            samples: 0,
+            max_inflight: 0,
+            overloaded: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: f64,
+    min_limit: f64,
+    max_limit: f64,
+    // long term latency (ms), what the runtime answers in when it isn't queueing
+    long_rtt: Option<f64>,
+    window: Window,
+}
+
+impl LimiterState {
+    // Gradient update: the limit grows while the latency of the window stays
+    // close to the long term one and shrinks as it rises (requests queueing up)
+    fn update(&mut self, short_rtt: f64, max_inflight: usize, overloaded: bool) {
+        let short_rtt = short_rtt.max(0.001);
+        let mut long_rtt = match self.long_rtt {
+            Some(long_rtt) => {
+                long_rtt * (1.0 - LONG_RTT_SMOOTHING) + short_rtt * LONG_RTT_SMOOTHING
+            }
+            None => short_rtt,
+        };
+        // recovers quickly once the latency drops, eg: after a slow deployment
+        if long_rtt / short_rtt > 2.0 {
+            long_rtt *= 0.95;
+        }
+        self.long_rtt = Some(long_rtt);
+
+        let limit = if overloaded {
+            self.limit * BACKOFF
+        } else if (max_inflight as f64) < self.limit / 2.0 {
+            // not enough traffic to tell whether a higher limit would hold
+            return;
+        } else {
+            let gradient = (TOLERANCE * long_rtt / short_rtt).clamp(0.5, 1.0);
+            let target = self.limit * gradient + self.limit.sqrt();
+            self.limit * (1.0 - SMOOTHING) + target * SMOOTHING
+        };
+        self.limit = limit.clamp(self.min_limit, self.max_limit);
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyLimiterSnapshot {
+    pub limit: usize,
+    pub inflight: usize,
+    pub shed: u64,
+    pub rtt_ms: Option<f64>,
+}
+
+// Adaptive concurrency limit of the requests served by the runtime, measured
+// until their response head
+pub struct ConcurrencyLimiter {
+    limit: AtomicUsize,
+    inflight: AtomicUsize,
+    shed: AtomicU64,
+    state: Mutex<LimiterState>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_limit: usize) -> Self {
+        let max_limit = max_limit.max(MIN_LIMIT);
+        let limit = INITIAL_LIMIT.min(max_limit);
+        Self {
+            limit: AtomicUsize::new(limit),
+            inflight: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+            state: Mutex::new(LimiterState {
+                limit: limit as f64,
+                min_limit: MIN_LIMIT as f64,
+                max_limit: max_limit as f64,
+                long_rtt: None,
+                window: Window::new(Instant::now()),
+            }),
+        }
+    }
+
+    // A permit to serve the request, or `None` when it's to be shed
+    pub fn try_acquire(&self) -> Option<ConcurrencyPermit<'_>> {
+        let inflight = self.inflight.fetch_add(1, Ordering::Relaxed) + 1;
+        if inflight > self.limit.load(Ordering::Relaxed) {
+            self.inflight.fetch_sub(1, Ordering::Relaxed);
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(ConcurrencyPermit {
+            limiter: self,
+            started: Instant::now(),
+        })
+    }
+
+    fn sample(&self, rtt: Duration, overloaded: bool) {
+        let inflight = self.inflight.load(Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        let window = &mut state.window;
+        window.rtt_sum += rtt.as_secs_f64() * 1000.0;
+        window.samples += 1;
+        window.max_inflight = window.max_inflight.max(inflight);
+        window.overloaded |= overloaded;
+
+        let now = Instant::now();
+        if now.duration_since(window.started) < WINDOW || window.samples < MIN_WINDOW_SAMPLES {
+            return;
+        }
+        let window = std::mem::replace(&mut state.window, Window::new(now));
+        state.update(
+            window.rtt_sum / window.samples as f64,
+            window.max_inflight,
+            window.overloaded,
+        );
+        self.limit
+            .store(state.limit.round() as usize, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConcurrencyLimiterSnapshot {
+        ConcurrencyLimiterSnapshot {
+            limit: self.limit.load(Ordering::Relaxed),
+            inflight: self.inflight.load(Ordering::Relaxed),
+            shed: self.shed.load(Ordering::Relaxed),
+            rtt_ms: self.state.lock().unwrap().long_rtt,
+        }
+    }
+}
+
+// Counts a request against the limit until dropped
+pub struct ConcurrencyPermit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    started: Instant,
+}
+
+impl ConcurrencyPermit<'_> {
+    // Measures the latency of a request that got a response, `overloaded` when
+    // it was answered with a 503 or a timeout
+    pub fn finish(self, overloaded: bool) {
+        self.limiter.sample(self.started.elapsed(), overloaded);
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConcurrencyLimiter, LimiterState, Window, INITIAL_LIMIT};
+    use std::time::Instant;
+
+    fn state(limit: f64) -> LimiterState {
+        LimiterState {
+            limit,
+            min_limit: 4.0,
+            max_limit: 100.0,
+            long_rtt: None,
+            window: Window::new(Instant::now()),
+        }
+    }
+
+    #[test]
+    fn test_limit_follows_latency() {
+        // grows while the latency holds
+        let mut limiter = state(20.0);
+        for _ in 0..100 {
+            let inflight = limiter.limit as usize;
+            limiter.update(10.0, inflight, false);
+        }
+        assert_eq!(limiter.limit, 100.0);
+
+        // shrinks once requests queue up
+        for _ in 0..50 {
+            limiter.update(40.0, 100, false);
+        }
+        assert!(limiter.limit < 50.0, "{}", limiter.limit);
+
+        // backs off on overload, down to the min
+        let mut limiter = state(20.0);
+        limiter.update(10.0, 1, true);
+        assert_eq!(limiter.limit, 18.0);
+        for _ in 0..50 {
+            limiter.update(10.0, 1, true);
+        }
+        assert_eq!(limiter.limit, 4.0);
+
+        // no change without enough traffic
+        let mut limiter = state(20.0);
+        limiter.update(10.0, 2, false);
+        assert_eq!(limiter.limit, 20.0);
+    }
+
+    #[test]
+    fn test_requests_over_the_limit_are_shed() {
+        let limiter = ConcurrencyLimiter::new(1000);
+        let permits: Vec<_> = (0..INITIAL_LIMIT)
+            .map(|_| limiter.try_acquire().unwrap())
+            .collect();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.snapshot().shed, 1);
+        assert_eq!(limiter.snapshot().inflight, INITIAL_LIMIT);
+
+        drop(permits);
+        assert_eq!(limiter.snapshot().inflight, 0);
+        limiter.try_acquire().unwrap().finish(false);
+        assert_eq!(limiter.snapshot().inflight, 0);
+    }
+}
@@ -30,7 +30,8 @@ macro_rules! integration_test {
                 $crate::server::WorkerEntrypoints {
                     main: None,
                     events: None,
-                }
+                },
+                Default::default(),
             ) => {
                 panic!("This one should not end first");
             }
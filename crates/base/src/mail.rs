@@ -0,0 +1,301 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::futures::future::BoxFuture;
+use deno_core::futures::FutureExt;
+use deno_core::serde_json;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::warn;
+use sb_worker_context::mail::{Mailer, OutgoingMail, SharedMailer};
+use sb_worker_context::service_scope::load_service_config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{mpsc, oneshot};
+
+// `EdgeRuntime.mail`: mail is sent over SMTP by the runtime, with pooled
+// connections per service account. Accounts (and their transports) live on the
+// server's runtime, so pooled connections outlive the workers sending on them.
+
+static MAILER: OnceLock<SharedMailer> = OnceLock::new();
+
+// services without an account of their own send with this one, if set
+const DEFAULT_ACCOUNT: &str = "*";
+const DEFAULT_MAX_CONNECTIONS: u32 = 4;
+
+pub fn set_mailer(mailer: SharedMailer) {
+    if MAILER.set(mailer).is_err() {
+        warn!("mailer is already set");
+    }
+}
+
+pub fn mailer() -> Option<SharedMailer> {
+    MAILER.get().cloned()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    // upgrades the connection with STARTTLS (port 587)
+    #[default]
+    Starttls,
+    // TLS from the start (port 465)
+    Tls,
+    // unencrypted, for relays on the same host only
+    None,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SmtpAccountConfig {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default)]
+    pub username: Option<String>,
+    // env var holding the password
+    #[serde(default)]
+    pub password_env: Option<String>,
+    // sender of mail without a `from`
+    #[serde(default)]
+    pub from: Option<String>,
+    // connections kept open to the server
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+}
+
+// eg: `{ "services": { "billing": { "host": "smtp.example.com", "username": "billing", "passwordEnv": "BILLING_SMTP_PASSWORD" } } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MailConfig {
+    pub services: HashMap<String, SmtpAccountConfig>,
+}
+
+struct SmtpAccount {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Option<Mailbox>,
+}
+
+impl SmtpAccount {
+    fn new(config: SmtpAccountConfig) -> Result<Self, Error> {
+        let mut builder = match config.tls {
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = config.username {
+            let password = match &config.password_env {
+                Some(var) => std::env::var(var)
+                    .with_context(|| format!("failed to read the password from ${}", var))?,
+                None => String::new(),
+            };
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        let from = match config.from {
+            Some(from) => Some(parse_mailbox(&from)?),
+            None => None,
+        };
+        let max_connections = config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+
+        Ok(Self {
+            transport: builder
+                .pool_config(PoolConfig::new().max_size(max_connections))
+                .build(),
+            from,
+        })
+    }
+
+    async fn send(&self, mail: OutgoingMail) -> Result<String, Error> {
+        let message = build_message(mail, self.from.as_ref())?;
+        let message_id = message
+            .headers()
+            .get_raw("Message-ID")
+            .unwrap_or_default()
+            .to_string();
+        self.transport.send(message).await?;
+        Ok(message_id)
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, Error> {
+    address
+        .parse()
+        .with_context(|| format!("invalid address {}", address))
+}
+
+fn build_message(mail: OutgoingMail, default_from: Option<&Mailbox>) -> Result<Message, Error> {
+    let from = match &mail.from {
+        Some(from) => parse_mailbox(from)?,
+        None => default_from
+            .cloned()
+            .ok_or_else(|| anyhow!("mail without a from address"))?,
+    };
+    if mail.to.is_empty() {
+        bail!("mail without recipients");
+    }
+
+    let mut builder = Message::builder()
+        .from(from)
+        .subject(mail.subject)
+        .message_id(None);
+    for to in &mail.to {
+        builder = builder.to(parse_mailbox(to)?);
+    }
+    for cc in &mail.cc {
+        builder = builder.cc(parse_mailbox(cc)?);
+    }
+    for bcc in &mail.bcc {
+        builder = builder.bcc(parse_mailbox(bcc)?);
+    }
+    if let Some(reply_to) = &mail.reply_to {
+        builder = builder.reply_to(parse_mailbox(reply_to)?);
+    }
+
+    Ok(match (mail.text, mail.html) {
+        (Some(text), Some(html)) => {
+            builder.multipart(MultiPart::alternative_plain_html(text, html))?
+        }
+        (None, Some(html)) => builder.header(ContentType::TEXT_HTML).body(html)?,
+        (text, None) => builder
+            .header(ContentType::TEXT_PLAIN)
+            .body(text.unwrap_or_default())?,
+    })
+}
+
+struct MailRequest {
+    service: String,
+    mail: OutgoingMail,
+    result_tx: oneshot::Sender<Result<String, Error>>,
+}
+
+// Hands mail over to the accounts' task
+pub struct SmtpMailer {
+    tx: mpsc::UnboundedSender<MailRequest>,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, service: &str, mail: OutgoingMail) -> BoxFuture<'static, Result<String, Error>> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let sent = self.tx.send(MailRequest {
+            service: service.to_string(),
+            mail,
+            result_tx,
+        });
+        async move {
+            sent.map_err(|_| anyhow!("mailer is shut down"))?;
+            result_rx.await?
+        }
+        .boxed()
+    }
+}
+
+impl MailConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_service_config(path, Self::parse, |config| &mut config.services)
+    }
+
+    // Starts sending on the current runtime
+    pub fn start(self) -> Result<SmtpMailer, Error> {
+        let mut accounts = HashMap::new();
+        for (service, config) in self.services {
+            let account = SmtpAccount::new(config)
+                .with_context(|| format!("invalid mail account of {}", service))?;
+            accounts.insert(service, Arc::new(account));
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<MailRequest>();
+        tokio::spawn(async move {
+            while let Some(req) = rx.recv().await {
+                let account = accounts
+                    .get(&req.service)
+                    .or_else(|| accounts.get(DEFAULT_ACCOUNT))
+                    .cloned();
+                let Some(account) = account else {
+                    let _ = req
+                        .result_tx
+                        .send(Err(anyhow!("no mail account for {}", req.service)));
+                    continue;
+                };
+                tokio::spawn(async move {
+                    let _ = req.result_tx.send(account.send(req.mail).await);
+                });
+            }
+        });
+        Ok(SmtpMailer { tx })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_message, parse_mailbox, MailConfig, SmtpTls};
+    use sb_worker_context::mail::OutgoingMail;
+
+    fn mail() -> OutgoingMail {
+        OutgoingMail {
+            to: vec!["Jane <jane@example.com>".to_string()],
+            subject: "Your invoice".to_string(),
+            text: Some("Hello".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_message() {
+        let from = parse_mailbox("billing@example.com").unwrap();
+        let message = build_message(mail(), Some(&from)).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("From: billing@example.com"));
+        assert!(formatted.contains("To: Jane <jane@example.com>"));
+        assert!(formatted.contains("Subject: Your invoice"));
+        assert!(message.headers().get_raw("Message-ID").is_some());
+
+        let html = OutgoingMail {
+            html: Some("<p>Hello</p>".to_string()),
+            ..mail()
+        };
+        let formatted =
+            String::from_utf8(build_message(html, Some(&from)).unwrap().formatted()).unwrap();
+        assert!(formatted.contains("multipart/alternative"));
+
+        // needs a sender and recipients
+        assert!(build_message(mail(), None).is_err());
+        let no_recipients = OutgoingMail {
+            to: vec![],
+            ..mail()
+        };
+        assert!(build_message(no_recipients, Some(&from)).is_err());
+        let invalid = OutgoingMail {
+            to: vec!["not an address".to_string()],
+            ..mail()
+        };
+        assert!(build_message(invalid, Some(&from)).is_err());
+    }
+
+    #[test]
+    fn test_mail_config() {
+        let config = MailConfig::parse(
+            r#"{ "services": { "*": { "host": "localhost", "port": 2525, "tls": "none" }, "billing": { "host": "smtp.example.com", "username": "billing" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.services["*"].tls, SmtpTls::None);
+        assert_eq!(config.services["billing"].tls, SmtpTls::Starttls);
+
+        assert!(
+            MailConfig::parse(r#"{ "services": { "a": { "host": "x", "pass": "y" } } }"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use deno_core::serde_json::{self, Value};
+use sb_worker_context::essentials::{MAX_CONCURRENT_REQUESTS, MIN_HANG_THRESHOLD_MS};
+use sb_worker_context::manifest::{Manifest, ServiceEntry};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Manifests submitted to the admin API (`PUT /_admin/manifest`) are validated
+// and diffed against the current one before they replace it, so a bad update is
+// rejected with every error at once instead of breaking the services it touches.
+
+// cgroup v2 `cpu.weight` range, as for `EdgeRuntime.userWorkers.create`
+const MIN_CPU_WEIGHT: u64 = 1;
+const MAX_CPU_WEIGHT: u64 = 10000;
+
+// A problem of a submitted manifest, `path` pointing at the field in fault (eg:
+// `services.api.limits.memoryLimitMb`)
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestError {
+    pub path: String,
+    pub message: String,
+}
+
+impl ManifestError {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+// How a submitted manifest differs from the current one
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // fields of each changed service that differ (the values of env vars aren't
+    // reported, they may be secrets)
+    pub changed: BTreeMap<String, Vec<String>>,
+    pub preload_changed: bool,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && !self.preload_changed
+    }
+}
+
+// Parses and validates a submitted manifest, relative paths being resolved from
+// `base_dir` like those of the manifest file
+pub fn parse_manifest_update(json: &[u8], base_dir: &Path) -> Result<Manifest, Vec<ManifestError>> {
+    let json = std::str::from_utf8(json)
+        .map_err(|_| vec![ManifestError::new("", "the manifest isn't valid UTF-8")])?;
+    let manifest = Manifest::parse(json, base_dir)
+        .map_err(|err| vec![ManifestError::new("", err.to_string())])?;
+    let errors = validate_manifest(&manifest);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(manifest)
+}
+
+fn positive<T: Into<u64> + Copy>(
+    errors: &mut Vec<ManifestError>,
+    path: &str,
+    field: &str,
+    value: Option<T>,
+) {
+    if value.is_some_and(|value| value.into() == 0) {
+        errors.push(ManifestError::new(
+            format!("{}.{}", path, field),
+            "must be greater than 0",
+        ));
+    }
+}
+
+fn validate_service(name: &str, entry: &ServiceEntry, errors: &mut Vec<ManifestError>) {
+    let path = format!("services.{}", name);
+    if name.is_empty() || name.contains('/') {
+        errors.push(ManifestError::new(
+            &path,
+            "service names must be non-empty and can't contain '/'",
+        ));
+    }
+    if entry.entrypoint.is_empty() {
+        errors.push(ManifestError::new(
+            format!("{}.entrypoint", path),
+            "must not be empty",
+        ));
+    }
+    if entry.import_map.as_deref() == Some("") {
+        errors.push(ManifestError::new(
+            format!("{}.importMap", path),
+            "must not be empty",
+        ));
+    }
+    let mut env_names: Vec<&String> = entry.env.keys().collect();
+    env_names.sort();
+    for env_name in env_names {
+        if env_name.is_empty()
+            || env_name.contains(['=', '\0'])
+            || entry.env[env_name].contains('\0')
+        {
+            errors.push(ManifestError::new(
+                format!("{}.env.{}", path, env_name),
+                "invalid environment variable",
+            ));
+        }
+    }
+    for route in &entry.routes {
+        if !route.starts_with('/') {
+            errors.push(ManifestError::new(
+                format!("{}.routes", path),
+                format!("route {:?} must start with '/'", route),
+            ));
+        }
+    }
+
+    let limits = &entry.limits;
+    let path = format!("{}.limits", path);
+    positive(errors, &path, "memoryLimitMb", limits.memory_limit_mb);
+    positive(
+        errors,
+        &path,
+        "lowMemoryMultiplier",
+        limits.low_memory_multiplier,
+    );
+    positive(errors, &path, "workerTimeoutMs", limits.worker_timeout_ms);
+    positive(
+        errors,
+        &path,
+        "cpuTimeThresholdMs",
+        limits.cpu_time_threshold_ms,
+    );
+    positive(
+        errors,
+        &path,
+        "cpuBurstIntervalMs",
+        limits.cpu_burst_interval_ms,
+    );
+    if limits
+        .hang_threshold_ms
+        .is_some_and(|threshold| threshold < MIN_HANG_THRESHOLD_MS)
+    {
+        errors.push(ManifestError::new(
+            format!("{}.hangThresholdMs", path),
+            format!("must be at least {}", MIN_HANG_THRESHOLD_MS),
+        ));
+    }
+    positive(
+        errors,
+        &path,
+        "maxConcurrentRequests",
+        limits.max_concurrent_requests.map(|max| max as u64),
+    );
+    if limits
+        .max_concurrent_requests
+        .is_some_and(|max| max > MAX_CONCURRENT_REQUESTS)
+    {
+        errors.push(ManifestError::new(
+            format!("{}.maxConcurrentRequests", path),
+            format!("must be at most {}", MAX_CONCURRENT_REQUESTS),
+        ));
+    }
+    positive(
+        errors,
+        &path,
+        "maxWorkers",
+        limits.max_workers.map(|max| max as u64),
+    );
+    if let Some(weight) = limits.cpu_weight {
+        if !(MIN_CPU_WEIGHT..=MAX_CPU_WEIGHT).contains(&weight) {
+            errors.push(ManifestError::new(
+                format!("{}.cpuWeight", path),
+                format!("must be between {} and {}", MIN_CPU_WEIGHT, MAX_CPU_WEIGHT),
+            ));
+        }
+    }
+    if let Some(rate) = limits.telemetry_sample_rate {
+        if !(0.0..=1.0).contains(&rate) {
+            errors.push(ManifestError::new(
+                format!("{}.telemetrySampleRate", path),
+                "must be between 0 and 1",
+            ));
+        }
+    }
+    if let Some(timers) = &limits.timers {
+        positive(
+            errors,
+            &path,
+            "timers.maxTimers",
+            timers.max_timers.map(|max| max as u64),
+        );
+        positive(
+            errors,
+            &path,
+            "timers.idleCoalesceMs",
+            timers.idle_coalesce_ms,
+        );
+    }
+}
+
+// Every problem of a manifest, in the order of its services
+pub fn validate_manifest(manifest: &Manifest) -> Vec<ManifestError> {
+    let mut errors = vec![];
+    let mut names: Vec<&String> = manifest.services.keys().collect();
+    names.sort();
+
+    // a route served by two services would be served by either
+    let conflicts = manifest.route_conflicts();
+    for name in names {
+        validate_service(name, &manifest.services[name], &mut errors);
+        for conflict in conflicts.iter().filter(|c| &c.service == name) {
+            errors.push(ManifestError::new(
+                format!("services.{}.routes", name),
+                format!(
+                    "route {:?} is already served by {}",
+                    conflict.route, conflict.other
+                ),
+            ));
+        }
+    }
+    errors
+}
+
+fn entry_fields(entry: &ServiceEntry) -> BTreeMap<String, Value> {
+    let Ok(Value::Object(entry)) = serde_json::to_value(entry) else {
+        return BTreeMap::new();
+    };
+    let mut fields = BTreeMap::new();
+    for (key, value) in entry {
+        match value {
+            // changes within limits and env are reported field by field
+            Value::Object(nested) if key == "limits" || key == "env" => {
+                for (nested_key, nested_value) in nested {
+                    fields.insert(format!("{}.{}", key, nested_key), nested_value);
+                }
+            }
+            value => {
+                fields.insert(key, value);
+            }
+        }
+    }
+    fields
+}
+
+fn changed_fields(current: &ServiceEntry, updated: &ServiceEntry) -> Vec<String> {
+    let current = entry_fields(current);
+    let updated = entry_fields(updated);
+    let mut changed: Vec<String> = current
+        .iter()
+        .filter(|(key, value)| updated.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .chain(
+            updated
+                .keys()
+                .filter(|key| !current.contains_key(*key))
+                .cloned(),
+        )
+        .collect();
+    changed.sort();
+    changed
+}
+
+pub fn diff_manifests(current: &Manifest, updated: &Manifest) -> ManifestDiff {
+    let mut diff = ManifestDiff::default();
+    for (name, entry) in &updated.services {
+        match current.services.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(current_entry) if current_entry != entry => {
+                diff.changed
+                    .insert(name.clone(), changed_fields(current_entry, entry));
+            }
+            Some(_) => {}
+        }
+    }
+    diff.removed = current
+        .services
+        .keys()
+        .filter(|name| !updated.services.contains_key(*name))
+        .cloned()
+        .collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.preload_changed = current.preload != updated.preload;
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff_manifests, parse_manifest_update, ManifestError};
+    use sb_worker_context::manifest::Manifest;
+    use std::path::Path;
+
+    fn manifest(json: &str) -> Manifest {
+        Manifest::parse(json, Path::new("/etc/functions")).unwrap()
+    }
+
+    #[test]
+    fn test_validate_manifest_update() {
+        let errors = parse_manifest_update(
+            br#"{
+                "services": {
+                    "api": { "entrypoint": "./api", "routes": ["/v1", "v2"], "limits": { "memoryLimitMb": 0, "cpuWeight": 20000 } },
+                    "b": { "entrypoint": "./b", "limits": { "maxConcurrentRequests": 0, "hangThresholdMs": 10 } },
+                    "c": { "entrypoint": "./c", "limits": { "maxConcurrentRequests": 18446744073709551615 } },
+                    "hello": { "entrypoint": "./hello", "routes": ["/v1/"], "env": { "A=B": "c" } }
+                }
+            }"#,
+            Path::new("/etc/functions"),
+        )
+        .unwrap_err();
+        let paths: Vec<&str> = errors.iter().map(|err| err.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "services.api.routes",
+                "services.api.limits.memoryLimitMb",
+                "services.api.limits.cpuWeight",
+                "services.b.limits.hangThresholdMs",
+                "services.b.limits.maxConcurrentRequests",
+                "services.c.limits.maxConcurrentRequests",
+                "services.hello.env.A=B",
+                "services.hello.routes",
+            ]
+        );
+
+        // not a manifest at all
+        assert_eq!(
+            parse_manifest_update(b"{ \"services\": [] }", Path::new("."))
+                .unwrap_err()
+                .len(),
+            1
+        );
+        let errors = parse_manifest_update(
+            br#"{ "services": {}, "preload": ["missing"] }"#,
+            Path::new("."),
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ManifestError::new(
+                "",
+                "preloaded service missing is not in the manifest"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_diff_manifests() {
+        let current = manifest(
+            r#"{
+                "services": {
+                    "api": { "entrypoint": "./api", "env": { "TOKEN": "a" }, "limits": { "memoryLimitMb": 150 } },
+                    "hello": { "entrypoint": "./hello" },
+                    "old": { "entrypoint": "./old" }
+                }
+            }"#,
+        );
+        let updated = manifest(
+            r#"{
+                "services": {
+                    "api": { "entrypoint": "./api", "env": { "TOKEN": "b" }, "limits": { "memoryLimitMb": 256 }, "routes": ["/v1"] },
+                    "hello": { "entrypoint": "./hello" },
+                    "new": { "entrypoint": "./new" }
+                },
+                "preload": ["new"]
+            }"#,
+        );
+        let diff = diff_manifests(&current, &updated);
+        assert_eq!(diff.added, vec!["new"]);
+        assert_eq!(diff.removed, vec!["old"]);
+        assert_eq!(
+            diff.changed["api"],
+            vec!["env.TOKEN", "limits.memoryLimitMb", "routes"]
+        );
+        assert!(!diff.changed.contains_key("hello"));
+        assert!(diff.preload_changed);
+        assert!(diff_manifests(&current, &current).is_empty());
+    }
+}
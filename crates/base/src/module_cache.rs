@@ -0,0 +1,104 @@
+use crate::utils::units::{bytes_to_display, human_elapsed};
+use anyhow::{Context, Error};
+use deno_core::url::Url;
+use module_fetcher::cache::{
+    cache_doctor, inspect_cached_module, list_cached_modules, CacheDoctorReport, CachedModuleInfo,
+    DenoDir,
+};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+fn deno_dir() -> Result<DenoDir, Error> {
+    DenoDir::new(None).context("failed to resolve the module cache directory")
+}
+
+// Where remote modules are cached (`$DENO_DIR/deps`)
+pub fn deps_dir() -> Result<PathBuf, Error> {
+    Ok(deno_dir()?.deps_folder_path())
+}
+
+// Checks the module caches for entries workers would fail to load, removing them
+// unless `dry_run` is set
+pub fn cache_doctor_report(dry_run: bool) -> Result<CacheDoctorReport, Error> {
+    cache_doctor(deno_dir()?.root_path(), dry_run)
+}
+
+pub fn cache_info(specifier: &str) -> Result<Option<CachedModuleInfo>, Error> {
+    let url = Url::parse(specifier).with_context(|| format!("invalid specifier {}", specifier))?;
+    inspect_cached_module(&deps_dir()?, &url)
+}
+
+pub fn cache_ls() -> Result<Vec<CachedModuleInfo>, Error> {
+    let deps_dir = deps_dir()?;
+    if !deps_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    list_cached_modules(&deps_dir)
+}
+
+fn age(info: &CachedModuleInfo) -> String {
+    human_elapsed(info.age_secs.saturating_mul(1000))
+}
+
+// `edge-runtime cache info` output
+pub fn format_cache_info(info: &CachedModuleInfo) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "url:       {}", info.url);
+    let _ = writeln!(out, "location:  {}", info.path.display());
+    let _ = writeln!(out, "size:      {}", bytes_to_display(info.size));
+    let _ = writeln!(out, "age:       {}", age(info));
+    let _ = writeln!(
+        out,
+        "integrity: {}",
+        info.integrity
+            .as_deref()
+            .map(|sha| format!("sha256-{}", sha))
+            .unwrap_or_else(|| "missing source".to_string())
+    );
+    let _ = writeln!(out, "headers:");
+    let mut headers: Vec<_> = info.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        let _ = writeln!(out, "  {}: {}", name, value);
+    }
+    out
+}
+
+// One line per module of `edge-runtime cache ls`
+pub fn format_cache_entry(info: &CachedModuleInfo) -> String {
+    format!(
+        "{}\t{}\t{}",
+        bytes_to_display(info.size),
+        age(info),
+        info.url
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_cache_entry, format_cache_info};
+    use module_fetcher::cache::CachedModuleInfo;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_format_cache_info() {
+        let info = CachedModuleInfo {
+            url: "https://deno.land/std/http/server.ts".to_string(),
+            path: PathBuf::from("/deno/deps/https/deno.land/abc"),
+            size: 2048,
+            headers: HashMap::from([("etag".to_string(), "\"v1\"".to_string())]),
+            age_secs: 90,
+            integrity: Some("ab12".to_string()),
+        };
+        let out = format_cache_info(&info);
+        assert!(out.contains("size:      2.00KiB\n"));
+        assert!(out.contains("age:       1m30s\n"));
+        assert!(out.contains("integrity: sha256-ab12\n"));
+        assert!(out.contains("  etag: \"v1\"\n"));
+        assert_eq!(
+            format_cache_entry(&info),
+            "2.00KiB\t1m30s\thttps://deno.land/std/http/server.ts"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Error;
+use hyper::{Body, HeaderMap, Request};
+use sb_worker_context::essentials::WorkerContextInitOpts;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+// Asks for the modules of the worker serving the request to be fetched again,
+// bypassing the module cache (eg: previews, purge and reload flows): `1` for all
+// the remote modules, or a comma separated list of urls (and url prefixes)
+pub const RELOAD_MODULES_HEADER: &str = "x-edge-runtime-reload-modules";
+
+static TRUSTED_RELOAD_PROXIES: OnceLock<Vec<IpAddr>> = OnceLock::new();
+
+// Peers the reload header is accepted from, it's dropped from the requests of
+// any other peer
+pub fn set_trusted_reload_proxies(proxies: &[String]) -> Result<(), Error> {
+    let proxies = proxies
+        .iter()
+        .map(|proxy| proxy.parse::<IpAddr>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let _ = TRUSTED_RELOAD_PROXIES.set(proxies);
+    Ok(())
+}
+
+fn is_trusted(peer: Option<SocketAddr>) -> bool {
+    // requests made in process (eg: by an embedder) don't come from a peer
+    let Some(peer) = peer else {
+        return true;
+    };
+    TRUSTED_RELOAD_PROXIES
+        .get()
+        .is_some_and(|proxies| proxies.contains(&peer.ip()))
+}
+
+// Drops the reload header of requests not sent by a trusted proxy, so the main
+// worker can act on the ones it gets
+pub fn strip_untrusted_reload_header(req: &mut Request<Body>, peer: Option<SocketAddr>) {
+    if !is_trusted(peer) {
+        req.headers_mut().remove(RELOAD_MODULES_HEADER);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleReload {
+    All,
+    Some(Vec<String>),
+}
+
+impl ModuleReload {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(RELOAD_MODULES_HEADER)?.to_str().ok()?.trim();
+        if value == "1" || value == "true" {
+            return Some(ModuleReload::All);
+        }
+        let urls: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        (!urls.is_empty()).then_some(ModuleReload::Some(urls))
+    }
+
+    // Boots a new worker for the request, fetching the modules again
+    pub fn apply(self, opts: &mut WorkerContextInitOpts) {
+        match self {
+            ModuleReload::All => opts.no_module_cache = true,
+            ModuleReload::Some(urls) => {
+                if let Some(conf) = opts.conf.as_user_worker_mut() {
+                    conf.reload_modules = urls;
+                }
+            }
+        }
+        if let Some(conf) = opts.conf.as_user_worker_mut() {
+            conf.force_create = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ModuleReload, RELOAD_MODULES_HEADER};
+    use hyper::HeaderMap;
+
+    #[test]
+    fn test_module_reload_from_headers() {
+        let reload = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RELOAD_MODULES_HEADER, value.parse().unwrap());
+            ModuleReload::from_headers(&headers)
+        };
+        assert_eq!(ModuleReload::from_headers(&HeaderMap::new()), None);
+        assert_eq!(reload("1"), Some(ModuleReload::All));
+        assert_eq!(
+            reload("https://esm.sh/my-lib, https://deno.land/x/app/mod.ts"),
+            Some(ModuleReload::Some(vec![
+                "https://esm.sh/my-lib".to_string(),
+                "https://deno.land/x/app/mod.ts".to_string(),
+            ]))
+        );
+        assert_eq!(reload(" , "), None);
+    }
+}
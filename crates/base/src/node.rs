@@ -0,0 +1,81 @@
+use hyper::header::HeaderValue;
+use log::warn;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+// set on responses, so traffic can be attributed to the node that served it
+pub const SERVED_BY_HEADER: &str = "x-served-by";
+
+static NODE_IDENTITY: OnceLock<NodeIdentity> = OnceLock::new();
+
+// Where this runtime instance runs, in a multi-region fleet. Added to responses,
+// worker events, usage records and metrics, and exposed to functions through
+// `EdgeRuntime.context`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeIdentity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    // Kubernetes node and `<namespace>/<pod>` of the instance, see `k8s`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
+}
+
+impl NodeIdentity {
+    pub fn is_empty(&self) -> bool {
+        self.region.is_none() && self.zone.is_none() && self.node.is_none() && self.pod.is_none()
+    }
+
+    // `<region>/<zone>`, or whichever of the two is set
+    pub fn served_by(&self) -> Option<String> {
+        match (&self.region, &self.zone) {
+            (Some(region), Some(zone)) => Some(format!("{}/{}", region, zone)),
+            (Some(id), None) | (None, Some(id)) => Some(id.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+pub fn set_node_identity(identity: NodeIdentity) {
+    if NODE_IDENTITY.set(identity).is_err() {
+        warn!("node identity is already set");
+    }
+}
+
+pub fn node_identity() -> NodeIdentity {
+    NODE_IDENTITY.get().cloned().unwrap_or_default()
+}
+
+pub fn served_by_header() -> Option<HeaderValue> {
+    let served_by = NODE_IDENTITY.get()?.served_by()?;
+    HeaderValue::from_str(&served_by).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::NodeIdentity;
+
+    #[test]
+    fn test_served_by() {
+        let identity = NodeIdentity {
+            region: Some("eu-west-1".to_string()),
+            zone: Some("eu-west-1b".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(identity.served_by().unwrap(), "eu-west-1/eu-west-1b");
+        assert_eq!(
+            NodeIdentity {
+                zone: None,
+                ..identity
+            }
+            .served_by()
+            .unwrap(),
+            "eu-west-1"
+        );
+        assert!(NodeIdentity::default().served_by().is_none());
+    }
+}
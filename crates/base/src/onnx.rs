@@ -0,0 +1,384 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::FutureExt;
+use deno_core::serde_json;
+use log::warn;
+use ort::{
+    GraphOptimizationLevel, PrimitiveTensorElementType, Session, SessionInputValue, Tensor,
+    TensorElementType, Value,
+};
+use sb_worker_context::onnx::{
+    ModelRunner, SharedModelRunner, TensorInput, TensorOutput, TensorType,
+};
+use sb_worker_context::service_scope::load_service_config;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// `EdgeRuntime.onnx`: small ONNX models (embeddings, rerankers) run on the node
+// with onnxruntime, loaded from `ORT_DYLIB_PATH`. A model is loaded on first use
+// and shared by the isolates of its service. Inference runs on the blocking
+// pool, so it doesn't stall the worker's event loop.
+
+static MODEL_RUNNER: OnceLock<SharedModelRunner> = OnceLock::new();
+
+// services without models of their own use these, if set
+const DEFAULT_SERVICE: &str = "*";
+const DEFAULT_MAX_MEMORY_MB: u64 = 256;
+const DEFAULT_INTRA_THREADS: usize = 1;
+
+pub fn set_model_runner(runner: SharedModelRunner) {
+    if MODEL_RUNNER.set(runner).is_err() {
+        warn!("model runner is already set");
+    }
+}
+
+pub fn model_runner() -> Option<SharedModelRunner> {
+    MODEL_RUNNER.get().cloned()
+}
+
+static ORT_ENVIRONMENT: OnceLock<Result<(), String>> = OnceLock::new();
+
+// onnxruntime is loaded once, for every model of the runtime. ort panics when
+// the library can't be loaded, that's reported as an error instead.
+pub fn ort_environment() -> Result<(), Error> {
+    ORT_ENVIRONMENT
+        .get_or_init(|| {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                ort::init()
+                    .with_name("edge-runtime")
+                    .commit()
+                    .map_err(|err| err.to_string())
+            }))
+            .unwrap_or_else(|_| Err("onnxruntime could not be loaded".to_string()))
+        })
+        .clone()
+        .map_err(|err| anyhow!("failed to load onnxruntime (set ORT_DYLIB_PATH): {}", err))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OnnxModelConfig {
+    pub path: PathBuf,
+    // caps the model file, and the inputs and outputs of a single run
+    #[serde(default)]
+    pub max_memory_mb: Option<u64>,
+    #[serde(default)]
+    pub intra_threads: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OnnxServiceConfig {
+    pub models: HashMap<String, OnnxModelConfig>,
+}
+
+// eg: `{ "services": { "search": { "models": { "embed": { "path": "/models/minilm.onnx", "maxMemoryMb": 128 } } } } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OnnxConfig {
+    pub services: HashMap<String, OnnxServiceConfig>,
+}
+
+struct OnnxModel {
+    config: OnnxModelConfig,
+    max_memory_bytes: u64,
+    session: Mutex<Option<Arc<Session>>>,
+}
+
+impl OnnxModel {
+    fn new(config: OnnxModelConfig) -> Self {
+        let max_memory_bytes = config.max_memory_mb.unwrap_or(DEFAULT_MAX_MEMORY_MB) * 1024 * 1024;
+        Self {
+            config,
+            max_memory_bytes,
+            session: Mutex::new(None),
+        }
+    }
+
+    fn session(&self) -> Result<Arc<Session>, Error> {
+        let mut session = self.session.lock().unwrap();
+        if let Some(session) = &*session {
+            return Ok(session.clone());
+        }
+
+        let path = &self.config.path;
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("failed to read model {}", path.display()))?
+            .len();
+        if size > self.max_memory_bytes {
+            bail!("model {} exceeds its memory limit", path.display());
+        }
+        let loaded = Arc::new(
+            Session::builder()?
+                .with_optimization_level(GraphOptimizationLevel::Level3)?
+                .with_intra_threads(self.config.intra_threads.unwrap_or(DEFAULT_INTRA_THREADS))?
+                .commit_from_file(path)?,
+        );
+        *session = Some(loaded.clone());
+        Ok(loaded)
+    }
+}
+
+trait Element: Copy + Debug + PrimitiveTensorElementType + 'static {
+    fn from_ne(bytes: &[u8]) -> Self;
+    fn write_ne(self, out: &mut Vec<u8>);
+}
+
+macro_rules! impl_element {
+    ($($ty:ty),*) => {
+        $(
+            impl Element for $ty {
+                fn from_ne(bytes: &[u8]) -> Self {
+                    <$ty>::from_ne_bytes(bytes.try_into().unwrap())
+                }
+
+                fn write_ne(self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_ne_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(f32, i32, i64, u8);
+
+// Reinterprets the bytes of a typed array in place, copying them only if
+// they're misaligned (eg: a view at an odd offset)
+fn elements<T: Element>(bytes: &[u8]) -> Cow<'_, [T]> {
+    let size = std::mem::size_of::<T>();
+    if bytes.as_ptr() as usize % std::mem::align_of::<T>() == 0 {
+        // SAFETY: the pointer is aligned, and any bit pattern is a valid T
+        Cow::Borrowed(unsafe {
+            std::slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size)
+        })
+    } else {
+        Cow::Owned(bytes.chunks_exact(size).map(T::from_ne).collect())
+    }
+}
+
+fn tensor<T: Element>(input: &TensorInput) -> Result<SessionInputValue<'static>, Error> {
+    let data = elements::<T>(&input.data).into_owned();
+    Ok(Tensor::from_array((input.shape.clone(), data))?.into())
+}
+
+fn input_value(input: &TensorInput) -> Result<SessionInputValue<'static>, Error> {
+    let len: usize = input.shape.iter().product();
+    if input.data.len() != len * input.tensor_type.size() {
+        bail!(
+            "input {} has {} bytes, its shape takes {}",
+            input.name,
+            input.data.len(),
+            len * input.tensor_type.size()
+        );
+    }
+    match input.tensor_type {
+        TensorType::Float32 => tensor::<f32>(input),
+        TensorType::Int32 => tensor::<i32>(input),
+        TensorType::Int64 => tensor::<i64>(input),
+        TensorType::Uint8 => tensor::<u8>(input),
+    }
+}
+
+fn tensor_type(element: Option<TensorElementType>) -> Option<TensorType> {
+    match element? {
+        TensorElementType::Float32 => Some(TensorType::Float32),
+        TensorElementType::Int32 => Some(TensorType::Int32),
+        TensorElementType::Int64 => Some(TensorType::Int64),
+        TensorElementType::Uint8 => Some(TensorType::Uint8),
+        _ => None,
+    }
+}
+
+fn extract<T: Element>(value: &Value) -> Result<(Vec<usize>, Vec<u8>), Error> {
+    let view = value.try_extract_tensor::<T>()?;
+    let mut data = Vec::with_capacity(view.len() * std::mem::size_of::<T>());
+    for element in view.iter() {
+        element.write_ne(&mut data);
+    }
+    Ok((view.shape().to_vec(), data))
+}
+
+fn run_model(
+    session: &Session,
+    inputs: Vec<TensorInput>,
+    max_memory_bytes: u64,
+) -> Result<Vec<TensorOutput>, Error> {
+    let input_bytes: usize = inputs.iter().map(|input| input.data.len()).sum();
+    if input_bytes as u64 > max_memory_bytes {
+        bail!("inputs exceed the model's memory limit");
+    }
+
+    // the model takes its inputs in order
+    let mut by_name: HashMap<String, TensorInput> = inputs
+        .into_iter()
+        .map(|input| (input.name.clone(), input))
+        .collect();
+    let mut ordered = vec![];
+    for input in &session.inputs {
+        let tensor = by_name
+            .remove(&input.name)
+            .ok_or_else(|| anyhow!("missing input {}", input.name))?;
+        if tensor_type(input.input_type.tensor_type()) != Some(tensor.tensor_type) {
+            bail!(
+                "input {} must be {:?} (got {:?})",
+                input.name,
+                input.input_type,
+                tensor.tensor_type
+            );
+        }
+        ordered.push(tensor);
+    }
+    if let Some(name) = by_name.keys().next() {
+        bail!("the model has no input named {}", name);
+    }
+
+    let values = ordered
+        .iter()
+        .map(|input| Ok((input.name.as_str(), input_value(input)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let outputs = session.run(values)?;
+
+    let mut output_bytes = 0;
+    let mut tensors = vec![];
+    for output in &session.outputs {
+        let value = &outputs[output.name.as_str()];
+        let (tensor_type, (shape, data)) = match tensor_type(output.output_type.tensor_type()) {
+            Some(TensorType::Float32) => (TensorType::Float32, extract::<f32>(value)?),
+            Some(TensorType::Int32) => (TensorType::Int32, extract::<i32>(value)?),
+            Some(TensorType::Int64) => (TensorType::Int64, extract::<i64>(value)?),
+            Some(TensorType::Uint8) => (TensorType::Uint8, extract::<u8>(value)?),
+            None => bail!(
+                "output {} has an unsupported type ({:?})",
+                output.name,
+                output.output_type
+            ),
+        };
+        output_bytes += data.len() as u64;
+        if output_bytes > max_memory_bytes {
+            bail!("outputs exceed the model's memory limit");
+        }
+        tensors.push(TensorOutput {
+            name: output.name.clone(),
+            tensor_type,
+            shape,
+            data,
+        });
+    }
+    Ok(tensors)
+}
+
+pub struct OnnxRunner {
+    services: HashMap<String, HashMap<String, Arc<OnnxModel>>>,
+}
+
+impl OnnxRunner {
+    fn service_models(&self, service: &str) -> Option<&HashMap<String, Arc<OnnxModel>>> {
+        self.services
+            .get(service)
+            .or_else(|| self.services.get(DEFAULT_SERVICE))
+    }
+}
+
+impl ModelRunner for OnnxRunner {
+    fn models(&self, service: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .service_models(service)
+            .map(|models| models.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    fn run(
+        &self,
+        service: &str,
+        model: &str,
+        inputs: Vec<TensorInput>,
+    ) -> BoxFuture<'static, Result<Vec<TensorOutput>, Error>> {
+        let Some(model) = self
+            .service_models(service)
+            .and_then(|models| models.get(model))
+            .cloned()
+        else {
+            return future::ready(Err(anyhow!("no model named {}", model))).boxed();
+        };
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let session = model.session()?;
+                run_model(&session, inputs, model.max_memory_bytes)
+            })
+            .await?
+        }
+        .boxed()
+    }
+}
+
+impl OnnxConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_service_config(path, Self::parse, |config| &mut config.services)
+    }
+
+    // Fails if onnxruntime can't be loaded, models are loaded on first use
+    pub fn into_runner(self) -> Result<OnnxRunner, Error> {
+        ort_environment()?;
+        let services = self
+            .services
+            .into_iter()
+            .map(|(service, config)| {
+                let models = config
+                    .models
+                    .into_iter()
+                    .map(|(name, config)| (name, Arc::new(OnnxModel::new(config))))
+                    .collect();
+                (service, models)
+            })
+            .collect();
+        Ok(OnnxRunner { services })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{elements, OnnxConfig, OnnxModel};
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_onnx_config() {
+        let config = OnnxConfig::parse(
+            r#"{ "services": { "search": { "models": { "embed": { "path": "/models/minilm.onnx", "maxMemoryMb": 64 } } } } }"#,
+        )
+        .unwrap();
+        let model = OnnxModel::new(config.services["search"].models["embed"].clone());
+        assert_eq!(model.max_memory_bytes, 64 * 1024 * 1024);
+
+        assert!(OnnxConfig::parse(
+            r#"{ "services": { "search": { "models": { "embed": { "file": "x.onnx" } } } } }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_tensor_elements() {
+        let floats: Vec<f32> = vec![0.5, -1.0, 2.25];
+        let mut bytes = vec![0u8; 1];
+        for float in &floats {
+            bytes.extend_from_slice(&float.to_ne_bytes());
+        }
+
+        // aligned views are borrowed, misaligned ones copied
+        let aligned =
+            unsafe { std::slice::from_raw_parts(floats.as_ptr() as *const u8, floats.len() * 4) };
+        assert!(matches!(elements::<f32>(aligned), Cow::Borrowed(_)));
+        assert_eq!(elements::<f32>(aligned).as_ref(), floats.as_slice());
+        assert_eq!(elements::<f32>(&bytes[1..]).as_ref(), floats.as_slice());
+    }
+}
@@ -0,0 +1,509 @@
+use crate::key_store::key_store;
+use crate::utils::send_event_if_event_worker_available;
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json;
+use event_worker::events::{
+    EventMetadata, WebhookDeliveryEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use log::{error, warn};
+use rusqlite::{params, Connection};
+use sb_worker_context::outbound_webhooks::{
+    DeliveryStatus, SharedWebhookStore, WebhookDelivery, WebhookStore,
+};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+// `EdgeRuntime.webhooks.send`: webhooks are queued in a local sqlite database and
+// POSTed by the runtime, signed in the Standard Webhooks format with a key of the
+// key store. Failed attempts are retried with exponential backoff until the
+// delivery runs out of attempts and is dead-lettered.
+
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// how long a claimed delivery has to be attempted before it's claimed again
+const WEBHOOK_LEASE_MS: u64 = 60_000;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(15);
+const WEBHOOK_MAX_ATTEMPTS: u32 = 8;
+const WEBHOOK_RETRY_BASE_MS: u64 = 5_000;
+// delivered webhooks are kept around for status lookups this long
+const WEBHOOK_RETENTION_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+const WEBHOOK_ID_HEADER: &str = "webhook-id";
+const WEBHOOK_TIMESTAMP_HEADER: &str = "webhook-timestamp";
+const WEBHOOK_SIGNATURE_HEADER: &str = "webhook-signature";
+const WEBHOOK_EVENT_TYPE_HEADER: &str = "webhook-event-type";
+
+static WEBHOOK_STORE: OnceLock<SharedWebhookStore> = OnceLock::new();
+
+// Store of the dispatcher, if outbound webhooks are enabled
+pub fn webhook_store() -> Option<SharedWebhookStore> {
+    WEBHOOK_STORE.get().cloned()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Deliveries in a local sqlite database
+pub struct SqliteWebhookStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteWebhookStore {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id TEXT PRIMARY KEY,
+                service TEXT NOT NULL,
+                url TEXT NOT NULL,
+                event_type TEXT,
+                payload TEXT NOT NULL,
+                signing_key TEXT,
+                status TEXT NOT NULL,
+                attempt INTEGER NOT NULL,
+                next_attempt_ms INTEGER NOT NULL,
+                last_error TEXT
+            );
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_due
+                ON webhook_deliveries (status, next_attempt_ms);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+const DELIVERY_COLUMNS: &str = "id, service, url, event_type, payload, signing_key, status, \
+     attempt, next_attempt_ms, last_error";
+
+fn query_deliveries(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<WebhookDelivery>, Error> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| {
+        Ok((
+            WebhookDelivery {
+                id: row.get(0)?,
+                service: row.get(1)?,
+                url: row.get(2)?,
+                event_type: row.get(3)?,
+                payload: serde_json::Value::Null,
+                signing_key: row.get(5)?,
+                status: DeliveryStatus::Pending,
+                attempt: row.get(7)?,
+                next_attempt_ms: row.get::<_, i64>(8)? as u64,
+                last_error: row.get(9)?,
+            },
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(6)?,
+        ))
+    })?;
+
+    let mut deliveries = vec![];
+    for row in rows {
+        let (mut delivery, payload, status) = row?;
+        delivery.payload = serde_json::from_str(&payload)?;
+        delivery.status = DeliveryStatus::parse(&status)
+            .ok_or_else(|| anyhow!("invalid delivery status {}", status))?;
+        deliveries.push(delivery);
+    }
+    Ok(deliveries)
+}
+
+impl WebhookStore for SqliteWebhookStore {
+    fn insert(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            &format!(
+                "INSERT INTO webhook_deliveries ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                DELIVERY_COLUMNS
+            ),
+            params![
+                delivery.id,
+                delivery.service,
+                delivery.url,
+                delivery.event_type,
+                serde_json::to_string(&delivery.payload)?,
+                delivery.signing_key,
+                delivery.status.as_str(),
+                delivery.attempt,
+                delivery.next_attempt_ms as i64,
+                delivery.last_error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, service: &str, id: &str) -> Result<Option<WebhookDelivery>, Error> {
+        let deliveries = query_deliveries(
+            &self.conn.lock().unwrap(),
+            &format!(
+                "SELECT {} FROM webhook_deliveries WHERE id = ?1 AND service = ?2",
+                DELIVERY_COLUMNS
+            ),
+            params![id, service],
+        )?;
+        Ok(deliveries.into_iter().next())
+    }
+
+    fn dead_letters(&self, service: &str) -> Result<Vec<WebhookDelivery>, Error> {
+        query_deliveries(
+            &self.conn.lock().unwrap(),
+            &format!(
+                "SELECT {} FROM webhook_deliveries WHERE service = ?1 AND status = 'dead' \
+                 ORDER BY next_attempt_ms",
+                DELIVERY_COLUMNS
+            ),
+            params![service],
+        )
+    }
+
+    fn redeliver(&self, service: &str, id: &str, now_ms: u64) -> Result<bool, Error> {
+        let updated = self.conn.lock().unwrap().execute(
+            "UPDATE webhook_deliveries SET status = 'pending', attempt = 0, next_attempt_ms = ?1
+             WHERE id = ?2 AND service = ?3 AND status = 'dead'",
+            params![now_ms as i64, id, service],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn claim_due(&self, now_ms: u64, lease_ms: u64) -> Result<Vec<WebhookDelivery>, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let deliveries = query_deliveries(
+            &tx,
+            &format!(
+                "SELECT {} FROM webhook_deliveries
+                 WHERE status = 'pending' AND next_attempt_ms <= ?1 ORDER BY next_attempt_ms",
+                DELIVERY_COLUMNS
+            ),
+            params![now_ms as i64],
+        )?;
+        tx.execute(
+            "UPDATE webhook_deliveries SET next_attempt_ms = ?1
+             WHERE status = 'pending' AND next_attempt_ms <= ?2",
+            params![(now_ms + lease_ms) as i64, now_ms as i64],
+        )?;
+        tx.commit()?;
+        Ok(deliveries)
+    }
+
+    fn delivered(&self, id: &str, now_ms: u64) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE webhook_deliveries
+             SET status = 'delivered', attempt = attempt + 1, next_attempt_ms = ?1, last_error = NULL
+             WHERE id = ?2",
+            params![now_ms as i64, id],
+        )?;
+        Ok(())
+    }
+
+    fn retry(&self, id: &str, next_attempt_ms: u64, error: &str) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE webhook_deliveries
+             SET attempt = attempt + 1, next_attempt_ms = ?1, last_error = ?2
+             WHERE id = ?3 AND status = 'pending'",
+            params![next_attempt_ms as i64, error, id],
+        )?;
+        Ok(())
+    }
+
+    fn dead_letter(&self, id: &str, error: &str) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE webhook_deliveries
+             SET status = 'dead', attempt = attempt + 1, last_error = ?1
+             WHERE id = ?2 AND status = 'pending'",
+            params![error, id],
+        )?;
+        Ok(())
+    }
+
+    fn prune(&self, before_ms: u64) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM webhook_deliveries WHERE status = 'delivered' AND next_attempt_ms < ?1",
+            params![before_ms as i64],
+        )?;
+        Ok(())
+    }
+}
+
+// `v1` signatures are HMAC-SHA256, `v1a` ed25519 (as in the Standard Webhooks spec)
+fn signature_version(algorithm: &str) -> Result<&'static str, Error> {
+    match algorithm {
+        "hmac-sha256" => Ok("v1"),
+        "ed25519" => Ok("v1a"),
+        _ => bail!("{} keys can't sign webhooks", algorithm),
+    }
+}
+
+// Signs `<id>.<timestamp>.<body>`, returning the `webhook-signature` header
+async fn sign(
+    delivery: &WebhookDelivery,
+    key: &str,
+    timestamp: u64,
+    body: &[u8],
+) -> Result<String, Error> {
+    let signer = key_store()
+        .and_then(|store| store.get(key, &delivery.service))
+        .ok_or_else(|| anyhow!("no signing key named {}", key))?;
+    let version = signature_version(signer.algorithm())?;
+
+    let mut signed = format!("{}.{}.", delivery.id, timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    let signature = signer.sign(signed).await?;
+    Ok(format!("{},{}", version, base64::encode(signature)))
+}
+
+enum AttemptError {
+    // not worth retrying (eg: the signing key is gone)
+    Permanent(String),
+    Transient(String, Option<u16>),
+}
+
+// POSTs the payload. Succeeds if the receiver responded with a 2xx status.
+async fn attempt(
+    client: &reqwest::Client,
+    delivery: &WebhookDelivery,
+) -> Result<u16, AttemptError> {
+    let body = serde_json::to_vec(&delivery.payload)
+        .map_err(|err| AttemptError::Permanent(err.to_string()))?;
+    let timestamp = now_ms() / 1000;
+
+    let mut req = client
+        .post(&delivery.url)
+        .header("content-type", "application/json")
+        .header(WEBHOOK_ID_HEADER, &delivery.id)
+        .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string());
+    if let Some(event_type) = &delivery.event_type {
+        req = req.header(WEBHOOK_EVENT_TYPE_HEADER, event_type);
+    }
+    if let Some(key) = &delivery.signing_key {
+        let signature = sign(delivery, key, timestamp, &body)
+            .await
+            .map_err(|err| AttemptError::Permanent(err.to_string()))?;
+        req = req.header(WEBHOOK_SIGNATURE_HEADER, signature);
+    }
+
+    let res = req
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| AttemptError::Transient(err.to_string(), None))?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(AttemptError::Transient(
+            format!("receiver responded with {}", status),
+            Some(status.as_u16()),
+        ));
+    }
+    Ok(status.as_u16())
+}
+
+fn retry_backoff_ms(attempt: u32) -> u64 {
+    WEBHOOK_RETRY_BASE_MS << attempt.min(16)
+}
+
+async fn deliver(
+    store: SharedWebhookStore,
+    client: reqwest::Client,
+    delivery: WebhookDelivery,
+    events_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+) {
+    let attempt_no = delivery.attempt + 1;
+    let (status, status_code, error, result) = match attempt(&client, &delivery).await {
+        Ok(status_code) => (
+            DeliveryStatus::Delivered,
+            Some(status_code),
+            None,
+            store.delivered(&delivery.id, now_ms()),
+        ),
+        Err(AttemptError::Transient(err, status_code)) if attempt_no < WEBHOOK_MAX_ATTEMPTS => {
+            let next_attempt_ms = now_ms() + retry_backoff_ms(delivery.attempt);
+            let result = store.retry(&delivery.id, next_attempt_ms, &err);
+            (DeliveryStatus::Pending, status_code, Some(err), result)
+        }
+        Err(AttemptError::Transient(err, status_code)) => {
+            let result = store.dead_letter(&delivery.id, &err);
+            (DeliveryStatus::Dead, status_code, Some(err), result)
+        }
+        Err(AttemptError::Permanent(err)) => {
+            let result = store.dead_letter(&delivery.id, &err);
+            (DeliveryStatus::Dead, None, Some(err), result)
+        }
+    };
+    if let Err(err) = result {
+        error!("failed to update webhook delivery {}: {}", delivery.id, err);
+    }
+    if status == DeliveryStatus::Dead {
+        warn!(
+            "webhook {} of {} to {} was dead-lettered: {}",
+            delivery.id,
+            delivery.service,
+            delivery.url,
+            error.as_deref().unwrap_or_default()
+        );
+    }
+
+    send_event_if_event_worker_available(
+        events_tx,
+        WorkerEvents::WebhookDelivery(WebhookDeliveryEvent {
+            id: delivery.id,
+            url: delivery.url,
+            event_type: delivery.event_type,
+            status: match status {
+                DeliveryStatus::Pending => "retrying",
+                status => status.as_str(),
+            }
+            .to_string(),
+            attempt: attempt_no,
+            status_code,
+            error,
+        }),
+        EventMetadata {
+            service_path: Some(delivery.service),
+            ..Default::default()
+        },
+    );
+}
+
+// Polls the store for due deliveries and POSTs them, reporting every attempt to
+// the events worker
+pub fn start_webhook_dispatcher(
+    store: SharedWebhookStore,
+    events_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+) -> Result<(), Error> {
+    if WEBHOOK_STORE.set(store.clone()).is_err() {
+        warn!("webhook dispatcher is already running");
+        return Ok(());
+    }
+    // receivers must not redirect deliveries elsewhere
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent("supabase-edge-runtime")
+        .build()?;
+
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(WEBHOOK_POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let now = now_ms();
+            if let Err(err) = store.prune(now.saturating_sub(WEBHOOK_RETENTION_MS)) {
+                error!("failed to prune webhook deliveries: {}", err);
+            }
+            let deliveries = match store.claim_due(now, WEBHOOK_LEASE_MS) {
+                Ok(deliveries) => deliveries,
+                Err(err) => {
+                    error!("failed to poll webhook deliveries: {}", err);
+                    continue;
+                }
+            };
+            for delivery in deliveries {
+                tokio::task::spawn(deliver(
+                    store.clone(),
+                    client.clone(),
+                    delivery,
+                    events_tx.clone(),
+                ));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{retry_backoff_ms, signature_version, SqliteWebhookStore};
+    use deno_core::serde_json::json;
+    use sb_worker_context::outbound_webhooks::{DeliveryStatus, WebhookDelivery, WebhookStore};
+
+    fn delivery(id: &str, service: &str, next_attempt_ms: u64) -> WebhookDelivery {
+        WebhookDelivery {
+            id: id.to_string(),
+            service: service.to_string(),
+            url: "https://example.com/hooks".to_string(),
+            event_type: Some("invoice.paid".to_string()),
+            payload: json!({ "invoice": 1 }),
+            signing_key: Some("webhooks".to_string()),
+            status: DeliveryStatus::Pending,
+            attempt: 0,
+            next_attempt_ms,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_sqlite_webhook_store() {
+        let store = SqliteWebhookStore::in_memory().unwrap();
+        store.insert(&delivery("a", "billing", 1_000)).unwrap();
+        store.insert(&delivery("b", "billing", 5_000)).unwrap();
+        store.insert(&delivery("c", "other", 1_000)).unwrap();
+
+        // services only see their own deliveries
+        assert!(store.get("billing", "c").unwrap().is_none());
+        assert_eq!(
+            store.get("billing", "a").unwrap(),
+            Some(delivery("a", "billing", 1_000))
+        );
+
+        // claimed deliveries are leased, so they aren't claimed twice
+        let due = store.claim_due(2_000, 60_000).unwrap();
+        assert_eq!(due.len(), 2);
+        assert!(store.claim_due(2_000, 60_000).unwrap().is_empty());
+
+        store
+            .retry("a", 3_000, "receiver responded with 503")
+            .unwrap();
+        let due = store.claim_due(3_000, 60_000).unwrap();
+        assert_eq!(due[0].attempt, 1);
+        assert_eq!(
+            due[0].last_error.as_deref(),
+            Some("receiver responded with 503")
+        );
+
+        store.delivered("a", 4_000).unwrap();
+        let a = store.get("billing", "a").unwrap().unwrap();
+        assert_eq!(a.status, DeliveryStatus::Delivered);
+        assert_eq!(a.attempt, 2);
+
+        // dead letters stay until they're redelivered
+        store
+            .dead_letter("c", "no signing key named webhooks")
+            .unwrap();
+        assert!(store.dead_letters("billing").unwrap().is_empty());
+        assert_eq!(store.dead_letters("other").unwrap()[0].id, "c");
+        assert!(!store.redeliver("billing", "c", 6_000).unwrap());
+        assert!(store.redeliver("other", "c", 6_000).unwrap());
+        let c = store.get("other", "c").unwrap().unwrap();
+        assert_eq!((c.status, c.attempt), (DeliveryStatus::Pending, 0));
+
+        // only delivered webhooks are pruned
+        store.prune(10_000).unwrap();
+        assert!(store.get("billing", "a").unwrap().is_none());
+        assert!(store.get("billing", "b").unwrap().is_some());
+        assert!(store.get("other", "c").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_webhook_signing_and_backoff() {
+        assert_eq!(signature_version("hmac-sha256").unwrap(), "v1");
+        assert_eq!(signature_version("ed25519").unwrap(), "v1a");
+        assert!(signature_version("ecdsa-p256").is_err());
+
+        assert_eq!(retry_backoff_ms(0), 5_000);
+        assert_eq!(retry_backoff_ms(3), 40_000);
+    }
+}
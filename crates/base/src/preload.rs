@@ -0,0 +1,171 @@
+use crate::fallback::manifest_worker_opts;
+use anyhow::Error;
+use deno_core::futures::future::join_all;
+use log::{error, info};
+use sb_worker_context::essentials::{
+    CreateUserWorkerResult, UserWorkerMsgs, WorkerContextInitOpts,
+};
+use sb_worker_context::manifest::Manifest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot, watch};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "status")]
+pub enum PreloadStatus {
+    Pending,
+    Ready {
+        #[serde(rename = "bootMs")]
+        boot_ms: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PreloadReport {
+    pub ready: bool,
+    pub services: BTreeMap<String, PreloadStatus>,
+}
+
+async fn create_worker(
+    pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+    opts: WorkerContextInitOpts,
+) -> Result<CreateUserWorkerResult, Error> {
+    let (tx, rx) = oneshot::channel();
+    pool_tx.send(UserWorkerMsgs::Create(opts, tx))?;
+    rx.await?
+}
+
+// Workers booted at startup (the `preload` list of the manifest), so the first
+// requests after a deploy don't pay for fetching and compiling their graphs.
+// The runtime isn't ready until every preload completed (or failed).
+#[derive(Clone)]
+pub struct Preloads {
+    report: watch::Receiver<PreloadReport>,
+}
+
+impl Default for Preloads {
+    fn default() -> Self {
+        let (_, report) = watch::channel(PreloadReport {
+            ready: true,
+            ..Default::default()
+        });
+        Self { report }
+    }
+}
+
+impl Preloads {
+    pub fn start(manifest: &Manifest, pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>) -> Self {
+        if manifest.preload.is_empty() {
+            return Self::default();
+        }
+        let services: Vec<_> = manifest
+            .preload
+            .iter()
+            .filter_map(|name| manifest.get(name))
+            .collect();
+        let (report_tx, report) = watch::channel(PreloadReport {
+            ready: false,
+            services: services
+                .iter()
+                .map(|service| (service.name.clone(), PreloadStatus::Pending))
+                .collect(),
+        });
+
+        info!("preloading {} services", services.len());
+        tokio::task::spawn(async move {
+            let boots = services.into_iter().map(|service| {
+                let pool_tx = pool_tx.clone();
+                let report_tx = &report_tx;
+                async move {
+                    let started = Instant::now();
+                    let opts = manifest_worker_opts(service.worker_options);
+                    let status = match create_worker(&pool_tx, opts).await {
+                        Ok(_) => PreloadStatus::Ready {
+                            boot_ms: started.elapsed().as_millis() as u64,
+                        },
+                        Err(err) => {
+                            error!("failed to preload {}: {:#}", service.name, err);
+                            PreloadStatus::Failed {
+                                error: err.to_string(),
+                            }
+                        }
+                    };
+                    report_tx.send_modify(|report| {
+                        report.services.insert(service.name, status);
+                    });
+                }
+            });
+            join_all(boots).await;
+            report_tx.send_modify(|report| report.ready = true);
+            info!("preloaded services");
+        });
+        Self { report }
+    }
+
+    pub fn report(&self) -> PreloadReport {
+        self.report.borrow().clone()
+    }
+
+    // Resolves once every preload completed
+    pub async fn wait(&self) {
+        let mut report = self.report.clone();
+        let _ = report.wait_for(|report| report.ready).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PreloadReport, PreloadStatus, Preloads};
+    use sb_worker_context::essentials::{CreateUserWorkerResult, UserWorkerMsgs};
+    use sb_worker_context::manifest::Manifest;
+    use std::path::Path;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_preloads() {
+        assert!(Preloads::default().report().ready);
+
+        let manifest = Manifest::parse(
+            r#"{
+                "services": { "hello": { "entrypoint": "./hello" }, "broken": { "entrypoint": "./broken" } },
+                "preload": ["hello", "broken"]
+            }"#,
+            Path::new("/etc/functions"),
+        )
+        .unwrap();
+        let (pool_tx, mut pool_rx) = mpsc::unbounded_channel();
+        let preloads = Preloads::start(&manifest, pool_tx);
+        assert!(!preloads.report().ready);
+
+        for _ in 0..2 {
+            let Some(UserWorkerMsgs::Create(opts, tx)) = pool_rx.recv().await else {
+                panic!("expected a worker creation");
+            };
+            let res = if opts.service_path.ends_with("hello") {
+                Ok(CreateUserWorkerResult {
+                    key: uuid::Uuid::new_v4(),
+                })
+            } else {
+                Err(anyhow::anyhow!("boot failure"))
+            };
+            tx.send(res).unwrap();
+        }
+        preloads.wait().await;
+
+        let PreloadReport { ready, services } = preloads.report();
+        assert!(ready);
+        assert!(matches!(services["hello"], PreloadStatus::Ready { .. }));
+        assert_eq!(
+            services["broken"],
+            PreloadStatus::Failed {
+                error: "boot failure".to_string()
+            }
+        );
+
+        assert!(Manifest::parse(r#"{ "preload": ["missing"] }"#, Path::new("/")).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Error};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::{FutureExt, StreamExt};
+use deno_core::serde_json::{self, json, Value};
+use log::warn;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, IntoConnectionInfo};
+use sb_worker_context::redis::{RedisCommand, RedisPool, SharedRedisPool};
+use sb_worker_context::service_scope::load_service_config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, OnceCell};
+
+// `EdgeRuntime.redis`: a connection per Redis server, shared by the isolates of
+// all services instead of a connection per isolate. Connections are driven by
+// the server's runtime, so they outlive the workers using them. Keys and
+// channels are prefixed per service.
+
+static REDIS_POOL: OnceLock<SharedRedisPool> = OnceLock::new();
+
+// services without an account of their own use this one, if set
+const DEFAULT_ACCOUNT: &str = "*";
+
+pub fn set_redis_pool(pool: SharedRedisPool) {
+    if REDIS_POOL.set(pool).is_err() {
+        warn!("redis pool is already set");
+    }
+}
+
+pub fn redis_pool() -> Option<SharedRedisPool> {
+    REDIS_POOL.get().cloned()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RedisAccountConfig {
+    pub url: String,
+    // env var holding the password, injected into the connection
+    #[serde(default)]
+    pub password_env: Option<String>,
+    // prepended to keys and channels, `<service>:` by default
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+// eg: `{ "services": { "*": { "url": "redis://cache:6379", "passwordEnv": "REDIS_PASSWORD" } } }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RedisConfig {
+    pub services: HashMap<String, RedisAccountConfig>,
+}
+
+struct RedisAccount {
+    client: redis::Client,
+    prefix: Option<String>,
+    // connected on first use, reconnects by itself
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl RedisAccount {
+    fn new(config: RedisAccountConfig) -> Result<Self, Error> {
+        let mut info = config.url.as_str().into_connection_info()?;
+        if let Some(var) = &config.password_env {
+            info.redis.password = Some(
+                std::env::var(var)
+                    .with_context(|| format!("failed to read the password from ${}", var))?,
+            );
+        }
+        Ok(Self {
+            client: redis::Client::open(info)?,
+            prefix: config.prefix,
+            conn: OnceCell::new(),
+        })
+    }
+
+    fn prefix(&self, service: &str) -> String {
+        self.prefix
+            .clone()
+            .unwrap_or_else(|| format!("{}:", service))
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager, Error> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?;
+        Ok(conn.clone())
+    }
+
+    async fn query(&self, prefix: &str, command: RedisCommand) -> Result<Value, Error> {
+        let mut conn = self.connection().await?;
+        let key = |key: &str| format!("{}{}", prefix, key);
+        Ok(match command {
+            RedisCommand::Get { key: k } => json!(conn.get::<_, Option<String>>(key(&k)).await?),
+            RedisCommand::Set {
+                key: k,
+                value,
+                ex_secs,
+            } => {
+                match ex_secs {
+                    Some(secs) => {
+                        conn.set_ex::<_, _, ()>(key(&k), value, secs as usize)
+                            .await?
+                    }
+                    None => conn.set::<_, _, ()>(key(&k), value).await?,
+                }
+                Value::Null
+            }
+            RedisCommand::Expire { key: k, secs } => {
+                json!(conn.expire::<_, bool>(key(&k), secs as usize).await?)
+            }
+            RedisCommand::Incr { key: k, by } => json!(conn.incr::<_, _, i64>(key(&k), by).await?),
+            RedisCommand::Publish { channel, message } => {
+                json!(conn.publish::<_, _, i64>(key(&channel), message).await?)
+            }
+        })
+    }
+
+    async fn subscribe(&self, channel: String) -> Result<mpsc::UnboundedReceiver<String>, Error> {
+        // subscriptions need a connection of their own
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(&channel).await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut messages = pubsub.on_message();
+            loop {
+                tokio::select! {
+                    msg = messages.next() => {
+                        let Some(msg) = msg else {
+                            return;
+                        };
+                        let Ok(payload) = msg.get_payload::<String>() else {
+                            continue;
+                        };
+                        if tx.send(payload).is_err() {
+                            return;
+                        }
+                    }
+                    // the subscriber is gone
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+pub struct SharedRedis {
+    handle: Handle,
+    accounts: HashMap<String, Arc<RedisAccount>>,
+}
+
+impl SharedRedis {
+    fn account(&self, service: &str) -> Result<Arc<RedisAccount>, Error> {
+        self.accounts
+            .get(service)
+            .or_else(|| self.accounts.get(DEFAULT_ACCOUNT))
+            .cloned()
+            .ok_or_else(|| anyhow!("no redis account for {}", service))
+    }
+}
+
+impl RedisPool for SharedRedis {
+    fn query(
+        &self,
+        service: &str,
+        command: RedisCommand,
+    ) -> BoxFuture<'static, Result<Value, Error>> {
+        let account = match self.account(service) {
+            Ok(account) => account,
+            Err(err) => return future::ready(Err(err)).boxed(),
+        };
+        let prefix = account.prefix(service);
+        let task = self
+            .handle
+            .spawn(async move { account.query(&prefix, command).await });
+        async move { task.await? }.boxed()
+    }
+
+    fn subscribe(
+        &self,
+        service: &str,
+        channel: String,
+    ) -> BoxFuture<'static, Result<mpsc::UnboundedReceiver<String>, Error>> {
+        let account = match self.account(service) {
+            Ok(account) => account,
+            Err(err) => return future::ready(Err(err)).boxed(),
+        };
+        let channel = format!("{}{}", account.prefix(service), channel);
+        let task = self
+            .handle
+            .spawn(async move { account.subscribe(channel).await });
+        async move { task.await? }.boxed()
+    }
+}
+
+impl RedisConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_service_config(path, Self::parse, |config| &mut config.services)
+    }
+
+    // Connections are driven by the current runtime
+    pub fn into_pool(self) -> Result<SharedRedis, Error> {
+        let mut accounts = HashMap::new();
+        for (service, config) in self.services {
+            let account = RedisAccount::new(config)
+                .with_context(|| format!("invalid redis account of {}", service))?;
+            accounts.insert(service, Arc::new(account));
+        }
+        Ok(SharedRedis {
+            handle: Handle::current(),
+            accounts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RedisConfig;
+    use deno_core::serde_json::{self, json};
+    use sb_worker_context::redis::RedisCommand;
+
+    #[tokio::test]
+    async fn test_redis_accounts() {
+        std::env::set_var("REDIS_POOL_TEST_PASSWORD", "secret");
+        let pool = RedisConfig::parse(
+            r#"{ "services": { "*": { "url": "redis://localhost:6379", "passwordEnv": "REDIS_POOL_TEST_PASSWORD" }, "billing": { "url": "redis://billing:6379/2", "prefix": "b:" } } }"#,
+        )
+        .unwrap()
+        .into_pool()
+        .unwrap();
+
+        // credentials are injected, services fall back to the `*` account
+        let account = pool.account("media").unwrap();
+        assert_eq!(
+            account
+                .client
+                .get_connection_info()
+                .redis
+                .password
+                .as_deref(),
+            Some("secret")
+        );
+        assert_eq!(account.prefix("media"), "media:");
+        assert_eq!(pool.account("billing").unwrap().prefix("billing"), "b:");
+
+        let pool =
+            RedisConfig::parse(r#"{ "services": { "billing": { "url": "redis://billing" } } }"#)
+                .unwrap()
+                .into_pool()
+                .unwrap();
+        assert!(pool.account("media").is_err());
+    }
+
+    #[test]
+    fn test_redis_commands() {
+        let command: RedisCommand = serde_json::from_value(json!({
+            "command": "set",
+            "key": "counter",
+            "value": "1",
+            "exSecs": 60,
+        }))
+        .unwrap();
+        assert_eq!(
+            command,
+            RedisCommand::Set {
+                key: "counter".to_string(),
+                value: "1".to_string(),
+                ex_secs: Some(60),
+            }
+        );
+        assert!(serde_json::from_value::<RedisCommand>(json!({ "command": "flushall" })).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::rt_worker::worker_ctx::{
+    create_main_worker, create_user_worker_pool, main_worker_env_vars, send_user_worker_request,
+};
+use anyhow::{anyhow, bail, Error};
+use bytes::{Bytes, BytesMut};
+use deno_core::futures::stream::{self, StreamExt};
+use deno_core::serde_json;
+use hyper::header::{HeaderValue, CONTENT_LENGTH};
+use hyper::{Body, Request, Response};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// requests carrying this header, set to the record token, are recorded (when a
+// record dir is configured). It's stripped from every client request.
+pub const RECORD_HEADER: &str = "x-edge-runtime-record";
+// env var holding the record token, kept out of CLI args and worker envs
+pub const RECORD_TOKEN_ENV: &str = "EDGE_RUNTIME_RECORD_TOKEN";
+// id of the recording, set on responses to recorded requests
+pub const RECORDING_ID_HEADER: &str = "x-edge-runtime-recording";
+
+const RECORDING_VERSION: u32 = 1;
+
+// requests aren't recorded past these, so the record dir can't fill the disk
+pub const MAX_RECORDINGS: usize = 1000;
+pub const MAX_RECORDED_BODY_BYTES: usize = 1024 * 1024;
+
+static REPLAY_SEED: OnceLock<ReplaySeed> = OnceLock::new();
+
+// Pins the clock and seeds `Math.random` of every worker created in this process
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplaySeed {
+    pub time_origin_ms: u64,
+    pub random_seed: u32,
+}
+
+pub fn set_replay_seed(seed: ReplaySeed) {
+    let _ = REPLAY_SEED.set(seed);
+}
+
+pub fn replay_seed() -> Option<ReplaySeed> {
+    REPLAY_SEED.get().copied()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedRequest {
+    pub version: u32,
+    pub id: String,
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    // base64 encoded
+    pub body: String,
+    // env vars of the main worker when the request was recorded
+    pub env: HashMap<String, String>,
+    pub seed: ReplaySeed,
+    pub main_service_path: String,
+    pub main_entrypoint: Option<String>,
+    pub import_map_path: Option<String>,
+    // sha256 of the main service eszip, to tell if a replay runs the same modules
+    pub main_service_checksum: Option<String>,
+}
+
+impl RecordedRequest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let recording: RecordedRequest = serde_json::from_slice(&std::fs::read(path)?)?;
+        if recording.version != RECORDING_VERSION {
+            bail!(
+                "unsupported recording version {} (expected {})",
+                recording.version,
+                RECORDING_VERSION
+            );
+        }
+        Ok(recording)
+    }
+
+    pub fn to_request(&self) -> Result<Request<Body>, Error> {
+        let mut builder = Request::builder()
+            .method(self.method.as_str())
+            .uri(self.uri.as_str());
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        Ok(builder.body(Body::from(base64::decode(&self.body)?))?)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn eszip_checksum(main_service_path: &str) -> Option<String> {
+    let path = Path::new(main_service_path);
+    if path.extension()? != "eszip" {
+        return None;
+    }
+    let bytes = std::fs::read(path).ok()?;
+    Some(format!("{:x}", Sha256::digest(bytes)))
+}
+
+fn token_digest(token: &[u8]) -> [u8; 32] {
+    Sha256::digest(token).into()
+}
+
+// Removes `RECORD_HEADER` from a client request, returning its value
+pub fn take_record_header(req: &mut Request<Body>) -> Option<HeaderValue> {
+    req.headers_mut().remove(RECORD_HEADER)
+}
+
+// Buffers up to `limit` bytes of a body. Bodies over it are given back whole
+// (the buffered bytes followed by the rest of the stream) as the error.
+async fn buffer_body(mut body: Body, limit: usize) -> Result<Result<Bytes, Body>, Error> {
+    let mut buffered = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        buffered.extend_from_slice(&chunk);
+        if buffered.len() > limit {
+            let head = stream::iter([Ok::<_, hyper::Error>(buffered.freeze())]);
+            return Ok(Err(Body::wrap_stream(head.chain(body))));
+        }
+    }
+    Ok(Ok(buffered.freeze()))
+}
+
+// Writes requests flagged with `RECORD_HEADER` to the record dir
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    dir: PathBuf,
+    // sha256 of the record token, compared against that of the header
+    token_digest: [u8; 32],
+    recordings: Arc<AtomicUsize>,
+    main_service_path: String,
+    main_entrypoint: Option<String>,
+    import_map_path: Option<String>,
+    main_service_checksum: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(
+        dir: PathBuf,
+        token: String,
+        main_service_path: String,
+        main_entrypoint: Option<String>,
+        import_map_path: Option<String>,
+    ) -> Result<Self, Error> {
+        if token.is_empty() {
+            bail!("recording requests requires a record token");
+        }
+        std::fs::create_dir_all(&dir)?;
+        // recordings left by previous runs count towards the limit
+        let recordings = std::fs::read_dir(&dir)?.count();
+        let main_service_checksum = eszip_checksum(&main_service_path);
+        Ok(Self {
+            dir,
+            token_digest: token_digest(token.as_bytes()),
+            recordings: Arc::new(AtomicUsize::new(recordings)),
+            main_service_path,
+            main_entrypoint,
+            import_map_path,
+            main_service_checksum,
+        })
+    }
+
+    // Whether the value of `RECORD_HEADER` is the record token
+    pub fn should_record(&self, record_header: Option<&HeaderValue>) -> bool {
+        record_header
+            .map(|value| token_digest(value.as_bytes()) == self.token_digest)
+            .unwrap_or(false)
+    }
+
+    // Buffers the request body and writes the recording. Returns the request (with
+    // the buffered body) and the recording id, if it was recorded: requests over
+    // the limits are passed on as is.
+    pub async fn record(
+        &self,
+        req: Request<Body>,
+    ) -> Result<(Request<Body>, Option<String>), Error> {
+        let (parts, body) = req.into_parts();
+        let declared_len = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+        if declared_len.is_some_and(|len| len > MAX_RECORDED_BODY_BYTES) {
+            warn!("request body is too large to be recorded");
+            return Ok((Request::from_parts(parts, body), None));
+        }
+        let body = match buffer_body(body, MAX_RECORDED_BODY_BYTES).await? {
+            Ok(body) => body,
+            Err(body) => {
+                warn!("request body is too large to be recorded");
+                return Ok((Request::from_parts(parts, body), None));
+            }
+        };
+        if self.recordings.fetch_add(1, Ordering::Relaxed) >= MAX_RECORDINGS {
+            self.recordings.fetch_sub(1, Ordering::Relaxed);
+            warn!(
+                "{} already holds {} recordings, not recording",
+                self.dir.display(),
+                MAX_RECORDINGS
+            );
+            return Ok((Request::from_parts(parts, Body::from(body)), None));
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let recording = RecordedRequest {
+            version: RECORDING_VERSION,
+            id: id.clone(),
+            method: parts.method.to_string(),
+            uri: parts.uri.to_string(),
+            headers: parts
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).to_string(),
+                    )
+                })
+                .collect(),
+            body: base64::encode(&body),
+            env: main_worker_env_vars(),
+            seed: ReplaySeed {
+                time_origin_ms: now_ms(),
+                random_seed: rand::random(),
+            },
+            main_service_path: self.main_service_path.clone(),
+            main_entrypoint: self.main_entrypoint.clone(),
+            import_map_path: self.import_map_path.clone(),
+            main_service_checksum: self.main_service_checksum.clone(),
+        };
+
+        let path = self.dir.join(format!("{}.json", id));
+        write_private(&path, &serde_json::to_vec_pretty(&recording)?)?;
+
+        Ok((Request::from_parts(parts, Body::from(body)), Some(id)))
+    }
+}
+
+// recordings include env vars (which usually hold secrets)
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOpts {
+    // defaults to the main service the request was recorded with
+    pub main_service_path: Option<String>,
+    pub main_entrypoint: Option<String>,
+    pub import_map_path: Option<String>,
+}
+
+// Re-executes a recorded request against a local main worker, with the recorded
+// env vars, clock and random seed.
+pub async fn replay(
+    recording: &RecordedRequest,
+    opts: ReplayOpts,
+) -> Result<Response<Body>, Error> {
+    let main_service_path = opts
+        .main_service_path
+        .unwrap_or_else(|| recording.main_service_path.clone());
+    if let Some(expected) = &recording.main_service_checksum {
+        match eszip_checksum(&main_service_path) {
+            Some(actual) if &actual == expected => {}
+            _ => warn!(
+                "{} differs from the main service the request was recorded with",
+                main_service_path
+            ),
+        }
+    }
+
+    // workers read their env vars from the process
+    for (key, value) in &recording.env {
+        std::env::set_var(key, value);
+    }
+    set_replay_seed(recording.seed);
+    cpu_timer::register_alarm()?;
+
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await?;
+    let main_worker_req_tx = create_main_worker(
+        PathBuf::from(&main_service_path),
+        opts.import_map_path
+            .or_else(|| recording.import_map_path.clone()),
+        false,
+        user_worker_msgs_tx,
+        opts.main_entrypoint
+            .or_else(|| recording.main_entrypoint.clone()),
+        None,
+    )
+    .await?;
+
+    send_user_worker_request(main_worker_req_tx, recording.to_request()?)
+        .await
+        .map_err(|err| {
+            error!("replayed request failed: {}", err);
+            anyhow!("replayed request {} failed: {}", recording.id, err)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        take_record_header, RecordedRequest, Recorder, MAX_RECORDED_BODY_BYTES, MAX_RECORDINGS,
+        RECORD_HEADER,
+    };
+    use hyper::{Body, Request};
+    use std::sync::atomic::Ordering;
+
+    fn recorder(dir: &std::path::Path) -> Recorder {
+        Recorder::new(
+            dir.to_path_buf(),
+            "secret".to_string(),
+            "./test_cases/main".to_string(),
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_request() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", uuid::Uuid::new_v4()));
+        let recorder = recorder(&dir);
+
+        let mut req = Request::builder()
+            .method("POST")
+            .uri("http://localhost/hello?name=foo")
+            .header(RECORD_HEADER, "secret")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"name":"bar"}"#))
+            .unwrap();
+        let record_header = take_record_header(&mut req);
+        assert!(recorder.should_record(record_header.as_ref()));
+        assert!(!req.headers().contains_key(RECORD_HEADER));
+        assert!(!recorder.should_record(Some(&"1".parse().unwrap())));
+        assert!(!recorder.should_record(None));
+
+        let (req, id) = recorder.record(req).await.unwrap();
+        let id = id.unwrap();
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"name":"bar"}"#);
+
+        let recording = RecordedRequest::load(&dir.join(format!("{}.json", id))).unwrap();
+        assert_eq!(recording.method, "POST");
+        assert_eq!(
+            recording.headers,
+            vec![("content-type".to_string(), "application/json".to_string())]
+        );
+
+        let replayed = recording.to_request().unwrap();
+        assert_eq!(replayed.uri(), "http://localhost/hello?name=foo");
+        let body = hyper::body::to_bytes(replayed.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"name":"bar"}"#);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recording_limits() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", uuid::Uuid::new_v4()));
+        let recorder = recorder(&dir);
+
+        // large bodies are passed on whole, without being recorded
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+            vec![Ok(vec![0; MAX_RECORDED_BODY_BYTES]), Ok(vec![1; 16])];
+        let req = Request::new(Body::wrap_stream(deno_core::futures::stream::iter(chunks)));
+        let (req, id) = recorder.record(req).await.unwrap();
+        assert!(id.is_none());
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(body.len(), MAX_RECORDED_BODY_BYTES + 16);
+
+        // nor are requests once the record dir is full
+        recorder.recordings.store(MAX_RECORDINGS, Ordering::Relaxed);
+        let (_, id) = recorder
+            .record(Request::new(Body::from("hello")))
+            .await
+            .unwrap();
+        assert!(id.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
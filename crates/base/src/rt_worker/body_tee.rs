@@ -0,0 +1,129 @@
+use crate::utils::send_event_if_event_worker_available;
+use bytes::Bytes;
+use deno_core::futures::Stream;
+use event_worker::events::{
+    BodyTeeEvent, BodyTeeKind, EventMetadata, WorkerEventWithMetadata, WorkerEvents,
+};
+use hyper::Body;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+// Wraps a body so that the first `limit` bytes flowing through it are copied
+// aside and sent to the events worker once the body completes (or is dropped).
+// Chunks are forwarded as-is, the copy never applies backpressure to the
+// primary path.
+struct BodyTee {
+    inner: Body,
+    kind: Option<BodyTeeKind>,
+    buf: Vec<u8>,
+    limit: usize,
+    size: usize,
+    events_tx: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    metadata: EventMetadata,
+}
+
+impl BodyTee {
+    fn record(&mut self, chunk: &Bytes) {
+        self.size += chunk.len();
+        let remaining = self.limit.saturating_sub(self.buf.len());
+        if remaining > 0 {
+            let len = std::cmp::min(remaining, chunk.len());
+            self.buf.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(kind) = self.kind.take() {
+            send_event_if_event_worker_available(
+                Some(self.events_tx.clone()),
+                WorkerEvents::BodyTee(BodyTeeEvent {
+                    kind,
+                    body: String::from_utf8_lossy(&self.buf).into_owned(),
+                    size: self.size,
+                    truncated: self.size > self.buf.len(),
+                }),
+                self.metadata.clone(),
+            );
+        }
+    }
+}
+
+impl Stream for BodyTee {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match std::task::ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+            Some(Ok(chunk)) => {
+                this.record(&chunk);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            other => {
+                this.flush();
+                Poll::Ready(other)
+            }
+        }
+    }
+}
+
+impl Drop for BodyTee {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+pub fn tee_body(
+    body: Body,
+    kind: BodyTeeKind,
+    limit: u64,
+    events_tx: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    metadata: EventMetadata,
+) -> Body {
+    Body::wrap_stream(BodyTee {
+        inner: body,
+        kind: Some(kind),
+        buf: vec![],
+        limit: usize::try_from(limit).unwrap_or(usize::MAX),
+        size: 0,
+        events_tx,
+        metadata,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::tee_body;
+    use event_worker::events::{BodyTeeKind, EventMetadata, WorkerEventWithMetadata, WorkerEvents};
+    use hyper::Body;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_tee_body_truncates_copy() {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok("hello "), Ok("world")];
+        let body = Body::wrap_stream(deno_core::futures::stream::iter(chunks));
+
+        let teed = tee_body(
+            body,
+            BodyTeeKind::Request,
+            8,
+            events_tx,
+            EventMetadata::default(),
+        );
+
+        // the primary path receives the full body
+        let bytes = hyper::body::to_bytes(teed).await.unwrap();
+        assert_eq!(bytes, "hello world");
+
+        let event = events_rx.recv().await.unwrap();
+        match event.event {
+            WorkerEvents::BodyTee(e) => {
+                assert_eq!(e.body, "hello wo");
+                assert_eq!(e.size, 11);
+                assert!(e.truncated);
+            }
+            _ => panic!("unexpected event"),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{bail, Context, Error};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Per-service cgroups (v2) user worker threads are moved into, so the kernel
+// enforces their CPU share on top of the V8 heap and CPU time limits.
+//
+// Worker threads live in the runtime process, so the service cgroups are
+// threaded children of the runtime's own cgroup (which must be delegated to the
+// runtime, eg: `Delegate=yes` under systemd). Only threaded controllers can be
+// used in a threaded subtree: `cpu.weight` always applies, `memory.max` only
+// where the kernel exposes the memory controller to the service cgroup. When
+// cgroups are unavailable, workers run as before.
+
+pub(crate) const CGROUP2_MOUNT: &str = "/sys/fs/cgroup";
+const SERVICE_CGROUP_PREFIX: &str = "edge-svc-";
+// kernel default, see cpu.weight in the cgroup v2 docs
+pub const DEFAULT_CPU_WEIGHT: u64 = 100;
+const MAX_CPU_WEIGHT: u64 = 10000;
+
+static WORKER_CGROUPS: OnceLock<WorkerCgroups> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceCgroupLimits {
+    // relative CPU share among services (1-10000)
+    pub cpu_weight: u64,
+    pub memory_limit_mb: u64,
+}
+
+pub struct WorkerCgroups {
+    root: PathBuf,
+}
+
+fn write(path: &Path, value: &str) -> Result<(), Error> {
+    fs::write(path, value).with_context(|| format!("failed to write {}", path.display()))
+}
+
+// `0::/system.slice/edge-runtime.service` -> `/system.slice/edge-runtime.service`
+pub(crate) fn parse_own_cgroup(proc_cgroup: &str) -> Option<&str> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+}
+
+pub(crate) fn service_cgroup_name(service_path: &str) -> String {
+    let digest = Sha256::digest(service_path.as_bytes());
+    let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", SERVICE_CGROUP_PREFIX, hash)
+}
+
+impl WorkerCgroups {
+    pub fn open(root: PathBuf) -> Result<Self, Error> {
+        let controllers = fs::read_to_string(root.join("cgroup.controllers"))
+            .with_context(|| format!("{} is not a cgroup v2 directory", root.display()))?;
+        if !controllers.split_whitespace().any(|c| c == "cpu") {
+            bail!("cpu controller isn't available in {}", root.display());
+        }
+        Ok(Self { root })
+    }
+
+    // Cgroup of the running process, under the cgroup v2 mount
+    pub fn open_own() -> Result<Self, Error> {
+        let proc_cgroup = fs::read_to_string("/proc/self/cgroup")
+            .context("failed to read the cgroup of the runtime")?;
+        let Some(own) = parse_own_cgroup(&proc_cgroup) else {
+            bail!("the runtime isn't in a cgroup v2 hierarchy");
+        };
+        Self::open(Path::new(CGROUP2_MOUNT).join(own.trim_start_matches('/')))
+    }
+
+    fn service_cgroup(&self, service_path: &str) -> Result<PathBuf, Error> {
+        let dir = self.root.join(service_cgroup_name(service_path));
+        if !dir.exists() {
+            match fs::create_dir(&dir) {
+                Ok(_) => {}
+                // created by another worker of the service
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(err).with_context(|| format!("failed to create {}", dir.display()))
+                }
+            }
+        }
+        let cgroup_type = fs::read_to_string(dir.join("cgroup.type")).unwrap_or_default();
+        if cgroup_type.trim() != "threaded" {
+            write(&dir.join("cgroup.type"), "threaded")?;
+        }
+        // only possible once the runtime's cgroup has a threaded child, as it
+        // has processes of its own
+        let subtree_control = self.root.join("cgroup.subtree_control");
+        let enabled = fs::read_to_string(&subtree_control).unwrap_or_default();
+        if !enabled.split_whitespace().any(|c| c == "cpu") {
+            write(&subtree_control, "+cpu")?;
+        }
+        Ok(dir)
+    }
+
+    // Moves the calling thread into the cgroup of the service
+    pub fn join(&self, service_path: &str, limits: ServiceCgroupLimits) -> Result<(), Error> {
+        let dir = self.service_cgroup(service_path)?;
+
+        let weight = limits.cpu_weight.clamp(1, MAX_CPU_WEIGHT);
+        write(&dir.join("cpu.weight"), &weight.to_string())?;
+        let memory_max = dir.join("memory.max");
+        if memory_max.exists() {
+            write(
+                &memory_max,
+                &(limits.memory_limit_mb * 1024 * 1024).to_string(),
+            )?;
+        }
+
+        write(
+            &dir.join("cgroup.threads"),
+            &current_thread_id().to_string(),
+        )?;
+        debug!(
+            "moved worker thread of {} into {}",
+            service_path,
+            dir.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn current_thread_id() -> libc::c_long {
+    unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_thread_id() -> u64 {
+    0
+}
+
+// Places user worker threads into per-service cgroups from now on. Falls back to
+// running without them (with a warning) if cgroups can't be used.
+pub fn enable_worker_cgroups(root: Option<PathBuf>) {
+    if !cfg!(target_os = "linux") {
+        warn!("worker cgroups are only supported on linux");
+        return;
+    }
+    let result = match root {
+        Some(root) => WorkerCgroups::open(root),
+        None => WorkerCgroups::open_own(),
+    };
+    match result {
+        Ok(cgroups) => {
+            info!(
+                "user workers are placed in cgroups under {}",
+                cgroups.root.display()
+            );
+            let _ = WORKER_CGROUPS.set(cgroups);
+        }
+        Err(err) => warn!("worker cgroups are disabled: {:#}", err),
+    }
+}
+
+// Called on the worker's thread
+pub fn join_service_cgroup(service_path: &str, limits: ServiceCgroupLimits) {
+    let Some(cgroups) = WORKER_CGROUPS.get() else {
+        return;
+    };
+    if let Err(err) = cgroups.join(service_path, limits) {
+        warn!(
+            "failed to place worker of {} in its cgroup: {:#}",
+            service_path, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_own_cgroup, service_cgroup_name};
+
+    #[test]
+    fn test_parse_own_cgroup() {
+        assert_eq!(
+            parse_own_cgroup("0::/system.slice/edge-runtime.service\n"),
+            Some("/system.slice/edge-runtime.service")
+        );
+        // cgroup v1 only
+        assert_eq!(parse_own_cgroup("12:cpu,cpuacct:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn test_service_cgroup_name() {
+        let name = service_cgroup_name("./services/hello");
+        assert!(name.starts_with("edge-svc-"));
+        assert_eq!(name.len(), "edge-svc-".len() + 16);
+        assert_eq!(name, service_cgroup_name("./services/hello"));
+        assert_ne!(name, service_cgroup_name("./services/other"));
+    }
+}
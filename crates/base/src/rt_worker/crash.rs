@@ -0,0 +1,159 @@
+use anyhow::Error;
+use deno_core::serde_json;
+use event_worker::events::{EventMetadata, WorkerCrashedEvent};
+use log::error;
+use serde::Serialize;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+static INSTALL_HOOK: Once = Once::new();
+static CRASH_REPORT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+thread_local! {
+    // backtrace of the last panic on this thread, captured by the panic hook
+    static LAST_PANIC_BACKTRACE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+// Captures a backtrace for every panic, so it can be reported once the panic is
+// caught by the worker thread. The previous hook still runs.
+pub fn install_panic_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let prev_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::force_capture().to_string();
+            LAST_PANIC_BACKTRACE.with(|last| *last.borrow_mut() = Some(backtrace));
+            prev_hook(info);
+        }));
+    });
+}
+
+// Crash reports are only written when a directory is configured
+pub fn set_crash_report_dir(dir: PathBuf) {
+    if CRASH_REPORT_DIR.set(dir).is_err() {
+        error!("crash report directory is already set");
+    }
+}
+
+pub fn crash_report_dir() -> Option<&'static Path> {
+    CRASH_REPORT_DIR.get().map(PathBuf::as_path)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+    pub id: Uuid,
+    // unix timestamp (ms)
+    pub timestamp: u64,
+    pub thread_name: String,
+    pub worker_key: Option<Uuid>,
+    pub service_path: Option<String>,
+    pub execution_id: Option<Uuid>,
+    pub message: String,
+    pub backtrace: String,
+}
+
+impl CrashReport {
+    // Builds a report for a panic caught on the current thread
+    pub fn from_panic(
+        payload: &(dyn Any + Send),
+        thread_name: String,
+        worker_key: Option<Uuid>,
+        metadata: &EventMetadata,
+    ) -> Self {
+        let backtrace = LAST_PANIC_BACKTRACE
+            .with(|last| last.borrow_mut().take())
+            .unwrap_or_default();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            id: Uuid::new_v4(),
+            timestamp,
+            thread_name,
+            worker_key,
+            service_path: metadata.service_path.clone(),
+            execution_id: metadata.execution_id,
+            message: panic_message(payload),
+            backtrace,
+        }
+    }
+
+    pub fn write_to(&self, dir: &Path) -> Result<PathBuf, Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("crash-{}-{}.json", self.timestamp, self.id));
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+
+    // Writes the report to the configured crash report directory (if any) and
+    // converts it to a worker crash event
+    pub fn into_event(self) -> WorkerCrashedEvent {
+        let report_path = CRASH_REPORT_DIR
+            .get()
+            .and_then(|dir| match self.write_to(dir) {
+                Ok(path) => Some(path.to_string_lossy().to_string()),
+                Err(err) => {
+                    error!("failed to write crash report: {}", err);
+                    None
+                }
+            });
+
+        WorkerCrashedEvent {
+            message: self.message,
+            backtrace: self.backtrace,
+            report_path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{install_panic_hook, CrashReport};
+    use event_worker::events::EventMetadata;
+    use std::panic;
+
+    #[test]
+    fn test_crash_report_from_panic() {
+        install_panic_hook();
+        let payload = panic::catch_unwind(|| panic!("worker exploded")).unwrap_err();
+
+        let report = CrashReport::from_panic(
+            payload.as_ref(),
+            "sb-iso-test".to_string(),
+            None,
+            &EventMetadata {
+                service_path: Some("./hello".to_string()),
+                execution_id: None,
+                ..Default::default()
+            },
+        );
+        assert_eq!(report.message, "worker exploded");
+        assert!(!report.backtrace.is_empty());
+
+        let dir = std::env::temp_dir().join(format!("crash-{}", report.id));
+        let path = report.write_to(&dir).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(written.contains(r#""servicePath": "./hello""#));
+        assert!(written.contains(r#""message": "worker exploded""#));
+    }
+}
@@ -0,0 +1,133 @@
+use deno_core::futures::stream::{self, StreamExt};
+use hyper::header::HeaderValue;
+use hyper::{Body, Request};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+// relative deadline (ms) set by upstreams
+pub const DEADLINE_MS_HEADER: &str = "x-deadline-ms";
+// absolute deadline (unix ms) derived from `x-deadline-ms` when the request enters the runtime
+pub const DEADLINE_AT_HEADER: &str = "x-deadline-at";
+// how long an isolate may keep running without yielding once a request missed
+// its deadline, before the supervisor terminates it
+pub const DEADLINE_GRACE: Duration = Duration::from_millis(100);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn header_u64(req: &Request<Body>, name: &str) -> Option<u64> {
+    req.headers().get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+// Converts the inbound relative deadline (or the configured default) into an
+// absolute one, so it stays accurate while the request is forwarded between workers.
+// Any `x-deadline-at` sent by the client is dropped.
+pub fn apply_inbound_deadline(req: &mut Request<Body>, default_deadline_ms: Option<u64>) {
+    let maybe_deadline_ms = header_u64(req, DEADLINE_MS_HEADER).or(default_deadline_ms);
+    let headers = req.headers_mut();
+    headers.remove(DEADLINE_AT_HEADER);
+
+    if let Some(deadline_ms) = maybe_deadline_ms {
+        let deadline_at = now_ms().saturating_add(deadline_ms);
+        headers.insert(DEADLINE_AT_HEADER, HeaderValue::from(deadline_at));
+    }
+}
+
+// Returns the instant a request must be answered by and its absolute deadline
+pub fn request_deadline(req: &Request<Body>) -> Option<(Instant, u64)> {
+    let deadline_at = header_u64(req, DEADLINE_AT_HEADER)?;
+    let remaining = Duration::from_millis(deadline_at.saturating_sub(now_ms()));
+    Some((Instant::now() + remaining, deadline_at))
+}
+
+// Ends the response body with an error once the deadline passes, calling
+// `on_exceeded` if it was still being streamed
+pub fn limit_body_to_deadline<F>(body: Body, deadline: Instant, on_exceeded: F) -> Body
+where
+    F: FnOnce() + Send + 'static,
+{
+    let sleep = Box::pin(tokio::time::sleep_until(deadline));
+    Body::wrap_stream(stream::unfold(
+        Some((body, sleep, on_exceeded)),
+        |state| async move {
+            let (mut body, mut sleep, on_exceeded) = state?;
+            tokio::select! {
+                chunk = body.next() => {
+                    let chunk = chunk?.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+                    Some((chunk, Some((body, sleep, on_exceeded))))
+                }
+                _ = &mut sleep => {
+                    on_exceeded();
+                    let err = io::Error::new(io::ErrorKind::TimedOut, "request deadline exceeded");
+                    Some((Err(err), None))
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply_inbound_deadline, limit_body_to_deadline, request_deadline, DEADLINE_AT_HEADER,
+    };
+    use hyper::{Body, Request};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_inbound_deadline() {
+        let mut req = Request::builder()
+            .header("x-deadline-ms", "250")
+            .header(DEADLINE_AT_HEADER, "1")
+            .body(Body::empty())
+            .unwrap();
+        apply_inbound_deadline(&mut req, Some(10_000));
+
+        let (deadline, _) = request_deadline(&req).unwrap();
+        let remaining = deadline - tokio::time::Instant::now();
+        assert!(remaining.as_millis() <= 250 && remaining.as_millis() > 200);
+
+        // requests without a deadline are left alone
+        let mut req = Request::builder().body(Body::empty()).unwrap();
+        apply_inbound_deadline(&mut req, None);
+        assert!(request_deadline(&req).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_body_deadline() {
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let on_exceeded = {
+            let exceeded = exceeded.clone();
+            move || exceeded.store(true, Ordering::Relaxed)
+        };
+
+        // bodies sent in time are left alone
+        let body = limit_body_to_deadline(
+            Body::from("hello"),
+            Instant::now() + Duration::from_secs(5),
+            on_exceeded.clone(),
+        );
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello");
+        assert!(!exceeded.load(Ordering::Relaxed));
+
+        // a body still streaming at the deadline is cut off
+        let (mut tx, body) = Body::channel();
+        tx.try_send_data("partial".into()).unwrap();
+        let body = limit_body_to_deadline(
+            body,
+            Instant::now() + Duration::from_millis(50),
+            on_exceeded,
+        );
+        assert!(hyper::body::to_bytes(body).await.is_err());
+        assert!(exceeded.load(Ordering::Relaxed));
+        drop(tx);
+    }
+}
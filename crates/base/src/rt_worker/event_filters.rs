@@ -0,0 +1,511 @@
+use crate::rt_worker::events_supervisor::EventsMetrics;
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json;
+use event_worker::events::{LogLevel, WorkerEventWithMetadata, WorkerEvents};
+use rand::Rng;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+
+const EVENT_TYPES: &[&str] = &[
+    "Boot",
+    "BootFailure",
+    "UncaughtException",
+    "UncaughtError",
+    "Shutdown",
+    "EventLoopCompleted",
+    "Log",
+    "BodyTee",
+    "DeadlineExceeded",
+    "WorkerCrashed",
+    "WebhookDelivery",
+    "LogQuotaExceeded",
+    "SlowRequest",
+    "ResourceLeak",
+    "ServiceStateChanged",
+];
+
+fn event_type(event: &WorkerEvents) -> &'static str {
+    match event {
+        WorkerEvents::Boot(_) => "Boot",
+        WorkerEvents::BootFailure(_) => "BootFailure",
+        WorkerEvents::UncaughtException(_) => "UncaughtException",
+        WorkerEvents::UncaughtError(_) => "UncaughtError",
+        WorkerEvents::Shutdown(_) => "Shutdown",
+        WorkerEvents::EventLoopCompleted(_) => "EventLoopCompleted",
+        WorkerEvents::Log(_) => "Log",
+        WorkerEvents::BodyTee(_) => "BodyTee",
+        WorkerEvents::DeadlineExceeded(_) => "DeadlineExceeded",
+        WorkerEvents::WorkerCrashed(_) => "WorkerCrashed",
+        WorkerEvents::WebhookDelivery(_) => "WebhookDelivery",
+        WorkerEvents::LogQuotaExceeded(_) => "LogQuotaExceeded",
+        WorkerEvents::SlowRequest(_) => "SlowRequest",
+        WorkerEvents::ResourceLeak(_) => "ResourceLeak",
+        WorkerEvents::ServiceStateChanged(_) => "ServiceStateChanged",
+    }
+}
+
+fn level_rank(level: &LogLevel) -> u8 {
+    match level {
+        LogLevel::Debug => 0,
+        LogLevel::Info => 1,
+        LogLevel::Warning => 2,
+        LogLevel::Error => 3,
+    }
+}
+
+fn parse_level(name: &str) -> Result<u8, Error> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "debug" => 0,
+        "info" => 1,
+        "warn" | "warning" => 2,
+        "error" => 3,
+        _ => bail!("unknown log level {:?}", name),
+    })
+}
+
+// `*` matches any run of characters (including `/`), `?` a single one
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it's matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(f64),
+    Op(&'static str),
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!("unterminated string in event filter"),
+                    Some('"') => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(c) => {
+                        value.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            match number.parse() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => bail!("invalid number {:?} in event filter", number),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let next = chars.get(i + 1).copied();
+            let (op, len) = match (c, next) {
+                ('=', Some('=')) => ("==", 2),
+                ('!', Some('=')) => ("!=", 2),
+                ('>', Some('=')) => (">=", 2),
+                ('<', Some('=')) => ("<=", 2),
+                ('>', _) => (">", 1),
+                ('<', _) => ("<", 1),
+                ('~', _) => ("~", 1),
+                ('(', _) => ("(", 1),
+                (')', _) => (")", 1),
+                ('[', _) => ("[", 1),
+                (']', _) => ("]", 1),
+                (',', _) => (",", 1),
+                _ => bail!("unexpected {:?} in event filter", c),
+            };
+            tokens.push(Token::Op(op));
+            i += len;
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    // event types, matching any of them
+    Type(Vec<&'static str>),
+    // service path globs, matching any of them
+    Service(Vec<String>),
+    // level of logs, compared with `op`
+    Level(&'static str, u8),
+    Sample(f64),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_ident(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), Error> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            found => bail!("expected {:?} in event filter, found {:?}", op, found),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while self.eat_ident("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.unary()?;
+        while self.eat_ident("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.eat_ident("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek() == Some(&Token::Op("(")) {
+            self.pos += 1;
+            let expr = self.or()?;
+            self.expect_op(")")?;
+            return Ok(expr);
+        }
+        if self.eat_ident("sample") {
+            self.expect_op("(")?;
+            let rate = match self.next() {
+                Some(Token::Number(rate)) if (0.0..=1.0).contains(&rate) => rate,
+                found => bail!("expected a rate between 0 and 1, found {:?}", found),
+            };
+            self.expect_op(")")?;
+            return Ok(Expr::Sample(rate));
+        }
+        self.comparison()
+    }
+
+    // a single value, or a list of values after `in`
+    fn values(&mut self, op: &str) -> Result<Vec<String>, Error> {
+        let value = |token: Option<Token>| match token {
+            Some(Token::Ident(value)) | Some(Token::Str(value)) => Ok(value),
+            found => bail!("expected a value in event filter, found {:?}", found),
+        };
+        if op != "in" {
+            return Ok(vec![value(self.next())?]);
+        }
+        self.expect_op("[")?;
+        let mut values = vec![value(self.next())?];
+        while self.peek() == Some(&Token::Op(",")) {
+            self.pos += 1;
+            values.push(value(self.next())?);
+        }
+        self.expect_op("]")?;
+        Ok(values)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Error> {
+        let field = match self.next() {
+            Some(Token::Ident(field)) => field,
+            found => bail!("expected a field in event filter, found {:?}", found),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            Some(Token::Ident(ident)) if ident == "in" => "in",
+            found => bail!("expected an operator after {}, found {:?}", field, found),
+        };
+        let values = self.values(op)?;
+
+        let expr = match (field.as_str(), op) {
+            ("type", "==" | "!=" | "in") => Expr::Type(
+                values
+                    .iter()
+                    .map(|value| {
+                        EVENT_TYPES
+                            .iter()
+                            .find(|name| name.eq_ignore_ascii_case(value))
+                            .copied()
+                            .ok_or_else(|| anyhow!("unknown event type {:?}", value))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            ("service_path", "==" | "!=" | "~" | "in") => Expr::Service(values),
+            ("level", "==" | "!=" | ">=" | "<=" | ">" | "<") => {
+                return Ok(Expr::Level(op, parse_level(&values[0])?));
+            }
+            _ => bail!("{} can't be compared with {}", field, op),
+        };
+        Ok(match op {
+            "!=" => Expr::Not(Box::new(expr)),
+            _ => expr,
+        })
+    }
+}
+
+impl Expr {
+    fn eval(&self, event: &WorkerEventWithMetadata, roll: &mut impl FnMut() -> f64) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(event, roll) || b.eval(event, roll),
+            Expr::And(a, b) => a.eval(event, roll) && b.eval(event, roll),
+            Expr::Not(expr) => !expr.eval(event, roll),
+            Expr::Type(types) => types.contains(&event_type(&event.event)),
+            Expr::Service(globs) => event.metadata.service_path.as_ref().is_some_and(|path| {
+                globs
+                    .iter()
+                    .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+            }),
+            // only logs have a level
+            Expr::Level(op, level) => match &event.event {
+                WorkerEvents::Log(log) => {
+                    let rank = level_rank(&log.level);
+                    match *op {
+                        "==" => rank == *level,
+                        "!=" => rank != *level,
+                        ">=" => rank >= *level,
+                        "<=" => rank <= *level,
+                        ">" => rank > *level,
+                        _ => rank < *level,
+                    }
+                }
+                _ => false,
+            },
+            Expr::Sample(rate) => roll() < *rate,
+        }
+    }
+}
+
+// Expression selecting the events forwarded to a sink, eg:
+// `not (type == Log and level < warning) and service_path ~ "/home/deno/functions/*"`
+#[derive(Debug, Clone)]
+pub struct EventFilter(Expr);
+
+impl EventFilter {
+    pub fn parse(src: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {:?} in event filter", token);
+        }
+        Ok(Self(expr))
+    }
+
+    pub fn matches(&self, event: &WorkerEventWithMetadata) -> bool {
+        let mut rng = rand::thread_rng();
+        self.0.eval(event, &mut || rng.gen::<f64>())
+    }
+}
+
+// Filters of each sink, the events of sinks without one aren't filtered
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EventFiltersConfig {
+    pub events_worker: Option<String>,
+    // the listener of `TestRuntime`
+    pub listener: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventFilters {
+    pub events_worker: Option<EventFilter>,
+    pub listener: Option<EventFilter>,
+}
+
+impl EventFiltersConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn into_filters(self) -> Result<EventFilters, Error> {
+        let parse = |src: Option<String>| src.as_deref().map(EventFilter::parse).transpose();
+        Ok(EventFilters {
+            events_worker: parse(self.events_worker)?,
+            listener: parse(self.listener)?,
+        })
+    }
+}
+
+// Forwards the events matching `filter` to `next`, counting the others in the
+// events metrics
+pub fn filter_events(
+    next: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    filter: EventFilter,
+    metrics: EventsMetrics,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        while let Some(event) = events_rx.recv().await {
+            if filter.matches(&event) {
+                let _ = next.send(event);
+            } else {
+                metrics.filtered.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+    events_tx
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, EventFilter, EventFiltersConfig};
+    use event_worker::events::{
+        BootEvent, EventMetadata, LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents,
+    };
+
+    fn event(service_path: &str, event: WorkerEvents) -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event,
+            metadata: EventMetadata {
+                service_path: Some(service_path.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn log(service_path: &str, level: LogLevel) -> WorkerEventWithMetadata {
+        event(
+            service_path,
+            WorkerEvents::Log(LogEvent {
+                msg: "hello".to_string(),
+                level,
+            }),
+        )
+    }
+
+    fn eval(filter: &str, event: &WorkerEventWithMetadata, roll: f64) -> bool {
+        EventFilter::parse(filter)
+            .unwrap()
+            .0
+            .eval(event, &mut || roll)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"/functions/*", b"/functions/hello/index.ts"));
+        assert!(glob_match(b"*-v?", b"billing-v2"));
+        assert!(glob_match(b"a*b*c", b"axxbyybc"));
+        assert!(!glob_match(b"/functions/*", b"/other/hello"));
+        assert!(!glob_match(b"*-v?", b"billing-v"));
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let debug = log("/functions/hello", LogLevel::Debug);
+        let error = log("/functions/billing", LogLevel::Error);
+        let boot = event(
+            "/functions/hello",
+            WorkerEvents::Boot(BootEvent {
+                boot_time: 1,
+                modules: vec![],
+            }),
+        );
+
+        let quiet = "not (type == Log and level < warning)";
+        assert!(!eval(quiet, &debug, 0.0));
+        assert!(eval(quiet, &error, 0.0));
+        assert!(eval(quiet, &boot, 0.0));
+
+        let billing = r#"service_path ~ "*/billing" or type in [boot, BootFailure]"#;
+        assert!(eval(billing, &error, 0.0));
+        assert!(eval(billing, &boot, 0.0));
+        assert!(!eval(billing, &debug, 0.0));
+
+        // `and` binds tighter than `or`
+        let sampled = "level >= error or type == Log and sample(0.1)";
+        assert!(eval(sampled, &error, 0.9));
+        assert!(eval(sampled, &debug, 0.05));
+        assert!(!eval(sampled, &debug, 0.5));
+        assert!(!eval(sampled, &boot, 0.0));
+
+        for invalid in [
+            "type == Nope",
+            "level ~ info",
+            "sample(2)",
+            "type == Log and",
+            "(type == Log",
+            r#"service_path == "a"#,
+        ] {
+            assert!(EventFilter::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let filters = EventFiltersConfig::parse(r#"{ "eventsWorker": "type != Log" }"#)
+            .unwrap()
+            .into_filters()
+            .unwrap();
+        assert!(filters.listener.is_none());
+        assert!(!filters.events_worker.unwrap().matches(&debug));
+        assert!(
+            EventFiltersConfig::parse(r#"{ "eventsWorker": "type ==" }"#)
+                .unwrap()
+                .into_filters()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use crate::rt_worker::events_supervisor::EventsMetrics;
+use event_worker::events::{LogQuotaExceededEvent, WorkerEventWithMetadata, WorkerEvents};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+// Limits of each service, per minute
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventQuota {
+    pub events_per_minute: Option<u64>,
+    pub log_bytes_per_minute: Option<u64>,
+}
+
+impl EventQuota {
+    pub fn is_set(&self) -> bool {
+        self.events_per_minute.is_some() || self.log_bytes_per_minute.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct ServiceWindow {
+    started: Instant,
+    events: u64,
+    log_bytes: u64,
+    dropped: u64,
+}
+
+impl ServiceWindow {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            events: 0,
+            log_bytes: 0,
+            dropped: 0,
+        }
+    }
+}
+
+// Events tenant code can emit in a loop, lifecycle events (boot, shutdown,
+// crashes) are always delivered
+fn is_limited(event: &WorkerEvents) -> bool {
+    matches!(
+        event,
+        WorkerEvents::Log(_) | WorkerEvents::BodyTee(_) | WorkerEvents::WebhookDelivery(_)
+    )
+}
+
+#[derive(Debug)]
+struct EventQuotas {
+    quota: EventQuota,
+    windows: HashMap<String, ServiceWindow>,
+}
+
+impl EventQuotas {
+    fn new(quota: EventQuota) -> Self {
+        Self {
+            quota,
+            windows: HashMap::new(),
+        }
+    }
+
+    // Whether the event is within its service's quota. The first event dropped
+    // in a window is returned along with a `LogQuotaExceeded` event to emit.
+    fn admit(
+        &mut self,
+        event: &WorkerEventWithMetadata,
+        now: Instant,
+    ) -> (bool, Option<WorkerEventWithMetadata>) {
+        let Some(service_path) = &event.metadata.service_path else {
+            return (true, None);
+        };
+        if !is_limited(&event.event) {
+            return (true, None);
+        }
+
+        let window = self
+            .windows
+            .entry(service_path.clone())
+            .or_insert_with(|| ServiceWindow::new(now));
+        if now.duration_since(window.started) >= QUOTA_WINDOW {
+            *window = ServiceWindow::new(now);
+        }
+
+        let log_bytes = match &event.event {
+            WorkerEvents::Log(log) => log.msg.len() as u64,
+            _ => 0,
+        };
+        let over_events = self
+            .quota
+            .events_per_minute
+            .is_some_and(|max| window.events >= max);
+        let over_log_bytes = self
+            .quota
+            .log_bytes_per_minute
+            .is_some_and(|max| window.log_bytes + log_bytes > max);
+        if !over_events && !over_log_bytes {
+            window.events += 1;
+            window.log_bytes += log_bytes;
+            return (true, None);
+        }
+
+        window.dropped += 1;
+        if window.dropped > 1 {
+            return (false, None);
+        }
+        let exceeded = WorkerEventWithMetadata {
+            event: WorkerEvents::LogQuotaExceeded(LogQuotaExceededEvent {
+                events_per_minute: self.quota.events_per_minute,
+                log_bytes_per_minute: self.quota.log_bytes_per_minute,
+                // until the window ends
+                retry_after_ms: QUOTA_WINDOW
+                    .saturating_sub(now.duration_since(window.started))
+                    .as_millis() as u64,
+            }),
+            metadata: event.metadata.clone(),
+        };
+        (false, Some(exceeded))
+    }
+
+    // windows of services that stopped emitting
+    fn prune(&mut self, now: Instant) {
+        self.windows
+            .retain(|_, window| now.duration_since(window.started) < QUOTA_WINDOW);
+    }
+}
+
+// Drops the events of services over their quota before they reach the events
+// worker, counting them in the events metrics
+pub fn limit_events(
+    next: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    quota: EventQuota,
+    metrics: EventsMetrics,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        let mut quotas = EventQuotas::new(quota);
+        let mut last_prune = Instant::now();
+        while let Some(event) = events_rx.recv().await {
+            let now = Instant::now();
+            if now.duration_since(last_prune) >= QUOTA_WINDOW {
+                quotas.prune(now);
+                last_prune = now;
+            }
+            let (admitted, maybe_exceeded) = quotas.admit(&event, now);
+            if let Some(exceeded) = maybe_exceeded {
+                let _ = next.send(exceeded);
+            }
+            if admitted {
+                let _ = next.send(event);
+            } else {
+                metrics.quota_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+    events_tx
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventQuota, EventQuotas, QUOTA_WINDOW};
+    use event_worker::events::{
+        BootEvent, EventMetadata, LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents,
+    };
+    use std::time::{Duration, Instant};
+
+    fn event(service_path: &str, event: WorkerEvents) -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event,
+            metadata: EventMetadata {
+                service_path: Some(service_path.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn log(service_path: &str, msg: &str) -> WorkerEventWithMetadata {
+        event(
+            service_path,
+            WorkerEvents::Log(LogEvent {
+                msg: msg.to_string(),
+                level: LogLevel::Info,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_event_quotas() {
+        let mut quotas = EventQuotas::new(EventQuota {
+            events_per_minute: Some(3),
+            log_bytes_per_minute: Some(10),
+        });
+        let now = Instant::now();
+
+        assert!(quotas.admit(&log("a", "12345"), now).0);
+        assert!(quotas.admit(&log("a", "12345"), now).0);
+        // over the log bytes, reported once
+        let (admitted, exceeded) = quotas.admit(&log("a", "1"), now);
+        assert!(!admitted);
+        let exceeded = exceeded.unwrap();
+        assert!(matches!(
+            exceeded.event,
+            WorkerEvents::LogQuotaExceeded(ref e) if e.retry_after_ms == 60_000
+        ));
+        assert_eq!(exceeded.metadata.service_path.as_deref(), Some("a"));
+        assert!(matches!(quotas.admit(&log("a", "1"), now), (false, None)));
+
+        // lifecycle events and other services aren't affected
+        let boot = event(
+            "a",
+            WorkerEvents::Boot(BootEvent {
+                boot_time: 1,
+                modules: vec![],
+            }),
+        );
+        assert!(quotas.admit(&boot, now).0);
+        assert!(quotas.admit(&log("b", "12345"), now).0);
+
+        // a new window starts after a minute
+        let later = now + QUOTA_WINDOW + Duration::from_millis(1);
+        assert!(quotas.admit(&log("a", "1"), later).0);
+        assert!(quotas.admit(&log("a", "1"), later).0);
+        assert!(quotas.admit(&log("a", "1"), later).0);
+        // over the events
+        let (admitted, exceeded) = quotas.admit(&log("a", "1"), later);
+        assert!(!admitted && exceeded.is_some());
+
+        quotas.prune(later);
+        assert_eq!(quotas.windows.len(), 1);
+    }
+}
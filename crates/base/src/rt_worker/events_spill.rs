@@ -0,0 +1,243 @@
+use crate::rt_worker::events_supervisor::EventsMetrics;
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use event_worker::events::WorkerEventWithMetadata;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+pub const DEFAULT_EVENTS_SPILL_MAX_MB: u64 = 256;
+// the spill is split in this many segments, the oldest one is evicted when full
+const SEGMENTS: u64 = 8;
+
+#[derive(Debug, Clone)]
+pub struct EventsSpillOpts {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    events: u64,
+}
+
+// Bounded on-disk ring buffer of events (JSON lines in segment files), holding the
+// events that don't fit in memory while the events worker is down or lagging
+// behind. Segments left by a previous process are replayed.
+pub struct EventsSpill {
+    opts: EventsSpillOpts,
+    // oldest first, the last one is written to
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+    next_seq: u64,
+    metrics: EventsMetrics,
+}
+
+fn segment_seq(path: &std::path::Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("events-")?
+        .strip_suffix(".jsonl")?
+        .parse()
+        .ok()
+}
+
+impl EventsSpill {
+    pub fn open(opts: EventsSpillOpts, metrics: EventsMetrics) -> Result<Self, Error> {
+        fs::create_dir_all(&opts.dir)
+            .with_context(|| format!("failed to create {}", opts.dir.display()))?;
+
+        let mut found = vec![];
+        for entry in fs::read_dir(&opts.dir)? {
+            let path = entry?.path();
+            if let Some(seq) = segment_seq(&path) {
+                found.push((seq, path));
+            }
+        }
+        found.sort();
+
+        let mut segments = VecDeque::new();
+        for (_, path) in &found {
+            let contents = fs::read(path)?;
+            segments.push_back(Segment {
+                path: path.clone(),
+                bytes: contents.len() as u64,
+                events: contents.iter().filter(|b| **b == b'\n').count() as u64,
+            });
+        }
+        let spill = Self {
+            next_seq: found.last().map(|(seq, _)| seq + 1).unwrap_or(0),
+            opts,
+            segments,
+            writer: None,
+            metrics,
+        };
+        spill.update_metrics();
+        Ok(spill)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.events as usize).sum()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.bytes).sum()
+    }
+
+    fn update_metrics(&self) {
+        self.metrics
+            .spilled
+            .store(self.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .spill_bytes
+            .store(self.bytes(), Ordering::Relaxed);
+    }
+
+    fn segment_max_bytes(&self) -> u64 {
+        (self.opts.max_bytes / SEGMENTS).max(1)
+    }
+
+    pub fn push(&mut self, event: &WorkerEventWithMetadata) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        let full = self
+            .segments
+            .back()
+            .map(|s| s.bytes >= self.segment_max_bytes())
+            .unwrap_or(true);
+        if self.writer.is_none() || full {
+            let path = self
+                .opts
+                .dir
+                .join(format!("events-{:020}.jsonl", self.next_seq));
+            self.next_seq += 1;
+            self.writer = Some(OpenOptions::new().create(true).append(true).open(&path)?);
+            self.segments.push_back(Segment {
+                path,
+                bytes: 0,
+                events: 0,
+            });
+        }
+        self.writer.as_mut().unwrap().write_all(&line)?;
+        let segment = self.segments.back_mut().unwrap();
+        segment.bytes += line.len() as u64;
+        segment.events += 1;
+
+        // the oldest events go first when the spill is full
+        while self.bytes() > self.opts.max_bytes && self.segments.len() > 1 {
+            let evicted = self.segments.pop_front().unwrap();
+            let _ = fs::remove_file(&evicted.path);
+            self.metrics
+                .dropped
+                .fetch_add(evicted.events, Ordering::Relaxed);
+        }
+        self.update_metrics();
+        Ok(())
+    }
+
+    // Takes the events of the oldest segment, removing it from disk
+    pub fn pop_segment(&mut self) -> Result<Vec<WorkerEventWithMetadata>, Error> {
+        let Some(segment) = self.segments.pop_front() else {
+            return Ok(vec![]);
+        };
+        if self.segments.is_empty() {
+            // the next event starts a new segment
+            self.writer = None;
+        }
+        let contents = fs::read_to_string(&segment.path)?;
+        fs::remove_file(&segment.path)?;
+        self.update_metrics();
+
+        let mut events = vec![];
+        for line in contents.lines() {
+            // a line can be cut short if the process died while writing it
+            match serde_json::from_str(line) {
+                Ok(event) => events.push(event),
+                Err(_) => {
+                    self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventsSpill, EventsSpillOpts};
+    use crate::rt_worker::events_supervisor::EventsMetrics;
+    use deno_core::serde_json;
+    use event_worker::events::{
+        EventMetadata, LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents,
+    };
+
+    fn log_event(msg: &str) -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: msg.to_string(),
+                level: LogLevel::Info,
+            }),
+            metadata: EventMetadata::default(),
+        }
+    }
+
+    fn msgs(events: Vec<WorkerEventWithMetadata>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event.event {
+                WorkerEvents::Log(e) => e.msg,
+                _ => panic!("unexpected event"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_events_spill() {
+        let dir = std::env::temp_dir().join(format!("events-spill-{}", uuid::Uuid::new_v4()));
+        let line_bytes = serde_json::to_vec(&log_event("a")).unwrap().len() as u64 + 1;
+        let opts = EventsSpillOpts {
+            dir: dir.clone(),
+            // 2 events per segment, 16 events in total
+            max_bytes: line_bytes * 16,
+        };
+        let metrics = EventsMetrics::default();
+
+        let mut spill = EventsSpill::open(opts.clone(), metrics.clone()).unwrap();
+        for msg in ["a", "b", "c"] {
+            spill.push(&log_event(msg)).unwrap();
+        }
+        assert_eq!(spill.len(), 3);
+        assert_eq!(metrics.snapshot().spilled, 3);
+        assert_eq!(metrics.snapshot().spill_bytes, line_bytes * 3);
+
+        // persisted across processes
+        drop(spill);
+        let mut spill = EventsSpill::open(opts.clone(), metrics.clone()).unwrap();
+        assert_eq!(spill.len(), 3);
+        assert_eq!(msgs(spill.pop_segment().unwrap()), vec!["a", "b"]);
+        spill.push(&log_event("d")).unwrap();
+        assert_eq!(msgs(spill.pop_segment().unwrap()), vec!["c"]);
+        assert_eq!(msgs(spill.pop_segment().unwrap()), vec!["d"]);
+        assert!(spill.is_empty());
+        assert!(spill.pop_segment().unwrap().is_empty());
+
+        // the oldest segments are evicted when full
+        for i in 0..20 {
+            spill.push(&log_event(&(i % 10).to_string())).unwrap();
+        }
+        assert_eq!(spill.len(), 16);
+        assert_eq!(metrics.snapshot().dropped, 4);
+        assert_eq!(msgs(spill.pop_segment().unwrap()), vec!["4", "5"]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::node::{node_identity, NodeIdentity};
+use crate::rt_worker::events_spill::{EventsSpill, EventsSpillOpts};
+use crate::rt_worker::main_worker_supervisor::Backoff;
+use crate::rt_worker::worker_ctx::create_worker;
+use anyhow::{anyhow, Error};
+use event_worker::events::WorkerEventWithMetadata;
+use event_worker::AcceptedEvents;
+use log::error;
+use sb_eszip::module_loader::EszipPayloadKind;
+use sb_worker_context::essentials::{
+    EventWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// max number of events held while the events worker is down or lagging behind
+pub const EVENTS_BUFFER_CAP: usize = 10_000;
+// an events worker not accepting pending events for this long is restarted
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default)]
+pub struct EventsMetrics {
+    pub dropped: Arc<AtomicU64>,
+    pub restarts: Arc<AtomicU64>,
+    pub buffered: Arc<AtomicU64>,
+    // events of services over their quota (see event_quotas)
+    pub quota_dropped: Arc<AtomicU64>,
+    // events left out by the filter of a sink (see event_filters)
+    pub filtered: Arc<AtomicU64>,
+    // events (and their size) spilled to disk, see events_spill
+    pub spilled: Arc<AtomicU64>,
+    pub spill_bytes: Arc<AtomicU64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsMetricsSnapshot {
+    pub dropped: u64,
+    pub restarts: u64,
+    pub buffered: u64,
+    pub quota_dropped: u64,
+    pub filtered: u64,
+    pub spilled: u64,
+    pub spill_bytes: u64,
+    #[serde(flatten)]
+    pub node: NodeIdentity,
+}
+
+impl EventsMetrics {
+    pub fn snapshot(&self) -> EventsMetricsSnapshot {
+        EventsMetricsSnapshot {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            restarts: self.restarts.load(Ordering::Relaxed),
+            buffered: self.buffered.load(Ordering::Relaxed),
+            quota_dropped: self.quota_dropped.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            spill_bytes: self.spill_bytes.load(Ordering::Relaxed),
+            node: node_identity(),
+        }
+    }
+}
+
+// Bounded queue of events, dropping the oldest ones when full. With a spill,
+// events that don't fit in memory are written to disk instead.
+struct EventsBuffer {
+    events: VecDeque<WorkerEventWithMetadata>,
+    cap: usize,
+    metrics: EventsMetrics,
+    spill: Option<EventsSpill>,
+}
+
+impl EventsBuffer {
+    fn new(cap: usize, metrics: EventsMetrics) -> Self {
+        Self {
+            events: VecDeque::new(),
+            cap,
+            metrics,
+            spill: None,
+        }
+    }
+
+    fn with_spill(mut self, spill: EventsSpill) -> Self {
+        self.spill = Some(spill);
+        self
+    }
+
+    fn push_back(&mut self, event: WorkerEventWithMetadata) {
+        if let Some(spill) = &mut self.spill {
+            // once events are spilled, newer ones follow them to keep the order
+            if self.events.len() >= self.cap || !spill.is_empty() {
+                match spill.push(&event) {
+                    Ok(()) => return,
+                    Err(err) => error!("failed to spill event: {}", err),
+                }
+            }
+        }
+        if self.events.len() >= self.cap {
+            self.events.pop_front();
+            self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.events.push_back(event);
+    }
+
+    // puts back events that have to be replayed ahead of newer ones
+    fn requeue(&mut self, events: VecDeque<WorkerEventWithMetadata>) {
+        for event in events.into_iter().rev() {
+            // kept in memory with a spill, there are at most `cap` of them
+            if self.spill.is_none() && self.events.len() >= self.cap {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.events.push_front(event);
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<WorkerEventWithMetadata> {
+        if self.events.is_empty() {
+            if let Some(spill) = &mut self.spill {
+                match spill.pop_segment() {
+                    Ok(events) => self.events.extend(events),
+                    Err(err) => error!("failed to read spilled events: {}", err),
+                }
+            }
+        }
+        self.events.pop_front()
+    }
+
+    fn push_front(&mut self, event: WorkerEventWithMetadata) {
+        self.events.push_front(event);
+    }
+
+    fn len(&self) -> usize {
+        self.events.len() + self.spill.as_ref().map(|s| s.len()).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventsWorkerOpts {
+    pub service_path: PathBuf,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    pub maybe_entrypoint: Option<String>,
+    pub spill: Option<EventsSpillOpts>,
+}
+
+struct RunningEventsWorker {
+    events_tx: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    // closes when the worker thread exits
+    worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    accepted: AcceptedEvents,
+}
+
+impl RunningEventsWorker {
+    fn is_dead(&self) -> bool {
+        self.events_tx.is_closed() || self.worker_req_tx.is_closed()
+    }
+}
+
+async fn boot(opts: &EventsWorkerOpts) -> Result<RunningEventsWorker, Error> {
+    let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    let accepted = AcceptedEvents::default();
+
+    let mut service_path = opts.service_path.clone();
+    let mut maybe_eszip = None;
+    if let Some(ext) = opts.service_path.extension() {
+        if ext == "eszip" {
+            service_path = opts.service_path.parent().unwrap().to_path_buf();
+            maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(
+                &opts.service_path,
+            )?));
+        }
+    }
+
+    let worker_req_tx = create_worker(WorkerContextInitOpts {
+        service_path,
+        no_module_cache: opts.no_module_cache,
+        import_map_path: opts.import_map_path.clone(),
+        env_vars: std::env::vars().collect(),
+        events_rx: Some(events_rx),
+        maybe_eszip,
+        maybe_entrypoint: opts.maybe_entrypoint.clone(),
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::EventsWorker(EventWorkerRuntimeOpts {
+            accepted_events: Some(accepted.clone()),
+        }),
+    })
+    .await
+    .map_err(|err| anyhow!("events worker boot error: {}", err))?;
+
+    Ok(RunningEventsWorker {
+        events_tx,
+        worker_req_tx,
+        accepted,
+    })
+}
+
+// Relays events to the events worker. Events are buffered (up to a cap) while the
+// worker is down, and events it didn't accept before dying are replayed once it's
+// restarted.
+async fn supervise(
+    opts: EventsWorkerOpts,
+    worker: RunningEventsWorker,
+    mut buffer: EventsBuffer,
+    mut events_rx: mpsc::UnboundedReceiver<WorkerEventWithMetadata>,
+    metrics: EventsMetrics,
+) {
+    let mut maybe_worker = Some(worker);
+    // events sent to the worker, but not accepted yet
+    let mut in_flight: VecDeque<WorkerEventWithMetadata> = VecDeque::new();
+    let mut acked: u64 = 0;
+    let mut last_progress = Instant::now();
+    let mut backoff = Backoff::new(RESTART_BACKOFF_INITIAL, RESTART_BACKOFF_MAX);
+    let mut restart_at: Option<Instant> = None;
+
+    let mut check = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            maybe_event = events_rx.recv() => match maybe_event {
+                Some(event) => buffer.push_back(event),
+                // all producers are gone
+                None => break,
+            },
+            _ = check.tick() => {}
+        }
+
+        if let Some(worker) = &maybe_worker {
+            let accepted = worker.accepted.0.load(Ordering::Relaxed);
+            while acked < accepted && in_flight.pop_front().is_some() {
+                acked += 1;
+                last_progress = Instant::now();
+                backoff.reset();
+            }
+
+            let stalled = !in_flight.is_empty() && last_progress.elapsed() > STALL_TIMEOUT;
+            if worker.is_dead() || stalled {
+                let delay = backoff.next_delay();
+                error!(
+                    "events worker {} (replaying {} events, restarting in {:?})",
+                    if stalled { "stalled" } else { "exited" },
+                    in_flight.len(),
+                    delay
+                );
+                buffer.requeue(std::mem::take(&mut in_flight));
+                // dropping the sender lets a stalled worker finish once it catches up
+                maybe_worker = None;
+                restart_at = Some(Instant::now() + delay);
+            }
+        }
+
+        if maybe_worker.is_none() && restart_at.map(|t| t <= Instant::now()).unwrap_or(true) {
+            match boot(&opts).await {
+                Ok(worker) => {
+                    metrics.restarts.fetch_add(1, Ordering::Relaxed);
+                    maybe_worker = Some(worker);
+                    acked = 0;
+                    last_progress = Instant::now();
+                    restart_at = None;
+                }
+                Err(err) => {
+                    let delay = backoff.next_delay();
+                    error!("{} (retrying in {:?})", err, delay);
+                    restart_at = Some(Instant::now() + delay);
+                }
+            }
+        }
+
+        if let Some(worker) = &maybe_worker {
+            while in_flight.len() < EVENTS_BUFFER_CAP {
+                let Some(event) = buffer.pop_front() else {
+                    break;
+                };
+                if in_flight.is_empty() {
+                    last_progress = Instant::now();
+                }
+                in_flight.push_back(event.clone());
+                if let Err(mpsc::error::SendError(event)) = worker.events_tx.send(event) {
+                    in_flight.pop_back();
+                    buffer.push_front(event);
+                    break;
+                }
+            }
+        }
+
+        metrics
+            .buffered
+            .store((buffer.len() + in_flight.len()) as u64, Ordering::Relaxed);
+    }
+}
+
+// Boots the events worker and keeps it running. Returns the sender used to emit
+// events, which stays valid across restarts of the events worker.
+pub async fn start_events_worker_supervisor(
+    opts: EventsWorkerOpts,
+    metrics: EventsMetrics,
+) -> Result<mpsc::UnboundedSender<WorkerEventWithMetadata>, Error> {
+    let mut buffer = EventsBuffer::new(EVENTS_BUFFER_CAP, metrics.clone());
+    if let Some(spill_opts) = opts.spill.clone() {
+        buffer = buffer.with_spill(EventsSpill::open(spill_opts, metrics.clone())?);
+    }
+    // fail fast if the events worker can't boot at all
+    let worker = boot(&opts).await?;
+
+    let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(supervise(opts, worker, buffer, events_rx, metrics));
+
+    Ok(events_tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventsBuffer, EventsMetrics};
+    use crate::rt_worker::events_spill::{EventsSpill, EventsSpillOpts};
+    use event_worker::events::{
+        EventMetadata, LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents,
+    };
+    use std::collections::VecDeque;
+
+    fn log_event(msg: &str) -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: msg.to_string(),
+                level: LogLevel::Info,
+            }),
+            metadata: EventMetadata::default(),
+        }
+    }
+
+    fn msg(event: WorkerEventWithMetadata) -> String {
+        match event.event {
+            WorkerEvents::Log(e) => e.msg,
+            _ => panic!("unexpected event"),
+        }
+    }
+
+    #[test]
+    fn test_events_buffer_cap_and_replay() {
+        let metrics = EventsMetrics::default();
+        let mut buffer = EventsBuffer::new(2, metrics.clone());
+        buffer.push_back(log_event("a"));
+        buffer.push_back(log_event("b"));
+        buffer.push_back(log_event("c"));
+        assert_eq!(metrics.snapshot().dropped, 1);
+
+        // replayed events go ahead of newer ones
+        let mut buffer = EventsBuffer::new(3, metrics.clone());
+        buffer.push_back(log_event("c"));
+        buffer.requeue(VecDeque::from(vec![log_event("a"), log_event("b")]));
+        assert_eq!(msg(buffer.pop_front().unwrap()), "a");
+        assert_eq!(msg(buffer.pop_front().unwrap()), "b");
+        assert_eq!(msg(buffer.pop_front().unwrap()), "c");
+        assert!(buffer.pop_front().is_none());
+    }
+
+    #[test]
+    fn test_events_buffer_spill() {
+        let dir = std::env::temp_dir().join(format!("events-buffer-{}", uuid::Uuid::new_v4()));
+        let metrics = EventsMetrics::default();
+        let spill = EventsSpill::open(
+            EventsSpillOpts {
+                dir: dir.clone(),
+                max_bytes: 1024 * 1024,
+            },
+            metrics.clone(),
+        )
+        .unwrap();
+        let mut buffer = EventsBuffer::new(2, metrics.clone()).with_spill(spill);
+        for msg in ["a", "b", "c", "d", "e"] {
+            buffer.push_back(log_event(msg));
+        }
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(metrics.snapshot().spilled, 3);
+
+        // in order, without dropping any
+        let mut msgs = vec![];
+        while let Some(event) = buffer.pop_front() {
+            msgs.push(msg(event));
+        }
+        assert_eq!(msgs, vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(metrics.snapshot().dropped, 0);
+        assert_eq!(metrics.snapshot().spilled, 0);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,181 @@
+use event_worker::events::ShutdownReason;
+use hyper::{Body, Request, Response};
+use log::warn;
+use sb_worker_context::essentials::{WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+static WORKER_HOOKS: OnceLock<SharedWorkerHooks> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerKind {
+    Main,
+    Events,
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerLimits {
+    pub memory_limit_mb: u64,
+    pub worker_timeout_ms: u64,
+    pub cpu_time_threshold_ms: u64,
+    pub max_cpu_bursts: u64,
+    pub hang_threshold_ms: u64,
+    pub max_concurrent_requests: Option<usize>,
+}
+
+// Metadata of the worker a hook is called for
+#[derive(Debug, Clone)]
+pub struct WorkerInfo {
+    pub kind: WorkerKind,
+    pub service_path: String,
+    // only set for user workers
+    pub execution_id: Option<Uuid>,
+    pub limits: Option<WorkerLimits>,
+}
+
+impl WorkerInfo {
+    pub fn from_opts(opts: &WorkerContextInitOpts) -> Self {
+        let service_path = opts.service_path.to_string_lossy().to_string();
+        match &opts.conf {
+            WorkerRuntimeOpts::MainWorker(_) => Self {
+                kind: WorkerKind::Main,
+                service_path,
+                execution_id: None,
+                limits: None,
+            },
+            WorkerRuntimeOpts::EventsWorker(_) => Self {
+                kind: WorkerKind::Events,
+                service_path,
+                execution_id: None,
+                limits: None,
+            },
+            WorkerRuntimeOpts::UserWorker(conf) => Self {
+                kind: WorkerKind::User,
+                service_path: conf.service_path.clone().unwrap_or(service_path),
+                execution_id: conf.key,
+                limits: Some(WorkerLimits {
+                    memory_limit_mb: conf.memory_limit_mb,
+                    worker_timeout_ms: conf.worker_timeout_ms,
+                    cpu_time_threshold_ms: conf.cpu_time_threshold_ms,
+                    max_cpu_bursts: conf.max_cpu_bursts,
+                    hang_threshold_ms: conf.hang_threshold_ms,
+                    max_concurrent_requests: conf.max_concurrent_requests,
+                }),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestOutcome {
+    // `None` if the worker failed to respond
+    pub status: Option<u16>,
+    // time until the response started
+    pub duration: Duration,
+}
+
+// Called by the runtime around the life of every worker. Hooks run on the runtime's
+// threads, so they should return quickly.
+pub trait WorkerLifecycleHooks: Send + Sync {
+    fn on_boot(&self, _worker: &WorkerInfo, _boot_time: Duration) {}
+
+    // Returning a response rejects the request, without sending it to the worker
+    fn before_request(
+        &self,
+        _worker: &WorkerInfo,
+        _req: &mut Request<Body>,
+    ) -> Option<Response<Body>> {
+        None
+    }
+
+    fn after_request(&self, _worker: &WorkerInfo, _outcome: RequestOutcome) {}
+
+    // `reason` is set if the worker was terminated for exceeding a limit
+    fn on_shutdown(&self, _worker: &WorkerInfo, _reason: Option<ShutdownReason>) {}
+}
+
+pub type SharedWorkerHooks = Arc<dyn WorkerLifecycleHooks>;
+
+pub fn set_worker_hooks(hooks: SharedWorkerHooks) {
+    if WORKER_HOOKS.set(hooks).is_err() {
+        warn!("worker lifecycle hooks are already set");
+    }
+}
+
+pub fn worker_hooks() -> Option<SharedWorkerHooks> {
+    WORKER_HOOKS.get().cloned()
+}
+
+// Swaps the response sender of the request, to call `after_request` once the
+// worker responds (or fails to)
+pub fn observe_response(
+    msg: WorkerRequestMsg,
+    hooks: SharedWorkerHooks,
+    worker: WorkerInfo,
+) -> WorkerRequestMsg {
+    let WorkerRequestMsg { req, res_tx } = msg;
+    let (observed_tx, observed_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
+    let start = Instant::now();
+
+    tokio::task::spawn(async move {
+        let result = observed_rx.await;
+        let status = match &result {
+            Ok(Ok(res)) => Some(res.status().as_u16()),
+            _ => None,
+        };
+        hooks.after_request(
+            &worker,
+            RequestOutcome {
+                status,
+                duration: start.elapsed(),
+            },
+        );
+        if let Ok(result) = result {
+            let _ = res_tx.send(result);
+        }
+    });
+
+    WorkerRequestMsg {
+        req,
+        res_tx: observed_tx,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WorkerInfo, WorkerKind};
+    use sb_worker_context::essentials::{
+        UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+    };
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_worker_info_from_user_worker_opts() {
+        let key = Uuid::new_v4();
+        let opts = WorkerContextInitOpts {
+            service_path: PathBuf::from("./test_cases/main"),
+            no_module_cache: false,
+            import_map_path: None,
+            env_vars: Default::default(),
+            events_rx: None,
+            maybe_eszip: None,
+            maybe_entrypoint: None,
+            maybe_module_code: None,
+            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                key: Some(key),
+                memory_limit_mb: 64,
+                ..Default::default()
+            }),
+        };
+
+        let info = WorkerInfo::from_opts(&opts);
+        assert_eq!(info.kind, WorkerKind::User);
+        assert_eq!(info.service_path, "./test_cases/main");
+        assert_eq!(info.execution_id, Some(key));
+        assert_eq!(info.limits.unwrap().memory_limit_mb, 64);
+    }
+}
@@ -0,0 +1,623 @@
+use bytes::Bytes;
+use deno_core::futures::{stream, Stream, StreamExt};
+use hyper::header::{
+    HeaderMap, HeaderValue, AUTHORIZATION, CONNECTION, SET_COOKIE, TRANSFER_ENCODING,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use sb_core::problem::{problem_response, RuntimeErrorCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Idempotency keys: the response to a request carrying an `Idempotency-Key`
+// header is kept for a while and returned to the retries of the request (eg: a
+// webhook delivered twice) instead of running the function again.
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// set on the responses returned from the store
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+pub const DEFAULT_IDEMPOTENCY_MAX_ENTRIES: usize = 10_000;
+pub const DEFAULT_IDEMPOTENCY_MAX_MB: u64 = 64;
+// larger responses are passed through without being stored
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+// the bodies of requests are hashed to tell retries from other requests sent
+// with the key, larger ones bypass the store
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+const MAX_KEY_LEN: usize = 255;
+
+static IDEMPOTENCY_STORE: OnceLock<IdempotencyStore> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyOpts {
+    pub ttl: Duration,
+    pub max_entries: usize,
+    // of the bodies stored
+    pub max_bytes: usize,
+}
+
+// A key only matches the retries of the request it came with, the same key sent
+// to another service or path, or by another caller (the hash of its
+// `Authorization` header), is another entry. Anonymous callers can't be told
+// apart, so their keys are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EntryKey {
+    service_path: String,
+    method: String,
+    path: String,
+    caller: [u8; 32],
+    key: String,
+}
+
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn replay(&self) -> Response<Body> {
+        let mut res = Response::new(Body::from(self.body.clone()));
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.headers.clone();
+        res.headers_mut()
+            .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        res
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    // `None` until the first request with the key responds
+    response: Option<StoredResponse>,
+    // retries must send the same body
+    body_hash: [u8; 32],
+    since: Instant,
+    // tells the entry from one stored again under the same key after it expired
+    id: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<EntryKey, Entry>,
+    // oldest first, along with the id of the entry when it was queued
+    order: VecDeque<(EntryKey, u64)>,
+    bytes: usize,
+    next_id: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &EntryKey) {
+        if let Some(entry) = self.map.remove(key) {
+            self.bytes -= entry.response.map_or(0, |res| res.body.len());
+        }
+    }
+
+    fn evict(&mut self, opts: &IdempotencyOpts, now: Instant) {
+        while let Some((key, id)) = self.order.front() {
+            let current = self.map.get(key).filter(|entry| entry.id == *id);
+            let evicted = match current {
+                // stale, the entry was removed or replaced
+                None => true,
+                Some(entry) => {
+                    now.duration_since(entry.since) >= opts.ttl
+                        || self.map.len() > opts.max_entries
+                        || self.bytes > opts.max_bytes
+                }
+            };
+            if !evicted {
+                break;
+            }
+            let (key, id) = self.order.pop_front().unwrap();
+            if self.map.get(&key).is_some_and(|entry| entry.id == id) {
+                self.remove(&key);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IdempotencySnapshot {
+    pub entries: usize,
+    pub bytes: usize,
+    // responses returned from the store
+    pub replayed: u64,
+    // retries declined while the first request was in flight
+    pub conflicts: u64,
+    // requests declined for reusing a key with another body
+    pub mismatches: u64,
+}
+
+pub struct IdempotencyStore {
+    opts: IdempotencyOpts,
+    entries: Mutex<Entries>,
+    replayed: AtomicU64,
+    conflicts: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+pub enum Idempotency {
+    // the request has no (valid) key, is anonymous, or its method is idempotent
+    // already
+    Bypass,
+    Replay(Response<Body>),
+    // the first request with the key is still in flight
+    Conflict(Response<Body>),
+    // the key was sent with another body
+    Mismatch(Response<Body>),
+    // the first request with the key, its response is stored by the guard
+    Record(IdempotencyGuard),
+}
+
+impl IdempotencyStore {
+    pub fn new(opts: IdempotencyOpts) -> Self {
+        Self {
+            opts,
+            entries: Default::default(),
+            replayed: AtomicU64::new(0),
+            conflicts: AtomicU64::new(0),
+            mismatches: AtomicU64::new(0),
+        }
+    }
+
+    // The body of a request with a key is read first, to be hashed. The request
+    // is handed back along with what to do with it.
+    pub async fn begin(
+        &'static self,
+        service_path: &str,
+        req: Request<Body>,
+    ) -> (Request<Body>, Idempotency) {
+        if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            return (req, Idempotency::Bypass);
+        }
+        let Some(key) = req
+            .headers()
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        else {
+            return (req, Idempotency::Bypass);
+        };
+        let Some(caller) = req
+            .headers()
+            .get(AUTHORIZATION)
+            .filter(|value| !value.is_empty())
+        else {
+            return (req, Idempotency::Bypass);
+        };
+        let caller: [u8; 32] = Sha256::digest(caller.as_bytes()).into();
+        let key = EntryKey {
+            service_path: service_path.to_string(),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            caller,
+            key: key.to_string(),
+        };
+
+        let (parts, body) = req.into_parts();
+        let body = match read_body(body, MAX_REQUEST_BYTES).await {
+            Ok(body) => body,
+            Err(body) => return (Request::from_parts(parts, body), Idempotency::Bypass),
+        };
+        let body_hash: [u8; 32] = Sha256::digest(&body).into();
+        let req = Request::from_parts(parts, Body::from(body));
+        (req, self.begin_with_body(key, body_hash))
+    }
+
+    fn begin_with_body(&'static self, key: EntryKey, body_hash: [u8; 32]) -> Idempotency {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        match entries
+            .map
+            .get(&key)
+            .filter(|entry| now.duration_since(entry.since) < self.opts.ttl)
+        {
+            Some(entry) if entry.body_hash != body_hash => {
+                self.mismatches.fetch_add(1, Ordering::Relaxed);
+                return Idempotency::Mismatch(problem_response(
+                    RuntimeErrorCode::IdempotencyKeyReused,
+                    Some(format!(
+                        "idempotency key {:?} was sent with another request body",
+                        key.key
+                    )),
+                ));
+            }
+            Some(Entry {
+                response: Some(response),
+                ..
+            }) => {
+                self.replayed.fetch_add(1, Ordering::Relaxed);
+                return Idempotency::Replay(response.replay());
+            }
+            Some(_) => {
+                self.conflicts.fetch_add(1, Ordering::Relaxed);
+                let mut res = problem_response(
+                    RuntimeErrorCode::IdempotencyConflict,
+                    Some(format!(
+                        "a request with idempotency key {:?} is in progress",
+                        key.key
+                    )),
+                );
+                res.headers_mut()
+                    .insert("retry-after", HeaderValue::from_static("1"));
+                return Idempotency::Conflict(res);
+            }
+            None => {}
+        }
+
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.remove(&key);
+        entries.map.insert(
+            key.clone(),
+            Entry {
+                response: None,
+                body_hash,
+                since: now,
+                id,
+            },
+        );
+        entries.order.push_back((key.clone(), id));
+        entries.evict(&self.opts, now);
+        Idempotency::Record(IdempotencyGuard {
+            store: self,
+            key,
+            id,
+        })
+    }
+
+    fn complete(&self, key: &EntryKey, id: u64, response: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        let len = response.body.len();
+        let Some(entry) = entries.map.get_mut(key).filter(|entry| entry.id == id) else {
+            return;
+        };
+        // kept for the TTL from the response
+        entry.since = Instant::now();
+        entry.response = Some(response);
+        entries.bytes += len;
+        entries.evict(&self.opts, Instant::now());
+    }
+
+    // The request failed or its response wasn't stored, retries run it again
+    fn abandon(&self, key: &EntryKey, id: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .map
+            .get(key)
+            .is_some_and(|entry| entry.id == id && entry.response.is_none())
+        {
+            entries.remove(key);
+        }
+    }
+
+    pub fn snapshot(&self) -> IdempotencySnapshot {
+        let entries = self.entries.lock().unwrap();
+        IdempotencySnapshot {
+            entries: entries.map.len(),
+            bytes: entries.bytes,
+            replayed: self.replayed.load(Ordering::Relaxed),
+            conflicts: self.conflicts.load(Ordering::Relaxed),
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct IdempotencyGuard {
+    store: &'static IdempotencyStore,
+    key: EntryKey,
+    id: u64,
+}
+
+impl IdempotencyGuard {
+    // Stores the response once its body is sent. Server errors aren't stored, so
+    // the request can be retried, nor are the responses over the size limit.
+    pub fn record(self, res: Response<Body>) -> Response<Body> {
+        if res.status().is_server_error() {
+            return res;
+        }
+        let (parts, body) = res.into_parts();
+        let mut headers = parts.headers.clone();
+        headers.remove(TRANSFER_ENCODING);
+        headers.remove(CONNECTION);
+        // cookies are never handed out again, whoever retries
+        headers.remove(SET_COOKIE);
+        let limit = std::cmp::min(MAX_RESPONSE_BYTES, self.store.opts.max_bytes);
+        let body = Body::wrap_stream(RecordingBody {
+            inner: body,
+            status: parts.status,
+            headers,
+            buf: vec![],
+            limit,
+            guard: Some(self),
+        });
+        Response::from_parts(parts, body)
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        self.store.abandon(&self.key, self.id);
+    }
+}
+
+struct RecordingBody {
+    inner: Body,
+    status: StatusCode,
+    headers: HeaderMap,
+    buf: Vec<u8>,
+    limit: usize,
+    // dropped (abandoning the entry) unless the body completes within the limit
+    guard: Option<IdempotencyGuard>,
+}
+
+impl Stream for RecordingBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let next = std::task::ready!(Pin::new(&mut this.inner).poll_next(cx));
+        match &next {
+            Some(Ok(chunk)) if this.guard.is_some() => {
+                if this.buf.len() + chunk.len() > this.limit {
+                    this.guard = None;
+                    this.buf = vec![];
+                } else {
+                    this.buf.extend_from_slice(chunk);
+                }
+            }
+            Some(Ok(_)) => {}
+            Some(Err(_)) => this.guard = None,
+            None => {
+                if let Some(guard) = this.guard.take() {
+                    guard.store.complete(
+                        &guard.key,
+                        guard.id,
+                        StoredResponse {
+                            status: this.status,
+                            headers: std::mem::take(&mut this.headers),
+                            body: Bytes::from(std::mem::take(&mut this.buf)),
+                        },
+                    );
+                }
+            }
+        }
+        Poll::Ready(next)
+    }
+}
+
+// Reads a body of up to `limit` bytes. A larger one (or one failing to be read)
+// is handed back as it was.
+async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, Body> {
+    let mut chunks = vec![];
+    let mut len = 0;
+    while let Some(chunk) = body.next().await {
+        let failed = chunk.is_err();
+        len += chunk.as_ref().map_or(0, |chunk| chunk.len());
+        chunks.push(chunk);
+        if failed || len > limit {
+            return Err(Body::wrap_stream(stream::iter(chunks).chain(body)));
+        }
+    }
+    let mut buf = Vec::with_capacity(len);
+    for chunk in chunks.into_iter().flatten() {
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
+// Responses to requests with an `Idempotency-Key` are stored for `opts.ttl`
+pub fn enable_idempotency(opts: IdempotencyOpts) {
+    let _ = IDEMPOTENCY_STORE.set(IdempotencyStore::new(opts));
+}
+
+pub fn idempotency_store() -> Option<&'static IdempotencyStore> {
+    IDEMPOTENCY_STORE.get()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Idempotency, IdempotencyOpts, IdempotencyStore, IDEMPOTENT_REPLAYED_HEADER};
+    use hyper::{Body, Request, Response};
+    use std::time::Duration;
+
+    fn store(max_entries: usize) -> &'static IdempotencyStore {
+        Box::leak(Box::new(IdempotencyStore::new(IdempotencyOpts {
+            ttl: Duration::from_secs(60),
+            max_entries,
+            max_bytes: 1024,
+        })))
+    }
+
+    fn request(key: &str) -> Request<Body> {
+        request_from(key, "Bearer 1", vec![])
+    }
+
+    fn request_from(key: &str, authorization: &str, body: Vec<u8>) -> Request<Body> {
+        Request::post("http://localhost/hook")
+            .header("idempotency-key", key)
+            .header("authorization", authorization)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn begin(
+        store: &'static IdempotencyStore,
+        service_path: &str,
+        req: Request<Body>,
+    ) -> Idempotency {
+        store.begin(service_path, req).await.1
+    }
+
+    async fn respond(guard: Idempotency, status: u16, body: &str) -> Response<Body> {
+        let Idempotency::Record(guard) = guard else {
+            panic!("the request should run");
+        };
+        let res = guard.record(
+            Response::builder()
+                .status(status)
+                .header("x-id", "1")
+                .header("set-cookie", "session=1")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        );
+        // the response is stored once its body is sent
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        Response::from_parts(parts, Body::from(body))
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_replay() {
+        let store = store(16);
+        let first = begin(store, "./hooks", request("a")).await;
+        // a retry while the first delivery runs
+        let Idempotency::Conflict(conflict) = begin(store, "./hooks", request("a")).await else {
+            panic!("the retry should conflict");
+        };
+        assert_eq!(conflict.status(), 409);
+        respond(first, 201, "created").await;
+
+        let Idempotency::Replay(replay) = begin(store, "./hooks", request("a")).await else {
+            panic!("the retry should be replayed");
+        };
+        assert_eq!(replay.status(), 201);
+        assert_eq!(replay.headers().get("x-id").unwrap(), "1");
+        assert!(replay.headers().get("set-cookie").is_none());
+        assert_eq!(
+            replay.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+        let body = hyper::body::to_bytes(replay.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"created");
+
+        // other services, keys and methods run
+        assert!(matches!(
+            begin(store, "./other", request("a")).await,
+            Idempotency::Record(_)
+        ));
+        assert!(matches!(
+            begin(store, "./hooks", request("b")).await,
+            Idempotency::Record(_)
+        ));
+        let get = Request::get("http://localhost/hook")
+            .header("idempotency-key", "a")
+            .header("authorization", "Bearer 1")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            begin(store, "./hooks", get).await,
+            Idempotency::Bypass
+        ));
+        assert_eq!(store.snapshot().replayed, 1);
+        assert_eq!(store.snapshot().conflicts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests_are_not_stored() {
+        let store = store(16);
+        respond(
+            begin(store, "./hooks", request("a")).await,
+            503,
+            "unavailable",
+        )
+        .await;
+        // over the size limit
+        respond(
+            begin(store, "./hooks", request("a")).await,
+            200,
+            &"x".repeat(2048),
+        )
+        .await;
+        // dropped before responding
+        drop(begin(store, "./hooks", request("a")).await);
+        assert!(matches!(
+            begin(store, "./hooks", request("a")).await,
+            Idempotency::Record(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_store_is_bounded() {
+        let store = store(2);
+        for key in ["a", "b", "c"] {
+            respond(begin(store, "./hooks", request(key)).await, 200, "ok").await;
+        }
+        assert_eq!(store.snapshot().entries, 2);
+        // the oldest was evicted
+        assert!(matches!(
+            begin(store, "./hooks", request("a")).await,
+            Idempotency::Record(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_scoped_to_the_request() {
+        let store = store(16);
+        let first = begin(
+            store,
+            "./hooks",
+            request_from("a", "Bearer 1", b"{}".to_vec()),
+        )
+        .await;
+        respond(first, 200, "ok").await;
+
+        // another caller sending the same key
+        assert!(matches!(
+            begin(
+                store,
+                "./hooks",
+                request_from("a", "Bearer 2", b"{}".to_vec())
+            )
+            .await,
+            Idempotency::Record(_)
+        ));
+        // the same caller reusing the key for another request
+        let Idempotency::Mismatch(mismatch) = begin(
+            store,
+            "./hooks",
+            request_from("a", "Bearer 1", b"[]".to_vec()),
+        )
+        .await
+        else {
+            panic!("the request should be declined");
+        };
+        assert_eq!(mismatch.status(), 422);
+        assert!(matches!(
+            begin(
+                store,
+                "./hooks",
+                request_from("a", "Bearer 1", b"{}".to_vec())
+            )
+            .await,
+            Idempotency::Replay(_)
+        ));
+        assert_eq!(store.snapshot().mismatches, 1);
+
+        // anonymous callers would share their keys
+        let anonymous = Request::post("http://localhost/hook")
+            .header("idempotency-key", "a")
+            .body(Body::from("{}"))
+            .unwrap();
+        assert!(matches!(
+            begin(store, "./hooks", anonymous).await,
+            Idempotency::Bypass
+        ));
+
+        // bodies too large to be hashed bypass the store, and are passed on whole
+        let large = vec![b'x'; super::MAX_REQUEST_BYTES + 1];
+        let (req, idempotency) = store
+            .begin("./hooks", request_from("b", "Bearer 1", large))
+            .await;
+        assert!(matches!(idempotency, Idempotency::Bypass));
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(body.len(), super::MAX_REQUEST_BYTES + 1);
+    }
+}
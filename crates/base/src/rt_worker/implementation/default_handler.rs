@@ -2,8 +2,8 @@ use crate::deno_runtime::DenoRuntime;
 use crate::rt_worker::worker::{HandleCreationType, Worker, WorkerHandler};
 use anyhow::Error;
 use event_worker::events::{BootFailureEvent, PseudoEvent, UncaughtExceptionEvent, WorkerEvents};
+use sb_worker_context::request_metadata::WorkerConnection;
 use std::any::Any;
-use tokio::net::UnixStream;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::oneshot::Receiver;
 
@@ -18,10 +18,11 @@ impl WorkerHandler for Worker {
     fn handle_creation(
         &self,
         created_rt: DenoRuntime,
-        unix_stream_rx: UnboundedReceiver<UnixStream>,
+        unix_stream_rx: UnboundedReceiver<WorkerConnection>,
         termination_event_rx: Receiver<WorkerEvents>,
     ) -> HandleCreationType {
-        let run_worker_rt = async {
+        let cancelled_ops = created_rt.cancelled_ops.clone();
+        let run_worker_rt = async move {
             match created_rt.run(unix_stream_rx).await {
                 // if the error is execution terminated, check termination event reason
                 Err(err) => {
@@ -29,7 +30,11 @@ impl WorkerHandler for Worker {
                     if err_string.ends_with("execution terminated")
                         || err_string.ends_with("wall clock duration reached")
                     {
-                        Ok(termination_event_rx.await.unwrap())
+                        let mut event = termination_event_rx.await.unwrap();
+                        if let WorkerEvents::Shutdown(e) = &mut event {
+                            e.cancelled_ops = std::mem::take(&mut *cancelled_ops.lock().unwrap());
+                        }
+                        Ok(event)
                     } else {
                         Ok(WorkerEvents::UncaughtException(UncaughtExceptionEvent {
                             exception: err_string,
@@ -0,0 +1,134 @@
+use crate::rt_worker::worker_ctx::create_main_worker;
+use log::error;
+use sb_worker_context::essentials::{UserWorkerMsgs, WorkerRequestMsg};
+use sb_worker_context::manifest::SharedManifest;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(30);
+// a main worker running at least this long resets the backoff
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+// Request sender of the running main worker (`None` while it's unavailable)
+pub type MainWorkerSlot = Arc<RwLock<Option<mpsc::UnboundedSender<WorkerRequestMsg>>>>;
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MainWorkerOpts {
+    pub service_path: PathBuf,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub maybe_entrypoint: Option<String>,
+    pub maybe_manifest: Option<SharedManifest>,
+}
+
+async fn boot(
+    opts: &MainWorkerOpts,
+) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, anyhow::Error> {
+    create_main_worker(
+        opts.service_path.clone(),
+        opts.import_map_path.clone(),
+        opts.no_module_cache,
+        opts.worker_pool_tx.clone(),
+        opts.maybe_entrypoint.clone(),
+        opts.maybe_manifest.clone(),
+    )
+    .await
+}
+
+// Boots the main worker and restarts it (with exponential backoff) whenever it
+// fails to boot or exits. The slot is empty while the main worker is down.
+pub async fn start_main_worker_supervisor(opts: MainWorkerOpts) -> MainWorkerSlot {
+    let slot = MainWorkerSlot::default();
+    let mut maybe_worker = match boot(&opts).await {
+        Ok(worker) => Some(worker),
+        Err(err) => {
+            error!("{} (requests are served by the fallback router)", err);
+            None
+        }
+    };
+    *slot.write().unwrap() = maybe_worker.clone();
+
+    let supervised_slot = slot.clone();
+    tokio::task::spawn(async move {
+        let mut backoff = Backoff::new(RESTART_BACKOFF_INITIAL, RESTART_BACKOFF_MAX);
+        loop {
+            let worker = match maybe_worker.take() {
+                Some(worker) => worker,
+                None => {
+                    let delay = backoff.next_delay();
+                    tokio::time::sleep(delay).await;
+                    match boot(&opts).await {
+                        Ok(worker) => worker,
+                        Err(err) => {
+                            error!("{} (retrying in {:?})", err, backoff.current);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            *supervised_slot.write().unwrap() = Some(worker.clone());
+            let started = Instant::now();
+
+            // the request channel closes when the main worker exits
+            worker.closed().await;
+            *supervised_slot.write().unwrap() = None;
+            error!("main worker exited, restarting it");
+
+            if started.elapsed() >= HEALTHY_AFTER {
+                backoff.reset();
+            }
+        }
+    });
+
+    slot
+}
+
+#[cfg(test)]
+mod test {
+    use super::Backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(350));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(350));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::gc::nudge_gc;
+use crate::rt_worker::cgroups::{parse_own_cgroup, CGROUP2_MOUNT};
+use anyhow::{bail, Error};
+use log::{info, warn};
+use sb_worker_context::essentials::UserWorkerMsgs;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// share of the memory limit past which idle workers are evicted and the others
+// are asked to collect garbage
+pub const DEFAULT_MEMORY_HIGH_RATIO: f64 = 0.8;
+// past which no new workers are booted
+pub const DEFAULT_MEMORY_CRITICAL_RATIO: f64 = 0.95;
+
+static MEMORY_COORDINATOR: OnceLock<MemoryCoordinator> = OnceLock::new();
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryPressure {
+    Normal,
+    High,
+    Critical,
+}
+
+impl MemoryPressure {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => MemoryPressure::Normal,
+            1 => MemoryPressure::High,
+            _ => MemoryPressure::Critical,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryCoordinatorOpts {
+    // RSS limit of the process, the memory.max of its cgroup when not set
+    pub limit_bytes: Option<u64>,
+    pub high_ratio: f64,
+    pub critical_ratio: f64,
+}
+
+// Where the memory usage of the node is read from
+#[derive(Debug)]
+enum MemorySource {
+    // RSS of the process, against a given limit
+    Rss(u64),
+    // usage of the process's cgroup, against its memory.max
+    Cgroup(PathBuf),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MemorySnapshot {
+    pub pressure: MemoryPressure,
+    pub usage_bytes: u64,
+    pub limit_bytes: u64,
+    // workers shut down to free memory
+    pub evicted: u64,
+    // worker creations declined at critical pressure
+    pub rejected: u64,
+}
+
+// Watches the memory of the node and, under pressure, evicts idle workers,
+// nudges the others to collect garbage and (as a last resort) declines to boot
+// new workers, instead of letting the OOM killer take the whole process
+pub struct MemoryCoordinator {
+    opts: MemoryCoordinatorOpts,
+    source: MemorySource,
+    pressure: AtomicU8,
+    usage_bytes: AtomicU64,
+    limit_bytes: AtomicU64,
+    evicted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+// `VmRSS:	  123456 kB` -> bytes
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+// `max` when the cgroup isn't limited
+fn parse_memory_max(memory_max: &str) -> Option<u64> {
+    memory_max.trim().parse().ok()
+}
+
+fn own_cgroup_dir() -> Option<PathBuf> {
+    let proc_cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let own = parse_own_cgroup(&proc_cgroup)?;
+    Some(PathBuf::from(CGROUP2_MOUNT).join(own.trim_start_matches('/')))
+}
+
+fn pressure_level(usage: u64, limit: u64, opts: &MemoryCoordinatorOpts) -> MemoryPressure {
+    let ratio = usage as f64 / limit.max(1) as f64;
+    if ratio >= opts.critical_ratio {
+        MemoryPressure::Critical
+    } else if ratio >= opts.high_ratio {
+        MemoryPressure::High
+    } else {
+        MemoryPressure::Normal
+    }
+}
+
+impl MemorySource {
+    fn detect(opts: &MemoryCoordinatorOpts) -> Result<Self, Error> {
+        if let Some(limit) = opts.limit_bytes {
+            return Ok(MemorySource::Rss(limit));
+        }
+        let limited = own_cgroup_dir().filter(|dir| {
+            fs::read_to_string(dir.join("memory.max"))
+                .ok()
+                .and_then(|max| parse_memory_max(&max))
+                .is_some()
+        });
+        match limited {
+            Some(dir) => Ok(MemorySource::Cgroup(dir)),
+            None => bail!("the runtime's cgroup has no memory.max, a memory limit has to be given"),
+        }
+    }
+
+    // (usage, limit) in bytes
+    fn read(&self) -> Option<(u64, u64)> {
+        match self {
+            MemorySource::Rss(limit) => {
+                let status = fs::read_to_string("/proc/self/status").ok()?;
+                Some((parse_vm_rss(&status)?, *limit))
+            }
+            MemorySource::Cgroup(dir) => {
+                let current = fs::read_to_string(dir.join("memory.current")).ok()?;
+                let max = fs::read_to_string(dir.join("memory.max")).ok()?;
+                Some((current.trim().parse().ok()?, parse_memory_max(&max)?))
+            }
+        }
+    }
+}
+
+impl MemoryCoordinator {
+    pub fn pressure(&self) -> MemoryPressure {
+        MemoryPressure::from_u8(self.pressure.load(Ordering::Relaxed))
+    }
+
+    pub fn snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot {
+            pressure: self.pressure(),
+            usage_bytes: self.usage_bytes.load(Ordering::Relaxed),
+            limit_bytes: self.limit_bytes.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_evicted(&self, workers: usize) {
+        self.evicted.fetch_add(workers as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn check(&self, pool_tx: &mpsc::UnboundedSender<UserWorkerMsgs>) {
+        let Some((usage, limit)) = self.source.read() else {
+            return;
+        };
+        self.usage_bytes.store(usage, Ordering::Relaxed);
+        self.limit_bytes.store(limit, Ordering::Relaxed);
+
+        let pressure = pressure_level(usage, limit, &self.opts);
+        let previous =
+            MemoryPressure::from_u8(self.pressure.swap(pressure as u8, Ordering::Relaxed));
+        if pressure != previous {
+            let msg = format!(
+                "memory pressure is {:?} ({} of {} bytes)",
+                pressure, usage, limit
+            );
+            match pressure {
+                MemoryPressure::Normal => info!("{}", msg),
+                _ => warn!("{}", msg),
+            }
+        }
+        if pressure >= MemoryPressure::High {
+            let _ = pool_tx.send(UserWorkerMsgs::EvictIdle);
+            nudge_gc();
+        }
+    }
+}
+
+pub fn memory_coordinator() -> Option<&'static MemoryCoordinator> {
+    MEMORY_COORDINATOR.get()
+}
+
+// Pressure of the node, always normal when the coordinator isn't running
+pub fn memory_pressure() -> MemoryPressure {
+    memory_coordinator()
+        .map(|coordinator| coordinator.pressure())
+        .unwrap_or(MemoryPressure::Normal)
+}
+
+pub fn start_memory_coordinator(
+    opts: MemoryCoordinatorOpts,
+    pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&opts.high_ratio) || opts.high_ratio > opts.critical_ratio {
+        bail!("the high memory ratio must be between 0 and the critical one");
+    }
+    let source = MemorySource::detect(&opts)?;
+    info!("watching memory pressure of the node ({:?})", source);
+    let coordinator = MEMORY_COORDINATOR.get_or_init(|| MemoryCoordinator {
+        opts,
+        source,
+        pressure: AtomicU8::new(MemoryPressure::Normal as u8),
+        usage_bytes: AtomicU64::new(0),
+        limit_bytes: AtomicU64::new(0),
+        evicted: AtomicU64::new(0),
+        rejected: AtomicU64::new(0),
+    });
+
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if pool_tx.is_closed() {
+                break;
+            }
+            coordinator.check(&pool_tx);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_memory_max, parse_vm_rss, pressure_level, MemoryCoordinatorOpts, MemoryPressure,
+        DEFAULT_MEMORY_CRITICAL_RATIO, DEFAULT_MEMORY_HIGH_RATIO,
+    };
+
+    #[test]
+    fn test_memory_pressure() {
+        let status = "Name:\tedge-runtime\nVmPeak:\t  900 kB\nVmRSS:\t  2048 kB\nThreads:\t12\n";
+        assert_eq!(parse_vm_rss(status), Some(2 * 1024 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tedge-runtime\n"), None);
+        assert_eq!(parse_memory_max("1073741824\n"), Some(1 << 30));
+        assert_eq!(parse_memory_max("max\n"), None);
+
+        let opts = MemoryCoordinatorOpts {
+            limit_bytes: None,
+            high_ratio: DEFAULT_MEMORY_HIGH_RATIO,
+            critical_ratio: DEFAULT_MEMORY_CRITICAL_RATIO,
+        };
+        assert_eq!(pressure_level(50, 100, &opts), MemoryPressure::Normal);
+        assert_eq!(pressure_level(80, 100, &opts), MemoryPressure::High);
+        assert_eq!(pressure_level(99, 100, &opts), MemoryPressure::Critical);
+        assert_eq!(pressure_level(120, 100, &opts), MemoryPressure::Critical);
+        assert!(MemoryPressure::Critical > MemoryPressure::High);
+    }
+}
@@ -1,5 +1,25 @@
+pub mod body_tee;
+pub mod cgroups;
+pub mod crash;
+pub mod deadline;
+pub mod event_filters;
+pub mod event_quotas;
+pub mod events_spill;
+pub mod events_supervisor;
+pub mod hooks;
+pub mod idempotency;
 pub mod implementation;
+pub mod main_worker_supervisor;
+pub mod memory_pressure;
+pub mod pending_ops;
+pub mod process_worker;
+pub mod routes;
+pub mod sandbox;
+pub mod slow_requests;
+pub mod uncaught_errors;
 pub mod utils;
+pub mod watchdog;
+pub mod web_worker;
 pub mod worker;
 pub mod worker_ctx;
 pub mod worker_pool;
@@ -0,0 +1,76 @@
+use deno_core::{JsRuntime, Resource};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+// opened by the runtime itself, not by the worker's code
+const RUNTIME_RESOURCES: [&str; 3] = ["stdin", "stdout", "stderr"];
+
+// Resources still open when a worker stopped, counted by type (eg: `timer`,
+// `fetchRequest`)
+pub type CancelledOps = BTreeMap<String, usize>;
+
+// Closes the resources still open once the event loop of a worker stopped, which
+// cancels the ops pending on them (fetches, timers, ...) instead of leaking them
+// with the isolate
+pub fn cancel_pending_ops(js_runtime: &mut JsRuntime) -> CancelledOps {
+    let op_state_rc = js_runtime.op_state();
+    let resources: Vec<(String, Rc<dyn Resource>)> = {
+        let mut op_state = op_state_rc.borrow_mut();
+        let open: Vec<_> = op_state
+            .resource_table
+            .names()
+            .map(|(rid, name)| (rid, name.to_string()))
+            .filter(|(_, name)| !RUNTIME_RESOURCES.contains(&name.as_str()))
+            .collect();
+        open.into_iter()
+            .filter_map(|(rid, name)| {
+                let resource = op_state.resource_table.take_any(rid).ok()?;
+                Some((name, resource))
+            })
+            .collect()
+    };
+
+    // closed without the op state borrowed, as closing may wake ops up
+    let mut cancelled = CancelledOps::new();
+    for (name, resource) in resources {
+        resource.close();
+        *cancelled.entry(name).or_default() += 1;
+    }
+    cancelled
+}
+
+#[cfg(test)]
+mod test {
+    use super::cancel_pending_ops;
+    use deno_core::{JsRuntime, Resource};
+    use std::borrow::Cow;
+
+    struct TestResource(&'static str);
+
+    impl Resource for TestResource {
+        fn name(&self) -> Cow<str> {
+            self.0.into()
+        }
+    }
+
+    #[test]
+    fn test_cancel_pending_ops() {
+        let mut js_runtime = JsRuntime::new(Default::default());
+        {
+            let op_state_rc = js_runtime.op_state();
+            let table = &mut op_state_rc.borrow_mut().resource_table;
+            table.add(TestResource("timer"));
+            table.add(TestResource("fetchRequest"));
+            table.add(TestResource("timer"));
+            table.add(TestResource("stdout"));
+        }
+
+        let cancelled = cancel_pending_ops(&mut js_runtime);
+        assert_eq!(cancelled.get("timer"), Some(&2));
+        assert_eq!(cancelled.get("fetchRequest"), Some(&1));
+        assert!(!cancelled.contains_key("stdout"));
+        // only the runtime's resources are left
+        let op_state_rc = js_runtime.op_state();
+        assert_eq!(op_state_rc.borrow().resource_table.names().count(), 1);
+    }
+}
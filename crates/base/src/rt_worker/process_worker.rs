@@ -0,0 +1,565 @@
+use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use event_worker::events::UncaughtErrorPolicy;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request};
+use log::{debug, error, info};
+use sb_core::problem::{problem_response, RuntimeErrorCode};
+use sb_eszip::module_loader::EszipPayloadKind;
+use sb_worker_context::essentials::{
+    DeterministicMode, TimerPolicy, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    WorkerIsolation, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use sb_worker_context::listen::ListenPermission;
+use sb_worker_context::request_metadata::{
+    read_metadata_frame, write_metadata_frame, RequestMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+// Process isolation: the worker of a service runs in a child process
+// (`edge-runtime worker-host`) instead of a thread of the runtime, and the pool
+// proxies its requests over a unix socket. The child boots a single worker,
+// supervised with the service's limits, and exits along with it. The pool then
+// handles it like any other worker that exited: the next request starts a new
+// process.
+
+pub const WORKER_HOST_COMMAND: &str = "worker-host";
+// printed by the worker host on stdout once its worker booted (or failed to)
+const READY_LINE: &str = "edge-runtime-worker-host:ready";
+const ERROR_LINE_PREFIX: &str = "edge-runtime-worker-host:error:";
+const BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+// env vars the worker host needs for itself (module cache, certificates, proxies,
+// logging and the node's identity). Those of the worker are sent on its stdin.
+const HOST_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USERPROFILE",
+    "TMPDIR",
+    "XDG_CACHE_HOME",
+    "DENO_DIR",
+    "DENO_AUTH_TOKENS",
+    "DENO_REGISTRY_URL",
+    "NPM_CONFIG_REGISTRY",
+    "DENO_TLS_CA_STORE",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "RUST_LOG",
+    "RUST_BACKTRACE",
+    "NODE_NAME",
+    "POD_NAME",
+    "POD_NAMESPACE",
+    "TOPOLOGY_ZONE",
+    "TOPOLOGY_REGION",
+];
+
+static PROCESS_ISOLATION: AtomicBool = AtomicBool::new(false);
+
+// Runs every user worker in a process of its own from now on
+pub fn enable_process_isolation() {
+    info!("user workers run in child processes");
+    PROCESS_ISOLATION.store(true, Ordering::Relaxed);
+}
+
+pub fn is_process_isolated(conf: &UserWorkerRuntimeOpts) -> bool {
+    conf.isolation == WorkerIsolation::Process || PROCESS_ISOLATION.load(Ordering::Relaxed)
+}
+
+// What the worker host boots, sent as a JSON line on its stdin (so env vars
+// don't show up in its environment)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WorkerHostOpts {
+    service_path: PathBuf,
+    no_module_cache: bool,
+    import_map_path: Option<String>,
+    env_vars: HashMap<String, String>,
+    maybe_entrypoint: Option<String>,
+    maybe_module_code: Option<String>,
+    // eszips are written to a file next to the socket
+    maybe_eszip_path: Option<PathBuf>,
+
+    memory_limit_mb: u64,
+    low_memory_multiplier: u64,
+    worker_timeout_ms: u64,
+    cpu_time_threshold_ms: u64,
+    cpu_burst_interval_ms: u64,
+    max_cpu_bursts: u64,
+    hang_threshold_ms: u64,
+    max_web_workers: usize,
+    net_access_disabled: bool,
+    allow_remote_modules: bool,
+    freeze_remote_origins: bool,
+    reload_modules: Vec<String>,
+    disallow_code_generation: bool,
+    deterministic: Option<DeterministicMode>,
+    timer_policy: Option<TimerPolicy>,
+    uncaught_error_policy: UncaughtErrorPolicy,
+    detect_leaks: bool,
+    custom_module_root: Option<String>,
+    listen: Vec<ListenPermission>,
+}
+
+impl WorkerHostOpts {
+    fn new(
+        init_opts: &WorkerContextInitOpts,
+        conf: &UserWorkerRuntimeOpts,
+        maybe_eszip_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            service_path: init_opts.service_path.clone(),
+            no_module_cache: init_opts.no_module_cache,
+            import_map_path: init_opts.import_map_path.clone(),
+            env_vars: init_opts.env_vars.clone(),
+            maybe_entrypoint: init_opts.maybe_entrypoint.clone(),
+            maybe_module_code: init_opts
+                .maybe_module_code
+                .as_ref()
+                .map(|code| code.as_str().to_string()),
+            maybe_eszip_path,
+            memory_limit_mb: conf.memory_limit_mb,
+            low_memory_multiplier: conf.low_memory_multiplier,
+            worker_timeout_ms: conf.worker_timeout_ms,
+            cpu_time_threshold_ms: conf.cpu_time_threshold_ms,
+            cpu_burst_interval_ms: conf.cpu_burst_interval_ms,
+            max_cpu_bursts: conf.max_cpu_bursts,
+            hang_threshold_ms: conf.hang_threshold_ms,
+            max_web_workers: conf.max_web_workers,
+            net_access_disabled: conf.net_access_disabled,
+            allow_remote_modules: conf.allow_remote_modules,
+            freeze_remote_origins: conf.freeze_remote_origins,
+            reload_modules: conf.reload_modules.clone(),
+            disallow_code_generation: conf.disallow_code_generation,
+            deterministic: conf.deterministic,
+            timer_policy: conf.timer_policy,
+            uncaught_error_policy: conf.uncaught_error_policy,
+            detect_leaks: conf.detect_leaks,
+            custom_module_root: conf.custom_module_root.clone(),
+            listen: conf.listen.clone(),
+        }
+    }
+
+    // Options of the worker booted in the host, reporting its exit on `pool_msg_tx`
+    fn into_init_opts(
+        self,
+        key: Uuid,
+        pool_msg_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    ) -> Result<WorkerContextInitOpts, Error> {
+        let maybe_eszip = match &self.maybe_eszip_path {
+            Some(path) => Some(EszipPayloadKind::VecKind(
+                std::fs::read(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?,
+            )),
+            None => None,
+        };
+        let conf = UserWorkerRuntimeOpts {
+            service_path: Some(self.service_path.to_string_lossy().to_string()),
+            key: Some(key),
+            pool_msg_tx: Some(pool_msg_tx),
+            memory_limit_mb: self.memory_limit_mb,
+            low_memory_multiplier: self.low_memory_multiplier,
+            worker_timeout_ms: self.worker_timeout_ms,
+            cpu_time_threshold_ms: self.cpu_time_threshold_ms,
+            cpu_burst_interval_ms: self.cpu_burst_interval_ms,
+            max_cpu_bursts: self.max_cpu_bursts,
+            hang_threshold_ms: self.hang_threshold_ms,
+            max_web_workers: self.max_web_workers,
+            net_access_disabled: self.net_access_disabled,
+            allow_remote_modules: self.allow_remote_modules,
+            freeze_remote_origins: self.freeze_remote_origins,
+            reload_modules: self.reload_modules,
+            disallow_code_generation: self.disallow_code_generation,
+            deterministic: self.deterministic,
+            timer_policy: self.timer_policy,
+            uncaught_error_policy: self.uncaught_error_policy,
+            detect_leaks: self.detect_leaks,
+            custom_module_root: self.custom_module_root,
+            listen: self.listen,
+            // a thread of the host
+            ..Default::default()
+        };
+
+        Ok(WorkerContextInitOpts {
+            service_path: self.service_path,
+            no_module_cache: self.no_module_cache,
+            import_map_path: self.import_map_path,
+            env_vars: self.env_vars,
+            events_rx: None,
+            conf: WorkerRuntimeOpts::UserWorker(conf),
+            maybe_eszip,
+            maybe_module_code: self.maybe_module_code.map(|code| code.into()),
+            maybe_entrypoint: self.maybe_entrypoint,
+        })
+    }
+}
+
+fn eszip_bytes(eszip: &EszipPayloadKind) -> &[u8] {
+    match eszip {
+        EszipPayloadKind::JsBufferKind(buf) => buf,
+        EszipPayloadKind::VecKind(buf) => buf,
+    }
+}
+
+// The variables of `env` (the runtime's) a worker host inherits
+fn host_env_vars(env: impl Iterator<Item = (OsString, OsString)>) -> Vec<(OsString, OsString)> {
+    env.filter(|(name, _)| HOST_ENV_VARS.iter().any(|host| name == *host))
+        .collect()
+}
+
+// The socket (and bundle) of a worker host live in a directory only the
+// runtime's user can enter. It must not exist yet, so it can't be planted.
+fn create_host_dir(key: Uuid) -> Result<PathBuf, Error> {
+    let dir = std::env::temp_dir().join(format!("edge-runtime-{}", key.simple()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+// Starts the worker host and waits for its worker to boot
+async fn spawn_worker_host(socket_path: &Path, opts: &WorkerHostOpts) -> Result<Child, Error> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg(WORKER_HOST_COMMAND)
+        .arg("--socket")
+        .arg(socket_path)
+        // the runtime's secrets (and config) aren't passed on to the host
+        .env_clear()
+        .envs(host_env_vars(std::env::vars_os()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        // the host exits once its stdin is closed, this covers hosts that hang
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start the worker process")?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let mut line = serde_json::to_vec(opts)?;
+    line.push(b'\n');
+    stdin.write_all(&line).await?;
+    child.stdin = Some(stdin);
+
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let booted = tokio::time::timeout(BOOT_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await? {
+            if line == READY_LINE {
+                return Ok(());
+            }
+            if let Some(err) = line.strip_prefix(ERROR_LINE_PREFIX) {
+                bail!("{}", err);
+            }
+            // console output of the worker while booting
+            println!("{}", line);
+        }
+        bail!("worker process exited before booting")
+    })
+    .await;
+    match booted {
+        Ok(result) => result?,
+        Err(_) => bail!("worker process did not boot within {:?}", BOOT_TIMEOUT),
+    }
+
+    // the rest of its output is passed through
+    let mut stdout = lines.into_inner();
+    tokio::task::spawn(async move {
+        let _ = tokio::io::copy(&mut stdout, &mut tokio::io::stdout()).await;
+    });
+    Ok(child)
+}
+
+async fn forward_request(socket_path: PathBuf, mut msg: WorkerRequestMsg) {
+    let mut stream = match UnixStream::connect(&socket_path).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!("failed to connect to the worker process: {}", err);
+            let res = problem_response(RuntimeErrorCode::WorkerUnavailable, None);
+            let _ = msg.res_tx.send(Ok(res));
+            return;
+        }
+    };
+    // the request's metadata is passed on the same way it's passed to worker threads
+    let metadata = msg.req.extensions_mut().remove::<RequestMetadata>();
+    if let Err(err) = write_metadata_frame(&mut stream, metadata.as_ref()).await {
+        error!(
+            "failed to send request metadata to the worker process: {}",
+            err
+        );
+        let res = problem_response(RuntimeErrorCode::WorkerUnavailable, None);
+        let _ = msg.res_tx.send(Ok(res));
+        return;
+    }
+    let (mut request_sender, connection) = match hyper::client::conn::handshake(stream).await {
+        Ok(handshake) => handshake,
+        Err(err) => {
+            let _ = msg.res_tx.send(Err(err));
+            return;
+        }
+    };
+    tokio::task::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Error in worker process connection: {}", e);
+        }
+    });
+
+    let _ = msg.res_tx.send(request_sender.send_request(msg.req).await);
+}
+
+// Boots the worker in a child process. Requests sent on the returned channel are
+// proxied to it, and its exit is reported to the pool like a worker thread's.
+pub async fn create_process_worker(
+    init_opts: WorkerContextInitOpts,
+) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
+    let Some(conf) = init_opts.conf.as_user_worker() else {
+        bail!("only user workers can run in a process of their own");
+    };
+    let key = conf.key.unwrap_or_else(Uuid::new_v4);
+    let pool_msg_tx = conf.pool_msg_tx.clone();
+
+    let host_dir = create_host_dir(key)?;
+    let socket_path = host_dir.join("worker.sock");
+    let result = async {
+        let maybe_eszip_path = match &init_opts.maybe_eszip {
+            Some(eszip) => {
+                let path = host_dir.join("worker.eszip");
+                tokio::fs::write(&path, eszip_bytes(eszip)).await?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        let host_opts = WorkerHostOpts::new(&init_opts, conf, maybe_eszip_path.clone());
+        let result = spawn_worker_host(&socket_path, &host_opts).await;
+        // read by the host while booting
+        if let Some(path) = maybe_eszip_path {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        result
+    }
+    .await;
+    let mut child = match result {
+        Ok(child) => child,
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&host_dir);
+            return Err(err);
+        }
+    };
+    let pid = child.id();
+    debug!(
+        "worker of {} runs in process {:?}",
+        init_opts.service_path.display(),
+        pid
+    );
+
+    let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+    tokio::task::spawn(async move {
+        loop {
+            tokio::select! {
+                msg = worker_req_rx.recv() => match msg {
+                    Some(msg) => {
+                        tokio::task::spawn(forward_request(socket_path.clone(), msg));
+                    }
+                    // the worker can't be reached anymore
+                    None => break,
+                },
+                status = child.wait() => {
+                    match status {
+                        Ok(status) => debug!("worker process {:?} exited: {}", pid, status),
+                        Err(err) => error!("failed to wait for worker process {:?}: {}", pid, err),
+                    }
+                    break;
+                }
+            }
+        }
+
+        let _ = child.kill().await;
+        let _ = std::fs::remove_dir_all(&host_dir);
+        if let Some(tx) = pool_msg_tx {
+            if tx.send(UserWorkerMsgs::Shutdown(key)).is_err() {
+                error!("user worker msgs receiver dropped")
+            }
+        }
+    });
+
+    Ok(worker_req_tx)
+}
+
+// Entry point of `edge-runtime worker-host`: boots the worker described on stdin
+// and serves it on `socket_path`, until the worker exits or stdin is closed
+pub async fn run_worker_host(socket_path: PathBuf) -> Result<(), Error> {
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    let mut line = String::new();
+    stdin.read_line(&mut line).await?;
+    let opts: WorkerHostOpts =
+        serde_json::from_str(&line).context("invalid worker host options")?;
+
+    cpu_timer::register_alarm()?;
+
+    let (pool_msg_tx, mut pool_msg_rx) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+    let boot = match opts.into_init_opts(Uuid::new_v4(), pool_msg_tx) {
+        Ok(init_opts) => create_worker(init_opts).await,
+        Err(err) => Err(err),
+    };
+    let worker_req_tx = match boot {
+        Ok(tx) => tx,
+        Err(err) => {
+            let msg = format!("{:#}", err).replace('\n', " ");
+            println!("{}{}", ERROR_LINE_PREFIX, msg);
+            return Err(err);
+        }
+    };
+
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)
+        .with_context(|| format!("failed to bind {}", socket_path.display()))?;
+    println!("{}", READY_LINE);
+
+    // closed when the runtime goes away
+    let (closed_tx, mut closed_rx) = oneshot::channel::<()>();
+    tokio::task::spawn(async move {
+        let mut rest = vec![];
+        let _ = stdin.read_to_end(&mut rest).await;
+        let _ = closed_tx.send(());
+    });
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut stream, _) = accepted?;
+                let worker_req_tx = worker_req_tx.clone();
+                tokio::task::spawn(async move {
+                    let metadata = match read_metadata_frame(&mut stream).await {
+                        Ok(metadata) => metadata,
+                        Err(err) => {
+                            debug!("worker host connection closed: {}", err);
+                            return;
+                        }
+                    };
+                    let service = service_fn(move |mut req: Request<Body>| {
+                        let worker_req_tx = worker_req_tx.clone();
+                        if let Some(metadata) = metadata.clone() {
+                            req.extensions_mut().insert(metadata);
+                        }
+                        async move {
+                            let res = send_user_worker_request(worker_req_tx, req)
+                                .await
+                                .unwrap_or_else(|err| {
+                                    error!("worker request failed: {:#}", err);
+                                    problem_response(RuntimeErrorCode::InternalError, None)
+                                });
+                            Ok::<_, Infallible>(res)
+                        }
+                    });
+                    if let Err(err) = Http::new().serve_connection(stream, service).await {
+                        debug!("worker host connection closed: {}", err);
+                    }
+                });
+            }
+            Some(msg) = pool_msg_rx.recv() => {
+                if matches!(msg, UserWorkerMsgs::Shutdown(_)) {
+                    break;
+                }
+            }
+            _ = &mut closed_rx => break,
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{create_host_dir, host_env_vars, WorkerHostOpts};
+    use deno_core::serde_json;
+    use sb_worker_context::essentials::{
+        UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerIsolation, WorkerRuntimeOpts,
+    };
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_worker_host_opts() {
+        let init_opts = WorkerContextInitOpts {
+            service_path: PathBuf::from("./test_cases/main"),
+            no_module_cache: false,
+            import_map_path: None,
+            env_vars: HashMap::from([("KEY".to_string(), "value".to_string())]),
+            events_rx: None,
+            conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                memory_limit_mb: 64,
+                worker_timeout_ms: 1000,
+                isolation: WorkerIsolation::Process,
+                ..Default::default()
+            }),
+            maybe_eszip: None,
+            maybe_module_code: Some("Deno.serve(() => new Response())".into()),
+            maybe_entrypoint: None,
+        };
+        let conf = init_opts.conf.as_user_worker().unwrap();
+        let json = serde_json::to_string(&WorkerHostOpts::new(&init_opts, conf, None)).unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let key = Uuid::new_v4();
+        let host_opts: WorkerHostOpts = serde_json::from_str(&json).unwrap();
+        let opts = host_opts.into_init_opts(key, tx).unwrap();
+        assert_eq!(opts.service_path, PathBuf::from("./test_cases/main"));
+        assert_eq!(opts.env_vars.get("KEY").unwrap(), "value");
+        assert!(opts.maybe_module_code.is_some());
+
+        let conf = opts.conf.as_user_worker().unwrap();
+        assert_eq!(conf.memory_limit_mb, 64);
+        assert_eq!(conf.worker_timeout_ms, 1000);
+        assert_eq!(conf.key, Some(key));
+        // the host runs the worker in a thread, it doesn't start another process
+        assert_eq!(conf.isolation, WorkerIsolation::Thread);
+    }
+
+    #[test]
+    fn test_worker_host_env() {
+        let env = [
+            ("PATH", "/usr/bin"),
+            ("PROCESS_WORKER_TEST_SECRET", "secret"),
+        ]
+        .map(|(name, value)| (OsString::from(name), OsString::from(value)));
+        assert_eq!(
+            host_env_vars(env.into_iter()),
+            vec![(OsString::from("PATH"), OsString::from("/usr/bin"))]
+        );
+    }
+
+    #[test]
+    fn test_worker_host_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let key = Uuid::new_v4();
+        let dir = create_host_dir(key).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // a directory that already exists may have been planted
+        assert!(create_host_dir(key).is_err());
+        std::fs::remove_dir(dir).unwrap();
+    }
+}
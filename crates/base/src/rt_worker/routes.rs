@@ -0,0 +1,322 @@
+use anyhow::Error;
+use deno_core::serde_json;
+use hyper::{Body, Request};
+use log::error;
+use sb_core::problem::{Problem, RuntimeErrorCode, PROBLEM_CONTENT_TYPE};
+use sb_worker_context::essentials::WorkerContextInitOpts;
+use sb_worker_context::request_metadata::RequestRoute;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// directory (relative to the service path) holding file-based routes
+pub const ROUTES_DIR: &str = "routes";
+
+const ROUTE_EXTENSIONS: [&str; 6] = ["ts", "tsx", "js", "jsx", "mjs", "mts"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    CatchAll(String),
+}
+
+impl Segment {
+    fn parse(value: &str) -> Self {
+        match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(name) => match name.strip_prefix("...") {
+                Some(rest) => Segment::CatchAll(rest.to_string()),
+                None => Segment::Param(name.to_string()),
+            },
+            None => Segment::Static(value.to_string()),
+        }
+    }
+
+    // lower ranks are matched first
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::CatchAll(_) => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    segments: Vec<Segment>,
+    // module path relative to the service path
+    module: String,
+}
+
+impl Route {
+    fn matches(&self, parts: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::CatchAll(name) => {
+                    params.insert(name.clone(), parts.get(i..)?.join("/"));
+                    return Some(params);
+                }
+                Segment::Static(value) => {
+                    if *parts.get(i)? != value.as_str() {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = urlencoding::decode(parts.get(i)?).ok()?;
+                    params.insert(name.clone(), value.into_owned());
+                }
+            }
+        }
+
+        if parts.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RouteMatch {
+    pub index: usize,
+    pub params: HashMap<String, String>,
+}
+
+// Maps request paths to route modules found under `<service>/routes`.
+//   routes/index.ts          -> /
+//   routes/users/index.ts    -> /users
+//   routes/users/[id].ts     -> /users/:id
+//   routes/docs/[...path].ts -> /docs/*
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    // request path prefix the service is mounted on (eg: /hello-world)
+    mount: Option<String>,
+    routes: Vec<Route>,
+}
+
+impl RouteTable {
+    pub fn from_paths(mount: Option<String>, paths: Vec<PathBuf>) -> Self {
+        let mut routes = vec![];
+        for path in paths {
+            let is_route = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ROUTE_EXTENSIONS.contains(&ext))
+                .unwrap_or(false);
+            if !is_route {
+                continue;
+            }
+
+            let mut segments: Vec<Segment> = path
+                .with_extension("")
+                .components()
+                .filter_map(|c| c.as_os_str().to_str())
+                .map(Segment::parse)
+                .collect();
+            if segments.last() == Some(&Segment::Static("index".to_string())) {
+                segments.pop();
+            }
+
+            let module = Path::new(ROUTES_DIR)
+                .join(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            routes.push(Route { segments, module });
+        }
+
+        // prefer static segments over params, and params over catch-alls
+        routes.sort_by_key(|r| r.segments.iter().map(Segment::rank).collect::<Vec<u8>>());
+
+        Self { mount, routes }
+    }
+
+    // Builds the route table for a service, if it has a routes directory
+    pub fn from_service_path(service_path: &Path) -> Result<Option<Self>, Error> {
+        let routes_dir = service_path.join(ROUTES_DIR);
+        if !routes_dir.is_dir() {
+            return Ok(None);
+        }
+
+        let mut paths = vec![];
+        let mut pending = vec![routes_dir.clone()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if let Ok(relative) = path.strip_prefix(&routes_dir) {
+                    paths.push(relative.to_path_buf());
+                }
+            }
+        }
+
+        let mount = service_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| format!("/{}", name));
+
+        Ok(Some(Self::from_paths(mount, paths)))
+    }
+
+    // Route modules, relative to the service directory
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|r| r.module.as_str())
+    }
+
+    pub fn match_path(&self, path: &str) -> Option<RouteMatch> {
+        let path = match &self.mount {
+            Some(mount) => match path.strip_prefix(mount.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+                _ => path,
+            },
+            None => path,
+        };
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+
+        self.routes.iter().enumerate().find_map(|(index, route)| {
+            route
+                .matches(&parts)
+                .map(|params| RouteMatch { index, params })
+        })
+    }
+
+    // Attaches the matched route to the request, passed to the generated
+    // entrypoint next to the request rather than in its headers. Returns false if
+    // no route matches the request.
+    pub fn annotate_request(&self, req: &mut Request<Body>) -> bool {
+        let Some(route_match) = self.match_path(req.uri().path()) else {
+            return false;
+        };
+
+        match serde_json::to_string(&route_match) {
+            Ok(json) => {
+                req.extensions_mut().insert(RequestRoute(json));
+            }
+            Err(err) => error!("failed to serialize the route params: {}", err),
+        }
+        true
+    }
+
+    // Entrypoint that dispatches to the default export of the matched route module,
+    // passing path params as the handler's context.
+    pub fn module_code(&self) -> String {
+        let mut code = String::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            let specifier = serde_json::to_string(&format!("./{}", route.module)).unwrap();
+            code.push_str(&format!("import * as route{} from {};\n", i, specifier));
+        }
+
+        let names: Vec<String> = (0..self.routes.len())
+            .map(|i| format!("route{}", i))
+            .collect();
+        code.push_str(&format!("const routes = [{}];\n", names.join(", ")));
+        let not_found = Problem::new(
+            RuntimeErrorCode::RouteNotFound,
+            Some("route module has no default export".to_string()),
+        );
+        let not_found = serde_json::to_string(&not_found).unwrap();
+        code.push_str(&format!(
+            r#"
+const requestRoute = globalThis[Symbol.for("edgeRuntime.requestRoute")];
+Deno.serve((req) => {{
+  const match = requestRoute(req);
+  const route = match ? routes[match.index] : undefined;
+  if (!route || typeof route.default !== "function") {{
+    return new Response({}, {{ status: 404, headers: {{ "content-type": "{}" }} }});
+  }}
+  return route.default(req, {{ params: match.params }});
+}});
+"#,
+            serde_json::to_string(&not_found).unwrap(),
+            PROBLEM_CONTENT_TYPE,
+        ));
+
+        code
+    }
+}
+
+// Generates an entrypoint for user services using file-based routing. Returns the
+// route table used to annotate requests sent to the worker.
+pub fn setup_routes(opts: &mut WorkerContextInitOpts) -> Result<Option<RouteTable>, Error> {
+    if !opts.conf.is_user_worker()
+        || opts.maybe_eszip.is_some()
+        || opts.maybe_entrypoint.is_some()
+        || opts.maybe_module_code.is_some()
+        || opts.service_path.join("index.ts").exists()
+    {
+        return Ok(None);
+    }
+
+    let Some(table) = RouteTable::from_service_path(&opts.service_path)? else {
+        return Ok(None);
+    };
+    opts.maybe_module_code = Some(table.module_code().into());
+
+    Ok(Some(table))
+}
+
+#[cfg(test)]
+mod test {
+    use super::RouteTable;
+    use deno_core::serde_json;
+    use hyper::{Body, Request};
+    use sb_worker_context::request_metadata::RequestRoute;
+    use std::path::PathBuf;
+
+    fn table() -> RouteTable {
+        RouteTable::from_paths(
+            Some("/svc".to_string()),
+            vec![
+                PathBuf::from("users/[id].ts"),
+                PathBuf::from("index.ts"),
+                PathBuf::from("users/me.ts"),
+                PathBuf::from("docs/[...path].ts"),
+                PathBuf::from("README.md"),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let table = table();
+
+        let m = table.match_path("/svc/users/42").unwrap();
+        assert_eq!(m.params.get("id").unwrap(), "42");
+
+        // static segments win over params
+        let m = table.match_path("/svc/users/me").unwrap();
+        assert!(m.params.is_empty());
+        assert_eq!(table.routes[m.index].module, "routes/users/me.ts");
+
+        let m = table.match_path("/svc/docs/a/b").unwrap();
+        assert_eq!(m.params.get("path").unwrap(), "a/b");
+
+        let m = table.match_path("/svc").unwrap();
+        assert_eq!(table.routes[m.index].module, "routes/index.ts");
+
+        assert!(table.match_path("/svc/users/1/posts").is_none());
+    }
+
+    #[test]
+    fn test_annotate_request() {
+        let table = table();
+
+        // decoded params that aren't valid header values are passed on as well
+        let mut req = Request::get("/svc/users/caf%C3%A9")
+            .body(Body::empty())
+            .unwrap();
+        assert!(table.annotate_request(&mut req));
+        let route = req.extensions().get::<RequestRoute>().unwrap();
+        let route: serde_json::Value = serde_json::from_str(&route.0).unwrap();
+        assert_eq!(route["params"]["id"], "café");
+
+        let mut req = Request::get("/svc/users/1/posts")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!table.annotate_request(&mut req));
+        assert!(req.extensions().get::<RequestRoute>().is_none());
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{bail, Error};
+use log::info;
+use module_fetcher::cache::DenoDir;
+use sb_worker_context::essentials::UserWorkerRuntimeOpts;
+use sb_worker_context::listen::ListenPermission;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Hardened mode: each user worker thread restricts itself with a seccomp filter
+// and Landlock rules right after it's spawned, before any user code is loaded.
+// Both apply to the calling thread (and threads it spawns) only, so the rest of
+// the runtime is unaffected.
+//
+// - seccomp denies syscalls a worker never needs (exec, ptrace, mounts, kernel
+//   modules, namespaces, ...) and, when its net access is disabled, creating
+//   IPv4/IPv6 sockets. The sockets the worker was granted to listen on are
+//   bound beforehand.
+// - Landlock limits the filesystem to reading the service (and the few system
+//   files TLS and DNS read) and writing the module cache
+//
+// seccomp is required, Landlock is skipped (with a warning) on kernels without it.
+
+// read by TLS and DNS resolution on the worker thread
+const SYSTEM_READ_PATHS: &[&str] = &[
+    "/etc/ssl",
+    "/etc/pki",
+    "/usr/share/ca-certificates",
+    "/etc/hosts",
+    "/etc/resolv.conf",
+    "/etc/nsswitch.conf",
+    "/dev/null",
+    "/dev/urandom",
+    "/proc/self",
+];
+
+static SANDBOX: OnceLock<SandboxConfig> = OnceLock::new();
+
+struct SandboxConfig {
+    // writable by every worker
+    write_paths: Vec<PathBuf>,
+}
+
+// What a single worker may access
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    pub read_paths: Vec<PathBuf>,
+    pub write_paths: Vec<PathBuf>,
+    pub net_access: bool,
+    // sockets the worker may listen on
+    pub listen: Vec<ListenPermission>,
+}
+
+impl SandboxPolicy {
+    pub fn for_worker(
+        service_path: &Path,
+        import_map_path: Option<&str>,
+        conf: &UserWorkerRuntimeOpts,
+    ) -> Option<Self> {
+        let config = SANDBOX.get()?;
+        let mut read_paths: Vec<PathBuf> = SYSTEM_READ_PATHS.iter().map(PathBuf::from).collect();
+        read_paths.push(service_path.to_path_buf());
+        if let Some(path) = import_map_path.filter(|p| Path::new(p).exists()) {
+            read_paths.push(PathBuf::from(path));
+        }
+        if let Some(root) = &conf.custom_module_root {
+            read_paths.push(PathBuf::from(root));
+        }
+        Some(Self {
+            read_paths,
+            write_paths: config.write_paths.clone(),
+            net_access: !conf.net_access_disabled,
+            listen: conf.listen.clone(),
+        })
+    }
+}
+
+// Sandboxes user workers from now on. Fails if the platform can't enforce it.
+pub fn enable_worker_sandbox(crash_report_dir: Option<&Path>) -> Result<(), Error> {
+    if !cfg!(target_os = "linux") {
+        bail!("worker sandboxing is only supported on linux");
+    }
+    let deno_dir = DenoDir::new(None)?;
+    let mut write_paths = vec![deno_dir.root_path().to_path_buf()];
+    if let Some(dir) = crash_report_dir {
+        write_paths.push(dir.to_path_buf());
+    }
+    if SANDBOX.set(SandboxConfig { write_paths }).is_err() {
+        bail!("worker sandboxing can only be enabled once per process");
+    }
+    info!("user workers are sandboxed with seccomp and landlock");
+    Ok(())
+}
+
+// Called on the worker's thread, before the runtime boots
+pub fn apply_worker_sandbox(policy: &SandboxPolicy) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        // bound while the worker can still create sockets
+        if !policy.net_access {
+            sb_core::raw_net::prebind_sockets(&policy.listen)?;
+        }
+        linux::set_no_new_privs()?;
+        if !linux::apply_landlock(policy)? {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                log::warn!("landlock is unavailable, worker filesystem access isn't restricted")
+            });
+        }
+        linux::apply_seccomp(policy.net_access)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = policy;
+        bail!("worker sandboxing is only supported on linux")
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SandboxPolicy;
+    use anyhow::{bail, Context, Error};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn last_error() -> Error {
+        std::io::Error::last_os_error().into()
+    }
+
+    pub fn set_no_new_privs() -> Result<(), Error> {
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(last_error()).context("failed to set no_new_privs");
+        }
+        Ok(())
+    }
+
+    // Landlock, see landlock(7). Syscall numbers are shared by all architectures.
+    const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+    const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+    const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+    const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+    const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    // every right of ABI v1
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    // rights that apply to files (as opposed to directories)
+    const ACCESS_FILE: u64 =
+        ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    fn landlock_abi() -> libc::c_long {
+        unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        }
+    }
+
+    pub(super) fn handled_access(abi: libc::c_long) -> u64 {
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        handled
+    }
+
+    fn add_path_rule(ruleset_fd: i32, path: &std::path::Path, access: u64) -> Result<(), Error> {
+        // paths that don't exist on this host are skipped
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return Ok(());
+        };
+        let access = if metadata.is_dir() {
+            access
+        } else {
+            access & ACCESS_FILE
+        };
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(last_error()).with_context(|| format!("failed to open {}", path.display()));
+        }
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: fd,
+        };
+        let res = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                ruleset_fd,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0u32,
+            )
+        };
+        let err = last_error();
+        unsafe { libc::close(fd) };
+        if res != 0 {
+            return Err(err).with_context(|| format!("failed to allow {}", path.display()));
+        }
+        Ok(())
+    }
+
+    // Returns false if the kernel doesn't support landlock
+    pub fn apply_landlock(policy: &SandboxPolicy) -> Result<bool, Error> {
+        let abi = landlock_abi();
+        if abi < 1 {
+            return Ok(false);
+        }
+        let handled = handled_access(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let ruleset_fd = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if ruleset_fd < 0 {
+            return Err(last_error()).context("failed to create the landlock ruleset");
+        }
+        let ruleset_fd = ruleset_fd as i32;
+
+        let result = (|| {
+            for path in &policy.read_paths {
+                add_path_rule(ruleset_fd, path, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR)?;
+            }
+            for path in &policy.write_paths {
+                add_path_rule(ruleset_fd, path, handled)?;
+            }
+            if unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset_fd, 0u32) } != 0 {
+                return Err(last_error()).context("failed to enforce the landlock ruleset");
+            }
+            Ok(())
+        })();
+        unsafe { libc::close(ruleset_fd) };
+        result.map(|_| true)
+    }
+
+    // classic BPF, see seccomp(2)
+    // BPF_LD | BPF_W | BPF_ABS
+    const BPF_LD_W_ABS: u16 = 0x20;
+    // BPF_JMP | BPF_JEQ | BPF_K
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    // BPF_JMP | BPF_JGE | BPF_K
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    // BPF_RET | BPF_K
+    const BPF_RET_K: u16 = 0x06;
+    pub(super) const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    pub(super) const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    // offsets in `struct seccomp_data`
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+    // low half of the first argument (little endian)
+    const DATA_ARG0: u32 = 16;
+
+    const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+    // x32 syscalls run with the x86_64 arch, their numbers have this bit set
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(AUDIT_ARCH_X86_64);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    pub(super) const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_userfaultfd,
+        libc::SYS_personality,
+    ];
+
+    fn stmt(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    // jumps over the next instruction unless the accumulator equals `k`
+    fn jeq_next(k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: BPF_JMP_JEQ_K,
+            jt: 0,
+            jf: 1,
+            k,
+        }
+    }
+
+    pub(super) fn seccomp_program(arch: u32, deny_inet: bool) -> Vec<libc::sock_filter> {
+        let deny = stmt(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        let mut program = vec![
+            stmt(BPF_LD_W_ABS, DATA_ARCH),
+            // syscall numbers of other ABIs aren't checked below
+            libc::sock_filter {
+                code: BPF_JMP_JEQ_K,
+                jt: 1,
+                jf: 0,
+                k: arch,
+            },
+            deny,
+            stmt(BPF_LD_W_ABS, DATA_NR),
+        ];
+        if arch == AUDIT_ARCH_X86_64 {
+            program.push(libc::sock_filter {
+                code: BPF_JMP_JGE_K,
+                jt: 0,
+                jf: 1,
+                k: X32_SYSCALL_BIT,
+            });
+            program.push(deny);
+        }
+        for nr in DENIED_SYSCALLS {
+            program.push(jeq_next(*nr as u32));
+            program.push(deny);
+        }
+        if deny_inet {
+            // io_uring can open sockets without calling socket(2)
+            program.push(jeq_next(libc::SYS_io_uring_setup as u32));
+            program.push(deny);
+            program.push(libc::sock_filter {
+                code: BPF_JMP_JEQ_K,
+                jt: 0,
+                jf: 5,
+                k: libc::SYS_socket as u32,
+            });
+            program.push(stmt(BPF_LD_W_ABS, DATA_ARG0));
+            program.push(jeq_next(libc::AF_INET as u32));
+            program.push(deny);
+            program.push(jeq_next(libc::AF_INET6 as u32));
+            program.push(deny);
+        }
+        program.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+        program
+    }
+
+    pub fn apply_seccomp(net_access: bool) -> Result<(), Error> {
+        let Some(arch) = AUDIT_ARCH else {
+            bail!("seccomp filtering isn't supported on this architecture");
+        };
+        let program = seccomp_program(arch, !net_access);
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut libc::sock_filter,
+        };
+        let res = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+        if res != 0 {
+            bail!("failed to install the seccomp filter: {}", last_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::linux::{
+        handled_access, seccomp_program, DENIED_SYSCALLS, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO,
+    };
+
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+
+    // Runs the subset of classic BPF the filter uses
+    fn run(program: &[libc::sock_filter], nr: u32, arch: u32, arg0: u32) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            pc += 1;
+            match insn.code {
+                0x20 => {
+                    acc = match insn.k {
+                        0 => nr,
+                        4 => arch,
+                        16 => arg0,
+                        k => panic!("unexpected offset {}", k),
+                    }
+                }
+                0x15 => {
+                    let offset = if acc == insn.k { insn.jt } else { insn.jf };
+                    pc += offset as usize;
+                }
+                0x35 => {
+                    let offset = if acc >= insn.k { insn.jt } else { insn.jf };
+                    pc += offset as usize;
+                }
+                0x06 => return insn.k,
+                code => panic!("unexpected instruction {:#x}", code),
+            }
+        }
+    }
+
+    #[test]
+    fn test_seccomp_program() {
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let program = seccomp_program(AUDIT_ARCH, true);
+
+        assert_eq!(
+            run(&program, libc::SYS_read as u32, AUDIT_ARCH, 0),
+            SECCOMP_RET_ALLOW
+        );
+        for nr in DENIED_SYSCALLS {
+            assert_eq!(run(&program, *nr as u32, AUDIT_ARCH, 0), deny);
+        }
+        assert_eq!(run(&program, libc::SYS_read as u32, 0x4000_0003, 0), deny);
+        // x32 numbers of allowed and denied syscalls alike
+        for nr in [libc::SYS_read, libc::SYS_execve] {
+            let x32 = nr as u32 | 0x4000_0000;
+            assert_eq!(run(&program, x32, AUDIT_ARCH, 0), deny);
+        }
+        assert_eq!(
+            run(&program, libc::SYS_io_uring_setup as u32, AUDIT_ARCH, 0),
+            deny
+        );
+
+        let socket = libc::SYS_socket as u32;
+        assert_eq!(
+            run(&program, socket, AUDIT_ARCH, libc::AF_INET6 as u32),
+            deny
+        );
+        assert_eq!(
+            run(&program, socket, AUDIT_ARCH, libc::AF_UNIX as u32),
+            SECCOMP_RET_ALLOW
+        );
+
+        let program = seccomp_program(AUDIT_ARCH, false);
+        assert_eq!(
+            run(&program, socket, AUDIT_ARCH, libc::AF_INET as u32),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            run(&program, libc::SYS_io_uring_setup as u32, AUDIT_ARCH, 0),
+            SECCOMP_RET_ALLOW
+        );
+    }
+
+    #[test]
+    fn test_landlock_handled_access() {
+        assert_eq!(handled_access(1), (1 << 13) - 1);
+        assert_eq!(handled_access(3), (1 << 15) - 1);
+    }
+}
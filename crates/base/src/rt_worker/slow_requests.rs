@@ -0,0 +1,161 @@
+use event_worker::events::{
+    EventMetadata, SlowRequestEvent, WorkerEventWithMetadata, WorkerEvents,
+};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// a request sent this soon after its worker booted waited for the boot
+pub const BOOT_WAIT_WINDOW: Duration = Duration::from_secs(1);
+
+static SLOW_REQUEST_THRESHOLD: OnceLock<Duration> = OnceLock::new();
+
+// Requests to user workers taking longer than this are reported with a
+// `SlowRequest` event
+pub fn set_slow_request_threshold(threshold: Duration) {
+    let _ = SLOW_REQUEST_THRESHOLD.set(threshold);
+}
+
+pub fn slow_request_threshold() -> Option<Duration> {
+    SLOW_REQUEST_THRESHOLD.get().copied()
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+// Phases of a request to a user worker. Moved into the response body, the
+// request is reported once dropped (when the body was sent) if it was slow.
+pub struct RequestTimings {
+    threshold: Duration,
+    events_tx: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    metadata: EventMetadata,
+    method: String,
+    path: String,
+    status: Option<u16>,
+    boot: Duration,
+    queue: Duration,
+    exec: Duration,
+    // when the response head was received
+    responded: Option<Instant>,
+}
+
+impl RequestTimings {
+    pub fn new(
+        threshold: Duration,
+        events_tx: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+        metadata: EventMetadata,
+        method: String,
+        path: String,
+        boot: Duration,
+    ) -> Self {
+        Self {
+            threshold,
+            events_tx,
+            metadata,
+            method,
+            path,
+            status: None,
+            boot,
+            queue: Duration::ZERO,
+            exec: Duration::ZERO,
+            responded: None,
+        }
+    }
+
+    pub fn queued(&mut self, since: Instant) {
+        self.queue = since.elapsed();
+    }
+
+    pub fn responded(&mut self, sent: Instant, status: Option<u16>) {
+        self.exec = sent.elapsed();
+        self.status = status;
+        self.responded = Some(Instant::now());
+    }
+
+    fn event(&self) -> Option<SlowRequestEvent> {
+        let write = self
+            .responded
+            .map(|responded| responded.elapsed())
+            .unwrap_or_default();
+        let total = self.boot + self.queue + self.exec + write;
+        if total < self.threshold {
+            return None;
+        }
+        Some(SlowRequestEvent {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            status: self.status,
+            total_ms: millis(total),
+            boot_ms: millis(self.boot),
+            queue_ms: millis(self.queue),
+            exec_ms: millis(self.exec),
+            write_ms: millis(write),
+        })
+    }
+}
+
+impl Drop for RequestTimings {
+    fn drop(&mut self) {
+        if let Some(event) = self.event() {
+            let _ = self.events_tx.send(WorkerEventWithMetadata {
+                event: WorkerEvents::SlowRequest(event),
+                metadata: self.metadata.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RequestTimings;
+    use event_worker::events::{EventMetadata, WorkerEventWithMetadata, WorkerEvents};
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc;
+
+    fn timings(
+        threshold: Duration,
+        boot: Duration,
+    ) -> (
+        RequestTimings,
+        mpsc::UnboundedReceiver<WorkerEventWithMetadata>,
+    ) {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let metadata = EventMetadata {
+            service_path: Some("hello".to_string()),
+            ..Default::default()
+        };
+        let timings = RequestTimings::new(
+            threshold,
+            events_tx,
+            metadata,
+            "GET".to_string(),
+            "/hello".to_string(),
+            boot,
+        );
+        (timings, events_rx)
+    }
+
+    #[test]
+    fn test_slow_requests_are_reported() {
+        let (mut fast, mut events_rx) = timings(Duration::from_secs(60), Duration::ZERO);
+        fast.responded(Instant::now(), Some(200));
+        drop(fast);
+        assert!(events_rx.try_recv().is_err());
+
+        let (mut slow, mut events_rx) =
+            timings(Duration::from_millis(100), Duration::from_millis(150));
+        slow.queued(Instant::now());
+        slow.responded(Instant::now(), Some(200));
+        drop(slow);
+        let event = events_rx.try_recv().unwrap();
+        assert_eq!(event.metadata.service_path.as_deref(), Some("hello"));
+        let WorkerEvents::SlowRequest(slow) = event.event else {
+            panic!("expected a slow request event");
+        };
+        assert_eq!(slow.status, Some(200));
+        assert_eq!(slow.boot_ms, 150);
+        assert!(slow.total_ms >= 150);
+        assert_eq!(slow.path, "/hello");
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Error;
+use deno_core::error::JsError;
+use deno_core::{JsRuntime, ModuleCode};
+use event_worker::events::{
+    EventMetadata, UncaughtErrorEvent, UncaughtErrorKind, UncaughtErrorPolicy,
+    WorkerEventWithMetadata, WorkerEvents,
+};
+use log::error;
+use tokio::sync::mpsc;
+
+// answers the requests being served with a 500, see `failInflightRequests` in
+// http.js
+const FAIL_INFLIGHT_REQUESTS: &str =
+    "globalThis[Symbol.for('edgeRuntime.failInflightRequests')]?.() ?? 0";
+
+// The kind of an error the event loop stopped on, if it's an uncaught error the
+// worker can survive (not a termination or a runtime error)
+pub fn survivable_error_kind(err: &Error, terminating: bool) -> Option<UncaughtErrorKind> {
+    if terminating {
+        return None;
+    }
+    let js_error = err.downcast_ref::<JsError>()?;
+    let message = js_error.exception_message.as_str();
+    if message.ends_with("execution terminated") {
+        return None;
+    }
+    Some(if message.starts_with("Uncaught (in promise)") {
+        UncaughtErrorKind::UnhandledRejection
+    } else {
+        UncaughtErrorKind::Exception
+    })
+}
+
+// Applies the `log-only` or `fail-request` policy to an uncaught error, reporting
+// it with an `UncaughtError` event
+pub fn handle_uncaught_error(
+    js_runtime: &mut JsRuntime,
+    err: &Error,
+    kind: UncaughtErrorKind,
+    policy: UncaughtErrorPolicy,
+) {
+    let failed_requests = if policy == UncaughtErrorPolicy::FailRequest {
+        fail_inflight_requests(js_runtime)
+    } else {
+        0
+    };
+    let event = UncaughtErrorEvent {
+        kind,
+        message: err.to_string(),
+        policy,
+        failed_requests,
+    };
+
+    let op_state_rc = js_runtime.op_state();
+    let op_state = op_state_rc.borrow();
+    match op_state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>() {
+        Some(events_tx) => {
+            let metadata = op_state
+                .try_borrow::<EventMetadata>()
+                .cloned()
+                .unwrap_or_default();
+            let _ = events_tx.send(WorkerEventWithMetadata {
+                event: WorkerEvents::UncaughtError(event),
+                metadata,
+            });
+        }
+        None => error!("{:?}: {}", event.kind, event.message),
+    }
+}
+
+fn fail_inflight_requests(js_runtime: &mut JsRuntime) -> usize {
+    let result = js_runtime.execute_script(
+        "ext:uncaught_errors",
+        ModuleCode::from_static(FAIL_INFLIGHT_REQUESTS),
+    );
+    let Ok(value) = result else {
+        return 0;
+    };
+    let scope = &mut js_runtime.handle_scope();
+    let value = deno_core::v8::Local::new(scope, value);
+    value.uint32_value(scope).unwrap_or(0) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::survivable_error_kind;
+    use anyhow::anyhow;
+    use deno_core::{JsRuntime, ModuleCode};
+    use event_worker::events::UncaughtErrorKind;
+
+    #[test]
+    fn test_survivable_error_kind() {
+        let mut js_runtime = JsRuntime::new(Default::default());
+        let err = js_runtime
+            .execute_script("<anon>", ModuleCode::from_static("throw new Error('boom')"))
+            .unwrap_err();
+        assert_eq!(
+            survivable_error_kind(&err, false),
+            Some(UncaughtErrorKind::Exception)
+        );
+        // the worker is being terminated anyway
+        assert_eq!(survivable_error_kind(&err, true), None);
+        // not thrown by JS
+        assert_eq!(
+            survivable_error_kind(&anyhow!("wall clock duration reached"), false),
+            None
+        );
+    }
+}
@@ -32,12 +32,14 @@ pub fn get_event_metadata(conf: &WorkerRuntimeOpts) -> EventMetadata {
     let mut event_metadata = EventMetadata {
         service_path: None,
         execution_id: None,
+        ..Default::default()
     };
     if conf.is_user_worker() {
         let conf = conf.as_user_worker().unwrap();
         event_metadata = EventMetadata {
             service_path: conf.service_path.clone(),
             execution_id: conf.key,
+            ..Default::default()
         };
     }
 
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Tracks how long the current event loop poll has been running. A poll that
+// doesn't return (eg: an infinite synchronous loop) means the event loop is hung.
+// An idle event loop isn't polled, so it's never reported as hung.
+#[derive(Debug, Clone)]
+pub struct EventLoopWatch {
+    origin: Instant,
+    // ms since `origin` when the current poll started (+1), or 0 when not polling
+    busy_since: Arc<AtomicU64>,
+}
+
+impl Default for EventLoopWatch {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            busy_since: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl EventLoopWatch {
+    pub fn enter(&self) {
+        self.enter_at(self.origin.elapsed());
+    }
+
+    pub fn exit(&self) {
+        self.busy_since.store(0, Ordering::Relaxed);
+    }
+
+    // how long the current poll has been running
+    pub fn busy_for(&self) -> Option<Duration> {
+        self.busy_for_at(self.origin.elapsed())
+    }
+
+    pub fn is_hung(&self, threshold: Duration) -> bool {
+        self.is_hung_at(self.origin.elapsed(), threshold)
+    }
+
+    // the same, `elapsed` being the time since `origin`
+    fn enter_at(&self, elapsed: Duration) {
+        let now = elapsed.as_millis() as u64 + 1;
+        self.busy_since.store(now, Ordering::Relaxed);
+    }
+
+    fn busy_for_at(&self, elapsed: Duration) -> Option<Duration> {
+        match self.busy_since.load(Ordering::Relaxed) {
+            0 => None,
+            since => {
+                let now = elapsed.as_millis() as u64 + 1;
+                Some(Duration::from_millis(now.saturating_sub(since)))
+            }
+        }
+    }
+
+    fn is_hung_at(&self, elapsed: Duration, threshold: Duration) -> bool {
+        self.busy_for_at(elapsed)
+            .map(|d| d > threshold)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EventLoopWatch;
+    use std::time::Duration;
+
+    #[test]
+    fn test_event_loop_watch() {
+        let ms = Duration::from_millis;
+        let watch = EventLoopWatch::default();
+        let threshold = ms(20);
+        assert!(!watch.is_hung_at(ms(100), threshold));
+
+        watch.enter_at(ms(5));
+        assert_eq!(watch.busy_for_at(ms(15)), Some(ms(10)));
+        assert!(!watch.is_hung_at(ms(25), threshold));
+        assert!(watch.is_hung_at(ms(26), threshold));
+
+        // an idle event loop is never hung
+        watch.exit();
+        assert!(!watch.is_hung_at(ms(100), threshold));
+        assert_eq!(watch.busy_for_at(ms(100)), None);
+    }
+}
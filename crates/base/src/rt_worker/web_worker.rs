@@ -0,0 +1,215 @@
+use crate::deno_runtime::DenoRuntime;
+use crate::rt_worker::worker::TerminationReason;
+use crate::rt_worker::worker_ctx::create_supervisor;
+use crate::usage::{WorkerUsageMeter, WORKER_USAGE_INTERVAL};
+use crate::utils::units::mib_to_bytes;
+use anyhow::Error;
+use cpu_timer::get_thread_time;
+use deno_core::SharedArrayBufferStore;
+use log::debug;
+use sb_worker_context::essentials::{
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use sb_worker_context::usage::UsageCollector;
+use sb_worker_context::web_workers::{
+    WebWorkerEvent, WebWorkerSpawnOpts, WebWorkers, WorkerBudget, WorkerBudgetAlarms,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// Web workers (`new Worker()`) of a user worker run in threads of their own,
+// booted from the same service with the limits and permissions of the user
+// worker. Their threads are spawned from the user worker's thread, so they also
+// inherit its cgroup and sandbox. A web worker can't spawn web workers itself.
+//
+// Web workers have no budget of their own: their CPU alarms count towards the
+// bursts of the user worker, their heap towards its memory limit, and the user
+// worker is terminated (along with them) once either is exceeded. Their CPU time
+// is billed to the service of the user worker, whose memory is already billed
+// from its limit.
+
+// What web workers spawned by a user worker boot with
+struct WebWorkerTemplate {
+    service_path: PathBuf,
+    no_module_cache: bool,
+    import_map_path: Option<String>,
+    env_vars: HashMap<String, String>,
+    conf: UserWorkerRuntimeOpts,
+    // the usage of the user worker, and its service
+    usage: Option<(UsageCollector, String)>,
+}
+
+impl WebWorkerTemplate {
+    fn init_opts(&self, spawn: &WebWorkerSpawnOpts) -> WorkerContextInitOpts {
+        let conf = UserWorkerRuntimeOpts {
+            // not a worker of the pool
+            key: None,
+            pool_msg_tx: None,
+            usage: None,
+            max_web_workers: 0,
+            web_worker: Some(spawn.port.clone()),
+            ..self.conf.clone()
+        };
+        WorkerContextInitOpts {
+            service_path: self.service_path.clone(),
+            no_module_cache: self.no_module_cache,
+            import_map_path: self.import_map_path.clone(),
+            env_vars: self.env_vars.clone(),
+            events_rx: None,
+            conf: WorkerRuntimeOpts::UserWorker(conf),
+            maybe_eszip: None,
+            maybe_module_code: None,
+            maybe_entrypoint: Some(spawn.specifier.clone()),
+        }
+    }
+}
+
+// Web workers the user worker booting with these options can spawn, if any,
+// and the alarms of their budget. Bundled (eszip) services can't spawn web
+// workers yet.
+pub fn web_workers_for(
+    opts: &WorkerContextInitOpts,
+    shared_array_buffers: &SharedArrayBufferStore,
+) -> Option<(WebWorkers, WorkerBudgetAlarms)> {
+    let conf = opts.conf.as_user_worker()?;
+    if conf.web_worker.is_some() || conf.max_web_workers == 0 || opts.maybe_eszip.is_some() {
+        return None;
+    }
+
+    let template = Arc::new(WebWorkerTemplate {
+        service_path: opts.service_path.clone(),
+        no_module_cache: opts.no_module_cache,
+        import_map_path: opts.import_map_path.clone(),
+        env_vars: opts.env_vars.clone(),
+        conf: conf.clone(),
+        usage: conf.usage.clone().zip(conf.service_path.clone()),
+    });
+    let (budget, alarms) = WorkerBudget::new(mib_to_bytes(conf.memory_limit_mb) as usize);
+    let web_workers = WebWorkers::new(
+        conf.max_web_workers,
+        shared_array_buffers.clone(),
+        budget,
+        Arc::new(move |spawn| {
+            spawn_web_worker(template.init_opts(&spawn), spawn, template.usage.clone())
+        }),
+    );
+    Some((web_workers, alarms))
+}
+
+fn spawn_web_worker(
+    opts: WorkerContextInitOpts,
+    spawn: WebWorkerSpawnOpts,
+    maybe_usage: Option<(UsageCollector, String)>,
+) -> Result<(), Error> {
+    let thread_name = format!("sb-web-worker-{}", spawn.port.name);
+    let WebWorkerSpawnOpts {
+        specifier,
+        port,
+        terminate_rx,
+        slot,
+    } = spawn;
+    let (isolate_handle_tx, isolate_handle_rx) = oneshot::channel();
+
+    thread::Builder::new().name(thread_name).spawn(move || {
+        let _slot = slot;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+
+        local.block_on(&runtime, async move {
+            let mut web_worker = match DenoRuntime::new(opts).await {
+                Ok(web_worker) => web_worker,
+                Err(err) => {
+                    let _ = port
+                        .to_parent
+                        .send(WebWorkerEvent::Error(format!("{:#}", err)));
+                    return;
+                }
+            };
+            let _ = isolate_handle_tx.send(web_worker.js_runtime.v8_isolate().thread_safe_handle());
+
+            let termination_reason = TerminationReason::default();
+            let (termination_event_tx, _termination_event_rx) = oneshot::channel();
+            // web workers don't serve requests, so have no deadlines to miss
+            let (_, deadline_missed_rx) = mpsc::unbounded_channel();
+            // kept in scope for as long as the worker runs
+            let _cputimer = match create_supervisor(
+                Uuid::new_v4(),
+                &mut web_worker,
+                termination_event_tx,
+                termination_reason.clone(),
+                None,
+                deadline_missed_rx,
+            ) {
+                Ok(cputimer) => cputimer,
+                Err(err) => {
+                    let _ = port
+                        .to_parent
+                        .send(WebWorkerEvent::Error(format!("{:#}", err)));
+                    return;
+                }
+            };
+
+            // CPU time only, the memory is the user worker's
+            let mut usage_meter = maybe_usage.and_then(|(usage, service_path)| {
+                Some(WorkerUsageMeter::new(
+                    usage,
+                    service_path,
+                    0,
+                    get_thread_time().ok()?,
+                    Instant::now(),
+                    Some(web_worker.module_downloads.clone()),
+                ))
+            });
+
+            let (_unix_stream_tx, unix_stream_rx) = mpsc::unbounded_channel();
+            let run = web_worker.run(unix_stream_rx);
+            tokio::pin!(run);
+            let mut ticker = tokio::time::interval(WORKER_USAGE_INTERVAL);
+            // first tick completes immediately
+            ticker.tick().await;
+            let result = loop {
+                tokio::select! {
+                    result = &mut run => break result,
+                    _ = ticker.tick() => {
+                        if let (Some(meter), Ok(now)) = (usage_meter.as_mut(), get_thread_time()) {
+                            meter.record(now, Instant::now());
+                        }
+                    }
+                }
+            };
+            if let (Some(meter), Ok(now)) = (usage_meter.as_mut(), get_thread_time()) {
+                meter.record(now, Instant::now());
+            }
+            if let Err(err) = result {
+                let message = match *termination_reason.lock().unwrap() {
+                    Some(reason) => format!("web worker terminated: {:?} limit reached", reason),
+                    None => format!("{:#}", err),
+                };
+                let _ = port.to_parent.send(WebWorkerEvent::Error(message));
+            }
+            debug!("web worker {} exited", specifier);
+        });
+    })?;
+
+    // terminates the worker once its parent terminates it (or is gone), even
+    // while it's running JS
+    thread::Builder::new()
+        .name("sb-web-worker-terminator".to_string())
+        .spawn(move || {
+            let _ = terminate_rx.blocking_recv();
+            if let Ok(handle) = isolate_handle_rx.blocking_recv() {
+                if !handle.terminate_execution() {
+                    debug!("web worker already exited");
+                }
+            }
+        })?;
+    Ok(())
+}
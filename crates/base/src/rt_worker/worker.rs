@@ -1,25 +1,38 @@
 use crate::deno_runtime::DenoRuntime;
+use crate::fault_injection::inject_cpu_exhaustion;
+use crate::rt_worker::cgroups::{join_service_cgroup, ServiceCgroupLimits, DEFAULT_CPU_WEIGHT};
+use crate::rt_worker::crash::{install_panic_hook, CrashReport};
+use crate::rt_worker::sandbox::{apply_worker_sandbox, SandboxPolicy};
 use crate::rt_worker::utils::{get_event_metadata, parse_worker_conf};
 use crate::rt_worker::worker_ctx::create_supervisor;
+use crate::usage::{WorkerUsageMeter, WORKER_USAGE_INTERVAL};
 use crate::utils::send_event_if_event_worker_available;
 use anyhow::{anyhow, Error};
 use cpu_timer::get_thread_time;
 use event_worker::events::{
-    EventMetadata, ShutdownEvent, UncaughtExceptionEvent, WorkerEventWithMetadata, WorkerEvents,
+    BootModule, EventMetadata, ShutdownEvent, ShutdownReason, UncaughtExceptionEvent,
+    WorkerEventWithMetadata, WorkerEvents,
 };
 use log::{debug, error};
 use sb_worker_context::essentials::{UserWorkerMsgs, WorkerContextInitOpts};
+use sb_worker_context::request_metadata::WorkerConnection;
 use std::any::Any;
+use std::cell::RefCell;
 use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tokio::net::UnixStream;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::{Receiver, Sender};
 use tokio::time::Instant;
 use uuid::Uuid;
 
+// Set by the supervisor when it terminates a worker for exceeding a limit
+pub type TerminationReason = Arc<Mutex<Option<ShutdownReason>>>;
+
 #[derive(Clone)]
 pub struct Worker {
     pub worker_boot_start_time: Instant,
@@ -28,6 +41,7 @@ pub struct Worker {
     pub event_metadata: EventMetadata,
     pub worker_key: Option<Uuid>,
     pub thread_name: String,
+    pub termination_reason: TerminationReason,
 }
 
 pub type HandleCreationType = Pin<Box<dyn Future<Output = Result<WorkerEvents, Error>>>>;
@@ -37,7 +51,7 @@ pub trait WorkerHandler: Send {
     fn handle_creation(
         &self,
         created_rt: DenoRuntime,
-        unix_stream_rx: UnboundedReceiver<UnixStream>,
+        unix_stream_rx: UnboundedReceiver<WorkerConnection>,
         termination_event_rx: Receiver<WorkerEvents>,
     ) -> HandleCreationType;
     fn as_any(&self) -> &dyn Any;
@@ -58,111 +72,239 @@ impl Worker {
             event_metadata,
             worker_key,
             thread_name,
+            termination_reason: TerminationReason::default(),
         })
     }
 
     pub fn start(
         &self,
         opts: WorkerContextInitOpts,
-        unix_channel_rx: UnboundedReceiver<UnixStream>,
-        booter_signal: Sender<Result<(), Error>>,
+        unix_channel_rx: UnboundedReceiver<WorkerConnection>,
+        booter_signal: Sender<Result<Vec<BootModule>, Error>>,
+        exit_signal: Sender<()>,
+        deadline_missed_rx: UnboundedReceiver<()>,
     ) {
+        install_panic_hook();
+
         let thread_name = self.thread_name.clone();
         let events_msg_tx = self.events_msg_tx.clone();
         let event_metadata = self.event_metadata.clone();
         let worker_key = self.worker_key;
         let pool_msg_tx = self.pool_msg_tx.clone();
         let method_cloner = self.clone();
+        let worker_boot_start_time = self.worker_boot_start_time;
+        let termination_reason = self.termination_reason.clone();
+        let maybe_usage = opts.conf.as_user_worker().and_then(|conf| {
+            conf.usage
+                .clone()
+                .zip(conf.service_path.clone())
+                .map(|(usage, service_path)| (usage, service_path, conf.memory_limit_mb))
+        });
+        let maybe_cgroup = opts.conf.as_user_worker().and_then(|conf| {
+            conf.service_path.clone().map(|service_path| {
+                let limits = ServiceCgroupLimits {
+                    cpu_weight: conf.cpu_weight.unwrap_or(DEFAULT_CPU_WEIGHT),
+                    memory_limit_mb: conf.memory_limit_mb,
+                };
+                (service_path, limits)
+            })
+        });
+        let maybe_sandbox = opts.conf.as_user_worker().and_then(|conf| {
+            SandboxPolicy::for_worker(&opts.service_path, opts.import_map_path.as_deref(), conf)
+        });
+
+        // kept aside to report the crash if the worker thread panics
+        let crash_thread_name = thread_name.clone();
+        let crash_events_msg_tx = events_msg_tx.clone();
+        let crash_event_metadata = event_metadata.clone();
+        let crash_pool_msg_tx = pool_msg_tx.clone();
 
         let _handle: thread::JoinHandle<Result<(), Error>> = thread::Builder::new()
             .name(thread_name)
             .spawn(move || {
-                let runtime = tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-                let local = tokio::task::LocalSet::new();
-
-                let mut start_time = 0;
-
-                let result: Result<WorkerEvents, Error> = local.block_on(&runtime, async {
-                    match DenoRuntime::new(opts).await {
-                        Ok(mut new_runtime) => {
-                            let _ = booter_signal.send(Ok(()));
-
-                            // CPU TIMER
-                            let (termination_event_tx, termination_event_rx) =
-                                oneshot::channel::<WorkerEvents>();
-                            let _cputimer;
-
-                            // TODO: Allow customization of supervisor
-                            if new_runtime.conf.is_user_worker() {
-                                // cputimer is returned from supervisor and assigned here to keep it in scope.
-                                _cputimer = create_supervisor(
-                                    worker_key.unwrap_or(Uuid::nil()),
-                                    &mut new_runtime,
-                                    termination_event_tx,
-                                    pool_msg_tx.clone(),
-                                )?;
+                // dropped when the thread exits, including on panics
+                let _exit_signal = exit_signal;
+
+                if let Some((service_path, limits)) = maybe_cgroup {
+                    join_service_cgroup(&service_path, limits);
+                }
+
+                let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .unwrap();
+                    let local = tokio::task::LocalSet::new();
+
+                    let mut start_time = 0;
+                    let mut module_loads = None;
+                    let usage_meter: Rc<RefCell<Option<WorkerUsageMeter>>> = Rc::default();
+
+                    let result: Result<WorkerEvents, Error> = local.block_on(&runtime, async {
+                        // no user code runs before the worker is sandboxed
+                        let boot = match maybe_sandbox.as_ref().map(apply_worker_sandbox) {
+                            Some(Err(err)) => Err(err),
+                            _ => DenoRuntime::new(opts).await,
+                        };
+                        match boot {
+                            Ok(mut new_runtime) => {
+                                let boot_modules = std::mem::take(&mut new_runtime.boot_modules);
+                                let _ = booter_signal.send(Ok(boot_modules));
+                                let module_downloads = new_runtime.module_downloads.clone();
+                                module_loads = new_runtime.module_loads.clone();
+
+                                // CPU TIMER
+                                let (termination_event_tx, termination_event_rx) =
+                                    oneshot::channel::<WorkerEvents>();
+                                let _cputimer;
+
+                                // TODO: Allow customization of supervisor
+                                if new_runtime.conf.is_user_worker() {
+                                    // cputimer is returned from supervisor and assigned here to keep it in scope.
+                                    _cputimer = create_supervisor(
+                                        worker_key.unwrap_or(Uuid::nil()),
+                                        &mut new_runtime,
+                                        termination_event_tx,
+                                        termination_reason,
+                                        pool_msg_tx.clone(),
+                                        deadline_missed_rx,
+                                    )?;
+                                }
+
+                                start_time = get_thread_time()?;
+                                if let Some((usage, service_path, memory_limit_mb)) = maybe_usage {
+                                    *usage_meter.borrow_mut() = Some(WorkerUsageMeter::new(
+                                        usage,
+                                        service_path,
+                                        memory_limit_mb,
+                                        start_time,
+                                        worker_boot_start_time,
+                                        Some(module_downloads),
+                                    ));
+                                    let usage_meter = usage_meter.clone();
+                                    tokio::task::spawn_local(async move {
+                                        let mut ticker =
+                                            tokio::time::interval(WORKER_USAGE_INTERVAL);
+                                        // first tick completes immediately
+                                        ticker.tick().await;
+                                        loop {
+                                            ticker.tick().await;
+                                            let Ok(now) = get_thread_time() else {
+                                                continue;
+                                            };
+                                            if let Some(meter) = usage_meter.borrow_mut().as_mut() {
+                                                meter.record(now, Instant::now());
+                                            }
+                                        }
+                                    });
+                                }
+                                if new_runtime.conf.is_user_worker() {
+                                    inject_cpu_exhaustion();
+                                }
+                                let data = method_cloner.handle_creation(
+                                    new_runtime,
+                                    unix_channel_rx,
+                                    termination_event_rx,
+                                );
+                                data.await
+                            }
+                            Err(err) => {
+                                let _ = booter_signal.send(Err(anyhow!("worker boot error")));
+                                method_cloner.handle_error(err)
                             }
+                        }
+                    });
 
-                            start_time = get_thread_time()?;
-                            let data = method_cloner.handle_creation(
-                                new_runtime,
-                                unix_channel_rx,
-                                termination_event_rx,
+                    let end_time = get_thread_time()?;
+                    let cpu_time_used =
+                        usize::try_from((end_time - start_time) / 1_000_000).unwrap_or(0);
+                    debug!("CPU time used: {:?}ms", cpu_time_used);
+                    if let Some(loads) = module_loads {
+                        debug!(
+                            "modules loaded from bundle: {} ({} on demand)",
+                            loads.loaded(),
+                            loads.on_demand()
+                        );
+                    }
+
+                    // what was used since the last periodic record
+                    if let Some(meter) = usage_meter.borrow_mut().as_mut() {
+                        meter.record(end_time, Instant::now());
+                    }
+
+                    match result {
+                        Ok(event) => {
+                            let event_with_cpu_time = match event {
+                                WorkerEvents::Shutdown(e) => {
+                                    WorkerEvents::Shutdown(ShutdownEvent {
+                                        reason: e.reason,
+                                        memory_used: e.memory_used,
+                                        cpu_time_used,
+                                        cancelled_ops: e.cancelled_ops,
+                                    })
+                                }
+                                WorkerEvents::UncaughtException(e) => {
+                                    WorkerEvents::UncaughtException(UncaughtExceptionEvent {
+                                        exception: e.exception,
+                                        cpu_time_used,
+                                    })
+                                }
+                                other => other,
+                            };
+                            send_event_if_event_worker_available(
+                                events_msg_tx.clone(),
+                                event_with_cpu_time,
+                                event_metadata.clone(),
                             );
-                            data.await
-                        }
-                        Err(err) => {
-                            let _ = booter_signal.send(Err(anyhow!("worker boot error")));
-                            method_cloner.handle_error(err)
                         }
-                    }
-                });
-
-                let end_time = get_thread_time()?;
-                let cpu_time_used =
-                    usize::try_from((end_time - start_time) / 1_000_000).unwrap_or(0);
-                debug!("CPU time used: {:?}ms", cpu_time_used);
-
-                match result {
-                    Ok(event) => {
-                        let event_with_cpu_time = match event {
-                            WorkerEvents::Shutdown(e) => WorkerEvents::Shutdown(ShutdownEvent {
-                                reason: e.reason,
-                                memory_used: e.memory_used,
-                                cpu_time_used,
-                            }),
-                            WorkerEvents::UncaughtException(e) => {
-                                WorkerEvents::UncaughtException(UncaughtExceptionEvent {
-                                    exception: e.exception,
-                                    cpu_time_used,
-                                })
+                        Err(err) => error!("unexpected worker error {}", err),
+                    };
+
+                    worker_key.and_then(|worker_key_unwrapped| {
+                        pool_msg_tx.map(|tx| {
+                            if let Err(err) =
+                                tx.send(UserWorkerMsgs::Shutdown(worker_key_unwrapped))
+                            {
+                                error!(
+                                    "failed to send the shutdown signal to user worker pool: {:?}",
+                                    err
+                                );
                             }
-                            other => other,
-                        };
-                        send_event_if_event_worker_available(
-                            events_msg_tx.clone(),
-                            event_with_cpu_time,
-                            event_metadata.clone(),
-                        );
-                    }
-                    Err(err) => error!("unexpected worker error {}", err),
-                };
+                        })
+                    });
 
-                worker_key.and_then(|worker_key_unwrapped| {
-                    pool_msg_tx.map(|tx| {
-                        if let Err(err) = tx.send(UserWorkerMsgs::Shutdown(worker_key_unwrapped)) {
+                    Ok(())
+                }));
+
+                result.unwrap_or_else(|payload| {
+                    let report = CrashReport::from_panic(
+                        payload.as_ref(),
+                        crash_thread_name,
+                        worker_key,
+                        &crash_event_metadata,
+                    );
+                    error!(
+                        "worker thread panicked: {} (thread: {})",
+                        report.message, report.thread_name
+                    );
+                    send_event_if_event_worker_available(
+                        crash_events_msg_tx,
+                        WorkerEvents::WorkerCrashed(report.into_event()),
+                        crash_event_metadata,
+                    );
+
+                    // drop the crashed worker from the pool so requests are not routed to it
+                    if let Some((key, tx)) = worker_key.zip(crash_pool_msg_tx) {
+                        if let Err(err) = tx.send(UserWorkerMsgs::Shutdown(key)) {
                             error!(
                                 "failed to send the shutdown signal to user worker pool: {:?}",
                                 err
                             );
                         }
-                    })
-                });
+                    }
 
-                Ok(())
+                    Ok(())
+                })
             })
             .unwrap();
     }
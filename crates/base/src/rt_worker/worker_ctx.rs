@@ -1,22 +1,42 @@
+use crate::cluster::release_worker_slot;
 use crate::deno_runtime::DenoRuntime;
+use crate::replay::RECORD_TOKEN_ENV;
 use crate::utils::send_event_if_event_worker_available;
 use crate::utils::units::bytes_to_display;
 
-use crate::rt_worker::worker::{Worker, WorkerHandler};
+use crate::rt_worker::deadline::{limit_body_to_deadline, request_deadline, DEADLINE_GRACE};
+use crate::rt_worker::events_spill::EventsSpillOpts;
+use crate::rt_worker::events_supervisor::{
+    start_events_worker_supervisor, EventsMetrics, EventsWorkerOpts,
+};
+use crate::rt_worker::hooks::{observe_response, worker_hooks, WorkerInfo};
+use crate::rt_worker::memory_pressure::memory_coordinator;
+use crate::rt_worker::routes::setup_routes;
+use crate::rt_worker::worker::{TerminationReason, Worker, WorkerHandler};
 use crate::rt_worker::worker_pool::WorkerPool;
 use anyhow::{anyhow, bail, Error};
 use cpu_timer::{CPUAlarmVal, CPUTimer};
 use event_worker::events::{
-    BootEvent, ShutdownEvent, ShutdownReason, WorkerEventWithMetadata, WorkerEvents,
-    WorkerMemoryUsed,
+    BootEvent, BootModule, DeadlineExceededEvent, EventMetadata, ShutdownEvent, ShutdownReason,
+    WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed,
 };
-use hyper::{Body, Request, Response};
+use hyper::body::HttpBody;
+use hyper::client::conn::SendRequest;
+use hyper::{Body, Method, Request, Response, StatusCode};
 use log::{debug, error};
+use sb_core::problem::{problem_response, RuntimeErrorCode};
 use sb_eszip::module_loader::EszipPayloadKind;
 use sb_worker_context::essentials::{
-    EventWorkerRuntimeOpts, MainWorkerRuntimeOpts, UserWorkerMsgs, WorkerContextInitOpts,
-    WorkerRequestMsg, WorkerRuntimeOpts,
+    MainWorkerRuntimeOpts, UserWorkerMsgs, WorkerContextInitOpts, WorkerRequestMsg,
+    WorkerRuntimeOpts,
+};
+use sb_worker_context::manifest::SharedManifest;
+use sb_worker_context::request_metadata::{
+    split_wire_headers, write_metadata_frame, RequestMetadata, RequestRoute, WorkerConnection,
 };
+use sb_worker_context::usage::UsageCollector;
+use sb_workers::SMALL_RESPONSE_MAX_BYTES;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -25,14 +45,110 @@ use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
+fn limit_error_code(reason: ShutdownReason) -> RuntimeErrorCode {
+    match reason {
+        ShutdownReason::WallClockTime => RuntimeErrorCode::WallClockLimit,
+        ShutdownReason::CPUTime => RuntimeErrorCode::CpuTimeLimit,
+        ShutdownReason::Memory => RuntimeErrorCode::MemoryLimit,
+        ShutdownReason::Hang => RuntimeErrorCode::EventLoopHang,
+        ShutdownReason::Deadline => RuntimeErrorCode::DeadlineExceeded,
+    }
+}
+
+// Requests failing because the supervisor terminated the worker get a problem
+// response naming the exceeded limit
+fn map_worker_response(
+    result: Result<Response<Body>, hyper::Error>,
+    termination_reason: &TerminationReason,
+) -> Result<Response<Body>, hyper::Error> {
+    match result {
+        Err(err) => match *termination_reason.lock().unwrap() {
+            Some(reason) => Ok(problem_response(limit_error_code(reason), None)),
+            None => Err(err),
+        },
+        ok => ok,
+    }
+}
+
+// Reports a request that missed its deadline, and has the supervisor check the
+// isolate isn't stuck running its handler
+fn deadline_exceeded(
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    event_metadata: EventMetadata,
+    deadline_missed_tx: mpsc::UnboundedSender<()>,
+    deadline_at: u64,
+    path: String,
+) {
+    error!("request deadline exceeded (path: {})", path);
+    let _ = deadline_missed_tx.send(());
+    send_event_if_event_worker_available(
+        events_msg_tx,
+        WorkerEvents::DeadlineExceeded(DeadlineExceededEvent { deadline_at, path }),
+        event_metadata,
+    );
+}
+
+// responses that can't have a body
+fn skips_body(method: &Method, status: StatusCode) -> bool {
+    *method == Method::HEAD
+        || status.is_informational()
+        || matches!(
+            status,
+            StatusCode::NO_CONTENT | StatusCode::RESET_CONTENT | StatusCode::NOT_MODIFIED
+        )
+}
+
+// Small responses are read at once, so they're written to the client along with
+// their head instead of being streamed from the worker's connection, and those
+// without a body don't carry the connection's body along
+async fn buffer_small_response(
+    method: &Method,
+    res: Response<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    if skips_body(method, res.status()) {
+        return Ok(res.map(|_| Body::empty()));
+    }
+    let size = HttpBody::size_hint(res.body()).exact();
+    if size.map_or(false, |size| size <= SMALL_RESPONSE_MAX_BYTES) {
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        return Ok(Response::from_parts(parts, Body::from(body)));
+    }
+    Ok(res)
+}
+
+async fn send_request(
+    request_sender: &mut SendRequest<Body>,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let method = req.method().clone();
+    let res = request_sender.send_request(req).await?;
+    buffer_small_response(&method, res).await
+}
+
 async fn handle_request(
-    unix_stream_tx: mpsc::UnboundedSender<UnixStream>,
-    msg: WorkerRequestMsg,
+    unix_stream_tx: mpsc::UnboundedSender<WorkerConnection>,
+    mut msg: WorkerRequestMsg,
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    event_metadata: EventMetadata,
+    termination_reason: TerminationReason,
+    deadline_missed_tx: mpsc::UnboundedSender<()>,
 ) -> Result<(), Error> {
     // create a unix socket pair
-    let (sender_stream, recv_stream) = UnixStream::pair()?;
-
-    let _ = unix_stream_tx.send(recv_stream);
+    let (mut sender_stream, recv_stream) = UnixStream::pair()?;
+
+    // read before the headers are handed to the worker
+    let deadline = request_deadline(&msg.req);
+
+    let metadata = msg.req.extensions_mut().remove::<RequestMetadata>();
+    write_metadata_frame(&mut sender_stream, metadata.as_ref()).await?;
+    // only the wire headers are sent over the connection, the worker reads the
+    // rest through ops
+    let _ = unix_stream_tx.send(WorkerConnection {
+        stream: recv_stream,
+        headers: Some(split_wire_headers(msg.req.headers_mut())),
+        route: msg.req.extensions_mut().remove::<RequestRoute>(),
+    });
 
     // send the HTTP request to the worker over Unix stream
     let (mut request_sender, connection) = hyper::client::conn::handshake(sender_stream).await?;
@@ -45,8 +161,50 @@ async fn handle_request(
     });
     tokio::task::yield_now().await;
 
-    let result = request_sender.send_request(msg.req).await;
-    let _ = msg.res_tx.send(result);
+    // the response has to be sent before the request's deadline
+    let Some((deadline, deadline_at)) = deadline else {
+        let result = send_request(&mut request_sender, msg.req).await;
+        let _ = msg
+            .res_tx
+            .send(map_worker_response(result, &termination_reason));
+        return Ok(());
+    };
+
+    let path = msg.req.uri().path().to_string();
+    match tokio::time::timeout_at(deadline, send_request(&mut request_sender, msg.req)).await {
+        Ok(result) => {
+            // the body is cut off if it's still streamed at the deadline
+            let result = map_worker_response(result, &termination_reason).map(|res| {
+                res.map(|body| {
+                    limit_body_to_deadline(body, deadline, move || {
+                        deadline_exceeded(
+                            events_msg_tx,
+                            event_metadata,
+                            deadline_missed_tx,
+                            deadline_at,
+                            path,
+                        )
+                    })
+                })
+            });
+            let _ = msg.res_tx.send(result);
+        }
+        Err(_) => {
+            // dropping the request sender closes the connection, aborting the request in the isolate
+            deadline_exceeded(
+                events_msg_tx,
+                event_metadata,
+                deadline_missed_tx,
+                deadline_at,
+                path,
+            );
+            let res = problem_response(
+                RuntimeErrorCode::DeadlineExceeded,
+                Some(format!("response did not start before {}", deadline_at)),
+            );
+            let _ = msg.res_tx.send(Ok(res));
+        }
+    }
 
     Ok(())
 }
@@ -90,14 +248,29 @@ pub fn create_supervisor(
     key: Uuid,
     worker_runtime: &mut DenoRuntime,
     termination_event_tx: oneshot::Sender<WorkerEvents>,
+    termination_reason: TerminationReason,
     pool_msg_tx: Option<UnboundedSender<UserWorkerMsgs>>,
+    mut deadline_missed_rx: mpsc::UnboundedReceiver<()>,
 ) -> Result<CPUTimer, Error> {
-    let (memory_limit_tx, mut memory_limit_rx) = mpsc::unbounded_channel::<()>();
     let thread_safe_handle = worker_runtime.js_runtime.v8_isolate().thread_safe_handle();
+    let event_loop_watch = worker_runtime.event_loop_watch.clone();
 
     // we assert supervisor is only run for user workers
     let conf = worker_runtime.conf.as_user_worker().unwrap().clone();
 
+    // the CPU alarms and heap limit of web workers are those of the user worker
+    // that spawned them, which receives them in its budget alarms
+    let (mut cpu_alarms_tx, mut cpu_alarms_rx) = mpsc::unbounded_channel::<()>();
+    let (mut memory_limit_tx, mut memory_limit_rx) = mpsc::unbounded_channel::<()>();
+    if let Some(budget) = worker_runtime.budget.as_ref() {
+        cpu_alarms_tx = budget.cpu_alarms_tx.clone();
+        memory_limit_tx = budget.memory_limit_tx.clone();
+    }
+    if let Some(alarms) = worker_runtime.budget_alarms.take() {
+        cpu_alarms_rx = alarms.cpu_alarms_rx;
+        memory_limit_rx = alarms.memory_limit_rx;
+    }
+
     worker_runtime.js_runtime.add_near_heap_limit_callback(move |cur, _| {
         debug!(
             "Low memory alert triggered: {}",
@@ -114,7 +287,6 @@ pub fn create_supervisor(
     });
 
     // Note: CPU timer must be started in the same thread as the worker runtime
-    let (cpu_alarms_tx, mut cpu_alarms_rx) = mpsc::unbounded_channel::<()>();
     let cputimer = CPUTimer::start(conf.cpu_time_threshold_ms, CPUAlarmVal { cpu_alarms_tx })?;
 
     let thread_name = format!("sb-sup-{:?}", key);
@@ -144,6 +316,14 @@ pub fn create_supervisor(
 
                 let mut wall_clock_alerts = 0;
 
+                // watchdog for event loops that stopped making progress
+                let hang_threshold = Duration::from_millis(conf.hang_threshold_ms);
+                let hang_check = tokio::time::interval((hang_threshold / 4).max(Duration::from_millis(10)));
+                tokio::pin!(hang_check);
+
+                // set once a request missed its deadline, the isolate has until then to yield
+                let mut deadline_check: Option<tokio::time::Instant> = None;
+
                 loop {
                     tokio::select! {
                         Some(_) = cpu_alarms_rx.recv() => {
@@ -152,6 +332,7 @@ pub fn create_supervisor(
                                 last_burst = Instant::now();
                             }
                             if bursts > conf.max_cpu_bursts {
+                                *termination_reason.lock().unwrap() = Some(ShutdownReason::CPUTime);
                                 let interrupt_data = IsolateInterruptData {
                                     should_terminate: true,
                                     isolate_memory_usage_tx
@@ -179,6 +360,7 @@ pub fn create_supervisor(
                                 // wall-clock limit reached
                                 // Don't terminate isolate from supervisor when wall-clock
                                 // duration reached. It's dropped in deno_runtime.rs
+                                *termination_reason.lock().unwrap() = Some(ShutdownReason::WallClockTime);
                                 let interrupt_data = IsolateInterruptData {
                                     should_terminate: false,
                                     isolate_memory_usage_tx
@@ -190,8 +372,42 @@ pub fn create_supervisor(
                         }
 
 
+                        Some(_) = deadline_missed_rx.recv() => {
+                            let check_at = tokio::time::Instant::now() + DEADLINE_GRACE;
+                            deadline_check = Some(deadline_check.map_or(check_at, |at| at.min(check_at)));
+                        }
+
+                        _ = tokio::time::sleep_until(deadline_check.unwrap_or_else(tokio::time::Instant::now)), if deadline_check.is_some() => {
+                            deadline_check = None;
+                            // an isolate that yields sees the closed connection and aborts the request
+                            if event_loop_watch.is_hung(DEADLINE_GRACE) {
+                                *termination_reason.lock().unwrap() = Some(ShutdownReason::Deadline);
+                                let interrupt_data = IsolateInterruptData {
+                                    should_terminate: true,
+                                    isolate_memory_usage_tx
+                                };
+                                thread_safe_handle.request_interrupt(handle_interrupt, Box::into_raw(Box::new(interrupt_data)) as *mut std::ffi::c_void);
+                                error!("isolate kept running past a request deadline. isolate: {:?}", key);
+                                return ShutdownReason::Deadline;
+                            }
+                        }
+
+                        _ = hang_check.tick() => {
+                            if event_loop_watch.is_hung(hang_threshold) {
+                                *termination_reason.lock().unwrap() = Some(ShutdownReason::Hang);
+                                let interrupt_data = IsolateInterruptData {
+                                    should_terminate: true,
+                                    isolate_memory_usage_tx
+                                };
+                                thread_safe_handle.request_interrupt(handle_interrupt, Box::into_raw(Box::new(interrupt_data)) as *mut std::ffi::c_void);
+                                error!("event loop hung. isolate: {:?}", key);
+                                return ShutdownReason::Hang;
+                            }
+                        }
+
                         // memory usage
                         Some(_) = memory_limit_rx.recv() => {
+                            *termination_reason.lock().unwrap() = Some(ShutdownReason::Memory);
                             let interrupt_data = IsolateInterruptData {
                                 should_terminate: true,
                                 isolate_memory_usage_tx
@@ -229,6 +445,7 @@ pub fn create_supervisor(
                 reason,
                 memory_used,
                 cpu_time_used: 0, // this will be set later
+                cancelled_ops: Default::default(), // set once the runtime stopped
             });
             let _ = termination_event_tx.send(termination_event);
         })
@@ -238,11 +455,17 @@ pub fn create_supervisor(
 }
 
 pub async fn create_worker(
-    init_opts: WorkerContextInitOpts,
+    mut init_opts: WorkerContextInitOpts,
 ) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
-    let (worker_boot_result_tx, worker_boot_result_rx) = oneshot::channel::<Result<(), Error>>();
-    let (unix_stream_tx, unix_stream_rx) = mpsc::unbounded_channel::<UnixStream>();
+    let maybe_routes = setup_routes(&mut init_opts)?;
+    let (worker_boot_result_tx, worker_boot_result_rx) =
+        oneshot::channel::<Result<Vec<BootModule>, Error>>();
+    let (unix_stream_tx, unix_stream_rx) = mpsc::unbounded_channel::<WorkerConnection>();
+    let (exit_signal_tx, mut exit_signal_rx) = oneshot::channel::<()>();
+    let (deadline_missed_tx, deadline_missed_rx) = mpsc::unbounded_channel::<()>();
     let worker_init = Worker::new(&init_opts)?;
+    let worker_info = WorkerInfo::from_opts(&init_opts);
+    let hooks = worker_hooks();
 
     let worker: Box<dyn WorkerHandler> = Box::new(worker_init);
 
@@ -251,17 +474,74 @@ pub async fn create_worker(
     // Downcasting it to Worker will give us access to its parent implementation
     let downcast_reference = worker.as_any().downcast_ref::<Worker>();
     if let Some(worker_struct_ref) = downcast_reference {
-        worker_struct_ref.start(init_opts, unix_stream_rx, worker_boot_result_tx);
+        worker_struct_ref.start(
+            init_opts,
+            unix_stream_rx,
+            worker_boot_result_tx,
+            exit_signal_tx,
+            deadline_missed_rx,
+        );
 
         // create an async task waiting for requests for worker
         let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+        let events_msg_tx = worker_struct_ref.events_msg_tx.clone();
+        let event_metadata = worker_struct_ref.event_metadata.clone();
+        let termination_reason = worker_struct_ref.termination_reason.clone();
+        let req_hooks = hooks.clone();
+        let req_worker_info = worker_info.clone();
 
         let worker_req_handle: tokio::task::JoinHandle<Result<(), Error>> =
             tokio::task::spawn(async move {
-                while let Some(msg) = worker_req_rx.recv().await {
+                loop {
+                    let mut msg = tokio::select! {
+                        Some(msg) = worker_req_rx.recv() => msg,
+                        // stop accepting requests once the worker thread exits, so
+                        // senders can tell the worker is gone
+                        _ = &mut exit_signal_rx => {
+                            if let Some(hooks) = &req_hooks {
+                                let reason = *termination_reason.lock().unwrap();
+                                hooks.on_shutdown(&req_worker_info, reason);
+                            }
+                            break;
+                        }
+                        else => break,
+                    };
+
+                    if let Some(routes) = &maybe_routes {
+                        if !routes.annotate_request(&mut msg.req) {
+                            let res = problem_response(
+                                RuntimeErrorCode::RouteNotFound,
+                                Some(format!("no route matches {}", msg.req.uri().path())),
+                            );
+                            let _ = msg.res_tx.send(Ok(res));
+                            continue;
+                        }
+                    }
+
+                    if let Some(hooks) = &req_hooks {
+                        if let Some(res) = hooks.before_request(&req_worker_info, &mut msg.req) {
+                            let _ = msg.res_tx.send(Ok(res));
+                            continue;
+                        }
+                        msg = observe_response(msg, hooks.clone(), req_worker_info.clone());
+                    }
+
                     let unix_stream_tx_clone = unix_stream_tx.clone();
+                    let events_msg_tx = events_msg_tx.clone();
+                    let event_metadata = event_metadata.clone();
+                    let termination_reason = termination_reason.clone();
+                    let deadline_missed_tx = deadline_missed_tx.clone();
                     tokio::task::spawn(async move {
-                        if let Err(err) = handle_request(unix_stream_tx_clone, msg).await {
+                        if let Err(err) = handle_request(
+                            unix_stream_tx_clone,
+                            msg,
+                            events_msg_tx,
+                            event_metadata,
+                            termination_reason,
+                            deadline_missed_tx,
+                        )
+                        .await
+                        {
                             error!("worker failed to handle request: {:?}", err);
                         }
                     });
@@ -278,15 +558,17 @@ pub async fn create_worker(
                 worker_req_handle.abort();
                 bail!(err)
             }
-            Ok(_) => {
-                let elapsed = worker_struct_ref
-                    .worker_boot_start_time
-                    .elapsed()
-                    .as_millis();
+            Ok(modules) => {
+                let boot_time = worker_struct_ref.worker_boot_start_time.elapsed();
+                if let Some(hooks) = &hooks {
+                    hooks.on_boot(&worker_info, boot_time);
+                }
+                let elapsed = boot_time.as_millis();
                 send_event_if_event_worker_available(
                     worker_struct_ref.events_msg_tx.clone(),
                     WorkerEvents::Boot(BootEvent {
                         boot_time: elapsed as usize,
+                        modules,
                     }),
                     worker_struct_ref.event_metadata.clone(),
                 );
@@ -316,12 +598,20 @@ pub async fn send_user_worker_request(
     Ok(res)
 }
 
+// The env vars of the main worker: those of the runtime, except its own secrets
+pub fn main_worker_env_vars() -> HashMap<String, String> {
+    std::env::vars()
+        .filter(|(key, _)| key != RECORD_TOKEN_ENV)
+        .collect()
+}
+
 pub async fn create_main_worker(
     main_worker_path: PathBuf,
     import_map_path: Option<String>,
     no_module_cache: bool,
     user_worker_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
     maybe_entrypoint: Option<String>,
+    maybe_manifest: Option<SharedManifest>,
 ) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
     let mut service_path = main_worker_path.clone();
     let mut maybe_eszip = None;
@@ -342,8 +632,9 @@ pub async fn create_main_worker(
         maybe_module_code: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx: user_worker_msgs_tx,
+            manifest: maybe_manifest,
         }),
-        env_vars: std::env::vars().collect(),
+        env_vars: main_worker_env_vars(),
     })
     .await
     .map_err(|err| anyhow!("main worker boot error: {}", err))?;
@@ -351,44 +642,31 @@ pub async fn create_main_worker(
     Ok(main_worker_req_tx)
 }
 
+// The events worker is supervised and restarted if it dies (see events_supervisor)
 pub async fn create_events_worker(
     events_worker_path: PathBuf,
     import_map_path: Option<String>,
     no_module_cache: bool,
     maybe_entrypoint: Option<String>,
+    maybe_spill: Option<EventsSpillOpts>,
+    metrics: EventsMetrics,
 ) -> Result<mpsc::UnboundedSender<WorkerEventWithMetadata>, Error> {
-    let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
-
-    let mut service_path = events_worker_path.clone();
-    let mut maybe_eszip = None;
-    if let Some(ext) = events_worker_path.extension() {
-        if ext == "eszip" {
-            service_path = events_worker_path.parent().unwrap().to_path_buf();
-            maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(
-                events_worker_path,
-            )?));
-        }
-    }
-
-    let _ = create_worker(WorkerContextInitOpts {
-        service_path,
-        no_module_cache,
-        import_map_path,
-        env_vars: std::env::vars().collect(),
-        events_rx: Some(events_rx),
-        maybe_eszip,
-        maybe_entrypoint,
-        maybe_module_code: None,
-        conf: WorkerRuntimeOpts::EventsWorker(EventWorkerRuntimeOpts {}),
-    })
+    start_events_worker_supervisor(
+        EventsWorkerOpts {
+            service_path: events_worker_path,
+            import_map_path,
+            no_module_cache,
+            maybe_entrypoint,
+            spill: maybe_spill,
+        },
+        metrics,
+    )
     .await
-    .map_err(|err| anyhow!("events worker boot error: {}", err))?;
-
-    Ok(events_tx)
 }
 
 pub async fn create_user_worker_pool(
     worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    usage: Option<UsageCollector>,
 ) -> Result<mpsc::UnboundedSender<UserWorkerMsgs>, Error> {
     let (user_worker_msgs_tx, mut user_worker_msgs_rx) =
         mpsc::unbounded_channel::<UserWorkerMsgs>();
@@ -396,7 +674,8 @@ pub async fn create_user_worker_pool(
     let user_worker_msgs_tx_clone = user_worker_msgs_tx.clone();
 
     let _handle: tokio::task::JoinHandle<Result<(), Error>> = tokio::spawn(async move {
-        let mut worker_pool = WorkerPool::new(worker_event_sender, user_worker_msgs_tx_clone);
+        let mut worker_pool =
+            WorkerPool::new(worker_event_sender, user_worker_msgs_tx_clone, usage);
 
         // Note: Keep this loop non-blocking. Spawn a task to run blocking calls.
         // Handle errors within tasks and log them - do not bubble up errors.
@@ -404,19 +683,62 @@ pub async fn create_user_worker_pool(
             match user_worker_msgs_rx.recv().await {
                 None => break,
                 Some(UserWorkerMsgs::Create(worker_options, tx)) => {
-                    worker_pool.create_user_worker(worker_options, tx);
+                    worker_pool.create_user_worker(worker_options, tx, false);
                 }
                 Some(UserWorkerMsgs::Created(key, profile)) => {
                     worker_pool.add_user_worker(key, profile);
                 }
+                Some(UserWorkerMsgs::Stage(worker_options, tx)) => {
+                    worker_pool.create_user_worker(worker_options, tx, true);
+                }
+                Some(UserWorkerMsgs::Activate(service_path, version, key)) => {
+                    worker_pool.activate_version(service_path, version, key);
+                }
+                Some(UserWorkerMsgs::Rollback(service_path, tx)) => {
+                    if tx
+                        .send(worker_pool.rollback_version(&service_path))
+                        .is_err()
+                    {
+                        error!("admin receiver dropped")
+                    }
+                }
+                Some(UserWorkerMsgs::GetVersions(service_path, tx)) => {
+                    if tx.send(worker_pool.get_versions(&service_path)).is_err() {
+                        error!("admin receiver dropped")
+                    }
+                }
+                Some(UserWorkerMsgs::StartCanary(service_path, canary)) => {
+                    worker_pool.start_canary(service_path, canary);
+                }
+                Some(UserWorkerMsgs::PromoteCanary(service_path, tx)) => {
+                    if tx.send(worker_pool.promote_canary(&service_path)).is_err() {
+                        error!("admin receiver dropped")
+                    }
+                }
+                Some(UserWorkerMsgs::CheckCanary(service_path)) => {
+                    worker_pool.check_canary(&service_path);
+                }
                 Some(UserWorkerMsgs::SendRequest(key, req, res_tx)) => {
                     worker_pool.send_request(&key, req, res_tx);
                 }
                 Some(UserWorkerMsgs::Retire(key)) => {
                     worker_pool.retire(&key);
                 }
+                Some(UserWorkerMsgs::RetireAll) => {
+                    worker_pool.retire_all();
+                }
+                Some(UserWorkerMsgs::RetireServices(service_paths)) => {
+                    worker_pool.retire_services(&service_paths);
+                }
+                Some(UserWorkerMsgs::EvictIdle) => {
+                    let evicted = worker_pool.evict_idle();
+                    if let Some(coordinator) = memory_coordinator() {
+                        coordinator.record_evicted(evicted);
+                    }
+                }
                 Some(UserWorkerMsgs::Shutdown(key)) => {
                     worker_pool.shutdown(&key);
+                    release_worker_slot(key);
                 }
             }
         }
@@ -426,3 +748,63 @@ pub async fn create_user_worker_pool(
 
     Ok(user_worker_msgs_tx)
 }
+
+#[cfg(test)]
+mod test {
+    use super::send_request;
+    use hyper::body::HttpBody;
+    use hyper::header::CONTENT_LENGTH;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Method, Request, Response, StatusCode};
+    use std::convert::Infallible;
+
+    async fn respond(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        Ok(match req.uri().path() {
+            "/small" => Response::new(Body::from("hello")),
+            "/empty" => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap(),
+            _ => Response::new(Body::wrap_stream(deno_core::futures::stream::iter(vec![
+                Ok::<_, Infallible>("a"),
+                Ok("b"),
+            ]))),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_buffer_small_response() {
+        for (method, path, buffered) in [
+            (Method::GET, "/small", Some("hello")),
+            (Method::HEAD, "/small", Some("")),
+            (Method::GET, "/empty", Some("")),
+            (Method::GET, "/stream", None),
+        ] {
+            let (client, server) = tokio::io::duplex(1024);
+            tokio::spawn(Http::new().serve_connection(server, service_fn(respond)));
+            let (mut sender, conn) = hyper::client::conn::handshake(client).await.unwrap();
+            let conn = tokio::spawn(conn);
+
+            let req = Request::builder()
+                .method(method.clone())
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let res = send_request(&mut sender, req).await.unwrap();
+            if method == Method::HEAD {
+                assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+            }
+
+            // a buffered body is read even once the connection is gone
+            conn.abort();
+            let _ = conn.await;
+            match buffered {
+                Some(body) => {
+                    assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), body)
+                }
+                None => assert_eq!(res.body().size_hint().exact(), None),
+            }
+        }
+    }
+}
@@ -1,17 +1,35 @@
+use crate::cluster::{acquire_worker_slot, release_worker_slot};
+use crate::rt_worker::body_tee::tee_body;
+use crate::rt_worker::idempotency::{idempotency_store, Idempotency};
+use crate::rt_worker::memory_pressure::{memory_coordinator, memory_pressure, MemoryPressure};
+use crate::rt_worker::process_worker::{create_process_worker, is_process_isolated};
+use crate::rt_worker::slow_requests::{slow_request_threshold, RequestTimings, BOOT_WAIT_WINDOW};
 use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use crate::sampling::sample_request;
+use crate::timeline::{timelines, worker_track, SpanGuard};
 use anyhow::{anyhow, Error};
-use event_worker::events::WorkerEventWithMetadata;
+use deno_core::futures::StreamExt;
+use deno_core::serde_json::json;
+use event_worker::events::{BodyTeeKind, EventMetadata, WorkerEventWithMetadata};
 use http::{Request, Response};
 use hyper::Body;
 use log::error;
+use rand::Rng;
+use sb_eszip::module_loader::EszipPayloadKind;
 use sb_worker_context::essentials::{
-    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerProfile, WorkerContextInitOpts,
-    WorkerRuntimeOpts,
+    CanaryVersion, ConcurrencyOverflowPolicy, CreateUserWorkerResult, PendingBoot, ServiceVersion,
+    ServiceVersions, UserWorkerMsgs, UserWorkerProfile, WorkerContextInitOpts,
+    WorkerCreationRejected, WorkerReusePolicy, WorkerRuntimeOpts,
 };
+use sb_worker_context::paused_services::{service_pause, ServicePaused};
+use sb_worker_context::usage::UsageCollector;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::Sender;
+use tokio::sync::{mpsc, Semaphore};
 use uuid::Uuid;
 
 // every new worker gets a new UUID (can reuse execution_id)
@@ -22,24 +40,76 @@ use uuid::Uuid;
 // create_worker returns true if an active_worker is available for service_path (force create
 // retires current one adds new one)
 // send_request is called with UUID
+// service_versions - hashmap of (service_path - active and previous deployed versions)
+// Counts a request as in flight until it's dropped, along with the response body
+struct InflightGuard(Arc<AtomicUsize>);
+
+impl InflightGuard {
+    fn new(inflight: Arc<AtomicUsize>) -> Self {
+        inflight.fetch_add(1, Ordering::Relaxed);
+        Self(inflight)
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Counts the bytes of a response body, recorded as usage once the body is
+// dropped so the collector isn't locked for every chunk
+struct EgressGuard {
+    usage: UsageCollector,
+    service_path: String,
+    bytes: u64,
+}
+
+impl Drop for EgressGuard {
+    fn drop(&mut self) {
+        let bytes = self.bytes;
+        self.usage
+            .record(&self.service_path, |u| u.egress_bytes += bytes);
+    }
+}
+
+fn count_egress(body: Body, usage: UsageCollector, service_path: String) -> Body {
+    let mut egress = EgressGuard {
+        usage,
+        service_path,
+        bytes: 0,
+    };
+    Body::wrap_stream(body.map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            egress.bytes += bytes.len() as u64;
+        }
+        chunk
+    }))
+}
+
 pub struct WorkerPool {
     pub user_workers: HashMap<Uuid, UserWorkerProfile>,
     pub active_workers: HashMap<String, Uuid>,
+    pub service_versions: HashMap<String, ServiceVersions>,
     pub worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
 
     // TODO: refactor this out of worker pool
     pub worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    pub usage: Option<UsageCollector>,
 }
 
 impl WorkerPool {
     pub(crate) fn new(
         worker_event_sender: Option<UnboundedSender<WorkerEventWithMetadata>>,
         worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+        usage: Option<UsageCollector>,
     ) -> Self {
         Self {
             worker_event_sender,
+            usage,
             user_workers: HashMap::new(),
             active_workers: HashMap::new(),
+            service_versions: HashMap::new(),
             worker_pool_msgs_tx,
         }
     }
@@ -48,6 +118,7 @@ impl WorkerPool {
         &self,
         mut worker_options: WorkerContextInitOpts,
         tx: Sender<Result<CreateUserWorkerResult, Error>>,
+        staged: bool,
     ) {
         let mut user_worker_rt_opts = match worker_options.conf {
             WorkerRuntimeOpts::UserWorker(opts) => opts,
@@ -59,38 +130,126 @@ impl WorkerPool {
             .to_str()
             .unwrap_or("")
             .to_string();
-        if let Some(active_worker_uuid) =
-            self.maybe_active_worker(&service_path, user_worker_rt_opts.force_create)
-        {
-            if tx
-                .send(Ok(CreateUserWorkerResult {
-                    key: *active_worker_uuid,
-                }))
-                .is_err()
-            {
+        // no worker is booted for a paused service, not even a staged one
+        if let Some(pause) = service_pause(&service_path) {
+            let paused = ServicePaused {
+                service_path,
+                pause,
+            };
+            if tx.send(Err(paused.into())).is_err() {
+                error!("main worker receiver dropped")
+            }
+            return;
+        }
+
+        let reuse = user_worker_rt_opts.reuse;
+        // staged workers boot with the options they were given
+        let mut version = None;
+        if !staged {
+            let force_create =
+                user_worker_rt_opts.force_create || reuse != WorkerReusePolicy::Active;
+            let maybe_canary = self.maybe_canary(&service_path);
+            let maybe_worker = match maybe_canary {
+                Some(canary) if !force_create => self
+                    .user_workers
+                    .contains_key(&canary.key)
+                    .then_some(&canary.key),
+                Some(_) => None,
+                None => self.maybe_active_worker(&service_path, force_create),
+            };
+
+            if let Some(worker_uuid) = maybe_worker {
+                if tx
+                    .send(Ok(CreateUserWorkerResult { key: *worker_uuid }))
+                    .is_err()
+                {
+                    error!("main worker receiver dropped")
+                }
+                return;
+            }
+
+            let maybe_version = maybe_canary.map(|canary| &canary.version).or_else(|| {
+                self.service_versions
+                    .get(&service_path)
+                    .and_then(|v| v.active.as_ref())
+            });
+            if let Some(target) = maybe_version {
+                apply_version(&mut worker_options, target);
+                version = Some(target.clone());
+            }
+        }
+
+        // the node is about to run out of memory, booting another worker could
+        // get the whole process killed
+        if memory_pressure() == MemoryPressure::Critical {
+            if let Some(coordinator) = memory_coordinator() {
+                coordinator.record_rejected();
+            }
+            let rejected = WorkerCreationRejected("the node is low on memory".to_string());
+            if tx.send(Err(rejected.into())).is_err() {
                 error!("main worker receiver dropped")
             }
             return;
         }
 
         let uuid = uuid::Uuid::new_v4();
+        let body_tee_max_bytes = user_worker_rt_opts.body_tee_max_bytes;
+        let telemetry_sample_rate = user_worker_rt_opts.telemetry_sample_rate;
+        let permits = user_worker_rt_opts
+            .max_concurrent_requests
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let concurrency_overflow = user_worker_rt_opts.concurrency_overflow;
+        let max_workers = user_worker_rt_opts.max_workers;
+        let process_isolated = is_process_isolated(&user_worker_rt_opts);
 
         user_worker_rt_opts.service_path = Some(service_path.clone());
         user_worker_rt_opts.key = Some(uuid);
 
         user_worker_rt_opts.pool_msg_tx = Some(self.worker_pool_msgs_tx.clone());
         user_worker_rt_opts.events_msg_tx = self.worker_event_sender.clone();
+        user_worker_rt_opts.usage = self.usage.clone();
 
         worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
         let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
 
         tokio::task::spawn(async move {
-            let result = create_worker(worker_options).await;
+            if let Some(max_workers) = max_workers {
+                if let Err(err) = acquire_worker_slot(&service_path, uuid, max_workers).await {
+                    if tx.send(Err(err)).is_err() {
+                        error!("main worker receiver dropped")
+                    }
+                    return;
+                }
+            }
+            let started = Instant::now();
+            let result = if process_isolated {
+                create_process_worker(worker_options).await
+            } else {
+                create_worker(worker_options).await
+            };
+            if let Some(timelines) = timelines() {
+                timelines.span(
+                    &worker_track(&service_path, &uuid),
+                    "worker",
+                    "boot",
+                    started,
+                    json!({ "ok": result.is_ok(), "processIsolated": process_isolated }),
+                );
+            }
             match result {
                 Ok(worker_request_msg_tx) => {
                     let profile = UserWorkerProfile {
                         worker_request_msg_tx,
                         service_path,
+                        body_tee_max_bytes,
+                        telemetry_sample_rate,
+                        permits,
+                        concurrency_overflow,
+                        staged,
+                        isolated: reuse == WorkerReusePolicy::Isolated,
+                        version,
+                        inflight: Arc::default(),
+                        boot: PendingBoot::new(Instant::now(), started.elapsed()),
                     };
                     if worker_pool_msgs_tx
                         .send(UserWorkerMsgs::Created(uuid, profile))
@@ -103,6 +262,7 @@ impl WorkerPool {
                     };
                 }
                 Err(e) => {
+                    release_worker_slot(uuid);
                     if tx.send(Err(e)).is_err() {
                         error!("main worker receiver dropped")
                     } else {
@@ -114,26 +274,316 @@ impl WorkerPool {
     }
 
     pub fn add_user_worker(&mut self, key: Uuid, profile: UserWorkerProfile) {
-        self.active_workers
-            .insert(profile.service_path.clone(), key);
+        if !profile.staged && !profile.isolated {
+            // workers booted for a canary replace the canary's worker instead
+            let maybe_canary = self
+                .service_versions
+                .get_mut(&profile.service_path)
+                .and_then(|v| v.canary.as_mut())
+                .filter(|canary| profile.version.as_ref().map(|v| v.id) == Some(canary.version.id));
+            match maybe_canary {
+                Some(canary) => canary.key = key,
+                None => {
+                    self.active_workers
+                        .insert(profile.service_path.clone(), key);
+                }
+            }
+        }
         self.user_workers.insert(key, profile);
     }
 
+    // Switches routing for a service to a staged worker running the given version.
+    // The version being replaced is kept for rollbacks.
+    pub fn activate_version(&mut self, service_path: String, version: ServiceVersion, key: Uuid) {
+        match self.user_workers.get_mut(&key) {
+            Some(profile) => {
+                profile.staged = false;
+                profile.version = Some(version.clone());
+                self.active_workers.insert(service_path.clone(), key);
+            }
+            None => {
+                // the next request boots a worker running the activated version
+                self.active_workers.remove(&service_path);
+            }
+        }
+
+        let versions = self.service_versions.entry(service_path).or_default();
+        versions.previous = versions.active.replace(version);
+    }
+
+    // Starts routing a share of the service's traffic to a staged worker
+    pub fn start_canary(&mut self, service_path: String, canary: CanaryVersion) {
+        let Some(profile) = self.user_workers.get_mut(&canary.key) else {
+            error!("canary worker {} is no longer available", canary.key);
+            return;
+        };
+        profile.version = Some(canary.version.clone());
+
+        let versions = self.service_versions.entry(service_path).or_default();
+        if let Some(replaced) = versions.canary.replace(canary) {
+            self.user_workers.remove(&replaced.key);
+        }
+    }
+
+    pub fn promote_canary(&mut self, service_path: &str) -> Result<ServiceVersions, Error> {
+        let canary = self
+            .service_versions
+            .get_mut(service_path)
+            .and_then(|v| v.canary.take())
+            .ok_or_else(|| anyhow!("no canary for {}", service_path))?;
+
+        self.activate_version(service_path.to_string(), canary.version, canary.key);
+        Ok(self.get_versions(service_path))
+    }
+
+    pub fn check_canary(&mut self, service_path: &str) {
+        let Some(versions) = self.service_versions.get_mut(service_path) else {
+            return;
+        };
+        if !versions
+            .canary
+            .as_ref()
+            .map(|c| c.is_failing())
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        if let Some(canary) = versions.canary.take() {
+            error!(
+                "rolling back canary {} of {} (error rate: {:.2})",
+                canary.version.id,
+                service_path,
+                canary.version.stats.error_rate()
+            );
+            self.user_workers.remove(&canary.key);
+        }
+    }
+
+    // Drops the canary if there's one, otherwise restores the previous version
+    pub fn rollback_version(&mut self, service_path: &str) -> Result<ServiceVersions, Error> {
+        let versions = self
+            .service_versions
+            .get_mut(service_path)
+            .filter(|v| v.previous.is_some() || v.canary.is_some())
+            .ok_or_else(|| anyhow!("no previous version for {}", service_path))?;
+
+        if let Some(canary) = versions.canary.take() {
+            let versions = versions.clone();
+            self.user_workers.remove(&canary.key);
+            return Ok(versions);
+        }
+
+        std::mem::swap(&mut versions.active, &mut versions.previous);
+        let versions = versions.clone();
+
+        // the next request boots a worker running the restored version
+        self.active_workers.remove(service_path);
+        Ok(versions)
+    }
+
+    pub fn get_versions(&self, service_path: &str) -> ServiceVersions {
+        self.service_versions
+            .get(service_path)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     pub fn send_request(
         &self,
         key: &Uuid,
-        req: Request<Body>,
+        mut req: Request<Body>,
         res_tx: Sender<Result<Response<Body>, Error>>,
     ) {
         let _: Result<(), Error> = match self.user_workers.get(key) {
             Some(worker) => {
+                // answered here if the service was paused since the worker was created
+                if let Some(pause) = service_pause(&worker.service_path) {
+                    if res_tx.send(Ok(pause.response())).is_err() {
+                        error!("main worker receiver dropped")
+                    }
+                    return;
+                }
                 let profile = worker.clone();
 
+                // detailed telemetry is only collected for sampled requests
+                let sampled = sample_request(req.headers_mut(), profile.telemetry_sample_rate);
+                let maybe_timelines = timelines().filter(|_| sampled);
+
+                // tee request and response bodies to the events worker if requested
+                let maybe_tee = profile
+                    .body_tee_max_bytes
+                    .filter(|_| sampled)
+                    .zip(self.worker_event_sender.clone())
+                    .map(|(limit, events_tx)| {
+                        let metadata = EventMetadata {
+                            service_path: Some(profile.service_path.clone()),
+                            execution_id: Some(*key),
+                            ..Default::default()
+                        };
+                        (limit, events_tx, metadata)
+                    });
+
+                let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
+                let worker_event_sender = self.worker_event_sender.clone();
+                let usage = self.usage.clone();
+                let inflight = InflightGuard::new(profile.inflight.clone());
+                let execution_id = *key;
+                let track = worker_track(&profile.service_path, key);
+                let request_name = format!("{} {}", req.method(), req.uri().path());
+
                 // Create a closure to handle the request and send the response
                 let request_handler = async move {
+                    // retries of a request with an `Idempotency-Key` get the stored response
+                    let (req, maybe_idempotency) = match idempotency_store() {
+                        Some(store) => match store.begin(&profile.service_path, req).await {
+                            (_, Idempotency::Replay(res))
+                            | (_, Idempotency::Conflict(res))
+                            | (_, Idempotency::Mismatch(res)) => return Ok(res),
+                            (req, Idempotency::Record(guard)) => (req, Some(guard)),
+                            (req, Idempotency::Bypass) => (req, None),
+                        },
+                        None => (req, None),
+                    };
+
+                    let req = match maybe_tee.clone() {
+                        Some((limit, events_tx, metadata)) => {
+                            let (parts, body) = req.into_parts();
+                            let body =
+                                tee_body(body, BodyTeeKind::Request, limit, events_tx, metadata);
+                            Request::from_parts(parts, body)
+                        }
+                        None => req,
+                    };
+
+                    // reported once the response body is sent, if the request was slow
+                    let mut maybe_timings = slow_request_threshold().zip(worker_event_sender).map(
+                        |(threshold, events_tx)| {
+                            let metadata = EventMetadata {
+                                service_path: Some(profile.service_path.clone()),
+                                execution_id: Some(execution_id),
+                                ..Default::default()
+                            };
+                            RequestTimings::new(
+                                threshold,
+                                events_tx,
+                                metadata,
+                                req.method().to_string(),
+                                req.uri().path().to_string(),
+                                profile.boot.take(BOOT_WAIT_WINDOW),
+                            )
+                        },
+                    );
+
+                    // wait for a free slot if the worker limits in-flight requests
+                    let queued = Instant::now();
+                    let maybe_permit = match profile.permits {
+                        Some(permits) => Some(permits.acquire_owned().await?),
+                        None => None,
+                    };
+                    if let (Some(timelines), Some(_)) = (maybe_timelines, &maybe_permit) {
+                        timelines.span(&track, "request", "queue", queued, json!({}));
+                    }
+                    if let Some(timings) = &mut maybe_timings {
+                        timings.queued(queued);
+                    }
+
+                    let sent = Instant::now();
                     let result = send_user_worker_request(profile.worker_request_msg_tx, req).await;
+                    // until the response head, the body is streamed afterwards
+                    if let Some(timelines) = maybe_timelines {
+                        let status = result.as_ref().ok().map(|rep| rep.status().as_u16());
+                        timelines.span(
+                            &track,
+                            "request",
+                            &request_name,
+                            sent,
+                            json!({ "status": status }),
+                        );
+                    }
+                    if let Some(timings) = &mut maybe_timings {
+                        let status = result.as_ref().ok().map(|rep| rep.status().as_u16());
+                        timings.responded(sent, status);
+                    }
+
+                    // track per-version error rates, used to roll back failing canaries
+                    if let Some(version) = &profile.version {
+                        let is_error = result
+                            .as_ref()
+                            .map(|rep| rep.status().is_server_error())
+                            .unwrap_or(true);
+                        version.stats.record(is_error);
+                        if is_error {
+                            let _ = worker_pool_msgs_tx
+                                .send(UserWorkerMsgs::CheckCanary(profile.service_path.clone()));
+                        }
+                    }
+
+                    // count the request and the response bytes sent back for usage accounting
+                    let result = match usage {
+                        Some(usage) => {
+                            let service_path = profile.service_path.clone();
+                            usage.record(&service_path, |u| u.requests += 1);
+                            result
+                                .map(|rep| rep.map(|body| count_egress(body, usage, service_path)))
+                        }
+                        None => result,
+                    };
+
+                    // keep the slot occupied until the response body is fully streamed
+                    let result = result.map(|rep| match maybe_permit {
+                        Some(permit) => rep.map(|body| {
+                            Body::wrap_stream(body.map(move |chunk| {
+                                let _permit = &permit;
+                                chunk
+                            }))
+                        }),
+                        None => rep,
+                    });
+                    let result = result.map(|rep| {
+                        rep.map(|body| {
+                            Body::wrap_stream(body.map(move |chunk| {
+                                let _inflight = &inflight;
+                                let _timings = &maybe_timings;
+                                chunk
+                            }))
+                        })
+                    });
+
+                    let result = match maybe_timelines {
+                        Some(_) => result.map(|rep| {
+                            let span = SpanGuard::new(track, "response", request_name);
+                            rep.map(|body| {
+                                Body::wrap_stream(body.map(move |chunk| {
+                                    let _span = &span;
+                                    chunk
+                                }))
+                            })
+                        }),
+                        None => result,
+                    };
+
+                    // stored once the response body is sent, dropped if the request failed
+                    let result = match maybe_idempotency {
+                        Some(guard) => result.map(|rep| guard.record(rep)),
+                        None => result,
+                    };
+
                     match result {
-                        Ok(rep) => Ok(rep),
+                        Ok(rep) => match maybe_tee {
+                            Some((limit, events_tx, metadata)) => {
+                                let (parts, body) = rep.into_parts();
+                                let body = tee_body(
+                                    body,
+                                    BodyTeeKind::Response,
+                                    limit,
+                                    events_tx,
+                                    metadata,
+                                );
+                                Ok(Response::from_parts(parts, body))
+                            }
+                            None => Ok(rep),
+                        },
                         Err(err) => {
                             error!("failed to send request to user worker: {}", err.to_string());
                             Err(err)
@@ -165,7 +615,22 @@ impl WorkerPool {
 
     pub fn retire(&mut self, key: &Uuid) {
         if let Some(profile) = self.user_workers.get(key) {
-            self.active_workers.remove(&profile.service_path);
+            if self.active_workers.get(&profile.service_path) == Some(key) {
+                self.active_workers.remove(&profile.service_path);
+            }
+        }
+    }
+
+    // stop routing new requests to any of the current workers
+    pub fn retire_all(&mut self) {
+        self.active_workers.clear();
+    }
+
+    // stop routing new requests to the current workers of these services (eg:
+    // their definition changed)
+    pub fn retire_services(&mut self, service_paths: &[String]) {
+        for service_path in service_paths {
+            self.active_workers.remove(service_path);
         }
     }
 
@@ -174,10 +639,355 @@ impl WorkerPool {
         self.user_workers.remove(key);
     }
 
+    // Drops the workers without requests in flight (their threads exit once
+    // they can't be reached), staged workers are kept for their activation
+    pub fn evict_idle(&mut self) -> usize {
+        let idle: Vec<Uuid> = self
+            .user_workers
+            .iter()
+            .filter(|(_, profile)| !profile.staged && profile.inflight.load(Ordering::Relaxed) == 0)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &idle {
+            self.shutdown(key);
+        }
+        idle.len()
+    }
+
+    // Picks the canary of a service for a share of requests matching its weight
+    fn maybe_canary(&self, service_path: &String) -> Option<&CanaryVersion> {
+        let canary = self.service_versions.get(service_path)?.canary.as_ref()?;
+        if rand::thread_rng().gen_range(0..100) < canary.weight {
+            Some(canary)
+        } else {
+            None
+        }
+    }
+
     fn maybe_active_worker(&self, service_path: &String, force_create: bool) -> Option<&Uuid> {
         if force_create {
             return None;
         }
-        self.active_workers.get(service_path)
+        let key = self.active_workers.get(service_path)?;
+
+        // a saturated worker that prefers spawning over queueing is not reused
+        if let Some(profile) = self.user_workers.get(key) {
+            let saturated = profile
+                .permits
+                .as_ref()
+                .map(|permits| permits.available_permits() == 0)
+                .unwrap_or(false);
+            if saturated && profile.concurrency_overflow == ConcurrencyOverflowPolicy::Spawn {
+                return None;
+            }
+        }
+
+        Some(key)
+    }
+}
+
+pub(crate) fn apply_version(worker_options: &mut WorkerContextInitOpts, version: &ServiceVersion) {
+    worker_options.maybe_entrypoint = version.maybe_entrypoint.clone();
+    worker_options.maybe_eszip = version
+        .maybe_eszip
+        .as_ref()
+        .map(|eszip| EszipPayloadKind::VecKind(eszip.to_vec()));
+    worker_options.maybe_module_code = None;
+    if version.import_map_path.is_some() {
+        worker_options.import_map_path = version.import_map_path.clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_egress, WorkerPool};
+    use http::Request;
+    use hyper::Body;
+    use sb_worker_context::essentials::{
+        CanaryVersion, ConcurrencyOverflowPolicy, ServiceVersion, UserWorkerProfile,
+        UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+    };
+    use sb_worker_context::paused_services::{
+        pause_service, resume_service, ServicePause, ServicePaused,
+    };
+    use sb_worker_context::usage::UsageCollector;
+    use std::path::PathBuf;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::{mpsc, oneshot, Semaphore};
+    use uuid::Uuid;
+
+    fn staged_profile(service_path: &str) -> UserWorkerProfile {
+        let (worker_request_msg_tx, _) = mpsc::unbounded_channel();
+        UserWorkerProfile {
+            worker_request_msg_tx,
+            service_path: service_path.to_string(),
+            body_tee_max_bytes: None,
+            telemetry_sample_rate: None,
+            permits: None,
+            concurrency_overflow: ConcurrencyOverflowPolicy::default(),
+            staged: true,
+            isolated: false,
+            version: None,
+            inflight: Default::default(),
+            boot: Default::default(),
+        }
+    }
+
+    fn version() -> ServiceVersion {
+        ServiceVersion {
+            id: Uuid::new_v4(),
+            maybe_entrypoint: Some("file:///srv/hello/index.ts".to_string()),
+            maybe_eszip: None,
+            import_map_path: None,
+            stats: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_activate_and_rollback_version() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/hello";
+
+        let (v1, v2) = (version(), version());
+        let (k1, k2) = (Uuid::new_v4(), Uuid::new_v4());
+
+        pool.add_user_worker(k1, staged_profile(service_path));
+        assert!(pool.active_workers.get(service_path).is_none());
+        pool.activate_version(service_path.to_string(), v1.clone(), k1);
+        assert_eq!(pool.active_workers.get(service_path), Some(&k1));
+
+        pool.add_user_worker(k2, staged_profile(service_path));
+        pool.activate_version(service_path.to_string(), v2.clone(), k2);
+        assert_eq!(pool.active_workers.get(service_path), Some(&k2));
+
+        // retiring the replaced worker keeps routing on the new one
+        pool.retire(&k1);
+        assert_eq!(pool.active_workers.get(service_path), Some(&k2));
+
+        let versions = pool.rollback_version(service_path).unwrap();
+        assert_eq!(versions.active.unwrap().id, v1.id);
+        assert_eq!(versions.previous.unwrap().id, v2.id);
+        assert!(pool.active_workers.get(service_path).is_none());
+    }
+
+    #[test]
+    fn test_retire_services() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let (hello, api) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.active_workers
+            .insert("./examples/hello".to_string(), hello);
+        pool.active_workers
+            .insert("./examples/api".to_string(), api);
+
+        pool.retire_services(&["./examples/api".to_string()]);
+        assert!(pool.active_workers.get("./examples/api").is_none());
+        assert_eq!(pool.active_workers.get("./examples/hello"), Some(&hello));
+    }
+
+    #[tokio::test]
+    async fn test_paused_service() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/paused";
+        let key = Uuid::new_v4();
+        let mut profile = staged_profile(service_path);
+        profile.staged = false;
+        pool.add_user_worker(key, profile);
+
+        pause_service(
+            service_path,
+            ServicePause {
+                status: 402,
+                content_type: "text/plain".to_string(),
+                body: "payment required".to_string(),
+                reason: None,
+                paused_at_ms: 0,
+            },
+        );
+
+        // workers created before the pause stop serving
+        let (res_tx, res_rx) = oneshot::channel();
+        pool.send_request(&key, Request::new(Body::empty()), res_tx);
+        assert_eq!(res_rx.await.unwrap().unwrap().status(), 402);
+
+        let (tx, rx) = oneshot::channel();
+        pool.create_user_worker(
+            WorkerContextInitOpts {
+                service_path: PathBuf::from(service_path),
+                no_module_cache: false,
+                import_map_path: None,
+                env_vars: Default::default(),
+                events_rx: None,
+                maybe_eszip: None,
+                maybe_module_code: None,
+                maybe_entrypoint: None,
+                conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts::default()),
+            },
+            tx,
+            false,
+        );
+        let err = rx.await.unwrap().unwrap_err();
+        assert!(err.is::<ServicePaused>());
+
+        resume_service(service_path);
+    }
+
+    #[tokio::test]
+    async fn test_count_egress() {
+        let usage = UsageCollector::default();
+        let chunks: Vec<Result<&'static str, std::io::Error>> = vec![Ok("hello "), Ok("world")];
+        let body = Body::wrap_stream(deno_core::futures::stream::iter(chunks));
+
+        let body = count_egress(body, usage.clone(), "./examples/hello".to_string());
+        // nothing is recorded while the body is being streamed
+        assert!(usage.take().is_empty());
+        let bytes = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(bytes.len(), 11);
+        assert_eq!(usage.take()["./examples/hello"].egress_bytes, 11);
+    }
+
+    // the clock only moves once every task is idle, ie: the request is parked
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_overflow_policy() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/queued";
+        let key = Uuid::new_v4();
+        let (worker_tx, mut worker_rx) = mpsc::unbounded_channel();
+        let permits = Arc::new(Semaphore::new(1));
+        let mut profile = staged_profile(service_path);
+        profile.staged = false;
+        profile.worker_request_msg_tx = worker_tx;
+        profile.permits = Some(permits.clone());
+        profile.concurrency_overflow = ConcurrencyOverflowPolicy::Queue;
+        pool.add_user_worker(key, profile);
+
+        // a saturated worker is still routed to, the request waits for a slot
+        let in_flight = permits.clone().try_acquire_owned().unwrap();
+        assert_eq!(
+            pool.maybe_active_worker(&service_path.to_string(), false),
+            Some(&key)
+        );
+        let (res_tx, _res_rx) = oneshot::channel();
+        pool.send_request(&key, Request::new(Body::empty()), res_tx);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), worker_rx.recv())
+                .await
+                .is_err()
+        );
+
+        drop(in_flight);
+        let msg = tokio::time::timeout(Duration::from_secs(1), worker_rx.recv())
+            .await
+            .unwrap();
+        assert!(msg.is_some());
+    }
+
+    #[test]
+    fn test_spawn_overflow_policy() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/spawned";
+        let key = Uuid::new_v4();
+        let permits = Arc::new(Semaphore::new(1));
+        let mut profile = staged_profile(service_path);
+        profile.staged = false;
+        profile.permits = Some(permits.clone());
+        profile.concurrency_overflow = ConcurrencyOverflowPolicy::Spawn;
+        pool.add_user_worker(key, profile);
+
+        assert_eq!(
+            pool.maybe_active_worker(&service_path.to_string(), false),
+            Some(&key)
+        );
+        // a saturated worker isn't reused, another one is booted instead
+        let _in_flight = permits.try_acquire_owned().unwrap();
+        assert!(pool
+            .maybe_active_worker(&service_path.to_string(), false)
+            .is_none());
+    }
+
+    #[test]
+    fn test_evict_idle_workers() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+
+        let (idle_key, busy_key, staged_key) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut idle = staged_profile("./examples/idle");
+        idle.staged = false;
+        pool.add_user_worker(idle_key, idle);
+        let mut busy = staged_profile("./examples/busy");
+        busy.staged = false;
+        busy.inflight.fetch_add(1, Ordering::Relaxed);
+        pool.add_user_worker(busy_key, busy);
+        pool.add_user_worker(staged_key, staged_profile("./examples/staged"));
+
+        assert_eq!(pool.evict_idle(), 1);
+        assert!(!pool.user_workers.contains_key(&idle_key));
+        assert!(pool.active_workers.get("./examples/idle").is_none());
+        assert_eq!(pool.active_workers.get("./examples/busy"), Some(&busy_key));
+        assert!(pool.user_workers.contains_key(&staged_key));
+    }
+
+    #[test]
+    fn test_isolated_worker_is_not_routed() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/hello";
+
+        let (active_key, isolated_key) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut active = staged_profile(service_path);
+        active.staged = false;
+        pool.add_user_worker(active_key, active);
+
+        let mut isolated = staged_profile(service_path);
+        isolated.staged = false;
+        isolated.isolated = true;
+        pool.add_user_worker(isolated_key, isolated);
+
+        assert!(pool.user_workers.contains_key(&isolated_key));
+        assert_eq!(pool.active_workers.get(service_path), Some(&active_key));
+    }
+
+    #[test]
+    fn test_failing_canary_is_rolled_back() {
+        let (pool_tx, _) = mpsc::unbounded_channel();
+        let mut pool = WorkerPool::new(None, pool_tx, None);
+        let service_path = "./examples/hello";
+
+        let (active_key, canary_key) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.add_user_worker(active_key, staged_profile(service_path));
+        pool.activate_version(service_path.to_string(), version(), active_key);
+
+        let canary = CanaryVersion {
+            version: version(),
+            key: canary_key,
+            weight: 100,
+            max_error_rate: 0.5,
+            min_requests: 4,
+        };
+        pool.add_user_worker(canary_key, staged_profile(service_path));
+        pool.start_canary(service_path.to_string(), canary.clone());
+        assert_eq!(
+            pool.maybe_canary(&service_path.to_string()).map(|c| c.key),
+            Some(canary_key)
+        );
+
+        // not enough requests to judge the canary yet
+        for _ in 0..3 {
+            canary.version.stats.record(true);
+        }
+        pool.check_canary(service_path);
+        assert!(pool.get_versions(service_path).canary.is_some());
+
+        canary.version.stats.record(false);
+        pool.check_canary(service_path);
+        assert!(pool.get_versions(service_path).canary.is_none());
+        assert!(!pool.user_workers.contains_key(&canary_key));
+        assert_eq!(pool.active_workers.get(service_path), Some(&active_key));
     }
 }
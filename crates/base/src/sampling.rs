@@ -0,0 +1,90 @@
+use anyhow::{bail, Error};
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+use rand::Rng;
+use std::sync::OnceLock;
+
+// requests with this header set to `1` are always sampled, eg: to debug them
+pub const FORCE_SAMPLE_HEADER: &str = "x-edge-runtime-sample";
+// the decision passed on to the user worker, so its own telemetry can follow it
+pub const SAMPLED_HEADER: &str = "x-edge-runtime-sampled";
+
+static DEFAULT_SAMPLE_RATE: OnceLock<f64> = OnceLock::new();
+
+fn validate_rate(rate: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&rate) {
+        bail!("sample rate must be between 0 and 1, got {}", rate);
+    }
+    Ok(())
+}
+
+// Rate of services without a rate of their own, every request is sampled when
+// it's not set
+pub fn set_default_sample_rate(rate: f64) -> Result<(), Error> {
+    validate_rate(rate)?;
+    let _ = DEFAULT_SAMPLE_RATE.set(rate);
+    Ok(())
+}
+
+fn sample_rate(service_rate: Option<f64>) -> f64 {
+    service_rate
+        .or(DEFAULT_SAMPLE_RATE.get().copied())
+        .unwrap_or(1.0)
+}
+
+fn is_forced(headers: &HeaderMap) -> bool {
+    headers
+        .get(FORCE_SAMPLE_HEADER)
+        .is_some_and(|value| value == "1" || value == "true")
+}
+
+// `roll` is uniform in [0, 1)
+fn decide(forced: bool, rate: f64, roll: f64) -> bool {
+    forced || roll < rate
+}
+
+// Decides once, when the request reaches the user worker, whether its detailed
+// telemetry (timeline spans and body tees) is collected, and marks the request
+// with the decision
+pub fn sample_request(headers: &mut HeaderMap, service_rate: Option<f64>) -> bool {
+    let sampled = decide(
+        is_forced(headers),
+        sample_rate(service_rate),
+        rand::thread_rng().gen::<f64>(),
+    );
+    headers.insert(
+        SAMPLED_HEADER,
+        HeaderValue::from_static(if sampled { "1" } else { "0" }),
+    );
+    sampled
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decide, sample_request, validate_rate, FORCE_SAMPLE_HEADER, SAMPLED_HEADER};
+    use hyper::HeaderMap;
+
+    #[test]
+    fn test_sampling_decision() {
+        assert!(decide(false, 1.0, 0.999));
+        assert!(!decide(false, 0.0, 0.0));
+        assert!(decide(false, 0.25, 0.1));
+        assert!(!decide(false, 0.25, 0.3));
+        assert!(decide(true, 0.0, 0.5));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(FORCE_SAMPLE_HEADER, "1".parse().unwrap());
+        // a client can't opt out of sampling by sending the decision itself
+        headers.insert(SAMPLED_HEADER, "0".parse().unwrap());
+        assert!(sample_request(&mut headers, Some(0.0)));
+        assert_eq!(headers.get(SAMPLED_HEADER).unwrap(), "1");
+
+        let mut headers = HeaderMap::new();
+        assert!(!sample_request(&mut headers, Some(0.0)));
+        assert_eq!(headers.get(SAMPLED_HEADER).unwrap(), "0");
+
+        assert!(validate_rate(0.5).is_ok());
+        assert!(validate_rate(1.5).is_err());
+        assert!(validate_rate(f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+use sb_worker_context::sequences::SharedSequences;
+use std::sync::OnceLock;
+
+static SEQUENCES: OnceLock<SharedSequences> = OnceLock::new();
+
+// Sequences shared by every worker of the node
+pub fn sequences() -> SharedSequences {
+    SEQUENCES.get_or_init(Default::default).clone()
+}
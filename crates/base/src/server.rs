@@ -1,36 +1,136 @@
-use crate::rt_worker::worker_ctx::{
-    create_events_worker, create_main_worker, create_user_worker_pool,
+use crate::admin::{serve_admin, AdminState};
+use crate::ai_gateway::{set_ai_gateway, AiConfig};
+use crate::alarms::{start_alarm_scheduler, SqliteAlarmStore};
+use crate::broadcast::enable_broadcast_relay;
+use crate::cluster::{cluster_member, is_cluster_leader, join_cluster};
+use crate::connections::connection_metrics;
+use crate::discovery::{start_registration, DiscoveryConfig};
+use crate::embeddings::{set_embedding_models, EmbeddingSessions};
+use crate::fallback::{FallbackRouter, FallbackServices};
+use crate::fault_injection::{enable_fault_injection, FaultInjectionConfig};
+use crate::feature_flags::{
+    start_feature_flags, FeatureFlagSource, DEFAULT_FEATURE_FLAGS_REFRESH_SECS,
 };
-use anyhow::Error;
+use crate::gc::{set_gc_config, GcConfig};
+use crate::geo::{apply_geo_headers, enable_geoip};
+use crate::handoff::{
+    inherited_admin_listener, inherited_listener, notify_handed_over, Successor, UpgradeSignal,
+    DEFAULT_DRAIN_TIMEOUT,
+};
+use crate::http3::{serve_http3, Http3Config};
+use crate::images::enable_images;
+use crate::k8s::{
+    downward_api_identity, enable_kubernetes_lifecycle, kubernetes_lifecycle, start_probes,
+    Lifecycle, ProbePorts, TerminateSignal, DEFAULT_PRESTOP_DELAY,
+    DEFAULT_TERMINATION_GRACE_PERIOD,
+};
+use crate::key_store::{set_key_store, KeyStoreConfig};
+use crate::load_shedding::{concurrency_limiter, enable_load_shedding, DEFAULT_MAX_CONCURRENCY};
+use crate::mail::{set_mailer, MailConfig};
+use crate::module_reload::{set_trusted_reload_proxies, strip_untrusted_reload_header};
+use crate::node::{
+    node_identity, served_by_header, set_node_identity, NodeIdentity, SERVED_BY_HEADER,
+};
+use crate::onnx::{set_model_runner, OnnxConfig};
+use crate::outbound_webhooks::{start_webhook_dispatcher, SqliteWebhookStore};
+use crate::preload::Preloads;
+use crate::redis_pool::{set_redis_pool, RedisConfig};
+use crate::replay::{take_record_header, Recorder, RECORDING_ID_HEADER, RECORD_TOKEN_ENV};
+use crate::rt_worker::cgroups::enable_worker_cgroups;
+use crate::rt_worker::crash::{crash_report_dir, set_crash_report_dir};
+use crate::rt_worker::deadline::apply_inbound_deadline;
+use crate::rt_worker::event_filters::{filter_events, EventFilters, EventFiltersConfig};
+use crate::rt_worker::event_quotas::{limit_events, EventQuota};
+use crate::rt_worker::events_spill::{EventsSpillOpts, DEFAULT_EVENTS_SPILL_MAX_MB};
+use crate::rt_worker::events_supervisor::EventsMetrics;
+use crate::rt_worker::idempotency::{
+    enable_idempotency, IdempotencyOpts, DEFAULT_IDEMPOTENCY_MAX_ENTRIES,
+    DEFAULT_IDEMPOTENCY_MAX_MB,
+};
+use crate::rt_worker::main_worker_supervisor::{
+    start_main_worker_supervisor, MainWorkerOpts, MainWorkerSlot,
+};
+use crate::rt_worker::memory_pressure::{
+    start_memory_coordinator, MemoryCoordinatorOpts, DEFAULT_MEMORY_CRITICAL_RATIO,
+    DEFAULT_MEMORY_HIGH_RATIO,
+};
+use crate::rt_worker::process_worker::enable_process_isolation;
+use crate::rt_worker::sandbox::enable_worker_sandbox;
+use crate::rt_worker::slow_requests::set_slow_request_threshold;
+use crate::rt_worker::worker_ctx::{create_events_worker, create_user_worker_pool};
+use crate::sampling::set_default_sample_rate;
+use crate::service_env::disallow_service_env_files;
+use crate::std_bundle::enable_std_bundle;
+use crate::systemd;
+use crate::test_runtime::enable_test_clock;
+use crate::timeline::enable_timelines;
+use crate::usage::{start_usage_reporter, UsageSink, DEFAULT_USAGE_FLUSH_INTERVAL_SECS};
+use crate::utils::units::mib_to_bytes;
+use anyhow::{anyhow, bail, Error};
 use event_worker::events::WorkerEventWithMetadata;
-use hyper::{server::conn::Http, service::Service, Body, Request, Response};
-use log::{debug, error, info};
-use sb_worker_context::essentials::WorkerRequestMsg;
+use event_worker::redaction::{redactor, set_redactor, RedactionConfig, Redactor};
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::{server::conn::Http, service::Service, Body, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use module_fetcher::fetch_retry::{set_fetch_retry_policy, FetchRetryPolicy};
+use module_fetcher::module_scanner::{set_module_scanner, CommandScanner, DEFAULT_SCAN_TIMEOUT};
+use module_fetcher::registry_mirrors::{set_registry_mirrors, RegistryMirrors};
+use sb_core::images::ImageLimits;
+use sb_core::problem::{problem_response, RuntimeErrorCode};
+use sb_worker_context::alarms::SharedAlarmStore;
+use sb_worker_context::essentials::{UserWorkerMsgs, WorkerRequestMsg};
+use sb_worker_context::manifest::{Manifest, SharedManifest};
+use sb_worker_context::usage::UsageCollector;
 use std::future::Future;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use std::task::Poll;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+// backlog of the listener shared by the members of a cluster (the std default)
+const LISTEN_BACKLOG: u32 = 128;
 
 pub enum ServerCodes {
     Listening,
     Failure,
 }
 
+#[derive(Clone)]
 struct WorkerService {
-    worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker: MainWorkerSlot,
+    fallback: FallbackRouter,
+    default_deadline_ms: Option<u64>,
+    recorder: Option<Recorder>,
+    // address of the connection's peer, if the request came through a listener
+    peer: Option<SocketAddr>,
 }
 
 impl WorkerService {
-    fn new(worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>) -> Self {
-        Self { worker_req_tx }
+    fn new(
+        main_worker: MainWorkerSlot,
+        fallback: FallbackRouter,
+        default_deadline_ms: Option<u64>,
+        recorder: Option<Recorder>,
+        peer: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            main_worker,
+            fallback,
+            default_deadline_ms,
+            recorder,
+            peer,
+        }
     }
 }
 
@@ -43,46 +143,336 @@ impl Service<Request<Body>> for WorkerService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        connection_metrics().record_request();
+        // shed requests early while the runtime is over its concurrency limit,
+        // instead of queueing them up
+        let maybe_permit = match concurrency_limiter() {
+            Some(limiter) => match limiter.try_acquire() {
+                Some(permit) => Some(permit),
+                None => {
+                    let mut res = problem_response(
+                        RuntimeErrorCode::Overloaded,
+                        Some("the runtime is over its concurrency limit".to_string()),
+                    );
+                    res.headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+                    return Box::pin(async { Ok(res) });
+                }
+            },
+            None => None,
+        };
+        apply_inbound_deadline(&mut req, self.default_deadline_ms);
+        apply_geo_headers(&mut req, self.peer);
+        strip_untrusted_reload_header(&mut req, self.peer);
+        let record_header = take_record_header(&mut req);
+
         // create a response in a future.
-        let worker_req_tx = self.worker_req_tx.clone();
+        let maybe_worker_req_tx = self.main_worker.read().unwrap().clone();
+        let fallback = self.fallback.clone();
+        let recorder = self.recorder.clone();
         let fut = async move {
             let req_uri = req.uri().clone();
 
+            let mut maybe_recording_id = None;
+            if let Some(recorder) =
+                recorder.filter(|recorder| recorder.should_record(record_header.as_ref()))
+            {
+                let (recorded_req, maybe_id) = recorder.record(req).await?;
+                req = recorded_req;
+                maybe_recording_id = maybe_id;
+            }
+
             let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
             let msg = WorkerRequestMsg { req, res_tx };
 
-            worker_req_tx.send(msg)?;
+            // route requests with the fallback router while the main worker is down
+            let Some(worker_req_tx) = maybe_worker_req_tx else {
+                return Ok(fallback.handle(msg.req).await);
+            };
+            if let Err(SendError(msg)) = worker_req_tx.send(msg) {
+                return Ok(fallback.handle(msg.req).await);
+            }
+
             let result = res_rx.await?;
             match result {
-                Ok(res) => Ok(res),
+                Ok(mut res) => {
+                    if let Some(id) = maybe_recording_id {
+                        res.headers_mut()
+                            .insert(RECORDING_ID_HEADER, HeaderValue::from_str(&id)?);
+                    }
+                    Ok(res)
+                }
                 Err(e) => {
                     error!(
                         "request failed (uri: {:?} reason: {:?})",
                         req_uri.to_string(),
                         e
                     );
-                    // FIXME: add an error body
-                    Ok(Response::builder().status(500).body(Body::empty()).unwrap())
+                    Ok(problem_response(RuntimeErrorCode::InternalError, None))
                 }
             }
         };
 
-        // Return the response as an immediate future
-        Box::pin(fut)
+        let served_by = served_by_header();
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(permit) = maybe_permit {
+                let status = res.status();
+                permit.finish(
+                    status == StatusCode::SERVICE_UNAVAILABLE
+                        || status == StatusCode::GATEWAY_TIMEOUT,
+                );
+            }
+            if let Some(served_by) = served_by {
+                res.headers_mut().insert(SERVED_BY_HEADER, served_by);
+            }
+            Ok(res)
+        })
     }
 }
 
+// Tags events with where this node runs (region, zone and Kubernetes node and pod)
+// before forwarding them
+fn stamp_node_identity(
+    next: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    identity: NodeIdentity,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        while let Some(mut event) = events_rx.recv().await {
+            event.metadata.region = identity.region.clone();
+            event.metadata.zone = identity.zone.clone();
+            event.metadata.node = identity.node.clone();
+            event.metadata.pod = identity.pod.clone();
+            let _ = next.send(event);
+        }
+    });
+    events_tx
+}
+
+// Redacts the text of events before they reach the events worker or listener
+fn redact_events(
+    next: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+    redactor: &'static Redactor,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        while let Some(mut event) = events_rx.recv().await {
+            redactor.redact_event(&mut event.event);
+            let _ = next.send(event);
+        }
+    });
+    events_tx
+}
+
+// Copies events to `listener` before forwarding them to the events worker (if any)
+fn tee_events(
+    maybe_events_worker: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    listener: mpsc::UnboundedSender<WorkerEventWithMetadata>,
+) -> mpsc::UnboundedSender<WorkerEventWithMetadata> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+    tokio::task::spawn(async move {
+        while let Some(event) = events_rx.recv().await {
+            let _ = listener.send(event.clone());
+            if let Some(events_worker) = &maybe_events_worker {
+                let _ = events_worker.send(event);
+            }
+        }
+    });
+    events_tx
+}
+
 pub struct WorkerEntrypoints {
     pub main: Option<String>,
     pub events: Option<String>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ServerFlags {
+    // path to a deployment manifest (functions.json)
+    pub manifest_path: Option<String>,
+    // port for the admin API (only bound on localhost)
+    pub admin_port: Option<u16>,
+    // file path or HTTP endpoint receiving usage records
+    pub usage_sink: Option<String>,
+    pub usage_flush_interval_secs: Option<u64>,
+    // deadline applied to requests without an `x-deadline-ms` header
+    pub request_deadline_ms: Option<u64>,
+    // services served by the fallback router (`/<name>` -> `<dir>/<name>`) when the
+    // main worker is down and the manifest doesn't match the request, see
+    // `FallbackServices`
+    pub fallback_services_dir: Option<String>,
+    // directory crash reports of panicking workers are written to
+    pub crash_report_dir: Option<String>,
+    // config of faults to inject for chaos testing (never set this in production)
+    pub fault_injection_path: Option<String>,
+    // requests with the `x-edge-runtime-record` header are recorded to this directory
+    pub record_dir: Option<String>,
+    // file path or HTTP endpoint serving feature flags (exposed as `EdgeRuntime.flags`)
+    pub feature_flags: Option<String>,
+    pub feature_flags_refresh_secs: Option<u64>,
+    // Redis relaying `BroadcastChannel` messages to other runtime instances
+    pub broadcast_redis_url: Option<String>,
+    // sqlite database alarms (`EdgeRuntime.alarms`) are persisted to
+    pub alarms_db_path: Option<String>,
+    // config of the signing keys functions can use through `EdgeRuntime.crypto`
+    pub key_store_path: Option<String>,
+    // SMTP accounts of the services sending mail with `EdgeRuntime.mail`
+    pub mail_config_path: Option<String>,
+    // Redis servers of the services using `EdgeRuntime.redis`
+    pub redis_config_path: Option<String>,
+    // providers and token quotas of the services using `EdgeRuntime.ai`
+    pub ai_config_path: Option<String>,
+    // ONNX models of the services using `EdgeRuntime.onnx`
+    pub onnx_config_path: Option<String>,
+    // directory built-in embedding models are downloaded to, `EdgeRuntime.ai.Session`
+    // is only available when set
+    pub embeddings_cache_dir: Option<String>,
+    // embedding batches run at once, half the CPUs by default
+    pub embeddings_max_concurrency: Option<usize>,
+    // sqlite database queueing webhooks sent with `EdgeRuntime.webhooks.send`
+    pub webhooks_db_path: Option<String>,
+    // memory a single image operation may use, `EdgeRuntime.images` is only
+    // available when set
+    pub image_memory_limit_mb: Option<u64>,
+    // MaxMind databases (eg: GeoLite2 City and ASN) requests are enriched from
+    pub geoip_db_paths: Vec<String>,
+    // user workers run on a virtual clock tests can freeze and advance
+    pub test_clock: bool,
+    // identity of this node in a multi-region fleet
+    pub region: Option<String>,
+    pub zone: Option<String>,
+    // how long open connections are given to finish after handing the listener
+    // over to a new process (on SIGUSR2)
+    pub drain_timeout_secs: Option<u64>,
+    // place user worker threads in per-service cgroups (v2), under this cgroup
+    // directory or the runtime's own cgroup
+    pub worker_cgroups: bool,
+    pub worker_cgroup_root: Option<String>,
+    // restrict user worker threads with seccomp and landlock (linux only)
+    pub sandbox_workers: bool,
+    // run every user worker in a child process of its own
+    pub process_isolation: bool,
+    // experimental HTTP/3 listener on this UDP port, serving with the given
+    // certificate and private key (PEM)
+    pub http3_port: Option<u16>,
+    pub http3_cert_path: Option<String>,
+    pub http3_key_path: Option<String>,
+    // record worker timelines, keeping this many spans (exported by the admin API)
+    pub trace_timelines: Option<usize>,
+    // share of requests detailed telemetry is collected for, by default (services
+    // can set their own with `telemetrySampleRate`)
+    pub telemetry_sample_rate: Option<f64>,
+    // rules redacting sensitive data from the console output and events of workers
+    pub redaction_config_path: Option<String>,
+    // events (logs, body tees, webhook deliveries) and log bytes each service may
+    // emit per minute, the rest are dropped
+    pub event_quota_per_minute: Option<u64>,
+    pub log_quota_bytes_per_minute: Option<u64>,
+    // directory events are spilled to while the events worker can't keep up,
+    // instead of dropping them, and replayed from once it recovers
+    pub events_spill_dir: Option<String>,
+    pub events_spill_max_mb: Option<u64>,
+    // expressions selecting the events forwarded to the events worker and the
+    // listener
+    pub event_filters_path: Option<String>,
+    // V8 heap sizes and idle garbage collection of each class of worker
+    pub gc_config_path: Option<String>,
+    // mirrors of the registries modules are downloaded from, tried when the
+    // origin fails
+    pub registry_mirrors_path: Option<String>,
+    // eszip of common remote modules served in place of the network, or the one
+    // embedded in the binary (`embedded_std_bundle`), limited to the modules
+    // under the `std_bundle_allow` prefixes (the std library by default)
+    pub std_bundle_path: Option<String>,
+    pub embedded_std_bundle: bool,
+    pub std_bundle_allow: Vec<String>,
+    // watch the memory of the node (its cgroup's memory.max, or an RSS limit) and
+    // shed idle workers, then new ones, under pressure
+    pub memory_coordinator: bool,
+    pub memory_limit_mb: Option<u64>,
+    pub memory_high_ratio: Option<f64>,
+    pub memory_critical_ratio: Option<f64>,
+    // shed requests over a concurrency limit adapted to the latency of responses,
+    // which can't grow past `max_concurrency`
+    pub adaptive_concurrency: bool,
+    pub max_concurrency: Option<usize>,
+    // requests to user workers slower than this are reported with a `SlowRequest`
+    // event, broken down by phase
+    pub slow_request_threshold_ms: Option<u64>,
+    // responses to requests with an `Idempotency-Key` are stored this long and
+    // returned to their retries, in a store bounded by entries and MiB of bodies
+    pub idempotency_ttl_secs: Option<u64>,
+    pub idempotency_max_entries: Option<usize>,
+    pub idempotency_max_mb: Option<u64>,
+    // retries of module downloads failing with transient errors, with an
+    // exponential backoff from `module_fetch_backoff_ms` and at most
+    // `module_fetch_retry_budget` retries per worker boot
+    pub module_fetch_max_attempts: Option<u32>,
+    pub module_fetch_backoff_ms: Option<u64>,
+    pub module_fetch_retry_budget: Option<u32>,
+    // command scanning the modules downloaded from remote registries before they
+    // are cached, given `module_scanner_args` then the specifier
+    pub module_scanner: Option<String>,
+    pub module_scanner_args: Vec<String>,
+    pub module_scanner_timeout_ms: Option<u64>,
+    // peers (eg: a preview proxy) whose requests may ask for their worker's
+    // modules to be fetched again with the `x-edge-runtime-reload-modules` header
+    pub reload_header_trusted_proxies: Vec<String>,
+    // ignore the `.env` files of services (eg: in production, where secrets are
+    // given by the platform)
+    pub no_service_env_files: bool,
+    // Consul or etcd endpoint this node registers itself (and the services it
+    // serves) with
+    pub discovery_config_path: Option<String>,
+    // Kubernetes lifecycle: SIGTERM drains connections within the termination
+    // grace period, after the `preStop` delay, and the node and pod are taken
+    // from the downward API
+    pub kubernetes: bool,
+    pub prestop_delay_secs: Option<u64>,
+    pub termination_grace_period_secs: Option<u64>,
+    // ports of the readiness (`/readyz`, `/prestop`) and liveness (`/livez`)
+    // probes, which may be the same
+    pub readiness_port: Option<u16>,
+    pub liveness_port: Option<u16>,
+    // receives a copy of every worker event (used by `TestRuntime`)
+    pub event_listener: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+}
+
+// Everything `Server` is built from. The main service is optional, without it
+// requests are routed to the services of the manifest (see `FallbackRouter`).
+pub struct ServerOpts {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub main_service_path: Option<String>,
+    pub events_service_path: Option<String>,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    pub callback_tx: Option<Sender<ServerCodes>>,
+    pub entrypoints: WorkerEntrypoints,
+    pub flags: ServerFlags,
+    // services registered programmatically, merged into the manifest
+    pub services: Option<Manifest>,
+    // takes precedence over `flags.alarms_db_path`
+    pub alarm_store: Option<SharedAlarmStore>,
+}
+
 pub struct Server {
     ip: Ipv4Addr,
     port: u16,
-    main_worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker: MainWorkerSlot,
+    fallback: FallbackRouter,
     callback_tx: Option<Sender<ServerCodes>>,
+    admin_port: Option<u16>,
+    admin_state: AdminState,
+    request_deadline_ms: Option<u64>,
+    recorder: Option<Recorder>,
+    drain_timeout: Duration,
+    http3: Option<Http3Config>,
+    // registered once listening, with the paths of the services it serves
+    discovery: Option<(DiscoveryConfig, Vec<String>)>,
+    probes: ProbePorts,
 }
 
 impl Server {
@@ -96,12 +486,204 @@ impl Server {
         no_module_cache: bool,
         callback_tx: Option<Sender<ServerCodes>>,
         entrypoints: WorkerEntrypoints,
+        flags: ServerFlags,
     ) -> Result<Self, Error> {
+        Self::with_opts(ServerOpts {
+            ip: Ipv4Addr::from_str(ip)?,
+            port,
+            main_service_path: Some(main_service_path),
+            events_service_path: maybe_events_service_path,
+            import_map_path,
+            no_module_cache,
+            callback_tx,
+            entrypoints,
+            flags,
+            services: None,
+            alarm_store: None,
+        })
+        .await
+    }
+
+    pub async fn with_opts(opts: ServerOpts) -> Result<Self, Error> {
+        let ServerOpts {
+            ip,
+            port,
+            main_service_path: maybe_main_service_path,
+            events_service_path: maybe_events_service_path,
+            import_map_path,
+            no_module_cache,
+            callback_tx,
+            entrypoints,
+            flags,
+            services,
+            alarm_store,
+        } = opts;
+
         let mut worker_events_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>> = None;
         let maybe_events_entrypoint = entrypoints.events;
         let maybe_main_entrypoint = entrypoints.main;
 
+        if let Some(member) = join_cluster()? {
+            info!("running as member {} of the cluster", member.index);
+        }
+        let mut identity = NodeIdentity {
+            region: flags.region.clone(),
+            zone: flags.zone.clone(),
+            ..Default::default()
+        };
+        if flags.kubernetes {
+            identity = downward_api_identity(identity);
+            enable_kubernetes_lifecycle(Lifecycle::new(
+                flags
+                    .prestop_delay_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_PRESTOP_DELAY),
+                flags
+                    .termination_grace_period_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_TERMINATION_GRACE_PERIOD),
+            ));
+        }
+        if !identity.is_empty() {
+            set_node_identity(identity);
+        }
+        if let Some(dir) = &flags.crash_report_dir {
+            set_crash_report_dir(PathBuf::from(dir));
+        }
+        if let Some(path) = &flags.fault_injection_path {
+            enable_fault_injection(FaultInjectionConfig::load(Path::new(path))?);
+        }
+        if let Some(path) = &flags.key_store_path {
+            set_key_store(KeyStoreConfig::load(Path::new(path))?.into_key_store()?);
+        }
+        if let Some(path) = &flags.mail_config_path {
+            set_mailer(Arc::new(MailConfig::load(Path::new(path))?.start()?));
+        }
+        if let Some(path) = &flags.redis_config_path {
+            set_redis_pool(Arc::new(RedisConfig::load(Path::new(path))?.into_pool()?));
+        }
+        if let Some(path) = &flags.ai_config_path {
+            set_ai_gateway(Arc::new(AiConfig::load(Path::new(path))?.into_gateway()?));
+        }
+        if let Some(path) = &flags.onnx_config_path {
+            set_model_runner(Arc::new(OnnxConfig::load(Path::new(path))?.into_runner()?));
+        }
+        if let Some(dir) = &flags.embeddings_cache_dir {
+            set_embedding_models(Arc::new(EmbeddingSessions::new(
+                PathBuf::from(dir),
+                flags.embeddings_max_concurrency,
+            )?));
+        }
+        if let Some(mb) = flags.image_memory_limit_mb {
+            enable_images(ImageLimits::from_mb(mb));
+        }
+        if flags.test_clock {
+            enable_test_clock();
+        }
+        if flags.adaptive_concurrency {
+            enable_load_shedding(flags.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY));
+        }
+        if let Some(threshold_ms) = flags.slow_request_threshold_ms {
+            set_slow_request_threshold(Duration::from_millis(threshold_ms));
+        }
+        if let Some(ttl_secs) = flags.idempotency_ttl_secs {
+            enable_idempotency(IdempotencyOpts {
+                ttl: Duration::from_secs(ttl_secs),
+                max_entries: flags
+                    .idempotency_max_entries
+                    .unwrap_or(DEFAULT_IDEMPOTENCY_MAX_ENTRIES),
+                max_bytes: mib_to_bytes(
+                    flags
+                        .idempotency_max_mb
+                        .unwrap_or(DEFAULT_IDEMPOTENCY_MAX_MB),
+                ) as usize,
+            });
+        }
+        let default_retry_policy = FetchRetryPolicy::default();
+        set_fetch_retry_policy(FetchRetryPolicy {
+            max_attempts: flags
+                .module_fetch_max_attempts
+                .unwrap_or(default_retry_policy.max_attempts)
+                .max(1),
+            base_delay: flags
+                .module_fetch_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default_retry_policy.base_delay),
+            budget: flags
+                .module_fetch_retry_budget
+                .unwrap_or(default_retry_policy.budget),
+            ..default_retry_policy
+        });
+        if let Some(path) = &flags.gc_config_path {
+            set_gc_config(GcConfig::load(Path::new(path))?);
+        }
+        if let Some(path) = &flags.registry_mirrors_path {
+            set_registry_mirrors(RegistryMirrors::load(Path::new(path))?);
+        }
+        set_trusted_reload_proxies(&flags.reload_header_trusted_proxies)?;
+        if flags.no_service_env_files {
+            disallow_service_env_files();
+        }
+        if let Some(program) = &flags.module_scanner {
+            set_module_scanner(Arc::new(CommandScanner {
+                program: PathBuf::from(program),
+                args: flags.module_scanner_args.clone(),
+                timeout: flags
+                    .module_scanner_timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_SCAN_TIMEOUT),
+            }));
+        }
+        if flags.std_bundle_path.is_some() || flags.embedded_std_bundle {
+            enable_std_bundle(
+                flags.std_bundle_path.as_deref().map(Path::new),
+                flags.std_bundle_allow.clone(),
+            )
+            .await?;
+        }
+        if let Some(capacity) = flags.trace_timelines {
+            enable_timelines(capacity);
+        }
+        if let Some(rate) = flags.telemetry_sample_rate {
+            set_default_sample_rate(rate)?;
+        }
+        if !flags.geoip_db_paths.is_empty() {
+            let paths: Vec<PathBuf> = flags.geoip_db_paths.iter().map(PathBuf::from).collect();
+            enable_geoip(&paths)?;
+        }
+        let http3 = match (
+            flags.http3_port,
+            &flags.http3_cert_path,
+            &flags.http3_key_path,
+        ) {
+            (Some(http3_port), Some(cert_path), Some(key_path)) => Some(Http3Config {
+                addr: SocketAddr::new(IpAddr::V4(ip), http3_port),
+                cert_path: PathBuf::from(cert_path),
+                key_path: PathBuf::from(key_path),
+            }),
+            (Some(_), _, _) => bail!("the HTTP/3 listener needs a certificate and a private key"),
+            (None, _, _) => None,
+        };
+        if let Some(url) = &flags.broadcast_redis_url {
+            enable_broadcast_relay(url)?;
+        }
+        if let Some(source) = &flags.feature_flags {
+            let interval = flags
+                .feature_flags_refresh_secs
+                .unwrap_or(DEFAULT_FEATURE_FLAGS_REFRESH_SECS);
+            start_feature_flags(
+                Arc::new(FeatureFlagSource::from_str(source)?),
+                Duration::from_secs(interval),
+            )
+            .await?;
+        }
+
         // Create Event Worker
+        let events_metrics = EventsMetrics::default();
+        let event_filters = match &flags.event_filters_path {
+            Some(path) => EventFiltersConfig::load(Path::new(path))?.into_filters()?,
+            None => EventFilters::default(),
+        };
         if let Some(events_service_path) = maybe_events_service_path {
             let events_path = Path::new(&events_service_path);
             let events_path_buf = events_path.to_path_buf();
@@ -111,61 +693,387 @@ impl Server {
                 import_map_path.clone(),
                 no_module_cache,
                 maybe_events_entrypoint,
+                flags.events_spill_dir.as_ref().map(|dir| EventsSpillOpts {
+                    dir: PathBuf::from(dir),
+                    max_bytes: mib_to_bytes(
+                        flags
+                            .events_spill_max_mb
+                            .unwrap_or(DEFAULT_EVENTS_SPILL_MAX_MB),
+                    ),
+                }),
+                events_metrics.clone(),
             )
             .await?;
 
-            worker_events_sender = Some(events_worker);
+            worker_events_sender = Some(match event_filters.events_worker {
+                Some(filter) => filter_events(events_worker, filter, events_metrics.clone()),
+                None => events_worker,
+            });
+        }
+        if let Some(listener) = flags.event_listener.clone() {
+            let listener = match event_filters.listener {
+                Some(filter) => filter_events(listener, filter, events_metrics.clone()),
+                None => listener,
+            };
+            worker_events_sender = Some(tee_events(worker_events_sender, listener));
+        }
+        let identity = node_identity();
+        if !identity.is_empty() {
+            worker_events_sender =
+                worker_events_sender.map(|next| stamp_node_identity(next, identity));
+        }
+        if let Some(path) = &flags.redaction_config_path {
+            set_redactor(Redactor::new(&RedactionConfig::load(Path::new(path))?)?);
+        }
+        if let Some(redactor) = redactor() {
+            worker_events_sender = worker_events_sender.map(|next| redact_events(next, redactor));
+        }
+        let event_quota = EventQuota {
+            events_per_minute: flags.event_quota_per_minute,
+            log_bytes_per_minute: flags.log_quota_bytes_per_minute,
+        };
+        if event_quota.is_set() {
+            worker_events_sender = worker_events_sender
+                .map(|next| limit_events(next, event_quota, events_metrics.clone()));
+        }
+
+        // Load deployment manifest
+        let mut maybe_manifest = match &flags.manifest_path {
+            Some(path) => Some(Manifest::load(Path::new(path))?),
+            None => None,
+        };
+        if let Some(services) = services {
+            let manifest = maybe_manifest.get_or_insert_with(Manifest::default);
+            manifest.services.extend(services.services);
+            manifest.preload.extend(services.preload);
+        }
+        let maybe_manifest: Option<SharedManifest> =
+            maybe_manifest.map(|manifest| Arc::new(RwLock::new(manifest)));
+
+        // Start usage accounting
+        let maybe_usage = match &flags.usage_sink {
+            Some(sink) => {
+                let collector = UsageCollector::default();
+                let interval = flags
+                    .usage_flush_interval_secs
+                    .unwrap_or(DEFAULT_USAGE_FLUSH_INTERVAL_SECS);
+                start_usage_reporter(
+                    collector.clone(),
+                    UsageSink::from_str(sink)?,
+                    Duration::from_secs(interval),
+                );
+                Some(collector)
+            }
+            None => None,
+        };
+
+        // the other members of a cluster leave the queued webhooks and alarms to
+        // the first one, so they are sent once
+        let webhooks_db_path = flags
+            .webhooks_db_path
+            .as_ref()
+            .filter(|_| is_cluster_leader());
+        if let Some(path) = webhooks_db_path {
+            start_webhook_dispatcher(
+                Arc::new(SqliteWebhookStore::open(Path::new(path))?),
+                worker_events_sender.clone(),
+            )?;
         }
 
         // Create a user worker pool
-        let user_worker_msgs_tx = create_user_worker_pool(worker_events_sender).await?;
+        let admin_events_tx = worker_events_sender.clone();
+        let user_worker_msgs_tx =
+            create_user_worker_pool(worker_events_sender, maybe_usage).await?;
+        let preloads = match &maybe_manifest {
+            Some(manifest) => {
+                Preloads::start(&manifest.read().unwrap(), user_worker_msgs_tx.clone())
+            }
+            None => Preloads::default(),
+        };
+        if flags.memory_coordinator {
+            start_memory_coordinator(
+                MemoryCoordinatorOpts {
+                    limit_bytes: flags.memory_limit_mb.map(mib_to_bytes),
+                    high_ratio: flags.memory_high_ratio.unwrap_or(DEFAULT_MEMORY_HIGH_RATIO),
+                    critical_ratio: flags
+                        .memory_critical_ratio
+                        .unwrap_or(DEFAULT_MEMORY_CRITICAL_RATIO),
+                },
+                user_worker_msgs_tx.clone(),
+            )?;
+        }
 
-        // create main worker
-        let main_worker_path = Path::new(&main_service_path).to_path_buf();
-        let main_worker_req_tx = create_main_worker(
-            main_worker_path,
-            import_map_path.clone(),
-            no_module_cache,
-            user_worker_msgs_tx,
-            maybe_main_entrypoint,
-        )
-        .await?;
+        let alarm_store: Option<SharedAlarmStore> = match (alarm_store, &flags.alarms_db_path) {
+            (Some(store), _) => Some(store),
+            (None, Some(path)) => Some(Arc::new(SqliteAlarmStore::open(Path::new(path))?)),
+            (None, None) => None,
+        };
+        if let Some(store) = alarm_store.filter(|_| is_cluster_leader()) {
+            start_alarm_scheduler(store, user_worker_msgs_tx.clone());
+        }
+
+        // the node is registered once by the first member of a cluster
+        let discovery = match flags
+            .discovery_config_path
+            .as_ref()
+            .filter(|_| is_cluster_leader())
+        {
+            Some(path) => {
+                let mut service_paths: Vec<String> =
+                    maybe_main_service_path.iter().cloned().collect();
+                if let Some(manifest) = &maybe_manifest {
+                    let manifest = manifest.read().unwrap();
+                    service_paths.extend(
+                        manifest
+                            .services
+                            .values()
+                            .map(|service| service.entrypoint.clone()),
+                    );
+                }
+                Some((DiscoveryConfig::load(Path::new(path))?, service_paths))
+            }
+            None => None,
+        };
+
+        let recorder = match &flags.record_dir {
+            Some(dir) => Some(Recorder::new(
+                PathBuf::from(dir),
+                std::env::var(RECORD_TOKEN_ENV).map_err(|_| {
+                    anyhow!("--record-dir requires the {} env var", RECORD_TOKEN_ENV)
+                })?,
+                maybe_main_service_path.clone().unwrap_or_default(),
+                maybe_main_entrypoint.clone(),
+                import_map_path.clone(),
+            )?),
+            None => None,
+        };
+
+        // create main worker (restarted whenever it exits)
+        let main_worker = match maybe_main_service_path {
+            Some(main_service_path) => {
+                start_main_worker_supervisor(MainWorkerOpts {
+                    service_path: PathBuf::from(main_service_path),
+                    import_map_path: import_map_path.clone(),
+                    no_module_cache,
+                    worker_pool_tx: user_worker_msgs_tx.clone(),
+                    maybe_entrypoint: maybe_main_entrypoint,
+                    maybe_manifest: maybe_manifest.clone(),
+                })
+                .await
+            }
+            None => MainWorkerSlot::default(),
+        };
+        let fallback = FallbackRouter::new(
+            user_worker_msgs_tx.clone(),
+            maybe_manifest.clone(),
+            flags
+                .fallback_services_dir
+                .map(|dir| FallbackServices::load(Path::new(&dir)))
+                .transpose()?,
+        );
+
+        if flags.sandbox_workers {
+            enable_worker_sandbox(crash_report_dir())?;
+        }
+        if flags.worker_cgroups {
+            enable_worker_cgroups(flags.worker_cgroup_root.map(PathBuf::from));
+        }
+        if flags.process_isolation {
+            enable_process_isolation();
+        }
 
         // register alarm signal handler
         cpu_timer::register_alarm()?;
 
-        let ip = Ipv4Addr::from_str(ip)?;
         Ok(Self {
             ip,
             port,
-            main_worker_req_tx,
+            main_worker,
+            fallback,
             callback_tx,
+            admin_port: flags.admin_port,
+            request_deadline_ms: flags.request_deadline_ms,
+            recorder,
+            drain_timeout: flags
+                .drain_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+            http3,
+            discovery,
+            probes: ProbePorts {
+                readiness: flags.readiness_port,
+                liveness: flags.liveness_port,
+            },
+            admin_state: AdminState {
+                manifest: maybe_manifest,
+                manifest_path: flags.manifest_path,
+                worker_pool_tx: user_worker_msgs_tx,
+                events_metrics,
+                preloads,
+                events_tx: admin_events_tx,
+            },
         })
     }
 
+    // Sender for creating user workers and sending requests to them
+    pub fn worker_pool_tx(&self) -> mpsc::UnboundedSender<UserWorkerMsgs> {
+        self.admin_state.worker_pool_tx.clone()
+    }
+
+    // Handles a single request, for embedders serving requests themselves
+    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        WorkerService::new(
+            self.main_worker.clone(),
+            self.fallback.clone(),
+            self.request_deadline_ms,
+            self.recorder.clone(),
+            None,
+        )
+        .call(req)
+        .await
+    }
+
+    // Serves on the listener handed over by a previous process (or systemd) if
+    // there's one, otherwise binds the configured address
     pub async fn listen(&mut self) -> Result<(), Error> {
-        let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
-        let listener = TcpListener::bind(&addr).await?;
+        let listener = match inherited_listener()? {
+            Some(listener) => TcpListener::from_std(listener)?,
+            None => {
+                let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
+                match cluster_member() {
+                    // the members of a cluster share the port
+                    Some(_) => {
+                        let socket = TcpSocket::new_v4()?;
+                        socket.set_reuseport(true)?;
+                        socket.bind(addr)?;
+                        socket.listen(LISTEN_BACKLOG)?
+                    }
+                    None => TcpListener::bind(&addr).await?,
+                }
+            }
+        };
+        self.listen_on(listener).await
+    }
+
+    // Serves requests on an already bound listener
+    pub async fn listen_on(&mut self, listener: TcpListener) -> Result<(), Error> {
         debug!("edge-runtime is listening on {:?}", listener.local_addr()?);
+        let maybe_registration = match self.discovery.take() {
+            Some((config, service_paths)) => {
+                let port = listener.local_addr()?.port();
+                let node = config.registration(self.ip, port, service_paths)?;
+                Some((config, node))
+            }
+            None => None,
+        };
+
+        // kept to hand the admin listener over on upgrades
+        let mut maybe_admin_listener: Option<std::net::TcpListener> = None;
+        // served by the first member of a cluster
+        if let Some(admin_port) = self.admin_port.filter(|_| is_cluster_leader()) {
+            let admin_listener = match inherited_admin_listener()? {
+                Some(listener) => listener,
+                None => {
+                    let admin_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), admin_port);
+                    let listener = std::net::TcpListener::bind(admin_addr)?;
+                    listener.set_nonblocking(true)?;
+                    listener
+                }
+            };
+            debug!(
+                "admin api is listening on {:?}",
+                admin_listener.local_addr()?
+            );
+            let served = TcpListener::from_std(admin_listener.try_clone()?)?;
+            tokio::task::spawn(serve_admin(served, self.admin_state.clone()));
+            maybe_admin_listener = Some(admin_listener);
+        }
+
+        // answered while the preloaded workers boot, readiness failing meanwhile
+        if self.probes.is_set() && is_cluster_leader() {
+            start_probes(
+                IpAddr::V4(self.ip),
+                self.probes,
+                self.admin_state.preloads.clone(),
+            )
+            .await?;
+        }
+
+        // not ready (nor taking over from a previous process) until the preloaded
+        // workers booted, connections wait in the listener's backlog meanwhile
+        self.admin_state.preloads.wait().await;
 
         if let Some(callback) = self.callback_tx.clone() {
             let _ = callback.send(ServerCodes::Listening).await;
         }
+        // lets the process that handed the listener over drain
+        notify_handed_over();
+        systemd::notify_ready();
+        systemd::start_watchdog();
+        // announced once ready to serve
+        let maybe_discovery = match maybe_registration {
+            Some((config, node)) => Some(start_registration(config, node)?),
+            None => None,
+        };
+
+        // connections finish their in-flight requests and close once this fires
+        let (drain_tx, drain_rx) = watch::channel(());
+        if let Some(config) = self.http3.clone().filter(|_| is_cluster_leader()) {
+            let service = WorkerService::new(
+                self.main_worker.clone(),
+                self.fallback.clone(),
+                self.request_deadline_ms,
+                self.recorder.clone(),
+                None,
+            );
+            let make_service = move |peer| WorkerService {
+                peer: Some(peer),
+                ..service.clone()
+            };
+            // the UDP socket isn't handed over on upgrades, so a new process can't
+            // serve HTTP/3 while this one is draining
+            let drain_rx = drain_rx.clone();
+            tokio::task::spawn(async move {
+                if let Err(err) = serve_http3(config, make_service, drain_rx).await {
+                    error!("http3 listener error ({:#})", err);
+                }
+            });
+        }
+        let mut upgrade_signal = UpgradeSignal::new()?;
+        let (successor_tx, mut successor_rx) = mpsc::channel::<Result<u32, Error>>(1);
+        let mut upgrading = false;
+        let mut terminate_signal = TerminateSignal::new()?;
+        // set on SIGTERM, new connections are accepted until then
+        let mut stop_accepting_at: Option<Instant> = None;
 
-        loop {
-            let main_worker_req_tx = self.main_worker_req_tx.clone();
+        let handed_over = loop {
+            let main_worker = self.main_worker.clone();
+            let fallback = self.fallback.clone();
+            let request_deadline_ms = self.request_deadline_ms;
+            let recorder = self.recorder.clone();
 
             tokio::select! {
                 msg = listener.accept() => {
                     match msg {
-                       Ok((conn, _)) => {
+                       Ok((conn, peer)) => {
+                           let mut drain_rx = drain_rx.clone();
+                           let connection = connection_metrics().accept();
                            tokio::task::spawn(async move {
-                             let service = WorkerService::new(main_worker_req_tx);
+                             let service = WorkerService::new(main_worker, fallback, request_deadline_ms, recorder, Some(peer));
 
                              let conn_fut = Http::new()
                                 .serve_connection(conn, service);
+                             tokio::pin!(conn_fut);
 
-                             if let Err(e) = conn_fut.await {
+                             let result = tokio::select! {
+                                 result = conn_fut.as_mut() => result,
+                                 _ = drain_rx.changed() => {
+                                     conn_fut.as_mut().graceful_shutdown();
+                                     conn_fut.await
+                                 }
+                             };
+                             if let Err(e) = result {
+                                 connection.failed();
                                  // Most common cause for these errors are when the client closes the connection before
                                  // we could send a response
                                  error!("client connection error ({:?})", e);
@@ -175,12 +1083,75 @@ impl Server {
                        Err(e) => error!("socket error: {}", e)
                     }
                 }
+                // the members of a cluster are restarted by the process supervising them
+                _ = upgrade_signal.recv(), if !upgrading && stop_accepting_at.is_none() && cluster_member().is_none() => {
+                    info!("upgrade signal received, starting a new runtime process");
+                    match Successor::spawn(&listener, maybe_admin_listener.as_ref()) {
+                        Ok(successor) => {
+                            upgrading = true;
+                            let successor_tx = successor_tx.clone();
+                            tokio::task::spawn(async move {
+                                let pid = successor.pid();
+                                let _ = successor_tx.send(successor.ready().await.map(|_| pid)).await;
+                            });
+                        }
+                        Err(err) => error!("{:#}", err),
+                    }
+                }
+                Some(result) = successor_rx.recv() => {
+                    upgrading = false;
+                    match result {
+                        Ok(pid) => {
+                            info!("new runtime process ({}) is listening, draining connections", pid);
+                            systemd::notify_main_pid(pid);
+                            break true;
+                        }
+                        // keep serving
+                        Err(err) => error!("upgrade failed: {:#}", err),
+                    }
+                }
+                _ = terminate_signal.recv(), if stop_accepting_at.is_none() => {
+                    info!("terminate signal received, draining");
+                    systemd::notify_stopping();
+                    stop_accepting_at = kubernetes_lifecycle().map(Lifecycle::stop_accepting_at);
+                }
+                _ = tokio::time::sleep_until(stop_accepting_at.unwrap_or_else(Instant::now)), if stop_accepting_at.is_some() => {
+                    break false;
+                }
                 // wait for shutdown signal...
                 _ = tokio::signal::ctrl_c() => {
                     info!("shutdown signal received");
-                    break;
+                    systemd::notify_stopping();
+                    if let Some(discovery) = maybe_discovery {
+                        discovery.stop(true).await;
+                    }
+                    return Ok(());
                 }
             }
+        };
+
+        // deregistered, unless a new process took the node over
+        if let Some(discovery) = maybe_discovery {
+            discovery.stop(!handed_over).await;
+        }
+        // on upgrades the socket stays open in the new process, so nothing is
+        // refused
+        drop(listener);
+        drop(drain_rx);
+        let _ = drain_tx.send(());
+        // a terminating pod drains within what its grace period leaves
+        let drain_deadline = match kubernetes_lifecycle().filter(|_| !handed_over) {
+            Some(lifecycle) => lifecycle.drain_deadline(),
+            None => Instant::now() + self.drain_timeout,
+        };
+        if tokio::time::timeout_at(drain_deadline, drain_tx.closed())
+            .await
+            .is_err()
+        {
+            warn!(
+                "closing {} connections still open after draining",
+                drain_tx.receiver_count()
+            );
         }
         Ok(())
     }
@@ -0,0 +1,183 @@
+use anyhow::{bail, Error};
+use deno_ast::EmitOptions;
+use deno_core::serde_json;
+use module_fetcher::args::config_file::ConfigFile;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+const CONFIG_FILE_NAMES: [&str; 2] = ["deno.json", "deno.jsonc"];
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    remote: BTreeMap<String, String>,
+}
+
+// The checksums (sha256 of the source) of the remote modules of a `deno.lock`
+pub fn read_lockfile(path: &Path) -> Result<BTreeMap<String, String>, Error> {
+    let lockfile: Lockfile = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|err| anyhow::anyhow!("invalid lockfile {}: {}", path.display(), err))?;
+    Ok(lockfile.remote)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct JsxOptions {
+    jsx: Option<String>,
+    jsx_factory: Option<String>,
+    jsx_fragment_factory: Option<String>,
+    jsx_import_source: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct LibOptions {
+    lib: Option<Vec<String>>,
+}
+
+// The `deno.json` (or `deno.jsonc`) in the directory of a service. Its import
+// map, JSX compiler options and lockfile are honored when the service's workers
+// boot, so it behaves as it does under `deno run`.
+pub struct ServiceConfig {
+    file: ConfigFile,
+}
+
+impl ServiceConfig {
+    pub fn discover(service_dir: &Path) -> Result<Option<Self>, Error> {
+        for name in CONFIG_FILE_NAMES {
+            let path = service_dir.join(name);
+            if path.is_file() {
+                let file = ConfigFile::read(&std::fs::canonicalize(path)?)?;
+                return Ok(Some(Self { file }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn dir(&self) -> std::path::PathBuf {
+        let mut path = self.file.specifier.to_file_path().unwrap();
+        path.pop();
+        path
+    }
+
+    fn compiler_option<T: for<'de> Deserialize<'de> + Default>(&self) -> T {
+        self.file
+            .compiler_options()
+            .cloned()
+            .and_then(|options| serde_json::from_value(options).ok())
+            .unwrap_or_default()
+    }
+
+    // The import map referenced by `importMap`, or else the one declared inline
+    // (`imports` and `scopes`), in a form `load_import_map` takes
+    pub fn import_map_path(&self) -> Option<String> {
+        if let Some(path) = self.file.to_import_map_path() {
+            return Some(self.dir().join(path).to_string_lossy().to_string());
+        }
+        let value = self.file.to_import_map_value();
+        if value.as_object().map_or(true, |value| value.is_empty()) {
+            return None;
+        }
+        let json = value.to_string();
+        Some(format!(
+            "data:{}?{}",
+            urlencoding::encode(&json),
+            urlencoding::encode(&self.dir().to_string_lossy())
+        ))
+    }
+
+    // The emit options with the JSX compiler options of the config applied
+    pub fn emit_options(&self, mut options: EmitOptions) -> Result<EmitOptions, Error> {
+        // validates `jsx` and `jsxImportSource` the way deno does
+        self.file.to_maybe_jsx_import_source_config()?;
+        let jsx: JsxOptions = self.compiler_option();
+        let (transform_jsx, jsx_automatic, jsx_development) = match jsx.jsx.as_deref() {
+            None | Some("react") => (true, false, false),
+            Some("react-jsx") => (true, true, false),
+            Some("react-jsxdev") => (true, true, true),
+            Some("preserve") => (false, false, false),
+            Some(other) => bail!("unsupported jsx compiler option '{}'", other),
+        };
+        options.transform_jsx = transform_jsx;
+        options.jsx_automatic = jsx_automatic;
+        options.jsx_development = jsx_development;
+        if let Some(factory) = jsx.jsx_factory {
+            options.jsx_factory = factory;
+        }
+        if let Some(factory) = jsx.jsx_fragment_factory {
+            options.jsx_fragment_factory = factory;
+        }
+        options.jsx_import_source = jsx.jsx_import_source;
+        Ok(options)
+    }
+
+    // `compilerOptions.lib`, without deno's own libs (the edge runtime's globals
+    // are always declared)
+    pub fn libs(&self) -> Option<Vec<String>> {
+        let options: LibOptions = self.compiler_option();
+        let libs: Vec<String> = options
+            .lib?
+            .into_iter()
+            .map(|lib| lib.to_lowercase())
+            .filter(|lib| !lib.starts_with("deno."))
+            .collect();
+        (!libs.is_empty()).then_some(libs)
+    }
+
+    // The checksums of the remote modules locked by the lockfile (`deno.lock`
+    // next to the config unless `lock` says otherwise), if there's one
+    pub fn locked_modules(&self) -> Result<Option<HashMap<String, String>>, Error> {
+        let Some(path) = self.file.resolve_lockfile_path()? else {
+            return Ok(None);
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(read_lockfile(&path)?.into_iter().collect()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServiceConfig;
+    use deno_ast::EmitOptions;
+
+    #[test]
+    fn test_service_config() {
+        let dir = std::env::temp_dir().join(format!("service-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("locks")).unwrap();
+        std::fs::write(
+            dir.join("deno.jsonc"),
+            r#"{
+                // comments are allowed
+                "compilerOptions": {
+                    "jsx": "react-jsx",
+                    "jsxImportSource": "npm:preact",
+                    "lib": ["deno.window", "DOM"]
+                },
+                "imports": { "preact": "npm:preact@10" },
+                "lock": "locks/deno.lock"
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("locks/deno.lock"),
+            r#"{ "version": "3", "remote": { "https://deno.land/std/http/server.ts": "abc" } }"#,
+        )
+        .unwrap();
+
+        let config = ServiceConfig::discover(&dir).unwrap().unwrap();
+        let options = config.emit_options(EmitOptions::default()).unwrap();
+        assert!(options.jsx_automatic && !options.jsx_development);
+        assert_eq!(options.jsx_import_source.as_deref(), Some("npm:preact"));
+        assert_eq!(config.libs(), Some(vec!["dom".to_string()]));
+        assert!(config.import_map_path().unwrap().starts_with("data:"));
+        let locked = config.locked_modules().unwrap().unwrap();
+        assert_eq!(locked["https://deno.land/std/http/server.ts"], "abc");
+
+        assert!(ServiceConfig::discover(&dir.join("locks"))
+            .unwrap()
+            .is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
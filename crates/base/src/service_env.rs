@@ -0,0 +1,327 @@
+use anyhow::{bail, Error};
+use log::warn;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// loaded in order, the later overriding the earlier
+const ENV_FILE_NAMES: [&str; 2] = [".env", ".env.local"];
+
+static ENV_FILES_DISALLOWED: AtomicBool = AtomicBool::new(false);
+
+// The `.env` files of services are ignored from now on (eg: in production, where
+// secrets come from the platform)
+pub fn disallow_service_env_files() {
+    ENV_FILES_DISALLOWED.store(true, Ordering::Relaxed);
+}
+
+pub fn service_env_files_allowed() -> bool {
+    !ENV_FILES_DISALLOWED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct EnvEntry {
+    key: String,
+    value: String,
+    // single quoted values are taken literally
+    expand: bool,
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Rest of a line after a closing quote, only a comment may follow it
+fn check_trailing(rest: &str, line_no: usize) -> Result<(), Error> {
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        bail!(
+            "unexpected '{}' after the quoted value on line {}",
+            rest,
+            line_no
+        );
+    }
+    Ok(())
+}
+
+// Parses a dotenv file: `KEY=value` lines (optionally prefixed with `export`),
+// `#` comments, and single or double quoted values spanning several lines
+fn parse_env_file(content: &str) -> Result<Vec<EnvEntry>, Error> {
+    let mut entries = vec![];
+    let mut lines = content.lines().enumerate().map(|(i, line)| (i + 1, line));
+    while let Some((line_no, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("expected KEY=value on line {}", line_no);
+        };
+        let key = key.trim();
+        if !is_valid_key(key) {
+            bail!("invalid variable name '{}' on line {}", key, line_no);
+        }
+        let value = value.trim_start();
+
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            // unquoted, up to a ` #` comment
+            let value = match value.find(" #") {
+                Some(end) => &value[..end],
+                None => value,
+            };
+            entries.push(EnvEntry {
+                key: key.to_string(),
+                value: value.trim_end().to_string(),
+                expand: true,
+            });
+            continue;
+        };
+
+        let mut raw = value[1..].to_string();
+        let value = loop {
+            if let Some(end) = find_closing_quote(&raw, quote) {
+                check_trailing(&raw[end + 1..], line_no)?;
+                raw.truncate(end);
+                break raw;
+            }
+            let Some((_, next)) = lines.next() else {
+                bail!("unterminated quoted value of {} on line {}", key, line_no);
+            };
+            raw.push('\n');
+            raw.push_str(next);
+        };
+        entries.push(EnvEntry {
+            key: key.to_string(),
+            value: if quote == '"' {
+                unescape(&value)
+            } else {
+                value
+            },
+            expand: quote == '"',
+        });
+    }
+    Ok(entries)
+}
+
+fn find_closing_quote(value: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            // single quoted values have no escapes
+            '\\' if quote == '"' => escaped = !escaped,
+            c if c == quote && !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+// Escapes of double quoted values, `\$` is kept for `expand`
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('$') => out.push_str("\\$"),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+// Interpolates `$VAR`, `${VAR}` and `${VAR:-default}` with `lookup`, undefined
+// variables expanding to the default or an empty string. `\$` is a literal `$`.
+fn expand<'a>(value: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> Result<String, Error> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("\\$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('\\') {
+            out.push('\\');
+            rest = after;
+            continue;
+        }
+
+        let after = &rest[1..];
+        if let Some(braced) = after.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                bail!("unterminated ${{ in '{}'", value);
+            };
+            let (name, default) = match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            };
+            if !is_valid_key(name) {
+                bail!("invalid variable name '{}' in '{}'", name, value);
+            }
+            match lookup(name).filter(|value| !value.is_empty()) {
+                Some(value) => out.push_str(value),
+                None => out.push_str(default.unwrap_or_default()),
+            }
+            rest = &braced[end + 1..];
+            continue;
+        }
+        let len = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        if len == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+            // not a variable, eg: a lone `$`
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        out.push_str(lookup(&after[..len]).unwrap_or_default());
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Adds the variables of the `.env` and `.env.local` files of a service to the
+// env vars its worker is created with. The vars given on creation take
+// precedence over `.env.local`, which takes precedence over `.env`. Values are
+// interpolated with the vars given on creation and those defined before them,
+// the env of the runtime process is never read.
+pub fn load_service_env(
+    service_dir: &Path,
+    mut env_vars: HashMap<String, String>,
+) -> Result<HashMap<String, String>, Error> {
+    let paths: Vec<_> = ENV_FILE_NAMES
+        .iter()
+        .map(|name| service_dir.join(name))
+        .filter(|path| path.is_file())
+        .collect();
+    if paths.is_empty() {
+        return Ok(env_vars);
+    }
+    if !service_env_files_allowed() {
+        warn!(
+            "ignoring the env files of {} (env files are disallowed)",
+            service_dir.display()
+        );
+        return Ok(env_vars);
+    }
+
+    let mut file_vars: HashMap<String, String> = HashMap::new();
+    for path in paths {
+        let content = std::fs::read_to_string(&path)?;
+        let entries = parse_env_file(&content)
+            .map_err(|err| anyhow::anyhow!("invalid env file {}: {}", path.display(), err))?;
+        for entry in entries {
+            let value = if entry.expand {
+                let lookup = |name: &str| {
+                    env_vars
+                        .get(name)
+                        .or_else(|| file_vars.get(name))
+                        .map(String::as_str)
+                };
+                expand(&entry.value, lookup).map_err(|err| {
+                    anyhow::anyhow!("invalid env file {}: {}", path.display(), err)
+                })?
+            } else {
+                entry.value
+            };
+            file_vars.insert(entry.key, value);
+        }
+    }
+    for (key, value) in file_vars {
+        env_vars.entry(key).or_insert(value);
+    }
+    Ok(env_vars)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{expand, load_service_env, parse_env_file, EnvEntry};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_env_file() {
+        let entries = parse_env_file(
+            r#"
+# comment
+export HOST=localhost # trailing comment
+URL = "http://${HOST}:$PORT\n"
+RAW='$HOST
+multi'
+EMPTY=
+"#,
+        )
+        .unwrap();
+        let entry = |key: &str, value: &str, expand: bool| EnvEntry {
+            key: key.to_string(),
+            value: value.to_string(),
+            expand,
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("HOST", "localhost", true),
+                entry("URL", "http://${HOST}:$PORT\n", true),
+                entry("RAW", "$HOST\nmulti", false),
+                entry("EMPTY", "", true),
+            ]
+        );
+
+        assert!(parse_env_file("NOT A VAR").is_err());
+        assert!(parse_env_file("1KEY=value").is_err());
+        assert!(parse_env_file("KEY=\"unterminated").is_err());
+        assert!(parse_env_file("KEY=\"value\" trailing").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let vars = HashMap::from([("HOST", "db"), ("EMPTY", "")]);
+        let lookup = |name: &str| vars.get(name).copied();
+        assert_eq!(
+            expand("postgres://$HOST:${PORT:-5432}/${EMPTY:-app}", lookup).unwrap(),
+            "postgres://db:5432/app"
+        );
+        assert_eq!(
+            expand("\\$HOST costs $5 $", lookup).unwrap(),
+            "$HOST costs $5 $"
+        );
+        assert_eq!(expand("$MISSING", lookup).unwrap(), "");
+        assert!(expand("${HOST", lookup).is_err());
+    }
+
+    #[test]
+    fn test_load_service_env() {
+        let dir = std::env::temp_dir().join(format!("service-env-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(".env"),
+            "DB_HOST=db\nDB_URL=postgres://${DB_HOST}/$DB_NAME\nLEVEL=info\nDB_NAME=app",
+        )
+        .unwrap();
+        std::fs::write(dir.join(".env.local"), "DB_HOST=localhost\nLEVEL=debug").unwrap();
+
+        let given = HashMap::from([("LEVEL".to_string(), "warn".to_string())]);
+        let env = load_service_env(&dir, given).unwrap();
+        // interpolated with the vars defined before it
+        assert_eq!(env["DB_URL"], "postgres://db/");
+        assert_eq!(env["DB_HOST"], "localhost");
+        // the vars given on creation win
+        assert_eq!(env["LEVEL"], "warn");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{bail, Error};
+use deno_ast::MediaType;
+use deno_core::futures::io::{AllowStdIo, BufReader};
+use deno_core::ModuleSpecifier;
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Bundle embedded at build time from the eszip at `EDGE_RUNTIME_STD_BUNDLE`,
+// empty when the variable wasn't set
+static EMBEDDED_STD_BUNDLE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/STD_BUNDLE.eszip"));
+
+// modules of the bundle served when no allowlist is given
+pub const DEFAULT_STD_BUNDLE_ALLOW: [&str; 2] =
+    ["https://deno.land/std@", "https://deno.land/std/"];
+
+static STD_BUNDLE: OnceLock<StdBundle> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct BundledModule {
+    // final specifier of the module, after the redirects recorded in the bundle
+    pub specifier: ModuleSpecifier,
+    pub media_type: MediaType,
+    pub source: Arc<str>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StdBundleSnapshot {
+    pub modules: usize,
+    pub served: u64,
+}
+
+// Commonly used remote modules (eg: the std library) pre-resolved in an eszip,
+// served by the module loader in place of the network. Only the modules under
+// the allowed prefixes are served, the others are fetched as usual.
+pub struct StdBundle {
+    modules: HashMap<ModuleSpecifier, BundledModule>,
+    allow: Vec<String>,
+    served: AtomicU64,
+}
+
+impl StdBundle {
+    pub async fn parse(bytes: Vec<u8>, allow: Vec<String>) -> Result<Self, Error> {
+        let reader = BufReader::new(AllowStdIo::new(bytes.as_slice()));
+        let (eszip, loader) = eszip::EszipV2::parse(reader).await?;
+        loader.await?;
+
+        let mut modules = HashMap::new();
+        for specifier in eszip.specifiers() {
+            let Ok(requested) = ModuleSpecifier::parse(&specifier) else {
+                continue;
+            };
+            if !matches!(requested.scheme(), "http" | "https") {
+                continue;
+            }
+            let Some(module) = eszip.get_module(&specifier) else {
+                continue;
+            };
+            let media_type = match module.kind {
+                eszip::ModuleKind::JavaScript => MediaType::JavaScript,
+                eszip::ModuleKind::Json => MediaType::Json,
+                _ => continue,
+            };
+            let Some(source) = module.source().await else {
+                continue;
+            };
+            modules.insert(
+                requested,
+                BundledModule {
+                    specifier: ModuleSpecifier::parse(&module.specifier)?,
+                    media_type,
+                    source: std::str::from_utf8(&source)?.into(),
+                },
+            );
+        }
+        Ok(Self {
+            modules,
+            allow,
+            served: AtomicU64::new(0),
+        })
+    }
+
+    pub fn get(&self, specifier: &ModuleSpecifier) -> Option<BundledModule> {
+        if !self
+            .allow
+            .iter()
+            .any(|prefix| specifier.as_str().starts_with(prefix.as_str()))
+        {
+            return None;
+        }
+        let module = self.modules.get(specifier)?;
+        self.served.fetch_add(1, Ordering::Relaxed);
+        Some(module.clone())
+    }
+
+    pub fn snapshot(&self) -> StdBundleSnapshot {
+        StdBundleSnapshot {
+            modules: self.modules.len(),
+            served: self.served.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Serves the modules of the bundle at `path` (the embedded one when not given)
+// from now on
+pub async fn enable_std_bundle(maybe_path: Option<&Path>, allow: Vec<String>) -> Result<(), Error> {
+    let bytes = match maybe_path {
+        Some(path) => tokio::fs::read(path).await?,
+        None if EMBEDDED_STD_BUNDLE.is_empty() => {
+            bail!("no std bundle was embedded in this build (see EDGE_RUNTIME_STD_BUNDLE)")
+        }
+        None => EMBEDDED_STD_BUNDLE.to_vec(),
+    };
+    let allow = if allow.is_empty() {
+        DEFAULT_STD_BUNDLE_ALLOW.map(String::from).to_vec()
+    } else {
+        allow
+    };
+    let bundle = StdBundle::parse(bytes, allow).await?;
+    info!(
+        "serving {} modules from the std bundle",
+        bundle.modules.len()
+    );
+    let _ = STD_BUNDLE.set(bundle);
+    Ok(())
+}
+
+pub fn std_bundle() -> Option<&'static StdBundle> {
+    STD_BUNDLE.get()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BundledModule, StdBundle, DEFAULT_STD_BUNDLE_ALLOW};
+    use deno_ast::MediaType;
+    use deno_core::ModuleSpecifier;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_only_allowed_modules_are_served() {
+        let module = |url: &str| {
+            let specifier = ModuleSpecifier::parse(url).unwrap();
+            let module = BundledModule {
+                specifier: specifier.clone(),
+                media_type: MediaType::JavaScript,
+                source: "export {};".into(),
+            };
+            (specifier, module)
+        };
+        let bundle = StdBundle {
+            modules: HashMap::from([
+                module("https://deno.land/std@0.203.0/http/server.ts"),
+                module("https://esm.sh/zod@3"),
+            ]),
+            allow: DEFAULT_STD_BUNDLE_ALLOW.map(String::from).to_vec(),
+            served: Default::default(),
+        };
+
+        let served =
+            ModuleSpecifier::parse("https://deno.land/std@0.203.0/http/server.ts").unwrap();
+        assert_eq!(bundle.get(&served).unwrap().specifier, served);
+        let missing = ModuleSpecifier::parse("https://deno.land/std@0.203.0/path/mod.ts").unwrap();
+        assert!(bundle.get(&missing).is_none());
+        // bundled, but not allowed
+        let not_allowed = ModuleSpecifier::parse("https://esm.sh/zod@3").unwrap();
+        assert!(bundle.get(&not_allowed).is_none());
+        assert_eq!(bundle.snapshot().served, 1);
+        assert_eq!(bundle.snapshot().modules, 2);
+    }
+}
@@ -0,0 +1,185 @@
+use log::{debug, error};
+use std::time::Duration;
+
+// Integration with systemd: listeners passed by socket activation and service
+// state reported through sd_notify(3). Everything here is a no-op when the
+// runtime isn't started by systemd.
+
+// `FileDescriptorName=` of the sockets, unnamed sockets are served as `http`
+pub const HTTP_SOCKET_NAME: &str = "http";
+pub const ADMIN_SOCKET_NAME: &str = "admin";
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{bail, Error};
+    use std::os::fd::RawFd;
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::{Mutex, OnceLock};
+
+    use super::HTTP_SOCKET_NAME;
+
+    // first fd passed by socket activation, see sd_listen_fds(3)
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    static ACTIVATED_FDS: OnceLock<Mutex<Vec<(String, RawFd)>>> = OnceLock::new();
+
+    // Sockets passed to `pid`, named by `LISTEN_FDNAMES`
+    pub(super) fn parse_listen_fds(
+        listen_pid: Option<&str>,
+        listen_fds: Option<&str>,
+        listen_fdnames: Option<&str>,
+        pid: u32,
+    ) -> Result<Vec<(String, RawFd)>, Error> {
+        if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+            return Ok(vec![]);
+        }
+        let Some(count) = listen_fds else {
+            return Ok(vec![]);
+        };
+        let Ok(count) = count.parse::<RawFd>() else {
+            bail!("invalid LISTEN_FDS: {}", count);
+        };
+        let names: Vec<&str> = listen_fdnames
+            .map(|n| n.split(':').collect())
+            .unwrap_or_default();
+        Ok((0..count)
+            .map(|i| {
+                let name = match names.get(i as usize) {
+                    Some(name) if !name.is_empty() && *name != "unknown" => name.to_string(),
+                    _ => HTTP_SOCKET_NAME.to_string(),
+                };
+                (name, SD_LISTEN_FDS_START + i)
+            })
+            .collect())
+    }
+
+    fn activated_fds() -> &'static Mutex<Vec<(String, RawFd)>> {
+        ACTIVATED_FDS.get_or_init(|| {
+            let var = |name| std::env::var(name).ok();
+            let fds = parse_listen_fds(
+                var("LISTEN_PID").as_deref(),
+                var("LISTEN_FDS").as_deref(),
+                var("LISTEN_FDNAMES").as_deref(),
+                std::process::id(),
+            )
+            .unwrap_or_else(|err| {
+                log::error!("{:#}", err);
+                vec![]
+            });
+            // not passed on to anything this process spawns
+            for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+                std::env::remove_var(name);
+            }
+            Mutex::new(fds)
+        })
+    }
+
+    // The socket named `name` passed by systemd, each socket is taken once
+    pub fn take_activated_fd(name: &str) -> Option<RawFd> {
+        let mut fds = activated_fds().lock().unwrap();
+        let i = fds.iter().position(|(n, _)| n == name)?;
+        Some(fds.remove(i).1)
+    }
+
+    pub fn notify(state: &str) -> Result<bool, Error> {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(false);
+        };
+        let socket = UnixDatagram::unbound()?;
+        let path = path.to_string_lossy();
+        match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => bail!("abstract notify sockets are only supported on linux"),
+            None => {
+                socket.send_to(state.as_bytes(), path.as_ref())?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(unix)]
+pub use unix::take_activated_fd;
+
+// Sends a state update (eg: `READY=1`) to systemd
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    match unix::notify(state) {
+        Ok(true) => debug!("sd_notify: {}", state.replace('\n', " ")),
+        Ok(false) => {}
+        Err(err) => error!("sd_notify failed: {:#}", err),
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+pub fn notify_ready() {
+    notify(&format!("READY=1\nMAINPID={}", std::process::id()));
+}
+
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+// Tells systemd to track `pid` (a new runtime process taking over) instead
+pub fn notify_main_pid(pid: u32) {
+    notify(&format!("MAINPID={}", pid));
+}
+
+// Interval of the watchdog pings, half the timeout systemd expects them within
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    let for_us = match std::env::var("WATCHDOG_PID") {
+        Ok(pid) => pid.parse::<u32>().ok() == Some(std::process::id()),
+        Err(_) => true,
+    };
+    if !for_us || usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+// Pings the watchdog from the server's event loop, so systemd restarts the
+// runtime when the loop hangs
+pub fn start_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    debug!("pinging the systemd watchdog every {:?}", interval);
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::unix::parse_listen_fds;
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(
+            parse_listen_fds(Some("42"), Some("2"), Some("http:admin"), 42).unwrap(),
+            vec![("http".to_string(), 3), ("admin".to_string(), 4)]
+        );
+        // unnamed sockets are served as http
+        assert_eq!(
+            parse_listen_fds(Some("42"), Some("1"), None, 42).unwrap(),
+            vec![("http".to_string(), 3)]
+        );
+        // meant for another process
+        assert!(parse_listen_fds(Some("7"), Some("1"), None, 42)
+            .unwrap()
+            .is_empty());
+        assert!(parse_listen_fds(Some("42"), Some("x"), None, 42).is_err());
+    }
+}
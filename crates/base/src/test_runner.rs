@@ -0,0 +1,398 @@
+use crate::coverage::CoverageReport;
+use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json;
+use hyper::{Body, Request};
+use sb_core::coverage::ScriptCoverage;
+use sb_worker_context::essentials::{
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const TEST_FILE_SUFFIXES: [&str; 2] = ["_test.ts", "_test.js"];
+
+#[derive(Debug, Clone, Default)]
+pub struct TestRunOpts {
+    pub service_path: PathBuf,
+    pub import_map_path: Option<String>,
+    pub no_module_cache: bool,
+    // only run tests whose name contains this string
+    pub filter: Option<String>,
+    // local files loaded in place of modules (eg: remote ones), by specifier
+    pub mocks: HashMap<String, PathBuf>,
+    // collects the coverage of the service's modules, written there as lcov
+    pub coverage_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Ok,
+    Failed,
+    Ignored,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+    pub name: String,
+    pub status: TestStatus,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TestFileOutput {
+    load_error: Option<String>,
+    results: Vec<TestResult>,
+    #[serde(default)]
+    coverage: Vec<ScriptCoverage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestFileReport {
+    pub path: PathBuf,
+    pub results: Vec<TestResult>,
+    // set if the file couldn't be loaded or run at all
+    pub error: Option<String>,
+    // V8 coverage of the modules it ran, when collected
+    pub coverage: Vec<ScriptCoverage>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    // test files that failed to load or run
+    pub errored: usize,
+}
+
+impl TestSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && self.errored == 0
+    }
+
+    fn add(&mut self, report: &TestFileReport) {
+        if report.error.is_some() {
+            self.errored += 1;
+        }
+        for result in &report.results {
+            match result.status {
+                TestStatus::Ok => self.passed += 1,
+                TestStatus::Failed => self.failed += 1,
+                TestStatus::Ignored => self.ignored += 1,
+            }
+        }
+    }
+}
+
+// Parses a `SPECIFIER=PATH` mock, as passed to `edge-runtime test --mock`
+pub fn parse_module_mock(mock: &str) -> Result<(String, PathBuf), Error> {
+    match mock.rsplit_once('=') {
+        Some((specifier, path)) if !specifier.is_empty() && !path.is_empty() => {
+            Ok((specifier.to_string(), PathBuf::from(path)))
+        }
+        _ => bail!("invalid mock {:?}, expected SPECIFIER=PATH", mock),
+    }
+}
+
+fn is_test_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| TEST_FILE_SUFFIXES.iter().any(|s| name.ends_with(s)))
+        .unwrap_or(false)
+}
+
+// Finds test files in the service directory (recursively), in a stable order
+pub fn discover_test_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with('.') || name == "node_modules" {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_test_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Entrypoint that registers the tests declared (with `Deno.test`) by the test
+// file, and runs them when it receives a request, responding with the results.
+fn test_module_code(specifier: &str, filter: Option<&str>) -> String {
+    format!(
+        r#"
+const tests = [];
+Deno.test = (t, fnOrOpts, maybeFn) => {{
+  let test;
+  if (typeof t === "function") {{
+    test = {{ name: t.name, fn: t }};
+  }} else if (typeof t === "string") {{
+    test = typeof fnOrOpts === "function"
+      ? {{ name: t, fn: fnOrOpts }}
+      : {{ ...fnOrOpts, name: t, fn: maybeFn }};
+  }} else {{
+    test = typeof fnOrOpts === "function" ? {{ ...t, fn: fnOrOpts }} : {{ ...t }};
+  }}
+  if (typeof test.fn !== "function") {{
+    throw new TypeError(`test "${{test.name}}" is missing a function`);
+  }}
+  tests.push(test);
+}};
+const registerWith = (extra) => (...args) => {{
+  Deno.test(...args);
+  Object.assign(tests[tests.length - 1], extra);
+}};
+Deno.test.ignore = registerWith({{ ignore: true }});
+Deno.test.only = registerWith({{ only: true }});
+
+const filter = {filter};
+const errorString = (e) => e?.stack ?? String(e);
+
+let loadError = null;
+try {{
+  await import({specifier});
+}} catch (e) {{
+  loadError = errorString(e);
+}}
+
+const context = (name) => ({{
+  name,
+  step: async (stepName, fn) => {{
+    if (typeof stepName !== "string") {{
+      fn = stepName.fn;
+      stepName = stepName.name;
+    }}
+    await fn(context(stepName));
+    return true;
+  }},
+}});
+
+Deno.serve(async () => {{
+  const hasOnly = tests.some((t) => t.only);
+  const results = [];
+  for (const t of tests) {{
+    if (filter !== null && !t.name.includes(filter)) {{
+      continue;
+    }}
+    if (t.ignore || (hasOnly && !t.only)) {{
+      results.push({{ name: t.name, status: "ignored", durationMs: 0 }});
+      continue;
+    }}
+    const start = performance.now();
+    try {{
+      await t.fn(context(t.name));
+      results.push({{ name: t.name, status: "ok", durationMs: Math.round(performance.now() - start) }});
+    }} catch (e) {{
+      results.push({{
+        name: t.name,
+        status: "failed",
+        durationMs: Math.round(performance.now() - start),
+        error: errorString(e),
+      }});
+    }}
+  }}
+  const coverage = EdgeRuntime.testing?.takeCoverage
+    ? await EdgeRuntime.testing.takeCoverage()
+    : [];
+  return Response.json({{ loadError, results, coverage }});
+}});
+"#,
+        filter = serde_json::to_string(&filter).unwrap(),
+        specifier = serde_json::to_string(specifier).unwrap(),
+    )
+}
+
+async fn run_test_file_output(
+    opts: &TestRunOpts,
+    test_file: &Path,
+) -> Result<TestFileOutput, Error> {
+    let relative = test_file.strip_prefix(&opts.service_path)?;
+    let specifier = format!(
+        "./{}",
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    );
+
+    // tests run with the same extensions, permissions and limits as user workers
+    let worker_req_tx = create_worker(WorkerContextInitOpts {
+        service_path: opts.service_path.clone(),
+        no_module_cache: opts.no_module_cache,
+        import_map_path: opts.import_map_path.clone(),
+        env_vars: std::env::vars().collect(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: Some(test_module_code(&specifier, opts.filter.as_deref()).into()),
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            service_path: Some(opts.service_path.to_string_lossy().to_string()),
+            test_clock: true,
+            coverage: opts.coverage_dir.is_some(),
+            module_mocks: opts.mocks.clone(),
+            ..UserWorkerRuntimeOpts::default()
+        }),
+    })
+    .await?;
+
+    let req = Request::builder()
+        .uri("http://localhost/")
+        .body(Body::empty())?;
+    let res = send_user_worker_request(worker_req_tx, req).await?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    if !status.is_success() {
+        bail!(
+            "test worker responded with {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        );
+    }
+
+    serde_json::from_slice(&body).map_err(|err| anyhow!("invalid test results: {}", err))
+}
+
+pub async fn run_test_file(opts: &TestRunOpts, test_file: &Path) -> TestFileReport {
+    match run_test_file_output(opts, test_file).await {
+        Ok(output) => TestFileReport {
+            path: test_file.to_path_buf(),
+            results: output.results,
+            error: output.load_error,
+            coverage: output.coverage,
+        },
+        Err(err) => TestFileReport {
+            path: test_file.to_path_buf(),
+            results: vec![],
+            error: Some(err.to_string()),
+            coverage: vec![],
+        },
+    }
+}
+
+fn print_report(report: &TestFileReport) {
+    println!(
+        "running {} tests from {}",
+        report.results.len(),
+        report.path.display()
+    );
+    if let Some(err) = &report.error {
+        println!("error: {}", err);
+    }
+    for result in &report.results {
+        match result.status {
+            TestStatus::Ok => println!("{} ... ok ({}ms)", result.name, result.duration_ms),
+            TestStatus::Ignored => println!("{} ... ignored", result.name),
+            TestStatus::Failed => {
+                println!("{} ... FAILED ({}ms)", result.name, result.duration_ms);
+                if let Some(err) = &result.error {
+                    println!("{}", err);
+                }
+            }
+        }
+    }
+}
+
+// Adds the coverage of the service's modules, leaving out the test files and
+// the entrypoint running them
+fn add_coverage(
+    coverage: &mut CoverageReport,
+    service_path: &Path,
+    report: &TestFileReport,
+) -> Result<(), Error> {
+    let service_path = std::fs::canonicalize(service_path)?;
+    let entrypoint = service_path.join("index.ts");
+    for script in &report.coverage {
+        coverage.add(script, |path| {
+            path.starts_with(&service_path) && path != entrypoint && !is_test_file(path)
+        })?;
+    }
+    Ok(())
+}
+
+// Runs every test file of the service, printing the results as they come
+pub async fn run_tests(opts: TestRunOpts) -> Result<TestSummary, Error> {
+    let files = discover_test_files(&opts.service_path)?;
+    if files.is_empty() {
+        bail!("no test files found in {}", opts.service_path.display());
+    }
+
+    let started = Instant::now();
+    let mut summary = TestSummary::default();
+    let mut coverage = CoverageReport::default();
+    for file in files {
+        let report = run_test_file(&opts, &file).await;
+        print_report(&report);
+        summary.add(&report);
+        if opts.coverage_dir.is_some() {
+            add_coverage(&mut coverage, &opts.service_path, &report)?;
+        }
+    }
+    if let Some(dir) = &opts.coverage_dir {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("lcov.info");
+        std::fs::write(&path, coverage.to_lcov())?;
+        println!("coverage written to {}", path.display());
+    }
+
+    println!(
+        "\n{} | {} passed | {} failed | {} ignored{} ({}ms)",
+        if summary.is_success() { "ok" } else { "FAILED" },
+        summary.passed,
+        summary.failed,
+        summary.ignored,
+        if summary.errored > 0 {
+            format!(" | {} files errored", summary.errored)
+        } else {
+            String::new()
+        },
+        started.elapsed().as_millis()
+    );
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{discover_test_files, parse_module_mock};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_discover_test_files() {
+        let files = discover_test_files(&PathBuf::from("./test_cases/test_runner")).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("./test_cases/test_runner/failing_test.ts"),
+                PathBuf::from("./test_cases/test_runner/lib/math_test.ts"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_module_mock() {
+        assert_eq!(
+            parse_module_mock("https://esm.sh/pkg?target=deno=./mocks/pkg.ts").unwrap(),
+            (
+                "https://esm.sh/pkg?target=deno".to_string(),
+                PathBuf::from("./mocks/pkg.ts")
+            )
+        );
+        assert!(parse_module_mock("https://esm.sh/pkg").is_err());
+        assert!(parse_module_mock("https://esm.sh/pkg=").is_err());
+    }
+}
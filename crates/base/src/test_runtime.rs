@@ -0,0 +1,192 @@
+use crate::server::{Server, ServerFlags, WorkerEntrypoints};
+use anyhow::Error;
+use event_worker::events::WorkerEventWithMetadata;
+use log::error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+static TEST_CLOCK: AtomicBool = AtomicBool::new(false);
+
+// User workers of the process run on the test clock (`EdgeRuntime.testing.clock`)
+pub fn enable_test_clock() {
+    TEST_CLOCK.store(true, Ordering::Relaxed);
+}
+
+pub fn test_clock_enabled() -> bool {
+    TEST_CLOCK.load(Ordering::Relaxed)
+}
+
+// Builds a `TestRuntime` running the full stack (main, events and user workers)
+pub struct TestRuntimeBuilder {
+    main_service_path: String,
+    events_service_path: Option<String>,
+    import_map_path: Option<String>,
+    no_module_cache: bool,
+    entrypoints: WorkerEntrypoints,
+    flags: ServerFlags,
+}
+
+impl TestRuntimeBuilder {
+    pub fn new(main_service_path: &str) -> Self {
+        Self {
+            main_service_path: main_service_path.to_string(),
+            events_service_path: None,
+            import_map_path: None,
+            // keep remote modules in memory, so tests don't touch the module cache
+            no_module_cache: true,
+            entrypoints: WorkerEntrypoints {
+                main: None,
+                events: None,
+            },
+            flags: ServerFlags::default(),
+        }
+    }
+
+    pub fn events_worker(mut self, events_service_path: &str) -> Self {
+        self.events_service_path = Some(events_service_path.to_string());
+        self
+    }
+
+    pub fn import_map(mut self, import_map_path: &str) -> Self {
+        self.import_map_path = Some(import_map_path.to_string());
+        self
+    }
+
+    pub fn module_cache(mut self, enabled: bool) -> Self {
+        self.no_module_cache = !enabled;
+        self
+    }
+
+    pub fn entrypoints(mut self, entrypoints: WorkerEntrypoints) -> Self {
+        self.entrypoints = entrypoints;
+        self
+    }
+
+    // user workers get `EdgeRuntime.testing.clock`
+    pub fn test_clock(mut self) -> Self {
+        self.flags.test_clock = true;
+        self
+    }
+
+    pub fn flags(mut self, flags: ServerFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    // Boots the runtime on an ephemeral port on localhost
+    pub async fn build(self) -> Result<TestRuntime, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+        let flags = ServerFlags {
+            event_listener: Some(events_tx),
+            ..self.flags
+        };
+
+        let mut server = Server::new(
+            "127.0.0.1",
+            addr.port(),
+            self.main_service_path,
+            self.events_service_path,
+            self.import_map_path,
+            self.no_module_cache,
+            None,
+            self.entrypoints,
+            flags,
+        )
+        .await?;
+
+        let handle = tokio::task::spawn(async move {
+            if let Err(err) = server.listen_on(listener).await {
+                error!("test runtime stopped: {}", err);
+            }
+        });
+
+        Ok(TestRuntime {
+            addr,
+            client: reqwest::Client::new(),
+            events_rx,
+            handle,
+        })
+    }
+}
+
+// A running edge runtime for integration tests. Stops serving when dropped.
+pub struct TestRuntime {
+    addr: SocketAddr,
+    client: reqwest::Client,
+    events_rx: mpsc::UnboundedReceiver<WorkerEventWithMetadata>,
+    handle: JoinHandle<()>,
+}
+
+impl TestRuntime {
+    pub fn builder(main_service_path: &str) -> TestRuntimeBuilder {
+        TestRuntimeBuilder::new(main_service_path)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.addr, path.trim_start_matches('/'))
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub async fn get(&self, path: &str) -> Result<reqwest::Response, reqwest::Error> {
+        self.client.get(self.url(path)).send().await
+    }
+
+    pub async fn post(
+        &self,
+        path: &str,
+        body: impl Into<reqwest::Body>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.client.post(self.url(path)).body(body).send().await
+    }
+
+    // Events emitted so far, that haven't been taken yet
+    pub fn take_events(&mut self) -> Vec<WorkerEventWithMetadata> {
+        let mut events = vec![];
+        while let Ok(event) = self.events_rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    // Waits for an event matching the predicate, skipping other events
+    pub async fn wait_for_event<F>(
+        &mut self,
+        timeout: Duration,
+        mut predicate: F,
+    ) -> Option<WorkerEventWithMetadata>
+    where
+        F: FnMut(&WorkerEventWithMetadata) -> bool,
+    {
+        tokio::time::timeout(timeout, async {
+            while let Some(event) = self.events_rx.recv().await {
+                if predicate(&event) {
+                    return Some(event);
+                }
+            }
+            None
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+impl Drop for TestRuntime {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
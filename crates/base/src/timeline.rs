@@ -0,0 +1,194 @@
+use deno_core::serde_json::{self, json, Value};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use uuid::Uuid;
+
+static TIMELINES: OnceLock<Timelines> = OnceLock::new();
+
+// Records spans of the workers (boot, module fetches, requests) of the process,
+// keeping the last `capacity` of them
+pub fn enable_timelines(capacity: usize) {
+    let _ = TIMELINES.set(Timelines::new(capacity));
+}
+
+pub fn timelines() -> Option<&'static Timelines> {
+    TIMELINES.get()
+}
+
+// Track (shown as a thread in trace viewers) the spans of a user worker are
+// recorded on
+pub fn worker_track(service_path: &str, key: &Uuid) -> String {
+    format!("{} ({})", service_path, key)
+}
+
+// Records a span from its creation until it's dropped, eg: with the response
+// body it's moved into
+pub struct SpanGuard {
+    track: String,
+    cat: &'static str,
+    name: String,
+    start: Instant,
+}
+
+impl SpanGuard {
+    pub fn new(track: String, cat: &'static str, name: String) -> Self {
+        Self {
+            track,
+            cat,
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some(timelines) = timelines() {
+            timelines.span(&self.track, self.cat, &self.name, self.start, json!({}));
+        }
+    }
+}
+
+// A complete ("X") event of the Chrome trace event format
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    // microseconds since the timelines were enabled
+    ts: u64,
+    dur: u64,
+    pid: u32,
+    tid: u64,
+    args: Value,
+}
+
+#[derive(Default)]
+struct TimelinesInner {
+    events: VecDeque<TraceEvent>,
+    // tracks by name, numbered in the order they were first seen
+    tracks: HashMap<String, u64>,
+}
+
+pub struct Timelines {
+    started: Instant,
+    capacity: usize,
+    inner: Mutex<TimelinesInner>,
+}
+
+impl Timelines {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    // Records a span from `start` to now
+    pub fn span(&self, track: &str, cat: &'static str, name: &str, start: Instant, args: Value) {
+        self.record(track, cat, name, start, Instant::now(), args);
+    }
+
+    pub fn record(
+        &self,
+        track: &str,
+        cat: &'static str,
+        name: &str,
+        start: Instant,
+        end: Instant,
+        args: Value,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let micros = |at: Instant| at.saturating_duration_since(self.started).as_micros() as u64;
+        let mut inner = self.inner.lock().unwrap();
+        let next_tid = inner.tracks.len() as u64 + 1;
+        let tid = *inner.tracks.entry(track.to_string()).or_insert(next_tid);
+        if inner.events.len() == self.capacity {
+            inner.events.pop_front();
+        }
+        inner.events.push_back(TraceEvent {
+            name: name.to_string(),
+            cat,
+            ph: "X",
+            ts: micros(start),
+            dur: micros(end).saturating_sub(micros(start)),
+            pid: std::process::id(),
+            tid,
+            args,
+        });
+    }
+
+    // JSON loadable in chrome://tracing or Perfetto
+    pub fn to_chrome_trace(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let pid = std::process::id();
+        // only the tracks with spans left in the buffer
+        let mut tracks: Vec<_> = inner
+            .tracks
+            .iter()
+            .filter(|(_, tid)| inner.events.iter().any(|event| event.tid == **tid))
+            .collect();
+        tracks.sort_by_key(|(_, tid)| **tid);
+        let mut events: Vec<Value> = tracks
+            .into_iter()
+            .map(|(name, tid)| {
+                json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": pid,
+                    "tid": tid,
+                    "args": { "name": name },
+                })
+            })
+            .collect();
+        events.extend(
+            inner
+                .events
+                .iter()
+                .map(|event| serde_json::to_value(event).unwrap()),
+        );
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Timelines;
+    use deno_core::serde_json::{self, json, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn test_chrome_trace() {
+        let timelines = Timelines::new(2);
+        let start = timelines.started + Duration::from_millis(1);
+        let end = start + Duration::from_micros(1500);
+        timelines.record("c", "worker", "boot", start, end, json!({}));
+        timelines.record("a", "worker", "boot", start, end, json!({}));
+        timelines.record("b", "worker", "boot", start, end, json!({}));
+        timelines.record("a", "request", "GET /", end, end, json!({ "status": 200 }));
+
+        let trace: Value = serde_json::from_str(&timelines.to_chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        // names of the tracks still having spans, then the last 2 spans
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["tid"], 2);
+        assert_eq!(events[0]["args"]["name"], "a");
+        assert_eq!(events[1]["tid"], 3);
+        assert_eq!(events[1]["args"]["name"], "b");
+        assert_eq!(events[2]["tid"], 3);
+        assert_eq!(events[2]["ts"], 1000);
+        assert_eq!(events[2]["dur"], 1500);
+        assert_eq!(events[3]["name"], "GET /");
+        assert_eq!(events[3]["cat"], "request");
+        assert_eq!(events[3]["tid"], 2);
+        assert_eq!(events[3]["ts"], 2500);
+        assert_eq!(events[3]["dur"], 0);
+        assert_eq!(events[3]["args"]["status"], 200);
+    }
+}
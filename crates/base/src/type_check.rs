@@ -0,0 +1,349 @@
+use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use crate::service_config::ServiceConfig;
+use crate::utils::graph_util::{create_graph, graph_valid, GraphValidOptions};
+use crate::vendor::{local_path, module_source, service_roots};
+use anyhow::{anyhow, bail, Error};
+use deno_ast::MediaType;
+use deno_core::serde_json;
+use deno_core::ModuleSpecifier;
+use eszip::deno_graph::{GraphKind, Module, ModuleGraph};
+use hyper::{Body, Request};
+use sb_worker_context::essentials::{
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+pub const DEFAULT_TYPESCRIPT_VERSION: &str = "5.2.2";
+
+const CHECK_JS: &str = include_str!("type_check/check.js");
+const EDGE_RUNTIME_TYPES: &str = include_str!("type_check/edge_runtime.d.ts");
+const GLOBALS_FILE_NAME: &str = "/$edge-runtime/globals.d.ts";
+const LIBS: [&str; 3] = ["esnext", "dom", "dom.iterable"];
+
+#[derive(Debug, Clone, Default)]
+pub struct TypeCheckOpts {
+    pub service_path: PathBuf,
+    // version of the TypeScript compiler, loaded from esm.sh (defaults to
+    // `DEFAULT_TYPESCRIPT_VERSION`)
+    pub typescript_version: Option<String>,
+    // also report diagnostics of remote modules
+    pub all: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticCategory {
+    Error,
+    Warning,
+    Suggestion,
+    Message,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeDiagnostic {
+    // module the diagnostic is in, if any
+    pub specifier: Option<String>,
+    // 1-based
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub code: u32,
+    pub category: DiagnosticCategory,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeCheckReport {
+    pub diagnostics: Vec<TypeDiagnostic>,
+}
+
+impl TypeCheckReport {
+    pub fn error_count(&self) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.category == DiagnosticCategory::Error)
+            .count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error_count() == 0
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // Diagnostics in the format of `deno check`
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        for d in &self.diagnostics {
+            let category = format!("{:?}", d.category).to_uppercase();
+            let _ = writeln!(out, "TS{} [{}]: {}", d.code, category, d.message);
+            if let Some(specifier) = &d.specifier {
+                let _ = writeln!(
+                    out,
+                    "    at {}:{}:{}",
+                    specifier,
+                    d.line.unwrap_or(1),
+                    d.column.unwrap_or(1)
+                );
+            }
+            out.push('\n');
+        }
+        match self.error_count() {
+            0 => {}
+            1 => out.push_str("Found 1 error.\n"),
+            n => {
+                let _ = writeln!(out, "Found {} errors.", n);
+            }
+        }
+        out
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CheckModule<'a> {
+    specifier: &'a str,
+    file_name: String,
+    source: &'a str,
+    // whether diagnostics of the module are reported
+    report: bool,
+    // import specifier -> file name
+    dependencies: BTreeMap<&'a str, String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CheckInput<'a> {
+    roots: Vec<String>,
+    modules: Vec<CheckModule<'a>>,
+    libs: Vec<String>,
+    lib_url: String,
+    globals: &'a str,
+    globals_file_name: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct CheckOutput {
+    diagnostics: Option<Vec<TypeDiagnostic>>,
+    error: Option<String>,
+}
+
+// Name of a module in the compiler's virtual file system. TypeScript infers how
+// to check a file from its extension, so one matching the media type is added
+// when the specifier has none.
+fn file_name(specifier: &ModuleSpecifier, media_type: MediaType) -> Option<String> {
+    if specifier.scheme() == "file" {
+        let mut path = specifier.to_file_path().ok()?.to_string_lossy().to_string();
+        let ext = media_type.as_ts_extension();
+        if !path.ends_with(ext) {
+            path.push_str(ext);
+        }
+        return Some(path);
+    }
+    local_path(specifier, media_type).map(|path| format!("/$remote/{}", path))
+}
+
+// `compilerOptions.lib` of the service's deno.json, if any
+fn service_libs(opts: &TypeCheckOpts) -> Vec<String> {
+    let service_dir = if opts.service_path.is_file() {
+        opts.service_path.parent().unwrap_or(&opts.service_path)
+    } else {
+        &opts.service_path
+    };
+    ServiceConfig::discover(service_dir)
+        .ok()
+        .flatten()
+        .and_then(|config| config.libs())
+        .unwrap_or_else(|| LIBS.map(String::from).to_vec())
+}
+
+fn check_input<'a>(
+    graph: &'a ModuleGraph,
+    roots: &[ModuleSpecifier],
+    opts: &TypeCheckOpts,
+    typescript_version: &str,
+) -> CheckInput<'a> {
+    let mut file_names = HashMap::new();
+    for module in graph.modules() {
+        if let Some((_, media_type)) = module_source(module) {
+            if let Some(name) = file_name(module.specifier(), media_type) {
+                file_names.insert(module.specifier().clone(), name);
+            }
+        }
+    }
+    // the file name a specifier resolves to, following redirects and the types
+    // of modules served with `X-TypeScript-Types`
+    let resolve = |specifier: &ModuleSpecifier| {
+        let specifier = graph.resolve(specifier);
+        let types = match graph.get(specifier) {
+            Some(Module::Esm(module)) => module
+                .maybe_types_dependency
+                .as_ref()
+                .and_then(|types| types.dependency.maybe_specifier())
+                .map(|types| graph.resolve(types)),
+            _ => None,
+        };
+        file_names.get(types.unwrap_or(specifier)).cloned()
+    };
+
+    let mut modules = vec![];
+    for module in graph.modules() {
+        let Some((source, _)) = module_source(module) else {
+            continue;
+        };
+        let Some(file_name) = file_names.get(module.specifier()).cloned() else {
+            continue;
+        };
+        let mut dependencies = BTreeMap::new();
+        if let Module::Esm(module) = module {
+            for (text, dependency) in &module.dependencies {
+                let resolved = dependency
+                    .maybe_type
+                    .maybe_specifier()
+                    .or_else(|| dependency.maybe_code.maybe_specifier());
+                if let Some(name) = resolved.and_then(&resolve) {
+                    dependencies.insert(text.as_str(), name);
+                }
+            }
+        }
+        modules.push(CheckModule {
+            specifier: module.specifier().as_str(),
+            file_name,
+            source,
+            report: opts.all || module.specifier().scheme() == "file",
+            dependencies,
+        });
+    }
+
+    CheckInput {
+        roots: roots.iter().filter_map(&resolve).collect(),
+        modules,
+        libs: service_libs(opts),
+        lib_url: format!(
+            "https://cdn.jsdelivr.net/npm/typescript@{}/lib/",
+            typescript_version
+        ),
+        globals: EDGE_RUNTIME_TYPES,
+        globals_file_name: GLOBALS_FILE_NAME,
+    }
+}
+
+// Type checks a service (its index.ts or route modules, and everything they
+// import) with the TypeScript compiler, run in a worker
+pub async fn type_check(opts: TypeCheckOpts) -> Result<TypeCheckReport, Error> {
+    let roots = service_roots(&opts.service_path)?;
+    let graph = create_graph(roots.clone(), GraphKind::All).await?;
+    graph_valid(
+        &graph,
+        &roots,
+        GraphValidOptions {
+            check_js: false,
+            follow_type_only: true,
+            is_vendoring: false,
+        },
+    )?;
+
+    let typescript_version = opts
+        .typescript_version
+        .as_deref()
+        .unwrap_or(DEFAULT_TYPESCRIPT_VERSION);
+    let input = check_input(&graph, &roots, &opts, typescript_version);
+    let module_code = format!(
+        "const INPUT = {};\n{}",
+        serde_json::to_string(&input)?,
+        CHECK_JS.replace(
+            "TYPESCRIPT_URL",
+            &format!("https://esm.sh/typescript@{}", typescript_version)
+        )
+    );
+
+    let service_path = if opts.service_path.is_file() {
+        opts.service_path
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_default()
+    } else {
+        opts.service_path.clone()
+    };
+    // compiling is cpu heavy and runs in a single event loop turn
+    let worker_req_tx = create_worker(WorkerContextInitOpts {
+        service_path: service_path.clone(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: Some(module_code.into()),
+        conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+            service_path: Some(service_path.to_string_lossy().to_string()),
+            memory_limit_mb: 2048,
+            worker_timeout_ms: 10 * 60 * 1000,
+            cpu_time_threshold_ms: 10 * 60 * 1000,
+            hang_threshold_ms: 10 * 60 * 1000,
+            ..UserWorkerRuntimeOpts::default()
+        }),
+    })
+    .await?;
+
+    let req = Request::builder()
+        .uri("http://localhost/")
+        .body(Body::empty())?;
+    let res = send_user_worker_request(worker_req_tx, req).await?;
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let output: CheckOutput = serde_json::from_slice(&body)
+        .map_err(|err| anyhow!("invalid type check output: {}", err))?;
+    if let Some(err) = output.error {
+        bail!("type checking failed: {}", err);
+    }
+
+    Ok(TypeCheckReport {
+        diagnostics: output.diagnostics.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_name, DiagnosticCategory, TypeCheckReport, TypeDiagnostic};
+    use deno_ast::MediaType;
+    use deno_core::ModuleSpecifier;
+
+    #[test]
+    fn test_file_name() {
+        let local = ModuleSpecifier::parse("file:///srv/hello/index.ts").unwrap();
+        assert_eq!(
+            file_name(&local, MediaType::TypeScript).unwrap(),
+            "/srv/hello/index.ts"
+        );
+        let remote = ModuleSpecifier::parse("https://esm.sh/preact@10").unwrap();
+        assert_eq!(
+            file_name(&remote, MediaType::Dts).unwrap(),
+            "/$remote/esm.sh/preact@10.d.ts"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let report = TypeCheckReport {
+            diagnostics: vec![TypeDiagnostic {
+                specifier: Some("file:///srv/hello/index.ts".to_string()),
+                line: Some(3),
+                column: Some(7),
+                code: 2322,
+                category: DiagnosticCategory::Error,
+                message: "Type 'string' is not assignable to type 'number'.".to_string(),
+            }],
+        };
+        assert!(!report.is_success());
+        assert_eq!(
+            report.to_pretty_string(),
+            "TS2322 [ERROR]: Type 'string' is not assignable to type 'number'.\n    at file:///srv/hello/index.ts:3:7\n\nFound 1 error.\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Type checks the modules in `INPUT` (prepended by the runtime) with the
+// TypeScript compiler, responding with the diagnostics.
+import ts from 'TYPESCRIPT_URL';
+
+const LIB_REFERENCE = /\/\/\/\s*<reference\s+lib="([^"]+)"\s*\/>/g;
+
+async function fetchLibs(libs) {
+	const files = new Map();
+	const pending = libs.map((lib) => `lib.${lib}.d.ts`);
+	while (pending.length > 0) {
+		const name = pending.pop();
+		if (files.has(name)) {
+			continue;
+		}
+		const res = await fetch(`${INPUT.libUrl}${name}`);
+		if (!res.ok) {
+			throw new Error(`failed to fetch ${name}: ${res.status}`);
+		}
+		const source = await res.text();
+		files.set(name, source);
+		for (const [, lib] of source.matchAll(LIB_REFERENCE)) {
+			pending.push(`lib.${lib.toLowerCase()}.d.ts`);
+		}
+	}
+	return files;
+}
+
+function category(diagnostic) {
+	switch (diagnostic.category) {
+		case ts.DiagnosticCategory.Error:
+			return 'error';
+		case ts.DiagnosticCategory.Warning:
+			return 'warning';
+		case ts.DiagnosticCategory.Suggestion:
+			return 'suggestion';
+		default:
+			return 'message';
+	}
+}
+
+async function check() {
+	const libFiles = await fetchLibs(INPUT.libs);
+	const modules = new Map(INPUT.modules.map((m) => [m.fileName, m]));
+	const sourceFiles = new Map();
+
+	const options = {
+		strict: true,
+		noEmit: true,
+		allowJs: true,
+		checkJs: false,
+		skipLibCheck: true,
+		allowImportingTsExtensions: true,
+		resolveJsonModule: true,
+		isolatedModules: true,
+		jsx: ts.JsxEmit.React,
+		target: ts.ScriptTarget.ESNext,
+		module: ts.ModuleKind.ESNext,
+		moduleResolution: ts.ModuleResolutionKind.Bundler,
+		lib: INPUT.libs.map((lib) => `lib.${lib}.d.ts`),
+	};
+
+	const readFile = (fileName) => {
+		if (modules.has(fileName)) {
+			return modules.get(fileName).source;
+		}
+		if (fileName === INPUT.globalsFileName) {
+			return INPUT.globals;
+		}
+		return libFiles.get(fileName.replace(/^\/\$lib\//, ''));
+	};
+
+	const host = {
+		getSourceFile(fileName, languageVersion) {
+			if (!sourceFiles.has(fileName)) {
+				const source = readFile(fileName);
+				if (source === undefined) {
+					return undefined;
+				}
+				sourceFiles.set(fileName, ts.createSourceFile(fileName, source, languageVersion));
+			}
+			return sourceFiles.get(fileName);
+		},
+		getDefaultLibFileName: () => '/$lib/lib.d.ts',
+		getDefaultLibLocation: () => '/$lib',
+		writeFile() {},
+		getCurrentDirectory: () => '/',
+		getCanonicalFileName: (fileName) => fileName,
+		useCaseSensitiveFileNames: () => true,
+		getNewLine: () => '\n',
+		fileExists: (fileName) => readFile(fileName) !== undefined,
+		readFile,
+		resolveModuleNames(moduleNames, containingFile) {
+			const dependencies = modules.get(containingFile)?.dependencies ?? {};
+			return moduleNames.map((name) => {
+				const resolvedFileName = dependencies[name];
+				if (resolvedFileName === undefined) {
+					return undefined;
+				}
+				return {
+					resolvedFileName,
+					extension: ts.extensionFromPath(resolvedFileName),
+					isExternalLibraryImport: false,
+				};
+			});
+		},
+	};
+
+	const program = ts.createProgram([...INPUT.roots, INPUT.globalsFileName], options, host);
+	const diagnostics = [
+		...program.getConfigFileParsingDiagnostics(),
+		...program.getOptionsDiagnostics(),
+		...program.getGlobalDiagnostics(),
+		...program.getSyntacticDiagnostics(),
+		...program.getSemanticDiagnostics(),
+	];
+
+	return diagnostics
+		.filter((d) => !d.file || modules.get(d.file.fileName)?.report)
+		.map((d) => {
+			const position = d.file && d.start !== undefined
+				? d.file.getLineAndCharacterOfPosition(d.start)
+				: undefined;
+			return {
+				specifier: d.file ? modules.get(d.file.fileName).specifier : null,
+				line: position ? position.line + 1 : null,
+				column: position ? position.character + 1 : null,
+				code: d.code,
+				category: category(d),
+				message: ts.flattenDiagnosticMessageText(d.messageText, '\n'),
+			};
+		});
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({ diagnostics: await check() });
+	} catch (e) {
+		return Response.json({ error: e?.stack ?? String(e) });
+	}
+});
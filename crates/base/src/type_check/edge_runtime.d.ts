@@ -0,0 +1,34 @@
+// Globals of the edge runtime. Only the common parts of `Deno` are typed, the
+// rest is `any`.
+
+interface DenoServeOptions {
+	port?: number;
+	hostname?: string;
+	signal?: AbortSignal;
+	onListen?: (params: { hostname: string; port: number }) => void;
+	onError?: (error: unknown) => Response | Promise<Response>;
+}
+
+type DenoServeHandler = (request: Request, info?: any) => Response | Promise<Response>;
+
+interface DenoEnv {
+	get(key: string): string | undefined;
+	set(key: string, value: string): void;
+	delete(key: string): void;
+	has(key: string): boolean;
+	toObject(): Record<string, string>;
+}
+
+interface DenoNamespace {
+	serve(handler: DenoServeHandler): any;
+	serve(options: DenoServeOptions, handler: DenoServeHandler): any;
+	serve(options: DenoServeOptions & { handler: DenoServeHandler }): any;
+	env: DenoEnv;
+	readTextFile(path: string | URL): Promise<string>;
+	readFile(path: string | URL): Promise<Uint8Array>;
+	cwd(): string;
+	[key: string]: any;
+}
+
+declare const Deno: DenoNamespace;
+declare const EdgeRuntime: any;
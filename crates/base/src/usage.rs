@@ -0,0 +1,265 @@
+use crate::node::{node_identity, NodeIdentity};
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use log::error;
+use sb_worker_context::usage::{ServiceUsage, UsageCollector};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+
+pub const DEFAULT_USAGE_FLUSH_INTERVAL_SECS: u64 = 60;
+
+// How often a running worker adds the CPU time and memory it used so far, so
+// long-lived workers show up in every reporting window and not only once they exit
+pub const WORKER_USAGE_INTERVAL: Duration = Duration::from_secs(10);
+
+// Where usage records are flushed to
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsageSink {
+    // appends newline delimited JSON records
+    File(PathBuf),
+    // POSTs a JSON array of records
+    Http(String),
+}
+
+impl FromStr for UsageSink {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Ok(UsageSink::Http(value.to_string()));
+        }
+
+        let path = value.strip_prefix("file://").unwrap_or(value);
+        if path.is_empty() {
+            bail!("invalid usage sink: {}", value);
+        }
+        Ok(UsageSink::File(PathBuf::from(path)))
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    pub service_path: String,
+    // unix timestamps (ms) of the reporting window
+    pub window_start: u64,
+    pub window_end: u64,
+    #[serde(flatten)]
+    pub usage: ServiceUsage,
+    #[serde(flatten)]
+    pub node: NodeIdentity,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn write_records(sink: &UsageSink, records: &[UsageRecord]) -> Result<(), Error> {
+    match sink {
+        UsageSink::File(path) => {
+            let mut lines = String::new();
+            for record in records {
+                lines.push_str(&serde_json::to_string(record)?);
+                lines.push('\n');
+            }
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(lines.as_bytes()).await?;
+        }
+        UsageSink::Http(url) => {
+            reqwest::Client::new()
+                .post(url)
+                .json(records)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn flush(collector: &UsageCollector, sink: &UsageSink, window_start: u64) -> u64 {
+    let window_end = now_ms();
+    let pending = collector.take();
+    if pending.is_empty() {
+        return window_end;
+    }
+
+    let records: Vec<UsageRecord> = pending
+        .iter()
+        .map(|(service_path, usage)| UsageRecord {
+            service_path: service_path.clone(),
+            window_start,
+            window_end,
+            usage: usage.clone(),
+            node: node_identity(),
+        })
+        .collect();
+
+    if let Err(err) = write_records(sink, &records).await {
+        // keep the usage around so it's reported with the next flush
+        error!("failed to flush usage records: {}", err);
+        collector.restore(pending);
+        return window_start;
+    }
+
+    window_end
+}
+
+// Usage of a single worker, recorded as deltas since the last `record` call
+pub struct WorkerUsageMeter {
+    usage: UsageCollector,
+    service_path: String,
+    memory_limit_mb: u64,
+    cpu_time_ns: i64,
+    recorded_at: Instant,
+    module_downloads: Option<Arc<AtomicU64>>,
+    recorded_downloads: u64,
+}
+
+impl WorkerUsageMeter {
+    // `cpu_time_ns` is the thread time user code starts being billed from,
+    // memory is billed from `started_at`
+    pub fn new(
+        usage: UsageCollector,
+        service_path: String,
+        memory_limit_mb: u64,
+        cpu_time_ns: i64,
+        started_at: Instant,
+        module_downloads: Option<Arc<AtomicU64>>,
+    ) -> Self {
+        Self {
+            usage,
+            service_path,
+            memory_limit_mb,
+            cpu_time_ns,
+            recorded_at: started_at,
+            module_downloads,
+            recorded_downloads: 0,
+        }
+    }
+
+    pub fn record(&mut self, cpu_time_ns: i64, now: Instant) {
+        // whole milliseconds only, the remainder is carried to the next record
+        let cpu_time_ms = (cpu_time_ns - self.cpu_time_ns).max(0) / 1_000_000;
+        self.cpu_time_ns += cpu_time_ms * 1_000_000;
+        let elapsed_secs = now
+            .saturating_duration_since(self.recorded_at)
+            .as_secs_f64();
+        self.recorded_at = now;
+        let downloads = self
+            .module_downloads
+            .as_ref()
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0);
+        let cache_misses = downloads.saturating_sub(self.recorded_downloads);
+        self.recorded_downloads = downloads;
+
+        let memory_limit_mb = self.memory_limit_mb;
+        self.usage.record(&self.service_path, |u| {
+            u.cpu_time_ms += cpu_time_ms as u64;
+            u.memory_gb_seconds += memory_limit_mb as f64 / 1024.0 * elapsed_secs;
+            u.module_cache_misses += cache_misses;
+        });
+    }
+}
+
+// Periodically flushes aggregated usage to the sink
+pub fn start_usage_reporter(collector: UsageCollector, sink: UsageSink, interval: Duration) {
+    tokio::task::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // first tick completes immediately
+        ticker.tick().await;
+
+        let mut window_start = now_ms();
+        loop {
+            ticker.tick().await;
+            window_start = flush(&collector, &sink, window_start).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{flush, UsageSink, WorkerUsageMeter};
+    use sb_worker_context::usage::UsageCollector;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_parse_usage_sink() {
+        assert_eq!(
+            "https://billing.local/usage".parse::<UsageSink>().unwrap(),
+            UsageSink::Http("https://billing.local/usage".to_string())
+        );
+        assert_eq!(
+            "file:///var/log/usage.ndjson".parse::<UsageSink>().unwrap(),
+            UsageSink::File(PathBuf::from("/var/log/usage.ndjson"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_usage_to_file() {
+        let path = std::env::temp_dir().join(format!("usage-{}.ndjson", uuid::Uuid::new_v4()));
+        let collector = UsageCollector::default();
+        collector.record("./hello", |u| {
+            u.requests += 2;
+            u.egress_bytes += 64;
+        });
+
+        flush(&collector, &UsageSink::File(path.clone()), 0).await;
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.contains(r#""servicePath":"./hello""#));
+        assert!(written.contains(r#""requests":2"#));
+        assert!(written.contains(r#""egressBytes":64"#));
+        assert!(collector.take().is_empty());
+    }
+
+    #[test]
+    fn test_worker_usage_meter_records_deltas() {
+        let collector = UsageCollector::default();
+        let downloads = Arc::new(AtomicU64::new(0));
+        let started_at = Instant::now();
+        let mut meter = WorkerUsageMeter::new(
+            collector.clone(),
+            "./hello".to_string(),
+            512,
+            1_000_000,
+            started_at,
+            Some(downloads.clone()),
+        );
+
+        downloads.store(2, Ordering::Relaxed);
+        meter.record(4_500_000, started_at + Duration::from_secs(2));
+        let window = collector.take();
+        assert_eq!(window["./hello"].cpu_time_ms, 3);
+        assert_eq!(window["./hello"].memory_gb_seconds, 1.0);
+        assert_eq!(window["./hello"].module_cache_misses, 2);
+
+        // the next window only gets what was used since, including the carried 0.5ms
+        downloads.store(3, Ordering::Relaxed);
+        meter.record(6_000_000, started_at + Duration::from_secs(6));
+        let window = collector.take();
+        assert_eq!(window["./hello"].cpu_time_ms, 2);
+        assert_eq!(window["./hello"].memory_gb_seconds, 2.0);
+        assert_eq!(window["./hello"].module_cache_misses, 1);
+    }
+}
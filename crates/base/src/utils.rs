@@ -1,3 +1,4 @@
+use crate::fault_injection::inject_dropped_event;
 use event_worker::events::{EventMetadata, WorkerEventWithMetadata, WorkerEvents};
 use tokio::sync::mpsc;
 
@@ -11,6 +12,9 @@ pub fn send_event_if_event_worker_available(
     metadata: EventMetadata,
 ) {
     if let Some(event_worker) = maybe_event_worker {
+        if inject_dropped_event() {
+            return;
+        }
         let _ = event_worker.send(WorkerEventWithMetadata { event, metadata });
     }
 }
@@ -6,6 +6,7 @@ use deno_core::error::{custom_error, AnyError};
 use deno_core::ModuleSpecifier;
 use eszip::deno_graph;
 use eszip::deno_graph::{ModuleGraph, ModuleGraphError};
+use sb_eszip::api_version::stamp_api_version;
 use std::path::PathBuf;
 
 #[derive(Clone, Copy)]
@@ -132,12 +133,20 @@ pub async fn build_graph_with_npm_resolution<'a>(
 
 pub async fn create_graph_and_maybe_check(
     roots: Vec<ModuleSpecifier>,
+) -> Result<deno_graph::ModuleGraph, AnyError> {
+    create_graph(roots, deno_graph::GraphKind::CodeOnly).await
+}
+
+// `GraphKind::All` also follows type only dependencies (`@deno-types`,
+// `X-TypeScript-Types`), for type checking
+pub async fn create_graph(
+    roots: Vec<ModuleSpecifier>,
+    graph_kind: deno_graph::GraphKind,
 ) -> Result<deno_graph::ModuleGraph, AnyError> {
     let emitter_factory = EmitterFactory::new();
 
     let mut cache = emitter_factory.file_fetcher_loader();
     let analyzer = emitter_factory.parsed_source_cache().unwrap().as_analyzer();
-    let graph_kind = deno_graph::GraphKind::CodeOnly;
     let mut graph = ModuleGraph::new(graph_kind);
     let graph_resolver = emitter_factory.graph_resolver();
 
@@ -199,7 +208,8 @@ pub async fn create_eszip_from_graph(graph: ModuleGraph) -> Vec<u8> {
     let parser_arc = emitter.parsed_source_cache().unwrap();
     let parser = parser_arc.as_capturing_parser();
 
-    let eszip = eszip::EszipV2::from_graph(graph, &parser, Default::default());
+    let mut eszip = eszip::EszipV2::from_graph(graph, &parser, Default::default()).unwrap();
+    stamp_api_version(&mut eszip, option_env!("GIT_V_TAG"));
 
-    eszip.unwrap().into_bytes()
+    eszip.into_bytes()
 }
@@ -0,0 +1,337 @@
+use crate::rt_worker::routes::RouteTable;
+use crate::utils::graph_util::{create_graph_and_maybe_check, graph_valid, GraphValidOptions};
+use anyhow::{anyhow, bail, Context, Error};
+use deno_ast::MediaType;
+use deno_core::serde_json;
+use deno_core::url::Url;
+use deno_core::ModuleSpecifier;
+use eszip::deno_graph::{Module, ModuleGraph};
+use log::info;
+use module_fetcher::util::checksum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const VENDOR_DIR: &str = "vendor";
+pub const VENDOR_IMPORT_MAP: &str = "import_map.json";
+
+pub struct VendorOpts {
+    pub service_path: PathBuf,
+    // defaults to `vendor` in the service directory
+    pub output_dir: Option<PathBuf>,
+    // replaces a non-empty output directory
+    pub force: bool,
+}
+
+#[derive(Debug)]
+pub struct VendorSummary {
+    pub output_dir: PathBuf,
+    pub import_map_path: PathBuf,
+    pub modules: usize,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct ImportMapJson {
+    imports: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    scopes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+fn host_dir(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}_{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+// Where a remote module would be vendored if its url could be used as a path
+// as is (`https://deno.land/std/path/mod.ts` -> `deno.land/std/path/mod.ts`)
+fn mirror_path(url: &Url) -> Option<String> {
+    Some(format!("{}{}", host_dir(url)?, url.path()))
+}
+
+// Where a remote module is vendored, relative to the vendor directory. Query
+// strings are hashed into the file name and an extension matching the media
+// type is added when the url has none (eg: `https://esm.sh/react@18`).
+pub(crate) fn local_path(url: &Url, media_type: MediaType) -> Option<String> {
+    let mut path = mirror_path(url)?;
+    if path.ends_with('/') {
+        path.push_str("index");
+    }
+    if let Some(query) = url.query() {
+        let hash = checksum::gen(&[query.as_bytes()]);
+        let (stem, ext) = match path
+            .rfind('.')
+            .filter(|i| *i > path.rfind('/').unwrap_or(0))
+        {
+            Some(i) => path.split_at(i),
+            None => (path.as_str(), ""),
+        };
+        path = format!("{}_{}{}", stem, &hash[..8], ext);
+    }
+    let as_specifier = Url::parse(&format!("file:///{}", path)).ok()?;
+    if MediaType::from_specifier(&as_specifier) != media_type {
+        path.push_str(media_type.as_ts_extension());
+    }
+    Some(path)
+}
+
+impl ImportMapJson {
+    fn add_host(&mut self, url: &Url) {
+        if let Some(host_dir) = host_dir(url) {
+            self.imports.insert(
+                format!("{}/", url.origin().ascii_serialization()),
+                format!("./{}/", host_dir),
+            );
+        }
+    }
+
+    // Maps `specifier` to a vendored module. Modules vendored at their mirror path
+    // are covered by the host entry, others need their own entry, for the remote
+    // url and for relative imports of vendored modules resolving to the mirror path.
+    fn add_module(&mut self, specifier: &Url, local_path: &str) {
+        let Some(mirror_path) = mirror_path(specifier) else {
+            return;
+        };
+        if mirror_path == local_path {
+            return;
+        }
+        let mut mirror_key = format!("./{}", mirror_path);
+        if let Some(query) = specifier.query() {
+            mirror_key.push('?');
+            mirror_key.push_str(query);
+        }
+        let target = format!("./{}", local_path);
+        self.imports.insert(specifier.to_string(), target.clone());
+        self.imports.insert(mirror_key, target);
+    }
+
+    // Host-absolute imports (`/v135/react.js`) of vendored modules resolve against
+    // the file system root, so they're remapped for the modules of the host.
+    fn add_scoped(&mut self, referrer: &Url, specifier: &str, local_path: &str) {
+        let Some(host_dir) = host_dir(referrer) else {
+            return;
+        };
+        self.scopes
+            .entry(format!("./{}/", host_dir))
+            .or_default()
+            .insert(specifier.to_string(), format!("./{}", local_path));
+    }
+}
+
+fn is_remote(specifier: &Url) -> bool {
+    matches!(specifier.scheme(), "http" | "https")
+}
+
+// Modules of a service: its index.ts or, for services using file-based routing,
+// its route modules
+pub(crate) fn service_roots(service_path: &Path) -> Result<Vec<ModuleSpecifier>, Error> {
+    let service_path = std::fs::canonicalize(service_path)
+        .with_context(|| format!("service {} not found", service_path.display()))?;
+    let to_specifier = |path: PathBuf| {
+        ModuleSpecifier::from_file_path(&path)
+            .map_err(|_| anyhow!("invalid module path {}", path.display()))
+    };
+
+    if service_path.is_file() {
+        return Ok(vec![to_specifier(service_path)?]);
+    }
+    let index = service_path.join("index.ts");
+    if index.exists() {
+        return Ok(vec![to_specifier(index)?]);
+    }
+    if let Some(table) = RouteTable::from_service_path(&service_path)? {
+        return table
+            .modules()
+            .map(|module| to_specifier(service_path.join(module)))
+            .collect();
+    }
+    bail!(
+        "{} has neither an index.ts nor a routes directory",
+        service_path.display()
+    )
+}
+
+pub(crate) fn module_source(module: &Module) -> Option<(&str, MediaType)> {
+    match module {
+        Module::Esm(module) => Some((&*module.source, module.media_type)),
+        Module::Json(module) => Some((&*module.source, module.media_type)),
+        _ => None,
+    }
+}
+
+fn write_vendor_dir(graph: &ModuleGraph, output_dir: &Path) -> Result<usize, Error> {
+    let mut import_map = ImportMapJson::default();
+    let mut local_paths = BTreeMap::new();
+
+    for module in graph.modules() {
+        let specifier = module.specifier();
+        if !is_remote(specifier) {
+            continue;
+        }
+        let Some((source, media_type)) = module_source(module) else {
+            continue;
+        };
+        let local = local_path(specifier, media_type)
+            .ok_or_else(|| anyhow!("can't vendor {}", specifier))?;
+        let path = output_dir.join(&local);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, source)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        import_map.add_host(specifier);
+        import_map.add_module(specifier, &local);
+        local_paths.insert(specifier.clone(), local);
+    }
+
+    for (from, to) in &graph.redirects {
+        if let Some(local) = local_paths.get(graph.resolve(to)) {
+            import_map.add_module(from, local);
+        }
+    }
+
+    for module in graph.modules() {
+        let Module::Esm(module) = module else {
+            continue;
+        };
+        if !is_remote(&module.specifier) {
+            continue;
+        }
+        for (text, dependency) in &module.dependencies {
+            if !text.starts_with('/') {
+                continue;
+            }
+            let resolved = dependency
+                .maybe_code
+                .maybe_specifier()
+                .map(|s| graph.resolve(s));
+            if let Some(local) = resolved.and_then(|s| local_paths.get(s)) {
+                import_map.add_scoped(&module.specifier, text, local);
+            }
+        }
+    }
+
+    let import_map_path = output_dir.join(VENDOR_IMPORT_MAP);
+    std::fs::write(&import_map_path, serde_json::to_string_pretty(&import_map)?)?;
+    Ok(local_paths.len())
+}
+
+// Downloads the remote modules a service depends on into a vendor directory,
+// along with an import map resolving them to it
+pub async fn vendor(opts: VendorOpts) -> Result<VendorSummary, Error> {
+    let output_dir = opts.output_dir.clone().unwrap_or_else(|| {
+        let service_dir = match opts.service_path.is_file() {
+            true => opts.service_path.parent().unwrap_or(Path::new(".")),
+            false => &opts.service_path,
+        };
+        service_dir.join(VENDOR_DIR)
+    });
+    let is_empty = std::fs::read_dir(&output_dir)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(true);
+    if !is_empty {
+        if !opts.force {
+            bail!(
+                "{} is not empty (use --force to replace it)",
+                output_dir.display()
+            );
+        }
+        std::fs::remove_dir_all(&output_dir)?;
+    }
+
+    let roots = service_roots(&opts.service_path)?;
+    let graph = create_graph_and_maybe_check(roots.clone()).await?;
+    graph_valid(
+        &graph,
+        &roots,
+        GraphValidOptions {
+            check_js: false,
+            follow_type_only: false,
+            is_vendoring: true,
+        },
+    )?;
+
+    std::fs::create_dir_all(&output_dir)?;
+    let modules = write_vendor_dir(&graph, &output_dir)?;
+    let import_map_path = output_dir.join(VENDOR_IMPORT_MAP);
+    info!("vendored {} modules to {}", modules, output_dir.display());
+
+    Ok(VendorSummary {
+        output_dir,
+        import_map_path,
+        modules,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{local_path, ImportMapJson};
+    use deno_ast::MediaType;
+    use deno_core::url::Url;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(
+            local_path(
+                &url("https://deno.land/std/path/mod.ts"),
+                MediaType::TypeScript
+            )
+            .unwrap(),
+            "deno.land/std/path/mod.ts"
+        );
+        assert_eq!(
+            local_path(&url("https://esm.sh/react@18"), MediaType::JavaScript).unwrap(),
+            "esm.sh/react@18.js"
+        );
+        assert_eq!(
+            local_path(&url("http://localhost:4545/"), MediaType::TypeScript).unwrap(),
+            "localhost_4545/index.ts"
+        );
+        let with_query = local_path(
+            &url("https://esm.sh/preact.js?target=deno"),
+            MediaType::JavaScript,
+        )
+        .unwrap();
+        assert!(with_query.starts_with("esm.sh/preact_"));
+        assert!(with_query.ends_with(".js"));
+    }
+
+    #[test]
+    fn test_import_map() {
+        let mut import_map = ImportMapJson::default();
+        let mod_ts = url("https://deno.land/std/path/mod.ts");
+        import_map.add_host(&mod_ts);
+        import_map.add_module(&mod_ts, "deno.land/std/path/mod.ts");
+        let react = url("https://esm.sh/react@18");
+        import_map.add_host(&react);
+        import_map.add_module(&react, "esm.sh/react@18.js");
+        import_map.add_scoped(&react, "/v135/react.js", "esm.sh/v135/react.js");
+
+        assert_eq!(
+            import_map.imports.get("https://deno.land/").unwrap(),
+            "./deno.land/"
+        );
+        assert!(!import_map.imports.contains_key(mod_ts.as_str()));
+        assert_eq!(
+            import_map.imports.get(react.as_str()).unwrap(),
+            "./esm.sh/react@18.js"
+        );
+        assert_eq!(
+            import_map.imports.get("./esm.sh/react@18").unwrap(),
+            "./esm.sh/react@18.js"
+        );
+        assert_eq!(
+            import_map.scopes["./esm.sh/"]
+                .get("/v135/react.js")
+                .unwrap(),
+            "./esm.sh/v135/react.js"
+        );
+    }
+}
@@ -0,0 +1,16 @@
+Deno.serve(async (req: Request) => {
+  const alarm = EdgeRuntime.alarms.fromRequest(req);
+  if (alarm) {
+    const payload = await req.json();
+    console.log(`alarm ${alarm.id} fired (attempt ${alarm.attempt}):`, payload);
+    return new Response(null, { status: 204 });
+  }
+
+  const id = EdgeRuntime.alarms.schedule({
+    delayMs: 100,
+    path: "/retry",
+    payload: { webhook: "order.created" },
+  });
+  const scheduled = EdgeRuntime.alarms.list();
+  return Response.json({ id, servicePath: scheduled[0].servicePath, scheduled: scheduled.length });
+});
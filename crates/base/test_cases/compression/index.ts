@@ -0,0 +1,29 @@
+const { CompressionStream, DecompressionStream } = EdgeRuntime.compression;
+
+async function roundTrip(format: string, text: string) {
+  const compressed = await new Response(
+    new Blob([text]).stream().pipeThrough(new CompressionStream(format)),
+  ).arrayBuffer();
+  const decompressed = await new Response(
+    new Blob([compressed]).stream().pipeThrough(new DecompressionStream(format)),
+  ).text();
+  return { smaller: compressed.byteLength < text.length, same: decompressed === text };
+}
+
+// `/compression/<format>` round trips some text, `/compression/invalid`
+// decompresses garbage
+Deno.serve(async (req) => {
+  const format = new URL(req.url).pathname.split("/").pop()!;
+  if (format !== "invalid") {
+    return Response.json(await roundTrip(format, "edge runtime ".repeat(1000)));
+  }
+
+  try {
+    await new Response(
+      new Blob(["not zstd"]).stream().pipeThrough(new DecompressionStream("zstd")),
+    ).arrayBuffer();
+    return Response.json({});
+  } catch (err) {
+    return Response.json({ error: err.name }, { status: 400 });
+  }
+});
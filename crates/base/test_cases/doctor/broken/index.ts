@@ -0,0 +1,3 @@
+import { missing } from "./not_there.ts";
+
+Deno.serve(() => new Response(missing));
@@ -0,0 +1,8 @@
+Deno.serve((req: Request) => {
+  const key = req.headers.get("x-user-id") ?? undefined;
+  return Response.json({
+    all: EdgeRuntime.flags.all({ key }),
+    newCheckout: EdgeRuntime.flags.get("newCheckout"),
+    missing: EdgeRuntime.flags.get("missing") ?? null,
+  });
+});
@@ -0,0 +1,24 @@
+async function collect(req: Request) {
+  const parts = [];
+  for await (const part of req.formDataStream({ maxFileBytes: 1024 })) {
+    if ("value" in part) {
+      parts.push({ name: part.name, value: part.value });
+    } else {
+      const text = await new Response(part.stream).text();
+      parts.push({ name: part.name, filename: part.filename, type: part.type, text });
+    }
+  }
+  return parts;
+}
+
+Deno.serve(async (req) => {
+  try {
+    const parts = await collect(req);
+    if (new URL(req.url).pathname === "/upload/consumed") {
+      await req.formData();
+    }
+    return Response.json({ parts });
+  } catch (err) {
+    return Response.json({ error: err.name }, { status: 400 });
+  }
+});
@@ -0,0 +1,35 @@
+// 4x2 red png
+const PNG = Uint8Array.from(
+  atob("iVBORw0KGgoAAAANSUhEUgAAAAQAAAACCAYAAAB/qH1jAAAAEklEQVR4nGP4z8DwHxkzoAsAAA8hD/EEN8afAAAAAElFTkSuQmCC"),
+  (c) => c.charCodeAt(0),
+);
+
+Deno.serve(async (req: Request) => {
+  const images = EdgeRuntime.images;
+
+  switch (new URL(req.url).pathname) {
+    case "/images/limits":
+      return Response.json(images.limits);
+    case "/images/info":
+      return Response.json(images.info(PNG));
+    case "/images/transform": {
+      const resized = await images.transform(PNG, {
+        resize: { width: 2 },
+        format: "jpeg",
+      });
+      return Response.json({
+        width: resized.width,
+        height: resized.height,
+        format: resized.format,
+        jpeg: resized.data[0] === 0xff && resized.data[1] === 0xd8,
+      });
+    }
+    default:
+      try {
+        await images.transform(PNG, { resize: { width: 100000, height: 100000 } });
+        return Response.json({});
+      } catch (err) {
+        return Response.json({ error: err.name }, { status: 413 });
+      }
+  }
+});
@@ -0,0 +1,21 @@
+const schema = {
+  type: "object",
+  required: ["email"],
+  properties: {
+    email: { type: "string", format: "email" },
+    age: { type: "integer", minimum: 0 },
+  },
+};
+
+Deno.serve(async (req) => {
+  // compiled by the first request only
+  const validator = EdgeRuntime.jsonSchema.compile(schema);
+  const { valid, errors } = validator.validateJson(await req.text());
+  if (!valid) {
+    return Response.json(
+      { errors: errors.map((err) => [err.instancePath, err.schemaPath]) },
+      { status: 400 },
+    );
+  }
+  return Response.json({ valid, same: validator.validate({ email: "a@b.co" }).valid });
+});
@@ -0,0 +1,22 @@
+Deno.serve(async (req: Request) => {
+  const url = new URL(req.url);
+  const path = url.searchParams.get("path") ?? "/files/report.pdf";
+
+  switch (url.pathname) {
+    case "/key_store/keys":
+      return Response.json({ keys: EdgeRuntime.crypto.keys() });
+    case "/key_store/sign": {
+      const signature = await EdgeRuntime.crypto.sign("urls", path);
+      const valid = await EdgeRuntime.crypto.verify("urls", path, signature);
+      return Response.json({ signature: Array.from(signature), valid });
+    }
+    default:
+      // the billing key belongs to another service
+      try {
+        await EdgeRuntime.crypto.sign("billing", path);
+        return Response.json({});
+      } catch (err) {
+        return Response.json({ error: err.message }, { status: 403 });
+      }
+  }
+});
@@ -0,0 +1,6 @@
+Deno.serve(() => {
+  for (let i = 0; i < 50; i++) {
+    console.log(`line ${i}`);
+  }
+  return new Response("flooded");
+});
@@ -0,0 +1,9 @@
+// the remote module is never fetched, the test runs with a local mock of it
+import { greet } from "https://example.com/greeter.ts";
+
+Deno.test("uses the mocked module", () => {
+  const greeting = greet("world");
+  if (greeting !== "Hello from the mock, world!") {
+    throw new Error(`unexpected greeting: ${greeting}`);
+  }
+});
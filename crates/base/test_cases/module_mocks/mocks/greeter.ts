@@ -0,0 +1,5 @@
+import { suffix } from "./suffix.ts";
+
+export function greet(name: string): string {
+  return `Hello from the mock, ${name}${suffix}`;
+}
@@ -0,0 +1 @@
+Deno.serve((req: Request) => Response.json(EdgeRuntime.context(req)));
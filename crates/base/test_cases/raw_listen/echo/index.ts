@@ -0,0 +1,28 @@
+// echoes what's written on the granted TCP socket, on an ephemeral port
+const listener = EdgeRuntime.listen({ hostname: "127.0.0.1", port: 0 });
+
+(async () => {
+  for await (const conn of listener) {
+    (async () => {
+      const buf = new Uint8Array(1024);
+      let n;
+      while ((n = await conn.read(buf)) !== null) {
+        await conn.write(buf.subarray(0, n));
+      }
+      conn.close();
+    })();
+  }
+})();
+
+let denied = null;
+try {
+  EdgeRuntime.listen({ hostname: "127.0.0.1", port: 18831 });
+} catch (err) {
+  denied = err.name;
+}
+
+Deno.serve((req: Request) =>
+  new URL(req.url).pathname === "/denied"
+    ? Response.json({ denied })
+    : Response.json({ addr: listener.addr })
+);
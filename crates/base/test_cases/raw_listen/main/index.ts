@@ -0,0 +1,22 @@
+Deno.serve(async (req: Request) => {
+  const worker = await EdgeRuntime.userWorkers.create({
+    servicePath: "./test_cases/raw_listen/echo",
+    permissions: { listen: [{ hostname: "127.0.0.1", port: 0 }] },
+  });
+  if (new URL(req.url).pathname === "/denied") {
+    return await worker.fetch(req);
+  }
+  // the worker listens once it booted
+  const info = await (await worker.fetch(req)).json();
+
+  const conn = await Deno.connect({ hostname: "127.0.0.1", port: info.addr.port });
+  await conn.write(new TextEncoder().encode("ping"));
+  const buf = new Uint8Array(4);
+  const n = await conn.read(buf);
+  conn.close();
+
+  return Response.json({
+    addr: info.addr,
+    echo: new TextDecoder().decode(buf.subarray(0, n ?? 0)),
+  });
+});
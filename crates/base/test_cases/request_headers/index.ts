@@ -0,0 +1,28 @@
+Deno.serve(async (req: Request) => {
+  const single = {
+    accept: req.headers.get("Accept"),
+    custom: req.headers.get("x-custom"),
+    missing: req.headers.get("x-missing"),
+    hasCustom: req.headers.has("X-Custom"),
+    length: req.headers.get("content-length"),
+  };
+  let immutable = false;
+  try {
+    req.headers.set("x-custom", "changed");
+  } catch (_) {
+    immutable = true;
+  }
+  const all = Object.fromEntries(req.headers);
+  const many = EdgeRuntime.getHeaders(req, ["accept", "X-Custom", "x-missing"]);
+  const envs = EdgeRuntime.getEnvs(["FOO", "MISSING"]);
+  return Response.json({
+    single,
+    many,
+    envs,
+    immutable,
+    all: { accept: all["accept"], custom: all["x-custom"] },
+    copied: new Headers(req.headers).get("x-custom"),
+    cloned: req.clone().headers.get("x-custom"),
+    body: await req.text(),
+  });
+});
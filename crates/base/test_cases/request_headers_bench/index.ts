@@ -0,0 +1,5 @@
+// reads a single header of requests carrying many
+Deno.serve((req: Request) => {
+  const header = req.headers.get("x-header-0");
+  return new Response(header, { status: header === "0" ? 200 : 500 });
+});
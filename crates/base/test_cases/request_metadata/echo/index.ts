@@ -0,0 +1,6 @@
+Deno.serve((req: Request) =>
+  Response.json({
+    metadata: EdgeRuntime.context(req).metadata,
+    header: req.headers.get("x-metadata"),
+  })
+);
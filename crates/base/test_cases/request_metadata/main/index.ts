@@ -0,0 +1,9 @@
+// forwards requests with the claims a real main worker would have verified
+Deno.serve(async (req: Request) => {
+  const worker = await EdgeRuntime.userWorkers.create({
+    servicePath: "./test_cases/request_metadata/echo",
+  });
+  return await worker.fetch(req, {
+    metadata: { sub: "user-1", role: "authenticated" },
+  });
+});
@@ -0,0 +1,16 @@
+// a platform-wide header policy and an error page
+EdgeRuntime.userWorkers.onResponse((res: Response) => {
+  res.headers.set("x-frame-options", "DENY");
+});
+EdgeRuntime.userWorkers.onResponse((res: Response, { buffered }) => {
+  if (buffered && res.status === 404) {
+    return new Response("custom not found", { status: 404, headers: res.headers });
+  }
+});
+
+Deno.serve(async (req: Request) => {
+  const worker = await EdgeRuntime.userWorkers.create({
+    servicePath: "./test_cases/response_hooks/user",
+  });
+  return await worker.fetch(req);
+});
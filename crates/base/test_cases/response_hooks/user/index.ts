@@ -0,0 +1,6 @@
+Deno.serve((req: Request) => {
+  if (new URL(req.url).pathname === "/missing") {
+    return new Response("not found", { status: 404 });
+  }
+  return new Response("ok");
+});
@@ -0,0 +1,41 @@
+const { csv, ndjson } = EdgeRuntime;
+
+async function collect<T>(stream: ReadableStream<T>) {
+  const items: T[] = [];
+  for await (const item of stream) {
+    items.push(item);
+  }
+  return items;
+}
+
+Deno.serve(async (req) => {
+  const path = new URL(req.url).pathname;
+  if (path === "/import/too_large") {
+    try {
+      await collect(
+        req.body!.pipeThrough(new csv.ParseStream({ maxRecordBytes: 64 })),
+      );
+      return Response.json({});
+    } catch (err) {
+      return Response.json({ error: err.name }, { status: 400 });
+    }
+  }
+
+  // csv upload in, ndjson out
+  const lines = await collect(
+    req.body!
+      .pipeThrough(new csv.ParseStream({ header: true }))
+      .pipeThrough(new ndjson.StringifyStream()),
+  );
+  if (path !== "/import/csv") {
+    return Response.json({ records: lines.map((line) => JSON.parse(line)) });
+  }
+
+  // and back to csv, with the columns reordered
+  const rows = await collect(
+    ReadableStream.from(lines)
+      .pipeThrough(new ndjson.ParseStream())
+      .pipeThrough(new csv.StringifyStream({ columns: ["qty", "sku"] })),
+  );
+  return Response.json({ csv: rows.join("") });
+});
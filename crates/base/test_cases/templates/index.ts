@@ -0,0 +1,5 @@
+Deno.serve((req: Request) => {
+  const name = new URL(req.url).searchParams.get("name") ?? "world";
+  const html = EdgeRuntime.render("pages/hello.html", { name });
+  return new Response(html, { headers: { "content-type": "text/html" } });
+});
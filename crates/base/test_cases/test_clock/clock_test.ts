@@ -0,0 +1,34 @@
+const { clock } = EdgeRuntime.testing;
+
+Deno.test("fires timers when the clock advances", async () => {
+  clock.freeze(new Date("2024-01-01T00:00:00Z"));
+  const fired: string[] = [];
+  const interval = setInterval(() => fired.push(`tick ${new Date().toISOString()}`), 1000);
+  setTimeout(() => fired.push("timeout"), 1500);
+
+  await clock.advance(2500);
+  clearInterval(interval);
+  await clock.advance(5000);
+
+  const expected = [
+    "tick 2024-01-01T00:00:01.000Z",
+    "timeout",
+    "tick 2024-01-01T00:00:02.000Z",
+  ];
+  if (JSON.stringify(fired) !== JSON.stringify(expected)) {
+    throw new Error(`unexpected timers: ${JSON.stringify(fired)}`);
+  }
+  if (Date.now() !== Date.parse("2024-01-01T00:00:07.500Z")) {
+    throw new Error(`unexpected time: ${new Date().toISOString()}`);
+  }
+});
+
+Deno.test("runs again once restored", async () => {
+  clock.freeze(0);
+  const start = performance.now();
+  clock.restore();
+  await new Promise((resolve) => setTimeout(resolve, 20));
+  if (Date.now() < 20 || Date.now() > 10_000 || performance.now() - start < 20) {
+    throw new Error(`clock didn't run from where it was frozen: ${Date.now()}`);
+  }
+});
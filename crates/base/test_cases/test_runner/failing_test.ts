@@ -0,0 +1,3 @@
+Deno.test("fails", () => {
+  throw new Error("boom");
+});
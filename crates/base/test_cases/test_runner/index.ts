@@ -0,0 +1,8 @@
+import { add } from "./lib/math.ts";
+
+Deno.serve((req: Request) => {
+  const url = new URL(req.url);
+  const a = Number(url.searchParams.get("a"));
+  const b = Number(url.searchParams.get("b"));
+  return new Response(String(add(a, b)));
+});
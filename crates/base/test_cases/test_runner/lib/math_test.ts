@@ -0,0 +1,23 @@
+import { add } from "./math.ts";
+
+function assertEquals(actual: unknown, expected: unknown) {
+  if (actual !== expected) {
+    throw new Error(`expected ${expected}, got ${actual}`);
+  }
+}
+
+Deno.test("adds numbers", () => {
+  assertEquals(add(1, 2), 3);
+});
+
+Deno.test("runs with the edge runtime", () => {
+  assertEquals(typeof EdgeRuntime, "object");
+});
+
+Deno.test({
+  name: "ignored test",
+  ignore: true,
+  fn: () => {
+    throw new Error("should not run");
+  },
+});
@@ -0,0 +1,5 @@
+import { greet } from "./greet.ts";
+
+const count: number = greet("world");
+
+Deno.serve(() => new Response(String(count)));
@@ -0,0 +1,15 @@
+// sums the numbers of the request in a web worker, through a SharedArrayBuffer
+Deno.serve(async (req: Request) => {
+  const numbers: number[] = await req.json();
+  const shared = new Int32Array(new SharedArrayBuffer(4));
+
+  const worker = new Worker(new URL("./sum.ts", import.meta.url), { type: "module" });
+  const reply = new Promise((resolve) => {
+    worker.onmessage = (e) => resolve(e.data);
+  });
+  worker.postMessage({ numbers, shared });
+  const data = await reply;
+  worker.terminate();
+
+  return Response.json({ reply: data, sum: Atomics.load(shared, 0) });
+});
@@ -0,0 +1,7 @@
+self.onmessage = (e: MessageEvent) => {
+  const { numbers, shared } = e.data;
+  for (const n of numbers) {
+    Atomics.add(shared, 0, n);
+  }
+  postMessage("done");
+};
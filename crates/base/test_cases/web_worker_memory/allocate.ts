@@ -0,0 +1,7 @@
+// holds about 24MB of heap, under the limit of the worker on its own
+const chunks: number[][] = [];
+for (let i = 0; i < 24; i++) {
+  chunks.push(new Array(128 * 1024).fill(i + 0.5));
+}
+postMessage(chunks.length);
+setInterval(() => chunks.length, 100);
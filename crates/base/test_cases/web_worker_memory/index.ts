@@ -0,0 +1,15 @@
+// two web workers that together hold more memory than the worker's limit
+Deno.serve(async () => {
+  const workers = [1, 2].map(() =>
+    new Worker(new URL("./allocate.ts", import.meta.url), { type: "module" })
+  );
+  await Promise.all(workers.map((worker) =>
+    new Promise((resolve) => {
+      worker.onmessage = resolve;
+    })
+  ));
+  // the budget is checked as the workers keep running
+  await new Promise((resolve) => setTimeout(resolve, 2000));
+
+  return new Response("allocated");
+});
@@ -0,0 +1,12 @@
+Deno.serve(async (req: Request) => {
+  const { valid, reason, payload } = await EdgeRuntime.webhooks.verify(req, {
+    provider: "github",
+    secret: "gh-secret",
+  });
+  if (!valid) {
+    return Response.json({ reason }, { status: 401 });
+  }
+
+  const event = JSON.parse(new TextDecoder().decode(payload));
+  return Response.json({ action: event.action });
+});
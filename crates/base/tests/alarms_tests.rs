@@ -0,0 +1,45 @@
+use base::alarms::{alarm_store, SqliteAlarmStore};
+use base::embed::EdgeRuntime;
+use deno_core::serde_json::{self, Value};
+use hyper::{Body, Request};
+use sb_worker_context::manifest::ServiceEntry;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_alarm_fires_after_scheduling_worker_responded() {
+    let rt = EdgeRuntime::builder()
+        .service("alarms", ServiceEntry::new("./test_cases/alarms"))
+        .alarm_store(SqliteAlarmStore::in_memory().unwrap())
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/alarms")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["scheduled"], 1);
+
+    // completed alarms are removed from the store
+    let store = alarm_store().unwrap();
+    let id = body["id"].as_str().unwrap();
+    let service_path = body["servicePath"].as_str().unwrap();
+    let fired = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if store
+                .list(service_path)
+                .unwrap()
+                .iter()
+                .all(|alarm| alarm.id != id)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    assert!(fired.is_ok(), "alarm {} did not fire", id);
+}
@@ -0,0 +1,48 @@
+use base::bench::{run_bench, BenchOpts};
+use std::path::PathBuf;
+
+#[tokio::test]
+async fn test_bench_reports_latency() {
+    let report = run_bench(BenchOpts {
+        service_path: PathBuf::from("./test_cases/std_user_worker"),
+        requests: 50,
+        concurrency: 5,
+        method: "POST".to_string(),
+        headers: vec![("content-type".to_string(), "application/json".to_string())],
+        body: Some(br#"{"name":"bench"}"#.to_vec()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(report.requests, 50);
+    assert_eq!(report.errors, 0);
+    assert!(report.cold_start_ms > 0.0);
+    let latency = &report.latency;
+    assert!(latency.min_ms <= latency.p50_ms);
+    assert!(latency.p50_ms <= latency.p95_ms);
+    assert!(latency.p95_ms <= latency.p99_ms);
+    assert!(latency.p99_ms <= latency.max_ms);
+    assert!(report.max_rss_mb > 0.0);
+}
+
+#[tokio::test]
+async fn test_bench_requests_with_many_headers() {
+    // handed to the worker without copying them, only one of them is read
+    let headers = (0..100)
+        .map(|i| (format!("x-header-{}", i), i.to_string()))
+        .collect();
+    let report = run_bench(BenchOpts {
+        service_path: PathBuf::from("./test_cases/request_headers_bench"),
+        requests: 200,
+        concurrency: 10,
+        headers,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(report.requests, 200);
+    assert_eq!(report.errors, 0);
+    assert!(report.requests_per_sec > 0.0);
+}
@@ -0,0 +1,66 @@
+// Helpers shared by the integration tests, not all of them use every helper
+#![allow(dead_code)]
+
+use base::embed::EdgeRuntime;
+use base::rt_worker::worker_ctx::{create_user_worker_pool, create_worker};
+use deno_core::serde_json::{self, Value};
+use hyper::{Body, Request, Response};
+use sb_worker_context::essentials::{
+    MainWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+
+pub fn get(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+pub fn post(uri: &str, body: impl Into<Body>) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .body(body.into())
+        .unwrap()
+}
+
+// Sends the request to the runtime, returns the response status and JSON body
+pub async fn json_response(rt: &EdgeRuntime, req: Request<Body>) -> (u16, Value) {
+    let res = rt.handle(req).await.unwrap();
+    let status = res.status().as_u16();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+// Boots the service as a main worker, with its own user worker pool
+pub async fn main_worker(service_path: &str) -> mpsc::UnboundedSender<WorkerRequestMsg> {
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
+    let opts = WorkerContextInitOpts {
+        service_path: service_path.into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
+            worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
+        }),
+    };
+    create_worker(opts).await.unwrap()
+}
+
+pub async fn json_body(res: Response<Body>) -> Value {
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+pub async fn send(
+    worker_req_tx: &mpsc::UnboundedSender<WorkerRequestMsg>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
+    let _ = worker_req_tx.send(WorkerRequestMsg { req, res_tx });
+    res_rx.await.unwrap().unwrap()
+}
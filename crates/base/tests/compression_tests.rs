@@ -0,0 +1,45 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::{get, json_response};
+use deno_core::serde_json::{json, Value};
+use sb_worker_context::manifest::ServiceEntry;
+
+async fn compression(case: &str) -> (u16, Value) {
+    let rt = EdgeRuntime::builder()
+        .service("compression", ServiceEntry::new("./test_cases/compression"))
+        .build()
+        .await
+        .unwrap();
+
+    let uri = format!("http://localhost/compression/{}", case);
+    json_response(&rt, get(&uri)).await
+}
+
+#[tokio::test]
+async fn test_gzip_round_trip() {
+    let (status, body) = compression("gzip").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "smaller": true, "same": true }));
+}
+
+#[tokio::test]
+async fn test_brotli_round_trip() {
+    let (status, body) = compression("br").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "smaller": true, "same": true }));
+}
+
+#[tokio::test]
+async fn test_zstd_round_trip() {
+    let (status, body) = compression("zstd").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "smaller": true, "same": true }));
+}
+
+#[tokio::test]
+async fn test_decompress_invalid_data() {
+    let (status, body) = compression("invalid").await;
+    assert_eq!(status, 400);
+    assert_eq!(body, json!({ "error": "TypeError" }));
+}
@@ -0,0 +1,48 @@
+use base::doctor::{doctor, CheckStatus, DoctorOpts};
+
+#[tokio::test]
+async fn test_doctor_reports_each_service() {
+    let report = doctor(DoctorOpts {
+        ip: "127.0.0.1".to_string(),
+        port: 0,
+        manifest_path: Some("./test_cases/doctor/functions.json".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let status = |category: &str, name: &str| {
+        report
+            .checks
+            .iter()
+            .find(|c| c.category == category && c.name == name)
+            .map(|c| c.status)
+    };
+    assert_eq!(status("config", "manifest"), Some(CheckStatus::Pass));
+    assert_eq!(status("port", "http"), Some(CheckStatus::Pass));
+    assert_eq!(status("import map", "hello"), Some(CheckStatus::Pass));
+    assert_eq!(status("boot", "hello"), Some(CheckStatus::Pass));
+    assert_eq!(status("boot", "broken"), Some(CheckStatus::Fail));
+    assert_eq!(status("boot", "missing"), Some(CheckStatus::Fail));
+    assert!(!report.is_success());
+}
+
+#[tokio::test]
+async fn test_doctor_fails_on_invalid_config() {
+    let report = doctor(DoctorOpts {
+        ip: "127.0.0.1".to_string(),
+        port: 0,
+        key_store_path: Some("./test_cases/doctor/functions.json".to_string()),
+        skip_boot: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let check = &report.checks[0];
+    assert_eq!(
+        (check.category.as_str(), check.name.as_str()),
+        ("config", "key store")
+    );
+    assert_eq!(check.status, CheckStatus::Fail);
+}
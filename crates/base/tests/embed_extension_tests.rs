@@ -0,0 +1,47 @@
+use base::embed::{CustomExtension, EdgeRuntime, EsmStage, WorkerKinds};
+use deno_core::op2;
+use hyper::{Body, Request};
+use sb_worker_context::manifest::ServiceEntry;
+
+#[op2]
+#[string]
+fn op_acme_platform_name() -> String {
+    "acme".to_string()
+}
+
+deno_core::extension!(acme_platform, ops = [op_acme_platform_name]);
+
+#[tokio::test]
+async fn test_custom_extension_in_user_workers() {
+    let rt = EdgeRuntime::builder()
+        .service("acme", ServiceEntry::new("./test_cases/embed_extension"))
+        .register_extension(
+            CustomExtension::new(acme_platform::init_ops)
+                .workers(WorkerKinds::USER)
+                .esm_stage(EsmStage::BeforeBootstrap)
+                .esm(
+                    "ext:acme_platform/setup.js",
+                    "const ops = globalThis.Deno.core.ops;
+                     globalThis.AcmePlatform = { name: () => ops.op_acme_platform_name() };",
+                ),
+        )
+        .register_extension(
+            CustomExtension::new(deno_core::Extension::default)
+                .workers(WorkerKinds::USER)
+                .esm(
+                    "ext:acme_platform/after.js",
+                    "globalThis.AcmePlatform.bootstrapped = typeof EdgeRuntime === 'object';",
+                ),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/acme")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"acme true");
+}
@@ -0,0 +1,36 @@
+use base::embed::EdgeRuntime;
+use hyper::{Body, Request};
+use sb_worker_context::manifest::ServiceEntry;
+
+#[tokio::test]
+async fn test_embedded_runtime_routes_to_registered_services() {
+    let rt = EdgeRuntime::builder()
+        .service(
+            "streams",
+            ServiceEntry::new("./test_cases/readable-stream-resp"),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/streams")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"Hello world from streams");
+
+    let req = Request::builder()
+        .uri("http://localhost/missing")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    assert_eq!(res.status().as_u16(), 503);
+}
+
+#[tokio::test]
+async fn test_embedded_runtime_requires_a_service() {
+    assert!(EdgeRuntime::builder().build().await.is_err());
+}
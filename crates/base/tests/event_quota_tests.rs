@@ -0,0 +1,47 @@
+use base::server::ServerFlags;
+use base::test_runtime::TestRuntime;
+use event_worker::events::{WorkerEventWithMetadata, WorkerEvents};
+use std::time::Duration;
+
+fn is_flood_log(event: &WorkerEventWithMetadata) -> bool {
+    matches!(event.event, WorkerEvents::Log(_))
+        && event
+            .metadata
+            .service_path
+            .as_deref()
+            .is_some_and(|path| path.ends_with("log_flood"))
+}
+
+#[tokio::test]
+async fn test_event_quota_drops_logs_over_quota() {
+    let mut rt = TestRuntime::builder("./test_cases/main")
+        .flags(ServerFlags {
+            event_quota_per_minute: Some(10),
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let resp = rt.get("/log_flood").await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.text().await.unwrap(), "flooded");
+
+    let mut logs = 0;
+    let exceeded = rt
+        .wait_for_event(Duration::from_secs(10), |e| {
+            if is_flood_log(e) {
+                logs += 1;
+            }
+            matches!(e.event, WorkerEvents::LogQuotaExceeded(_))
+        })
+        .await;
+    let WorkerEvents::LogQuotaExceeded(exceeded) = exceeded.unwrap().event else {
+        unreachable!()
+    };
+    assert_eq!(exceeded.events_per_minute, Some(10));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    logs += rt.take_events().iter().filter(|e| is_flood_log(e)).count();
+    assert_eq!(logs, 10);
+}
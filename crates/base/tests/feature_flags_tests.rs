@@ -0,0 +1,54 @@
+use anyhow::Error;
+use base::embed::EdgeRuntime;
+use base::feature_flags::FeatureFlagProvider;
+use deno_core::futures::future::BoxFuture;
+use deno_core::futures::FutureExt;
+use deno_core::serde_json::{self, json};
+use hyper::{Body, Request};
+use sb_worker_context::flags::FeatureFlags;
+use sb_worker_context::manifest::ServiceEntry;
+
+struct StaticFlags(&'static str);
+
+impl FeatureFlagProvider for StaticFlags {
+    fn fetch(&self) -> BoxFuture<'static, Result<FeatureFlags, Error>> {
+        let result = FeatureFlags::parse(self.0);
+        async move { result }.boxed()
+    }
+}
+
+#[tokio::test]
+async fn test_feature_flags_in_user_worker() {
+    let rt = EdgeRuntime::builder()
+        .service(
+            "feature_flags",
+            ServiceEntry::new("./test_cases/feature_flags"),
+        )
+        .feature_flag_provider(StaticFlags(
+            r#"{
+                "newCheckout": { "default": false, "services": { "feature_flags": true } },
+                "theme": { "default": "light", "rollout": { "percentage": 100, "value": "dark" } }
+            }"#,
+        ))
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/feature_flags")
+        .header("x-user-id", "user-1")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        body,
+        json!({
+            "all": { "newCheckout": true, "theme": "dark" },
+            "newCheckout": true,
+            "missing": null,
+        })
+    );
+}
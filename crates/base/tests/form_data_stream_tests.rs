@@ -0,0 +1,66 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::json_response;
+use deno_core::serde_json::{json, Value};
+use hyper::{Body, Request};
+use sb_worker_context::manifest::ServiceEntry;
+
+const NOTES: &str = "--b0undary\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+notes\r\n--b0undary\r\n\
+Content-Disposition: form-data; name=\"doc\"; filename=\"notes.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+first\r\nsecond\r\n--b0undary--\r\n";
+
+async fn upload(uri: &str, body: String) -> (u16, Value) {
+    let rt = EdgeRuntime::builder()
+        .service("upload", ServiceEntry::new("./test_cases/form_data_stream"))
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "multipart/form-data; boundary=b0undary")
+        .body(Body::from(body))
+        .unwrap();
+    json_response(&rt, req).await
+}
+
+#[tokio::test]
+async fn test_form_data_stream() {
+    let (status, body) = upload("http://localhost/upload", NOTES.to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "parts": [
+                { "name": "title", "value": "notes" },
+                {
+                    "name": "doc",
+                    "filename": "notes.txt",
+                    "type": "text/plain",
+                    "text": "first\r\nsecond",
+                },
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_form_data_stream_consumes_body() {
+    let (status, body) = upload("http://localhost/upload/consumed", NOTES.to_string()).await;
+    assert_eq!(status, 400);
+    assert_eq!(body, json!({ "error": "TypeError" }));
+}
+
+#[tokio::test]
+async fn test_form_data_stream_file_too_large() {
+    // over the 1KiB maxFileBytes of the service
+    let body = NOTES.replace("first\r\nsecond", &"x".repeat(2048));
+    let (status, body) = upload("http://localhost/upload", body).await;
+    assert_eq!(status, 400);
+    assert_eq!(body, json!({ "error": "RangeError" }));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use base::server::ServerFlags;
+use common::{get, json_response};
+use deno_core::serde_json::json;
+use sb_worker_context::manifest::ServiceEntry;
+
+async fn images_runtime() -> EdgeRuntime {
+    EdgeRuntime::builder()
+        .service("images", ServiceEntry::new("./test_cases/images"))
+        .flags(ServerFlags {
+            image_memory_limit_mb: Some(16),
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_image_limits() {
+    let rt = images_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/images/limits")).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "maxMemoryBytes": 16 * 1024 * 1024 }));
+}
+
+#[tokio::test]
+async fn test_image_info() {
+    let rt = images_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/images/info")).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "width": 4, "height": 2, "format": "png" }));
+}
+
+#[tokio::test]
+async fn test_transform_image() {
+    let rt = images_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/images/transform")).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({ "width": 2, "height": 1, "format": "jpeg", "jpeg": true })
+    );
+}
+
+#[tokio::test]
+async fn test_transform_over_memory_limit() {
+    let rt = images_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/images/too_large")).await;
+    assert_eq!(status, 413);
+    assert_eq!(body, json!({ "error": "RangeError" }));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::{json_response, post};
+use deno_core::serde_json::{json, Value};
+use sb_worker_context::manifest::ServiceEntry;
+
+async fn signup(payload: &'static str) -> (u16, Value) {
+    let rt = EdgeRuntime::builder()
+        .service("signup", ServiceEntry::new("./test_cases/json_schema"))
+        .build()
+        .await
+        .unwrap();
+
+    json_response(&rt, post("http://localhost/signup", payload)).await
+}
+
+#[tokio::test]
+async fn test_validate_json_schema() {
+    let (status, body) = signup(r#"{ "email": "a@b.co", "age": 3 }"#).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "valid": true, "same": true }));
+}
+
+#[tokio::test]
+async fn test_json_schema_errors() {
+    let (status, body) = signup(r#"{ "age": -1 }"#).await;
+    assert_eq!(status, 400);
+    assert_eq!(
+        body,
+        json!({ "errors": [["", "/required"], ["/age", "/properties/age/minimum"]] })
+    );
+}
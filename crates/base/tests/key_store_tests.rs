@@ -0,0 +1,74 @@
+mod common;
+
+use anyhow::Error;
+use base::embed::EdgeRuntime;
+use common::{get, json_response};
+use deno_core::futures::future::{self, BoxFuture};
+use deno_core::futures::FutureExt;
+use deno_core::serde_json::json;
+use sb_worker_context::keys::Signer;
+use sb_worker_context::manifest::ServiceEntry;
+
+// Stands in for a KMS: signs by appending a marker to the data
+struct FakeKms;
+
+impl Signer for FakeKms {
+    fn algorithm(&self) -> &str {
+        "fake"
+    }
+
+    fn sign(&self, mut data: Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, Error>> {
+        data.push(0xff);
+        future::ready(Ok(data)).boxed()
+    }
+
+    fn verify(
+        &self,
+        mut data: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> BoxFuture<'static, Result<bool, Error>> {
+        data.push(0xff);
+        future::ready(Ok(data == signature)).boxed()
+    }
+}
+
+async fn key_store_runtime() -> EdgeRuntime {
+    EdgeRuntime::builder()
+        .service("key_store", ServiceEntry::new("./test_cases/key_store"))
+        .signing_key("urls", FakeKms, vec!["key_store".to_string()])
+        .signing_key("billing", FakeKms, vec!["billing".to_string()])
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_list_service_keys() {
+    let rt = key_store_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/key_store/keys")).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "keys": ["urls"] }));
+}
+
+#[tokio::test]
+async fn test_sign_with_key_store() {
+    let rt = key_store_runtime().await;
+
+    let req = get("http://localhost/key_store/sign?path=/a");
+    let (status, body) = json_response(&rt, req).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({ "signature": [b'/', b'a', 0xff], "valid": true })
+    );
+}
+
+#[tokio::test]
+async fn test_key_of_other_service() {
+    let rt = key_store_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/key_store/billing")).await;
+    assert_eq!(status, 403);
+    assert_eq!(body, json!({ "error": "no signing key named billing" }));
+}
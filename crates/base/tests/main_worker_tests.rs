@@ -9,7 +9,7 @@ use tokio::sync::oneshot;
 #[tokio::test]
 async fn test_main_worker_options_request() {
     // create a user worker pool
-    let user_worker_msgs_tx = create_user_worker_pool(None).await.unwrap();
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
     let opts = WorkerContextInitOpts {
         service_path: "./test_cases/main".into(),
         no_module_cache: false,
@@ -21,6 +21,7 @@ async fn test_main_worker_options_request() {
         maybe_module_code: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
         }),
     };
     let worker_req_tx = create_worker(opts).await.unwrap();
@@ -51,7 +52,7 @@ async fn test_main_worker_options_request() {
 #[tokio::test]
 async fn test_main_worker_post_request() {
     // create a user worker pool
-    let user_worker_msgs_tx = create_user_worker_pool(None).await.unwrap();
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
     let opts = WorkerContextInitOpts {
         service_path: "./test_cases/main".into(),
         no_module_cache: false,
@@ -63,6 +64,7 @@ async fn test_main_worker_post_request() {
         maybe_module_code: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
         }),
     };
     let worker_req_tx = create_worker(opts).await.unwrap();
@@ -97,7 +99,7 @@ async fn test_main_worker_post_request() {
 #[tokio::test]
 async fn test_main_worker_boot_error() {
     // create a user worker pool
-    let user_worker_msgs_tx = create_user_worker_pool(None).await.unwrap();
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
     let opts = WorkerContextInitOpts {
         service_path: "./test_cases/main".into(),
         no_module_cache: false,
@@ -109,6 +111,7 @@ async fn test_main_worker_boot_error() {
         maybe_module_code: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
         }),
     };
     let result = create_worker(opts).await;
@@ -120,7 +123,7 @@ async fn test_main_worker_boot_error() {
 #[tokio::test]
 async fn test_main_worker_abort_request() {
     // create a user worker pool
-    let user_worker_msgs_tx = create_user_worker_pool(None).await.unwrap();
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
     let opts = WorkerContextInitOpts {
         service_path: "./test_cases/main_with_abort".into(),
         no_module_cache: false,
@@ -132,6 +135,7 @@ async fn test_main_worker_abort_request() {
         maybe_module_code: None,
         conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
             worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
         }),
     };
     let worker_req_tx = create_worker(opts).await.unwrap();
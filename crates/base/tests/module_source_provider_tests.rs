@@ -0,0 +1,66 @@
+use base::embed::EdgeRuntime;
+use base::js_worker::module_loader::{ModuleSourceFile, ModuleSourceKind, ModuleSourceProvider};
+use deno_ast::MediaType;
+use deno_core::error::AnyError;
+use deno_core::futures::future::LocalBoxFuture;
+use deno_core::futures::FutureExt;
+use deno_core::ModuleSpecifier;
+use hyper::{Body, Request};
+use sb_worker_context::manifest::ServiceEntry;
+use std::collections::HashMap;
+
+// Serves modules from memory, as an artifact store would
+struct InMemoryProvider {
+    modules: HashMap<String, &'static str>,
+}
+
+impl ModuleSourceProvider for InMemoryProvider {
+    fn fetch(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> LocalBoxFuture<'static, Result<ModuleSourceFile, AnyError>> {
+        let result = self
+            .modules
+            .get(specifier.path())
+            .map(|source| ModuleSourceFile {
+                specifier: specifier.clone(),
+                media_type: MediaType::from_specifier(specifier),
+                source: (*source).into(),
+                loaded_from: ModuleSourceKind::Provider,
+            })
+            .ok_or_else(|| anyhow::anyhow!("module not found: {}", specifier));
+        async move { result }.boxed_local()
+    }
+}
+
+#[tokio::test]
+async fn test_custom_module_source_provider() {
+    let provider = InMemoryProvider {
+        modules: HashMap::from([
+            (
+                "/artifacts/hello/index.ts".to_string(),
+                r#"import { greeting } from "./greeting.ts";
+                   Deno.serve(() => new Response(greeting));"#,
+            ),
+            (
+                "/artifacts/hello/greeting.ts".to_string(),
+                r#"export const greeting: string = "hello from the artifact store";"#,
+            ),
+        ]),
+    };
+
+    let rt = EdgeRuntime::builder()
+        .service("hello", ServiceEntry::new("/artifacts/hello/index.ts"))
+        .module_source_provider(provider)
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/hello")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"hello from the artifact store");
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use base::server::ServerFlags;
+use common::{get, json_response};
+use deno_core::serde_json::json;
+use sb_worker_context::manifest::ServiceEntry;
+
+// the node identity is set once per process, both tests use the same one
+async fn node_identity_runtime() -> EdgeRuntime {
+    EdgeRuntime::builder()
+        .service(
+            "node_identity",
+            ServiceEntry::new("./test_cases/node_identity"),
+        )
+        .flags(ServerFlags {
+            region: Some("eu-west-1".to_string()),
+            zone: Some("eu-west-1b".to_string()),
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_served_by_header() {
+    let rt = node_identity_runtime().await;
+
+    let res = rt
+        .handle(get("http://localhost/node_identity"))
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers().get("x-served-by").unwrap(),
+        "eu-west-1/eu-west-1b"
+    );
+}
+
+#[tokio::test]
+async fn test_node_identity_context() {
+    let rt = node_identity_runtime().await;
+
+    let (status, body) = json_response(&rt, get("http://localhost/node_identity")).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["region"], "eu-west-1");
+    assert_eq!(body["zone"], "eu-west-1b");
+    // no geoip database, and no metadata from a main worker
+    assert_eq!(body["geo"], json!(null));
+    assert_eq!(body["metadata"], json!(null));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{get, json_body, main_worker, send};
+use deno_core::serde_json::json;
+
+#[tokio::test]
+async fn test_user_worker_listens_on_granted_socket() {
+    let worker_req_tx = main_worker("./test_cases/raw_listen/main").await;
+
+    let res = send(&worker_req_tx, get("/")).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let body = json_body(res).await;
+    assert_eq!(body["addr"]["transport"], "tcp");
+    assert_eq!(body["addr"]["hostname"], "127.0.0.1");
+    // granted port 0, the worker got an ephemeral one
+    assert!(body["addr"]["port"].as_u64().is_some_and(|port| port > 0));
+    assert_eq!(body["echo"], "ping");
+}
+
+#[tokio::test]
+async fn test_user_worker_listen_not_granted() {
+    let worker_req_tx = main_worker("./test_cases/raw_listen/main").await;
+
+    // only the granted port
+    let res = send(&worker_req_tx, get("/denied")).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let body = json_body(res).await;
+    assert_eq!(body, json!({ "denied": "PermissionDenied" }));
+}
@@ -0,0 +1,67 @@
+use base::rt_worker::worker_ctx::{create_user_worker_pool, create_worker};
+use deno_core::serde_json::{self, json, Value};
+use hyper::{Body, Request, Response};
+use sb_worker_context::essentials::{
+    MainWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
+};
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn test_request_headers_are_read_lazily() {
+    let user_worker_msgs_tx = create_user_worker_pool(None, None).await.unwrap();
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/request_headers".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::from([("FOO".to_string(), "bar".to_string())]),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::MainWorker(MainWorkerRuntimeOpts {
+            worker_pool_tx: user_worker_msgs_tx,
+            manifest: None,
+        }),
+    };
+    let worker_req_tx = create_worker(opts).await.unwrap();
+    let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/")
+        .header("accept", "text/plain")
+        .header("x-custom", "a")
+        .header("x-custom", "b")
+        .body(Body::from("hello"))
+        .unwrap();
+    let _ = worker_req_tx.send(WorkerRequestMsg { req, res_tx });
+
+    let res = res_rx.await.unwrap().unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        body,
+        json!({
+            "single": {
+                "accept": "text/plain",
+                "custom": "a, b",
+                "missing": null,
+                "hasCustom": true,
+                // set by hyper on the way to the worker
+                "length": "5",
+            },
+            "many": ["text/plain", "a, b", null],
+            // `undefined` turns into `null` in JSON arrays
+            "envs": ["bar", null],
+            "immutable": true,
+            "all": { "accept": "text/plain", "custom": "a, b" },
+            "copied": "a, b",
+            // the request's own header list has them too
+            "cloned": "a, b",
+            "body": "hello",
+        })
+    );
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use common::{get, json_body, main_worker, send};
+use deno_core::serde_json::json;
+
+#[tokio::test]
+async fn test_request_metadata_reaches_user_worker() {
+    let worker_req_tx = main_worker("./test_cases/request_metadata/main").await;
+
+    let res = send(&worker_req_tx, get("/echo")).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let body = json_body(res).await;
+
+    assert_eq!(
+        body["metadata"],
+        json!({ "sub": "user-1", "role": "authenticated" })
+    );
+    // passed next to the request, not as a header
+    assert_eq!(body["header"], json!(null));
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::{get, main_worker, send};
+
+#[tokio::test]
+async fn test_response_hook_sets_headers() {
+    let worker_req_tx = main_worker("./test_cases/response_hooks/main").await;
+
+    let res = send(&worker_req_tx, get("/ok")).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers().get("x-frame-options").unwrap(), "DENY");
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "ok");
+}
+
+#[tokio::test]
+async fn test_response_hook_rewrites_buffered_error() {
+    let worker_req_tx = main_worker("./test_cases/response_hooks/main").await;
+
+    let res = send(&worker_req_tx, get("/missing")).await;
+    assert_eq!(res.status().as_u16(), 404);
+    // the headers set by the earlier hook are kept
+    assert_eq!(res.headers().get("x-frame-options").unwrap(), "DENY");
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "custom not found");
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::{json_response, post};
+use deno_core::serde_json::{json, Value};
+use sb_worker_context::manifest::ServiceEntry;
+
+const UPLOAD: &str = "sku,qty\nA-1,3\n\"B,2\",10\n";
+
+async fn import(uri: &str, body: String) -> (u16, Value) {
+    let rt = EdgeRuntime::builder()
+        .service("import", ServiceEntry::new("./test_cases/tabular"))
+        .build()
+        .await
+        .unwrap();
+
+    json_response(&rt, post(uri, body)).await
+}
+
+#[tokio::test]
+async fn test_csv_to_ndjson() {
+    let (status, body) = import("http://localhost/import", UPLOAD.to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "records": [
+                { "sku": "A-1", "qty": "3" },
+                { "sku": "B,2", "qty": "10" },
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_ndjson_to_csv() {
+    let (status, body) = import("http://localhost/import/csv", UPLOAD.to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "csv": "qty,sku\n3,A-1\n10,\"B,2\"\n" }));
+}
+
+#[tokio::test]
+async fn test_csv_record_too_large() {
+    let body = format!("a,b\n{}", "x".repeat(100));
+    let (status, body) = import("http://localhost/import/too_large", body).await;
+    assert_eq!(status, 400);
+    assert_eq!(body, json!({ "error": "RangeError" }));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::get;
+use sb_worker_context::manifest::ServiceEntry;
+
+async fn render(uri: &str) -> String {
+    let rt = EdgeRuntime::builder()
+        .service("templates", ServiceEntry::new("./test_cases/templates"))
+        .build()
+        .await
+        .unwrap();
+
+    let res = rt.handle(get(uri)).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers().get("content-type").unwrap(), "text/html");
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap().trim().to_string()
+}
+
+#[tokio::test]
+async fn test_render_service_template() {
+    assert_eq!(
+        render("http://localhost/templates").await,
+        "<title>Hello</title><p>Hello world</p>"
+    );
+}
+
+#[tokio::test]
+async fn test_render_escapes_values() {
+    assert_eq!(
+        render("http://localhost/templates?name=%3Cscript%3E").await,
+        "<title>Hello</title><p>Hello &lt;script&gt;</p>"
+    );
+}
@@ -0,0 +1,111 @@
+use base::coverage::CoverageReport;
+use base::test_runner::{run_test_file, TestRunOpts, TestStatus};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[tokio::test]
+async fn test_runner_reports_results() {
+    let opts = TestRunOpts {
+        service_path: PathBuf::from("./test_cases/test_runner"),
+        ..Default::default()
+    };
+
+    let report = run_test_file(
+        &opts,
+        &PathBuf::from("./test_cases/test_runner/lib/math_test.ts"),
+    )
+    .await;
+    assert!(report.error.is_none());
+    let statuses: Vec<(String, TestStatus)> = report
+        .results
+        .into_iter()
+        .map(|r| (r.name, r.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("adds numbers".to_string(), TestStatus::Ok),
+            ("runs with the edge runtime".to_string(), TestStatus::Ok),
+            ("ignored test".to_string(), TestStatus::Ignored),
+        ]
+    );
+
+    let report = run_test_file(
+        &opts,
+        &PathBuf::from("./test_cases/test_runner/failing_test.ts"),
+    )
+    .await;
+    assert_eq!(report.results[0].status, TestStatus::Failed);
+    assert!(report.results[0].error.as_ref().unwrap().contains("boom"));
+}
+
+#[tokio::test]
+async fn test_runner_virtual_clock() {
+    let opts = TestRunOpts {
+        service_path: PathBuf::from("./test_cases/test_clock"),
+        ..Default::default()
+    };
+
+    let report = run_test_file(
+        &opts,
+        &PathBuf::from("./test_cases/test_clock/clock_test.ts"),
+    )
+    .await;
+    assert!(report.error.is_none(), "{:?}", report.error);
+    for result in report.results {
+        assert_eq!(result.status, TestStatus::Ok, "{:?}", result.error);
+    }
+}
+
+#[tokio::test]
+async fn test_runner_module_mocks() {
+    let opts = TestRunOpts {
+        service_path: PathBuf::from("./test_cases/module_mocks"),
+        mocks: HashMap::from([(
+            "https://example.com/greeter.ts".to_string(),
+            PathBuf::from("./test_cases/module_mocks/mocks/greeter.ts"),
+        )]),
+        ..Default::default()
+    };
+
+    let report = run_test_file(
+        &opts,
+        &PathBuf::from("./test_cases/module_mocks/greeter_test.ts"),
+    )
+    .await;
+    assert!(report.error.is_none(), "{:?}", report.error);
+    assert_eq!(
+        report.results[0].status,
+        TestStatus::Ok,
+        "{:?}",
+        report.results[0].error
+    );
+}
+
+#[tokio::test]
+async fn test_runner_coverage() {
+    let opts = TestRunOpts {
+        service_path: PathBuf::from("./test_cases/test_runner"),
+        coverage_dir: Some(PathBuf::from("./coverage")),
+        ..Default::default()
+    };
+
+    let report = run_test_file(
+        &opts,
+        &PathBuf::from("./test_cases/test_runner/lib/math_test.ts"),
+    )
+    .await;
+    assert!(report.error.is_none(), "{:?}", report.error);
+
+    let mut coverage = CoverageReport::default();
+    for script in &report.coverage {
+        coverage
+            .add(script, |path| path.ends_with("lib/math.ts"))
+            .unwrap();
+    }
+    let lcov = coverage.to_lcov();
+    // mapped back to the TypeScript source
+    assert!(lcov.contains("FN:1,add\n"), "{}", lcov);
+    assert!(lcov.contains("FNDA:1,add\n"), "{}", lcov);
+    assert!(lcov.contains("DA:2,1\n"), "{}", lcov);
+}
@@ -0,0 +1,22 @@
+use base::test_runtime::TestRuntime;
+use event_worker::events::WorkerEvents;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_runtime_serves_requests_and_collects_events() {
+    let mut rt = TestRuntime::builder("./test_cases/main")
+        .build()
+        .await
+        .unwrap();
+
+    let resp = rt.get("/readable-stream-resp").await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.text().await.unwrap(), "Hello world from streams");
+
+    let boot = rt
+        .wait_for_event(Duration::from_secs(10), |e| {
+            matches!(e.event, WorkerEvents::Boot(_))
+        })
+        .await;
+    assert!(boot.is_some());
+}
@@ -0,0 +1,23 @@
+use base::type_check::{type_check, DiagnosticCategory, TypeCheckOpts};
+use std::path::PathBuf;
+
+#[tokio::test]
+async fn test_type_check_reports_errors() {
+    let report = type_check(TypeCheckOpts {
+        service_path: PathBuf::from("./test_cases/type_check"),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(report.error_count(), 1);
+    let diagnostic = &report.diagnostics[0];
+    assert_eq!(diagnostic.code, 2322);
+    assert_eq!(diagnostic.category, DiagnosticCategory::Error);
+    assert!(diagnostic
+        .specifier
+        .as_ref()
+        .unwrap()
+        .ends_with("type_check/index.ts"));
+    assert_eq!((diagnostic.line, diagnostic.column), (Some(3), Some(7)));
+}
@@ -0,0 +1,57 @@
+use base::embed::EdgeRuntime;
+use hyper::{Body, Request};
+use sb_worker_context::manifest::{ServiceEntry, ServiceLimits};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_web_worker_shares_array_buffer() {
+    let rt = EdgeRuntime::builder()
+        .service("web_worker", ServiceEntry::new("./test_cases/web_worker"))
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("http://localhost/web_worker")
+        .body(Body::from("[1, 2, 3, 4]"))
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        r#"{"reply":"done","sum":10}"#
+    );
+}
+
+#[tokio::test]
+async fn test_web_workers_share_memory_limit() {
+    let rt = EdgeRuntime::builder()
+        .service(
+            "web_worker_memory",
+            ServiceEntry {
+                limits: ServiceLimits {
+                    memory_limit_mb: Some(40),
+                    ..Default::default()
+                },
+                ..ServiceEntry::new("./test_cases/web_worker_memory")
+            },
+        )
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/web_worker_memory")
+        .body(Body::empty())
+        .unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(30), rt.handle(req))
+        .await
+        .unwrap();
+    // each web worker is under the limit, both together are not
+    if let Ok(res) = result {
+        assert!(!res.status().is_success());
+    }
+}
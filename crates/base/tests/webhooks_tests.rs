@@ -0,0 +1,51 @@
+mod common;
+
+use base::embed::EdgeRuntime;
+use common::json_response;
+use deno_core::serde_json::json;
+use hyper::{Body, Request};
+use ring::hmac;
+use sb_worker_context::manifest::ServiceEntry;
+
+const PAYLOAD: &str = r#"{"action":"opened"}"#;
+
+async fn webhooks_runtime() -> EdgeRuntime {
+    EdgeRuntime::builder()
+        .service("webhooks", ServiceEntry::new("./test_cases/webhooks"))
+        .build()
+        .await
+        .unwrap()
+}
+
+fn github_request(signature: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri("http://localhost/webhooks")
+        .header("x-hub-signature-256", format!("sha256={}", signature))
+        .body(Body::from(PAYLOAD))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_verify_github_webhook() {
+    let rt = webhooks_runtime().await;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, b"gh-secret");
+    let signature: String = hmac::sign(&key, PAYLOAD.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let (status, body) = json_response(&rt, github_request(&signature)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "action": "opened" }));
+}
+
+#[tokio::test]
+async fn test_reject_github_webhook_signature() {
+    let rt = webhooks_runtime().await;
+
+    let (status, body) = json_response(&rt, github_request(&"00".repeat(32))).await;
+    assert_eq!(status, 401);
+    assert_eq!(body, json!({ "reason": "no matching signature" }));
+}
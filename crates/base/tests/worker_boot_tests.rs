@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
 use base::rt_worker::worker_ctx::create_worker;
+use hyper::{Body, Request, Response};
 use sb_worker_context::essentials::{
-    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRuntimeOpts,
+    UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
 };
+use tokio::sync::oneshot;
 
 #[tokio::test]
 async fn test_worker_boot_invalid_imports() {
@@ -24,3 +26,42 @@ async fn test_worker_boot_invalid_imports() {
     assert!(result.is_err());
     assert_eq!(result.unwrap_err().to_string(), "worker boot error");
 }
+
+#[tokio::test]
+async fn test_worker_hung_at_top_level() {
+    // only the watchdog can stop it, the CPU limit is out of reach
+    let user_rt_opts = UserWorkerRuntimeOpts {
+        hang_threshold_ms: 200,
+        cpu_time_threshold_ms: 60 * 1000,
+        ..Default::default()
+    };
+    let opts = WorkerContextInitOpts {
+        service_path: "./test_cases/infinite_loop".into(),
+        no_module_cache: false,
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        events_rx: None,
+        maybe_eszip: None,
+        maybe_entrypoint: None,
+        maybe_module_code: None,
+        conf: WorkerRuntimeOpts::UserWorker(user_rt_opts),
+    };
+    let worker_req_tx = create_worker(opts).await.unwrap();
+    let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
+
+    let req = Request::builder()
+        .uri("/")
+        .method("GET")
+        .body(Body::empty())
+        .unwrap();
+    let _ = worker_req_tx.send(WorkerRequestMsg { req, res_tx });
+
+    let res = tokio::time::timeout(std::time::Duration::from_secs(10), res_rx)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 503);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("EVENT_LOOP_HANG"));
+}
@@ -0,0 +1,84 @@
+use base::embed::EdgeRuntime;
+use base::rt_worker::hooks::{RequestOutcome, WorkerInfo, WorkerKind, WorkerLifecycleHooks};
+use hyper::{Body, Request, Response, StatusCode};
+use sb_worker_context::manifest::ServiceEntry;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default, Clone)]
+struct RecordingHooks {
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl WorkerLifecycleHooks for RecordingHooks {
+    fn on_boot(&self, worker: &WorkerInfo, _boot_time: Duration) {
+        if worker.kind == WorkerKind::User {
+            assert!(worker.execution_id.is_some());
+            assert!(worker.limits.is_some());
+            self.calls.lock().unwrap().push("boot".to_string());
+        }
+    }
+
+    fn before_request(
+        &self,
+        worker: &WorkerInfo,
+        req: &mut Request<Body>,
+    ) -> Option<Response<Body>> {
+        if worker.kind != WorkerKind::User {
+            return None;
+        }
+        self.calls.lock().unwrap().push("before".to_string());
+        if req.headers().contains_key("x-deny") {
+            return Some(
+                Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            );
+        }
+        None
+    }
+
+    fn after_request(&self, worker: &WorkerInfo, outcome: RequestOutcome) {
+        if worker.kind == WorkerKind::User {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {:?}", outcome.status));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_worker_lifecycle_hooks() {
+    let hooks = RecordingHooks::default();
+    let rt = EdgeRuntime::builder()
+        .service(
+            "stream",
+            ServiceEntry::new("./test_cases/readable-stream-resp"),
+        )
+        .worker_hooks(hooks.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let req = Request::builder()
+        .uri("http://localhost/stream")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = Request::builder()
+        .uri("http://localhost/stream")
+        .header("x-deny", "1")
+        .body(Body::empty())
+        .unwrap();
+    let res = rt.handle(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    assert_eq!(
+        *hooks.calls.lock().unwrap(),
+        vec!["boot", "before", "after Some(200)", "before"]
+    );
+}
@@ -1,13 +1,25 @@
 mod logger;
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+use base::bench::{run_bench, BenchOpts};
+use base::cluster::{is_cluster_member, run_cluster};
 use base::commands::start_server;
-use base::server::WorkerEntrypoints;
+use base::doctor::{doctor, DoctorOpts};
+use base::module_cache::{
+    cache_doctor_report, cache_info, cache_ls, deps_dir, format_cache_entry, format_cache_info,
+};
+use base::replay::{replay, RecordedRequest, ReplayOpts};
+use base::rt_worker::process_worker::{run_worker_host, WORKER_HOST_COMMAND};
+use base::server::{ServerFlags, WorkerEntrypoints};
+use base::test_runner::{parse_module_mock, run_tests, TestRunOpts};
+use base::type_check::{type_check, TypeCheckOpts};
 use base::utils::graph_util::{create_eszip_from_graph, create_module_graph_from_path};
+use base::vendor::{vendor, VendorOpts};
 use clap::builder::FalseyValueParser;
 use clap::{arg, crate_version, value_parser, ArgAction, Command};
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 fn cli() -> Command {
     Command::new("edge-runtime")
@@ -46,6 +58,115 @@ fn cli() -> Command {
                 .arg(arg!(--"event-worker" <Path> "Path to event worker directory"))
                 .arg(arg!(--"main-entrypoint" <Path> "Path to entrypoint in main service (only for eszips)"))
                 .arg(arg!(--"events-entrypoint" <Path> "Path to entrypoint in events worker (only for eszips)"))
+                .arg(arg!(--"manifest" <Path> "Path to deployment manifest (functions.json)"))
+                .arg(arg!(--"admin-port" <PORT> "Port for the admin API (bound on localhost)").value_parser(value_parser!(u16)))
+                .arg(arg!(--"usage-sink" <SINK> "File path or HTTP endpoint to flush usage records to"))
+                .arg(arg!(--"usage-flush-interval" <SECONDS> "Interval between usage flushes").value_parser(value_parser!(u64)))
+                .arg(arg!(--"request-deadline-ms" <MS> "Deadline for requests without an x-deadline-ms header").value_parser(value_parser!(u64)))
+                .arg(arg!(--"fallback-services-dir" <DIR> "Services served by the fallback router while the main worker is down, with a fallback.json listing the publicServices that don't need a JWT"))
+                .arg(arg!(--"crash-report-dir" <DIR> "Directory to write crash reports of panicking workers to"))
+                .arg(arg!(--"fault-injection" <Path> "Path to a fault injection config, for chaos testing"))
+                .arg(arg!(--"record-dir" <DIR> "Directory to record requests sent with an x-edge-runtime-record header set to $EDGE_RUNTIME_RECORD_TOKEN to"))
+                .arg(arg!(--"feature-flags" <SOURCE> "File path or HTTP endpoint to load feature flags from"))
+                .arg(arg!(--"feature-flags-refresh" <SECONDS> "Interval between feature flag refreshes").value_parser(value_parser!(u64)))
+                .arg(arg!(--"broadcast-redis" <URL> "Redis URL to relay BroadcastChannel messages to other runtime instances through"))
+                .arg(arg!(--"alarms-db" <PATH> "Path to the sqlite database persisting alarms scheduled by functions"))
+                .arg(arg!(--"key-store" <PATH> "Path to the config of signing keys available to functions"))
+                .arg(arg!(--"mail-config" <PATH> "Path to the config of SMTP accounts functions send mail with"))
+                .arg(arg!(--"redis-config" <PATH> "Path to the config of Redis servers shared by functions"))
+                .arg(arg!(--"ai-config" <PATH> "Path to the config of AI providers and token quotas of functions"))
+                .arg(arg!(--"onnx-config" <PATH> "Path to the config of ONNX models functions run on the node"))
+                .arg(arg!(--"embeddings-cache-dir" <DIR> "Enables EdgeRuntime.ai.Session, downloading built-in embedding models to this directory"))
+                .arg(arg!(--"embeddings-max-concurrency" <N> "Maximum number of embedding batches run at once").value_parser(value_parser!(usize)))
+                .arg(arg!(--"webhooks-db" <PATH> "Path to the sqlite database queueing webhooks sent by functions"))
+                .arg(arg!(--"image-memory-limit" <MB> "Enables EdgeRuntime.images, capping the memory of each image operation").value_parser(value_parser!(u64)))
+                .arg(arg!(--"geoip-db" <PATH> "Path to a MaxMind database to enrich requests with geo data from (can be repeated)").action(ArgAction::Append))
+                .arg(arg!(--"region" <REGION> "Region of this node, stamped on responses (x-served-by), events and metrics"))
+                .arg(arg!(--"zone" <ZONE> "Zone of this node within its region"))
+                .arg(arg!(--"worker-cgroups" "Place user workers in per-service cgroups (cgroup v2, linux only)").action(ArgAction::SetTrue))
+                .arg(arg!(--"worker-cgroup-root" <DIR> "Cgroup directory to create the service cgroups in (defaults to the runtime's own cgroup)"))
+                .arg(arg!(--"sandbox-workers" "Restrict user workers with seccomp and landlock (linux only)").action(ArgAction::SetTrue))
+                .arg(arg!(--"process-isolation" "Run every user worker in a child process of its own").action(ArgAction::SetTrue))
+                .arg(arg!(--"kubernetes" "Drain connections on SIGTERM within the termination grace period, and take the node and pod from the downward API (NODE_NAME, POD_NAME, POD_NAMESPACE, TOPOLOGY_ZONE, TOPOLOGY_REGION)").action(ArgAction::SetTrue))
+                .arg(arg!(--"prestop-delay" <SECONDS> "Time new connections are still accepted once the pod is terminating, for it to leave the endpoints of its services (default 5)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"termination-grace-period" <SECONDS> "terminationGracePeriodSeconds of the pod, open connections are drained before it ends (default 30)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"readiness-port" <PORT> "Port of the readiness probe (GET /readyz) and preStop hook (GET /prestop)").value_parser(value_parser!(u16)))
+                .arg(arg!(--"liveness-port" <PORT> "Port of the liveness probe (GET /livez), may be the readiness port").value_parser(value_parser!(u16)))
+                .arg(arg!(--"cluster" <PROCESSES> "Run this many runtime processes sharing the port (SO_REUSEPORT), supervised by this one").value_parser(value_parser!(usize)))
+                .arg(arg!(--"http3-port" <PORT> "Port of an experimental HTTP/3 (QUIC) listener").value_parser(value_parser!(u16)))
+                .arg(arg!(--"http3-cert" <PATH> "Certificate (PEM) of the HTTP/3 listener"))
+                .arg(arg!(--"http3-key" <PATH> "Private key (PEM) of the HTTP/3 listener"))
+                .arg(arg!(--"drain-timeout" <SECONDS> "Time open connections get to finish after handing the listener over to a new process (on SIGUSR2)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"redaction-config" <PATH> "Path to the rules (patterns and field names) redacting sensitive data from worker logs and events"))
+                .arg(arg!(--"event-filters" <PATH> "Path to the expressions selecting the events forwarded to the events worker"))
+                .arg(arg!(--"gc-config" <PATH> "Path to the V8 heap sizes and idle garbage collection of the main, events and user workers"))
+                .arg(arg!(--"registry-mirrors" <PATH> "Path to the mirrors of module registries, tried in order when the origin fails"))
+                .arg(arg!(--"discovery-config" <PATH> "Path to the Consul or etcd endpoint this node registers itself (and the services it serves) with"))
+                .arg(arg!(--"std-bundle" <PATH> "Path to an eszip of common remote modules served in place of the network"))
+                .arg(arg!(--"embedded-std-bundle" "Serve common remote modules from the bundle embedded in the binary").action(ArgAction::SetTrue))
+                .arg(arg!(--"std-bundle-allow" <PREFIX> "Url prefix of the modules served from the std bundle (can be repeated, https://deno.land/std by default)").action(ArgAction::Append))
+                .arg(arg!(--"event-quota" <EVENTS> "Logs, body tees and webhook deliveries each service may emit per minute, the rest are dropped").value_parser(value_parser!(u64)))
+                .arg(arg!(--"log-quota" <BYTES> "Bytes of logs each service may emit per minute, the rest are dropped").value_parser(value_parser!(u64)))
+                .arg(arg!(--"adaptive-concurrency" "Shed requests (with a 503) over a concurrency limit adapted to the latency of responses").action(ArgAction::SetTrue))
+                .arg(arg!(--"max-concurrency" <REQUESTS> "Upper bound of the adaptive concurrency limit (default 1000)").value_parser(value_parser!(usize)))
+                .arg(arg!(--"slow-request-threshold" <MS> "Report requests to user workers slower than this with a SlowRequest event").value_parser(value_parser!(u64)))
+                .arg(arg!(--"idempotency-ttl" <SECS> "Return the stored response to the retries of a request with an Idempotency-Key for this long").value_parser(value_parser!(u64)))
+                .arg(arg!(--"idempotency-max-entries" <N> "Maximum number of responses stored for idempotency keys (default 10000)").value_parser(value_parser!(usize)))
+                .arg(arg!(--"idempotency-max-mb" <MB> "Maximum size of the responses stored for idempotency keys (default 64)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"module-fetch-max-attempts" <N> "Attempts of a module download failing with transient errors (default 4)").value_parser(value_parser!(u32)))
+                .arg(arg!(--"module-fetch-backoff" <MS> "Delay before retrying a failed module download, doubled on every retry (default 100)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"module-fetch-retry-budget" <N> "Retries of module downloads allowed per worker boot (default 10)").value_parser(value_parser!(u32)))
+                .arg(arg!(--"module-scanner" <COMMAND> "Command scanning every downloaded module (on stdin, its specifier as last argument) before it's cached, a failure rejects the module"))
+                .arg(arg!(--"module-scanner-arg" <ARG> "Argument of the module scanner command (can be repeated)").action(ArgAction::Append).allow_hyphen_values(true))
+                .arg(arg!(--"module-scanner-timeout" <MS> "Time the module scanner may take on a module before it's rejected (default 10000)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"reload-header-trusted-proxy" <IP> "Peer whose requests may ask for their worker's modules to be fetched again with the x-edge-runtime-reload-modules header (can be repeated)").action(ArgAction::Append))
+                .arg(arg!(--"no-env-files" "Ignore the .env and .env.local files of services (for production, where secrets are given by the platform)").action(ArgAction::SetTrue))
+                .arg(arg!(--"memory-coordinator" "Evict idle workers, nudge the others to collect garbage and stop booting new ones when the node runs low on memory").action(ArgAction::SetTrue))
+                .arg(arg!(--"memory-limit" <MB> "Memory (RSS) the runtime may use, the memory.max of its cgroup by default").value_parser(value_parser!(u64)))
+                .arg(arg!(--"memory-high-ratio" <RATIO> "Share of the memory limit past which idle workers are evicted (default 0.8)").value_parser(value_parser!(f64)))
+                .arg(arg!(--"memory-critical-ratio" <RATIO> "Share of the memory limit past which new workers are rejected with a 503 (default 0.95)").value_parser(value_parser!(f64)))
+                .arg(arg!(--"events-spill-dir" <DIR> "Directory events are spilled to (and replayed from) while the events worker can't keep up, instead of dropping them"))
+                .arg(arg!(--"events-spill-max" <MB> "Disk space the spilled events may use, the oldest are dropped beyond it (default 256)").value_parser(value_parser!(u64)))
+                .arg(arg!(--"telemetry-sample-rate" <RATE> "Share of requests (0 to 1) timeline spans and body tees are collected for, for services without a rate of their own").value_parser(value_parser!(f64)))
+                .arg(arg!(--"trace-timelines" <SPANS> "Record worker timelines (boot, module fetches, requests), keeping the last SPANS spans for GET /_admin/timelines").value_parser(value_parser!(usize)))
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Checks the configuration `start` would be run with and boots every service once, reporting what would fail")
+                .arg(arg!(-i --ip <HOST> "Host IP address to listen on").default_value("0.0.0.0"))
+                .arg(
+                    arg!(-p --port <PORT> "Port to listen on")
+                        .default_value("9000")
+                        .value_parser(value_parser!(u16)),
+                )
+                .arg(arg!(--"main-service" <DIR> "Path to main service directory").default_value("examples/main"))
+                .arg(arg!(--"import-map" <Path> "Path to import map file"))
+                .arg(arg!(--"manifest" <Path> "Path to deployment manifest (functions.json)"))
+                .arg(arg!(--"admin-port" <PORT> "Port for the admin API (bound on localhost)").value_parser(value_parser!(u16)))
+                .arg(arg!(--"fault-injection" <Path> "Path to a fault injection config, for chaos testing"))
+                .arg(arg!(--"key-store" <PATH> "Path to the config of signing keys available to functions"))
+                .arg(arg!(--"mail-config" <PATH> "Path to the config of SMTP accounts functions send mail with"))
+                .arg(arg!(--"redis-config" <PATH> "Path to the config of Redis servers shared by functions"))
+                .arg(arg!(--"ai-config" <PATH> "Path to the config of AI providers and token quotas of functions"))
+                .arg(arg!(--"onnx-config" <PATH> "Path to the config of ONNX models functions run on the node"))
+                .arg(arg!(--"redaction-config" <PATH> "Path to the rules (patterns and field names) redacting sensitive data from worker logs and events"))
+                .arg(arg!(--"event-filters" <PATH> "Path to the expressions selecting the events forwarded to the events worker"))
+                .arg(arg!(--"gc-config" <PATH> "Path to the V8 heap sizes and idle garbage collection of the main, events and user workers"))
+                .arg(arg!(--"registry-mirrors" <PATH> "Path to the mirrors of module registries, tried in order when the origin fails"))
+                .arg(arg!(--"discovery-config" <PATH> "Path to the Consul or etcd endpoint this node registers itself (and the services it serves) with"))
+                .arg(arg!(--"geoip-db" <PATH> "Path to a MaxMind database to enrich requests with geo data from (can be repeated)").action(ArgAction::Append))
+                .arg(arg!(--"sandbox-workers" "Boot the services in workers restricted with seccomp and landlock (linux only)").action(ArgAction::SetTrue))
+                .arg(arg!(--"http3-port" <PORT> "Port of an experimental HTTP/3 (QUIC) listener").value_parser(value_parser!(u16)))
+                .arg(arg!(--"http3-cert" <PATH> "Certificate (PEM) of the HTTP/3 listener"))
+                .arg(arg!(--"http3-key" <PATH> "Private key (PEM) of the HTTP/3 listener"))
+                .arg(arg!(--"skip-boot" "Only check the configuration, without booting the services").action(ArgAction::SetTrue))
+                .arg(arg!(--"json" "Print the report as JSON").action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new(WORKER_HOST_COMMAND)
+                .about("Runs a single user worker for a runtime using process isolation")
+                .hide(true)
+                .arg(arg!(--"socket" <PATH> "Unix socket to serve the worker on").required(true))
         )
         .subcommand(
             Command::new("bundle")
@@ -53,6 +174,69 @@ fn cli() -> Command {
                 .arg(arg!(--"output" <DIR> "Path to output eszip file").default_value("bin.eszip"))
                 .arg(arg!(--"entrypoint" <Path> "Path to entrypoint to bundle as an eszip").required(true))
         )
+        .subcommand(
+            Command::new("test")
+                .about("Runs the *_test.ts files of a service inside the edge runtime")
+                .arg(arg!([DIR] "Path to the service directory").default_value("."))
+                .arg(arg!(--"import-map" <Path> "Path to import map file"))
+                .arg(arg!(--"disable-module-cache" "Disable using module cache").default_value("false").value_parser(FalseyValueParser::new()))
+                .arg(arg!(--"filter" <NAME> "Only run tests whose name contains this string"))
+                .arg(arg!(--"coverage" <DIR> "Collect the coverage of the service's modules, written to DIR/lcov.info"))
+                .arg(arg!(--"mock" <MOCK> "Load a local file in place of a module, as SPECIFIER=PATH (can be repeated)").action(ArgAction::Append))
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Boots a service locally and measures its cold start, latency and resource usage under load")
+                .arg(arg!([DIR] "Path to the service directory").default_value("."))
+                .arg(arg!(--"import-map" <Path> "Path to import map file"))
+                .arg(arg!(--"disable-module-cache" "Disable using module cache").default_value("false").value_parser(FalseyValueParser::new()))
+                .arg(arg!(--"requests" <N> "Requests to send after the first one").default_value("1000").value_parser(value_parser!(usize)))
+                .arg(arg!(--"concurrency" <N> "Requests in flight at a time").default_value("10").value_parser(value_parser!(usize)))
+                .arg(arg!(--"method" <METHOD> "Method of the requests").default_value("GET"))
+                .arg(arg!(--"path" <PATH> "Path (and query) of the requests").default_value("/"))
+                .arg(arg!(--"header" <HEADER> "Header sent with the requests, as NAME:VALUE (can be repeated)").action(ArgAction::Append))
+                .arg(arg!(--"body" <FILE> "File sent as the body of the requests"))
+                .arg(arg!(--"json" "Print the report as JSON").action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Re-executes a recorded request in a local main worker")
+                .arg(arg!(<FILE> "Path to the recorded request"))
+                .arg(arg!(--"main-service" <DIR> "Path to main service directory or eszip (defaults to the recorded one)"))
+                .arg(arg!(--"main-entrypoint" <Path> "Path to entrypoint in main service (only for eszips)"))
+                .arg(arg!(--"import-map" <Path> "Path to import map file"))
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Type checks a service with the TypeScript compiler")
+                .arg(arg!([DIR] "Path to the service directory or entrypoint").default_value("."))
+                .arg(arg!(--"json" "Print the diagnostics as JSON").action(ArgAction::SetTrue))
+                .arg(arg!(--"all" "Also report diagnostics of remote modules").action(ArgAction::SetTrue))
+                .arg(arg!(--"typescript-version" <VERSION> "Version of the TypeScript compiler to check with"))
+        )
+        .subcommand(
+            Command::new("vendor")
+                .about("Downloads the remote modules of a service into a vendor directory, along with an import map resolving them to it")
+                .arg(arg!([DIR] "Path to the service directory or entrypoint").default_value("."))
+                .arg(arg!(--"output" <DIR> "Path to the vendor directory (defaults to <DIR>/vendor)"))
+                .arg(arg!(--"force" "Replace the vendor directory if it isn't empty").action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspects the cache of remote modules")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("info")
+                        .about("Prints the location, size, headers, age and integrity of a cached module")
+                        .arg(arg!(<SPECIFIER> "URL of the module")),
+                )
+                .subcommand(Command::new("ls").about("Lists the cached modules"))
+                .subcommand(
+                    Command::new("doctor")
+                        .about("Detects and removes corrupt cache entries, which are fetched again when next imported")
+                        .arg(arg!(--"dry-run" "Only report the corrupt entries").action(ArgAction::SetTrue)),
+                ),
+        )
 }
 
 //async fn exit_with_code(result: Result<(), Error>) {
@@ -84,6 +268,13 @@ fn main() -> Result<(), anyhow::Error> {
 
         #[allow(clippy::single_match)]
         match matches.subcommand() {
+            // the supervising process of a cluster only runs its members
+            Some(("start", sub_matches))
+                if sub_matches.contains_id("cluster") && !is_cluster_member() =>
+            {
+                let size = sub_matches.get_one::<usize>("cluster").copied().unwrap();
+                run_cluster(size).await?;
+            }
             Some(("start", sub_matches)) => {
                 let ip = sub_matches.get_one::<String>("ip").cloned().unwrap();
                 let port = sub_matches.get_one::<u16>("port").copied().unwrap();
@@ -103,6 +294,118 @@ fn main() -> Result<(), anyhow::Error> {
                     sub_matches.get_one::<String>("main-entrypoint").cloned();
                 let maybe_events_entrypoint =
                     sub_matches.get_one::<String>("events-entrypoint").cloned();
+                let manifest_path = sub_matches.get_one::<String>("manifest").cloned();
+                let admin_port = sub_matches.get_one::<u16>("admin-port").copied();
+                let usage_sink = sub_matches.get_one::<String>("usage-sink").cloned();
+                let usage_flush_interval_secs =
+                    sub_matches.get_one::<u64>("usage-flush-interval").copied();
+                let request_deadline_ms =
+                    sub_matches.get_one::<u64>("request-deadline-ms").copied();
+                let fallback_services_dir = sub_matches
+                    .get_one::<String>("fallback-services-dir")
+                    .cloned();
+                let crash_report_dir = sub_matches.get_one::<String>("crash-report-dir").cloned();
+                let record_dir = sub_matches.get_one::<String>("record-dir").cloned();
+                let fault_injection_path =
+                    sub_matches.get_one::<String>("fault-injection").cloned();
+                let feature_flags = sub_matches.get_one::<String>("feature-flags").cloned();
+                let feature_flags_refresh_secs =
+                    sub_matches.get_one::<u64>("feature-flags-refresh").copied();
+                let broadcast_redis_url = sub_matches.get_one::<String>("broadcast-redis").cloned();
+                let alarms_db_path = sub_matches.get_one::<String>("alarms-db").cloned();
+                let key_store_path = sub_matches.get_one::<String>("key-store").cloned();
+                let mail_config_path = sub_matches.get_one::<String>("mail-config").cloned();
+                let redis_config_path = sub_matches.get_one::<String>("redis-config").cloned();
+                let ai_config_path = sub_matches.get_one::<String>("ai-config").cloned();
+                let onnx_config_path = sub_matches.get_one::<String>("onnx-config").cloned();
+                let embeddings_cache_dir = sub_matches
+                    .get_one::<String>("embeddings-cache-dir")
+                    .cloned();
+                let embeddings_max_concurrency = sub_matches
+                    .get_one::<usize>("embeddings-max-concurrency")
+                    .copied();
+                let webhooks_db_path = sub_matches.get_one::<String>("webhooks-db").cloned();
+                let image_memory_limit_mb =
+                    sub_matches.get_one::<u64>("image-memory-limit").copied();
+                let geoip_db_paths = sub_matches
+                    .get_many::<String>("geoip-db")
+                    .map(|paths| paths.cloned().collect())
+                    .unwrap_or_default();
+                let region = sub_matches.get_one::<String>("region").cloned();
+                let zone = sub_matches.get_one::<String>("zone").cloned();
+                let drain_timeout_secs = sub_matches.get_one::<u64>("drain-timeout").copied();
+                let worker_cgroups = sub_matches.get_flag("worker-cgroups");
+                let sandbox_workers = sub_matches.get_flag("sandbox-workers");
+                let process_isolation = sub_matches.get_flag("process-isolation");
+                let worker_cgroup_root =
+                    sub_matches.get_one::<String>("worker-cgroup-root").cloned();
+                let http3_port = sub_matches.get_one::<u16>("http3-port").copied();
+                let http3_cert_path = sub_matches.get_one::<String>("http3-cert").cloned();
+                let http3_key_path = sub_matches.get_one::<String>("http3-key").cloned();
+                let trace_timelines = sub_matches.get_one::<usize>("trace-timelines").copied();
+                let telemetry_sample_rate =
+                    sub_matches.get_one::<f64>("telemetry-sample-rate").copied();
+                let redaction_config_path =
+                    sub_matches.get_one::<String>("redaction-config").cloned();
+                let event_quota_per_minute = sub_matches.get_one::<u64>("event-quota").copied();
+                let log_quota_bytes_per_minute = sub_matches.get_one::<u64>("log-quota").copied();
+                let events_spill_dir = sub_matches.get_one::<String>("events-spill-dir").cloned();
+                let events_spill_max_mb = sub_matches.get_one::<u64>("events-spill-max").copied();
+                let event_filters_path = sub_matches.get_one::<String>("event-filters").cloned();
+                let gc_config_path = sub_matches.get_one::<String>("gc-config").cloned();
+                let registry_mirrors_path =
+                    sub_matches.get_one::<String>("registry-mirrors").cloned();
+                let std_bundle_path = sub_matches.get_one::<String>("std-bundle").cloned();
+                let embedded_std_bundle = sub_matches.get_flag("embedded-std-bundle");
+                let std_bundle_allow = sub_matches
+                    .get_many::<String>("std-bundle-allow")
+                    .map(|prefixes| prefixes.cloned().collect())
+                    .unwrap_or_default();
+                let memory_coordinator = sub_matches.get_flag("memory-coordinator");
+                let adaptive_concurrency = sub_matches.get_flag("adaptive-concurrency");
+                let max_concurrency = sub_matches.get_one::<usize>("max-concurrency").copied();
+                let slow_request_threshold_ms = sub_matches
+                    .get_one::<u64>("slow-request-threshold")
+                    .copied();
+                let idempotency_ttl_secs = sub_matches.get_one::<u64>("idempotency-ttl").copied();
+                let idempotency_max_entries = sub_matches
+                    .get_one::<usize>("idempotency-max-entries")
+                    .copied();
+                let idempotency_max_mb = sub_matches.get_one::<u64>("idempotency-max-mb").copied();
+                let module_fetch_max_attempts = sub_matches
+                    .get_one::<u32>("module-fetch-max-attempts")
+                    .copied();
+                let module_fetch_backoff_ms =
+                    sub_matches.get_one::<u64>("module-fetch-backoff").copied();
+                let module_fetch_retry_budget = sub_matches
+                    .get_one::<u32>("module-fetch-retry-budget")
+                    .copied();
+                let module_scanner = sub_matches.get_one::<String>("module-scanner").cloned();
+                let module_scanner_args = sub_matches
+                    .get_many::<String>("module-scanner-arg")
+                    .map(|args| args.cloned().collect())
+                    .unwrap_or_default();
+                let module_scanner_timeout_ms = sub_matches
+                    .get_one::<u64>("module-scanner-timeout")
+                    .copied();
+                let reload_header_trusted_proxies = sub_matches
+                    .get_many::<String>("reload-header-trusted-proxy")
+                    .map(|proxies| proxies.cloned().collect())
+                    .unwrap_or_default();
+                let no_service_env_files = sub_matches.get_flag("no-env-files");
+                let discovery_config_path =
+                    sub_matches.get_one::<String>("discovery-config").cloned();
+                let kubernetes = sub_matches.get_flag("kubernetes");
+                let prestop_delay_secs = sub_matches.get_one::<u64>("prestop-delay").copied();
+                let termination_grace_period_secs = sub_matches
+                    .get_one::<u64>("termination-grace-period")
+                    .copied();
+                let readiness_port = sub_matches.get_one::<u16>("readiness-port").copied();
+                let liveness_port = sub_matches.get_one::<u16>("liveness-port").copied();
+                let memory_limit_mb = sub_matches.get_one::<u64>("memory-limit").copied();
+                let memory_high_ratio = sub_matches.get_one::<f64>("memory-high-ratio").copied();
+                let memory_critical_ratio =
+                    sub_matches.get_one::<f64>("memory-critical-ratio").copied();
 
                 start_server(
                     ip.as_str(),
@@ -116,9 +419,87 @@ fn main() -> Result<(), anyhow::Error> {
                         main: maybe_main_entrypoint,
                         events: maybe_events_entrypoint,
                     },
+                    ServerFlags {
+                        manifest_path,
+                        admin_port,
+                        usage_sink,
+                        usage_flush_interval_secs,
+                        request_deadline_ms,
+                        fallback_services_dir,
+                        crash_report_dir,
+                        fault_injection_path,
+                        record_dir,
+                        feature_flags,
+                        feature_flags_refresh_secs,
+                        broadcast_redis_url,
+                        alarms_db_path,
+                        key_store_path,
+                        mail_config_path,
+                        redis_config_path,
+                        ai_config_path,
+                        onnx_config_path,
+                        embeddings_cache_dir,
+                        embeddings_max_concurrency,
+                        webhooks_db_path,
+                        image_memory_limit_mb,
+                        geoip_db_paths,
+                        test_clock: false,
+                        region,
+                        zone,
+                        drain_timeout_secs,
+                        worker_cgroups,
+                        worker_cgroup_root,
+                        sandbox_workers,
+                        process_isolation,
+                        http3_port,
+                        http3_cert_path,
+                        http3_key_path,
+                        trace_timelines,
+                        telemetry_sample_rate,
+                        redaction_config_path,
+                        event_quota_per_minute,
+                        log_quota_bytes_per_minute,
+                        events_spill_dir,
+                        events_spill_max_mb,
+                        event_filters_path,
+                        gc_config_path,
+                        registry_mirrors_path,
+                        std_bundle_path,
+                        embedded_std_bundle,
+                        std_bundle_allow,
+                        memory_coordinator,
+                        memory_limit_mb,
+                        memory_high_ratio,
+                        memory_critical_ratio,
+                        adaptive_concurrency,
+                        max_concurrency,
+                        slow_request_threshold_ms,
+                        idempotency_ttl_secs,
+                        idempotency_max_entries,
+                        idempotency_max_mb,
+                        module_fetch_max_attempts,
+                        module_fetch_backoff_ms,
+                        module_fetch_retry_budget,
+                        module_scanner,
+                        module_scanner_args,
+                        module_scanner_timeout_ms,
+                        reload_header_trusted_proxies,
+                        no_service_env_files,
+                        discovery_config_path,
+                        kubernetes,
+                        prestop_delay_secs,
+                        termination_grace_period_secs,
+                        readiness_port,
+                        liveness_port,
+                        event_listener: None,
+                    },
                 )
                 .await?;
             }
+            Some((WORKER_HOST_COMMAND, sub_matches)) => {
+                let socket_path = sub_matches.get_one::<String>("socket").cloned().unwrap();
+                run_worker_host(PathBuf::from(socket_path)).await?;
+            }
             Some(("bundle", sub_matches)) => {
                 let output_path = sub_matches.get_one::<String>("output").cloned().unwrap();
 
@@ -135,6 +516,226 @@ fn main() -> Result<(), anyhow::Error> {
                 let mut file = File::create(output_path.as_str()).unwrap();
                 file.write_all(&create_eszip).unwrap();
             }
+            Some(("test", sub_matches)) => {
+                let service_path = sub_matches.get_one::<String>("DIR").cloned().unwrap();
+                let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+                let no_module_cache = sub_matches
+                    .get_one::<bool>("disable-module-cache")
+                    .cloned()
+                    .unwrap();
+                let filter = sub_matches.get_one::<String>("filter").cloned();
+                let mocks = sub_matches
+                    .get_many::<String>("mock")
+                    .map(|mocks| {
+                        mocks
+                            .map(|mock| parse_module_mock(mock.as_str()))
+                            .collect::<Result<_, _>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+
+                let coverage_dir = sub_matches.get_one::<String>("coverage").map(PathBuf::from);
+
+                let summary = run_tests(TestRunOpts {
+                    service_path: PathBuf::from(service_path),
+                    import_map_path,
+                    no_module_cache,
+                    filter,
+                    mocks,
+                    coverage_dir,
+                })
+                .await?;
+                if !summary.is_success() {
+                    std::process::exit(1);
+                }
+            }
+            Some(("doctor", sub_matches)) => {
+                let string = |name: &str| sub_matches.get_one::<String>(name).cloned();
+                let report = doctor(DoctorOpts {
+                    ip: string("ip").unwrap(),
+                    port: sub_matches.get_one::<u16>("port").copied().unwrap(),
+                    main_service_path: string("main-service"),
+                    import_map_path: string("import-map"),
+                    manifest_path: string("manifest"),
+                    admin_port: sub_matches.get_one::<u16>("admin-port").copied(),
+                    http3_port: sub_matches.get_one::<u16>("http3-port").copied(),
+                    http3_cert_path: string("http3-cert"),
+                    http3_key_path: string("http3-key"),
+                    key_store_path: string("key-store"),
+                    mail_config_path: string("mail-config"),
+                    redis_config_path: string("redis-config"),
+                    ai_config_path: string("ai-config"),
+                    onnx_config_path: string("onnx-config"),
+                    fault_injection_path: string("fault-injection"),
+                    redaction_config_path: string("redaction-config"),
+                    event_filters_path: string("event-filters"),
+                    gc_config_path: string("gc-config"),
+                    registry_mirrors_path: string("registry-mirrors"),
+                    discovery_config_path: string("discovery-config"),
+                    geoip_db_paths: sub_matches
+                        .get_many::<String>("geoip-db")
+                        .map(|paths| paths.cloned().collect())
+                        .unwrap_or_default(),
+                    sandbox_workers: sub_matches.get_flag("sandbox-workers"),
+                    skip_boot: sub_matches.get_flag("skip-boot"),
+                })
+                .await?;
+                if sub_matches.get_flag("json") {
+                    println!("{}", report.to_json()?);
+                } else {
+                    print!("{}", report.to_pretty_string());
+                }
+                if !report.is_success() {
+                    std::process::exit(1);
+                }
+            }
+            Some(("bench", sub_matches)) => {
+                let service_path = sub_matches.get_one::<String>("DIR").cloned().unwrap();
+                let headers = sub_matches
+                    .get_many::<String>("header")
+                    .map(|headers| {
+                        headers
+                            .map(|header| {
+                                header
+                                    .split_once(':')
+                                    .map(|(name, value)| {
+                                        (name.trim().to_string(), value.trim().to_string())
+                                    })
+                                    .ok_or_else(|| {
+                                        anyhow!("invalid header {:?}, expected NAME:VALUE", header)
+                                    })
+                            })
+                            .collect::<Result<_, _>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                let body = match sub_matches.get_one::<String>("body") {
+                    Some(path) => Some(std::fs::read(path)?),
+                    None => None,
+                };
+
+                let report = run_bench(BenchOpts {
+                    service_path: PathBuf::from(service_path),
+                    import_map_path: sub_matches.get_one::<String>("import-map").cloned(),
+                    no_module_cache: sub_matches
+                        .get_one::<bool>("disable-module-cache")
+                        .cloned()
+                        .unwrap(),
+                    requests: sub_matches.get_one::<usize>("requests").copied().unwrap(),
+                    concurrency: sub_matches
+                        .get_one::<usize>("concurrency")
+                        .copied()
+                        .unwrap(),
+                    method: sub_matches.get_one::<String>("method").cloned().unwrap(),
+                    path: sub_matches.get_one::<String>("path").cloned().unwrap(),
+                    headers,
+                    body,
+                })
+                .await?;
+                if sub_matches.get_flag("json") {
+                    println!("{}", report.to_json()?);
+                } else {
+                    print!("{}", report.to_pretty_string());
+                }
+            }
+            Some(("replay", sub_matches)) => {
+                let path = sub_matches.get_one::<String>("FILE").cloned().unwrap();
+                let recording = RecordedRequest::load(Path::new(&path))?;
+
+                let res = replay(
+                    &recording,
+                    ReplayOpts {
+                        main_service_path: sub_matches.get_one::<String>("main-service").cloned(),
+                        main_entrypoint: sub_matches.get_one::<String>("main-entrypoint").cloned(),
+                        import_map_path: sub_matches.get_one::<String>("import-map").cloned(),
+                    },
+                )
+                .await?;
+
+                println!("{:?} {}", res.version(), res.status());
+                for (name, value) in res.headers() {
+                    println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+                }
+                println!();
+                let body = hyper::body::to_bytes(res.into_body()).await?;
+                std::io::stdout().write_all(&body)?;
+            }
+            Some(("check", sub_matches)) => {
+                let service_path = sub_matches.get_one::<String>("DIR").cloned().unwrap();
+                let report = type_check(TypeCheckOpts {
+                    service_path: PathBuf::from(service_path),
+                    typescript_version: sub_matches
+                        .get_one::<String>("typescript-version")
+                        .cloned(),
+                    all: sub_matches.get_flag("all"),
+                })
+                .await?;
+                if sub_matches.get_flag("json") {
+                    println!("{}", report.to_json()?);
+                } else {
+                    print!("{}", report.to_pretty_string());
+                }
+                if !report.is_success() {
+                    std::process::exit(1);
+                }
+            }
+            Some(("vendor", sub_matches)) => {
+                let service_path = sub_matches.get_one::<String>("DIR").cloned().unwrap();
+                let summary = vendor(VendorOpts {
+                    service_path: PathBuf::from(service_path),
+                    output_dir: sub_matches.get_one::<String>("output").map(PathBuf::from),
+                    force: sub_matches.get_flag("force"),
+                })
+                .await?;
+                println!(
+                    "Vendored {} modules into {}. Start the service with --import-map {}",
+                    summary.modules,
+                    summary.output_dir.display(),
+                    summary.import_map_path.display()
+                );
+            }
+            Some(("cache", sub_matches)) => match sub_matches.subcommand() {
+                Some(("info", info_matches)) => {
+                    let specifier = info_matches
+                        .get_one::<String>("SPECIFIER")
+                        .cloned()
+                        .unwrap();
+                    match cache_info(&specifier)? {
+                        Some(info) => print!("{}", format_cache_info(&info)),
+                        None => {
+                            eprintln!("{} is not cached in {}", specifier, deps_dir()?.display());
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Some(("doctor", doctor_matches)) => {
+                    let dry_run = doctor_matches.get_flag("dry-run");
+                    let report = cache_doctor_report(dry_run)?;
+                    for problem in &report.problems {
+                        println!("{:?}\t{}", problem.kind, problem.path.display());
+                    }
+                    println!(
+                        "checked {} files, {} problems{}",
+                        report.checked,
+                        report.problems.len(),
+                        if report.repaired && !report.problems.is_empty() {
+                            " (repaired)"
+                        } else {
+                            ""
+                        }
+                    );
+                    if dry_run && !report.problems.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Some(("ls", _)) => {
+                    println!("location: {}", deps_dir()?.display());
+                    for info in cache_ls()? {
+                        println!("{}", format_cache_entry(&info));
+                    }
+                }
+                _ => {}
+            },
             _ => {
                 // unrecognized command
             }
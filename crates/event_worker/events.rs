@@ -1,52 +1,119 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PseudoEvent {}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BootEvent {
     pub boot_time: usize,
+    // modules loaded while booting, in load order
+    #[serde(default)]
+    pub modules: Vec<BootModule>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ModuleSourceKind {
+    // read from disk, or from a data/blob url
+    Local,
+    // remote, read from the module cache
+    Cache,
+    // remote, downloaded
+    Network,
+    // served by the std bundle
+    Bundle,
+    // a mocked module (see `moduleMocks`)
+    Mock,
+    // served by a custom module source provider
+    Provider,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BootModule {
+    pub specifier: String,
+    // final url, after redirects
+    pub resolved: String,
+    // sha256 (hex) of the source, before transpiling
+    pub hash: String,
+    pub source: ModuleSourceKind,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BootFailureEvent {
     pub msg: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkerMemoryUsed {
     pub total: usize,
     pub heap: usize,
     pub external: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum ShutdownReason {
     WallClockTime,
     CPUTime,
     Memory,
+    // event loop stopped making progress
+    Hang,
+    // kept running without yielding after a request missed its deadline
+    Deadline,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ShutdownEvent {
     pub reason: ShutdownReason,
     pub cpu_time_used: usize,
     pub memory_used: WorkerMemoryUsed,
+    // ops still pending when the worker was shut down, by resource type
+    #[serde(default)]
+    pub cancelled_ops: BTreeMap<String, usize>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UncaughtExceptionEvent {
     pub exception: String,
     pub cpu_time_used: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// What a user worker does about an uncaught exception or unhandled promise
+// rejection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UncaughtErrorPolicy {
+    // report it and keep serving
+    LogOnly,
+    // report it and answer the requests being served with a 500
+    FailRequest,
+    // terminate the worker (reported as an `UncaughtException`)
+    #[default]
+    KillWorker,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UncaughtErrorKind {
+    Exception,
+    UnhandledRejection,
+}
+
+// An uncaught error a worker survived, as its `UncaughtErrorPolicy` asked
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UncaughtErrorEvent {
+    pub kind: UncaughtErrorKind,
+    pub message: String,
+    pub policy: UncaughtErrorPolicy,
+    // requests answered with a 500 because of it
+    pub failed_requests: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogEvent {
     pub msg: String,
     pub level: LogLevel,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum LogLevel {
     Debug,
     Info,
@@ -54,23 +121,133 @@ pub enum LogLevel {
     Error,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum BodyTeeKind {
+    Request,
+    Response,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BodyTeeEvent {
+    pub kind: BodyTeeKind,
+    pub body: String,
+    pub size: usize,
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadlineExceededEvent {
+    // unix timestamp (ms) the request had to be answered by
+    pub deadline_at: u64,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkerCrashedEvent {
+    pub message: String,
+    pub backtrace: String,
+    // crash report written to disk (if enabled)
+    pub report_path: Option<String>,
+}
+
+// Outcome of an attempt to deliver an outbound webhook
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookDeliveryEvent {
+    pub id: String,
+    pub url: String,
+    pub event_type: Option<String>,
+    // `delivered`, `retrying` or `dead`
+    pub status: String,
+    pub attempt: u32,
+    // status code the receiver responded with, if it responded
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+// The service emitted more logs or events than its quota allows, the ones
+// emitted until `retry_after_ms` are dropped
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogQuotaExceededEvent {
+    pub events_per_minute: Option<u64>,
+    pub log_bytes_per_minute: Option<u64>,
+    pub retry_after_ms: u64,
+}
+
+// A request to the service took longer than the slow request threshold, from
+// the boot it waited for (if any) to the end of its response body
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlowRequestEvent {
+    pub method: String,
+    pub path: String,
+    // `None` if the worker failed to respond
+    pub status: Option<u16>,
+    pub total_ms: u64,
+    // waiting for the worker's boot, for requests sent right after it
+    pub boot_ms: u64,
+    // waiting for a free slot of the worker
+    pub queue_ms: u64,
+    // until the response head
+    pub exec_ms: u64,
+    // streaming the response body
+    pub write_ms: u64,
+}
+
+// Resources the requests served by a worker left open, still open when its next
+// request came in (for workers created with `detectLeaks`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResourceLeakEvent {
+    // by resource type, eg: `fetchResponse`, `fsFile`, `tcpStream`
+    pub leaked: BTreeMap<String, usize>,
+    // the requests that left them, as `METHOD /path`
+    pub requests: Vec<String>,
+}
+
+// The service was paused or resumed through the admin API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServiceStateChangedEvent {
+    // `paused` or `resumed`
+    pub state: String,
+    pub reason: Option<String>,
+    // status its requests are answered with while paused
+    pub status: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WorkerEvents {
     Boot(BootEvent),
     BootFailure(BootFailureEvent),
     UncaughtException(UncaughtExceptionEvent),
+    UncaughtError(UncaughtErrorEvent),
     Shutdown(ShutdownEvent),
     EventLoopCompleted(PseudoEvent),
     Log(LogEvent),
+    BodyTee(BodyTeeEvent),
+    DeadlineExceeded(DeadlineExceededEvent),
+    WorkerCrashed(WorkerCrashedEvent),
+    WebhookDelivery(WebhookDeliveryEvent),
+    LogQuotaExceeded(LogQuotaExceededEvent),
+    SlowRequest(SlowRequestEvent),
+    ResourceLeak(ResourceLeakEvent),
+    ServiceStateChanged(ServiceStateChangedEvent),
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct EventMetadata {
     pub service_path: Option<String>,
     pub execution_id: Option<Uuid>,
+    // node the event happened on, in a multi-region fleet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    // Kubernetes node and `<namespace>/<pod>` of the instance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkerEventWithMetadata {
     pub event: WorkerEvents,
     pub metadata: EventMetadata,
@@ -1,9 +1,11 @@
-use crate::events::{EventMetadata, LogEvent, LogLevel, WorkerEvents};
+use crate::events::{EventMetadata, LogEvent, LogLevel, ResourceLeakEvent, WorkerEvents};
+use crate::redaction::redactor;
 use crate::WorkerEventWithMetadata;
 use deno_core::error::AnyError;
 use deno_core::op2;
 use deno_core::OpState;
-use log::error;
+use log::{error, warn};
+use std::borrow::Cow;
 use tokio::sync::mpsc;
 
 #[op2(fast)]
@@ -13,6 +15,11 @@ fn op_user_worker_log(
     is_err: bool,
 ) -> Result<(), AnyError> {
     let maybe_tx = state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>();
+    // events are redacted on their way to the events worker
+    let msg = match (&maybe_tx, redactor()) {
+        (None, Some(redactor)) => redactor.redact(msg),
+        _ => Cow::Borrowed(msg),
+    };
     let mut level = LogLevel::Info;
     if is_err {
         level = LogLevel::Error;
@@ -40,4 +47,31 @@ fn op_user_worker_log(
     Ok(())
 }
 
-deno_core::extension!(sb_events_js_interceptors, ops = [op_user_worker_log,],);
+#[op2]
+fn op_user_worker_resource_leak(
+    state: &mut OpState,
+    #[serde] leak: ResourceLeakEvent,
+) -> Result<(), AnyError> {
+    let Some(tx) = state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>() else {
+        warn!(
+            "resources left open by {}: {:?}",
+            leak.requests.join(", "),
+            leak.leaked
+        );
+        return Ok(());
+    };
+    let metadata = state
+        .try_borrow::<EventMetadata>()
+        .cloned()
+        .unwrap_or_default();
+    tx.send(WorkerEventWithMetadata {
+        event: WorkerEvents::ResourceLeak(leak),
+        metadata,
+    })?;
+    Ok(())
+}
+
+deno_core::extension!(
+    sb_events_js_interceptors,
+    ops = [op_user_worker_log, op_user_worker_resource_leak,],
+);
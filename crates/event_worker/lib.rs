@@ -4,10 +4,18 @@ use deno_core::op2;
 use deno_core::OpState;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
 pub mod events;
 pub mod js_interceptors;
+pub mod redaction;
+
+// Number of events accepted by the events worker, used to tell which events it
+// has received (and which have to be replayed if it dies)
+#[derive(Debug, Clone, Default)]
+pub struct AcceptedEvents(pub Arc<AtomicU64>);
 
 #[op2(async)]
 #[serde]
@@ -25,6 +33,11 @@ async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error>
 
     let mut op_state = state.borrow_mut();
     op_state.put::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>(rx);
+    if data.is_some() {
+        if let Some(accepted) = op_state.try_borrow::<AcceptedEvents>() {
+            accepted.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 
     match data {
         Some(event) => Ok(RawEvent::Event(event)),
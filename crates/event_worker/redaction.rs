@@ -0,0 +1,228 @@
+use crate::events::WorkerEvents;
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+// bearer tokens (eg: from an `authorization` header) and JWTs
+const BUILTIN_PATTERNS: &[&str] = &[
+    r"(?i)\bbearer\s+[a-z0-9\-._~+/]+=*",
+    r"\beyJ[a-zA-Z0-9_-]+\.[a-zA-Z0-9_-]+\.[a-zA-Z0-9_-]*",
+];
+
+fn default_builtins() -> bool {
+    true
+}
+
+// eg: `{ "patterns": ["\\b\\d{16}\\b"], "fields": ["password", "apiKey"] }`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RedactionConfig {
+    // regular expressions, whatever they match is replaced
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    // names of the fields whose values are replaced (case insensitive), as JSON
+    // properties or `name=value` / `name: value` pairs
+    #[serde(default)]
+    pub fields: Vec<String>,
+    pub replacement: Option<String>,
+    // also redact bearer tokens and JWTs
+    #[serde(default = "default_builtins")]
+    pub builtins: bool,
+}
+
+impl RedactionConfig {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    // captures the value of a sensitive field, quoted or not
+    fields: Option<Regex>,
+    replacement: String,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Result<Self, Error> {
+        let builtins: &[&str] = if config.builtins {
+            BUILTIN_PATTERNS
+        } else {
+            &[]
+        };
+        let patterns = builtins
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .chain(config.patterns.iter().cloned())
+            .map(|pattern| {
+                Regex::new(&pattern)
+                    .with_context(|| format!("invalid redaction pattern {:?}", pattern))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let fields = if config.fields.is_empty() {
+            None
+        } else {
+            let names = config
+                .fields
+                .iter()
+                .map(|name| regex::escape(name))
+                .collect::<Vec<_>>()
+                .join("|");
+            Some(Regex::new(&format!(
+                r#"(?i)(?P<name>"?\b(?:{})\b"?\s*[:=]\s*)(?:"(?P<quoted>(?:[^"\\]|\\.)*)"|(?P<bare>[^\s,;&"}}\]]+))"#,
+                names
+            ))?)
+        };
+
+        Ok(Self {
+            patterns,
+            fields,
+            replacement: config
+                .replacement
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REPLACEMENT.to_string()),
+        })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if let Some(fields) = &self.fields {
+            if fields.is_match(&text) {
+                let redacted = fields.replace_all(&text, |caps: &Captures| {
+                    // keeps JSON strings quoted
+                    match caps.name("quoted") {
+                        Some(_) => format!("{}\"{}\"", &caps["name"], self.replacement),
+                        None => format!("{}{}", &caps["name"], self.replacement),
+                    }
+                });
+                text = Cow::Owned(redacted.into_owned());
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&text) {
+                let redacted = pattern.replace_all(&text, self.replacement.as_str());
+                text = Cow::Owned(redacted.into_owned());
+            }
+        }
+        text
+    }
+
+    fn redact_in_place(&self, text: &mut String) {
+        let redacted = match self.redact(text) {
+            Cow::Owned(redacted) => redacted,
+            Cow::Borrowed(_) => return,
+        };
+        *text = redacted;
+    }
+
+    // Redacts the text tenant code can put in an event
+    pub fn redact_event(&self, event: &mut WorkerEvents) {
+        match event {
+            WorkerEvents::Log(log) => self.redact_in_place(&mut log.msg),
+            WorkerEvents::BodyTee(tee) => self.redact_in_place(&mut tee.body),
+            WorkerEvents::BootFailure(failure) => self.redact_in_place(&mut failure.msg),
+            WorkerEvents::UncaughtException(exception) => {
+                self.redact_in_place(&mut exception.exception)
+            }
+            WorkerEvents::UncaughtError(error) => self.redact_in_place(&mut error.message),
+            WorkerEvents::DeadlineExceeded(deadline) => self.redact_in_place(&mut deadline.path),
+            WorkerEvents::SlowRequest(slow) => self.redact_in_place(&mut slow.path),
+            WorkerEvents::WorkerCrashed(crash) => {
+                self.redact_in_place(&mut crash.message);
+                self.redact_in_place(&mut crash.backtrace);
+            }
+            WorkerEvents::WebhookDelivery(delivery) => {
+                self.redact_in_place(&mut delivery.url);
+                if let Some(error) = &mut delivery.error {
+                    self.redact_in_place(error);
+                }
+            }
+            WorkerEvents::ResourceLeak(leak) => {
+                for request in &mut leak.requests {
+                    self.redact_in_place(request);
+                }
+            }
+            WorkerEvents::Boot(boot) => {
+                for module in &mut boot.modules {
+                    self.redact_in_place(&mut module.specifier);
+                    self.redact_in_place(&mut module.resolved);
+                }
+            }
+            WorkerEvents::Shutdown(_)
+            | WorkerEvents::EventLoopCompleted(_)
+            | WorkerEvents::LogQuotaExceeded(_)
+            | WorkerEvents::ServiceStateChanged(_) => {}
+        }
+    }
+}
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+// Redacts console output and events of every worker of the process
+pub fn set_redactor(redactor: Redactor) {
+    let _ = REDACTOR.set(redactor);
+}
+
+pub fn redactor() -> Option<&'static Redactor> {
+    REDACTOR.get()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RedactionConfig, Redactor};
+
+    #[test]
+    fn test_redact() {
+        let config = RedactionConfig::parse(
+            r#"{ "patterns": ["\\b\\d{4}-\\d{4}-\\d{4}-\\d{4}\\b"], "fields": ["password", "api_key"] }"#,
+        )
+        .unwrap();
+        let redactor = Redactor::new(&config).unwrap();
+
+        assert_eq!(
+            redactor.redact(r#"{"user":"ann","password":"hunter\"2","age":3}"#),
+            r#"{"user":"ann","password":"[REDACTED]","age":3}"#
+        );
+        assert_eq!(
+            redactor.redact("GET /login?user=ann&API_KEY=abc123&page=2"),
+            "GET /login?user=ann&API_KEY=[REDACTED]&page=2"
+        );
+        assert_eq!(
+            redactor.redact("authorization: Bearer abc.def-123 sent"),
+            "authorization: [REDACTED] sent"
+        );
+        assert_eq!(
+            redactor.redact("card 1234-5678-9012-3456 charged"),
+            "card [REDACTED] charged"
+        );
+        // untouched text isn't copied
+        assert!(matches!(
+            redactor.redact("nothing to see"),
+            std::borrow::Cow::Borrowed("nothing to see")
+        ));
+
+        let config = RedactionConfig::parse(
+            r#"{ "builtins": false, "fields": ["token"], "replacement": "***" }"#,
+        )
+        .unwrap();
+        let redactor = Redactor::new(&config).unwrap();
+        assert_eq!(
+            redactor.redact("Bearer abc token: xyz"),
+            "Bearer abc token: ***"
+        );
+
+        let invalid = RedactionConfig::parse(r#"{ "patterns": ["("] }"#).unwrap();
+        assert!(Redactor::new(&invalid).is_err());
+    }
+}
@@ -834,6 +834,11 @@ impl ConfigFile {
         }
     }
 
+    /// The raw `compilerOptions` of the config file, if any.
+    pub fn compiler_options(&self) -> Option<&Value> {
+        self.json.compiler_options.as_ref()
+    }
+
     pub fn to_import_map_path(&self) -> Option<String> {
         self.json.import_map.clone()
     }
@@ -0,0 +1,294 @@
+use super::http_cache::url_to_filename;
+use super::{CachedUrlMetadata, GlobalHttpCache, HttpCache, RealDenoCacheEnv, CACHE_PERM};
+use crate::http_util::HeadersMap;
+use crate::util::checksum;
+use crate::util::fs::atomic_write_file;
+use deno_cache_dir::HttpCacheItemKey;
+use deno_core::error::AnyError;
+use deno_core::serde_json::{self, Value};
+use deno_core::url::Url;
+use deno_crypto::rand;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Directory of the deps cache the blobs are stored in.
+pub const BLOBS_DIR: &str = "blobs";
+// metadata field referencing the blob of a cached module
+const BLOB_FIELD: &str = "blob";
+
+/// Module bodies stored by their sha256 (`deps/blobs/sha256/<ab>/<abcdef...>`).
+/// Cached modules are hardlinks to their blob, so identical dependencies served
+/// by several registries (or urls) are stored once.
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+impl ContentStore {
+    pub fn new(deps_dir: &Path) -> Self {
+        Self {
+            root: deps_dir.join(BLOBS_DIR).join("sha256"),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join(&digest[..2.min(digest.len())]).join(digest)
+    }
+
+    /// Stores `content`, returning its digest.
+    pub fn put(&self, content: &[u8]) -> std::io::Result<String> {
+        let digest = checksum::gen(&[content]);
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            atomic_write_file(&path, content, CACHE_PERM)?;
+        }
+        Ok(digest)
+    }
+
+    /// Replaces `target` with a hardlink to a blob (or a copy of it, on file
+    /// systems without hardlinks).
+    pub fn link(&self, digest: &str, target: &Path) -> std::io::Result<()> {
+        let blob_path = self.blob_path(digest);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = target.with_extension(format!("{:08x}.tmp", rand::random::<u32>()));
+        if std::fs::hard_link(&blob_path, &temp_path).is_err() {
+            std::fs::copy(&blob_path, &temp_path)?;
+        }
+        std::fs::rename(&temp_path, target).map_err(|err| {
+            let _ = std::fs::remove_file(&temp_path);
+            err
+        })
+    }
+
+    /// Whether the blob still hashes to its digest.
+    pub fn verify(&self, digest: &str) -> std::io::Result<bool> {
+        let content = std::fs::read(self.blob_path(digest))?;
+        Ok(checksum::gen(&[content]) == digest)
+    }
+
+    /// Digests of the stored blobs.
+    pub fn digests(&self) -> Vec<String> {
+        WalkDir::new(&self.root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.file_name().to_str().map(str::to_string))
+            .filter(|name| name.len() == 64)
+            .collect()
+    }
+
+    /// Whether no cached module links to the blob anymore (always false on
+    /// platforms without link counts).
+    pub fn is_unreferenced(&self, digest: &str) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            std::fs::metadata(self.blob_path(digest))
+                .map(|m| m.nlink() == 1)
+                .unwrap_or(false)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = digest;
+            false
+        }
+    }
+}
+
+/// The blob a cached module's metadata references, if it was stored in the
+/// content store.
+pub fn read_blob_ref(metadata_path: &Path) -> Option<String> {
+    let bytes = std::fs::read(metadata_path).ok()?;
+    let metadata: Value = serde_json::from_slice(&bytes).ok()?;
+    metadata.get(BLOB_FIELD)?.as_str().map(str::to_string)
+}
+
+fn write_metadata(
+    cache_path: &Path,
+    metadata: &CachedUrlMetadata,
+    digest: &str,
+) -> Result<(), AnyError> {
+    let mut json = serde_json::to_value(metadata)?;
+    json[BLOB_FIELD] = Value::String(digest.to_string());
+    atomic_write_file(
+        &cache_path.with_extension("metadata.json"),
+        serde_json::to_string_pretty(&json)?,
+        CACHE_PERM,
+    )?;
+    Ok(())
+}
+
+/// Moves the body of a cached module into the store, keeping it readable at its
+/// current path.
+pub fn store_cached_module(
+    store: &ContentStore,
+    cache_path: &Path,
+) -> Result<Option<String>, AnyError> {
+    let metadata_path = cache_path.with_extension("metadata.json");
+    let metadata: CachedUrlMetadata = match std::fs::read(&metadata_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let content = match std::fs::read(cache_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let digest = store.put(&content)?;
+    store.link(&digest, cache_path)?;
+    write_metadata(cache_path, &metadata, &digest)?;
+    Ok(Some(digest))
+}
+
+/// Migrates a deps cache in the url keyed layout to the content store. Modules
+/// stay at their path, so runtimes reading the previous layout still find them.
+pub fn migrate_to_content_store(deps_dir: &Path) -> std::io::Result<()> {
+    let store = ContentStore::new(deps_dir);
+    let blobs_dir = deps_dir.join(BLOBS_DIR);
+    for entry in WalkDir::new(deps_dir)
+        .into_iter()
+        .filter_entry(|e| e.path() != blobs_dir)
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        let is_metadata = path
+            .to_str()
+            .map(|p| p.ends_with(".metadata.json"))
+            .unwrap_or(true);
+        if !entry.file_type().is_file() || is_metadata {
+            continue;
+        }
+        // corrupt entries are left to `cache doctor`
+        if let Err(err) = store_cached_module(&store, path) {
+            log::debug!("not migrating {}: {}", path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// The global http cache, storing module bodies in a `ContentStore`. Reads go
+/// through the url keyed layout, which modules stored before the content store
+/// are also read from.
+#[derive(Debug)]
+pub struct ContentAddressedHttpCache {
+    deps_dir: PathBuf,
+    global: GlobalHttpCache,
+    store: ContentStore,
+}
+
+impl ContentAddressedHttpCache {
+    pub fn new(deps_dir: PathBuf) -> Self {
+        Self {
+            global: GlobalHttpCache::new(deps_dir.clone(), RealDenoCacheEnv),
+            store: ContentStore::new(&deps_dir),
+            deps_dir,
+        }
+    }
+
+    fn cache_path(&self, url: &Url) -> Result<PathBuf, AnyError> {
+        Ok(self.deps_dir.join(url_to_filename(url)?))
+    }
+
+    /// Whether the cached module still matches the blob it was stored as.
+    /// `None` if it isn't cached or was cached before the content store.
+    pub fn verify(&self, url: &Url) -> Result<Option<bool>, AnyError> {
+        let cache_path = self.cache_path(url)?;
+        let Some(digest) = read_blob_ref(&cache_path.with_extension("metadata.json")) else {
+            return Ok(None);
+        };
+        match std::fs::read(&cache_path) {
+            Ok(content) => Ok(Some(checksum::gen(&[content]) == digest)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl HttpCache for ContentAddressedHttpCache {
+    fn cache_item_key<'a>(&self, url: &'a Url) -> Result<HttpCacheItemKey<'a>, AnyError> {
+        self.global.cache_item_key(url)
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        self.global.contains(url)
+    }
+
+    fn set(&self, url: &Url, headers: HeadersMap, content: &[u8]) -> Result<(), AnyError> {
+        let cache_path = self.cache_path(url)?;
+        let digest = self.store.put(content)?;
+        self.store.link(&digest, &cache_path)?;
+        let metadata = CachedUrlMetadata {
+            time: SystemTime::now(),
+            url: url.to_string(),
+            headers,
+        };
+        write_metadata(&cache_path, &metadata, &digest)
+    }
+
+    fn read_modified_time(&self, key: &HttpCacheItemKey) -> Result<Option<SystemTime>, AnyError> {
+        self.global.read_modified_time(key)
+    }
+
+    fn read_file_bytes(&self, key: &HttpCacheItemKey) -> Result<Option<Vec<u8>>, AnyError> {
+        self.global.read_file_bytes(key)
+    }
+
+    fn read_metadata(&self, key: &HttpCacheItemKey) -> Result<Option<CachedUrlMetadata>, AnyError> {
+        self.global.read_metadata(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate_to_content_store, read_blob_ref, ContentAddressedHttpCache, ContentStore};
+    use crate::cache::http_cache::url_to_filename;
+    use crate::cache::HttpCache;
+    use deno_core::url::Url;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_content_addressed_http_cache() {
+        let deps_dir = std::env::temp_dir().join(format!("cas-{}", std::process::id()));
+        let cache = ContentAddressedHttpCache::new(deps_dir.clone());
+        let a = Url::parse("https://deno.land/x/lib@1.0.0/mod.ts").unwrap();
+        let b = Url::parse("https://esm.sh/lib@1.0.0/mod.ts").unwrap();
+        cache.set(&a, HashMap::new(), b"export {};").unwrap();
+        cache.set(&b, HashMap::new(), b"export {};").unwrap();
+
+        let store = ContentStore::new(&deps_dir);
+        assert_eq!(store.digests().len(), 1);
+        let key = cache.cache_item_key(&b).unwrap();
+        assert_eq!(cache.read_file_bytes(&key).unwrap().unwrap(), b"export {};");
+        assert_eq!(
+            cache.read_metadata(&key).unwrap().unwrap().url,
+            b.to_string()
+        );
+        assert_eq!(cache.verify(&a).unwrap(), Some(true));
+
+        // modules cached before the content store are moved into it
+        let c = Url::parse("https://deno.land/x/lib@1.0.0/deps.ts").unwrap();
+        let c_path = deps_dir.join(url_to_filename(&c).unwrap());
+        std::fs::write(&c_path, "export {};").unwrap();
+        std::fs::write(
+            c_path.with_extension("metadata.json"),
+            r#"{"headers":{},"url":"https://deno.land/x/lib@1.0.0/deps.ts"}"#,
+        )
+        .unwrap();
+        assert_eq!(cache.verify(&c).unwrap(), None);
+        migrate_to_content_store(&deps_dir).unwrap();
+        assert_eq!(store.digests().len(), 1);
+        assert!(read_blob_ref(&c_path.with_extension("metadata.json")).is_some());
+        assert_eq!(cache.verify(&c).unwrap(), Some(true));
+
+        std::fs::remove_dir_all(&deps_dir).unwrap();
+    }
+}
@@ -2,9 +2,11 @@
 
 use once_cell::sync::OnceCell;
 
+use super::ensure_cache_format;
 use super::DiskCache;
 
 use std::env;
+use std::path::Path;
 use std::path::PathBuf;
 
 /// Lazily creates the deno dir which might be useful in scenarios
@@ -64,6 +66,7 @@ impl DenoDir {
             std::env::current_dir()?.join(root)
         };
         assert!(root.is_absolute());
+        ensure_cache_format(&root);
         let gen_path = root.join("gen");
 
         let deno_dir = Self {
@@ -79,6 +82,11 @@ impl DenoDir {
         self.root.display()
     }
 
+    /// The root directory of the DENO_DIR.
+    pub fn root_path(&self) -> &Path {
+        &self.root
+    }
+
     /// Path for the incremental cache used for formatting.
     pub fn fmt_incremental_cache_db_file_path(&self) -> PathBuf {
         // bump this version name to invalidate the entire cache
@@ -0,0 +1,248 @@
+use super::content_store::{read_blob_ref, ContentStore, BLOBS_DIR};
+use super::emit::EmitMetadata;
+use super::format::{migrate_cache_format, read_cache_format, CacheFormat};
+use super::CachedUrlMetadata;
+use crate::util::checksum;
+use deno_core::error::AnyError;
+use deno_core::serde_json;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CacheProblemKind {
+    // the caches were written by an unsupported runtime version
+    UnsupportedFormat,
+    // a cached module without metadata, or metadata that can't be parsed
+    CorruptMetadata,
+    // metadata of a cached module whose source is gone
+    MissingSource,
+    // a cached module that doesn't match the blob it was stored as
+    CorruptSource,
+    // a blob that doesn't hash to its digest
+    CorruptBlob,
+    // a blob no cached module links to anymore
+    UnreferencedBlob,
+    // an emit whose metadata can't be parsed or is gone
+    CorruptEmit,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheProblem {
+    pub kind: CacheProblemKind,
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheDoctorReport {
+    pub checked: usize,
+    pub problems: Vec<CacheProblem>,
+    // whether the problems were repaired
+    pub repaired: bool,
+}
+
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| p.to_str().map(|p| p.ends_with(extension)).unwrap_or(false))
+        .collect()
+}
+
+fn check_http_cache(deps_dir: &Path, report: &mut CacheDoctorReport) {
+    let blobs_dir = deps_dir.join(BLOBS_DIR);
+    for entry in WalkDir::new(deps_dir)
+        .into_iter()
+        .filter_entry(|e| e.path() != blobs_dir)
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        report.checked += 1;
+
+        if let Some(source_path) = path
+            .to_str()
+            .and_then(|p| p.strip_suffix(".metadata.json"))
+            .map(PathBuf::from)
+        {
+            let metadata = std::fs::read(path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<CachedUrlMetadata>(&bytes).ok());
+            let kind = match metadata {
+                None => Some(CacheProblemKind::CorruptMetadata),
+                Some(metadata) if metadata.headers.contains_key("location") => None,
+                Some(_) => match (std::fs::read(&source_path), read_blob_ref(path)) {
+                    (Err(_), _) => Some(CacheProblemKind::MissingSource),
+                    (Ok(source), Some(digest)) if checksum::gen(&[source]) != digest => {
+                        Some(CacheProblemKind::CorruptSource)
+                    }
+                    _ => None,
+                },
+            };
+            if let Some(kind) = kind {
+                report.problems.push(CacheProblem {
+                    kind,
+                    path: source_path,
+                });
+            }
+        } else if !path.with_extension("metadata.json").exists() {
+            report.problems.push(CacheProblem {
+                kind: CacheProblemKind::CorruptMetadata,
+                path: path.to_path_buf(),
+            });
+        }
+    }
+}
+
+fn check_content_store(deps_dir: &Path, report: &mut CacheDoctorReport) {
+    let store = ContentStore::new(deps_dir);
+    for digest in store.digests() {
+        report.checked += 1;
+        let kind = if !store.verify(&digest).unwrap_or(false) {
+            CacheProblemKind::CorruptBlob
+        } else if store.is_unreferenced(&digest) {
+            CacheProblemKind::UnreferencedBlob
+        } else {
+            continue;
+        };
+        report.problems.push(CacheProblem {
+            kind,
+            path: store.blob_path(&digest),
+        });
+    }
+}
+
+fn check_emit_cache(gen_dir: &Path, report: &mut CacheDoctorReport) {
+    for meta_path in files_with_extension(gen_dir, ".meta") {
+        report.checked += 1;
+        let valid = std::fs::read(&meta_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<EmitMetadata>(&bytes).ok())
+            .is_some();
+        if !valid || !meta_path.with_extension("js").exists() {
+            report.problems.push(CacheProblem {
+                kind: CacheProblemKind::CorruptEmit,
+                path: meta_path.with_extension("js"),
+            });
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+// Removes the entry, so it's downloaded (or emitted) again when next used
+fn repair(problem: &CacheProblem) -> std::io::Result<()> {
+    match problem.kind {
+        CacheProblemKind::UnsupportedFormat => Ok(()),
+        CacheProblemKind::CorruptBlob | CacheProblemKind::UnreferencedBlob => {
+            remove_if_exists(&problem.path)
+        }
+        CacheProblemKind::CorruptMetadata
+        | CacheProblemKind::MissingSource
+        | CacheProblemKind::CorruptSource => {
+            remove_if_exists(&problem.path)?;
+            remove_if_exists(&problem.path.with_extension("metadata.json"))
+        }
+        CacheProblemKind::CorruptEmit => {
+            remove_if_exists(&problem.path)?;
+            remove_if_exists(&problem.path.with_extension("meta"))
+        }
+    }
+}
+
+/// Checks the http and emit caches under `root` for entries the runtime can't
+/// use, removing them (and migrating the caches to the current format) unless
+/// `dry_run` is set.
+pub fn cache_doctor(root: &Path, dry_run: bool) -> Result<CacheDoctorReport, AnyError> {
+    let mut report = CacheDoctorReport::default();
+    if read_cache_format(root) != CacheFormat::CURRENT {
+        report.problems.push(CacheProblem {
+            kind: CacheProblemKind::UnsupportedFormat,
+            path: root.to_path_buf(),
+        });
+        if !dry_run {
+            migrate_cache_format(root)?;
+        }
+    }
+
+    check_http_cache(&root.join("deps"), &mut report);
+    check_content_store(&root.join("deps"), &mut report);
+    check_emit_cache(&root.join("gen"), &mut report);
+
+    if !dry_run {
+        for problem in &report.problems {
+            repair(problem)?;
+        }
+        report.repaired = true;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cache_doctor, CacheProblemKind};
+    use crate::cache::format::CacheFormat;
+    use deno_core::serde_json;
+
+    #[test]
+    fn test_cache_doctor() {
+        let root = std::env::temp_dir().join(format!("cache-doctor-{}", std::process::id()));
+        let host_dir = root.join("deps/https/deno.land");
+        std::fs::create_dir_all(&host_dir).unwrap();
+        std::fs::create_dir_all(root.join("gen/https/deno.land")).unwrap();
+        std::fs::write(
+            root.join("cache_format.json"),
+            serde_json::to_vec(&CacheFormat::CURRENT).unwrap(),
+        )
+        .unwrap();
+
+        std::fs::write(host_dir.join("ok"), "export {};").unwrap();
+        std::fs::write(
+            host_dir.join("ok.metadata.json"),
+            r#"{"headers":{},"url":"https://deno.land/ok.ts"}"#,
+        )
+        .unwrap();
+        std::fs::write(host_dir.join("corrupt"), "export {};").unwrap();
+        std::fs::write(host_dir.join("corrupt.metadata.json"), "{\"head").unwrap();
+        std::fs::write(
+            host_dir.join("missing.metadata.json"),
+            r#"{"headers":{},"url":"https://deno.land/missing.ts"}"#,
+        )
+        .unwrap();
+        std::fs::write(root.join("gen/https/deno.land/mod.meta"), "nope").unwrap();
+        std::fs::write(root.join("gen/https/deno.land/mod.js"), "").unwrap();
+
+        let report = cache_doctor(&root, true).unwrap();
+        let mut kinds: Vec<_> = report.problems.iter().map(|p| p.kind).collect();
+        kinds.sort_by_key(|k| format!("{:?}", k));
+        assert_eq!(
+            kinds,
+            vec![
+                CacheProblemKind::CorruptEmit,
+                CacheProblemKind::CorruptMetadata,
+                CacheProblemKind::MissingSource
+            ]
+        );
+        assert!(host_dir.join("corrupt").exists());
+
+        let report = cache_doctor(&root, false).unwrap();
+        assert!(report.repaired);
+        assert!(!host_dir.join("corrupt").exists());
+        assert!(!host_dir.join("missing.metadata.json").exists());
+        assert!(host_dir.join("ok").exists());
+        assert!(cache_doctor(&root, true).unwrap().problems.is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
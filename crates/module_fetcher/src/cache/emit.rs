@@ -13,7 +13,7 @@ use super::DiskCache;
 use super::FastInsecureHasher;
 
 #[derive(Debug, Deserialize, Serialize)]
-struct EmitMetadata {
+pub(super) struct EmitMetadata {
     pub source_hash: String,
     pub emit_hash: String,
 }
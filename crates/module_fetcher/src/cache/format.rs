@@ -0,0 +1,184 @@
+use super::content_store::migrate_to_content_store;
+use super::CACHE_PERM;
+use crate::util::fs::atomic_write_file;
+use deno_core::serde_json;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Version of the on-disk formats, bumped when a runtime upgrade changes how
+/// the caches are written.
+pub const HTTP_CACHE_FORMAT: u32 = 2;
+pub const EMIT_CACHE_FORMAT: u32 = 1;
+
+const CACHE_FORMAT_FILE: &str = "cache_format.json";
+const HTTP_CACHE_DIR: &str = "deps";
+const EMIT_CACHE_DIR: &str = "gen";
+
+type Migration = fn(&Path) -> std::io::Result<()>;
+
+// Migrations to the next version, keyed by the version they start from. A cache
+// in a version without a migration path, or written by a newer runtime, is
+// cleared instead.
+const HTTP_CACHE_MIGRATIONS: &[(u32, Migration)] = &[
+    // url keyed files -> content store
+    (1, migrate_to_content_store),
+];
+const EMIT_CACHE_MIGRATIONS: &[(u32, Migration)] = &[];
+
+// roots checked by this process
+static CHECKED_ROOTS: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Default::default);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheFormat {
+    pub http: u32,
+    pub emit: u32,
+}
+
+impl CacheFormat {
+    pub const CURRENT: CacheFormat = CacheFormat {
+        http: HTTP_CACHE_FORMAT,
+        emit: EMIT_CACHE_FORMAT,
+    };
+
+    // Caches written before formats were versioned have no format file and
+    // are in the first version
+    const UNVERSIONED: CacheFormat = CacheFormat { http: 1, emit: 1 };
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum CacheMigration {
+    UpToDate,
+    Migrated { from: u32 },
+    Cleared { from: u32 },
+}
+
+/// The format of the caches under `root`. A format file that can't be read is
+/// reported as version 0, so the caches get cleared.
+pub fn read_cache_format(root: &Path) -> CacheFormat {
+    match std::fs::read(root.join(CACHE_FORMAT_FILE)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or(CacheFormat { http: 0, emit: 0 }),
+        Err(_) => CacheFormat::UNVERSIONED,
+    }
+}
+
+fn migrate_dir(
+    dir: &Path,
+    mut version: u32,
+    current: u32,
+    migrations: &[(u32, Migration)],
+) -> std::io::Result<CacheMigration> {
+    let from = version;
+    if version == current || !dir.exists() {
+        return Ok(CacheMigration::UpToDate);
+    }
+    while version < current {
+        let Some((_, migration)) = migrations.iter().find(|(v, _)| *v == version) else {
+            break;
+        };
+        migration(dir)?;
+        version += 1;
+    }
+    if version == current {
+        return Ok(CacheMigration::Migrated { from });
+    }
+    std::fs::remove_dir_all(dir)?;
+    Ok(CacheMigration::Cleared { from })
+}
+
+/// Migrates (or clears) the caches under `root` to the formats of this runtime.
+pub fn migrate_cache_format(root: &Path) -> std::io::Result<(CacheMigration, CacheMigration)> {
+    let format = read_cache_format(root);
+    let http = migrate_dir(
+        &root.join(HTTP_CACHE_DIR),
+        format.http,
+        HTTP_CACHE_FORMAT,
+        HTTP_CACHE_MIGRATIONS,
+    )?;
+    let emit = migrate_dir(
+        &root.join(EMIT_CACHE_DIR),
+        format.emit,
+        EMIT_CACHE_FORMAT,
+        EMIT_CACHE_MIGRATIONS,
+    )?;
+    if format != CacheFormat::CURRENT || !root.join(CACHE_FORMAT_FILE).exists() {
+        std::fs::create_dir_all(root)?;
+        atomic_write_file(
+            &root.join(CACHE_FORMAT_FILE),
+            serde_json::to_vec(&CacheFormat::CURRENT)?,
+            CACHE_PERM,
+        )?;
+    }
+    Ok((http, emit))
+}
+
+/// Runs `migrate_cache_format` once per process and root. Failures are logged,
+/// the caches are then used as they are.
+pub fn ensure_cache_format(root: &Path) {
+    if !CHECKED_ROOTS.lock().unwrap().insert(root.to_path_buf()) {
+        return;
+    }
+    match migrate_cache_format(root) {
+        Ok((http, emit)) => {
+            for (name, migration) in [("http", http), ("emit", emit)] {
+                match migration {
+                    CacheMigration::UpToDate => {}
+                    CacheMigration::Migrated { from } => log::info!(
+                        "migrated the {} cache in {} from format {}",
+                        name,
+                        root.display(),
+                        from
+                    ),
+                    CacheMigration::Cleared { from } => log::warn!(
+                        "cleared the {} cache in {} (format {} is not supported)",
+                        name,
+                        root.display(),
+                        from
+                    ),
+                }
+            }
+        }
+        Err(err) => log::warn!(
+            "failed to migrate the caches in {}: {}",
+            root.display(),
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate_cache_format, read_cache_format, CacheFormat, CacheMigration};
+
+    #[test]
+    fn test_migrate_cache_format() {
+        let root = std::env::temp_dir().join(format!("cache-format-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("deps/https/deno.land")).unwrap();
+        std::fs::create_dir_all(root.join("gen")).unwrap();
+
+        // unversioned caches are in the first format
+        let (http, emit) = migrate_cache_format(&root).unwrap();
+        assert_eq!(http, CacheMigration::Migrated { from: 1 });
+        assert_eq!(emit, CacheMigration::UpToDate);
+        assert_eq!(read_cache_format(&root), CacheFormat::CURRENT);
+
+        // caches of a newer runtime are cleared
+        std::fs::write(root.join("cache_format.json"), r#"{"http":99,"emit":1}"#).unwrap();
+        let (http, emit) = migrate_cache_format(&root).unwrap();
+        assert_eq!(http, CacheMigration::Cleared { from: 99 });
+        assert_eq!(emit, CacheMigration::UpToDate);
+        assert!(!root.join("deps").exists());
+        assert!(root.join("gen").exists());
+
+        // so are caches with a corrupt format file
+        std::fs::write(root.join("cache_format.json"), "{").unwrap();
+        let (_, emit) = migrate_cache_format(&root).unwrap();
+        assert_eq!(emit, CacheMigration::Cleared { from: 0 });
+        assert_eq!(read_cache_format(&root), CacheFormat::CURRENT);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use super::http_cache::url_to_filename;
+use super::CachedUrlMetadata;
+use crate::util::checksum;
+use deno_core::error::AnyError;
+use deno_core::serde_json;
+use deno_core::url::Url;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+const METADATA_EXTENSION: &str = "metadata.json";
+
+/// A remote module in the deps cache, as shown by `edge-runtime cache`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedModuleInfo {
+    pub url: String,
+    pub path: PathBuf,
+    /// bytes (0 for redirects)
+    pub size: u64,
+    pub headers: HashMap<String, String>,
+    /// seconds since the module was downloaded
+    pub age_secs: u64,
+    /// sha256 of the cached source, hex encoded
+    pub integrity: Option<String>,
+}
+
+fn read_info(path: &Path) -> Result<Option<CachedModuleInfo>, AnyError> {
+    let metadata_path = path.with_extension(METADATA_EXTENSION);
+    let metadata: CachedUrlMetadata = match std::fs::read(&metadata_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let source = match std::fs::read(path) {
+        Ok(source) => Some(source),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    let age_secs = SystemTime::now()
+        .duration_since(metadata.time)
+        .map(|age| age.as_secs())
+        .unwrap_or(0);
+
+    Ok(Some(CachedModuleInfo {
+        url: metadata.url,
+        path: path.to_path_buf(),
+        size: source.as_ref().map(|s| s.len() as u64).unwrap_or(0),
+        headers: metadata.headers,
+        age_secs,
+        integrity: source.map(|s| checksum::gen(&[s])),
+    }))
+}
+
+/// Looks up a remote module in the deps cache (`$DENO_DIR/deps`).
+pub fn inspect_cached_module(
+    deps_dir: &Path,
+    specifier: &Url,
+) -> Result<Option<CachedModuleInfo>, AnyError> {
+    read_info(&deps_dir.join(url_to_filename(specifier)?))
+}
+
+/// Every remote module in the deps cache, sorted by url.
+pub fn list_cached_modules(deps_dir: &Path) -> Result<Vec<CachedModuleInfo>, AnyError> {
+    let mut modules = vec![];
+    for entry in WalkDir::new(deps_dir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_metadata = path
+            .to_str()
+            .map(|p| p.ends_with(METADATA_EXTENSION))
+            .unwrap_or(true);
+        if !entry.file_type().is_file() || is_metadata {
+            continue;
+        }
+        if let Some(info) = read_info(path)? {
+            modules.push(info);
+        }
+    }
+    modules.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{inspect_cached_module, list_cached_modules};
+    use crate::cache::http_cache::url_to_filename;
+    use deno_core::url::Url;
+
+    #[test]
+    fn test_inspect_cached_module() {
+        let deps_dir = std::env::temp_dir().join(format!("deps-{}", std::process::id()));
+        let url = Url::parse("https://deno.land/std/http/server.ts").unwrap();
+        let path = deps_dir.join(url_to_filename(&url).unwrap());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "export {};").unwrap();
+        std::fs::write(
+            path.with_extension("metadata.json"),
+            r#"{ "headers": { "etag": "\"abc\"" }, "url": "https://deno.land/std/http/server.ts" }"#,
+        )
+        .unwrap();
+
+        let info = inspect_cached_module(&deps_dir, &url).unwrap().unwrap();
+        let listed = list_cached_modules(&deps_dir).unwrap();
+        std::fs::remove_dir_all(&deps_dir).unwrap();
+
+        assert_eq!(info.size, 10);
+        assert_eq!(info.headers.get("etag").unwrap(), "\"abc\"");
+        assert_eq!(info.integrity.as_ref().unwrap().len(), 64);
+        assert_eq!(listed, vec![info]);
+        assert!(inspect_cached_module(
+            &deps_dir,
+            &Url::parse("https://deno.land/x/missing.ts").unwrap()
+        )
+        .unwrap()
+        .is_none());
+    }
+}
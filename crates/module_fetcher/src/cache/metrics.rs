@@ -0,0 +1,50 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of remote module fetches, process wide.
+#[derive(Debug, Default)]
+pub struct ModuleCacheMetrics {
+    /// served from the cache
+    pub hits: AtomicU64,
+    /// went to the origin, because the module wasn't cached or had to be
+    /// revalidated
+    pub misses: AtomicU64,
+    /// cached copy confirmed up to date by the origin (304)
+    pub revalidations: AtomicU64,
+    /// cached copy replaced by a newer download
+    pub evictions: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleCacheMetricsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub revalidations: u64,
+    pub evictions: u64,
+}
+
+static METRICS: ModuleCacheMetrics = ModuleCacheMetrics {
+    hits: AtomicU64::new(0),
+    misses: AtomicU64::new(0),
+    revalidations: AtomicU64::new(0),
+    evictions: AtomicU64::new(0),
+};
+
+pub fn module_cache_metrics() -> &'static ModuleCacheMetrics {
+    &METRICS
+}
+
+impl ModuleCacheMetrics {
+    pub fn snapshot(&self) -> ModuleCacheMetricsSnapshot {
+        ModuleCacheMetricsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            revalidations: self.revalidations.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -27,22 +27,37 @@ mod cache_db;
 mod caches;
 mod check;
 mod common;
+mod content_store;
 mod deno_dir;
 mod disk_cache;
+mod doctor;
 mod emit;
+mod format;
 mod http_cache;
 mod incremental;
+mod inspect;
+mod metrics;
 mod node;
 mod parsed_source;
 
 pub use caches::Caches;
 pub use check::TypeCheckCache;
 pub use common::FastInsecureHasher;
+pub use content_store::{
+    migrate_to_content_store, read_blob_ref, store_cached_module, ContentAddressedHttpCache,
+    ContentStore, BLOBS_DIR,
+};
 pub use deno_dir::DenoDir;
 pub use deno_dir::DenoDirProvider;
 pub use disk_cache::DiskCache;
+pub use doctor::{cache_doctor, CacheDoctorReport, CacheProblem, CacheProblemKind};
 pub use emit::EmitCache;
+pub use format::{
+    ensure_cache_format, migrate_cache_format, read_cache_format, CacheFormat, CacheMigration,
+};
 pub use incremental::IncrementalCache;
+pub use inspect::{inspect_cached_module, list_cached_modules, CachedModuleInfo};
+pub use metrics::{module_cache_metrics, ModuleCacheMetrics, ModuleCacheMetricsSnapshot};
 pub use node::NodeAnalysisCache;
 pub use parsed_source::ParsedSourceCache;
 
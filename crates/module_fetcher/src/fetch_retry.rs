@@ -0,0 +1,151 @@
+use deno_fetch::reqwest::StatusCode;
+use rand::Rng;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+static FETCH_RETRY_POLICY: OnceLock<FetchRetryPolicy> = OnceLock::new();
+
+/// How failed module downloads are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchRetryPolicy {
+    /// attempts of a download, the first one included
+    pub max_attempts: u32,
+    /// delay before the first retry, doubled for every other one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// retries shared by all the downloads of a worker boot, so a registry
+    /// that's down doesn't hold up the boot for every module of the graph
+    pub budget: u32,
+}
+
+impl Default for FetchRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            budget: 10,
+        }
+    }
+}
+
+pub fn set_fetch_retry_policy(policy: FetchRetryPolicy) {
+    let _ = FETCH_RETRY_POLICY.set(policy);
+}
+
+pub fn fetch_retry_policy() -> FetchRetryPolicy {
+    FETCH_RETRY_POLICY.get().copied().unwrap_or_default()
+}
+
+/// Why a download failed, when it may succeed if tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchFailure {
+    Connect,
+    Timeout,
+    Status(StatusCode),
+}
+
+impl FetchFailure {
+    /// Attempts worth making for this kind of failure. Connection errors,
+    /// timeouts, rate limiting and gateway errors are usually transient, other
+    /// server errors are tried once more.
+    fn max_attempts(&self, policy: &FetchRetryPolicy) -> u32 {
+        match self {
+            FetchFailure::Connect | FetchFailure::Timeout => policy.max_attempts,
+            FetchFailure::Status(
+                StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT,
+            ) => policy.max_attempts,
+            FetchFailure::Status(_) => policy.max_attempts.min(2),
+        }
+    }
+}
+
+/// Retries left to the downloads of a worker boot.
+#[derive(Debug, Clone)]
+pub struct RetryBudget(Arc<AtomicU32>);
+
+impl RetryBudget {
+    pub fn new(retries: u32) -> Self {
+        Self(Arc::new(AtomicU32::new(retries)))
+    }
+
+    fn try_spend(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
+
+/// Exponential backoff with equal jitter: half of the delay is fixed, the
+/// other half random so the workers booting together don't retry in lockstep.
+fn backoff(policy: &FetchRetryPolicy, retry: u32) -> Duration {
+    let delay = policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(policy.max_delay);
+    let half = delay / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// The delay before retrying a download that failed on its `attempt`-th try
+/// (starting at 1), `None` when it's not to be retried.
+pub fn retry_delay(
+    policy: &FetchRetryPolicy,
+    budget: &RetryBudget,
+    failure: FetchFailure,
+    attempt: u32,
+) -> Option<Duration> {
+    if attempt >= failure.max_attempts(policy) || !budget.try_spend() {
+        return None;
+    }
+    Some(backoff(policy, attempt - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = FetchRetryPolicy::default();
+        let budget = RetryBudget::new(100);
+
+        // exponential, within the jitter
+        for attempt in 1..4 {
+            let delay = retry_delay(&policy, &budget, FetchFailure::Connect, attempt).unwrap();
+            let full = policy.base_delay * 2u32.pow(attempt - 1);
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+        assert_eq!(
+            retry_delay(&policy, &budget, FetchFailure::Timeout, 4),
+            None
+        );
+        assert!(backoff(&policy, 20) <= policy.max_delay);
+
+        // other server errors are retried once
+        let failure = FetchFailure::Status(StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(retry_delay(&policy, &budget, failure, 1).is_some());
+        assert_eq!(retry_delay(&policy, &budget, failure, 2), None);
+        let failure = FetchFailure::Status(StatusCode::TOO_MANY_REQUESTS);
+        assert!(retry_delay(&policy, &budget, failure, 3).is_some());
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let policy = FetchRetryPolicy::default();
+        let budget = RetryBudget::new(2);
+        let shared = budget.clone();
+        assert!(retry_delay(&policy, &budget, FetchFailure::Connect, 1).is_some());
+        assert!(retry_delay(&policy, &shared, FetchFailure::Connect, 1).is_some());
+        assert_eq!(
+            retry_delay(&policy, &budget, FetchFailure::Connect, 1),
+            None
+        );
+    }
+}
@@ -4,11 +4,15 @@ pub use crate::args::CacheSetting;
 use crate::auth_tokens::AuthToken;
 use crate::auth_tokens::AuthTokens;
 use crate::cache::HttpCache;
+use crate::cache::{module_cache_metrics, ModuleCacheMetrics};
+use crate::fetch_retry::{fetch_retry_policy, retry_delay, FetchFailure, RetryBudget};
 use crate::http_util;
 use crate::http_util::resolve_redirect_from_response;
 use crate::http_util::CacheSemantics;
 use crate::http_util::HeadersMap;
 use crate::http_util::HttpClient;
+use crate::module_scanner::scan_downloaded_module;
+use crate::registry_mirrors::module_sources;
 use crate::util::text_encoding;
 
 use crate::permissions::Permissions;
@@ -36,6 +40,7 @@ use std::fs;
 use std::future::Future;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -56,6 +61,9 @@ pub struct File {
     pub specifier: ModuleSpecifier,
 
     pub maybe_headers: Option<HashMap<String, String>>,
+    /// Whether the file was downloaded, as opposed to read from the cache (or
+    /// the disk for local files).
+    pub downloaded: bool,
 }
 
 /// Simple struct implementing in-process caching to prevent multiple
@@ -91,6 +99,7 @@ fn fetch_local(specifier: &ModuleSpecifier) -> Result<File, AnyError> {
         source: source.into(),
         specifier: specifier.clone(),
         maybe_headers: None,
+        downloaded: false,
     })
 }
 
@@ -172,6 +181,8 @@ pub struct FileFetcher {
     http_client: Arc<HttpClient>,
     blob_store: Arc<BlobStore>,
     download_log_level: log::Level,
+    maybe_download_counter: Option<Arc<AtomicU64>>,
+    retry_budget: RetryBudget,
 }
 
 impl FileFetcher {
@@ -191,9 +202,16 @@ impl FileFetcher {
             http_client,
             blob_store,
             download_log_level: log::Level::Info,
+            maybe_download_counter: None,
+            retry_budget: RetryBudget::new(fetch_retry_policy().budget),
         }
     }
 
+    /// Counts remote modules that had to be downloaded (ie. were not in the cache).
+    pub fn set_download_counter(&mut self, counter: Arc<AtomicU64>) {
+        self.maybe_download_counter = Some(counter);
+    }
+
     pub fn cache_setting(&self) -> &CacheSetting {
         &self.cache_setting
     }
@@ -226,6 +244,7 @@ impl FileFetcher {
             source: source.into(),
             specifier: specifier.clone(),
             maybe_headers: Some(headers.clone()),
+            downloaded: false,
         })
     }
 
@@ -243,8 +262,19 @@ impl FileFetcher {
         }
 
         let cache_key = self.http_cache.cache_item_key(specifier)?; // compute this once
-        let Some(metadata) = self.http_cache.read_metadata(&cache_key)? else {
-            return Ok(None);
+        let metadata = match self.http_cache.read_metadata(&cache_key) {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => return Ok(None),
+            // a corrupt entry is downloaded again (and overwritten) instead of
+            // failing the import
+            Err(err) => {
+                log::warn!(
+                    "ignoring corrupt cache entry of {}: {} (run `edge-runtime cache doctor` to repair the cache)",
+                    specifier,
+                    err
+                );
+                return Ok(None);
+            }
         };
         let headers = metadata.headers;
         if let Some(redirect_to) = headers.get("location") {
@@ -273,6 +303,7 @@ impl FileFetcher {
             source: source.into(),
             specifier: specifier.clone(),
             maybe_headers: Some(headers),
+            downloaded: false,
         })
     }
 
@@ -300,6 +331,7 @@ impl FileFetcher {
             source: source.into(),
             specifier: specifier.clone(),
             maybe_headers: Some(headers),
+            downloaded: false,
         })
     }
 
@@ -328,6 +360,7 @@ impl FileFetcher {
         if self.should_use_cache(specifier, cache_setting) {
             match self.fetch_cached(specifier, redirect_limit) {
                 Ok(Some(file)) => {
+                    ModuleCacheMetrics::record(&module_cache_metrics().hits);
                     return futures::future::ok(file).boxed();
                 }
                 Ok(None) => {}
@@ -353,6 +386,11 @@ impl FileFetcher {
             format!("Download"),
             specifier
         );
+        if let Some(counter) = &self.maybe_download_counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        ModuleCacheMetrics::record(&module_cache_metrics().misses);
+        let was_cached = self.http_cache.contains(specifier);
 
         let maybe_etag = self
             .http_cache
@@ -360,86 +398,106 @@ impl FileFetcher {
             .ok()
             .and_then(|key| self.http_cache.read_metadata(&key).ok().flatten())
             .and_then(|metadata| metadata.headers.get("etag").cloned());
-        let maybe_auth_token = self.auth_tokens.get(specifier);
         let specifier = specifier.clone();
         let client = self.http_client.clone();
         let file_fetcher = self.clone();
         let cache_setting = cache_setting.clone();
-        // A single pass of fetch either yields code or yields a redirect, server
-        // error causes a single retry to avoid crashing hard on intermittent failures.
-
-        async fn handle_request_or_server_error(
-            retried: &mut bool,
-            specifier: &Url,
-            err_str: String,
-        ) -> Result<(), AnyError> {
-            // Retry once, and bail otherwise.
-            if !*retried {
-                *retried = true;
-                log::debug!("Import '{}' failed: {}. Retrying...", specifier, err_str);
-                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                Ok(())
-            } else {
-                Err(generic_error(format!(
-                    "Import '{}' failed: {}",
-                    specifier, err_str
-                )))
-            }
-        }
+        let retry_policy = fetch_retry_policy();
+        // A single pass of fetch either yields code or yields a redirect, transient
+        // errors are retried with a backoff (see `FetchRetryPolicy`) to avoid
+        // crashing hard on intermittent failures. Once retries are exhausted, the
+        // mirrors of the registry (if any) are tried in turn.
 
         async move {
-            let mut retried = false;
-            loop {
-                let result = match fetch_once(
-                    &client,
-                    FetchOnceArgs {
-                        url: specifier.clone(),
-                        maybe_accept: maybe_accept.clone(),
-                        maybe_etag: maybe_etag.clone(),
-                        maybe_auth_token: maybe_auth_token.clone(),
-                    },
-                )
-                .await?
-                {
-                    FetchOnceResult::NotModified => {
-                        let file = file_fetcher.fetch_cached(&specifier, 10)?.unwrap();
-                        Ok(file)
-                    }
-                    FetchOnceResult::Redirect(redirect_url, headers) => {
-                        file_fetcher.http_cache.set(&specifier, headers, &[])?;
-                        file_fetcher
-                            .fetch_remote(
-                                &redirect_url,
-                                permissions,
-                                redirect_limit - 1,
-                                maybe_accept,
-                                &cache_setting,
-                            )
-                            .await
-                    }
-                    FetchOnceResult::Code(bytes, headers) => {
-                        file_fetcher
-                            .http_cache
-                            .set(&specifier, headers.clone(), &bytes)?;
-                        let file = file_fetcher.build_remote_file(&specifier, bytes, &headers)?;
-                        Ok(file)
-                    }
-                    FetchOnceResult::RequestError(err) => {
-                        handle_request_or_server_error(&mut retried, &specifier, err).await?;
-                        continue;
-                    }
-                    FetchOnceResult::ServerError(status) => {
-                        handle_request_or_server_error(
-                            &mut retried,
-                            &specifier,
-                            status.to_string(),
-                        )
-                        .await?;
-                        continue;
-                    }
+            let mut last_err = None;
+            for source in module_sources(&specifier) {
+                let maybe_auth_token = file_fetcher.auth_tokens.get(&source.url);
+                let mut attempt = 0;
+                let err_str = loop {
+                    attempt += 1;
+                    let result = match fetch_once(
+                        &client,
+                        FetchOnceArgs {
+                            url: source.url.clone(),
+                            maybe_accept: maybe_accept.clone(),
+                            maybe_etag: maybe_etag.clone(),
+                            maybe_auth_token: maybe_auth_token.clone(),
+                        },
+                    )
+                    .await?
+                    {
+                        FetchOnceResult::NotModified => {
+                            ModuleCacheMetrics::record(&module_cache_metrics().revalidations);
+                            let file = file_fetcher.fetch_cached(&specifier, 10)?.unwrap();
+                            Ok(file)
+                        }
+                        FetchOnceResult::Redirect(mirror_redirect_url, mut headers) => {
+                            let redirect_url = source.to_origin(mirror_redirect_url.clone());
+                            if redirect_url != mirror_redirect_url {
+                                // cached redirects point to the origin, not the mirror
+                                headers.insert("location".to_string(), redirect_url.to_string());
+                            }
+                            file_fetcher.http_cache.set(&specifier, headers, &[])?;
+                            source.record(true);
+                            return file_fetcher
+                                .fetch_remote(
+                                    &redirect_url,
+                                    permissions,
+                                    redirect_limit - 1,
+                                    maybe_accept,
+                                    &cache_setting,
+                                )
+                                .await;
+                        }
+                        FetchOnceResult::Code(bytes, headers) => {
+                            // rejected modules are neither cached nor retried
+                            let bytes = match scan_downloaded_module(&specifier, bytes).await {
+                                Ok(bytes) => bytes,
+                                Err(err) => {
+                                    source.record(true);
+                                    return Err(err);
+                                }
+                            };
+                            if was_cached {
+                                ModuleCacheMetrics::record(&module_cache_metrics().evictions);
+                            }
+                            file_fetcher
+                                .http_cache
+                                .set(&specifier, headers.clone(), &bytes)?;
+                            let mut file =
+                                file_fetcher.build_remote_file(&specifier, bytes, &headers)?;
+                            file.downloaded = true;
+                            Ok(file)
+                        }
+                        FetchOnceResult::Failed(failure, err_str) => {
+                            let Some(delay) = retry_delay(
+                                &retry_policy,
+                                &file_fetcher.retry_budget,
+                                failure,
+                                attempt,
+                            ) else {
+                                break err_str;
+                            };
+                            log::debug!(
+                                "Import '{}' failed: {}. Retrying in {:?}...",
+                                source.url,
+                                err_str,
+                                delay
+                            );
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                    };
+                    source.record(true);
+                    return result;
                 };
-                break result;
+                source.record(false);
+                last_err = Some(generic_error(format!(
+                    "Import '{}' failed: {}",
+                    source.url, err_str
+                )));
             }
+            Err(last_err.unwrap())
         }
         .boxed()
     }
@@ -566,8 +624,8 @@ enum FetchOnceResult {
     Code(Vec<u8>, HeadersMap),
     NotModified,
     Redirect(Url, HeadersMap),
-    RequestError(String),
-    ServerError(StatusCode),
+    // may succeed if tried again
+    Failed(FetchFailure, String),
 }
 
 #[derive(Debug)]
@@ -604,8 +662,17 @@ async fn fetch_once(
     let response = match request.send().await {
         Ok(resp) => resp,
         Err(err) => {
-            if err.is_connect() || err.is_timeout() {
-                return Ok(FetchOnceResult::RequestError(err.to_string()));
+            if err.is_connect() {
+                return Ok(FetchOnceResult::Failed(
+                    FetchFailure::Connect,
+                    err.to_string(),
+                ));
+            }
+            if err.is_timeout() {
+                return Ok(FetchOnceResult::Failed(
+                    FetchFailure::Timeout,
+                    err.to_string(),
+                ));
             }
             return Err(err.into());
         }
@@ -640,8 +707,11 @@ async fn fetch_once(
 
     let status = response.status();
 
-    if status.is_server_error() {
-        return Ok(FetchOnceResult::ServerError(status));
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return Ok(FetchOnceResult::Failed(
+            FetchFailure::Status(status),
+            status.to_string(),
+        ));
     }
 
     if status.is_client_error() {
@@ -2,10 +2,13 @@ pub mod args;
 mod auth_tokens;
 pub mod cache;
 pub mod emit;
+pub mod fetch_retry;
 pub mod file_fetcher;
 pub mod http_util;
+pub mod module_scanner;
 pub mod node;
 pub mod npm;
 pub mod permissions;
+pub mod registry_mirrors;
 pub mod util;
 pub mod version;
@@ -0,0 +1,185 @@
+use deno_core::anyhow::{anyhow, bail};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::ModuleSpecifier;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How long the scanner command may take on a module by default.
+pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+static MODULE_SCANNER: OnceLock<Mutex<mpsc::Sender<ScanJob>>> = OnceLock::new();
+
+struct ScanJob {
+    specifier: ModuleSpecifier,
+    bytes: Vec<u8>,
+    reply: oneshot::Sender<(Result<(), AnyError>, Vec<u8>)>,
+}
+
+/// Scans the modules downloaded from remote registries (eg: for malware or
+/// leaked secrets) before they are cached and executed. Modules read from the
+/// cache were scanned when they were downloaded.
+pub trait ModuleScanner: Send + Sync {
+    /// Rejects the module with an error. Called off the event loop, so it can
+    /// block.
+    fn scan(&self, specifier: &ModuleSpecifier, bytes: &[u8]) -> Result<(), AnyError>;
+}
+
+/// Scans modules with an external command, given the specifier as its last
+/// argument and the module on stdin. The module is rejected when the command
+/// exits with an error (its stderr being the reason) or doesn't finish in time.
+pub struct CommandScanner {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl ModuleScanner for CommandScanner {
+    fn scan(&self, specifier: &ModuleSpecifier, bytes: &[u8]) -> Result<(), AnyError> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(specifier.as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("failed to run {}: {}", self.program.display(), err))?;
+
+        // written from another thread, the command may not read all of it
+        let mut stdin = child.stdin.take().unwrap();
+        let bytes = bytes.to_vec();
+        let writer = std::thread::spawn(move || {
+            let _ = stdin.write_all(&bytes);
+        });
+        // read while the command runs, so it doesn't block on a full pipe
+        let mut stderr = child.stderr.take().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut reason = String::new();
+            let _ = stderr.read_to_string(&mut reason);
+            reason
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("scan timed out after {:?}", self.timeout);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let _ = writer.join();
+        let reason = reader.join().unwrap_or_default();
+
+        if status.success() {
+            return Ok(());
+        }
+        match reason.trim() {
+            "" => bail!("scanner exited with {}", status),
+            reason => bail!("{}", reason),
+        }
+    }
+}
+
+/// Scans run on threads spawned by a thread started here, at startup, so they
+/// aren't restricted by the sandbox of the worker threads downloading modules
+/// (eg: scanner commands can still be executed).
+pub fn set_module_scanner(scanner: Arc<dyn ModuleScanner>) {
+    let (jobs_tx, jobs_rx) = mpsc::channel::<ScanJob>();
+    if MODULE_SCANNER.set(Mutex::new(jobs_tx)).is_err() {
+        return;
+    }
+    let spawned = std::thread::Builder::new()
+        .name("module-scanner".to_string())
+        .spawn(move || {
+            for job in jobs_rx {
+                let scanner = scanner.clone();
+                std::thread::spawn(move || {
+                    let result = scanner.scan(&job.specifier, &job.bytes);
+                    let _ = job.reply.send((result, job.bytes));
+                });
+            }
+        });
+    if let Err(err) = spawned {
+        log::error!("failed to start the module scanner: {}", err);
+    }
+}
+
+/// Scans a module that was just downloaded, giving its bytes back when it can
+/// be cached and executed.
+pub async fn scan_downloaded_module(
+    specifier: &ModuleSpecifier,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>, AnyError> {
+    let Some(jobs_tx) = MODULE_SCANNER.get() else {
+        return Ok(bytes);
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let job = ScanJob {
+        specifier: specifier.clone(),
+        bytes,
+        reply: reply_tx,
+    };
+    // a module that can't be scanned isn't cached either
+    let sent = jobs_tx.lock().unwrap().send(job);
+    let (result, bytes) = match sent {
+        Ok(()) => reply_rx
+            .await
+            .unwrap_or_else(|_| (Err(anyhow!("the scanner stopped")), vec![])),
+        Err(_) => (Err(anyhow!("the scanner isn't running")), vec![]),
+    };
+    if let Err(err) = result {
+        log::warn!("module {} rejected by the scanner: {}", specifier, err);
+        return Err(custom_error(
+            "PermissionDenied",
+            format!("Import '{specifier}' was rejected by the module scanner: {err}"),
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CommandScanner, ModuleScanner};
+    use deno_core::ModuleSpecifier;
+    use std::time::Duration;
+
+    fn sh(script: &str, timeout: Duration) -> CommandScanner {
+        CommandScanner {
+            program: "sh".into(),
+            args: vec!["-c".to_string(), script.to_string(), "scanner".to_string()],
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_command_scanner() {
+        let specifier = ModuleSpecifier::parse("https://esm.sh/left-pad@1").unwrap();
+        let scanner = sh(
+            r#"if grep -q EVIL; then echo "malware found in $1" >&2; exit 1; fi"#,
+            Duration::from_secs(10),
+        );
+        assert!(scanner.scan(&specifier, b"export default 1;").is_ok());
+        let err = scanner
+            .scan(&specifier, b"/* EVIL */ export default 1;")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malware found in https://esm.sh/left-pad@1"
+        );
+
+        let slow = sh("sleep 5", Duration::from_millis(100));
+        let err = slow.scan(&specifier, b"export default 1;").unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        // more than a pipe holds
+        let verbose = sh("head -c 1000000 /dev/zero >&2", Duration::from_secs(10));
+        assert!(verbose.scan(&specifier, b"export default 1;").is_ok());
+    }
+}
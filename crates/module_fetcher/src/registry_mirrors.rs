@@ -0,0 +1,282 @@
+use deno_core::anyhow::{bail, Error};
+use deno_core::parking_lot::Mutex;
+use deno_core::serde_json;
+use deno_core::url::Url;
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Consecutive failed downloads after which a registry is considered down.
+const FAILURES_TO_DOWN: u32 = 3;
+/// How long a registry that's down is only tried after the others.
+const DOWN_FOR: Duration = Duration::from_secs(30);
+
+static REGISTRY_MIRRORS: OnceLock<RegistryMirrors> = OnceLock::new();
+
+/// Health of a registry, from the outcome of the downloads made from it.
+#[derive(Debug, Default)]
+struct RegistryHealth {
+    consecutive_failures: AtomicU32,
+    successes: AtomicU64,
+    failures: AtomicU64,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl RegistryHealth {
+    fn is_up(&self) -> bool {
+        match *self.down_until.lock() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn record(&self, ok: bool) {
+        if ok {
+            self.successes.fetch_add(1, Ordering::Relaxed);
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+            *self.down_until.lock() = None;
+            return;
+        }
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= FAILURES_TO_DOWN {
+            *self.down_until.lock() = Some(Instant::now() + DOWN_FOR);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Registry {
+    base: String,
+    health: RegistryHealth,
+}
+
+/// An origin and the mirrors its modules can also be downloaded from.
+#[derive(Debug)]
+struct MirroredOrigin {
+    /// the origin itself first, then its mirrors in order
+    registries: Vec<Registry>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistryStatus {
+    pub origin: String,
+    pub base: String,
+    pub up: bool,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+/// Mirrors of the registries modules are downloaded from, eg:
+/// `{ "https://esm.sh/": ["https://esm-mirror.internal/"] }`. When downloads
+/// from an origin fail, its mirrors are tried in order.
+#[derive(Debug, Default)]
+pub struct RegistryMirrors {
+    origins: Vec<MirroredOrigin>,
+}
+
+fn normalize_base(base: &str) -> Result<String, Error> {
+    let base = format!("{}/", base.trim_end_matches('/'));
+    let url = Url::parse(&base)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("registry '{}' isn't an http(s) url", base);
+    }
+    Ok(base)
+}
+
+impl RegistryMirrors {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let config: IndexMap<String, Vec<String>> = serde_json::from_str(json)?;
+        let mut origins = vec![];
+        for (origin, mirrors) in config {
+            if mirrors.is_empty() {
+                bail!("no mirrors given for '{}'", origin);
+            }
+            let registries = std::iter::once(origin)
+                .chain(mirrors)
+                .map(|base| {
+                    Ok(Registry {
+                        base: normalize_base(&base)?,
+                        health: Default::default(),
+                    })
+                })
+                .collect::<Result<_, Error>>()?;
+            origins.push(MirroredOrigin { registries });
+        }
+        Ok(Self { origins })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Where to download `url` from, in order: the registries up first (in the
+    /// order they were given), then those that are down as a last resort.
+    pub fn sources(&'static self, url: &Url) -> Vec<ModuleSource> {
+        let Some(origin) = self
+            .origins
+            .iter()
+            .find(|origin| url.as_str().starts_with(&origin.registries[0].base))
+        else {
+            return vec![ModuleSource::direct(url.clone())];
+        };
+        let path = &url.as_str()[origin.registries[0].base.len()..];
+        let mut sources: Vec<_> = origin
+            .registries
+            .iter()
+            .filter_map(|registry| {
+                let url = Url::parse(&format!("{}{}", registry.base, path)).ok()?;
+                Some(ModuleSource {
+                    url,
+                    maybe_registry: Some((origin, registry)),
+                })
+            })
+            .collect();
+        // stable, keeps the configured order within the up and down ones
+        sources.sort_by_key(|source| !source.is_up());
+        sources
+    }
+
+    pub fn status(&self) -> Vec<RegistryStatus> {
+        self.origins
+            .iter()
+            .flat_map(|origin| {
+                let name = &origin.registries[0].base;
+                origin
+                    .registries
+                    .iter()
+                    .map(move |registry| RegistryStatus {
+                        origin: name.clone(),
+                        base: registry.base.clone(),
+                        up: registry.health.is_up(),
+                        successes: registry.health.successes.load(Ordering::Relaxed),
+                        failures: registry.health.failures.load(Ordering::Relaxed),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// A url a module can be downloaded from.
+pub struct ModuleSource {
+    pub url: Url,
+    maybe_registry: Option<(&'static MirroredOrigin, &'static Registry)>,
+}
+
+impl ModuleSource {
+    pub fn direct(url: Url) -> Self {
+        Self {
+            url,
+            maybe_registry: None,
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.maybe_registry
+            .map(|(_, registry)| registry.health.is_up())
+            .unwrap_or(true)
+    }
+
+    /// Records whether the download succeeded, for the health of the registry.
+    pub fn record(&self, ok: bool) {
+        if let Some((_, registry)) = self.maybe_registry {
+            registry.health.record(ok);
+        }
+    }
+
+    /// The url on the origin of a url of this registry (eg: a redirect given by
+    /// a mirror), so modules keep being identified by their origin's urls.
+    pub fn to_origin(&self, url: Url) -> Url {
+        let Some((origin, registry)) = self.maybe_registry else {
+            return url;
+        };
+        match url.as_str().strip_prefix(registry.base.as_str()) {
+            Some(path) => {
+                Url::parse(&format!("{}{}", origin.registries[0].base, path)).unwrap_or(url)
+            }
+            None => url,
+        }
+    }
+}
+
+pub fn set_registry_mirrors(mirrors: RegistryMirrors) {
+    let _ = REGISTRY_MIRRORS.set(mirrors);
+}
+
+pub fn registry_mirrors() -> Option<&'static RegistryMirrors> {
+    REGISTRY_MIRRORS.get()
+}
+
+/// Where to download `url` from, the url itself unless its origin is mirrored.
+pub fn module_sources(url: &Url) -> Vec<ModuleSource> {
+    match registry_mirrors() {
+        Some(mirrors) => mirrors.sources(url),
+        None => vec![ModuleSource::direct(url.clone())],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RegistryMirrors;
+    use deno_core::url::Url;
+
+    fn urls(mirrors: &'static RegistryMirrors, url: &str) -> Vec<String> {
+        mirrors
+            .sources(&Url::parse(url).unwrap())
+            .into_iter()
+            .map(|source| source.url.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_registry_mirrors() {
+        let mirrors: &'static RegistryMirrors = Box::leak(Box::new(
+            RegistryMirrors::parse(
+                r#"{ "https://esm.sh": ["https://mirror-a.internal/esm/", "https://mirror-b.internal"] }"#,
+            )
+            .unwrap(),
+        ));
+        assert_eq!(
+            urls(mirrors, "https://esm.sh/zod@3?target=deno"),
+            [
+                "https://esm.sh/zod@3?target=deno",
+                "https://mirror-a.internal/esm/zod@3?target=deno",
+                "https://mirror-b.internal/zod@3?target=deno",
+            ]
+        );
+        assert_eq!(
+            urls(mirrors, "https://deno.land/std/http/server.ts"),
+            ["https://deno.land/std/http/server.ts"]
+        );
+
+        // the origin is tried last once it's down, until it recovers
+        let sources = mirrors.sources(&Url::parse("https://esm.sh/zod@3").unwrap());
+        for _ in 0..3 {
+            sources[0].record(false);
+        }
+        assert_eq!(
+            urls(mirrors, "https://esm.sh/zod@3"),
+            [
+                "https://mirror-a.internal/esm/zod@3",
+                "https://mirror-b.internal/zod@3",
+                "https://esm.sh/zod@3",
+            ]
+        );
+        assert_eq!(
+            sources[1]
+                .to_origin(Url::parse("https://mirror-a.internal/esm/zod@3.22.4").unwrap())
+                .as_str(),
+            "https://esm.sh/zod@3.22.4"
+        );
+        sources[0].record(true);
+        assert!(mirrors.status().iter().all(|status| status.up));
+        assert_eq!(mirrors.status()[0].failures, 3);
+
+        assert!(RegistryMirrors::parse(r#"{ "https://esm.sh/": [] }"#).is_err());
+        assert!(RegistryMirrors::parse(r#"{ "https://esm.sh/": ["ftp://mirror"] }"#).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{
+    AsyncRefCell, CancelFuture, CancelHandle, OpState, RcRef, Resource, ResourceId, ToJsBuffer,
+};
+use sb_worker_context::ai::{AiError, AiRequest, AiUsage, SharedAiGateway, WorkerAi};
+use sb_worker_context::embeddings::{EmbedOptions, SharedEmbeddingModels};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc;
+
+fn worker_ai(state: &OpState) -> Result<(SharedAiGateway, String), AnyError> {
+    state
+        .try_borrow::<WorkerAi>()
+        .map(|ai| (ai.gateway.clone(), ai.service.clone()))
+        .ok_or_else(|| {
+            custom_error(
+                "NotSupported",
+                "AI providers aren't configured for the runtime",
+            )
+        })
+}
+
+struct AiResponseBodyResource {
+    rx: AsyncRefCell<mpsc::Receiver<Result<Vec<u8>, String>>>,
+    cancel: CancelHandle,
+}
+
+impl Resource for AiResponseBodyResource {
+    fn name(&self) -> Cow<str> {
+        "aiResponseBody".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+// `(rid of the body, status, content type)` of the provider's response
+#[op2(async)]
+#[serde]
+pub async fn op_ai_request(
+    state: Rc<RefCell<OpState>>,
+    #[serde] req: AiRequest,
+) -> Result<(ResourceId, u16, Option<String>), AnyError> {
+    let (gateway, service) = worker_ai(&state.borrow())?;
+    let res = gateway
+        .request(&service, req)
+        .await
+        .map_err(|err| match err {
+            AiError::QuotaExceeded(msg) => custom_error("DOMExceptionQuotaExceededError", msg),
+            AiError::Other(err) => custom_error("AiError", format!("{:#}", err)),
+        })?;
+    let rid = state
+        .borrow_mut()
+        .resource_table
+        .add(AiResponseBodyResource {
+            rx: AsyncRefCell::new(res.body),
+            cancel: CancelHandle::default(),
+        });
+    Ok((rid, res.status, res.content_type))
+}
+
+// Next chunk of the body, or null once it ended
+#[op2(async)]
+#[serde]
+pub async fn op_ai_read(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<AiResponseBodyResource>(rid)?;
+    let mut rx = RcRef::map(&resource, |r| &r.rx).borrow_mut().await;
+    let cancel = RcRef::map(&resource, |r| &r.cancel);
+    match rx.recv().or_cancel(cancel).await {
+        Ok(Some(Ok(chunk))) => Ok(Some(chunk.into())),
+        Ok(Some(Err(err))) => Err(custom_error("AiError", err)),
+        Ok(None) | Err(_) => Ok(None),
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_embedding_models(state: &mut OpState) -> Vec<String> {
+    match state.try_borrow::<SharedEmbeddingModels>() {
+        Some(models) => models.models(),
+        None => vec![],
+    }
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_embed(
+    state: Rc<RefCell<OpState>>,
+    #[string] model: String,
+    #[string] text: String,
+    #[serde] opts: EmbedOptions,
+) -> Result<Vec<f32>, AnyError> {
+    let embed = {
+        let state = state.borrow();
+        let Some(models) = state.try_borrow::<SharedEmbeddingModels>() else {
+            return Err(custom_error(
+                "NotSupported",
+                "embedding models aren't enabled for the runtime",
+            ));
+        };
+        models.embed(&model, text, opts)
+    };
+    embed
+        .await
+        .map_err(|err| custom_error("AiError", format!("{:#}", err)))
+}
+
+#[op2]
+#[serde]
+pub fn op_ai_usage(state: &mut OpState) -> Result<AiUsage, AnyError> {
+    let (gateway, service) = worker_ai(state)?;
+    Ok(gateway.usage(&service))
+}
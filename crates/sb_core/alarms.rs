@@ -0,0 +1,65 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::Value;
+use deno_core::OpState;
+use sb_worker_context::alarms::{Alarm, WorkerAlarms};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleAlarmOptions {
+    fire_at_ms: u64,
+    path: String,
+    #[serde(default)]
+    payload: Value,
+}
+
+fn worker_alarms(state: &OpState) -> Result<&WorkerAlarms, AnyError> {
+    state.try_borrow::<WorkerAlarms>().ok_or_else(|| {
+        custom_error(
+            "NotSupported",
+            "alarms are not enabled (start the runtime with an alarm store)",
+        )
+    })
+}
+
+#[op2]
+#[string]
+pub fn op_alarm_schedule(
+    state: &mut OpState,
+    #[serde] opts: ScheduleAlarmOptions,
+) -> Result<String, AnyError> {
+    let alarms = worker_alarms(state)?;
+    if !opts.path.starts_with('/') {
+        return Err(custom_error(
+            "TypeError",
+            format!("alarm path must start with '/' (got {})", opts.path),
+        ));
+    }
+
+    let alarm = Alarm {
+        id: uuid::Uuid::new_v4().to_string(),
+        service_path: alarms.service_path.clone(),
+        import_map_path: alarms.import_map_path.clone(),
+        entrypoint: alarms.entrypoint.clone(),
+        path: opts.path,
+        payload: opts.payload,
+        fire_at_ms: opts.fire_at_ms,
+        attempt: 0,
+    };
+    alarms.store.insert(&alarm)?;
+    Ok(alarm.id)
+}
+
+#[op2(fast)]
+pub fn op_alarm_cancel(state: &mut OpState, #[string] id: &str) -> Result<bool, AnyError> {
+    let alarms = worker_alarms(state)?;
+    alarms.store.cancel(&alarms.service_path, id)
+}
+
+#[op2]
+#[serde]
+pub fn op_alarm_list(state: &mut OpState) -> Result<Vec<Alarm>, AnyError> {
+    let alarms = worker_alarms(state)?;
+    alarms.store.list(&alarms.service_path)
+}
@@ -0,0 +1,288 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{AsyncRefCell, JsBuffer, OpState, RcRef, Resource, ResourceId, ToJsBuffer};
+use flate2::Compression;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecFormat {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "deflate")]
+    Deflate,
+    #[serde(rename = "deflate-raw")]
+    DeflateRaw,
+    #[serde(rename = "br")]
+    Brotli,
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+impl CodecFormat {
+    // (min, default, max) compression level
+    fn levels(&self) -> (i32, i32, i32) {
+        match self {
+            CodecFormat::Gzip | CodecFormat::Deflate | CodecFormat::DeflateRaw => (0, 6, 9),
+            CodecFormat::Brotli => (0, 6, 11),
+            CodecFormat::Zstd => (1, 3, 22),
+        }
+    }
+}
+
+// Output of a codec, drained after every write
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+trait Codec: Write + Send {
+    // writes the end of the stream, failing if a decompressed one is truncated
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "compressed stream is truncated",
+    )
+}
+
+impl Codec for flate2::write::GzEncoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for flate2::write::ZlibEncoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for flate2::write::DeflateEncoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for flate2::write::GzDecoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for flate2::write::ZlibDecoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for flate2::write::DeflateDecoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for brotli::CompressorWriter<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).into_inner();
+        Ok(())
+    }
+}
+
+impl Codec for brotli::DecompressorWriter<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).into_inner().map(drop).map_err(|_| truncated())
+    }
+}
+
+impl Codec for zstd::stream::write::Encoder<'static, Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish().map(drop)
+    }
+}
+
+impl Codec for zstd::stream::write::Decoder<'static, Sink> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+fn new_codec(
+    format: CodecFormat,
+    decompress: bool,
+    level: i32,
+    sink: Sink,
+) -> io::Result<Box<dyn Codec>> {
+    let flate_level = Compression::new(level as u32);
+    Ok(match (format, decompress) {
+        (CodecFormat::Gzip, false) => Box::new(flate2::write::GzEncoder::new(sink, flate_level)),
+        (CodecFormat::Deflate, false) => {
+            Box::new(flate2::write::ZlibEncoder::new(sink, flate_level))
+        }
+        (CodecFormat::DeflateRaw, false) => {
+            Box::new(flate2::write::DeflateEncoder::new(sink, flate_level))
+        }
+        (CodecFormat::Brotli, false) => {
+            Box::new(brotli::CompressorWriter::new(sink, 4096, level as u32, 22))
+        }
+        (CodecFormat::Zstd, false) => Box::new(zstd::stream::write::Encoder::new(sink, level)?),
+        (CodecFormat::Gzip, true) => Box::new(flate2::write::GzDecoder::new(sink)),
+        (CodecFormat::Deflate, true) => Box::new(flate2::write::ZlibDecoder::new(sink)),
+        (CodecFormat::DeflateRaw, true) => Box::new(flate2::write::DeflateDecoder::new(sink)),
+        (CodecFormat::Brotli, true) => Box::new(brotli::DecompressorWriter::new(sink, 4096)),
+        (CodecFormat::Zstd, true) => Box::new(zstd::stream::write::Decoder::new(sink)?),
+    })
+}
+
+fn codec_error(err: io::Error) -> AnyError {
+    custom_error("TypeError", format!("invalid compressed data: {}", err))
+}
+
+struct CodecResource {
+    // taken while a chunk is processed on the blocking pool
+    codec: AsyncRefCell<Option<Box<dyn Codec>>>,
+    sink: Sink,
+}
+
+impl Resource for CodecResource {
+    fn name(&self) -> Cow<str> {
+        "streamCodec".into()
+    }
+}
+
+#[op2]
+#[smi]
+pub fn op_codec_new(
+    state: &mut OpState,
+    #[serde] format: CodecFormat,
+    decompress: bool,
+    #[serde] level: Option<i32>,
+) -> Result<ResourceId, AnyError> {
+    let (min, default, max) = format.levels();
+    let level = level.unwrap_or(default);
+    if level < min || level > max {
+        return Err(custom_error(
+            "RangeError",
+            format!("compression level must be between {} and {}", min, max),
+        ));
+    }
+    let sink = Sink::default();
+    let codec = new_codec(format, decompress, level, sink.clone())?;
+    Ok(state.resource_table.add(CodecResource {
+        codec: AsyncRefCell::new(Some(codec)),
+        sink,
+    }))
+}
+
+// Chunks are (de)compressed on the blocking pool, off the worker's event loop
+#[op2(async)]
+#[serde]
+pub async fn op_codec_write(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[buffer] chunk: JsBuffer,
+) -> Result<ToJsBuffer, AnyError> {
+    let resource = state.borrow().resource_table.get::<CodecResource>(rid)?;
+    let mut slot = RcRef::map(&resource, |r| &r.codec).borrow_mut().await;
+    let mut codec = slot
+        .take()
+        .ok_or_else(|| custom_error("BadResource", "stream ended"))?;
+    let (codec, res) = tokio::task::spawn_blocking(move || {
+        let res = codec.write_all(&chunk);
+        (codec, res)
+    })
+    .await?;
+    *slot = Some(codec);
+    res.map_err(codec_error)?;
+    Ok(resource.sink.take().into())
+}
+
+// Rest of the output, closing the stream
+#[op2(async)]
+#[serde]
+pub async fn op_codec_finish(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<ToJsBuffer, AnyError> {
+    let resource = state
+        .borrow_mut()
+        .resource_table
+        .take::<CodecResource>(rid)?;
+    let codec = RcRef::map(&resource, |r| &r.codec)
+        .borrow_mut()
+        .await
+        .take()
+        .ok_or_else(|| custom_error("BadResource", "stream ended"))?;
+    tokio::task::spawn_blocking(move || codec.finish())
+        .await?
+        .map_err(codec_error)?;
+    Ok(resource.sink.take().into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{new_codec, CodecFormat, Sink};
+    use std::io::Write;
+
+    fn run(format: CodecFormat, decompress: bool, input: &[u8]) -> Vec<u8> {
+        let sink = Sink::default();
+        let mut codec = new_codec(format, decompress, format.levels().1, sink.clone()).unwrap();
+        let mut output = vec![];
+        for chunk in input.chunks(100) {
+            codec.write_all(chunk).unwrap();
+            output.extend(sink.take());
+        }
+        codec.finish().unwrap();
+        output.extend(sink.take());
+        output
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let input = "edge runtime ".repeat(1000).into_bytes();
+        for format in [
+            CodecFormat::Gzip,
+            CodecFormat::Deflate,
+            CodecFormat::DeflateRaw,
+            CodecFormat::Brotli,
+            CodecFormat::Zstd,
+        ] {
+            let compressed = run(format, false, &input);
+            assert!(compressed.len() < input.len() / 10, "{:?}", format);
+            assert_eq!(run(format, true, &compressed), input, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_truncated_brotli() {
+        let input = "edge runtime ".repeat(1000).into_bytes();
+        let compressed = run(CodecFormat::Brotli, false, &input);
+        let sink = Sink::default();
+        let mut codec = new_codec(CodecFormat::Brotli, true, 0, sink).unwrap();
+        codec
+            .write_all(&compressed[..compressed.len() / 2])
+            .unwrap();
+        assert!(codec.finish().is_err());
+    }
+}
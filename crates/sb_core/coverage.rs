@@ -0,0 +1,117 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::{self, json};
+use deno_core::{LocalInspectorSession, OpState};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Inspector session collecting the V8 coverage of a worker run by the test
+// runner, started before its main module is loaded
+pub struct CoverageSession(pub LocalInspectorSession);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageRange {
+    // UTF-16 offsets in the (emitted) source of the script
+    pub start_offset: usize,
+    pub end_offset: usize,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCoverage {
+    pub function_name: String,
+    // the first range covers the whole function, the others its blocks
+    pub ranges: Vec<CoverageRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptCoverage {
+    pub url: String,
+    // as run by V8, with the inline source map of transpiled modules
+    pub source: String,
+    pub functions: Vec<FunctionCoverage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfilerScriptCoverage {
+    script_id: String,
+    url: String,
+    functions: Vec<FunctionCoverage>,
+}
+
+#[derive(Deserialize)]
+struct TakePreciseCoverageResult {
+    result: Vec<ProfilerScriptCoverage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetScriptSourceResult {
+    script_source: String,
+}
+
+// Needs the worker's event loop to be polled, inspector messages are dispatched
+// by it
+pub async fn start_coverage(session: &mut LocalInspectorSession) -> Result<(), AnyError> {
+    // the sources of the scripts are only available with the debugger enabled
+    session.post_message::<()>("Debugger.enable", None).await?;
+    session.post_message::<()>("Profiler.enable", None).await?;
+    session
+        .post_message(
+            "Profiler.startPreciseCoverage",
+            Some(json!({ "callCount": true, "detailed": true })),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn take_coverage(
+    session: &mut LocalInspectorSession,
+) -> Result<Vec<ScriptCoverage>, AnyError> {
+    let taken: TakePreciseCoverageResult = serde_json::from_value(
+        session
+            .post_message::<()>("Profiler.takePreciseCoverage", None)
+            .await?,
+    )?;
+
+    let mut scripts = vec![];
+    // only local modules, not the runtime's or remote ones
+    for script in taken.result {
+        if !script.url.starts_with("file:///") {
+            continue;
+        }
+        let source: GetScriptSourceResult = serde_json::from_value(
+            session
+                .post_message(
+                    "Debugger.getScriptSource",
+                    Some(json!({ "scriptId": script.script_id })),
+                )
+                .await?,
+        )?;
+        scripts.push(ScriptCoverage {
+            url: script.url,
+            source: source.script_source,
+            functions: script.functions,
+        });
+    }
+    Ok(scripts)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_test_coverage_take(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Vec<ScriptCoverage>, AnyError> {
+    let mut session = state
+        .borrow_mut()
+        .try_take::<CoverageSession>()
+        .ok_or_else(|| custom_error("NotSupported", "coverage is not enabled"))?;
+    let res = take_coverage(&mut session.0).await;
+    state.borrow_mut().put(session);
+    res
+}
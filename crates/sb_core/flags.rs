@@ -0,0 +1,54 @@
+use deno_core::error::AnyError;
+use deno_core::op2;
+use deno_core::serde_json::{Map, Value};
+use deno_core::OpState;
+use sb_worker_context::flags::WorkerFeatureFlags;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Flags are empty unless the runtime was started with a flag source
+#[op2]
+#[serde]
+pub fn op_feature_flags(state: &mut OpState, #[serde] key: Option<String>) -> Map<String, Value> {
+    match state.try_borrow::<WorkerFeatureFlags>() {
+        Some(worker_flags) => worker_flags
+            .flags
+            .borrow()
+            .evaluate_all(&worker_flags.service, key.as_deref()),
+        None => Map::new(),
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_feature_flag(
+    state: &mut OpState,
+    #[string] name: String,
+    #[serde] key: Option<String>,
+) -> Option<Value> {
+    let worker_flags = state.try_borrow::<WorkerFeatureFlags>()?;
+    let flags = worker_flags.flags.borrow();
+    flags.evaluate(&name, &worker_flags.service, key.as_deref())
+}
+
+// Resolves once the flags change. Resolves to false if they never will.
+#[op2(async)]
+pub async fn op_feature_flags_changed(state: Rc<RefCell<OpState>>) -> Result<bool, AnyError> {
+    let Some(mut flags) = state
+        .borrow()
+        .try_borrow::<WorkerFeatureFlags>()
+        .map(|worker_flags| worker_flags.flags.clone())
+    else {
+        return Ok(false);
+    };
+
+    if flags.changed().await.is_err() {
+        return Ok(false);
+    }
+
+    // mark the new version as seen by the worker
+    if let Some(worker_flags) = state.borrow_mut().try_borrow_mut::<WorkerFeatureFlags>() {
+        worker_flags.flags = flags;
+    }
+    Ok(true)
+}
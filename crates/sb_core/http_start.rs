@@ -4,10 +4,15 @@ use deno_core::error::bad_resource;
 use deno_core::error::bad_resource_id;
 use deno_core::error::AnyError;
 use deno_core::op2;
+use deno_core::ByteString;
 use deno_core::OpState;
 use deno_core::ResourceId;
 use deno_http::http_create_conn_resource;
 use deno_net::io::UnixStreamResource;
+use hyper::HeaderMap;
+use sb_worker_context::request_metadata::{
+    PendingRequestMetadata, PendingRequestRoutes, RequestHeaders,
+};
 
 #[op2(fast)]
 #[smi]
@@ -32,4 +37,147 @@ fn op_http_start(
     Err(bad_resource_id())
 }
 
-deno_core::extension!(sb_core_http, ops = [op_http_start]);
+// Metadata the main worker attached to the request of an accepted connection
+#[op2]
+#[string]
+fn op_http_request_metadata_take(
+    state: &mut OpState,
+    #[smi] stream_rid: ResourceId,
+) -> Option<String> {
+    state
+        .try_borrow_mut::<PendingRequestMetadata>()?
+        .0
+        .remove(&stream_rid)
+        .map(|metadata| metadata.as_str().to_string())
+}
+
+// File-based route the request of an accepted connection matched
+#[op2]
+#[string]
+fn op_http_request_route_take(
+    state: &mut OpState,
+    #[smi] stream_rid: ResourceId,
+) -> Option<String> {
+    state
+        .try_borrow_mut::<PendingRequestRoutes>()?
+        .0
+        .remove(&stream_rid)
+        .map(|route| route.0)
+}
+
+// Cookies are combined into a single header, separated by semicolons, like
+// deno_http does
+fn header_separator(name: &str) -> &'static str {
+    if name.eq_ignore_ascii_case("cookie") {
+        "; "
+    } else {
+        ", "
+    }
+}
+
+// Values of a header combined like `Headers.get` does. Values are byte strings,
+// each byte is a char.
+fn combined_header(headers: &HeaderMap, name: &str) -> Option<String> {
+    let mut values = headers.get_all(name).iter().peekable();
+    values.peek()?;
+    let separator = header_separator(name);
+    let mut combined = String::new();
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            combined.push_str(separator);
+        }
+        combined.extend(value.as_bytes().iter().map(|b| *b as char));
+    }
+    Some(combined)
+}
+
+// A header of the request of an accepted connection
+#[op2]
+#[string]
+fn op_http_request_header(
+    state: &mut OpState,
+    #[smi] stream_rid: ResourceId,
+    #[string] name: &str,
+) -> Option<String> {
+    let headers = state.try_borrow::<RequestHeaders>()?.0.get(&stream_rid)?;
+    combined_header(headers, name)
+}
+
+// Several headers of the request of an accepted connection, in one call
+#[op2]
+#[serde]
+fn op_get_headers(
+    state: &mut OpState,
+    #[smi] stream_rid: ResourceId,
+    #[serde] names: Vec<String>,
+) -> Vec<Option<String>> {
+    let maybe_headers = state
+        .try_borrow::<RequestHeaders>()
+        .and_then(|headers| headers.0.get(&stream_rid));
+    names
+        .iter()
+        .map(|name| maybe_headers.and_then(|headers| combined_header(headers, name)))
+        .collect()
+}
+
+// Every header of the request of an accepted connection, as the header list of
+// the request's `Headers`. Those sent over the connection aren't included.
+#[op2]
+#[serde]
+fn op_http_request_headers_entries(
+    state: &mut OpState,
+    #[smi] stream_rid: ResourceId,
+) -> Vec<(ByteString, ByteString)> {
+    let Some(headers) = state
+        .try_borrow::<RequestHeaders>()
+        .and_then(|headers| headers.0.get(&stream_rid))
+    else {
+        return vec![];
+    };
+    let mut entries = Vec::with_capacity(headers.keys_len());
+    for name in headers.keys() {
+        if name == hyper::header::COOKIE {
+            let cookies: Vec<&[u8]> = headers.get_all(name).iter().map(|v| v.as_bytes()).collect();
+            entries.push((name.as_str().into(), cookies.join("; ".as_bytes()).into()));
+        } else {
+            for value in headers.get_all(name) {
+                entries.push((name.as_str().into(), value.as_bytes().into()));
+            }
+        }
+    }
+    entries
+}
+
+#[op2(fast)]
+fn op_http_request_has_headers(state: &mut OpState, #[smi] stream_rid: ResourceId) -> bool {
+    state
+        .try_borrow::<RequestHeaders>()
+        .map(|headers| headers.0.contains_key(&stream_rid))
+        .unwrap_or(false)
+}
+
+#[op2(fast)]
+fn op_http_request_headers_drop(state: &mut OpState, #[smi] stream_rid: ResourceId) {
+    if let Some(headers) = state.try_borrow_mut::<RequestHeaders>() {
+        headers.0.remove(&stream_rid);
+    }
+}
+
+deno_core::extension!(
+    sb_core_http,
+    ops = [
+        op_http_start,
+        op_http_request_metadata_take,
+        op_http_request_route_take,
+        op_http_request_has_headers,
+        op_http_request_header,
+        op_get_headers,
+        op_http_request_headers_entries,
+        op_http_request_headers_drop
+    ],
+    state = |state| {
+        state.put(PendingRequestMetadata::default());
+        state.put(PendingRequestRoutes::default());
+        state.put(RequestHeaders::default());
+    }
+);
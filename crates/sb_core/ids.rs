@@ -0,0 +1,189 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::OpState;
+use ring::rand::{SecureRandom, SystemRandom};
+use sb_worker_context::sequences::WorkerSequences;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// Random bits following a millisecond timestamp. Ids generated within the same
+// millisecond (or after the clock went back) increment the previous random
+// bits instead, so a worker's ids always sort in the order they were made.
+struct Monotonic {
+    bits: u32,
+    last_ms: u64,
+    last: u128,
+}
+
+impl Monotonic {
+    fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            last_ms: 0,
+            last: 0,
+        }
+    }
+
+    fn next(&mut self, now_ms: u64, random: u128) -> (u64, u128) {
+        let mask = (1u128 << self.bits) - 1;
+        if now_ms > self.last_ms {
+            self.last_ms = now_ms;
+            self.last = random & mask;
+        } else if self.last == mask {
+            // out of ids for this millisecond, borrows the next one
+            self.last_ms += 1;
+            self.last = random & mask;
+        } else {
+            self.last += 1;
+        }
+        (self.last_ms, self.last)
+    }
+}
+
+// Per worker state of `EdgeRuntime.ids`
+pub struct IdGenerator {
+    rng: SystemRandom,
+    ulid: Monotonic,
+    uuid: Monotonic,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self {
+            rng: SystemRandom::new(),
+            ulid: Monotonic::new(80),
+            uuid: Monotonic::new(74),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn encode_ulid(ms: u64, random: u128) -> String {
+    let value = ((ms as u128) << 80) | random;
+    (0..26)
+        .map(|i| CROCKFORD[((value >> (125 - 5 * i)) & 31) as usize] as char)
+        .collect()
+}
+
+fn encode_uuidv7(ms: u64, random: u128) -> String {
+    let rand_a = (random >> 62) & 0xfff;
+    let rand_b = random & ((1 << 62) - 1);
+    let value = ((ms as u128 & 0xffff_ffff_ffff) << 80)
+        | (0x7 << 76)
+        | (rand_a << 64)
+        | (0b10 << 62)
+        | rand_b;
+    uuid::Uuid::from_u128(value).to_string()
+}
+
+impl IdGenerator {
+    fn random(&self) -> Result<u128, AnyError> {
+        let mut bytes = [0u8; 16];
+        self.rng
+            .fill(&mut bytes)
+            .map_err(|_| custom_error("Error", "failed to generate random bytes"))?;
+        Ok(u128::from_be_bytes(bytes))
+    }
+
+    fn ulid(&mut self, now_ms: u64) -> Result<String, AnyError> {
+        let random = self.random()?;
+        let (ms, random) = self.ulid.next(now_ms, random);
+        Ok(encode_ulid(ms, random))
+    }
+
+    fn uuidv7(&mut self, now_ms: u64) -> Result<String, AnyError> {
+        let random = self.random()?;
+        let (ms, random) = self.uuid.next(now_ms, random);
+        Ok(encode_uuidv7(ms, random))
+    }
+}
+
+#[op2]
+#[string]
+pub fn op_ids_ulid(state: &mut OpState) -> Result<String, AnyError> {
+    state.borrow_mut::<IdGenerator>().ulid(now_ms())
+}
+
+#[op2]
+#[string]
+pub fn op_ids_uuidv7(state: &mut OpState) -> Result<String, AnyError> {
+    state.borrow_mut::<IdGenerator>().uuidv7(now_ms())
+}
+
+fn worker_sequences<'a>(state: &'a OpState, name: &str) -> Result<&'a WorkerSequences, AnyError> {
+    if name.is_empty() || name.len() > 128 {
+        return Err(custom_error(
+            "TypeError",
+            "sequence names must have between 1 and 128 characters",
+        ));
+    }
+    state
+        .try_borrow::<WorkerSequences>()
+        .ok_or_else(|| custom_error("NotSupported", "sequences aren't available to this worker"))
+}
+
+#[op2]
+#[serde]
+pub fn op_ids_next(state: &mut OpState, #[string] name: &str, by: u32) -> Result<u64, AnyError> {
+    let worker = worker_sequences(state, name)?;
+    Ok(worker.sequences.next(&worker.service, name, by as u64))
+}
+
+#[op2]
+#[serde]
+pub fn op_ids_current(state: &mut OpState, #[string] name: &str) -> Result<u64, AnyError> {
+    let worker = worker_sequences(state, name)?;
+    Ok(worker.sequences.current(&worker.service, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_ulid, encode_uuidv7, IdGenerator, Monotonic};
+
+    #[test]
+    fn test_monotonic() {
+        let mut ids = Monotonic::new(8);
+        assert_eq!(ids.next(10, 0x1f0), (10, 0xf0));
+        // same millisecond, or the clock went back
+        assert_eq!(ids.next(10, 0x33), (10, 0xf1));
+        assert_eq!(ids.next(9, 0x33), (10, 0xf2));
+        // out of ids
+        let mut ids = Monotonic::new(8);
+        ids.next(10, 0xff);
+        assert_eq!(ids.next(10, 0x12), (11, 0x12));
+        assert_eq!(ids.next(12, 0x34), (12, 0x34));
+    }
+
+    #[test]
+    fn test_encode_ids() {
+        assert_eq!(encode_ulid(0, 0), "00000000000000000000000000");
+        assert_eq!(
+            encode_ulid(1_469_918_176_385, 0),
+            "01ARYZ6S410000000000000000"
+        );
+        assert_eq!(
+            encode_uuidv7(0x0189_6a1b_3c4d, (0xabc << 62) | 0x1234),
+            "01896a1b-3c4d-7abc-8000-000000001234"
+        );
+    }
+
+    #[test]
+    fn test_ids_sort() {
+        let mut ids = IdGenerator::default();
+        let ulids: Vec<_> = (0..100).map(|_| ids.ulid(1000).unwrap()).collect();
+        let uuids: Vec<_> = (0..100).map(|_| ids.uuidv7(1000).unwrap()).collect();
+        for ids in [ulids, uuids] {
+            let mut sorted = ids.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted, ids);
+        }
+    }
+}
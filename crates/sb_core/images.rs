@@ -0,0 +1,347 @@
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::op2;
+use deno_core::{JsBuffer, OpState, ToJsBuffer};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+const DEFAULT_QUALITY: u8 = 80;
+// fastest speed ravif still produces reasonable images with
+const AVIF_SPEED: u8 = 8;
+// decoded images are held as 8-bit RGBA at most
+const BYTES_PER_PIXEL: u64 = 4;
+
+// Caps of the `EdgeRuntime.images` ops. Workers only get `EdgeRuntime.images` when
+// the runtime was started with image limits.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageLimits {
+    // memory a single decode, resize or encode may allocate
+    pub max_memory_bytes: u64,
+}
+
+impl ImageLimits {
+    pub fn from_mb(max_memory_mb: u64) -> Self {
+        Self {
+            max_memory_bytes: max_memory_mb.saturating_mul(1024 * 1024),
+        }
+    }
+
+    fn check_dimensions(&self, width: u32, height: u32) -> Result<(), AnyError> {
+        if width as u64 * height as u64 * BYTES_PER_PIXEL > self.max_memory_bytes {
+            return Err(custom_error(
+                "RangeError",
+                format!("{}x{} image exceeds the image memory limit", width, height),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageOutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl ImageOutputFormat {
+    fn from_input(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Jpeg => ImageOutputFormat::Jpeg,
+            ImageFormat::WebP => ImageOutputFormat::Webp,
+            _ => ImageOutputFormat::Png,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFit {
+    // fits within the box, keeping the aspect ratio
+    #[default]
+    Contain,
+    // fills the box, keeping the aspect ratio and cropping the overflow
+    Cover,
+    // stretches to the box
+    Fill,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ResizeOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub fit: ImageFit,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TransformOptions {
+    pub resize: Option<ResizeOptions>,
+    // defaults to the input format (or png if it can't be encoded)
+    pub format: Option<ImageOutputFormat>,
+    // 1-100, for jpeg and avif (webp is encoded lossless)
+    pub quality: Option<u8>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformedImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageOutputFormat,
+    pub data: ToJsBuffer,
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Png => "png",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Avif => "avif",
+        ImageFormat::Gif => "gif",
+        _ => "unknown",
+    }
+}
+
+// AVIF is only encoded: the AVIF decoder of `image` needs the native dav1d library
+fn reader(input: &[u8]) -> Result<(Reader<Cursor<&[u8]>>, ImageFormat), AnyError> {
+    let reader = Reader::new(Cursor::new(input)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| type_error("unsupported image format"))?;
+    if format == ImageFormat::Avif {
+        return Err(custom_error(
+            "NotSupported",
+            "avif images can be encoded but not decoded",
+        ));
+    }
+    Ok((reader, format))
+}
+
+// Reads the dimensions from the header, without decoding the image
+pub fn image_info(input: &[u8]) -> Result<ImageInfo, AnyError> {
+    let (reader, format) = reader(input)?;
+    let (width, height) = reader.into_dimensions()?;
+    Ok(ImageInfo {
+        width,
+        height,
+        format: format_name(format),
+    })
+}
+
+// Target size of a resize. A missing dimension follows the aspect ratio.
+fn target_size(img: &DynamicImage, resize: &ResizeOptions) -> Result<(u32, u32), AnyError> {
+    let (width, height) = (img.width() as u64, img.height() as u64);
+    let (target_width, target_height) = match (resize.width, resize.height) {
+        (Some(w), Some(h)) => (w as u64, h as u64),
+        (Some(w), None) => (w as u64, (height * w as u64 / width).max(1)),
+        (None, Some(h)) => ((width * h as u64 / height).max(1), h as u64),
+        (None, None) => return Err(type_error("resize needs a width or a height")),
+    };
+    if target_width == 0 || target_height == 0 {
+        return Err(type_error("resize width and height must be positive"));
+    }
+    Ok((u32::try_from(target_width)?, u32::try_from(target_height)?))
+}
+
+fn encode(img: DynamicImage, format: ImageOutputFormat, quality: u8) -> Result<Vec<u8>, AnyError> {
+    let mut buf = vec![];
+    match format {
+        // jpeg has no alpha channel
+        ImageOutputFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))?,
+        ImageOutputFormat::Png => img.write_with_encoder(PngEncoder::new(&mut buf))?,
+        ImageOutputFormat::Webp => img.write_with_encoder(WebPEncoder::new_lossless(&mut buf))?,
+        ImageOutputFormat::Avif => img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut buf, AVIF_SPEED, quality,
+        ))?,
+    }
+    Ok(buf)
+}
+
+// Decodes (jpeg, png or webp), optionally resizes and encodes (jpeg, png, webp or
+// avif) an image
+pub fn transform_image(
+    input: &[u8],
+    opts: &TransformOptions,
+    limits: ImageLimits,
+) -> Result<TransformedImage, AnyError> {
+    let quality = opts.quality.unwrap_or(DEFAULT_QUALITY);
+    if !(1..=100).contains(&quality) {
+        return Err(type_error("quality must be between 1 and 100"));
+    }
+
+    let (mut reader, input_format) = reader(input)?;
+    let mut decode_limits = Limits::default();
+    decode_limits.max_alloc = Some(limits.max_memory_bytes);
+    reader.limits(decode_limits);
+    let mut img = reader.decode()?;
+
+    if let Some(resize) = &opts.resize {
+        let (width, height) = target_size(&img, resize)?;
+        limits.check_dimensions(width, height)?;
+        img = match resize.fit {
+            ImageFit::Contain => img.resize(width, height, FilterType::Lanczos3),
+            ImageFit::Cover => img.resize_to_fill(width, height, FilterType::Lanczos3),
+            ImageFit::Fill => img.resize_exact(width, height, FilterType::Lanczos3),
+        };
+    }
+
+    let format = opts
+        .format
+        .unwrap_or_else(|| ImageOutputFormat::from_input(input_format));
+    let (width, height) = (img.width(), img.height());
+    let data = encode(img, format, quality)?;
+    Ok(TransformedImage {
+        width,
+        height,
+        format,
+        data: data.into(),
+    })
+}
+
+fn image_limits(state: &OpState) -> Result<ImageLimits, AnyError> {
+    state
+        .try_borrow::<ImageLimits>()
+        .copied()
+        .ok_or_else(|| custom_error("NotSupported", "image transformations are not enabled"))
+}
+
+#[op2]
+#[serde]
+pub fn op_image_limits(state: &mut OpState) -> Option<ImageLimits> {
+    state.try_borrow::<ImageLimits>().copied()
+}
+
+#[op2]
+#[serde]
+pub fn op_image_info(state: &mut OpState, #[buffer] input: &[u8]) -> Result<ImageInfo, AnyError> {
+    image_limits(state)?;
+    image_info(input)
+}
+
+// Runs on the blocking pool, so large images don't stall the worker's event loop
+#[op2(async)]
+#[serde]
+pub async fn op_image_transform(
+    state: Rc<RefCell<OpState>>,
+    #[buffer] input: JsBuffer,
+    #[serde] opts: TransformOptions,
+) -> Result<TransformedImage, AnyError> {
+    let limits = image_limits(&state.borrow())?;
+    tokio::task::spawn_blocking(move || transform_image(&input, &opts, limits)).await?
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        image_info, transform_image, ImageFit, ImageLimits, ImageOutputFormat, ResizeOptions,
+        TransformOptions,
+    };
+    use deno_core::error::get_custom_error_class;
+    use image::{DynamicImage, ImageOutputFormat as EncodeFormat, RgbaImage};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buf = vec![];
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut buf), EncodeFormat::Png)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_image_info() {
+        let info = image_info(&png(40, 20)).unwrap();
+        assert_eq!((info.width, info.height, info.format), (40, 20, "png"));
+        assert!(image_info(b"not an image").is_err());
+    }
+
+    // AVIF is encoded, but not decoded
+    #[test]
+    fn test_avif_input() {
+        // the `ftyp` box AVIF files start with
+        let avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf".to_vec();
+        let err = image_info(&avif).unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("NotSupported"));
+        let err = transform_image(
+            &avif,
+            &TransformOptions::default(),
+            ImageLimits::from_mb(16),
+        )
+        .unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("NotSupported"));
+    }
+
+    #[test]
+    fn test_image_limits() {
+        assert_eq!(ImageLimits::from_mb(2).max_memory_bytes, 2 * 1024 * 1024);
+        assert_eq!(ImageLimits::from_mb(u64::MAX).max_memory_bytes, u64::MAX);
+    }
+
+    #[test]
+    fn test_transform_image() {
+        let limits = ImageLimits::from_mb(16);
+        let opts = TransformOptions {
+            resize: Some(ResizeOptions {
+                width: Some(20),
+                height: None,
+                fit: ImageFit::Contain,
+            }),
+            format: Some(ImageOutputFormat::Jpeg),
+            quality: None,
+        };
+        let out = transform_image(&png(40, 20), &opts, limits).unwrap();
+        assert_eq!((out.width, out.height), (20, 10));
+        assert_eq!(out.format, ImageOutputFormat::Jpeg);
+
+        let opts = TransformOptions {
+            resize: Some(ResizeOptions {
+                width: Some(10),
+                height: Some(10),
+                fit: ImageFit::Cover,
+            }),
+            ..Default::default()
+        };
+        let out = transform_image(&png(40, 20), &opts, limits).unwrap();
+        assert_eq!((out.width, out.height), (10, 10));
+        assert_eq!(out.format, ImageOutputFormat::Png);
+    }
+
+    #[test]
+    fn test_transform_image_memory_limit() {
+        let limits = ImageLimits::from_mb(1);
+        let opts = TransformOptions {
+            resize: Some(ResizeOptions {
+                width: Some(4096),
+                height: Some(4096),
+                fit: ImageFit::Fill,
+            }),
+            ..Default::default()
+        };
+        assert!(transform_image(&png(40, 20), &opts, limits).is_err());
+        // decoding is capped too
+        assert!(transform_image(&png(1024, 1024), &TransformOptions::default(), limits).is_err());
+    }
+}
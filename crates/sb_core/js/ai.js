@@ -0,0 +1,87 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+function bodyStream(rid) {
+	return new ReadableStream({
+		type: 'bytes',
+		async pull(controller) {
+			try {
+				const chunk = await core.opAsync('op_ai_read', rid);
+				if (chunk === null) {
+					core.tryClose(rid);
+					controller.close();
+					return;
+				}
+				controller.enqueue(chunk);
+			} catch (err) {
+				core.tryClose(rid);
+				controller.error(err);
+			}
+		},
+		cancel() {
+			core.tryClose(rid);
+		},
+	});
+}
+
+async function request(endpoint, body, { provider } = {}) {
+	if (body === null || typeof body !== 'object' || Array.isArray(body)) {
+		throw new TypeError('request body must be an object');
+	}
+	const { 0: rid, 1: status, 2: contentType } = await core.opAsync('op_ai_request', {
+		provider: provider === undefined ? null : String(provider),
+		endpoint,
+		body,
+	});
+	const headers = contentType ? { 'content-type': contentType } : {};
+	return new Response(bodyStream(rid), { status, headers });
+}
+
+// Embeds text with a built-in model (eg: `gte-small`), loaded once by the
+// runtime for every isolate (see embeddings.rs)
+class Session {
+	#model;
+
+	constructor(model) {
+		model = String(model);
+		if (!ops.op_embedding_models().includes(model)) {
+			throw new TypeError(`unknown embedding model ${model}`);
+		}
+		this.#model = model;
+	}
+
+	get model() {
+		return this.#model;
+	}
+
+	// resolves to the embedding, as an array of numbers
+	run(text, { meanPool = true, normalize = true } = {}) {
+		return core.opAsync('op_embed', this.#model, String(text), {
+			meanPool: Boolean(meanPool),
+			normalize: Boolean(normalize),
+		});
+	}
+}
+
+// OpenAI-compatible requests sent by the runtime (see ai_gateway.rs), which
+// injects the provider's API key and counts the tokens used against the
+// service's quota. Both resolve to the provider's `Response`, streamed as it
+// arrives (server-sent events for `stream: true`), so it can be returned as is.
+const AI = {
+	Session,
+
+	chat(body, opts) {
+		return request('chat', body, opts);
+	},
+
+	embeddings(body, opts) {
+		return request('embeddings', body, opts);
+	},
+
+	// `{ tokens, quota, resetsAtMs }` of the current quota window
+	usage() {
+		return ops.op_ai_usage();
+	},
+};
+
+export { AI };
@@ -0,0 +1,48 @@
+import { getHeaders } from 'ext:sb_core_main_js/js/http.js';
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const ALARM_HEADER = 'x-edge-runtime-alarm';
+const ALARM_ATTEMPT_HEADER = 'x-edge-runtime-alarm-attempt';
+
+// Alarms are requests the runtime sends to the service at a later time (see
+// alarms.rs). They survive the worker that scheduled them, and are retried until
+// the service responds without a server error.
+const ALARMS = {
+	// `at` (Date or unix ms) or `delayMs`; the payload is sent as the JSON body of a
+	// POST request to `path`
+	schedule({ at, delayMs, path = '/', payload = null } = {}) {
+		let fireAtMs = at instanceof Date ? at.getTime() : at;
+		if (fireAtMs === undefined) {
+			fireAtMs = Date.now() + (delayMs ?? 0);
+		}
+		if (!Number.isFinite(fireAtMs)) {
+			throw new TypeError('alarm time must be a finite number');
+		}
+		return ops.op_alarm_schedule({
+			fireAtMs: Math.max(0, Math.floor(fireAtMs)),
+			path: String(path),
+			payload,
+		});
+	},
+
+	cancel(id) {
+		return ops.op_alarm_cancel(String(id));
+	},
+
+	list() {
+		return ops.op_alarm_list();
+	},
+
+	// `{ id, attempt }` if the request was sent for an alarm, `null` otherwise
+	fromRequest(req) {
+		const [id, attempt] = getHeaders(req, [ALARM_HEADER, ALARM_ATTEMPT_HEADER]);
+		if (!id) {
+			return null;
+		}
+		return { id, attempt: Number(attempt ?? 0) };
+	},
+};
+
+export { ALARMS };
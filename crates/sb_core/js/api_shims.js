@@ -0,0 +1,20 @@
+// Shims restoring the behavior older bundles were built against, as
+// `[apiVersion, shim]`: the shim runs for bundles built before `apiVersion`
+// (the runtime API version that changed the behavior). Add one whenever
+// `RUNTIME_API_VERSION` is bumped.
+const SHIMS = [];
+
+// `apiVersion` is null for services loaded from source, which always run
+// against the current APIs
+function applyApiShims(apiVersion) {
+	if (apiVersion === null || apiVersion === undefined) {
+		return;
+	}
+	for (const [introducedIn, shim] of SHIMS) {
+		if (apiVersion < introducedIn) {
+			shim();
+		}
+	}
+}
+
+export { applyApiShims };
@@ -39,8 +39,19 @@ import { promiseRejectMacrotaskCallback } from 'ext:sb_core_main_js/js/promises.
 import { denoOverrides, fsVars } from 'ext:sb_core_main_js/js/denoOverrides.js';
 import * as performance from 'ext:deno_web/15_performance.js';
 import * as messagePort from 'ext:deno_web/13_message_port.js';
+import * as broadcastChannel from 'ext:deno_broadcast_channel/01_broadcast_channel.js';
 import { SupabaseEventListener } from 'ext:sb_user_event_worker/event_worker.js';
 import * as MainWorker from 'ext:sb_core_main_js/js/main_worker.js';
+import { setNodeIdentity, USER_EDGE_RUNTIME } from 'ext:sb_core_main_js/js/user_worker.js';
+import { installDeterministicMode, installReplayClock } from 'ext:sb_core_main_js/js/replay.js';
+import { installTestClock, installTestCoverage } from 'ext:sb_core_main_js/js/test_clock.js';
+import { installCodeGenerationGuard } from 'ext:sb_core_main_js/js/code_generation.js';
+import { installTimerPolicy } from 'ext:sb_core_main_js/js/timer_policy.js';
+import { isIdle, watchRequests } from 'ext:sb_core_main_js/js/http.js';
+import { installLeakDetector } from 'ext:sb_core_main_js/js/leaks.js';
+import { applyApiShims } from 'ext:sb_core_main_js/js/api_shims.js';
+import { installFormDataStream } from 'ext:sb_core_main_js/js/multipart.js';
+import { installWebWorkerScope, Worker } from 'ext:sb_user_workers/web_workers.js';
 import * as DenoWebCompression from 'ext:deno_web/14_compression.js';
 import * as DenoWSStream from 'ext:deno_websocket/02_websocketstream.js';
 
@@ -166,6 +177,12 @@ const globalScope = {
 	// messagePort
 	structuredClone: writable(messagePort.structuredClone),
 
+	// broadcast channel (shared by the workers of a service)
+	BroadcastChannel: nonEnumerable(broadcastChannel.BroadcastChannel),
+
+	// web workers (available to user workers)
+	Worker: nonEnumerable(Worker),
+
 	// Branding as a WebIDL object
 	[webidl.brand]: nonEnumerable(webidl.brand),
 };
@@ -225,6 +242,9 @@ const globalProperties = {
 };
 ObjectDefineProperties(globalThis, globalProperties);
 
+// `request.formDataStream()`
+installFormDataStream();
+
 const deleteDenoApis = (apis) => {
 	apis.forEach((key) => {
 		delete Deno[key];
@@ -244,15 +264,46 @@ globalThis.bootstrapSBEdge = (opts, isUserWorker, isEventsWorker, version) => {
 	const eventHandlers = ['error', 'load', 'beforeunload', 'unload', 'unhandledrejection'];
 	eventHandlers.forEach((handlerName) => event.defineEventHandler(globalThis, handlerName));
 
+	const {
+		replay,
+		node,
+		apiVersion,
+		webWorker,
+		testClock,
+		coverage,
+		codeGeneration,
+		deterministic,
+		timers,
+		detectLeaks,
+		...runtimeOpts
+	} = opts;
 	runtimeStart({
 		denoVersion: 'NA',
 		v8Version: 'NA',
 		tsVersion: 'NA',
 		noColor: true,
 		isTty: false,
-		...opts,
+		...runtimeOpts,
 	});
 
+	// set when replaying a recorded request
+	if (replay) {
+		installReplayClock(replay);
+	} else if (deterministic) {
+		// set for workers created with `deterministic` (never in production)
+		installDeterministicMode(deterministic);
+	}
+
+	// set for workers running tests
+	if (testClock) {
+		installTestClock();
+	}
+	if (coverage) {
+		installTestCoverage();
+	}
+
+	setNodeIdentity(node);
+
 	ObjectDefineProperty(globalThis, 'SUPABASE_VERSION', readOnly(String(version)));
 
 	// set these overrides after runtimeStart
@@ -270,12 +321,14 @@ globalThis.bootstrapSBEdge = (opts, isUserWorker, isEventsWorker, version) => {
 	});
 	ObjectDefineProperty(globalThis, 'Deno', readOnly(denoOverrides));
 
-	setNumCpus(1); // explicitly setting no of CPUs to 1 (since we don't allow workers)
+	setNumCpus(1); // explicitly setting no of CPUs to 1
 	setUserAgent('Supabase Edge Runtime');
 	setLanguage('en');
 
 	if (isUserWorker) {
+		// user workers get a restricted `EdgeRuntime` (without access to other workers)
 		delete globalThis.EdgeRuntime;
+		ObjectDefineProperty(globalThis, 'EdgeRuntime', readOnly(USER_EDGE_RUNTIME));
 
 		// override console
 		ObjectDefineProperties(globalThis, {
@@ -290,6 +343,25 @@ globalThis.bootstrapSBEdge = (opts, isUserWorker, isEventsWorker, version) => {
 		deleteDenoApis(Object.keys(fsVars).filter((k) => k !== 'cwd'));
 	}
 
+	// set for web workers spawned by a user worker
+	if (webWorker) {
+		installWebWorkerScope(webWorker.name);
+	}
+
+	// keeps bundles built against older runtime APIs working
+	applyApiShims(apiVersion);
+
+	// after the test clock, which replaces the timers
+	if (timers) {
+		installTimerPolicy(timers, isIdle);
+	}
+	if (codeGeneration === false) {
+		installCodeGenerationGuard();
+	}
+	if (detectLeaks) {
+		installLeakDetector(watchRequests);
+	}
+
 	if (isEventsWorker) {
 		// Event Manager should have the same as the `main` except it can't create workers (that would be catastrophic)
 		delete globalThis.EdgeRuntime;
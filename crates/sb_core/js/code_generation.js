@@ -0,0 +1,39 @@
+const {
+	EvalError,
+	ObjectDefineProperty,
+} = globalThis.__bootstrap.primordials;
+
+const disallowed = (what) =>
+	new EvalError(
+		`${what} is disallowed for this worker, it was created with \`disallowCodeGeneration\``,
+	);
+
+// V8 refuses to compile strings once the worker is booted (`eval`, `new
+// Function`, ...), these give the usual entry points a clearer error, the way a
+// content security policy without `unsafe-eval` does in browsers.
+const installCodeGenerationGuard = () => {
+	ObjectDefineProperty(globalThis, 'eval', {
+		value: function evalDisallowed() {
+			throw disallowed('eval()');
+		},
+		writable: true,
+		configurable: true,
+	});
+
+	for (const name of ['setTimeout', 'setInterval']) {
+		const timer = globalThis[name];
+		ObjectDefineProperty(globalThis, name, {
+			value: function (callback, ...args) {
+				if (typeof callback !== 'function') {
+					throw disallowed(`${name}() with a string`);
+				}
+				return timer(callback, ...args);
+			},
+			writable: true,
+			enumerable: true,
+			configurable: true,
+		});
+	}
+};
+
+export { installCodeGenerationGuard };
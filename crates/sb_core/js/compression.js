@@ -0,0 +1,78 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const FORMATS = ['gzip', 'deflate', 'deflate-raw', 'br', 'zstd'];
+
+function toBytes(chunk) {
+	if (ArrayBuffer.isView(chunk)) {
+		return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+	}
+	if (chunk instanceof ArrayBuffer) {
+		return new Uint8Array(chunk);
+	}
+	throw new TypeError('chunk must be an ArrayBuffer or a view of one');
+}
+
+function codecStream(format, decompress, level) {
+	if (!FORMATS.includes(format)) {
+		throw new TypeError(`unsupported compression format ${format}`);
+	}
+	const rid = ops.op_codec_new(format, decompress, level ?? null);
+	return new TransformStream({
+		async transform(chunk, controller) {
+			const output = await core.opAsync('op_codec_write', rid, toBytes(chunk));
+			if (output.byteLength > 0) {
+				controller.enqueue(output);
+			}
+		},
+		async flush(controller) {
+			const output = await core.opAsync('op_codec_finish', rid);
+			if (output.byteLength > 0) {
+				controller.enqueue(output);
+			}
+		},
+	});
+}
+
+// Like the web's `CompressionStream`, with brotli and zstd besides gzip and
+// deflate. Chunks are compressed in Rust on the blocking pool, so they don't
+// count against the worker's CPU time.
+class CompressionStream {
+	#stream;
+
+	constructor(format, { level } = {}) {
+		this.#stream = codecStream(format, false, level);
+	}
+
+	get readable() {
+		return this.#stream.readable;
+	}
+
+	get writable() {
+		return this.#stream.writable;
+	}
+}
+
+class DecompressionStream {
+	#stream;
+
+	constructor(format) {
+		this.#stream = codecStream(format, true, null);
+	}
+
+	get readable() {
+		return this.#stream.readable;
+	}
+
+	get writable() {
+		return this.#stream.writable;
+	}
+}
+
+const COMPRESSION = {
+	formats: FORMATS,
+	CompressionStream,
+	DecompressionStream,
+};
+
+export { COMPRESSION };
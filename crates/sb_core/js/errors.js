@@ -27,6 +27,7 @@ const buildDomErrorClass = (name) => class extends DOMException {
 
 const InvalidWorkerResponse = buildErrorClass('InvalidWorkerResponse');
 const InvalidWorkerCreation = buildErrorClass('InvalidWorkerCreation');
+const InvalidWorkerOptions = buildErrorClass('InvalidWorkerOptions');
 const NotFound = buildErrorClass('NotFound');
 const PermissionDenied = buildErrorClass('PermissionDenied');
 const ConnectionRefused = buildErrorClass('ConnectionRefused');
@@ -45,6 +46,10 @@ const UnexpectedEof = buildErrorClass('UnexpectedEof');
 const Http = buildErrorClass('Http');
 const Busy = buildErrorClass('Busy');
 const NotSupported = buildErrorClass('NotSupported');
+const MailError = buildErrorClass('MailError');
+const RedisError = buildErrorClass('RedisError');
+const AiError = buildErrorClass('AiError');
+const OnnxError = buildErrorClass('OnnxError');
 const DOMExceptionOperationError = buildDomErrorClass('OperationError');
 const DOMExceptionQuotaExceededError = buildDomErrorClass('QuotaExceededError');
 const DOMExceptionNotSupportedError = buildDomErrorClass('NotSupported');
@@ -56,6 +61,7 @@ const DOMExceptionDataError = buildDomErrorClass('DOMExceptionDataError');
 function registerErrors() {
     core.registerErrorClass("InvalidWorkerResponse", InvalidWorkerResponse);
     core.registerErrorClass("InvalidWorkerCreation", InvalidWorkerCreation);
+    core.registerErrorClass("InvalidWorkerOptions", InvalidWorkerOptions);
     core.registerErrorClass("NotFound", NotFound);
     core.registerErrorClass("PermissionDenied", PermissionDenied);
     core.registerErrorClass("ConnectionRefused", ConnectionRefused);
@@ -75,6 +81,10 @@ function registerErrors() {
     core.registerErrorClass("Http", Http);
     core.registerErrorClass("Busy", Busy);
     core.registerErrorClass("NotSupported", NotSupported);
+    core.registerErrorClass("MailError", MailError);
+    core.registerErrorClass("RedisError", RedisError);
+    core.registerErrorClass("AiError", AiError);
+    core.registerErrorClass("OnnxError", OnnxError);
     core.registerErrorClass(
         "DOMExceptionOperationError",
         DOMExceptionOperationError
@@ -0,0 +1,45 @@
+import { Event, EventTarget } from 'ext:deno_web/02_event.js';
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const promiseIdSymbol = Symbol.for('Deno.core.internalPromiseId');
+
+// Feature flags of the service, evaluated by the runtime (see flags.rs). Passing a
+// `key` (eg: a user id) opts the request into percentage rollouts. A `change` event
+// is dispatched whenever the runtime picks up new flags.
+class FeatureFlags extends EventTarget {
+	#watching = false;
+
+	get(name, options) {
+		return ops.op_feature_flag(String(name), options?.key ?? null) ?? undefined;
+	}
+
+	all(options) {
+		return ops.op_feature_flags(options?.key ?? null);
+	}
+
+	addEventListener(type, listener, options) {
+		super.addEventListener(type, listener, options);
+		if (type === 'change' && !this.#watching) {
+			this.#watching = true;
+			this.#watch();
+		}
+	}
+
+	async #watch() {
+		while (true) {
+			const changed = core.opAsync('op_feature_flags_changed');
+			// waiting for changes must not keep the worker alive
+			core.unrefOp(changed[promiseIdSymbol]);
+			if (!(await changed)) {
+				return;
+			}
+			this.dispatchEvent(new Event('change'));
+		}
+	}
+}
+
+const FEATURE_FLAGS = new FeatureFlags();
+
+export { FEATURE_FLAGS };
@@ -1,41 +1,287 @@
 import { HttpConn } from 'ext:deno_http/01_http.js';
+import { Headers } from 'ext:deno_fetch/20_headers.js';
+import { Request, toInnerRequest } from 'ext:deno_fetch/23_request.js';
+import { problemResponse } from 'ext:sb_core_main_js/js/problem.js';
 
 const core = globalThis.Deno.core;
 const ops = core.ops;
 
+const requestHeaders = Object.getOwnPropertyDescriptor(Request.prototype, 'headers').get;
+const inspectSymbol = Symbol.for('Deno.privateCustomInspect');
+
+// sent to the worker with the request (the URL, the framing of the body,
+// upgrades and the encoding of the response), so only the request itself has
+// them. The runtime holds the others.
+const WIRE_HEADERS = [
+	'host',
+	'content-length',
+	'transfer-encoding',
+	'connection',
+	'upgrade',
+	'accept-encoding',
+];
+
+// the runtime holds the headers of a request until the request is collected
+const headersRegistry = new FinalizationRegistry((rid) => {
+	ops.op_http_request_headers_drop(rid);
+});
+
+// Headers of a request, read one at a time from the runtime instead of building
+// all of them for functions that only look at a few. They are filled in from
+// the request once iterated.
+class RequestHeaders extends Headers {
+	#rid;
+	#request;
+	#filled = false;
+
+	constructor(rid, request) {
+		super();
+		this.#rid = rid;
+		this.#request = request;
+	}
+
+	#fill() {
+		if (this.#filled) {
+			return;
+		}
+		this.#filled = true;
+		for (const [name, value] of requestHeaders.call(this.#request)) {
+			super.append(name, value);
+		}
+	}
+
+	get(name) {
+		name = String(name);
+		if (!this.#filled && !WIRE_HEADERS.includes(name.toLowerCase())) {
+			return ops.op_http_request_header(this.#rid, name) ?? null;
+		}
+		this.#fill();
+		return super.get(name);
+	}
+
+	has(name) {
+		return this.get(name) !== null;
+	}
+
+	// several headers in a single call into the runtime
+	static getMany(headers, names) {
+		const wire = names.some((name) => WIRE_HEADERS.includes(name.toLowerCase()));
+		if (headers.#filled || wire) {
+			return names.map((name) => headers.get(name));
+		}
+		return ops.op_get_headers(headers.#rid, names).map((value) => value ?? null);
+	}
+
+	append() {
+		throw new TypeError('Headers are immutable.');
+	}
+
+	set() {
+		throw new TypeError('Headers are immutable.');
+	}
+
+	delete() {
+		throw new TypeError('Headers are immutable.');
+	}
+
+	entries() {
+		this.#fill();
+		return super.entries();
+	}
+
+	keys() {
+		this.#fill();
+		return super.keys();
+	}
+
+	values() {
+		this.#fill();
+		return super.values();
+	}
+
+	forEach(callback, thisArg) {
+		this.#fill();
+		return super.forEach(callback, thisArg);
+	}
+
+	[Symbol.iterator]() {
+		this.#fill();
+		return super[Symbol.iterator]();
+	}
+
+	[inspectSymbol](...args) {
+		this.#fill();
+		return super[inspectSymbol](...args);
+	}
+}
+
+// The header list of the request only has its wire headers, the others are
+// added the first time it's read (eg: to clone or forward the request)
+function fillHeaderList(request, rid) {
+	const inner = toInnerRequest(request);
+	const descriptor = Object.getOwnPropertyDescriptor(inner, 'headerList');
+	if (!descriptor?.get) {
+		return;
+	}
+	let headerList = null;
+	Object.defineProperty(inner, 'headerList', {
+		get() {
+			headerList ??= [
+				...ops.op_http_request_headers_entries(rid),
+				...descriptor.get.call(inner),
+			];
+			return headerList;
+		},
+		set(value) {
+			headerList = value;
+			descriptor.set?.call(inner, value);
+		},
+		configurable: true,
+		enumerable: true,
+	});
+}
+
+function useRequestHeaders(request, rid) {
+	fillHeaderList(request, rid);
+	let headers;
+	Object.defineProperty(request, 'headers', {
+		get() {
+			headers ??= new RequestHeaders(rid, request);
+			return headers;
+		},
+		configurable: true,
+	});
+	headersRegistry.register(request, rid);
+}
+
 function internalServerError() {
-	// "Internal Server Error"
-	return new Response(
-		new Uint8Array([
-			73,
-			110,
-			116,
-			101,
-			114,
-			110,
-			97,
-			108,
-			32,
-			83,
-			101,
-			114,
-			118,
-			101,
-			114,
-			32,
-			69,
-			114,
-			114,
-			111,
-			114,
-		]),
-		{ status: 500 },
-	);
+	return problemResponse('INTERNAL_ERROR');
+}
+
+// metadata the main worker attached to requests, read through `EdgeRuntime.context`
+const requestMetadata = new WeakMap();
+// file-based routes matched by requests, read by the generated entrypoint
+const requestRoutes = new WeakMap();
+Object.defineProperty(globalThis, Symbol.for('edgeRuntime.requestRoute'), {
+	value: (req) => requestRoutes.get(req) ?? null,
+});
+
+// Headers of a request by name (`null` for the missing ones), read at once
+function getHeaders(req, names) {
+	names = Array.from(names, String);
+	const headers = req?.headers;
+	if (headers instanceof RequestHeaders) {
+		return RequestHeaders.getMany(headers, names);
+	}
+	return names.map((name) => headers?.get(name) ?? null);
+}
+
+// connections being served, the worker is idle while there's none
+let openConnections = 0;
+
+function isIdle() {
+	return openConnections === 0;
+}
+
+// told when the worker starts serving requests after being idle, of each
+// request, and when it's idle again (see `installLeakDetector`)
+let requestsWatcher = null;
+
+function watchRequests(watcher) {
+	requestsWatcher = watcher;
+}
+
+// requests not responded to yet (with their `respondWith`), and those answered
+// with a 500 in their place
+const inflightRequests = new Map();
+const failedRequests = new WeakSet();
+
+// Answers the requests being served with a 500, for workers whose uncaught
+// error policy is `fail-request` (called by the runtime). Their handlers' own
+// responses are dropped.
+function failInflightRequests() {
+	const failed = inflightRequests.size;
+	for (const [event, respondWith] of inflightRequests) {
+		failedRequests.add(event);
+		respondWith(internalServerError()).catch(() => {});
+	}
+	inflightRequests.clear();
+	return failed;
+}
+Object.defineProperty(globalThis, Symbol.for('edgeRuntime.failInflightRequests'), {
+	value: failInflightRequests,
+});
+
+function trackRequest(event) {
+	const respondWith = event.respondWith;
+	inflightRequests.set(event, respondWith);
+	requestsWatcher?.request(event.request);
+	event.respondWith = (response) => {
+		if (failedRequests.has(event)) {
+			return Promise.resolve();
+		}
+		inflightRequests.delete(event);
+		return respondWith(response);
+	};
 }
 
 function serveHttp(conn) {
-	const rid = ops.op_http_start(conn.rid);
-	return new HttpConn(rid, conn.remoteAddr, conn.localAddr);
+	const connRid = conn.rid;
+	const metadata = ops.op_http_request_metadata_take(connRid);
+	const route = ops.op_http_request_route_take(connRid);
+	// the runtime sends a single request per connection
+	let hasHeaders = ops.op_http_request_has_headers(connRid);
+	const rid = ops.op_http_start(connRid);
+	const httpConn = new HttpConn(rid, conn.remoteAddr, conn.localAddr);
+	let open = true;
+	if (openConnections === 0) {
+		requestsWatcher?.busy();
+	}
+	openConnections++;
+	const closed = () => {
+		if (open) {
+			open = false;
+			openConnections--;
+			if (openConnections === 0) {
+				requestsWatcher?.idle();
+			}
+		}
+	};
+
+	const value = metadata === null ? null : JSON.parse(metadata);
+	const routeValue = route === null ? null : JSON.parse(route);
+	const nextRequest = httpConn.nextRequest.bind(httpConn);
+	httpConn.nextRequest = async () => {
+		let event;
+		try {
+			event = await nextRequest();
+		} catch (err) {
+			closed();
+			throw err;
+		}
+		if (!event) {
+			closed();
+			if (hasHeaders) {
+				// closed before its request was read
+				hasHeaders = false;
+				ops.op_http_request_headers_drop(connRid);
+			}
+			return event;
+		}
+		if (hasHeaders) {
+			hasHeaders = false;
+			useRequestHeaders(event.request, connRid);
+		}
+		if (value !== null) {
+			requestMetadata.set(event.request, value);
+		}
+		if (routeValue !== null) {
+			requestRoutes.set(event.request, routeValue);
+		}
+		trackRequest(event);
+		return event;
+	};
+	return httpConn;
 }
 
 async function serve(args1, args2) {
@@ -94,4 +340,4 @@ async function serve(args1, args2) {
 	};
 }
 
-export { serve, serveHttp };
+export { getHeaders, isIdle, requestMetadata, serve, serveHttp, watchRequests };
@@ -0,0 +1,31 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// Sortable ids generated from the system's secure random source. Ids made by a
+// worker always sort in the order they were made (even within a millisecond),
+// and ids of different isolates can't collide in practice.
+const IDS = {
+	uuidv7() {
+		return ops.op_ids_uuidv7();
+	},
+
+	ulid() {
+		return ops.op_ids_ulid();
+	},
+
+	// Adds `by` to the service's `name` counter and returns the new value. Counters
+	// are shared by the service's isolates on the node and kept in memory, so they
+	// start over when it restarts.
+	next(name, by = 1) {
+		if (!Number.isInteger(by) || by < 1 || by > 0xffffffff) {
+			throw new RangeError('by must be a positive 32-bit integer');
+		}
+		return ops.op_ids_next(String(name), by);
+	},
+
+	current(name) {
+		return ops.op_ids_current(String(name));
+	},
+};
+
+export { IDS };
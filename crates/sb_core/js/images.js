@@ -0,0 +1,49 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+function toBytes(input) {
+	if (ArrayBuffer.isView(input)) {
+		return new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+	}
+	if (input instanceof ArrayBuffer) {
+		return new Uint8Array(input);
+	}
+	throw new TypeError('image must be an ArrayBuffer or a view of one');
+}
+
+async function readImage(input) {
+	if (input instanceof Request || input instanceof Response || input instanceof Blob) {
+		return new Uint8Array(await input.arrayBuffer());
+	}
+	return toBytes(input);
+}
+
+// Decodes, resizes and encodes images in Rust (off the worker's event loop), so
+// they count against neither the CPU nor the heap limit of the worker. Every
+// operation is capped by the runtime's image memory limit.
+const IMAGES = {
+	get limits() {
+		return ops.op_image_limits();
+	},
+
+	// `{ width, height, format }`, read from the image header
+	info(input) {
+		return ops.op_image_info(toBytes(input));
+	},
+
+	// eg: `transform(bytes, { resize: { width: 320, fit: 'cover' }, format: 'webp' })`
+	// resolves to `{ data, width, height, format }`. Images can be encoded to avif,
+	// but not decoded from it.
+	async transform(input, opts = {}) {
+		const bytes = await readImage(input);
+		return await core.opAsync('op_image_transform', bytes, opts);
+	},
+};
+
+// `EdgeRuntime.images` is only set when the runtime was started with an image
+// memory limit
+function imagesIfEnabled() {
+	return ops.op_image_limits() ? IMAGES : undefined;
+}
+
+export { imagesIfEnabled };
@@ -0,0 +1,32 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// A JSON Schema compiled by the runtime. Compiling the same schema again (eg: in
+// a request handler) reuses the compiled one.
+class Validator {
+	#id;
+
+	constructor(schema) {
+		this.#id = ops.op_json_schema_compile(schema);
+	}
+
+	// `{ valid, errors }`, errors being `{ instancePath, schemaPath, message }`
+	validate(value) {
+		const errors = ops.op_json_schema_validate(this.#id, value);
+		return { valid: errors.length === 0, errors };
+	}
+
+	// Like `validate`, parsing `text` in Rust, eg: `validateJson(await req.text())`
+	validateJson(text) {
+		const errors = ops.op_json_schema_validate_json(this.#id, String(text));
+		return { valid: errors.length === 0, errors };
+	}
+}
+
+const JSON_SCHEMA = {
+	compile(schema) {
+		return new Validator(schema);
+	},
+};
+
+export { JSON_SCHEMA };
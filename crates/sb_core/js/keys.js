@@ -0,0 +1,35 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const encoder = new TextEncoder();
+
+function toBytes(data) {
+	if (typeof data === 'string') {
+		return encoder.encode(data);
+	}
+	if (ArrayBuffer.isView(data)) {
+		return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+	}
+	if (data instanceof ArrayBuffer) {
+		return new Uint8Array(data);
+	}
+	throw new TypeError('data must be a string, an ArrayBuffer or a view of one');
+}
+
+// Signs and verifies with keys registered in the runtime's key store. The key
+// material never enters the isolate; keys are referred to by name.
+const KEY_STORE_CRYPTO = {
+	keys() {
+		return ops.op_key_names();
+	},
+
+	sign(keyName, data) {
+		return core.opAsync('op_key_sign', String(keyName), toBytes(data));
+	},
+
+	verify(keyName, data, signature) {
+		return core.opAsync('op_key_verify', String(keyName), toBytes(data), toBytes(signature));
+	},
+};
+
+export { KEY_STORE_CRYPTO };
@@ -0,0 +1,69 @@
+const {
+	ObjectEntries,
+	ObjectKeys,
+	SafeMap,
+	SafeSet,
+} = globalThis.__bootstrap.primordials;
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// not opened by the requests themselves, or expected to outlive them
+const IGNORED_RESOURCES = ['stdin', 'stdout', 'stderr', 'timer'];
+// requests named in a report
+const MAX_REQUESTS = 10;
+
+// Reports the resources (fetch bodies, files, sockets, ...) the requests served
+// by a worker left open, for workers created with `detectLeaks`. The resource
+// table is snapshotted when the worker starts serving requests after being idle.
+// The resources opened since and still open once it's idle again are leaked if
+// they are still open when the next request comes in.
+function installLeakDetector(watchRequests) {
+	let baseline = null;
+	let candidates = null;
+	let requests = [];
+
+	const report = (open) => {
+		const leaked = {};
+		let count = 0;
+		for (const [rid, name] of candidates) {
+			if (open[rid] === name) {
+				leaked[name] = (leaked[name] ?? 0) + 1;
+				count++;
+			}
+		}
+		if (count > 0) {
+			ops.op_user_worker_resource_leak({ leaked, requests });
+		}
+	};
+
+	watchRequests({
+		busy() {
+			const open = core.resources();
+			if (candidates !== null) {
+				report(open);
+			}
+			baseline = new SafeSet(ObjectKeys(open));
+			candidates = null;
+			requests = [];
+		},
+		request(req) {
+			if (requests.length < MAX_REQUESTS) {
+				requests.push(`${req.method} ${new URL(req.url).pathname}`);
+			}
+		},
+		idle() {
+			if (baseline === null) {
+				return;
+			}
+			candidates = new SafeMap();
+			for (const [rid, name] of ObjectEntries(core.resources())) {
+				if (!baseline.has(rid) && !IGNORED_RESOURCES.includes(name)) {
+					candidates.set(rid, name);
+				}
+			}
+		},
+	});
+}
+
+export { installLeakDetector };
@@ -0,0 +1,50 @@
+const core = globalThis.Deno.core;
+
+function toAddresses(value, name) {
+	if (value === undefined || value === null) {
+		return [];
+	}
+	const addresses = Array.isArray(value) ? value : [value];
+	for (const address of addresses) {
+		if (typeof address !== 'string') {
+			throw new TypeError(`${name} must be an address or a list of addresses`);
+		}
+	}
+	return addresses;
+}
+
+function optionalString(value, name) {
+	if (value === undefined || value === null) {
+		return null;
+	}
+	if (typeof value !== 'string') {
+		throw new TypeError(`${name} must be a string`);
+	}
+	return value;
+}
+
+// Sends mail over SMTP with the account of the service (see mail.rs). The
+// credentials never enter the isolate.
+const MAIL = {
+	// resolves to `{ messageId }`
+	async send(message) {
+		const { from, to, cc, bcc, replyTo, subject = '', text, html } = message ?? {};
+		const mail = {
+			from: optionalString(from, 'from'),
+			to: toAddresses(to, 'to'),
+			cc: toAddresses(cc, 'cc'),
+			bcc: toAddresses(bcc, 'bcc'),
+			replyTo: optionalString(replyTo, 'replyTo'),
+			subject: String(subject),
+			text: optionalString(text, 'text'),
+			html: optionalString(html, 'html'),
+		};
+		if (mail.to.length === 0) {
+			throw new TypeError('mail needs at least one recipient in to');
+		}
+		const messageId = await core.opAsync('op_mail_send', mail);
+		return { messageId };
+	},
+};
+
+export { MAIL };
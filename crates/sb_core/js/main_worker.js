@@ -1,9 +1,37 @@
-import { SUPABASE_USER_WORKERS } from 'ext:sb_user_workers/user_workers.js';
+import { SUPABASE_SERVICES, SUPABASE_USER_WORKERS } from 'ext:sb_user_workers/user_workers.js';
+import {
+	context,
+	getEnvs,
+	getHeaders,
+	remainingBudgetMs,
+} from 'ext:sb_core_main_js/js/user_worker.js';
+import { getErrorCodes, problemResponse } from 'ext:sb_core_main_js/js/problem.js';
+import { FEATURE_FLAGS } from 'ext:sb_core_main_js/js/flags.js';
+import { KEY_STORE_CRYPTO } from 'ext:sb_core_main_js/js/keys.js';
+import { WEBHOOKS } from 'ext:sb_core_main_js/js/webhooks.js';
+import { imagesIfEnabled } from 'ext:sb_core_main_js/js/images.js';
+import { render } from 'ext:sb_core_main_js/js/templates.js';
 
 Object.defineProperty(globalThis, 'EdgeRuntime', {
 	get() {
 		return {
 			userWorkers: SUPABASE_USER_WORKERS,
+			services: SUPABASE_SERVICES,
+			remainingBudgetMs,
+			context,
+			getHeaders,
+			getEnvs,
+			flags: FEATURE_FLAGS,
+			crypto: KEY_STORE_CRYPTO,
+			webhooks: WEBHOOKS,
+			images: imagesIfEnabled(),
+			render,
+			errors: {
+				get codes() {
+					return getErrorCodes();
+				},
+				problemResponse,
+			},
 		};
 	},
 	configurable: true,
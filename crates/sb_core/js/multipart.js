@@ -0,0 +1,112 @@
+import { Request } from 'ext:deno_fetch/23_request.js';
+import {
+	getReadableStreamResourceBacking,
+	resourceForReadableStream,
+} from 'ext:deno_web/06_streams.js';
+import { nonEnumerable } from 'ext:sb_core_main_js/js/fieldUtils.js';
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const {
+	ObjectDefineProperty,
+	TypeError,
+} = globalThis.__bootstrap.primordials;
+
+// body of the `index`th part, ending when the part does
+function partStream(rid, index) {
+	return new ReadableStream({
+		async pull(controller) {
+			const chunk = await core.opAsync('op_multipart_read', rid, index);
+			if (chunk === null) {
+				controller.close();
+			} else {
+				controller.enqueue(chunk);
+			}
+		},
+	});
+}
+
+async function partText(rid, index) {
+	const decoder = new TextDecoder();
+	let text = '';
+	for (;;) {
+		const chunk = await core.opAsync('op_multipart_read', rid, index);
+		if (chunk === null) {
+			return text + decoder.decode();
+		}
+		text += decoder.decode(chunk, { stream: true });
+	}
+}
+
+// Parts are yielded as they are parsed: fields as `{ name, value }` and files
+// as `{ name, filename, type, stream }`. A file's stream has to be read before
+// moving to the next part, what's left of it is skipped otherwise.
+async function* parts(rid, bodyRid) {
+	try {
+		for (let index = 1;; index++) {
+			const part = await core.opAsync('op_multipart_next_part', rid);
+			if (part === null) {
+				return;
+			}
+			if (part.filename === null) {
+				yield { name: part.name, value: await partText(rid, index) };
+			} else {
+				yield {
+					name: part.name,
+					filename: part.filename,
+					type: part.contentType ?? 'application/octet-stream',
+					stream: partStream(rid, index),
+				};
+			}
+		}
+	} finally {
+		core.tryClose(rid);
+		if (bodyRid !== null) {
+			core.tryClose(bodyRid);
+		}
+	}
+}
+
+// Streams a multipart/form-data body through the runtime's parser instead of
+// buffering it like `formData()`. `limits` caps each part: `maxFileBytes`
+// (10MiB by default), `maxFieldBytes` (1MiB), `maxParts` (1000) and
+// `maxHeaderBytes` (16KiB).
+function formDataStream(limits = {}) {
+	const body = this.body;
+	if (body === null) {
+		throw new TypeError('Request has no body');
+	}
+	if (this.bodyUsed || body.locked) {
+		throw new TypeError('Body already consumed');
+	}
+
+	const contentType = this.headers.get('content-type') ?? '';
+	const backing = getReadableStreamResourceBacking(body);
+	let rid;
+	// set when the body resource was created for this parser
+	let bodyRid = null;
+	if (backing) {
+		// locks the body, which is then read by the parser directly
+		body.getReader();
+		rid = backing.rid;
+	} else {
+		bodyRid = resourceForReadableStream(body);
+		rid = bodyRid;
+	}
+
+	try {
+		return parts(ops.op_multipart_start(rid, contentType, limits), bodyRid);
+	} catch (err) {
+		if (bodyRid !== null) {
+			core.tryClose(bodyRid);
+		}
+		throw err;
+	}
+}
+
+function installFormDataStream() {
+	ObjectDefineProperty(Request.prototype, 'formDataStream', nonEnumerable(formDataStream));
+}
+
+export { installFormDataStream };
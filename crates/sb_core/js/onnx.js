@@ -0,0 +1,69 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const TENSOR_TYPES = {
+	float32: Float32Array,
+	int32: Int32Array,
+	int64: BigInt64Array,
+	uint8: Uint8Array,
+};
+
+function tensorType(data) {
+	for (const [type, TypedArray] of Object.entries(TENSOR_TYPES)) {
+		if (data instanceof TypedArray) {
+			return type;
+		}
+	}
+	throw new TypeError(
+		'tensor data must be a Float32Array, Int32Array, BigInt64Array or Uint8Array',
+	);
+}
+
+// A typed array (1-D), or `{ data, shape }`
+function toInput(name, tensor) {
+	const { data, shape = [data?.length] } = ArrayBuffer.isView(tensor) ? { data: tensor } : tensor ?? {};
+	const type = tensorType(data);
+	if (!Array.isArray(shape) || !shape.every((dim) => Number.isInteger(dim) && dim >= 0)) {
+		throw new TypeError(`shape of ${name} must be a list of non-negative integers`);
+	}
+	return {
+		name,
+		type,
+		shape,
+		data: new Uint8Array(data.buffer, data.byteOffset, data.byteLength),
+	};
+}
+
+function toTensor({ type, shape, data }) {
+	const TypedArray = TENSOR_TYPES[type];
+	return {
+		type,
+		shape,
+		data: new TypedArray(data.buffer, data.byteOffset, data.byteLength / TypedArray.BYTES_PER_ELEMENT),
+	};
+}
+
+// ONNX models configured for the service, run on the node (see onnx.rs). Inputs
+// are handed to the model without copying their typed arrays.
+const ONNX = {
+	// names of the models the service can run
+	models() {
+		return ops.op_onnx_models();
+	},
+
+	// eg: `run('embed', { input_ids: { data: ids, shape: [1, ids.length] } })`,
+	// resolving to `{ [output]: { type, shape, data } }`
+	async run(model, inputs) {
+		if (inputs === null || typeof inputs !== 'object') {
+			throw new TypeError('inputs must be an object of tensors by input name');
+		}
+		const outputs = await core.opAsync(
+			'op_onnx_run',
+			String(model),
+			Object.entries(inputs).map(([name, tensor]) => toInput(name, tensor)),
+		);
+		return Object.fromEntries(outputs.map((output) => [output.name, toTensor(output)]));
+	},
+};
+
+export { ONNX };
@@ -0,0 +1,33 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const PROBLEM_CONTENT_TYPE = 'application/problem+json';
+
+// runtime error codes shared with Rust (see problem.rs), keyed by code
+let errorCodes = null;
+
+function getErrorCodes() {
+	if (errorCodes === null) {
+		errorCodes = {};
+		for (const problem of ops.op_runtime_error_codes()) {
+			errorCodes[problem.code] = problem;
+		}
+	}
+	return errorCodes;
+}
+
+// Builds an RFC 7807 `application/problem+json` response for a runtime error code.
+function problemResponse(code, detail) {
+	const problem = getErrorCodes()[code] ?? getErrorCodes()['INTERNAL_ERROR'];
+	const body = { ...problem };
+	if (detail !== undefined && detail !== null) {
+		body.detail = String(detail);
+	}
+
+	return new Response(JSON.stringify(body), {
+		status: problem.status,
+		headers: { 'content-type': PROBLEM_CONTENT_TYPE },
+	});
+}
+
+export { getErrorCodes, problemResponse };
@@ -0,0 +1,125 @@
+import { Conn } from 'ext:deno_net/01_net.js';
+import { errors } from 'ext:sb_core_main_js/js/errors.js';
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// `EdgeRuntime.listen`: raw TCP and UDP sockets for protocols other than HTTP
+// (see raw_net.rs). Only the addresses granted with the `listen` permission of
+// the worker can be listened on, and sockets close with the worker.
+
+function isClosedError(err) {
+	return err instanceof errors.BadResource || err instanceof errors.Interrupted;
+}
+
+class Listener {
+	#rid;
+	#addr;
+
+	constructor(rid, addr) {
+		this.#rid = rid;
+		this.#addr = addr;
+	}
+
+	get rid() {
+		return this.#rid;
+	}
+
+	get addr() {
+		return this.#addr;
+	}
+
+	async accept() {
+		const { 0: rid, 1: localAddr, 2: remoteAddr } = await core.opAsync(
+			'op_raw_accept',
+			this.#rid,
+		);
+		return new Conn(
+			rid,
+			{ transport: 'tcp', ...remoteAddr },
+			{ transport: 'tcp', ...localAddr },
+		);
+	}
+
+	close() {
+		core.tryClose(this.#rid);
+	}
+
+	async *[Symbol.asyncIterator]() {
+		while (true) {
+			let conn;
+			try {
+				conn = await this.accept();
+			} catch (err) {
+				if (isClosedError(err)) {
+					return;
+				}
+				throw err;
+			}
+			yield conn;
+		}
+	}
+}
+
+class Datagram {
+	#rid;
+	#addr;
+
+	constructor(rid, addr) {
+		this.#rid = rid;
+		this.#addr = addr;
+	}
+
+	get rid() {
+		return this.#rid;
+	}
+
+	get addr() {
+		return this.#addr;
+	}
+
+	// `[data, remoteAddr]` of the next datagram
+	async receive(buf = new Uint8Array(65507)) {
+		const { 0: nread, 1: remoteAddr } = await core.opAsync('op_raw_recv', this.#rid, buf);
+		return [buf.subarray(0, nread), { transport: 'udp', ...remoteAddr }];
+	}
+
+	async send(data, { hostname, port }) {
+		return await core.opAsync('op_raw_send', this.#rid, { hostname, port }, data);
+	}
+
+	close() {
+		core.tryClose(this.#rid);
+	}
+
+	async *[Symbol.asyncIterator]() {
+		while (true) {
+			let datagram;
+			try {
+				datagram = await this.receive();
+			} catch (err) {
+				if (isClosedError(err)) {
+					return;
+				}
+				throw err;
+			}
+			yield datagram;
+		}
+	}
+}
+
+function listen({ transport = 'tcp', hostname = '0.0.0.0', port } = {}) {
+	if (transport !== 'tcp' && transport !== 'udp') {
+		throw new TypeError('transport must be "tcp" or "udp"');
+	}
+	if (!Number.isInteger(port) || port < 0 || port > 65535) {
+		throw new TypeError('port must be an integer between 0 and 65535');
+	}
+
+	const { 0: rid, 1: addr } = ops.op_raw_listen({ transport, hostname, port });
+	if (transport === 'udp') {
+		return new Datagram(rid, { transport, ...addr });
+	}
+	return new Listener(rid, { transport, ...addr });
+}
+
+export { listen };
@@ -0,0 +1,93 @@
+const core = globalThis.Deno.core;
+
+function toKey(key, name = 'key') {
+	if (typeof key !== 'string' || key.length === 0) {
+		throw new TypeError(`${name} must be a non-empty string`);
+	}
+	return key;
+}
+
+function toSecs(secs, name) {
+	if (!Number.isInteger(secs) || secs <= 0) {
+		throw new TypeError(`${name} must be a positive integer`);
+	}
+	return secs;
+}
+
+function query(command) {
+	return core.opAsync('op_redis_query', command);
+}
+
+class Subscription {
+	#rid;
+	#closed = false;
+
+	constructor(rid) {
+		this.#rid = rid;
+	}
+
+	close() {
+		if (this.#closed) {
+			return;
+		}
+		this.#closed = true;
+		core.tryClose(this.#rid);
+	}
+
+	async *[Symbol.asyncIterator]() {
+		try {
+			while (!this.#closed) {
+				const message = await core.opAsync('op_redis_next_message', this.#rid);
+				if (message === null) {
+					return;
+				}
+				yield message;
+			}
+		} finally {
+			this.close();
+		}
+	}
+}
+
+// Redis through connections the runtime shares between isolates (see
+// redis_pool.rs). Keys and channels are scoped to the service, and its
+// credentials never enter the isolate. Values are strings.
+const REDIS = {
+	get(key) {
+		return query({ command: 'get', key: toKey(key) });
+	},
+
+	async set(key, value, { exSecs } = {}) {
+		await query({
+			command: 'set',
+			key: toKey(key),
+			value: String(value),
+			exSecs: exSecs === undefined ? null : toSecs(exSecs, 'exSecs'),
+		});
+	},
+
+	// whether the key exists
+	expire(key, secs) {
+		return query({ command: 'expire', key: toKey(key), secs: toSecs(secs, 'secs') });
+	},
+
+	incr(key, by = 1) {
+		if (!Number.isSafeInteger(by)) {
+			throw new TypeError('by must be an integer');
+		}
+		return query({ command: 'incr', key: toKey(key), by });
+	},
+
+	// resolves to the number of subscribers that received the message
+	publish(channel, message) {
+		return query({ command: 'publish', channel: toKey(channel, 'channel'), message: String(message) });
+	},
+
+	// messages published on the channel, `close()` unsubscribes
+	async subscribe(channel) {
+		const rid = await core.opAsync('op_redis_subscribe', toKey(channel, 'channel'));
+		return new Subscription(rid);
+	},
+};
+
+export { REDIS };
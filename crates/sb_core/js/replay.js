@@ -0,0 +1,102 @@
+const {
+	ObjectDefineProperty,
+	TypeError,
+} = globalThis.__bootstrap.primordials;
+
+// mulberry32, values between 0 and 1
+const seededRandom = (seed) => {
+	let state = seed >>> 0;
+	return () => {
+		state = (state + 0x6d2b79f5) >>> 0;
+		let t = state;
+		t = Math.imul(t ^ (t >>> 15), t | 1);
+		t ^= t + Math.imul(t ^ (t >>> 7), t | 61);
+		return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+	};
+};
+
+const installClock = (now) => {
+	const NativeDate = globalThis.Date;
+
+	class FixedDate extends NativeDate {
+		constructor(...args) {
+			if (args.length === 0) {
+				super(now());
+			} else {
+				super(...args);
+			}
+		}
+
+		static now() {
+			return now();
+		}
+	}
+	globalThis.Date = FixedDate;
+};
+
+// Pins the clock to the time a request was recorded at and seeds Math.random, so
+// a replayed request sees the same time and random values on every replay.
+const installReplayClock = ({ timeOriginMs, randomSeed }) => {
+	const NativeDate = globalThis.Date;
+	const offset = timeOriginMs - NativeDate.now();
+	installClock(() => NativeDate.now() + offset);
+
+	Math.random = seededRandom(randomSeed);
+};
+
+// Seeds Math.random and crypto.getRandomValues (and so crypto.randomUUID) and
+// coarsens the clock to `timerResolutionMs` steps from `timeOriginMs`, for
+// workers created with `deterministic`. The random values are predictable, this
+// is only meant for replaying and debugging executions.
+const installDeterministicMode = ({ seed, timeOriginMs, timerResolutionMs }) => {
+	const NativeDate = globalThis.Date;
+	const nativeNow = performance.now.bind(performance);
+	const bootedAt = nativeNow();
+	const origin = timeOriginMs ?? NativeDate.now();
+	const elapsed = () =>
+		Math.floor((nativeNow() - bootedAt) / timerResolutionMs) * timerResolutionMs;
+
+	installClock(() => origin + elapsed());
+	ObjectDefineProperty(performance, 'now', {
+		value: () => bootedAt + elapsed(),
+		writable: true,
+		configurable: true,
+	});
+
+	Math.random = seededRandom(seed);
+
+	// a stream of its own, so Math.random calls don't shift the random bytes
+	const randomByte = seededRandom(seed ^ 0x9e3779b9);
+	const getRandomValues = (array) => {
+		const isIntegerArray = ArrayBuffer.isView(array) && !(array instanceof DataView) &&
+			!(array instanceof Float32Array) && !(array instanceof Float64Array);
+		if (!isIntegerArray) {
+			throw new TypeError('Argument 1 must be an integer typed array');
+		}
+		const bytes = new Uint8Array(array.buffer, array.byteOffset, array.byteLength);
+		for (let i = 0; i < bytes.length; i++) {
+			bytes[i] = Math.floor(randomByte() * 256);
+		}
+		return array;
+	};
+	const randomUUID = () => {
+		const bytes = getRandomValues(new Uint8Array(16));
+		bytes[6] = (bytes[6] & 0x0f) | 0x40;
+		bytes[8] = (bytes[8] & 0x3f) | 0x80;
+		const hex = Array.from(bytes, (byte) => byte.toString(16).padStart(2, '0')).join('');
+		const parts = [hex.slice(0, 8), hex.slice(8, 12), hex.slice(12, 16), hex.slice(16, 20)];
+		return [...parts, hex.slice(20)].join('-');
+	};
+	ObjectDefineProperty(globalThis.crypto, 'getRandomValues', {
+		value: getRandomValues,
+		writable: true,
+		configurable: true,
+	});
+	ObjectDefineProperty(globalThis.crypto, 'randomUUID', {
+		value: randomUUID,
+		writable: true,
+		configurable: true,
+	});
+};
+
+export { installDeterministicMode, installReplayClock };
@@ -0,0 +1,87 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const encoder = new TextEncoder();
+
+function toBytes(chunk) {
+	if (typeof chunk === 'string') {
+		return encoder.encode(chunk);
+	}
+	if (ArrayBuffer.isView(chunk)) {
+		return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+	}
+	if (chunk instanceof ArrayBuffer) {
+		return new Uint8Array(chunk);
+	}
+	throw new TypeError('chunk must be a string, an ArrayBuffer or a view of one');
+}
+
+// Text (or bytes) in, one record out per line. Only the record being read is
+// buffered, up to `maxRecordBytes`.
+function parseStream(format, opts) {
+	const rid = ops.op_tabular_parser_new(format, opts);
+	return new TransformStream({
+		transform(chunk, controller) {
+			const bytes = toBytes(chunk);
+			// an empty chunk ends the file
+			if (bytes.byteLength === 0) {
+				return;
+			}
+			for (const record of ops.op_tabular_parse(rid, bytes)) {
+				controller.enqueue(record);
+			}
+		},
+		flush(controller) {
+			for (const record of ops.op_tabular_parse(rid, new Uint8Array())) {
+				controller.enqueue(record);
+			}
+		},
+	});
+}
+
+// Records in, text out
+function stringifyStream(format, opts) {
+	const rid = ops.op_tabular_writer_new(format, opts);
+	return new TransformStream({
+		transform(record, controller) {
+			controller.enqueue(ops.op_tabular_stringify(rid, [record]));
+		},
+		flush() {
+			core.tryClose(rid);
+		},
+	});
+}
+
+function streamClass(create) {
+	return class {
+		#stream;
+
+		constructor(opts = {}) {
+			this.#stream = create(opts);
+		}
+
+		get readable() {
+			return this.#stream.readable;
+		}
+
+		get writable() {
+			return this.#stream.writable;
+		}
+	};
+}
+
+// `new CSV.ParseStream({ header, delimiter, maxRecordBytes, maxFields })` yields
+// arrays of fields, or objects keyed by the header's fields.
+// `new CSV.StringifyStream({ columns, delimiter })` takes arrays or objects.
+const CSV = {
+	ParseStream: streamClass((opts) => parseStream('csv', opts)),
+	StringifyStream: streamClass((opts) => stringifyStream('csv', opts)),
+};
+
+// `new NDJSON.ParseStream({ maxRecordBytes })`, blank lines are skipped
+const NDJSON = {
+	ParseStream: streamClass((opts) => parseStream('ndjson', opts)),
+	StringifyStream: streamClass((opts) => stringifyStream('ndjson', opts)),
+};
+
+export { CSV, NDJSON };
@@ -0,0 +1,10 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// Renders a template of the service's `templates` directory, eg:
+// `new Response(EdgeRuntime.render('pages/index.html', { user }), { headers: { 'content-type': 'text/html' } })`
+function render(template, data = {}) {
+	return ops.op_render_template(String(template), data);
+}
+
+export { render };
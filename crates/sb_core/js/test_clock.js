@@ -0,0 +1,163 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const {
+	ObjectDefineProperty,
+	TypeError,
+} = globalThis.__bootstrap.primordials;
+
+let testing = null;
+
+// Swaps `Date`, `performance.now` and the timers for ones reading the runtime's
+// test clock. While it's frozen, timers are only fired by `clock.advance()`.
+function installTestClock() {
+	const NativeDate = globalThis.Date;
+	const native = {
+		setTimeout: globalThis.setTimeout,
+		clearTimeout: globalThis.clearTimeout,
+		setInterval: globalThis.setInterval,
+		clearInterval: globalThis.clearInterval,
+	};
+	const now = () => ops.op_test_clock_now();
+	const frozen = () => ops.op_test_clock_frozen();
+
+	class TestDate extends NativeDate {
+		constructor(...args) {
+			if (args.length === 0) {
+				super(now());
+			} else {
+				super(...args);
+			}
+		}
+
+		static now() {
+			return now();
+		}
+	}
+	globalThis.Date = TestDate;
+
+	const timeOrigin = performance.timeOrigin;
+	ObjectDefineProperty(performance, 'now', {
+		value: () => now() - timeOrigin,
+		writable: true,
+		configurable: true,
+	});
+
+	// timers created while the clock is frozen, `native` is set once they were
+	// handed to the real timers by `restore()`
+	const timers = new Map();
+	let nextId = 1_000_000_000;
+
+	const schedule = (fn, delay, args, repeat) => {
+		const ms = Math.max(0, Number(delay) || 0);
+		if (!frozen()) {
+			return repeat ? native.setInterval(fn, ms, ...args) : native.setTimeout(fn, ms, ...args);
+		}
+		const id = nextId++;
+		timers.set(id, { at: now() + ms, fn, args, interval: repeat ? Math.max(1, ms) : null });
+		return id;
+	};
+	const clear = (id) => {
+		const timer = timers.get(id);
+		if (timer === undefined) {
+			native.clearTimeout(id);
+			return;
+		}
+		timers.delete(id);
+		if (timer.native !== undefined) {
+			native.clearTimeout(timer.native);
+		}
+	};
+	globalThis.setTimeout = (fn, delay, ...args) => schedule(fn, delay, args, false);
+	globalThis.setInterval = (fn, delay, ...args) => schedule(fn, delay, args, true);
+	globalThis.clearTimeout = clear;
+	globalThis.clearInterval = clear;
+
+	// earliest frozen timer due by `until`
+	const nextDue = (until) => {
+		let next = null;
+		for (const entry of timers) {
+			const timer = entry[1];
+			if (timer.native === undefined && timer.at <= until && (next === null || timer.at < next[1].at)) {
+				next = entry;
+			}
+		}
+		return next;
+	};
+
+	const clock = {
+		now,
+
+		get frozen() {
+			return frozen();
+		},
+
+		// stops the clock at `at` (a Date or milliseconds since the epoch), now by default
+		freeze(at) {
+			ops.op_test_clock_freeze(at === undefined ? now() : Number(at));
+		},
+
+		// moves the clock without firing timers
+		set(at) {
+			ops.op_test_clock_set(Number(at));
+		},
+
+		// moves the frozen clock `ms` forward, firing the timers due on the way in
+		// order (each at its own time), and letting their promises settle
+		async advance(ms) {
+			if (!frozen()) {
+				throw new TypeError('the clock has to be frozen to be advanced');
+			}
+			const until = now() + Math.max(0, Number(ms) || 0);
+			for (let entry = nextDue(until); entry !== null; entry = nextDue(until)) {
+				const [id, timer] = entry;
+				ops.op_test_clock_set(timer.at);
+				if (timer.interval === null) {
+					timers.delete(id);
+				} else {
+					timer.at += timer.interval;
+				}
+				timer.fn(...timer.args);
+				await new Promise((resolve) => native.setTimeout(resolve, 0));
+			}
+			ops.op_test_clock_set(until);
+		},
+
+		// lets the clock run again from where it is, pending timers then fire in
+		// real time
+		restore() {
+			ops.op_test_clock_restore();
+			const current = now();
+			for (const [id, timer] of timers) {
+				if (timer.native !== undefined) {
+					continue;
+				}
+				timer.native = native.setTimeout(() => {
+					if (timer.interval === null) {
+						timers.delete(id);
+					} else {
+						timer.native = native.setInterval(timer.fn, timer.interval, ...timer.args);
+					}
+					timer.fn(...timer.args);
+				}, Math.max(0, timer.at - current));
+			}
+		},
+	};
+
+	testing = { clock };
+}
+
+// Lets the test runner take the V8 coverage of the modules the worker ran
+function installTestCoverage() {
+	testing = {
+		...testing,
+		takeCoverage: () => core.opAsync('op_test_coverage_take'),
+	};
+}
+
+// `EdgeRuntime.testing`, only set for workers running tests
+function testingIfEnabled() {
+	return testing;
+}
+
+export { installTestClock, installTestCoverage, testingIfEnabled };
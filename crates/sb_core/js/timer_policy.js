@@ -0,0 +1,67 @@
+const {
+	RangeError,
+} = globalThis.__bootstrap.primordials;
+
+// Applies the timer policy a worker was created with (`timers`): delays are at
+// least `minDelayMs`, at most `maxTimers` timers may be pending at a time, and
+// while the worker is idle (not serving any request) timers are delayed to the
+// next multiple of `idleCoalesceMs` of the clock, so the timers of all the idle
+// workers of the node fire on the same wakeups.
+function installTimerPolicy({ minDelayMs, maxTimers, idleCoalesceMs }, isIdle) {
+	const native = {
+		setTimeout: globalThis.setTimeout,
+		clearTimeout: globalThis.clearTimeout,
+		setInterval: globalThis.setInterval,
+		clearInterval: globalThis.clearInterval,
+	};
+	const pending = new Set();
+
+	const delayOf = (delay, repeat) => {
+		let ms = Math.max(0, Number(delay) || 0, minDelayMs ?? 0);
+		if (idleCoalesceMs && isIdle()) {
+			if (repeat) {
+				ms = Math.ceil(Math.max(ms, 1) / idleCoalesceMs) * idleCoalesceMs;
+			} else {
+				const now = Date.now();
+				ms = Math.ceil((now + ms) / idleCoalesceMs) * idleCoalesceMs - now;
+			}
+		}
+		return ms;
+	};
+	const checkCapacity = () => {
+		if (maxTimers && pending.size >= maxTimers) {
+			throw new RangeError(
+				`too many pending timers, the worker may have at most ${maxTimers} (timers.maxTimers)`,
+			);
+		}
+	};
+
+	globalThis.setTimeout = (fn, delay, ...args) => {
+		checkCapacity();
+		const callback = typeof fn === 'function'
+			? (...args) => {
+				pending.delete(id);
+				return fn(...args);
+			}
+			: fn;
+		const id = native.setTimeout(callback, delayOf(delay, false), ...args);
+		pending.add(id);
+		return id;
+	};
+	globalThis.setInterval = (fn, delay, ...args) => {
+		checkCapacity();
+		const id = native.setInterval(fn, delayOf(delay, true), ...args);
+		pending.add(id);
+		return id;
+	};
+	globalThis.clearTimeout = (id) => {
+		pending.delete(id);
+		native.clearTimeout(id);
+	};
+	globalThis.clearInterval = (id) => {
+		pending.delete(id);
+		native.clearInterval(id);
+	};
+}
+
+export { installTimerPolicy };
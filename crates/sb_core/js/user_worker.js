@@ -0,0 +1,86 @@
+import { FEATURE_FLAGS } from 'ext:sb_core_main_js/js/flags.js';
+import { ALARMS } from 'ext:sb_core_main_js/js/alarms.js';
+import { AI } from 'ext:sb_core_main_js/js/ai.js';
+import { ONNX } from 'ext:sb_core_main_js/js/onnx.js';
+import { KEY_STORE_CRYPTO } from 'ext:sb_core_main_js/js/keys.js';
+import { MAIL } from 'ext:sb_core_main_js/js/mail.js';
+import { REDIS } from 'ext:sb_core_main_js/js/redis.js';
+import { WEBHOOKS } from 'ext:sb_core_main_js/js/webhooks.js';
+import { COMPRESSION } from 'ext:sb_core_main_js/js/compression.js';
+import { JSON_SCHEMA } from 'ext:sb_core_main_js/js/json_schema.js';
+import { CSV, NDJSON } from 'ext:sb_core_main_js/js/tabular.js';
+import { IDS } from 'ext:sb_core_main_js/js/ids.js';
+import { testingIfEnabled } from 'ext:sb_core_main_js/js/test_clock.js';
+import { imagesIfEnabled } from 'ext:sb_core_main_js/js/images.js';
+import { render } from 'ext:sb_core_main_js/js/templates.js';
+import { getHeaders, requestMetadata } from 'ext:sb_core_main_js/js/http.js';
+import { getEnvs } from 'ext:sb_env/env.js';
+import { listen } from 'ext:sb_core_main_js/js/raw_net.js';
+
+const DEADLINE_AT_HEADER = 'x-deadline-at';
+const GEO_COUNTRY_HEADER = 'x-geo-country';
+const GEO_REGION_HEADER = 'x-geo-region';
+const GEO_ASN_HEADER = 'x-geo-asn';
+
+// Milliseconds left until the request's deadline, or `null` if it has none.
+function remainingBudgetMs(req) {
+	const deadlineAt = Number(req?.headers?.get(DEADLINE_AT_HEADER));
+	if (!deadlineAt) {
+		return null;
+	}
+	return Math.max(0, deadlineAt - Date.now());
+}
+
+// region and zone of the node serving requests
+let nodeIdentity = { region: null, zone: null };
+
+function setNodeIdentity(node) {
+	nodeIdentity = { region: node?.region ?? null, zone: node?.zone ?? null };
+}
+
+// What the runtime knows about a request. `geo` is `null` unless the runtime
+// was started with a geoip database and found the client in it, `metadata` is
+// `null` unless the main worker attached some to the request.
+function context(req) {
+	const [country, region, asn] = getHeaders(req, [
+		GEO_COUNTRY_HEADER,
+		GEO_REGION_HEADER,
+		GEO_ASN_HEADER,
+	]);
+	const geo = country || region || asn
+		? { country, region, asn: asn ? Number(asn) : null }
+		: null;
+	const metadata = requestMetadata.get(req) ?? null;
+	return { geo, metadata, ...nodeIdentity };
+}
+
+// `EdgeRuntime` as seen by user workers
+const USER_EDGE_RUNTIME = {
+	remainingBudgetMs,
+	context,
+	getHeaders,
+	getEnvs,
+	flags: FEATURE_FLAGS,
+	alarms: ALARMS,
+	crypto: KEY_STORE_CRYPTO,
+	mail: MAIL,
+	redis: REDIS,
+	ai: AI,
+	onnx: ONNX,
+	webhooks: WEBHOOKS,
+	compression: COMPRESSION,
+	jsonSchema: JSON_SCHEMA,
+	csv: CSV,
+	ndjson: NDJSON,
+	ids: IDS,
+	render,
+	listen,
+	get images() {
+		return imagesIfEnabled();
+	},
+	get testing() {
+		return testingIfEnabled();
+	},
+};
+
+export { context, getEnvs, getHeaders, remainingBudgetMs, setNodeIdentity, USER_EDGE_RUNTIME };
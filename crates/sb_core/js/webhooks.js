@@ -0,0 +1,100 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+const encoder = new TextEncoder();
+
+const PROVIDERS = ['stripe', 'github', 'svix'];
+
+function toBytes(payload) {
+	if (typeof payload === 'string') {
+		return encoder.encode(payload);
+	}
+	if (ArrayBuffer.isView(payload)) {
+		return new Uint8Array(payload.buffer, payload.byteOffset, payload.byteLength);
+	}
+	if (payload instanceof ArrayBuffer) {
+		return new Uint8Array(payload);
+	}
+	throw new TypeError('payload must be a string, an ArrayBuffer or a view of one');
+}
+
+function toHeaderMap(headers) {
+	const map = {};
+	for (const [name, value] of new Headers(headers)) {
+		map[name] = value;
+	}
+	return map;
+}
+
+function checkOptions(opts) {
+	const { provider, secret, toleranceSecs } = opts ?? {};
+	if (!PROVIDERS.includes(provider)) {
+		throw new TypeError(`provider must be one of ${PROVIDERS.join(', ')}`);
+	}
+	if (typeof secret !== 'string' || secret === '') {
+		throw new TypeError('secret is required');
+	}
+	if (toleranceSecs !== undefined && !(Number.isInteger(toleranceSecs) && toleranceSecs >= 0)) {
+		throw new TypeError('toleranceSecs must be a non-negative integer');
+	}
+	return { provider, secret, toleranceSecs: toleranceSecs ?? null };
+}
+
+// Verifies the signature of a webhook payload against its headers.
+// Returns `{ valid, reason, timestamp }`.
+function verifyPayload(payload, headers, opts) {
+	const { provider, secret, toleranceSecs } = checkOptions(opts);
+	return ops.op_webhook_verify(
+		provider,
+		secret,
+		toHeaderMap(headers),
+		toBytes(payload),
+		toleranceSecs,
+	);
+}
+
+// Webhooks sent by the service are queued by the runtime (see
+// outbound_webhooks.rs), which POSTs them as JSON, signed with `signingKey` of
+// the key store, and retries them until the receiver responds with a 2xx status.
+// Deliveries that run out of attempts are dead-lettered until redelivered.
+function send({ url, payload = null, eventType, signingKey } = {}) {
+	if (typeof url !== 'string' && !(url instanceof URL)) {
+		throw new TypeError('url is required');
+	}
+	return ops.op_webhook_send({
+		url: String(url),
+		payload,
+		eventType: eventType === undefined ? null : String(eventType),
+		signingKey: signingKey === undefined ? null : String(signingKey),
+	});
+}
+
+// Signature checks for the webhook formats of common providers, done in Rust
+// with constant-time comparisons. `verify` consumes the request body and hands
+// it back as `payload`.
+const WEBHOOKS = {
+	async verify(req, opts) {
+		checkOptions(opts);
+		const payload = new Uint8Array(await req.arrayBuffer());
+		return { ...verifyPayload(payload, req.headers, opts), payload };
+	},
+
+	verifyPayload,
+
+	send,
+
+	// the delivery with the id returned by `send`, `null` once it's forgotten
+	status(id) {
+		return ops.op_webhook_status(String(id));
+	},
+
+	deadLetters() {
+		return ops.op_webhook_dead_letters();
+	},
+
+	redeliver(id) {
+		return ops.op_webhook_redeliver(String(id));
+	},
+};
+
+export { WEBHOOKS };
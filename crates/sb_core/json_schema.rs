@@ -0,0 +1,148 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::{self, Value};
+use deno_core::OpState;
+use jsonschema::JSONSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// schemas a worker may compile, each is kept until the worker exits
+const MAX_SCHEMAS: usize = 256;
+// errors reported by a single validation
+const MAX_ERRORS: usize = 100;
+
+// Schemas compiled by a worker, by their source so compiling one again (eg: on
+// every request) is a lookup
+#[derive(Default)]
+pub struct JsonSchemas {
+    ids: HashMap<String, u32>,
+    compiled: Vec<JSONSchema>,
+}
+
+impl JsonSchemas {
+    fn compile(&mut self, schema: &Value) -> Result<u32, AnyError> {
+        let source = schema.to_string();
+        if let Some(id) = self.ids.get(&source) {
+            return Ok(*id);
+        }
+        if self.compiled.len() >= MAX_SCHEMAS {
+            return Err(custom_error(
+                "RangeError",
+                format!("a worker can't compile more than {} schemas", MAX_SCHEMAS),
+            ));
+        }
+        let compiled = JSONSchema::compile(schema)
+            .map_err(|err| custom_error("TypeError", format!("invalid schema: {}", err)))?;
+        let id = self.compiled.len() as u32;
+        self.compiled.push(compiled);
+        self.ids.insert(source, id);
+        Ok(id)
+    }
+
+    fn get(&self, id: u32) -> Result<&JSONSchema, AnyError> {
+        self.compiled
+            .get(id as usize)
+            .ok_or_else(|| custom_error("TypeError", "unknown schema"))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaError {
+    // JSON pointer to the invalid value (eg: `/items/0/name`)
+    pub instance_path: String,
+    // JSON pointer to the keyword it failed (eg: `/properties/items/items/required`)
+    pub schema_path: String,
+    pub message: String,
+}
+
+fn validate(schema: &JSONSchema, instance: &Value) -> Vec<SchemaError> {
+    match schema.validate(instance) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .take(MAX_ERRORS)
+            .map(|err| SchemaError {
+                instance_path: err.instance_path.to_string(),
+                schema_path: err.schema_path.to_string(),
+                message: err.to_string(),
+            })
+            .collect(),
+    }
+}
+
+#[op2]
+pub fn op_json_schema_compile(
+    state: &mut OpState,
+    #[serde] schema: Value,
+) -> Result<u32, AnyError> {
+    state.borrow_mut::<JsonSchemas>().compile(&schema)
+}
+
+#[op2]
+#[serde]
+pub fn op_json_schema_validate(
+    state: &mut OpState,
+    id: u32,
+    #[serde] instance: Value,
+) -> Result<Vec<SchemaError>, AnyError> {
+    let schemas = state.borrow::<JsonSchemas>();
+    Ok(validate(schemas.get(id)?, &instance))
+}
+
+// Parses `json` (eg: a request body read as text) in Rust before validating it,
+// which is cheaper than passing the parsed value
+#[op2]
+#[serde]
+pub fn op_json_schema_validate_json(
+    state: &mut OpState,
+    id: u32,
+    #[string] json: &str,
+) -> Result<Vec<SchemaError>, AnyError> {
+    let instance: Value = serde_json::from_str(json)
+        .map_err(|err| custom_error("SyntaxError", format!("invalid JSON: {}", err)))?;
+    let schemas = state.borrow::<JsonSchemas>();
+    Ok(validate(schemas.get(id)?, &instance))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate, JsonSchemas};
+    use deno_core::serde_json::json;
+
+    #[test]
+    fn test_validate_json_schema() {
+        let mut schemas = JsonSchemas::default();
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            },
+        });
+        let id = schemas.compile(&schema).unwrap();
+        // compiled once
+        assert_eq!(schemas.compile(&schema).unwrap(), id);
+        assert_eq!(schemas.compiled.len(), 1);
+
+        let compiled = schemas.get(id).unwrap();
+        assert!(validate(compiled, &json!({ "name": "a", "tags": ["b"] })).is_empty());
+
+        let errors = validate(compiled, &json!({ "tags": ["b", 1] }));
+        let mut paths: Vec<_> = errors
+            .iter()
+            .map(|err| (err.instance_path.as_str(), err.schema_path.as_str()))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                ("", "/required"),
+                ("/tags/1", "/properties/tags/items/type")
+            ]
+        );
+
+        assert!(schemas.compile(&json!({ "type": 1 })).is_err());
+        assert!(schemas.get(7).is_err());
+    }
+}
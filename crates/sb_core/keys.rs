@@ -0,0 +1,47 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{JsBuffer, OpState, ToJsBuffer};
+use sb_worker_context::keys::{SharedSigner, WorkerKeys};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Unknown keys and keys the service may not use are reported the same way, so
+// tenants can't probe for each other's keys
+fn signer(state: &OpState, name: &str) -> Result<SharedSigner, AnyError> {
+    state
+        .try_borrow::<WorkerKeys>()
+        .and_then(|keys| keys.store.get(name, &keys.service))
+        .ok_or_else(|| custom_error("NotFound", format!("no signing key named {}", name)))
+}
+
+#[op2]
+#[serde]
+pub fn op_key_names(state: &mut OpState) -> Vec<String> {
+    match state.try_borrow::<WorkerKeys>() {
+        Some(keys) => keys.store.names(&keys.service),
+        None => vec![],
+    }
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_key_sign(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[buffer] data: JsBuffer,
+) -> Result<ToJsBuffer, AnyError> {
+    let signer = signer(&state.borrow(), &name)?;
+    let signature = signer.sign(data.to_vec()).await?;
+    Ok(signature.into())
+}
+
+#[op2(async)]
+pub async fn op_key_verify(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[buffer] data: JsBuffer,
+    #[buffer] signature: JsBuffer,
+) -> Result<bool, AnyError> {
+    let signer = signer(&state.borrow(), &name)?;
+    signer.verify(data.to_vec(), signature.to_vec()).await
+}
@@ -1,7 +1,27 @@
+pub mod ai;
+pub mod alarms;
+pub mod compression;
+pub mod coverage;
+pub mod flags;
 pub mod http_start;
+pub mod ids;
+pub mod images;
+pub mod json_schema;
+pub mod keys;
+pub mod mail;
+pub mod multipart;
 pub mod net;
+pub mod onnx;
+pub mod outbound_webhooks;
 pub mod permissions;
+pub mod problem;
+pub mod raw_net;
+pub mod redis;
 pub mod runtime;
+pub mod tabular;
+pub mod templates;
+pub mod test_clock;
+pub mod webhooks;
 
 deno_core::extension!(
     sb_core_main_js,
@@ -11,10 +31,34 @@ deno_core::extension!(
         "js/errors.js",
         "js/fieldUtils.js",
         "js/promises.js",
+        "js/problem.js",
         "js/http.js",
+        "js/multipart.js",
+        "js/raw_net.js",
         "js/denoOverrides.js",
         "js/navigator.js",
         "js/bootstrap.js",
         "js/main_worker.js",
+        "js/user_worker.js",
+        "js/replay.js",
+        "js/flags.js",
+        "js/alarms.js",
+        "js/ai.js",
+        "js/onnx.js",
+        "js/keys.js",
+        "js/mail.js",
+        "js/redis.js",
+        "js/webhooks.js",
+        "js/images.js",
+        "js/compression.js",
+        "js/json_schema.js",
+        "js/tabular.js",
+        "js/ids.js",
+        "js/test_clock.js",
+        "js/code_generation.js",
+        "js/timer_policy.js",
+        "js/leaks.js",
+        "js/templates.js",
+        "js/api_shims.js",
     ]
 );
@@ -0,0 +1,27 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::OpState;
+use sb_worker_context::mail::{OutgoingMail, WorkerMailer};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Resolves to the message id of the sent mail
+#[op2(async)]
+#[string]
+pub async fn op_mail_send(
+    state: Rc<RefCell<OpState>>,
+    #[serde] mail: OutgoingMail,
+) -> Result<String, AnyError> {
+    let send = {
+        let state = state.borrow();
+        let Some(worker_mailer) = state.try_borrow::<WorkerMailer>() else {
+            return Err(custom_error(
+                "NotSupported",
+                "mail isn't configured for the runtime",
+            ));
+        };
+        worker_mailer.mailer.send(&worker_mailer.service, mail)
+    };
+    send.await
+        .map_err(|err| custom_error("MailError", format!("{:#}", err)))
+}
@@ -0,0 +1,580 @@
+use bytes::{Buf, BytesMut};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{
+    AsyncRefCell, CancelFuture, CancelHandle, OpState, RcRef, Resource, ResourceId, ToJsBuffer,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// bytes read from the request body at once
+const READ_SIZE: usize = 64 * 1024;
+
+fn default_max_file_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_field_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_max_parts() -> usize {
+    1000
+}
+
+fn default_max_header_bytes() -> usize {
+    16 * 1024
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MultipartLimits {
+    // size of each file part
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    // size of each part without a filename, which is read as text
+    #[serde(default = "default_max_field_bytes")]
+    pub max_field_bytes: u64,
+    #[serde(default = "default_max_parts")]
+    pub max_parts: usize,
+    // size of the headers of each part
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: default_max_file_bytes(),
+            max_field_bytes: default_max_field_bytes(),
+            max_parts: default_max_parts(),
+            max_header_bytes: default_max_header_bytes(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PartHeaders {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Malformed(&'static str),
+    TooLarge(String),
+}
+
+impl From<MultipartError> for AnyError {
+    fn from(err: MultipartError) -> Self {
+        match err {
+            MultipartError::Malformed(msg) => {
+                custom_error("TypeError", format!("invalid multipart body: {}", msg))
+            }
+            MultipartError::TooLarge(msg) => custom_error("RangeError", msg),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    Part(PartHeaders),
+    Data(Vec<u8>),
+    PartEnd,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    // right after a boundary, either the last one or followed by a part
+    Boundary,
+    Headers,
+    Body,
+    Done,
+}
+
+// Push parser of a multipart/form-data body, emitting the data of each part as
+// it arrives so uploads never have to be held in memory
+pub struct MultipartParser {
+    // `\r\n--boundary`, the body is prefixed with `\r\n` so the first boundary
+    // matches too
+    delimiter: Vec<u8>,
+    buf: BytesMut,
+    state: State,
+    limits: MultipartLimits,
+    parts: usize,
+    part_limit: u64,
+    part_bytes: u64,
+    eof: bool,
+}
+
+// Boundary of a `multipart/form-data` content type
+pub fn parse_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let essence = params.next()?.trim();
+    if !essence.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Value of a `Content-Disposition` parameter, unquoted
+fn disposition_param(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let (key, after) = rest.split_once('=')?;
+        let key = key.trim();
+        let after = after.trim_start();
+        let (param, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let mut param = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            param.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => param.push(c),
+                }
+            }
+            (param, &quoted[end..])
+        } else {
+            let end = after.find(';').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(param);
+        }
+        rest = next;
+    }
+}
+
+fn parse_headers(block: &[u8]) -> Result<PartHeaders, MultipartError> {
+    let block = String::from_utf8_lossy(block);
+    let mut disposition = None;
+    let mut content_type = None;
+    for line in block.split("\r\n") {
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(MultipartError::Malformed("invalid part header"));
+        };
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("content-disposition") {
+            disposition = Some(value.trim().to_string());
+        } else if key.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    let disposition = disposition.ok_or(MultipartError::Malformed(
+        "part without a content-disposition",
+    ))?;
+    let is_form_data = disposition
+        .split(';')
+        .next()
+        .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("form-data"));
+    if !is_form_data {
+        return Err(MultipartError::Malformed("part isn't form-data"));
+    }
+    let name = disposition_param(&disposition, "name")
+        .ok_or(MultipartError::Malformed("part without a name"))?;
+    Ok(PartHeaders {
+        name,
+        filename: disposition_param(&disposition, "filename"),
+        content_type,
+    })
+}
+
+enum Progress {
+    Emit(Step),
+    // moved to another state
+    Continue,
+    NeedData,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: MultipartLimits) -> Self {
+        let mut buf = BytesMut::with_capacity(READ_SIZE);
+        buf.extend_from_slice(b"\r\n");
+        Self {
+            delimiter: [&b"\r\n--"[..], boundary.as_bytes()].concat(),
+            buf,
+            state: State::Preamble,
+            limits,
+            parts: 0,
+            part_limit: 0,
+            part_bytes: 0,
+            eof: false,
+        }
+    }
+
+    // Adds a chunk of the body, an empty one once it ended
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            self.eof = true;
+        } else if self.state != State::Done {
+            self.buf.extend_from_slice(chunk);
+        }
+    }
+
+    // 1-based index of the part being read, if any
+    pub fn current_part(&self) -> Option<usize> {
+        (self.state == State::Body).then_some(self.parts)
+    }
+
+    // Next step of the body, None when more of it has to be fed first
+    pub fn step(&mut self) -> Result<Option<Step>, MultipartError> {
+        loop {
+            let progress = match self.state {
+                State::Preamble => self.preamble(),
+                State::Boundary => self.boundary()?,
+                State::Headers => self.headers()?,
+                State::Body => self.body()?,
+                State::Done => Progress::Emit(Step::End),
+            };
+            match progress {
+                Progress::Emit(step) => return Ok(Some(step)),
+                Progress::Continue => {}
+                Progress::NeedData if self.eof => {
+                    return Err(MultipartError::Malformed("unexpected end of body"))
+                }
+                Progress::NeedData => return Ok(None),
+            }
+        }
+    }
+
+    fn preamble(&mut self) -> Progress {
+        match find(&self.buf, &self.delimiter) {
+            Some(i) => {
+                self.buf.advance(i + self.delimiter.len());
+                self.state = State::Boundary;
+                Progress::Continue
+            }
+            None => {
+                // the end of the buffer may be the start of the boundary
+                let keep = self.delimiter.len() - 1;
+                if self.buf.len() > keep {
+                    self.buf.advance(self.buf.len() - keep);
+                }
+                Progress::NeedData
+            }
+        }
+    }
+
+    fn boundary(&mut self) -> Result<Progress, MultipartError> {
+        // transport padding
+        let padding = self
+            .buf
+            .iter()
+            .take_while(|b| **b == b' ' || **b == b'\t')
+            .count();
+        if padding > 64 {
+            return Err(MultipartError::Malformed("invalid boundary"));
+        }
+        let rest = &self.buf[padding..];
+        if rest.len() < 2 {
+            return Ok(Progress::NeedData);
+        }
+        if rest.starts_with(b"--") {
+            self.buf.clear();
+            self.state = State::Done;
+            Ok(Progress::Emit(Step::End))
+        } else if rest.starts_with(b"\r\n") {
+            self.buf.advance(padding + 2);
+            self.state = State::Headers;
+            Ok(Progress::Continue)
+        } else {
+            Err(MultipartError::Malformed("invalid boundary"))
+        }
+    }
+
+    fn headers(&mut self) -> Result<Progress, MultipartError> {
+        if self.buf.starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed("part without headers"));
+        }
+        let Some(end) = find(&self.buf, b"\r\n\r\n") else {
+            if self.buf.len() > self.limits.max_header_bytes {
+                return Err(MultipartError::TooLarge(format!(
+                    "part headers are larger than {} bytes",
+                    self.limits.max_header_bytes
+                )));
+            }
+            return Ok(Progress::NeedData);
+        };
+        if end > self.limits.max_header_bytes {
+            return Err(MultipartError::TooLarge(format!(
+                "part headers are larger than {} bytes",
+                self.limits.max_header_bytes
+            )));
+        }
+
+        let headers = parse_headers(&self.buf[..end])?;
+        self.buf.advance(end + 4);
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::TooLarge(format!(
+                "body has more than {} parts",
+                self.limits.max_parts
+            )));
+        }
+        self.part_limit = if headers.filename.is_some() {
+            self.limits.max_file_bytes
+        } else {
+            self.limits.max_field_bytes
+        };
+        self.part_bytes = 0;
+        self.state = State::Body;
+        Ok(Progress::Emit(Step::Part(headers)))
+    }
+
+    fn body(&mut self) -> Result<Progress, MultipartError> {
+        let len = match find(&self.buf, &self.delimiter) {
+            Some(0) => {
+                self.buf.advance(self.delimiter.len());
+                self.state = State::Boundary;
+                return Ok(Progress::Emit(Step::PartEnd));
+            }
+            Some(i) => i,
+            // the end of the buffer may be the start of the boundary
+            None => self.buf.len().saturating_sub(self.delimiter.len() - 1),
+        };
+        if len == 0 {
+            return Ok(Progress::NeedData);
+        }
+
+        self.part_bytes += len as u64;
+        if self.part_bytes > self.part_limit {
+            return Err(MultipartError::TooLarge(format!(
+                "part is larger than {} bytes",
+                self.part_limit
+            )));
+        }
+        Ok(Progress::Emit(Step::Data(self.buf.split_to(len).to_vec())))
+    }
+}
+
+struct MultipartResource {
+    body: Rc<dyn Resource>,
+    parser: AsyncRefCell<MultipartParser>,
+    cancel: CancelHandle,
+}
+
+impl Resource for MultipartResource {
+    fn name(&self) -> Cow<str> {
+        "multipartBody".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+async fn next_step(
+    resource: &Rc<MultipartResource>,
+    parser: &mut MultipartParser,
+) -> Result<Step, AnyError> {
+    loop {
+        if let Some(step) = parser.step()? {
+            return Ok(step);
+        }
+        let cancel = RcRef::map(resource, |r| &r.cancel);
+        let chunk = resource
+            .body
+            .clone()
+            .read(READ_SIZE)
+            .or_cancel(cancel)
+            .await??;
+        parser.feed(&chunk);
+    }
+}
+
+// Parses the body read from `body_rid` (a request body) as it's streamed
+#[op2]
+#[smi]
+pub fn op_multipart_start(
+    state: &mut OpState,
+    #[smi] body_rid: ResourceId,
+    #[string] content_type: &str,
+    #[serde] limits: MultipartLimits,
+) -> Result<ResourceId, AnyError> {
+    let boundary = parse_boundary(content_type).ok_or_else(|| {
+        custom_error(
+            "TypeError",
+            "content type isn't multipart/form-data with a boundary",
+        )
+    })?;
+    let body = state.resource_table.get_any(body_rid)?;
+    Ok(state.resource_table.add(MultipartResource {
+        body,
+        parser: AsyncRefCell::new(MultipartParser::new(&boundary, limits)),
+        cancel: CancelHandle::default(),
+    }))
+}
+
+// Headers of the next part, skipping what's left of the current one, or null
+// once the body ended
+#[op2(async)]
+#[serde]
+pub async fn op_multipart_next_part(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<PartHeaders>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<MultipartResource>(rid)?;
+    let mut parser = RcRef::map(&resource, |r| &r.parser).borrow_mut().await;
+    loop {
+        match next_step(&resource, &mut parser).await? {
+            Step::Part(headers) => return Ok(Some(headers)),
+            Step::End => return Ok(None),
+            Step::Data(_) | Step::PartEnd => {}
+        }
+    }
+}
+
+// Next chunk of the `part`th part, or null once it ended
+#[op2(async)]
+#[serde]
+pub async fn op_multipart_read(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[smi] part: u32,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<MultipartResource>(rid)?;
+    let mut parser = RcRef::map(&resource, |r| &r.parser).borrow_mut().await;
+    if parser.current_part() != Some(part as usize) {
+        return Ok(None);
+    }
+    match next_step(&resource, &mut parser).await? {
+        Step::Data(chunk) => Ok(Some(chunk.into())),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+hello\r\n--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+line 1\r\nline 2\r\n--XyZ--\r\nepilogue";
+
+    // feeds the body in chunks of `size`, merging consecutive data
+    fn parse(
+        body: &[u8],
+        size: usize,
+        limits: MultipartLimits,
+    ) -> Result<Vec<Step>, MultipartError> {
+        let mut parser = MultipartParser::new("XyZ", limits);
+        let mut chunks = body.chunks(size);
+        let mut steps: Vec<Step> = vec![];
+        loop {
+            match parser.step()? {
+                None => parser.feed(chunks.next().unwrap_or_default()),
+                Some(Step::End) => {
+                    steps.push(Step::End);
+                    return Ok(steps);
+                }
+                Some(Step::Data(data)) => match steps.last_mut() {
+                    Some(Step::Data(prev)) => prev.extend(data),
+                    _ => steps.push(Step::Data(data)),
+                },
+                Some(step) => steps.push(step),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_multipart() {
+        let expected = vec![
+            Step::Part(PartHeaders {
+                name: "title".to_string(),
+                filename: None,
+                content_type: None,
+            }),
+            Step::Data(b"hello".to_vec()),
+            Step::PartEnd,
+            Step::Part(PartHeaders {
+                name: "file".to_string(),
+                filename: Some("a \"b\".txt".to_string()),
+                content_type: Some("text/plain".to_string()),
+            }),
+            Step::Data(b"line 1\r\nline 2".to_vec()),
+            Step::PartEnd,
+            Step::End,
+        ];
+        for size in [1, 3, 7, BODY.len()] {
+            let steps = parse(BODY, size, MultipartLimits::default()).unwrap();
+            assert_eq!(steps, expected, "chunks of {}", size);
+        }
+    }
+
+    #[test]
+    fn test_multipart_limits() {
+        let limits = MultipartLimits {
+            max_file_bytes: 8,
+            ..Default::default()
+        };
+        assert!(matches!(
+            parse(BODY, 4, limits),
+            Err(MultipartError::TooLarge(_))
+        ));
+
+        let limits = MultipartLimits {
+            max_parts: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            parse(BODY, 4, limits),
+            Err(MultipartError::TooLarge(_))
+        ));
+
+        let truncated = &BODY[..BODY.len() - 20];
+        assert!(matches!(
+            parse(truncated, 4, MultipartLimits::default()),
+            Err(MultipartError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_boundary() {
+        assert_eq!(
+            parse_boundary("multipart/form-data; boundary=\"abc\""),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            parse_boundary("Multipart/Form-Data;charset=utf-8;BOUNDARY=x-1"),
+            Some("x-1".to_string())
+        );
+        assert_eq!(parse_boundary("multipart/form-data"), None);
+        assert_eq!(parse_boundary("application/json; boundary=abc"), None);
+    }
+}
@@ -13,6 +13,10 @@ use deno_core::Resource;
 use deno_core::ResourceId;
 use deno_net::io::UnixStreamResource;
 use deno_net::ops::IpAddr;
+use sb_worker_context::request_metadata::{
+    read_metadata_frame, PendingRequestMetadata, PendingRequestRoutes, RequestHeaders,
+    WorkerConnection,
+};
 use std::cell::RefCell;
 use std::rc::Rc;
 use tokio::io::AsyncReadExt;
@@ -96,7 +100,7 @@ pub async fn op_net_accept(
     // we need to add it back later after processing a message.
     let rx = {
         let mut op_state = state.borrow_mut();
-        op_state.try_take::<mpsc::UnboundedReceiver<tokio::net::UnixStream>>()
+        op_state.try_take::<mpsc::UnboundedReceiver<WorkerConnection>>()
     };
 
     if rx.is_none() {
@@ -104,19 +108,43 @@ pub async fn op_net_accept(
     }
     let mut rx = rx.unwrap();
 
-    let unix_stream = rx.recv().await;
-    if unix_stream.is_none() {
+    let Some(WorkerConnection {
+        stream: mut unix_stream,
+        headers: maybe_headers,
+        route: maybe_route,
+    }) = rx.recv().await
+    else {
         return Err(bad_resource("unix stream channel is closed"));
-    }
-    let unix_stream = unix_stream.unwrap();
+    };
+    // written by the sender before the stream was sent, so it's already buffered.
+    // A malformed frame only drops the metadata, the request fails to parse anyway.
+    let maybe_metadata = read_metadata_frame(&mut unix_stream).await.ok().flatten();
 
     let resource = UnixStreamResource::new(unix_stream.into_split());
 
     // since the op state was dropped before,
     // reborrow and add the channel receiver again
     let mut op_state = state.borrow_mut();
-    op_state.put::<mpsc::UnboundedReceiver<tokio::net::UnixStream>>(rx);
+    op_state.put::<mpsc::UnboundedReceiver<WorkerConnection>>(rx);
     let rid = op_state.resource_table.add(resource);
+    if let Some(metadata) = maybe_metadata {
+        op_state
+            .borrow_mut::<PendingRequestMetadata>()
+            .0
+            .insert(rid, metadata);
+    }
+    if let Some(route) = maybe_route {
+        op_state
+            .borrow_mut::<PendingRequestRoutes>()
+            .0
+            .insert(rid, route);
+    }
+    if let Some(headers) = maybe_headers {
+        op_state
+            .borrow_mut::<RequestHeaders>()
+            .0
+            .insert(rid, headers);
+    }
     Ok((
         rid,
         IpAddr {
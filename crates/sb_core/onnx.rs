@@ -0,0 +1,58 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{OpState, ToJsBuffer};
+use sb_worker_context::onnx::{TensorInput, TensorType, WorkerModels};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunOutput {
+    name: String,
+    #[serde(rename = "type")]
+    tensor_type: TensorType,
+    shape: Vec<usize>,
+    data: ToJsBuffer,
+}
+
+#[op2]
+#[serde]
+pub fn op_onnx_models(state: &mut OpState) -> Vec<String> {
+    match state.try_borrow::<WorkerModels>() {
+        Some(models) => models.runner.models(&models.service),
+        None => vec![],
+    }
+}
+
+// Inputs are read from the typed arrays they were passed as, without copying
+#[op2(async)]
+#[serde]
+pub async fn op_onnx_run(
+    state: Rc<RefCell<OpState>>,
+    #[string] model: String,
+    #[serde] inputs: Vec<TensorInput>,
+) -> Result<Vec<RunOutput>, AnyError> {
+    let run = {
+        let state = state.borrow();
+        let Some(models) = state.try_borrow::<WorkerModels>() else {
+            return Err(custom_error(
+                "NotSupported",
+                "ONNX models aren't configured for the runtime",
+            ));
+        };
+        models.runner.run(&models.service, &model, inputs)
+    };
+    let outputs = run
+        .await
+        .map_err(|err| custom_error("OnnxError", format!("{:#}", err)))?;
+    Ok(outputs
+        .into_iter()
+        .map(|output| RunOutput {
+            name: output.name,
+            tensor_type: output.tensor_type,
+            shape: output.shape,
+            data: output.data.into(),
+        })
+        .collect())
+}
@@ -0,0 +1,105 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::Value;
+use deno_core::url::Url;
+use deno_core::OpState;
+use sb_worker_context::keys::WorkerKeys;
+use sb_worker_context::outbound_webhooks::{DeliveryStatus, WebhookDelivery, WorkerWebhooks};
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SendWebhookOptions {
+    url: String,
+    #[serde(default)]
+    event_type: Option<String>,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default)]
+    signing_key: Option<String>,
+}
+
+fn worker_webhooks(state: &OpState) -> Result<&WorkerWebhooks, AnyError> {
+    state.try_borrow::<WorkerWebhooks>().ok_or_else(|| {
+        custom_error(
+            "NotSupported",
+            "outbound webhooks are not enabled (start the runtime with a webhooks database)",
+        )
+    })
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[op2]
+#[string]
+pub fn op_webhook_send(
+    state: &mut OpState,
+    #[serde] opts: SendWebhookOptions,
+) -> Result<String, AnyError> {
+    let webhooks = worker_webhooks(state)?;
+    let url = Url::parse(&opts.url)
+        .map_err(|err| custom_error("TypeError", format!("invalid webhook url: {}", err)))?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(custom_error(
+            "TypeError",
+            format!("webhook url must be http(s) (got {})", url.scheme()),
+        ));
+    }
+    // fail now rather than dead-lettering every delivery
+    if let Some(name) = &opts.signing_key {
+        let known = state
+            .try_borrow::<WorkerKeys>()
+            .and_then(|keys| keys.store.get(name, &keys.service))
+            .is_some();
+        if !known {
+            return Err(custom_error(
+                "NotFound",
+                format!("no signing key named {}", name),
+            ));
+        }
+    }
+
+    let delivery = WebhookDelivery {
+        id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+        service: webhooks.service.clone(),
+        url: url.to_string(),
+        event_type: opts.event_type,
+        payload: opts.payload,
+        signing_key: opts.signing_key,
+        status: DeliveryStatus::Pending,
+        attempt: 0,
+        next_attempt_ms: now_ms(),
+        last_error: None,
+    };
+    webhooks.store.insert(&delivery)?;
+    Ok(delivery.id)
+}
+
+#[op2]
+#[serde]
+pub fn op_webhook_status(
+    state: &mut OpState,
+    #[string] id: &str,
+) -> Result<Option<WebhookDelivery>, AnyError> {
+    let webhooks = worker_webhooks(state)?;
+    webhooks.store.get(&webhooks.service, id)
+}
+
+#[op2]
+#[serde]
+pub fn op_webhook_dead_letters(state: &mut OpState) -> Result<Vec<WebhookDelivery>, AnyError> {
+    let webhooks = worker_webhooks(state)?;
+    webhooks.store.dead_letters(&webhooks.service)
+}
+
+#[op2(fast)]
+pub fn op_webhook_redeliver(state: &mut OpState, #[string] id: &str) -> Result<bool, AnyError> {
+    let webhooks = worker_webhooks(state)?;
+    webhooks.store.redeliver(&webhooks.service, id, now_ms())
+}
@@ -0,0 +1,189 @@
+use deno_core::op2;
+use deno_core::serde_json;
+use hyper::{Body, Response};
+use serde::Serialize;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+// base of the `type` URIs identifying each error code
+const PROBLEM_TYPE_BASE: &str = "urn:supabase:edge-runtime:error";
+
+// Errors generated by the runtime itself (as opposed to user code). The codes are
+// exposed to JS through `op_runtime_error_codes`, so they must stay stable.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RuntimeErrorCode {
+    BootFailure,
+    MemoryLimit,
+    CpuTimeLimit,
+    WallClockLimit,
+    EventLoopHang,
+    DeadlineExceeded,
+    Overloaded,
+    ServicePaused,
+    IdempotencyConflict,
+    IdempotencyKeyReused,
+    RouteNotFound,
+    WorkerUnavailable,
+    InternalError,
+}
+
+impl RuntimeErrorCode {
+    pub const ALL: [RuntimeErrorCode; 13] = [
+        RuntimeErrorCode::BootFailure,
+        RuntimeErrorCode::MemoryLimit,
+        RuntimeErrorCode::CpuTimeLimit,
+        RuntimeErrorCode::WallClockLimit,
+        RuntimeErrorCode::EventLoopHang,
+        RuntimeErrorCode::DeadlineExceeded,
+        RuntimeErrorCode::Overloaded,
+        RuntimeErrorCode::ServicePaused,
+        RuntimeErrorCode::IdempotencyConflict,
+        RuntimeErrorCode::IdempotencyKeyReused,
+        RuntimeErrorCode::RouteNotFound,
+        RuntimeErrorCode::WorkerUnavailable,
+        RuntimeErrorCode::InternalError,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuntimeErrorCode::BootFailure => "BOOT_FAILURE",
+            RuntimeErrorCode::MemoryLimit => "MEMORY_LIMIT",
+            RuntimeErrorCode::CpuTimeLimit => "CPU_TIME_LIMIT",
+            RuntimeErrorCode::WallClockLimit => "WALL_CLOCK_LIMIT",
+            RuntimeErrorCode::EventLoopHang => "EVENT_LOOP_HANG",
+            RuntimeErrorCode::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            RuntimeErrorCode::Overloaded => "OVERLOADED",
+            RuntimeErrorCode::ServicePaused => "SERVICE_PAUSED",
+            RuntimeErrorCode::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            RuntimeErrorCode::IdempotencyKeyReused => "IDEMPOTENCY_KEY_REUSED",
+            RuntimeErrorCode::RouteNotFound => "ROUTE_NOT_FOUND",
+            RuntimeErrorCode::WorkerUnavailable => "WORKER_UNAVAILABLE",
+            RuntimeErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            RuntimeErrorCode::RouteNotFound => 404,
+            RuntimeErrorCode::IdempotencyConflict => 409,
+            RuntimeErrorCode::IdempotencyKeyReused => 422,
+            RuntimeErrorCode::InternalError => 500,
+            RuntimeErrorCode::DeadlineExceeded => 504,
+            RuntimeErrorCode::BootFailure
+            | RuntimeErrorCode::MemoryLimit
+            | RuntimeErrorCode::CpuTimeLimit
+            | RuntimeErrorCode::WallClockLimit
+            | RuntimeErrorCode::EventLoopHang
+            | RuntimeErrorCode::Overloaded
+            | RuntimeErrorCode::ServicePaused
+            | RuntimeErrorCode::WorkerUnavailable => 503,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            RuntimeErrorCode::BootFailure => "Worker failed to boot",
+            RuntimeErrorCode::MemoryLimit => "Worker exceeded its memory limit",
+            RuntimeErrorCode::CpuTimeLimit => "Worker exceeded its CPU time limit",
+            RuntimeErrorCode::WallClockLimit => "Worker exceeded its wall clock limit",
+            RuntimeErrorCode::EventLoopHang => "Worker event loop stopped making progress",
+            RuntimeErrorCode::DeadlineExceeded => "Request deadline exceeded",
+            RuntimeErrorCode::Overloaded => "Service is overloaded",
+            RuntimeErrorCode::ServicePaused => "Service is paused",
+            RuntimeErrorCode::IdempotencyConflict => {
+                "A request with the same idempotency key is in progress"
+            }
+            RuntimeErrorCode::IdempotencyKeyReused => {
+                "The idempotency key was sent with another request body"
+            }
+            RuntimeErrorCode::RouteNotFound => "No route matches the request",
+            RuntimeErrorCode::WorkerUnavailable => "Worker is not available",
+            RuntimeErrorCode::InternalError => "Internal server error",
+        }
+    }
+
+    // eg: urn:supabase:edge-runtime:error:memory-limit
+    pub fn type_uri(&self) -> String {
+        format!(
+            "{}:{}",
+            PROBLEM_TYPE_BASE,
+            self.as_str().to_lowercase().replace('_', "-")
+        )
+    }
+}
+
+// RFC 7807 problem details
+#[derive(Serialize, Debug, Clone)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub code: RuntimeErrorCode,
+}
+
+impl Problem {
+    pub fn new(code: RuntimeErrorCode, detail: Option<String>) -> Self {
+        Self {
+            type_uri: code.type_uri(),
+            title: code.title(),
+            status: code.status(),
+            detail,
+            code,
+        }
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let body = serde_json::to_vec(&self).unwrap_or_default();
+        Response::builder()
+            .status(self.status)
+            .header(hyper::header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+
+pub fn problem_response(code: RuntimeErrorCode, detail: Option<String>) -> Response<Body> {
+    Problem::new(code, detail).into_response()
+}
+
+#[op2]
+#[serde]
+pub fn op_runtime_error_codes() -> Vec<Problem> {
+    RuntimeErrorCode::ALL
+        .iter()
+        .map(|code| Problem::new(*code, None))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{problem_response, RuntimeErrorCode, PROBLEM_CONTENT_TYPE};
+    use deno_core::serde_json::{self, Value};
+
+    #[tokio::test]
+    async fn test_problem_response() {
+        let res = problem_response(
+            RuntimeErrorCode::DeadlineExceeded,
+            Some("deadline passed".to_string()),
+        );
+        assert_eq!(res.status(), 504);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            PROBLEM_CONTENT_TYPE
+        );
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem["type"],
+            "urn:supabase:edge-runtime:error:deadline-exceeded"
+        );
+        assert_eq!(problem["code"], "DEADLINE_EXCEEDED");
+        assert_eq!(problem["status"], 504);
+        assert_eq!(problem["detail"], "deadline passed");
+    }
+}
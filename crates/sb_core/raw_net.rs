@@ -0,0 +1,280 @@
+use crate::net::TcpStreamResource;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::{
+    AsyncRefCell, CancelHandle, CancelTryFuture, JsBuffer, OpState, RcRef, Resource, ResourceId,
+};
+use deno_net::ops::IpAddr;
+use sb_worker_context::listen::{ListenPermission, ListenPermissions, ListenTransport};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use tokio::net::{TcpListener, UdpSocket};
+
+// Raw TCP and UDP sockets of `EdgeRuntime.listen`. Unlike `Deno.listen` (which
+// yields the HTTP connections routed to the worker), these bind real sockets,
+// so they're only available for the addresses granted to the worker. A UDP
+// socket only sends to the peers it received from, it can't be used to reach
+// arbitrary hosts.
+
+// peers a UDP socket can reply to, the oldest are forgotten first
+const MAX_UDP_PEERS: usize = 1024;
+
+// Sockets bound on the worker thread before it was sandboxed without network
+// access, taken when the worker listens on them
+enum PreboundSocket {
+    Tcp(std::net::TcpListener),
+    Udp(std::net::UdpSocket),
+}
+
+thread_local! {
+    static PREBOUND: RefCell<Vec<(ListenPermission, PreboundSocket)>> = RefCell::new(vec![]);
+}
+
+// Binds the sockets granted to the worker of the calling thread
+pub fn prebind_sockets(permissions: &[ListenPermission]) -> Result<(), AnyError> {
+    for permission in permissions {
+        let bind_addr = (permission.hostname.as_str(), permission.port);
+        let socket = match permission.transport {
+            ListenTransport::Tcp => PreboundSocket::Tcp(std::net::TcpListener::bind(bind_addr)?),
+            ListenTransport::Udp => PreboundSocket::Udp(std::net::UdpSocket::bind(bind_addr)?),
+        };
+        PREBOUND.with(|prebound| prebound.borrow_mut().push((permission.clone(), socket)));
+    }
+    Ok(())
+}
+
+fn take_prebound(addr: &ListenPermission) -> Option<PreboundSocket> {
+    PREBOUND.with(|prebound| {
+        let mut prebound = prebound.borrow_mut();
+        let index = prebound.iter().position(|(granted, _)| granted == addr)?;
+        Some(prebound.remove(index).1)
+    })
+}
+
+struct RawTcpListenerResource {
+    listener: AsyncRefCell<TcpListener>,
+    cancel: CancelHandle,
+}
+
+impl Resource for RawTcpListenerResource {
+    fn name(&self) -> Cow<str> {
+        "rawTcpListener".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+struct RawUdpSocketResource {
+    socket: UdpSocket,
+    peers: RefCell<VecDeque<SocketAddr>>,
+    cancel: CancelHandle,
+}
+
+impl RawUdpSocketResource {
+    fn add_peer(&self, addr: SocketAddr) {
+        let mut peers = self.peers.borrow_mut();
+        if peers.contains(&addr) {
+            return;
+        }
+        if peers.len() == MAX_UDP_PEERS {
+            peers.pop_front();
+        }
+        peers.push_back(addr);
+    }
+}
+
+impl Resource for RawUdpSocketResource {
+    fn name(&self) -> Cow<str> {
+        "rawUdpSocket".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+fn ip_addr(addr: SocketAddr) -> IpAddr {
+    IpAddr {
+        hostname: addr.ip().to_string(),
+        port: addr.port(),
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_raw_listen(
+    state: &mut OpState,
+    #[serde] addr: ListenPermission,
+) -> Result<(ResourceId, IpAddr), AnyError> {
+    let Some(permissions) = state.try_borrow::<ListenPermissions>() else {
+        return Err(custom_error(
+            "PermissionDenied",
+            "listening on sockets isn't permitted for the worker",
+        ));
+    };
+    permissions
+        .check(addr.transport, &addr.hostname, addr.port)
+        .map_err(|err| custom_error("PermissionDenied", err.to_string()))?;
+
+    let bind_addr = (addr.hostname.as_str(), addr.port);
+    let prebound = take_prebound(&addr);
+    match addr.transport {
+        ListenTransport::Tcp => {
+            let listener = match prebound {
+                Some(PreboundSocket::Tcp(listener)) => listener,
+                _ => std::net::TcpListener::bind(bind_addr)?,
+            };
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+            let local_addr = listener.local_addr()?;
+            let rid = state.resource_table.add(RawTcpListenerResource {
+                listener: AsyncRefCell::new(listener),
+                cancel: Default::default(),
+            });
+            Ok((rid, ip_addr(local_addr)))
+        }
+        ListenTransport::Udp => {
+            let socket = match prebound {
+                Some(PreboundSocket::Udp(socket)) => socket,
+                _ => std::net::UdpSocket::bind(bind_addr)?,
+            };
+            socket.set_nonblocking(true)?;
+            let socket = UdpSocket::from_std(socket)?;
+            let local_addr = socket.local_addr()?;
+            let rid = state.resource_table.add(RawUdpSocketResource {
+                socket,
+                peers: Default::default(),
+                cancel: Default::default(),
+            });
+            Ok((rid, ip_addr(local_addr)))
+        }
+    }
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_raw_accept(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<(ResourceId, IpAddr, IpAddr), AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<RawTcpListenerResource>(rid)?;
+    let listener = RcRef::map(&resource, |r| &r.listener)
+        .try_borrow_mut()
+        .ok_or_else(|| custom_error("Busy", "Another accept task is ongoing"))?;
+    let cancel = RcRef::map(&resource, |r| &r.cancel);
+    let (stream, remote_addr) = listener.accept().try_or_cancel(cancel).await?;
+    let local_addr = stream.local_addr()?;
+
+    let rid = state
+        .borrow_mut()
+        .resource_table
+        .add(TcpStreamResource::from(stream));
+    Ok((rid, ip_addr(local_addr), ip_addr(remote_addr)))
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_raw_recv(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[buffer] mut buf: JsBuffer,
+) -> Result<(usize, IpAddr), AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<RawUdpSocketResource>(rid)?;
+    let cancel = RcRef::map(&resource, |r| &r.cancel);
+    let (nread, remote_addr) = resource
+        .socket
+        .recv_from(&mut buf)
+        .try_or_cancel(cancel)
+        .await?;
+    resource.add_peer(remote_addr);
+    Ok((nread, ip_addr(remote_addr)))
+}
+
+#[op2(async)]
+#[number]
+pub async fn op_raw_send(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[serde] addr: IpAddr,
+    #[buffer] buf: JsBuffer,
+) -> Result<usize, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<RawUdpSocketResource>(rid)?;
+    let peer = addr
+        .hostname
+        .parse::<std::net::IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, addr.port))
+        .filter(|peer| resource.peers.borrow().contains(peer));
+    let Some(peer) = peer else {
+        return Err(custom_error(
+            "PermissionDenied",
+            format!(
+                "{}:{} hasn't sent to the socket, it can only reply to its peers",
+                addr.hostname, addr.port
+            ),
+        ));
+    };
+    let nwritten = resource.socket.send_to(&buf, peer).await?;
+    Ok(nwritten)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prebind_sockets, take_prebound, PreboundSocket, RawUdpSocketResource};
+    use sb_worker_context::listen::{ListenPermission, ListenTransport};
+
+    #[test]
+    fn test_prebound_sockets() {
+        let granted = ListenPermission {
+            transport: ListenTransport::Udp,
+            hostname: "127.0.0.1".to_string(),
+            port: 0,
+        };
+        prebind_sockets(&[granted.clone()]).unwrap();
+
+        let tcp = ListenPermission {
+            transport: ListenTransport::Tcp,
+            ..granted.clone()
+        };
+        assert!(take_prebound(&tcp).is_none());
+        assert!(matches!(
+            take_prebound(&granted),
+            Some(PreboundSocket::Udp(_))
+        ));
+        // taken once
+        assert!(take_prebound(&granted).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_udp_peers_are_bounded() {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resource = RawUdpSocketResource {
+            socket,
+            peers: Default::default(),
+            cancel: Default::default(),
+        };
+        for port in 1..=(super::MAX_UDP_PEERS as u16 + 1) {
+            resource.add_peer(([127, 0, 0, 1], port).into());
+        }
+        resource.add_peer(([127, 0, 0, 1], 2).into());
+
+        let peers = resource.peers.borrow();
+        assert_eq!(peers.len(), super::MAX_UDP_PEERS);
+        // the oldest peer was forgotten
+        assert!(!peers.contains(&([127, 0, 0, 1], 1).into()));
+    }
+}
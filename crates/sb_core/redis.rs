@@ -0,0 +1,86 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::Value;
+use deno_core::{AsyncRefCell, CancelFuture, CancelHandle, OpState, RcRef, Resource, ResourceId};
+use sb_worker_context::redis::{RedisCommand, SharedRedisPool, WorkerRedis};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc;
+
+fn worker_redis(state: &OpState) -> Result<(SharedRedisPool, String), AnyError> {
+    state
+        .try_borrow::<WorkerRedis>()
+        .map(|redis| (redis.pool.clone(), redis.service.clone()))
+        .ok_or_else(|| custom_error("NotSupported", "redis isn't configured for the runtime"))
+}
+
+fn redis_error(err: anyhow::Error) -> AnyError {
+    custom_error("RedisError", format!("{:#}", err))
+}
+
+struct RedisSubscriptionResource {
+    rx: AsyncRefCell<mpsc::UnboundedReceiver<String>>,
+    cancel: CancelHandle,
+}
+
+impl Resource for RedisSubscriptionResource {
+    fn name(&self) -> Cow<str> {
+        "redisSubscription".into()
+    }
+
+    // unsubscribes
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_redis_query(
+    state: Rc<RefCell<OpState>>,
+    #[serde] command: RedisCommand,
+) -> Result<Value, AnyError> {
+    let (pool, service) = worker_redis(&state.borrow())?;
+    pool.query(&service, command).await.map_err(redis_error)
+}
+
+#[op2(async)]
+#[smi]
+pub async fn op_redis_subscribe(
+    state: Rc<RefCell<OpState>>,
+    #[string] channel: String,
+) -> Result<ResourceId, AnyError> {
+    let (pool, service) = worker_redis(&state.borrow())?;
+    let rx = pool
+        .subscribe(&service, channel)
+        .await
+        .map_err(redis_error)?;
+    Ok(state
+        .borrow_mut()
+        .resource_table
+        .add(RedisSubscriptionResource {
+            rx: AsyncRefCell::new(rx),
+            cancel: CancelHandle::default(),
+        }))
+}
+
+// Next message of the subscription, or null once it ended
+#[op2(async)]
+#[string]
+pub async fn op_redis_next_message(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<String>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<RedisSubscriptionResource>(rid)?;
+    let mut rx = RcRef::map(&resource, |r| &r.rx).borrow_mut().await;
+    let cancel = RcRef::map(&resource, |r| &r.cancel);
+    match rx.recv().or_cancel(cancel).await {
+        Ok(message) => Ok(message),
+        // unsubscribed
+        Err(_) => Ok(None),
+    }
+}
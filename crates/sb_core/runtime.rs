@@ -1,4 +1,33 @@
+use crate::ai::{op_ai_read, op_ai_request, op_ai_usage, op_embed, op_embedding_models};
+use crate::alarms::{op_alarm_cancel, op_alarm_list, op_alarm_schedule};
+use crate::compression::{op_codec_finish, op_codec_new, op_codec_write};
+use crate::coverage::op_test_coverage_take;
+use crate::flags::{op_feature_flag, op_feature_flags, op_feature_flags_changed};
+use crate::ids::{op_ids_current, op_ids_next, op_ids_ulid, op_ids_uuidv7, IdGenerator};
+use crate::images::{op_image_info, op_image_limits, op_image_transform};
+use crate::json_schema::{
+    op_json_schema_compile, op_json_schema_validate, op_json_schema_validate_json, JsonSchemas,
+};
+use crate::keys::{op_key_names, op_key_sign, op_key_verify};
+use crate::mail::op_mail_send;
+use crate::multipart::{op_multipart_next_part, op_multipart_read, op_multipart_start};
+use crate::onnx::{op_onnx_models, op_onnx_run};
+use crate::outbound_webhooks::{
+    op_webhook_dead_letters, op_webhook_redeliver, op_webhook_send, op_webhook_status,
+};
 use crate::permissions::Permissions;
+use crate::problem::op_runtime_error_codes;
+use crate::raw_net::{op_raw_accept, op_raw_listen, op_raw_recv, op_raw_send};
+use crate::redis::{op_redis_next_message, op_redis_query, op_redis_subscribe};
+use crate::tabular::{
+    op_tabular_parse, op_tabular_parser_new, op_tabular_stringify, op_tabular_writer_new,
+};
+use crate::templates::op_render_template;
+use crate::test_clock::{
+    op_test_clock_freeze, op_test_clock_frozen, op_test_clock_now, op_test_clock_restore,
+    op_test_clock_set,
+};
+use crate::webhooks::op_webhook_verify;
 use anyhow::Context;
 use deno_core::error::AnyError;
 use deno_core::op2;
@@ -24,11 +53,72 @@ fn op_main_module(state: &mut OpState) -> Result<String, AnyError> {
 }
 
 deno_core::extension!(sb_core_runtime,
-    ops = [op_main_module],
+    ops = [
+        op_main_module,
+        op_runtime_error_codes,
+        op_feature_flags,
+        op_feature_flag,
+        op_feature_flags_changed,
+        op_alarm_schedule,
+        op_alarm_cancel,
+        op_alarm_list,
+        op_key_names,
+        op_key_sign,
+        op_key_verify,
+        op_webhook_verify,
+        op_webhook_send,
+        op_webhook_status,
+        op_webhook_dead_letters,
+        op_webhook_redeliver,
+        op_image_limits,
+        op_image_info,
+        op_image_transform,
+        op_render_template,
+        op_raw_listen,
+        op_raw_accept,
+        op_raw_recv,
+        op_raw_send,
+        op_mail_send,
+        op_redis_query,
+        op_redis_subscribe,
+        op_redis_next_message,
+        op_ai_request,
+        op_ai_read,
+        op_ai_usage,
+        op_embedding_models,
+        op_embed,
+        op_multipart_start,
+        op_multipart_next_part,
+        op_multipart_read,
+        op_codec_new,
+        op_codec_write,
+        op_codec_finish,
+        op_json_schema_compile,
+        op_json_schema_validate,
+        op_json_schema_validate_json,
+        op_tabular_parser_new,
+        op_tabular_parse,
+        op_tabular_writer_new,
+        op_tabular_stringify,
+        op_ids_ulid,
+        op_ids_uuidv7,
+        op_ids_next,
+        op_ids_current,
+        op_test_clock_now,
+        op_test_clock_frozen,
+        op_test_clock_freeze,
+        op_test_clock_set,
+        op_test_clock_restore,
+        op_test_coverage_take,
+        op_onnx_models,
+        op_onnx_run
+    ],
     options = {
         main_module: Option<ModuleSpecifier>
     },
     state = |state, options| {
+        state.put::<JsonSchemas>(JsonSchemas::default());
+        state.put::<IdGenerator>(IdGenerator::default());
         if let Some(module_init) = options.main_module {
             state.put::<ModuleSpecifier>(module_init);
         }
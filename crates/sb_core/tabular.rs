@@ -0,0 +1,513 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::{self, Map, Value};
+use deno_core::{JsBuffer, OpState, Resource, ResourceId};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TabularFormat {
+    Csv,
+    Ndjson,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_max_record_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_max_fields() -> usize {
+    10_000
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseOptions {
+    // csv: field delimiter, a single ASCII character
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    // csv: the first record names the fields, records are then objects
+    #[serde(default)]
+    pub header: bool,
+    // size of a record (a line for NDJSON), so a file without line breaks can't
+    // be buffered whole
+    #[serde(default = "default_max_record_bytes")]
+    pub max_record_bytes: usize,
+    // csv: fields of a record
+    #[serde(default = "default_max_fields")]
+    pub max_fields: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: default_delimiter(),
+            header: false,
+            max_record_bytes: default_max_record_bytes(),
+            max_fields: default_max_fields(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StringifyOptions {
+    // csv: field delimiter, a single ASCII character
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    // csv: fields written from object records, and the header row. Defaults to
+    // the keys of the first record when it's an object.
+    pub columns: Option<Vec<String>>,
+}
+
+impl Default for StringifyOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: default_delimiter(),
+            columns: None,
+        }
+    }
+}
+
+fn delimiter_byte(delimiter: char) -> Result<u8, AnyError> {
+    if !delimiter.is_ascii() || delimiter == '"' || delimiter == '\n' || delimiter == '\r' {
+        return Err(custom_error(
+            "TypeError",
+            "delimiter must be an ASCII character other than quotes and line breaks",
+        ));
+    }
+    Ok(delimiter as u8)
+}
+
+fn record_too_large(max: usize) -> AnyError {
+    custom_error("RangeError", format!("record is larger than {} bytes", max))
+}
+
+struct CsvParser {
+    reader: csv_core::Reader,
+    opts: ParseOptions,
+    // fields of the record being read, and where each one ends
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    header: Option<Vec<String>>,
+    records: usize,
+}
+
+impl CsvParser {
+    fn new(opts: ParseOptions) -> Result<Self, AnyError> {
+        let reader = csv_core::ReaderBuilder::new()
+            .delimiter(delimiter_byte(opts.delimiter)?)
+            .build();
+        Ok(Self {
+            reader,
+            output: vec![0; opts.max_record_bytes.min(1024)],
+            output_len: 0,
+            ends: vec![0; opts.max_fields.min(16)],
+            ends_len: 0,
+            header: None,
+            records: 0,
+            opts,
+        })
+    }
+
+    // Parses `input`, an empty one ending the file
+    fn feed(&mut self, mut input: &[u8], records: &mut Vec<Value>) -> Result<(), AnyError> {
+        let eof = input.is_empty();
+        loop {
+            // csv_core reads an empty input as the end of the file
+            if input.is_empty() && !eof {
+                return Ok(());
+            }
+            let (res, read, written, ended) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.output_len += written;
+            self.ends_len += ended;
+            match res {
+                csv_core::ReadRecordResult::InputEmpty | csv_core::ReadRecordResult::End => {
+                    return Ok(())
+                }
+                csv_core::ReadRecordResult::OutputFull => {
+                    if self.output.len() >= self.opts.max_record_bytes {
+                        return Err(record_too_large(self.opts.max_record_bytes));
+                    }
+                    let len = (self.output.len() * 2).min(self.opts.max_record_bytes);
+                    self.output.resize(len, 0);
+                }
+                csv_core::ReadRecordResult::OutputEndsFull => {
+                    if self.ends.len() >= self.opts.max_fields {
+                        return Err(custom_error(
+                            "RangeError",
+                            format!("record has more than {} fields", self.opts.max_fields),
+                        ));
+                    }
+                    let len = (self.ends.len() * 2).min(self.opts.max_fields);
+                    self.ends.resize(len, 0);
+                }
+                csv_core::ReadRecordResult::Record => {
+                    let fields = self.fields();
+                    self.output_len = 0;
+                    self.ends_len = 0;
+                    if let Some(record) = self.record(fields)? {
+                        records.push(record);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fields(&self) -> Vec<String> {
+        let mut start = 0;
+        self.ends[..self.ends_len]
+            .iter()
+            .map(|end| {
+                let field = String::from_utf8_lossy(&self.output[start..*end]).into_owned();
+                start = *end;
+                field
+            })
+            .collect()
+    }
+
+    fn record(&mut self, fields: Vec<String>) -> Result<Option<Value>, AnyError> {
+        self.records += 1;
+        if !self.opts.header {
+            return Ok(Some(fields.into()));
+        }
+        let Some(header) = &self.header else {
+            self.header = Some(fields);
+            return Ok(None);
+        };
+        if header.len() != fields.len() {
+            return Err(custom_error(
+                "TypeError",
+                format!(
+                    "record {} has {} fields, the header has {}",
+                    self.records,
+                    fields.len(),
+                    header.len()
+                ),
+            ));
+        }
+        let record: Map<String, Value> = header
+            .iter()
+            .cloned()
+            .zip(fields.into_iter().map(Value::from))
+            .collect();
+        Ok(Some(record.into()))
+    }
+}
+
+struct NdjsonParser {
+    max_line_bytes: usize,
+    line: Vec<u8>,
+    lines: usize,
+}
+
+impl NdjsonParser {
+    fn parse_line(&mut self, records: &mut Vec<Value>) -> Result<(), AnyError> {
+        self.lines += 1;
+        let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+        if !line.iter().all(u8::is_ascii_whitespace) {
+            let record = serde_json::from_slice(line).map_err(|err| {
+                custom_error("SyntaxError", format!("line {}: {}", self.lines, err))
+            })?;
+            records.push(record);
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    // Parses `input`, an empty one ending the file
+    fn feed(&mut self, input: &[u8], records: &mut Vec<Value>) -> Result<(), AnyError> {
+        if input.is_empty() {
+            return self.parse_line(records);
+        }
+        let mut lines = input.split(|b| *b == b'\n').peekable();
+        while let Some(part) = lines.next() {
+            if self.line.len() + part.len() > self.max_line_bytes {
+                return Err(record_too_large(self.max_line_bytes));
+            }
+            self.line.extend_from_slice(part);
+            // the last part is the start of the next line
+            if lines.peek().is_some() {
+                self.parse_line(records)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Parser {
+    Csv(Box<CsvParser>),
+    Ndjson(NdjsonParser),
+}
+
+impl Parser {
+    fn new(format: TabularFormat, opts: ParseOptions) -> Result<Self, AnyError> {
+        Ok(match format {
+            TabularFormat::Csv => Parser::Csv(Box::new(CsvParser::new(opts)?)),
+            TabularFormat::Ndjson => Parser::Ndjson(NdjsonParser {
+                max_line_bytes: opts.max_record_bytes,
+                line: vec![],
+                lines: 0,
+            }),
+        })
+    }
+
+    fn feed(&mut self, input: &[u8]) -> Result<Vec<Value>, AnyError> {
+        let mut records = vec![];
+        match self {
+            Parser::Csv(parser) => parser.feed(input, &mut records)?,
+            Parser::Ndjson(parser) => parser.feed(input, &mut records)?,
+        }
+        Ok(records)
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+enum Writer {
+    Csv {
+        delimiter: u8,
+        columns: Option<Vec<String>>,
+    },
+    Ndjson,
+}
+
+impl Writer {
+    fn new(format: TabularFormat, opts: StringifyOptions) -> Result<Self, AnyError> {
+        Ok(match format {
+            TabularFormat::Csv => Writer::Csv {
+                delimiter: delimiter_byte(opts.delimiter)?,
+                columns: opts.columns,
+            },
+            TabularFormat::Ndjson => Writer::Ndjson,
+        })
+    }
+
+    fn write(&mut self, records: &[Value], header: bool) -> Result<String, AnyError> {
+        let (delimiter, columns) = match self {
+            Writer::Ndjson => {
+                let mut out = String::new();
+                for record in records {
+                    out.push_str(&serde_json::to_string(record)?);
+                    out.push('\n');
+                }
+                return Ok(out);
+            }
+            Writer::Csv { delimiter, columns } => (*delimiter, columns),
+        };
+
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_writer(vec![]);
+        if columns.is_none() {
+            if let Some(Value::Object(first)) = records.first() {
+                *columns = Some(first.keys().cloned().collect());
+            }
+        }
+        if header {
+            if let Some(columns) = columns.as_ref() {
+                writer.write_record(columns.iter())?;
+            }
+        }
+        for record in records {
+            match record {
+                Value::Array(fields) => writer.write_record(fields.iter().map(csv_field))?,
+                Value::Object(fields) => {
+                    let columns = columns.as_deref().unwrap_or_default();
+                    writer.write_record(
+                        columns
+                            .iter()
+                            .map(|column| fields.get(column).map(csv_field).unwrap_or_default()),
+                    )?
+                }
+                _ => {
+                    return Err(custom_error(
+                        "TypeError",
+                        "csv records must be arrays or objects",
+                    ))
+                }
+            }
+        }
+        let out = writer.into_inner().map_err(|err| err.into_error())?;
+        Ok(String::from_utf8(out)?)
+    }
+}
+
+struct ParserResource(RefCell<Parser>);
+
+impl Resource for ParserResource {
+    fn name(&self) -> Cow<str> {
+        "tabularParser".into()
+    }
+}
+
+struct WriterResource {
+    writer: RefCell<Writer>,
+    // the header row is written before the first records
+    started: RefCell<bool>,
+}
+
+impl Resource for WriterResource {
+    fn name(&self) -> Cow<str> {
+        "tabularWriter".into()
+    }
+}
+
+#[op2]
+#[smi]
+pub fn op_tabular_parser_new(
+    state: &mut OpState,
+    #[serde] format: TabularFormat,
+    #[serde] opts: ParseOptions,
+) -> Result<ResourceId, AnyError> {
+    let parser = Parser::new(format, opts)?;
+    Ok(state
+        .resource_table
+        .add(ParserResource(RefCell::new(parser))))
+}
+
+// Records completed by `chunk`. An empty chunk ends the file, returning the
+// last record and closing the parser.
+#[op2]
+#[serde]
+pub fn op_tabular_parse(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    #[buffer] chunk: JsBuffer,
+) -> Result<Vec<Value>, AnyError> {
+    let resource: Rc<ParserResource> = if chunk.is_empty() {
+        state.resource_table.take(rid)?
+    } else {
+        state.resource_table.get(rid)?
+    };
+    let records = resource.0.borrow_mut().feed(&chunk)?;
+    Ok(records)
+}
+
+#[op2]
+#[smi]
+pub fn op_tabular_writer_new(
+    state: &mut OpState,
+    #[serde] format: TabularFormat,
+    #[serde] opts: StringifyOptions,
+) -> Result<ResourceId, AnyError> {
+    let writer = Writer::new(format, opts)?;
+    Ok(state.resource_table.add(WriterResource {
+        writer: RefCell::new(writer),
+        started: RefCell::new(false),
+    }))
+}
+
+#[op2]
+#[string]
+pub fn op_tabular_stringify(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    #[serde] records: Vec<Value>,
+) -> Result<String, AnyError> {
+    let resource = state.resource_table.get::<WriterResource>(rid)?;
+    let header = !resource.started.replace(true);
+    let out = resource.writer.borrow_mut().write(&records, header)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParseOptions, Parser, StringifyOptions, TabularFormat, Writer};
+    use deno_core::serde_json::{json, Value};
+
+    fn parse(format: TabularFormat, opts: ParseOptions, input: &str) -> Vec<Value> {
+        let mut parser = Parser::new(format, opts).unwrap();
+        let mut records = vec![];
+        for chunk in input.as_bytes().chunks(3) {
+            records.extend(parser.feed(chunk).unwrap());
+        }
+        records.extend(parser.feed(&[]).unwrap());
+        records
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let input = "name,note\nada,\"says \"\"hi\"\"\"\nbob,\"two\nlines\"";
+        assert_eq!(
+            parse(TabularFormat::Csv, ParseOptions::default(), input),
+            vec![
+                json!(["name", "note"]),
+                json!(["ada", "says \"hi\""]),
+                json!(["bob", "two\nlines"]),
+            ]
+        );
+
+        let opts = ParseOptions {
+            header: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse(TabularFormat::Csv, opts, input),
+            vec![
+                json!({ "name": "ada", "note": "says \"hi\"" }),
+                json!({ "name": "bob", "note": "two\nlines" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let opts = ParseOptions {
+            max_record_bytes: 8,
+            ..Default::default()
+        };
+        let mut parser = Parser::new(TabularFormat::Csv, opts.clone()).unwrap();
+        assert!(parser.feed(b"a,b\n0123456789abcdef,c\n").is_err());
+
+        let mut parser = Parser::new(TabularFormat::Ndjson, opts).unwrap();
+        assert!(parser.feed(b"{\"a\":1}\n{\"long\":true}\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let input = "{\"a\":1}\r\n\n[2]\n\"three\"";
+        assert_eq!(
+            parse(TabularFormat::Ndjson, ParseOptions::default(), input),
+            vec![json!({ "a": 1 }), json!([2]), json!("three")]
+        );
+
+        let mut parser = Parser::new(TabularFormat::Ndjson, ParseOptions::default()).unwrap();
+        assert!(parser.feed(b"{}\n{oops}\n").is_err());
+    }
+
+    #[test]
+    fn test_stringify() {
+        let mut writer = Writer::new(TabularFormat::Csv, StringifyOptions::default()).unwrap();
+        let out = writer
+            .write(&[json!({ "a": "x,y", "b": 1 }), json!({ "b": null })], true)
+            .unwrap();
+        assert_eq!(out, "a,b\n\"x,y\",1\n,\n");
+        let out = writer.write(&[json!(["z", true])], false).unwrap();
+        assert_eq!(out, "z,true\n");
+
+        let mut writer = Writer::new(TabularFormat::Ndjson, StringifyOptions::default()).unwrap();
+        let out = writer.write(&[json!({ "a": 1 }), json!(2)], true).unwrap();
+        assert_eq!(out, "{\"a\":1}\n2\n");
+    }
+}
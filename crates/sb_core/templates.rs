@@ -0,0 +1,134 @@
+use anyhow::{Context, Error};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::serde_json::Value;
+use deno_core::OpState;
+use minijinja::Environment;
+use std::path::Path;
+
+// Directory of a service its templates are loaded from
+pub const TEMPLATES_DIR: &str = "templates";
+
+// Templates of a worker's service, compiled once at boot. Templates are named by
+// their path relative to the templates directory (eg: `pages/index.html`).
+// Templates ending in .html, .htm or .xml are auto-escaped.
+pub struct WorkerTemplates {
+    env: Environment<'static>,
+}
+
+impl WorkerTemplates {
+    pub fn from_sources<I>(sources: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut env = Environment::new();
+        for (name, source) in sources {
+            env.add_template_owned(name.clone(), source)
+                .with_context(|| format!("failed to compile template {}", name))?;
+        }
+        Ok(Self { env })
+    }
+
+    // Loads every file under `dir`, or nothing if `dir` doesn't exist
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let mut sources = vec![];
+        if dir.is_dir() {
+            collect_sources(dir, dir, &mut sources)?;
+        }
+        Self::from_sources(sources)
+    }
+
+    pub fn render(&self, name: &str, data: &Value) -> Result<String, minijinja::Error> {
+        self.env.get_template(name)?.render(data)
+    }
+}
+
+fn collect_sources(
+    root: &Path,
+    dir: &Path,
+    sources: &mut Vec<(String, String)>,
+) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_sources(root, &path, sources)?;
+            continue;
+        }
+        let name = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read template {}", name))?;
+        sources.push((name, source));
+    }
+    Ok(())
+}
+
+#[op2]
+#[string]
+pub fn op_render_template(
+    state: &mut OpState,
+    #[string] name: String,
+    #[serde] data: Value,
+) -> Result<String, AnyError> {
+    let templates = state
+        .try_borrow::<WorkerTemplates>()
+        .ok_or_else(|| custom_error("NotFound", format!("no template named {}", name)))?;
+    templates
+        .render(&name, &data)
+        .map_err(|err| match err.kind() {
+            minijinja::ErrorKind::TemplateNotFound => {
+                custom_error("NotFound", format!("no template named {}", name))
+            }
+            _ => custom_error("Error", format!("failed to render {}: {:#}", name, err)),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::WorkerTemplates;
+    use deno_core::serde_json::json;
+
+    #[test]
+    fn test_render_template() {
+        let templates = WorkerTemplates::from_sources([
+            (
+                "layout.html".to_string(),
+                "<main>{% block body %}{% endblock %}</main>".to_string(),
+            ),
+            (
+                "pages/hello.html".to_string(),
+                r#"{% extends "layout.html" %}{% block body %}Hello {{ name }}{% endblock %}"#
+                    .to_string(),
+            ),
+            ("hello.txt".to_string(), "Hello {{ name }}".to_string()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            templates
+                .render("pages/hello.html", &json!({ "name": "<b>you</b>" }))
+                .unwrap(),
+            "<main>Hello &lt;b&gt;you&lt;&#x2f;b&gt;</main>"
+        );
+        assert_eq!(
+            templates
+                .render("hello.txt", &json!({ "name": "<b>you</b>" }))
+                .unwrap(),
+            "Hello <b>you</b>"
+        );
+        assert!(templates.render("missing.html", &json!({})).is_err());
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        assert!(WorkerTemplates::from_sources([(
+            "broken.html".to_string(),
+            "{% if %}".to_string()
+        )])
+        .is_err());
+    }
+}
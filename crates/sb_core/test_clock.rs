@@ -0,0 +1,103 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use deno_core::OpState;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Virtual clock of workers run by the test runner (or a test runtime), read by
+// `Date`, `performance.now` and the timers. Tests can freeze it, then move it
+// forward with `EdgeRuntime.testing.clock`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TestClock {
+    frozen_at: Option<f64>,
+    // virtual time minus real time, while the clock runs
+    offset_ms: f64,
+}
+
+impl TestClock {
+    fn now(&self, real_ms: f64) -> f64 {
+        self.frozen_at.unwrap_or(real_ms + self.offset_ms)
+    }
+
+    fn set(&mut self, at: f64, real_ms: f64) {
+        match &mut self.frozen_at {
+            Some(frozen_at) => *frozen_at = at,
+            None => self.offset_ms = at - real_ms,
+        }
+    }
+
+    // the clock runs again from where it was frozen
+    fn restore(&mut self, real_ms: f64) {
+        if let Some(at) = self.frozen_at.take() {
+            self.offset_ms = at - real_ms;
+        }
+    }
+}
+
+fn real_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+fn test_clock(state: &mut OpState) -> Result<&mut TestClock, AnyError> {
+    state
+        .try_borrow_mut::<TestClock>()
+        .ok_or_else(|| custom_error("NotSupported", "the test clock is only available in tests"))
+}
+
+#[op2(fast)]
+pub fn op_test_clock_now(state: &mut OpState) -> f64 {
+    let real_ms = real_ms();
+    match state.try_borrow::<TestClock>() {
+        Some(clock) => clock.now(real_ms),
+        None => real_ms,
+    }
+}
+
+#[op2(fast)]
+pub fn op_test_clock_frozen(state: &mut OpState) -> bool {
+    state
+        .try_borrow::<TestClock>()
+        .is_some_and(|clock| clock.frozen_at.is_some())
+}
+
+#[op2(fast)]
+pub fn op_test_clock_freeze(state: &mut OpState, at: f64) -> Result<(), AnyError> {
+    test_clock(state)?.frozen_at = Some(at);
+    Ok(())
+}
+
+#[op2(fast)]
+pub fn op_test_clock_set(state: &mut OpState, at: f64) -> Result<(), AnyError> {
+    test_clock(state)?.set(at, real_ms());
+    Ok(())
+}
+
+#[op2(fast)]
+pub fn op_test_clock_restore(state: &mut OpState) -> Result<(), AnyError> {
+    test_clock(state)?.restore(real_ms());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::TestClock;
+
+    #[test]
+    fn test_clock() {
+        let mut clock = TestClock::default();
+        assert_eq!(clock.now(100.0), 100.0);
+        clock.set(1000.0, 100.0);
+        assert_eq!(clock.now(150.0), 1050.0);
+
+        clock.frozen_at = Some(5000.0);
+        assert_eq!(clock.now(200.0), 5000.0);
+        clock.set(6000.0, 300.0);
+        assert_eq!(clock.now(400.0), 6000.0);
+
+        // runs again from 6000
+        clock.restore(500.0);
+        assert_eq!(clock.now(510.0), 6010.0);
+    }
+}
@@ -0,0 +1,297 @@
+use deno_core::op2;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// same default as the Stripe and Svix libraries
+const DEFAULT_TOLERANCE_SECS: u64 = 300;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookProvider {
+    Stripe,
+    Github,
+    Svix,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookVerification {
+    pub valid: bool,
+    // why the signature was rejected
+    pub reason: Option<String>,
+    // unix timestamp (s) the webhook was signed at, for providers that sign one
+    pub timestamp: Option<i64>,
+}
+
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Result<&'a str, String> {
+    headers
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| format!("missing {} header", name))
+}
+
+fn check_timestamp(timestamp: &str, tolerance_secs: u64, now_secs: i64) -> Result<i64, String> {
+    let timestamp: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| "invalid timestamp".to_string())?;
+    if now_secs.abs_diff(timestamp) > tolerance_secs {
+        return Err("timestamp outside the tolerance".to_string());
+    }
+    Ok(timestamp)
+}
+
+// `hmac::verify` compares in constant time
+fn any_signature_matches(key: &hmac::Key, signed: &[u8], signatures: &[Vec<u8>]) -> bool {
+    signatures
+        .iter()
+        .any(|signature| hmac::verify(key, signed, signature).is_ok())
+}
+
+fn signed_with(prefix: &str, payload: &[u8]) -> Vec<u8> {
+    let mut signed = Vec::with_capacity(prefix.len() + payload.len());
+    signed.extend_from_slice(prefix.as_bytes());
+    signed.extend_from_slice(payload);
+    signed
+}
+
+// `Stripe-Signature: t=<timestamp>,v1=<hex>[,v1=<hex>]`, signing `<timestamp>.<payload>`
+fn verify_stripe(
+    secret: &str,
+    headers: &HashMap<String, String>,
+    payload: &[u8],
+    tolerance_secs: u64,
+    now_secs: i64,
+) -> Result<Option<i64>, String> {
+    let mut timestamp = None;
+    let mut signatures = vec![];
+    for part in header(headers, "stripe-signature")?.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = Some(value),
+            Some(("v1", value)) => {
+                if let Ok(signature) = hex::decode(value) {
+                    signatures.push(signature);
+                }
+            }
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or("missing timestamp in stripe-signature header")?;
+    let signed = signed_with(&format!("{}.", timestamp), payload);
+    let timestamp = check_timestamp(timestamp, tolerance_secs, now_secs)?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    if !any_signature_matches(&key, &signed, &signatures) {
+        return Err("no matching signature".to_string());
+    }
+    Ok(Some(timestamp))
+}
+
+// `X-Hub-Signature-256: sha256=<hex>`, signing the payload
+fn verify_github(
+    secret: &str,
+    headers: &HashMap<String, String>,
+    payload: &[u8],
+) -> Result<Option<i64>, String> {
+    let signature = header(headers, "x-hub-signature-256")?
+        .strip_prefix("sha256=")
+        .and_then(|hex_signature| hex::decode(hex_signature).ok())
+        .ok_or("invalid x-hub-signature-256 header")?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    if !any_signature_matches(&key, payload, &[signature]) {
+        return Err("no matching signature".to_string());
+    }
+    Ok(None)
+}
+
+// `svix-signature: v1,<base64>[ v1,<base64>]`, signing `<id>.<timestamp>.<payload>`
+// with the base64 decoded part of a `whsec_` secret. The unbranded `webhook-*`
+// headers (Standard Webhooks) are accepted too.
+fn verify_svix(
+    secret: &str,
+    headers: &HashMap<String, String>,
+    payload: &[u8],
+    tolerance_secs: u64,
+    now_secs: i64,
+) -> Result<Option<i64>, String> {
+    let svix_header = |name: &str| {
+        header(headers, &format!("svix-{}", name))
+            .or_else(|_| header(headers, &format!("webhook-{}", name)))
+    };
+    let id = svix_header("id")?;
+    let timestamp = svix_header("timestamp")?;
+    let signatures: Vec<Vec<u8>> = svix_header("signature")?
+        .split_whitespace()
+        .filter_map(|part| part.strip_prefix("v1,"))
+        .filter_map(|signature| base64::decode(signature).ok())
+        .collect();
+
+    let signed = signed_with(&format!("{}.{}.", id, timestamp), payload);
+    let timestamp = check_timestamp(timestamp, tolerance_secs, now_secs)?;
+
+    let secret = base64::decode(secret.strip_prefix("whsec_").unwrap_or(secret))
+        .map_err(|_| "invalid secret (expected whsec_<base64>)".to_string())?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+    if !any_signature_matches(&key, &signed, &signatures) {
+        return Err("no matching signature".to_string());
+    }
+    Ok(Some(timestamp))
+}
+
+// Header names must be lowercase
+pub fn verify_webhook(
+    provider: WebhookProvider,
+    secret: &str,
+    headers: &HashMap<String, String>,
+    payload: &[u8],
+    tolerance_secs: u64,
+    now_secs: i64,
+) -> WebhookVerification {
+    let result = match provider {
+        WebhookProvider::Stripe => {
+            verify_stripe(secret, headers, payload, tolerance_secs, now_secs)
+        }
+        WebhookProvider::Github => verify_github(secret, headers, payload),
+        WebhookProvider::Svix => verify_svix(secret, headers, payload, tolerance_secs, now_secs),
+    };
+    match result {
+        Ok(timestamp) => WebhookVerification {
+            valid: true,
+            reason: None,
+            timestamp,
+        },
+        Err(reason) => WebhookVerification {
+            valid: false,
+            reason: Some(reason),
+            timestamp: None,
+        },
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_webhook_verify(
+    #[serde] provider: WebhookProvider,
+    #[string] secret: String,
+    #[serde] headers: HashMap<String, String>,
+    #[buffer] payload: &[u8],
+    #[serde] tolerance_secs: Option<u64>,
+) -> WebhookVerification {
+    let now_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    verify_webhook(
+        provider,
+        &secret,
+        &headers,
+        payload,
+        tolerance_secs.unwrap_or(DEFAULT_TOLERANCE_SECS),
+        now_secs,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify_webhook, WebhookProvider};
+    use ring::hmac;
+    use std::collections::HashMap;
+
+    const PAYLOAD: &[u8] = br#"{"type":"invoice.paid"}"#;
+
+    fn sign(secret: &[u8], signed: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        hmac::sign(&key, signed).as_ref().to_vec()
+    }
+
+    fn headers(pairs: &[(&str, String)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_stripe_webhook() {
+        let signed = [b"1700000000.".as_slice(), PAYLOAD].concat();
+        let signature = hex::encode(sign(b"whsec_test", &signed));
+        let h = headers(&[(
+            "stripe-signature",
+            format!("t=1700000000,v1=deadbeef,v1={}", signature),
+        )]);
+
+        let result = verify_webhook(
+            WebhookProvider::Stripe,
+            "whsec_test",
+            &h,
+            PAYLOAD,
+            300,
+            1700000100,
+        );
+        assert!(result.valid);
+        assert_eq!(result.timestamp, Some(1700000000));
+
+        let result = verify_webhook(
+            WebhookProvider::Stripe,
+            "whsec_test",
+            &h,
+            PAYLOAD,
+            300,
+            1700001000,
+        );
+        assert_eq!(
+            result.reason.as_deref(),
+            Some("timestamp outside the tolerance")
+        );
+
+        let result = verify_webhook(
+            WebhookProvider::Stripe,
+            "other",
+            &h,
+            PAYLOAD,
+            300,
+            1700000100,
+        );
+        assert_eq!(result.reason.as_deref(), Some("no matching signature"));
+    }
+
+    #[test]
+    fn test_verify_github_webhook() {
+        let signature = hex::encode(sign(b"gh-secret", PAYLOAD));
+        let h = headers(&[("x-hub-signature-256", format!("sha256={}", signature))]);
+        assert!(verify_webhook(WebhookProvider::Github, "gh-secret", &h, PAYLOAD, 300, 0).valid);
+        assert!(!verify_webhook(WebhookProvider::Github, "gh-secret", &h, b"{}", 300, 0).valid);
+
+        let result = verify_webhook(
+            WebhookProvider::Github,
+            "gh-secret",
+            &HashMap::new(),
+            PAYLOAD,
+            300,
+            0,
+        );
+        assert_eq!(
+            result.reason.as_deref(),
+            Some("missing x-hub-signature-256 header")
+        );
+    }
+
+    #[test]
+    fn test_verify_svix_webhook() {
+        let secret = format!("whsec_{}", base64::encode(b"svix-secret"));
+        let signed = [b"msg_1.1700000000.".as_slice(), PAYLOAD].concat();
+        let signature = base64::encode(sign(b"svix-secret", &signed));
+        let h = headers(&[
+            ("svix-id", "msg_1".to_string()),
+            ("svix-timestamp", "1700000000".to_string()),
+            ("svix-signature", format!("v1,bm9wZQ== v1,{}", signature)),
+        ]);
+
+        let result = verify_webhook(WebhookProvider::Svix, &secret, &h, PAYLOAD, 300, 1700000000);
+        assert!(result.valid);
+        assert!(!verify_webhook(WebhookProvider::Svix, &secret, &h, b"{}", 300, 1700000000).valid);
+    }
+}
@@ -17,6 +17,11 @@ class SupaEnv {
 
 const supaEnvInstance = new SupaEnv();
 
+// Several variables at once (`undefined` for the missing ones)
+function getEnvs(keys) {
+  return ops.op_get_envs(Array.from(keys, String)).map((value) => value ?? undefined);
+}
+
 const SUPABASE_ENV = {
   get: supaEnvInstance.getEnv,
   toObject() {
@@ -29,4 +34,4 @@ const SUPABASE_ENV = {
   delete: supaEnvInstance.deleteEnv,
 };
 
-export { SUPABASE_ENV };
+export { getEnvs, SUPABASE_ENV };
@@ -10,7 +10,7 @@ pub type EnvVars = HashMap<String, String>;
 
 deno_core::extension!(
     sb_env,
-    ops = [op_set_env, op_env, op_get_env, op_delete_env],
+    ops = [op_set_env, op_env, op_get_env, op_get_envs, op_delete_env],
     esm_entry_point = "ext:sb_env/env.js",
     esm = ["env.js"]
 );
@@ -32,13 +32,11 @@ fn op_env(state: &mut OpState) -> Result<HashMap<String, String>, AnyError> {
     Ok(env_vars.clone())
 }
 
-#[op2]
-#[string]
-fn op_get_env(state: &mut OpState, #[string] key: String) -> Result<Option<String>, AnyError> {
-    let skip_permission_check = NODE_ENV_VAR_ALLOWLIST.contains(&key);
+fn get_env(state: &mut OpState, key: &str) -> Result<Option<String>, AnyError> {
+    let skip_permission_check = NODE_ENV_VAR_ALLOWLIST.contains(key);
 
     if !skip_permission_check {
-        state.borrow_mut::<Permissions>().check_env(&key)?;
+        state.borrow_mut::<Permissions>().check_env(key)?;
     }
 
     if key.is_empty() {
@@ -53,10 +51,26 @@ fn op_get_env(state: &mut OpState, #[string] key: String) -> Result<Option<Strin
     }
 
     let env_vars = state.borrow::<EnvVars>();
-    let r = env_vars.get(&key).cloned();
+    let r = env_vars.get(key).cloned();
     Ok(r)
 }
 
+#[op2]
+#[string]
+fn op_get_env(state: &mut OpState, #[string] key: String) -> Result<Option<String>, AnyError> {
+    get_env(state, &key)
+}
+
+// Several variables in one call, failing if any of them can't be read
+#[op2]
+#[serde]
+fn op_get_envs(
+    state: &mut OpState,
+    #[serde] keys: Vec<String>,
+) -> Result<Vec<Option<String>>, AnyError> {
+    keys.iter().map(|key| get_env(state, key)).collect()
+}
+
 #[op2(fast)]
 fn op_delete_env(_state: &mut OpState, #[string] _key: String) -> Result<(), AnyError> {
     Err(not_supported())
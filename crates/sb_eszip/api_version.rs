@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use eszip::EszipV2;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Version of the APIs the runtime exposes to functions. Bump it when a runtime
+// upgrade changes the behavior of an API in a way deployed bundles may rely on,
+// and add a shim restoring the previous behavior in `js/api_shims.js`.
+pub const RUNTIME_API_VERSION: u32 = 1;
+// Oldest bundles this runtime still runs
+pub const MIN_RUNTIME_API_VERSION: u32 = 1;
+
+// eszip entry holding the stamp
+pub const API_VERSION_SPECIFIER: &str = "edge-runtime:api-version";
+// bundles built before they were stamped
+const UNSTAMPED_API_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiVersionStamp {
+    pub api_version: u32,
+    // runtime release the bundle was built with
+    pub runtime_version: Option<String>,
+}
+
+// Records the runtime API version an eszip is built against
+pub fn stamp_api_version(eszip: &mut EszipV2, runtime_version: Option<&str>) {
+    let stamp = ApiVersionStamp {
+        api_version: RUNTIME_API_VERSION,
+        runtime_version: runtime_version.map(str::to_string),
+    };
+    let data = serde_json::to_vec(&stamp).unwrap();
+    eszip.add_opaque_data(API_VERSION_SPECIFIER.to_string(), Arc::from(data));
+}
+
+pub async fn read_api_version(eszip: &EszipV2) -> Result<u32, Error> {
+    let Some(module) = eszip.get_module(API_VERSION_SPECIFIER) else {
+        return Ok(UNSTAMPED_API_VERSION);
+    };
+    let Some(data) = module.source().await else {
+        bail!("api version of the bundle was already read");
+    };
+    let stamp: ApiVersionStamp =
+        serde_json::from_slice(&data).context("invalid api version stamp in bundle")?;
+    Ok(stamp.api_version)
+}
+
+// Bundles built for a newer runtime, or for versions this runtime dropped the
+// shims of, are refused instead of failing in subtle ways at request time
+pub fn check_api_version(api_version: u32) -> Result<(), Error> {
+    if api_version > RUNTIME_API_VERSION {
+        bail!(
+            "bundle was built for runtime API version {}, this runtime supports versions {} to {}",
+            api_version,
+            MIN_RUNTIME_API_VERSION,
+            RUNTIME_API_VERSION
+        );
+    }
+    if api_version < MIN_RUNTIME_API_VERSION {
+        bail!(
+            "bundle was built for runtime API version {}, which this runtime no longer supports (oldest supported: {}); rebuild it",
+            api_version,
+            MIN_RUNTIME_API_VERSION
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_api_version, read_api_version, stamp_api_version, RUNTIME_API_VERSION};
+    use deno_core::futures::io::{AllowStdIo, BufReader};
+    use eszip::EszipV2;
+
+    async fn roundtrip(eszip: EszipV2) -> EszipV2 {
+        let bytes = eszip.into_bytes();
+        let (eszip, loader) = EszipV2::parse(BufReader::new(AllowStdIo::new(bytes.as_slice())))
+            .await
+            .unwrap();
+        loader.await.unwrap();
+        eszip
+    }
+
+    #[tokio::test]
+    async fn test_api_version_stamp() {
+        let mut eszip = EszipV2::default();
+        stamp_api_version(&mut eszip, Some("1.2.3"));
+        let eszip = roundtrip(eszip).await;
+        assert_eq!(read_api_version(&eszip).await.unwrap(), RUNTIME_API_VERSION);
+
+        // unstamped bundles are from the first version
+        let eszip = roundtrip(EszipV2::default()).await;
+        assert_eq!(read_api_version(&eszip).await.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_check_api_version() {
+        assert!(check_api_version(RUNTIME_API_VERSION).is_ok());
+        assert!(check_api_version(RUNTIME_API_VERSION + 1).is_err());
+        assert!(check_api_version(0).is_err());
+    }
+}
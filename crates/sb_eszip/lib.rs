@@ -1 +1,2 @@
+pub mod api_version;
 pub mod module_loader;
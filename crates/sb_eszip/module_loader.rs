@@ -1,4 +1,6 @@
+use crate::api_version::{check_api_version, read_api_version};
 use anyhow::{bail, Error};
+use deno_core::futures::future::{LocalBoxFuture, Shared};
 use deno_core::futures::io::{AllowStdIo, BufReader};
 use deno_core::futures::FutureExt;
 use deno_core::url::Url;
@@ -9,13 +11,55 @@ use deno_core::ModuleSourceFuture;
 use deno_core::ModuleSpecifier;
 use deno_core::ResolutionKind;
 use import_map::{parse_from_json, ImportMap};
-use log::warn;
+use log::{debug, warn};
+use std::future::Future;
+use std::io::Cursor;
 use std::path::Path;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+// The sources of a bundle are read (and their hashes checked) lazily: only the
+// header is parsed on boot, and the data section is read as far as the modules
+// loaded need. Only the static graph of the main module is loaded on boot,
+// dynamically imported subgraphs are read, loaded and evaluated when first
+// imported, so cold starts only pay for the modules the handler path touches.
+
+// Reads the data section of the bundle while polled, shared by the loads
+// waiting on a source
+type BundleData = Shared<LocalBoxFuture<'static, Result<(), String>>>;
+
+// Called with each module loaded, when its load started and whether it was
+// dynamically imported, eg: to record it on the timeline of the request
+pub type ModuleLoadHook = Rc<dyn Fn(&ModuleSpecifier, Instant, bool)>;
+
+// Modules of the bundle loaded by a worker
+#[derive(Debug, Default)]
+pub struct ModuleLoadStats {
+    loaded: AtomicUsize,
+    // loaded by dynamic imports
+    on_demand: AtomicUsize,
+}
+
+impl ModuleLoadStats {
+    pub fn loaded(&self) -> usize {
+        self.loaded.load(Ordering::Relaxed)
+    }
+
+    pub fn on_demand(&self) -> usize {
+        self.on_demand.load(Ordering::Relaxed)
+    }
+}
 
 pub struct EszipModuleLoader {
     eszip: eszip::EszipV2,
+    data: BundleData,
     maybe_import_map: Option<ImportMap>,
+    api_version: u32,
+    load_stats: Arc<ModuleLoadStats>,
+    maybe_load_hook: Option<ModuleLoadHook>,
 }
 
 #[derive(Debug)]
@@ -34,10 +78,15 @@ impl EszipModuleLoader {
             EszipPayloadKind::VecKind(vec) => vec,
         };
 
-        let bufreader = BufReader::new(AllowStdIo::new(bytes.as_slice()));
+        let bufreader = BufReader::new(AllowStdIo::new(Cursor::new(bytes)));
         let (eszip, loader) = eszip::EszipV2::parse(bufreader).await?;
+        let data: BundleData =
+            async move { loader.await.map(|_| ()).map_err(|err| err.to_string()) }
+                .boxed_local()
+                .shared();
 
-        loader.await?;
+        let api_version = with_bundle_data(&data, read_api_version(&eszip)).await?;
+        check_api_version(api_version)?;
 
         // load import map
         let mut maybe_import_map: Option<ImportMap> = None;
@@ -45,7 +94,9 @@ impl EszipModuleLoader {
             let import_map_url = Url::parse(&maybe_import_map_url.unwrap())?;
 
             if let Some(import_map_module) = eszip.get_import_map(import_map_url.as_str()) {
-                if let Some(source) = import_map_module.source().await {
+                let source =
+                    with_bundle_data(&data, async { Ok(import_map_module.source().await) }).await?;
+                if let Some(source) = source {
                     let source = std::str::from_utf8(&source)?.to_string();
                     let result = parse_from_json(&import_map_url, &source)?;
                     if !result.diagnostics.is_empty() {
@@ -66,9 +117,50 @@ impl EszipModuleLoader {
 
         Ok(Self {
             eszip,
+            data,
             maybe_import_map,
+            api_version,
+            load_stats: Arc::default(),
+            maybe_load_hook: None,
         })
     }
+
+    pub fn with_load_hook(mut self, hook: ModuleLoadHook) -> Self {
+        self.maybe_load_hook = Some(hook);
+        self
+    }
+
+    // Runtime API version the bundle was built against
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
+    pub fn load_stats(&self) -> Arc<ModuleLoadStats> {
+        self.load_stats.clone()
+    }
+}
+
+// Reads the data section of the bundle until `fut` is done. The sources still
+// pending once it failed never will be, so `fut` is given up on.
+async fn with_bundle_data<T>(
+    data: &BundleData,
+    fut: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    tokio::pin!(fut);
+    let mut data = data.clone();
+    let mut data_read = false;
+    loop {
+        tokio::select! {
+            biased;
+            result = &mut fut => return result,
+            result = &mut data, if !data_read => {
+                if let Err(err) = result {
+                    bail!("invalid bundle: {}", err);
+                }
+                data_read = true;
+            }
+        }
+    }
 }
 
 impl ModuleLoader for EszipModuleLoader {
@@ -102,14 +194,31 @@ impl ModuleLoader for EszipModuleLoader {
         &self,
         module_specifier: &ModuleSpecifier,
         _maybe_referrer: Option<&ModuleSpecifier>,
-        _is_dyn_import: bool,
+        is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
         let maybe_module = self.eszip.get_module(module_specifier.as_str());
         let module_specifier = module_specifier.clone();
+        let load_stats = self.load_stats.clone();
+        let data = self.data.clone();
+        let maybe_load_hook = self.maybe_load_hook.clone();
 
         async move {
             if let Some(module) = maybe_module {
-                if let Some(code) = module.source().await {
+                let start = Instant::now();
+                let source = with_bundle_data(&data, async { Ok(module.source().await) }).await?;
+                if let Some(code) = source {
+                    load_stats.loaded.fetch_add(1, Ordering::Relaxed);
+                    if let Some(hook) = &maybe_load_hook {
+                        hook(&module_specifier, start, is_dyn_import);
+                    }
+                    if is_dyn_import {
+                        load_stats.on_demand.fetch_add(1, Ordering::Relaxed);
+                        debug!(
+                            "loaded {} on demand in {:?}",
+                            module_specifier,
+                            start.elapsed()
+                        );
+                    }
                     let code = std::str::from_utf8(&code)?.to_string();
                     let module_type = match module.kind {
                         eszip::ModuleKind::JavaScript => Some(deno_core::ModuleType::JavaScript),
@@ -138,3 +247,32 @@ impl ModuleLoader for EszipModuleLoader {
         .boxed_local()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{with_bundle_data, EszipModuleLoader, EszipPayloadKind};
+    use eszip::EszipV2;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_bundle_data_is_read_lazily() {
+        let mut eszip = EszipV2::default();
+        eszip.add_opaque_data("file:///a".to_string(), Arc::from(b"aaaaaaaa".as_slice()));
+        eszip.add_opaque_data("file:///b".to_string(), Arc::from(b"bbbbbbbb".as_slice()));
+        let mut bytes = eszip.into_bytes();
+        // corrupts the source of the last module
+        let at = bytes.windows(8).position(|w| w == b"bbbbbbbb").unwrap();
+        bytes[at] = b'c';
+
+        // boots without reading the sources
+        let loader = EszipModuleLoader::new(EszipPayloadKind::VecKind(bytes), None)
+            .await
+            .unwrap();
+        let read = |specifier: &str| {
+            let module = loader.eszip.get_module(specifier).unwrap();
+            with_bundle_data(&loader.data, async move { Ok(module.source().await) })
+        };
+        assert_eq!(&*read("file:///a").await.unwrap().unwrap(), b"aaaaaaaa");
+        assert!(read("file:///b").await.is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use deno_core::serde_json::Value;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// OpenAI-compatible endpoints of `EdgeRuntime.ai`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AiEndpoint {
+    // `/chat/completions`
+    Chat,
+    // `/embeddings`
+    Embeddings,
+}
+
+impl AiEndpoint {
+    pub fn path(&self) -> &'static str {
+        match self {
+            AiEndpoint::Chat => "/chat/completions",
+            AiEndpoint::Embeddings => "/embeddings",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AiRequest {
+    // the service's default provider if not set
+    pub provider: Option<String>,
+    pub endpoint: AiEndpoint,
+    // request body, as the provider's API takes it
+    pub body: Value,
+}
+
+// Response of the provider, streamed back as it arrives
+pub struct AiResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: mpsc::Receiver<Result<Vec<u8>, String>>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AiUsage {
+    // tokens used in the current quota window
+    pub tokens: u64,
+    // tokens the service may use per window, unlimited if not set
+    pub quota: Option<u64>,
+    // unix timestamp (ms) the window resets at
+    pub resets_at_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum AiError {
+    // the service used up its tokens for the current window
+    QuotaExceeded(String),
+    Other(Error),
+}
+
+impl From<Error> for AiError {
+    fn from(err: Error) -> Self {
+        AiError::Other(err)
+    }
+}
+
+// Proxies requests to the providers of a service, injecting the credentials
+// the isolate never sees and counting the tokens the service used
+pub trait AiGateway: Send + Sync {
+    fn request(
+        &self,
+        service: &str,
+        req: AiRequest,
+    ) -> BoxFuture<'static, Result<AiResponse, AiError>>;
+
+    fn usage(&self, service: &str) -> AiUsage;
+}
+
+pub type SharedAiGateway = Arc<dyn AiGateway>;
+
+// AI gateway as seen by a worker, put in the op state
+pub struct WorkerAi {
+    pub gateway: SharedAiGateway,
+    pub service: String,
+}
@@ -0,0 +1,55 @@
+use anyhow::Error;
+use deno_core::serde_json::Value;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Request header carrying the id of the alarm a request was dispatched for
+pub const ALARM_HEADER: &str = "x-edge-runtime-alarm";
+// 0 on the first dispatch, incremented on every retry
+pub const ALARM_ATTEMPT_HEADER: &str = "x-edge-runtime-alarm-attempt";
+
+// A request the scheduler sends to a service at `fire_at_ms`, even if the worker
+// that scheduled it is gone by then
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Alarm {
+    pub id: String,
+    pub service_path: String,
+    pub import_map_path: Option<String>,
+    pub entrypoint: Option<String>,
+    // path of the request (eg: `/webhooks/retry`)
+    pub path: String,
+    // request body (JSON)
+    pub payload: Value,
+    // unix timestamp (ms)
+    pub fire_at_ms: u64,
+    pub attempt: u32,
+}
+
+// Persists alarms, so they survive worker recycling and runtime restarts
+pub trait AlarmStore: Send + Sync {
+    fn insert(&self, alarm: &Alarm) -> Result<(), Error>;
+
+    // false if the service has no such alarm
+    fn cancel(&self, service_path: &str, id: &str) -> Result<bool, Error>;
+
+    fn list(&self, service_path: &str) -> Result<Vec<Alarm>, Error>;
+
+    // Returns the alarms due at `now_ms`, pushing them back by `lease_ms` so they
+    // fire again if the runtime goes away before they're completed
+    fn claim_due(&self, now_ms: u64, lease_ms: u64) -> Result<Vec<Alarm>, Error>;
+
+    fn complete(&self, id: &str) -> Result<(), Error>;
+
+    fn retry(&self, id: &str, fire_at_ms: u64) -> Result<(), Error>;
+}
+
+pub type SharedAlarmStore = Arc<dyn AlarmStore>;
+
+// Alarms as seen by a user worker, put in the op state
+pub struct WorkerAlarms {
+    pub store: SharedAlarmStore,
+    pub service_path: String,
+    pub import_map_path: Option<String>,
+    pub entrypoint: Option<String>,
+}
@@ -0,0 +1,43 @@
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Arc;
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbedOptions {
+    // averages the token embeddings, the first token's embedding otherwise
+    #[serde(default = "default_true")]
+    pub mean_pool: bool,
+    // scales the embedding to unit length
+    #[serde(default = "default_true")]
+    pub normalize: bool,
+}
+
+impl Default for EmbedOptions {
+    fn default() -> Self {
+        Self {
+            mean_pool: true,
+            normalize: true,
+        }
+    }
+}
+
+// Built-in embedding models of `EdgeRuntime.ai.Session`, loaded once and shared
+// by all isolates
+pub trait EmbeddingModels: Send + Sync {
+    fn models(&self) -> Vec<String>;
+
+    fn embed(
+        &self,
+        model: &str,
+        text: String,
+        opts: EmbedOptions,
+    ) -> BoxFuture<'static, Result<Vec<f32>, Error>>;
+}
+
+pub type SharedEmbeddingModels = Arc<dyn EmbeddingModels>;
@@ -1,15 +1,98 @@
 use anyhow::Error;
 use deno_core::FastString;
 use enum_as_inner::EnumAsInner;
-use event_worker::events::WorkerEventWithMetadata;
+use event_worker::events::{UncaughtErrorPolicy, WorkerEventWithMetadata};
+use event_worker::AcceptedEvents;
 use hyper::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
-use tokio::sync::{mpsc, oneshot};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Semaphore};
 use uuid::Uuid;
 
+use crate::listen::ListenPermission;
+use crate::manifest::SharedManifest;
+use crate::usage::UsageCollector;
+use crate::web_workers::WebWorkerPort;
 use sb_eszip::module_loader::EszipPayloadKind;
 
+// Bounds of `max_concurrent_requests`, the size of a semaphore: with 0 permits
+// queued requests would wait forever
+pub const MAX_CONCURRENT_REQUESTS: usize = Semaphore::MAX_PERMITS;
+
+// Lower bound of `hang_threshold_ms`: a shorter one would terminate workers for
+// ordinary synchronous work (eg: parsing a large payload)
+pub const MIN_HANG_THRESHOLD_MS: u64 = 100;
+
+// What the pool does when a worker is already serving `max_concurrent_requests`
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcurrencyOverflowPolicy {
+    // wait for an in-flight request on the same worker to finish
+    #[default]
+    Queue,
+    // boot an additional worker for the service
+    Spawn,
+}
+
+// Whether creating a worker for a service reuses the worker it already runs
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerReusePolicy {
+    // reuse the service's active worker, booting one if there's none
+    #[default]
+    Active,
+    // boot a new worker replacing the active one
+    Replace,
+    // boot a new worker only reachable through its key, the active one is left as is
+    Isolated,
+}
+
+// Where a user worker runs
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerIsolation {
+    // a thread of the runtime process
+    #[default]
+    Thread,
+    // a child process of its own, trading memory for stronger isolation
+    Process,
+}
+
+// Limits of the timers of a worker, to cut the wakeups of nodes running
+// thousands of warm workers: delays are at least `min_delay_ms`, at most
+// `max_timers` timers may be pending, and the timers of idle workers are
+// aligned on multiples of `idle_coalesce_ms` so they fire together.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerPolicy {
+    pub min_delay_ms: Option<u64>,
+    pub max_timers: Option<usize>,
+    pub idle_coalesce_ms: Option<u64>,
+}
+
+fn default_timer_resolution_ms() -> u64 {
+    100
+}
+
+// Seeds the random values of a worker and coarsens its clock, so an execution
+// can be reproduced byte for byte (eg: to replay or debug it). Never meant for
+// production, its random values are predictable.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeterministicMode {
+    pub seed: u32,
+    // time of the worker's clock when it boots, the real time when not set
+    pub time_origin_ms: Option<u64>,
+    // the clock advances in steps of this many milliseconds
+    #[serde(default = "default_timer_resolution_ms")]
+    pub timer_resolution_ms: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWorkerRuntimeOpts {
     pub service_path: Option<String>,
@@ -17,6 +100,7 @@ pub struct UserWorkerRuntimeOpts {
 
     pub pool_msg_tx: Option<mpsc::UnboundedSender<UserWorkerMsgs>>,
     pub events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    pub usage: Option<UsageCollector>,
 
     pub memory_limit_mb: u64,
     pub low_memory_multiplier: u64,
@@ -27,10 +111,60 @@ pub struct UserWorkerRuntimeOpts {
     pub cpu_burst_interval_ms: u64,
     pub max_cpu_bursts: u64,
 
+    // terminate the worker if a single event loop turn runs longer than this (at
+    // least `MIN_HANG_THRESHOLD_MS`)
+    pub hang_threshold_ms: u64,
+
+    // CPU share of the service's cgroup, when worker cgroups are enabled
+    pub cpu_weight: Option<u64>,
+
+    pub isolation: WorkerIsolation,
+
+    // web workers (`new Worker()`) the worker can run at a time
+    pub max_web_workers: usize,
+    // set for web workers, connecting them to the worker that spawned them
+    pub web_worker: Option<WebWorkerPort>,
+
     pub force_create: bool,
+    pub reuse: WorkerReusePolicy,
     pub net_access_disabled: bool,
     pub custom_module_root: Option<String>,
     pub allow_remote_modules: bool,
+    // remote modules can only be imported dynamically from the origins of the
+    // static module graph, loaded on boot
+    pub freeze_remote_origins: bool,
+    // remote modules fetched again instead of read from the module cache, by url
+    // or url prefix (eg: the base url of a package)
+    pub reload_modules: Vec<String>,
+    // V8 refuses to compile code from strings (`eval`, `new Function`, ...)
+    pub disallow_code_generation: bool,
+    pub deterministic: Option<DeterministicMode>,
+    pub timer_policy: Option<TimerPolicy>,
+    // what the worker does about uncaught exceptions and unhandled rejections
+    pub uncaught_error_policy: UncaughtErrorPolicy,
+    // resources left open by requests are reported with `ResourceLeak` events
+    pub detect_leaks: bool,
+    // sockets the worker may listen on with `EdgeRuntime.listen`
+    pub listen: Vec<ListenPermission>,
+
+    // copy up to this many bytes of request/response bodies to the events worker
+    pub body_tee_max_bytes: Option<u64>,
+    // share of requests (0 to 1) detailed telemetry is collected for, the
+    // runtime's default rate when not set
+    pub telemetry_sample_rate: Option<f64>,
+
+    pub max_concurrent_requests: Option<usize>,
+    pub concurrency_overflow: ConcurrencyOverflowPolicy,
+    // workers the service may run at once (across the cluster in cluster mode)
+    pub max_workers: Option<usize>,
+
+    // runs the worker on a virtual clock tests can freeze and advance
+    pub test_clock: bool,
+    // collects the V8 coverage of the worker's modules, for the test runner
+    pub coverage: bool,
+    // local files loaded in place of modules, by specifier (eg: to stub remote
+    // modules in tests)
+    pub module_mocks: HashMap<String, PathBuf>,
 }
 
 impl Default for UserWorkerRuntimeOpts {
@@ -42,15 +176,38 @@ impl Default for UserWorkerRuntimeOpts {
             max_cpu_bursts: 10,
             cpu_burst_interval_ms: 100,
             cpu_time_threshold_ms: 50,
+            hang_threshold_ms: 10 * 1000,
+            cpu_weight: None,
+            isolation: WorkerIsolation::default(),
+            max_web_workers: 4,
+            web_worker: None,
 
             force_create: false,
+            reuse: WorkerReusePolicy::default(),
             key: None,
             pool_msg_tx: None,
             events_msg_tx: None,
+            usage: None,
             net_access_disabled: false,
             allow_remote_modules: true,
+            freeze_remote_origins: false,
+            reload_modules: vec![],
+            disallow_code_generation: false,
+            deterministic: None,
+            timer_policy: None,
+            uncaught_error_policy: UncaughtErrorPolicy::default(),
+            detect_leaks: false,
             custom_module_root: None,
+            listen: vec![],
             service_path: None,
+            body_tee_max_bytes: None,
+            telemetry_sample_rate: None,
+            max_concurrent_requests: None,
+            max_workers: None,
+            concurrency_overflow: ConcurrencyOverflowPolicy::default(),
+            test_clock: false,
+            coverage: false,
+            module_mocks: HashMap::new(),
         }
     }
 }
@@ -59,15 +216,125 @@ impl Default for UserWorkerRuntimeOpts {
 pub struct UserWorkerProfile {
     pub worker_request_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
     pub service_path: String,
+    pub body_tee_max_bytes: Option<u64>,
+    pub telemetry_sample_rate: Option<f64>,
+    pub permits: Option<Arc<Semaphore>>,
+    pub concurrency_overflow: ConcurrencyOverflowPolicy,
+    // staged workers are not routed to until their version is activated
+    pub staged: bool,
+    // isolated workers are only reached through their key, they never become
+    // the service's active worker
+    pub isolated: bool,
+    pub version: Option<ServiceVersion>,
+    // requests being handled (until their response body is sent), workers
+    // without any can be evicted under memory pressure
+    pub inflight: Arc<AtomicUsize>,
+    pub boot: PendingBoot,
+}
+
+// Boot of a worker, until a request is charged for waiting on it
+#[derive(Debug, Clone, Default)]
+pub struct PendingBoot(Arc<Mutex<Option<(Instant, Duration)>>>);
+
+impl PendingBoot {
+    pub fn new(finished: Instant, took: Duration) -> Self {
+        Self(Arc::new(Mutex::new(Some((finished, took)))))
+    }
+
+    // How long the boot took, for the first request sent within `window` of it
+    // (the one the worker was booted for), zero for any other
+    pub fn take(&self, window: Duration) -> Duration {
+        match self.0.lock().unwrap().take() {
+            Some((finished, took)) if finished.elapsed() <= window => took,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+// The pool declined to boot a worker, eg: while the node is under memory pressure
+#[derive(Debug)]
+pub struct WorkerCreationRejected(pub String);
+
+impl fmt::Display for WorkerCreationRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for WorkerCreationRejected {}
+
+#[derive(Debug, Default)]
+pub struct VersionStats {
+    pub requests: AtomicU64,
+    pub errors: AtomicU64,
+}
+
+impl VersionStats {
+    pub fn record(&self, is_error: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let requests = self.requests.load(Ordering::Relaxed);
+        if requests == 0 {
+            return 0.0;
+        }
+        self.errors.load(Ordering::Relaxed) as f64 / requests as f64
+    }
+}
+
+// A deployable revision of a service, registered through the admin API
+#[derive(Debug, Clone)]
+pub struct ServiceVersion {
+    pub id: Uuid,
+    pub maybe_entrypoint: Option<String>,
+    pub maybe_eszip: Option<Arc<Vec<u8>>>,
+    pub import_map_path: Option<String>,
+    // shared by all workers running this version
+    pub stats: Arc<VersionStats>,
+}
+
+// A version receiving a share of a service's traffic before being promoted
+#[derive(Debug, Clone)]
+pub struct CanaryVersion {
+    pub version: ServiceVersion,
+    pub key: Uuid,
+    // percentage of requests routed to the canary (0-100)
+    pub weight: u8,
+    // the canary is rolled back once its error rate exceeds this ratio...
+    pub max_error_rate: f64,
+    // ...after serving at least this many requests
+    pub min_requests: u64,
+}
+
+impl CanaryVersion {
+    pub fn is_failing(&self) -> bool {
+        self.version.stats.requests.load(Ordering::Relaxed) >= self.min_requests
+            && self.version.stats.error_rate() > self.max_error_rate
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServiceVersions {
+    pub active: Option<ServiceVersion>,
+    // kept around for rollbacks
+    pub previous: Option<ServiceVersion>,
+    pub canary: Option<CanaryVersion>,
 }
 
 #[derive(Debug, Clone)]
 pub struct MainWorkerRuntimeOpts {
     pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub manifest: Option<SharedManifest>,
 }
 
-#[derive(Debug, Clone)]
-pub struct EventWorkerRuntimeOpts {}
+#[derive(Debug, Clone, Default)]
+pub struct EventWorkerRuntimeOpts {
+    pub accepted_events: Option<AcceptedEvents>,
+}
 
 #[derive(Debug, Clone, EnumAsInner)]
 pub enum WorkerRuntimeOpts {
@@ -96,12 +363,28 @@ pub enum UserWorkerMsgs {
         oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
     ),
     Created(Uuid, UserWorkerProfile),
+    Stage(
+        WorkerContextInitOpts,
+        oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+    ),
+    Activate(String, ServiceVersion, Uuid),
+    Rollback(String, oneshot::Sender<Result<ServiceVersions, Error>>),
+    GetVersions(String, oneshot::Sender<ServiceVersions>),
+    StartCanary(String, CanaryVersion),
+    PromoteCanary(String, oneshot::Sender<Result<ServiceVersions, Error>>),
+    // rolls back the canary of a service if it exceeded its error threshold
+    CheckCanary(String),
     SendRequest(
         Uuid,
         Request<Body>,
         oneshot::Sender<Result<Response<Body>, Error>>,
     ),
     Retire(Uuid),
+    RetireAll,
+    // stops routing new requests to the current workers of these services
+    RetireServices(Vec<String>),
+    // shuts down the workers without requests in flight, to free memory
+    EvictIdle,
     Shutdown(Uuid),
 }
 
@@ -0,0 +1,169 @@
+use anyhow::{bail, Error};
+use deno_core::serde_json::{self, Map, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+// Serves `rollout.value` to `percentage` (0 - 100) of the keys passed by the
+// isolate (eg: a user id). Requests without a key never get the rollout value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Rollout {
+    pub percentage: f64,
+    pub value: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FlagDefinition {
+    #[serde(default)]
+    pub default: Value,
+    // overrides of the default, keyed by service name
+    #[serde(default)]
+    pub services: HashMap<String, Value>,
+    #[serde(default)]
+    pub rollout: Option<Rollout>,
+}
+
+// Flags keyed by name, eg:
+// `{ "newCheckout": { "default": false, "services": { "checkout": true } } }`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct FeatureFlags {
+    pub flags: BTreeMap<String, FlagDefinition>,
+}
+
+pub type SharedFeatureFlags = watch::Receiver<Arc<FeatureFlags>>;
+
+// Flags as seen by a worker, put in the op state
+pub struct WorkerFeatureFlags {
+    pub service: String,
+    pub flags: SharedFeatureFlags,
+}
+
+// Services are matched by the last component of their path
+pub fn flag_service_name(service_path: &str) -> String {
+    Path::new(service_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| service_path.to_string())
+}
+
+// Stable across processes, so a key stays in the same bucket on every instance
+fn rollout_bucket(flag: &str, key: &str) -> f64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in flag.bytes().chain(std::iter::once(b':')).chain(key.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % 10_000) as f64 / 100.0
+}
+
+impl FlagDefinition {
+    pub fn evaluate(&self, name: &str, service: &str, key: Option<&str>) -> Value {
+        if let (Some(rollout), Some(key)) = (&self.rollout, key) {
+            if rollout_bucket(name, key) < rollout.percentage {
+                return rollout.value.clone();
+            }
+        }
+        self.services.get(service).unwrap_or(&self.default).clone()
+    }
+}
+
+impl FeatureFlags {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let flags: FeatureFlags = serde_json::from_str(json)?;
+        for (name, flag) in &flags.flags {
+            if let Some(rollout) = &flag.rollout {
+                if !(0.0..=100.0).contains(&rollout.percentage) {
+                    bail!(
+                        "feature flag {}: rollout percentage must be between 0 and 100 (got {})",
+                        name,
+                        rollout.percentage
+                    );
+                }
+            }
+        }
+        Ok(flags)
+    }
+
+    // `None` if the flag isn't defined
+    pub fn evaluate(&self, name: &str, service: &str, key: Option<&str>) -> Option<Value> {
+        self.flags
+            .get(name)
+            .map(|flag| flag.evaluate(name, service, key))
+    }
+
+    pub fn evaluate_all(&self, service: &str, key: Option<&str>) -> Map<String, Value> {
+        self.flags
+            .iter()
+            .map(|(name, flag)| (name.clone(), flag.evaluate(name, service, key)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{flag_service_name, rollout_bucket, FeatureFlags};
+    use deno_core::serde_json::json;
+
+    #[test]
+    fn test_evaluate_feature_flags() {
+        let flags = FeatureFlags::parse(
+            r#"{
+                "newCheckout": { "default": false, "services": { "checkout": true } },
+                "theme": { "default": "light", "rollout": { "percentage": 100, "value": "dark" } },
+                "beta": { "default": false, "rollout": { "percentage": 0, "value": true } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            flags.evaluate("newCheckout", "checkout", None),
+            Some(json!(true))
+        );
+        assert_eq!(
+            flags.evaluate("newCheckout", "hello", None),
+            Some(json!(false))
+        );
+        assert_eq!(flags.evaluate("theme", "hello", None), Some(json!("light")));
+        assert_eq!(
+            flags.evaluate("theme", "hello", Some("user-1")),
+            Some(json!("dark"))
+        );
+        assert_eq!(
+            flags.evaluate("beta", "hello", Some("user-1")),
+            Some(json!(false))
+        );
+        assert_eq!(flags.evaluate("missing", "hello", None), None);
+
+        let all = flags.evaluate_all("checkout", None);
+        assert_eq!(all["newCheckout"], json!(true));
+        assert_eq!(all.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_invalid_feature_flags() {
+        assert!(FeatureFlags::parse(
+            r#"{ "beta": { "rollout": { "percentage": 150, "value": true } } }"#
+        )
+        .is_err());
+        assert!(FeatureFlags::parse(r#"{ "beta": { "enabled": true } }"#).is_err());
+    }
+
+    #[test]
+    fn test_rollout_bucket_is_stable() {
+        let bucket = rollout_bucket("beta", "user-1");
+        assert!((0.0..100.0).contains(&bucket));
+        assert_eq!(bucket, rollout_bucket("beta", "user-1"));
+    }
+
+    #[test]
+    fn test_flag_service_name() {
+        assert_eq!(flag_service_name("./examples/checkout"), "checkout");
+        assert_eq!(flag_service_name("/srv/hello/"), "hello");
+    }
+}
@@ -0,0 +1,95 @@
+use crate::service_scope::ANY_SERVICE;
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Signs with a key the isolate never sees. Implemented by the runtime for local
+// keys, and by embedders for keys held by a KMS or an HSM.
+pub trait Signer: Send + Sync {
+    fn algorithm(&self) -> &str;
+
+    fn sign(&self, data: Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, Error>>;
+
+    fn verify(&self, data: Vec<u8>, signature: Vec<u8>) -> BoxFuture<'static, Result<bool, Error>>;
+}
+
+pub type SharedSigner = Arc<dyn Signer>;
+
+#[derive(Clone)]
+pub struct RegisteredKey {
+    pub signer: SharedSigner,
+    // scopes of the services allowed to use the key (see `service_scope`), or
+    // `ANY_SERVICE` for all of them
+    pub services: Vec<String>,
+}
+
+impl RegisteredKey {
+    fn allows(&self, service: &str) -> bool {
+        self.services
+            .iter()
+            .any(|s| s == ANY_SERVICE || s == service)
+    }
+}
+
+// Named signing keys registered by the operator
+#[derive(Clone, Default)]
+pub struct KeyStore {
+    keys: HashMap<String, RegisteredKey>,
+}
+
+impl KeyStore {
+    pub fn insert(&mut self, name: &str, key: RegisteredKey) {
+        self.keys.insert(name.to_string(), key);
+    }
+
+    pub fn extend(&mut self, other: KeyStore) {
+        self.keys.extend(other.keys);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // Maps the services keys are restricted to, eg: from names to scopes
+    pub fn scope_services<F>(&mut self, scope: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        for key in self.keys.values_mut() {
+            for service in key.services.iter_mut() {
+                if service != ANY_SERVICE {
+                    *service = scope(service);
+                }
+            }
+        }
+    }
+
+    // `None` if there's no such key or the service may not use it
+    pub fn get(&self, name: &str, service: &str) -> Option<SharedSigner> {
+        self.keys
+            .get(name)
+            .filter(|key| key.allows(service))
+            .map(|key| key.signer.clone())
+    }
+
+    pub fn names(&self, service: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .keys
+            .iter()
+            .filter(|(_, key)| key.allows(service))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+pub type SharedKeyStore = Arc<KeyStore>;
+
+// Keys as seen by a worker, put in the op state
+pub struct WorkerKeys {
+    pub store: SharedKeyStore,
+    // matched against `RegisteredKey::services`
+    pub service: String,
+}
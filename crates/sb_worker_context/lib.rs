@@ -1 +1,18 @@
+pub mod ai;
+pub mod alarms;
+pub mod embeddings;
 pub mod essentials;
+pub mod flags;
+pub mod keys;
+pub mod listen;
+pub mod mail;
+pub mod manifest;
+pub mod onnx;
+pub mod outbound_webhooks;
+pub mod paused_services;
+pub mod redis;
+pub mod request_metadata;
+pub mod sequences;
+pub mod service_scope;
+pub mod usage;
+pub mod web_workers;
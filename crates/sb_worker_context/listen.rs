@@ -0,0 +1,92 @@
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+// Sockets a user worker may listen on with `EdgeRuntime.listen`, for protocols
+// other than HTTP. Nothing can be listened on unless it's granted.
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenTransport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+fn default_hostname() -> String {
+    "0.0.0.0".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenPermission {
+    #[serde(default)]
+    pub transport: ListenTransport,
+    #[serde(default = "default_hostname")]
+    pub hostname: String,
+    pub port: u16,
+}
+
+// Put in the op state of user workers granted any sockets
+#[derive(Debug, Clone, Default)]
+pub struct ListenPermissions(pub Vec<ListenPermission>);
+
+impl ListenPermissions {
+    pub fn check(
+        &self,
+        transport: ListenTransport,
+        hostname: &str,
+        port: u16,
+    ) -> Result<(), Error> {
+        let granted = self.0.iter().any(|permission| {
+            permission.transport == transport
+                && permission.hostname == hostname
+                && permission.port == port
+        });
+        if !granted {
+            bail!(
+                "listening on {:?} {}:{} isn't permitted for the user worker",
+                transport,
+                hostname,
+                port
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ListenPermission, ListenPermissions, ListenTransport};
+    use deno_core::serde_json::{self, json};
+
+    #[test]
+    fn test_listen_permissions() {
+        let permissions: Vec<ListenPermission> = serde_json::from_value(json!([
+            { "port": 1883 },
+            { "transport": "udp", "hostname": "127.0.0.1", "port": 5683 },
+        ]))
+        .unwrap();
+        let permissions = ListenPermissions(permissions);
+
+        assert!(permissions
+            .check(ListenTransport::Tcp, "0.0.0.0", 1883)
+            .is_ok());
+        assert!(permissions
+            .check(ListenTransport::Udp, "127.0.0.1", 5683)
+            .is_ok());
+
+        // only the granted transport, address and port
+        assert!(permissions
+            .check(ListenTransport::Udp, "0.0.0.0", 1883)
+            .is_err());
+        assert!(permissions
+            .check(ListenTransport::Udp, "0.0.0.0", 5683)
+            .is_err());
+        assert!(permissions
+            .check(ListenTransport::Tcp, "0.0.0.0", 1884)
+            .is_err());
+        assert!(ListenPermissions::default()
+            .check(ListenTransport::Tcp, "0.0.0.0", 1883)
+            .is_err());
+    }
+}
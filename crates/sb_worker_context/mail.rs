@@ -0,0 +1,35 @@
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Mail sent by a worker with `EdgeRuntime.mail.send`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingMail {
+    // defaults to the sender of the service's account
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+}
+
+// Sends mail with the credentials of a service, which the isolate never sees.
+// Resolves to the message id of the mail.
+pub trait Mailer: Send + Sync {
+    fn send(&self, service: &str, mail: OutgoingMail) -> BoxFuture<'static, Result<String, Error>>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer>;
+
+// Mailer as seen by a worker, put in the op state
+pub struct WorkerMailer {
+    pub mailer: SharedMailer,
+    pub service: String,
+}
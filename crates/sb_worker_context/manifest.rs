@@ -0,0 +1,399 @@
+use crate::essentials::{TimerPolicy, WorkerIsolation};
+use anyhow::{anyhow, Error};
+use deno_core::serde_json;
+use deno_core::url::Url;
+use event_worker::events::UncaughtErrorPolicy;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+pub type SharedManifest = Arc<RwLock<Manifest>>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLimits {
+    pub memory_limit_mb: Option<u64>,
+    pub low_memory_multiplier: Option<u64>,
+    pub worker_timeout_ms: Option<u64>,
+    pub cpu_time_threshold_ms: Option<u64>,
+    pub cpu_burst_interval_ms: Option<u64>,
+    pub max_cpu_bursts: Option<u64>,
+    pub hang_threshold_ms: Option<u64>,
+    pub cpu_weight: Option<u64>,
+    pub isolation: Option<WorkerIsolation>,
+    pub max_web_workers: Option<usize>,
+    pub max_concurrent_requests: Option<usize>,
+    pub max_workers: Option<usize>,
+    pub telemetry_sample_rate: Option<f64>,
+    pub disallow_code_generation: Option<bool>,
+    pub timers: Option<TimerPolicy>,
+    pub on_uncaught_error: Option<UncaughtErrorPolicy>,
+    pub detect_leaks: Option<bool>,
+}
+
+fn default_verify_jwt() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceEntry {
+    // service directory, or a module file inside it
+    pub entrypoint: String,
+    pub import_map: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub limits: ServiceLimits,
+    // request path prefixes served by this service (defaults to `/<name>`)
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(default = "default_verify_jwt")]
+    pub verify_jwt: bool,
+}
+
+impl ServiceEntry {
+    pub fn new(entrypoint: &str) -> Self {
+        Self {
+            entrypoint: entrypoint.to_string(),
+            import_map: None,
+            env: HashMap::new(),
+            limits: ServiceLimits::default(),
+            routes: vec![],
+            verify_jwt: default_verify_jwt(),
+        }
+    }
+}
+
+// Options in the shape accepted by `EdgeRuntime.userWorkers.create`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedWorkerOptions {
+    pub service_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maybe_entrypoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import_map_path: Option<String>,
+    pub env_vars: Vec<(String, String)>,
+    #[serde(flatten)]
+    pub limits: ServiceLimitsOptions,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLimitsOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_memory_multiplier: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_time_threshold_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_burst_interval_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cpu_bursts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hang_threshold_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_weight: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<WorkerIsolation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_web_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telemetry_sample_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disallow_code_generation: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timers: Option<TimerPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_uncaught_error: Option<UncaughtErrorPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect_leaks: Option<bool>,
+}
+
+impl From<&ServiceLimits> for ServiceLimitsOptions {
+    fn from(limits: &ServiceLimits) -> Self {
+        Self {
+            memory_limit_mb: limits.memory_limit_mb,
+            low_memory_multiplier: limits.low_memory_multiplier,
+            worker_timeout_ms: limits.worker_timeout_ms,
+            cpu_time_threshold_ms: limits.cpu_time_threshold_ms,
+            cpu_burst_interval_ms: limits.cpu_burst_interval_ms,
+            max_cpu_bursts: limits.max_cpu_bursts,
+            hang_threshold_ms: limits.hang_threshold_ms,
+            cpu_weight: limits.cpu_weight,
+            isolation: limits.isolation,
+            max_web_workers: limits.max_web_workers,
+            max_concurrent_requests: limits.max_concurrent_requests,
+            max_workers: limits.max_workers,
+            telemetry_sample_rate: limits.telemetry_sample_rate,
+            disallow_code_generation: limits.disallow_code_generation,
+            timers: limits.timers,
+            on_uncaught_error: limits.on_uncaught_error,
+            detect_leaks: limits.detect_leaks,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedService {
+    pub name: String,
+    pub verify_jwt: bool,
+    pub worker_options: ResolvedWorkerOptions,
+}
+
+// Describes all services served by a node (functions.json)
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    #[serde(default)]
+    pub services: HashMap<String, ServiceEntry>,
+    // services whose workers are booted when the runtime starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preload: Vec<String>,
+}
+
+fn resolve_relative(base: &Path, value: &str) -> String {
+    if value.starts_with("data:") || Path::new(value).is_absolute() {
+        return value.to_string();
+    }
+    base.join(value).to_string_lossy().to_string()
+}
+
+// A route served by two services, which of them serves it would be arbitrary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConflict {
+    pub service: String,
+    pub route: String,
+    // the service that already serves the route
+    pub other: String,
+}
+
+fn matches_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .map(|rest| rest.starts_with('/'))
+            .unwrap_or(false)
+}
+
+impl Manifest {
+    pub fn parse(json: &str, base_dir: &Path) -> Result<Self, Error> {
+        let mut manifest: Manifest = serde_json::from_str(json)?;
+
+        // relative paths in the manifest are relative to the manifest file
+        for entry in manifest.services.values_mut() {
+            entry.entrypoint = resolve_relative(base_dir, &entry.entrypoint);
+            entry.import_map = entry
+                .import_map
+                .as_ref()
+                .map(|v| resolve_relative(base_dir, v));
+        }
+
+        if let Some(name) = manifest
+            .preload
+            .iter()
+            .find(|name| !manifest.services.contains_key(*name))
+        {
+            return Err(anyhow!("preloaded service {} is not in the manifest", name));
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read manifest {:?}: {}", path, err))?;
+        let base_dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let manifest = Self::parse(&json, &base_dir)?;
+        if let Some(conflict) = manifest.route_conflicts().first() {
+            return Err(anyhow!(
+                "route {:?} of service {} is already served by {}",
+                conflict.route,
+                conflict.service,
+                conflict.other
+            ));
+        }
+        Ok(manifest)
+    }
+
+    fn routes<'a>(name: &str, entry: &'a ServiceEntry) -> Vec<Cow<'a, str>> {
+        if entry.routes.is_empty() {
+            vec![format!("/{}", name).into()]
+        } else {
+            entry
+                .routes
+                .iter()
+                .map(|route| route.as_str().into())
+                .collect()
+        }
+    }
+
+    // Routes served by more than one service, in the order of the services' names
+    pub fn route_conflicts(&self) -> Vec<RouteConflict> {
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+
+        let mut conflicts = vec![];
+        let mut routes: HashMap<String, &String> = HashMap::new();
+        for name in names {
+            for route in Self::routes(name, &self.services[name]) {
+                let route = route.trim_end_matches('/').to_string();
+                if let Some(other) = routes.insert(route.clone(), name) {
+                    conflicts.push(RouteConflict {
+                        service: name.clone(),
+                        route,
+                        other: other.clone(),
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+
+    // Finds the service serving a request path, preferring the longest matching
+    // route. Manifests with conflicting routes are rejected when loaded, those
+    // built otherwise resolve conflicts to the first service by name.
+    pub fn resolve(&self, path: &str) -> Option<ResolvedService> {
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+
+        let mut best: Option<(usize, &String, &ServiceEntry)> = None;
+        for name in names {
+            let entry = &self.services[name];
+            for route in Self::routes(name, entry) {
+                let len = route.trim_end_matches('/').len();
+                if matches_prefix(path, &route) && best.map(|(l, _, _)| len > l).unwrap_or(true) {
+                    best = Some((len, name, entry));
+                }
+            }
+        }
+
+        best.map(|(_, name, entry)| self.to_resolved(name, entry))
+    }
+
+    pub fn get(&self, name: &str) -> Option<ResolvedService> {
+        self.services
+            .get_key_value(name)
+            .map(|(name, entry)| self.to_resolved(name, entry))
+    }
+
+    fn to_resolved(&self, name: &str, entry: &ServiceEntry) -> ResolvedService {
+        let entrypoint = PathBuf::from(&entry.entrypoint);
+        let (service_path, maybe_entrypoint) = if entrypoint.extension().is_some() {
+            let abs_entrypoint = std::env::current_dir()
+                .map(|cwd| cwd.join(&entrypoint))
+                .unwrap_or(entrypoint.clone());
+            (
+                entrypoint
+                    .parent()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                Url::from_file_path(abs_entrypoint).ok().map(String::from),
+            )
+        } else {
+            (entry.entrypoint.clone(), None)
+        };
+
+        let mut env_vars: Vec<(String, String)> = entry
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        env_vars.sort();
+
+        ResolvedService {
+            name: name.to_string(),
+            verify_jwt: entry.verify_jwt,
+            worker_options: ResolvedWorkerOptions {
+                service_path,
+                maybe_entrypoint,
+                import_map_path: entry.import_map.clone(),
+                env_vars,
+                limits: (&entry.limits).into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Manifest;
+    use std::path::Path;
+
+    #[test]
+    fn test_manifest_resolve() {
+        let manifest = Manifest::parse(
+            r#"{
+                "services": {
+                    "hello": { "entrypoint": "./hello", "env": { "FOO": "bar" } },
+                    "api": { "entrypoint": "/srv/api/main.ts", "routes": ["/v1", "/v1/admin"], "verifyJwt": false }
+                }
+            }"#,
+            Path::new("/etc/functions"),
+        )
+        .unwrap();
+
+        let hello = manifest.resolve("/hello/world").unwrap();
+        assert_eq!(hello.name, "hello");
+        assert!(hello.verify_jwt);
+        assert_eq!(hello.worker_options.service_path, "/etc/functions/./hello");
+        assert_eq!(
+            hello.worker_options.env_vars,
+            vec![("FOO".to_string(), "bar".to_string())]
+        );
+
+        let api = manifest.resolve("/v1/admin/users").unwrap();
+        assert_eq!(api.name, "api");
+        assert!(!api.verify_jwt);
+        assert_eq!(api.worker_options.service_path, "/srv/api");
+        assert_eq!(
+            api.worker_options.maybe_entrypoint.as_deref(),
+            Some("file:///srv/api/main.ts")
+        );
+
+        assert!(manifest.resolve("/hellooo").is_none());
+    }
+
+    #[test]
+    fn test_manifest_route_conflicts() {
+        let manifest = Manifest::parse(
+            r#"{
+                "services": {
+                    "b": { "entrypoint": "./b", "routes": ["/api/"] },
+                    "a": { "entrypoint": "./a", "routes": ["/api"] },
+                    "api": { "entrypoint": "./api" }
+                }
+            }"#,
+            Path::new("/"),
+        )
+        .unwrap();
+
+        let conflicts = manifest.route_conflicts();
+        let services: Vec<(&str, &str)> = conflicts
+            .iter()
+            .map(|c| (c.service.as_str(), c.other.as_str()))
+            .collect();
+        assert_eq!(services, vec![("api", "a"), ("b", "api")]);
+
+        // a conflicting route always resolves to the same service
+        for _ in 0..8 {
+            assert_eq!(manifest.resolve("/api/users").unwrap().name, "a");
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use deno_core::JsBuffer;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TensorType {
+    Float32,
+    Int32,
+    Int64,
+    Uint8,
+}
+
+impl TensorType {
+    pub fn size(&self) -> usize {
+        match self {
+            TensorType::Float32 | TensorType::Int32 => 4,
+            TensorType::Int64 => 8,
+            TensorType::Uint8 => 1,
+        }
+    }
+}
+
+// Input of `EdgeRuntime.onnx.run`, holding the bytes of the typed array it was
+// passed as (not a copy)
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TensorInput {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub tensor_type: TensorType,
+    pub shape: Vec<usize>,
+    pub data: JsBuffer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorOutput {
+    pub name: String,
+    pub tensor_type: TensorType,
+    pub shape: Vec<usize>,
+    // native endian elements
+    pub data: Vec<u8>,
+}
+
+// Runs the ONNX models configured for a service. Implemented by the runtime,
+// which loads each model once and shares it between isolates.
+pub trait ModelRunner: Send + Sync {
+    fn models(&self, service: &str) -> Vec<String>;
+
+    fn run(
+        &self,
+        service: &str,
+        model: &str,
+        inputs: Vec<TensorInput>,
+    ) -> BoxFuture<'static, Result<Vec<TensorOutput>, Error>>;
+}
+
+pub type SharedModelRunner = Arc<dyn ModelRunner>;
+
+// Models as seen by a worker, put in the op state
+pub struct WorkerModels {
+    pub runner: SharedModelRunner,
+    pub service: String,
+}
@@ -0,0 +1,88 @@
+use anyhow::Error;
+use deno_core::serde_json::Value;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    // waiting for its next attempt
+    Pending,
+    Delivered,
+    // out of attempts, kept until it's redelivered
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(DeliveryStatus::Pending),
+            "delivered" => Some(DeliveryStatus::Delivered),
+            "dead" => Some(DeliveryStatus::Dead),
+            _ => None,
+        }
+    }
+}
+
+// A webhook a service sent with `EdgeRuntime.webhooks.send`. The runtime POSTs
+// it to `url` until the receiver accepts it, even if the worker is gone by then.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: String,
+    // scope of the sending service (see `service_scope`)
+    pub service: String,
+    pub url: String,
+    pub event_type: Option<String>,
+    pub payload: Value,
+    // key store key the payload is signed with
+    pub signing_key: Option<String>,
+    pub status: DeliveryStatus,
+    // attempts made so far
+    pub attempt: u32,
+    // unix timestamp (ms) of the next attempt, or of the delivery once delivered
+    pub next_attempt_ms: u64,
+    pub last_error: Option<String>,
+}
+
+// Persists deliveries, so they survive worker recycling and runtime restarts
+pub trait WebhookStore: Send + Sync {
+    fn insert(&self, delivery: &WebhookDelivery) -> Result<(), Error>;
+
+    fn get(&self, service: &str, id: &str) -> Result<Option<WebhookDelivery>, Error>;
+
+    fn dead_letters(&self, service: &str) -> Result<Vec<WebhookDelivery>, Error>;
+
+    // Queues a dead-lettered delivery again; false if the service has no such
+    // dead-lettered delivery
+    fn redeliver(&self, service: &str, id: &str, now_ms: u64) -> Result<bool, Error>;
+
+    // Returns the pending deliveries due at `now_ms`, pushing them back by
+    // `lease_ms` so they're attempted again if the runtime goes away meanwhile
+    fn claim_due(&self, now_ms: u64, lease_ms: u64) -> Result<Vec<WebhookDelivery>, Error>;
+
+    fn delivered(&self, id: &str, now_ms: u64) -> Result<(), Error>;
+
+    fn retry(&self, id: &str, next_attempt_ms: u64, error: &str) -> Result<(), Error>;
+
+    fn dead_letter(&self, id: &str, error: &str) -> Result<(), Error>;
+
+    // Forgets deliveries delivered before `before_ms`
+    fn prune(&self, before_ms: u64) -> Result<(), Error>;
+}
+
+pub type SharedWebhookStore = Arc<dyn WebhookStore>;
+
+// Outbound webhooks as seen by a worker, put in the op state
+pub struct WorkerWebhooks {
+    pub store: SharedWebhookStore,
+    pub service: String,
+}
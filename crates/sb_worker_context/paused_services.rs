@@ -0,0 +1,138 @@
+use hyper::header::HeaderValue;
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+// Services paused through the admin API (eg: for abuse or an unpaid bill). No
+// worker is booted for them and their requests are answered with the pause
+// response until they are resumed.
+
+static PAUSED_SERVICES: OnceLock<RwLock<BTreeMap<String, ServicePause>>> = OnceLock::new();
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServicePause {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+    // reported in the events and by `GET /_admin/services/paused`, not to clients
+    pub reason: Option<String>,
+    pub paused_at_ms: u64,
+}
+
+impl ServicePause {
+    // Pauses are validated by the admin API, an invalid status or content type
+    // falls back to a 503 without one
+    pub fn response(&self) -> Response<Body> {
+        let mut res = Response::new(Body::from(self.body.clone()));
+        *res.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        if let Ok(content_type) = HeaderValue::from_str(&self.content_type) {
+            res.headers_mut()
+                .insert(hyper::header::CONTENT_TYPE, content_type);
+        }
+        res
+    }
+}
+
+// The pool declined to boot a worker for a paused service
+#[derive(Debug)]
+pub struct ServicePaused {
+    pub service_path: String,
+    pub pause: ServicePause,
+}
+
+impl fmt::Display for ServicePaused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "service {} is paused", self.service_path)
+    }
+}
+
+impl std::error::Error for ServicePaused {}
+
+fn paused() -> &'static RwLock<BTreeMap<String, ServicePause>> {
+    PAUSED_SERVICES.get_or_init(Default::default)
+}
+
+// `./functions/hello/` and `./functions/hello` are the same service
+fn pause_key(service_path: &str) -> String {
+    match service_path.trim_end_matches('/') {
+        "" => service_path.to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+// Returns the pause it replaced, if the service was already paused
+pub fn pause_service(service_path: &str, pause: ServicePause) -> Option<ServicePause> {
+    paused()
+        .write()
+        .unwrap()
+        .insert(pause_key(service_path), pause)
+}
+
+pub fn resume_service(service_path: &str) -> Option<ServicePause> {
+    paused().write().unwrap().remove(&pause_key(service_path))
+}
+
+pub fn service_pause(service_path: &str) -> Option<ServicePause> {
+    paused()
+        .read()
+        .unwrap()
+        .get(&pause_key(service_path))
+        .cloned()
+}
+
+pub fn paused_services() -> BTreeMap<String, ServicePause> {
+    paused().read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pause_service, paused_services, resume_service, service_pause, ServicePause};
+
+    fn pause(status: u16) -> ServicePause {
+        ServicePause {
+            status,
+            content_type: "text/plain".to_string(),
+            body: "suspended".to_string(),
+            reason: Some("billing".to_string()),
+            paused_at_ms: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_service() {
+        assert!(pause_service("./functions/paused/", pause(402)).is_none());
+        assert_eq!(service_pause("./functions/paused"), Some(pause(402)));
+        assert!(service_pause("./functions/paused-not").is_none());
+        assert!(paused_services().contains_key("./functions/paused"));
+
+        // pausing again replaces the response
+        assert_eq!(
+            pause_service("./functions/paused", pause(503)),
+            Some(pause(402))
+        );
+        let res = service_pause("./functions/paused").unwrap().response();
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/plain");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"suspended");
+
+        assert_eq!(resume_service("./functions/paused/"), Some(pause(503)));
+        assert!(service_pause("./functions/paused").is_none());
+        assert!(resume_service("./functions/paused").is_none());
+    }
+
+    #[test]
+    fn test_invalid_pause_response() {
+        let invalid = ServicePause {
+            content_type: "text/plain\n".to_string(),
+            ..pause(1000)
+        };
+        let res = invalid.response();
+        assert_eq!(res.status(), 503);
+        assert!(res.headers().get("content-type").is_none());
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Error;
+use deno_core::futures::future::BoxFuture;
+use deno_core::serde_json::Value;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// Commands of `EdgeRuntime.redis`. Keys and channels are relative to the
+// service's prefix.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum RedisCommand {
+    Get {
+        key: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Set {
+        key: String,
+        value: String,
+        ex_secs: Option<u64>,
+    },
+    Expire {
+        key: String,
+        secs: u64,
+    },
+    Incr {
+        key: String,
+        by: i64,
+    },
+    Publish {
+        channel: String,
+        message: String,
+    },
+}
+
+// Redis connections shared by all isolates. Implemented by the runtime, which
+// also holds the credentials of the services.
+pub trait RedisPool: Send + Sync {
+    fn query(
+        &self,
+        service: &str,
+        command: RedisCommand,
+    ) -> BoxFuture<'static, Result<Value, Error>>;
+
+    // messages published on the channel, until the receiver is dropped
+    fn subscribe(
+        &self,
+        service: &str,
+        channel: String,
+    ) -> BoxFuture<'static, Result<mpsc::UnboundedReceiver<String>, Error>>;
+}
+
+pub type SharedRedisPool = Arc<dyn RedisPool>;
+
+// Redis pool as seen by a worker, put in the op state
+pub struct WorkerRedis {
+    pub pool: SharedRedisPool,
+    pub service: String,
+}
@@ -0,0 +1,170 @@
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use hyper::header::Entry;
+use hyper::HeaderMap;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+// Metadata main worker middleware attaches to a request it dispatches to a user
+// worker (eg: verified JWT claims). It travels next to the request instead of in
+// its headers, so clients can't forge it and user code reads it through
+// `EdgeRuntime.context(req).metadata`.
+
+pub const MAX_REQUEST_METADATA_BYTES: usize = 16 * 1024;
+
+// Serialized JSON value, carried in the request's extensions until the request
+// is handed to the worker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestMetadata(String);
+
+impl RequestMetadata {
+    pub fn new(json: String) -> Result<Self, Error> {
+        if json.len() > MAX_REQUEST_METADATA_BYTES {
+            bail!(
+                "request metadata is {} bytes, at most {} are allowed",
+                json.len(),
+                MAX_REQUEST_METADATA_BYTES
+            );
+        }
+        if let Err(err) = serde_json::from_str::<serde_json::Value>(&json) {
+            bail!("request metadata is not valid JSON: {}", err);
+        }
+        Ok(Self(json))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// Metadata of the connections a worker accepted, until its HTTP server takes it
+#[derive(Debug, Default)]
+pub struct PendingRequestMetadata(pub HashMap<u32, RequestMetadata>);
+
+// File-based route a request matched, as `{ "index": 0, "params": {} }` JSON.
+// Read by the service's generated entrypoint, not exposed through headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestRoute(pub String);
+
+// Connection a request is sent to a worker thread over, along with the request's
+// headers so JS can read them one at a time instead of building them all
+#[derive(Debug)]
+pub struct WorkerConnection {
+    pub stream: UnixStream,
+    pub headers: Option<HeaderMap>,
+    pub route: Option<RequestRoute>,
+}
+
+// Headers the worker's HTTP server reads itself (the URL, the framing of the
+// body, upgrades and the encoding of the response), so they stay on the request
+// sent over the connection
+pub const WIRE_HEADERS: [&str; 6] = [
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "upgrade",
+    "accept-encoding",
+];
+
+// Takes the headers of a request but its wire headers, which are moved instead
+// of copied
+pub fn split_wire_headers(headers: &mut HeaderMap) -> HeaderMap {
+    let mut rest = std::mem::take(headers);
+    for name in WIRE_HEADERS {
+        if let Entry::Occupied(entry) = rest.entry(name) {
+            let (name, values) = entry.remove_entry_mult();
+            for value in values {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+    rest
+}
+
+// Headers of the requests of the connections a worker accepted, by connection,
+// until the request is done
+#[derive(Debug, Default)]
+pub struct RequestHeaders(pub HashMap<u32, HeaderMap>);
+
+// Routes of the requests of the connections a worker accepted, until its HTTP
+// server takes them
+#[derive(Debug, Default)]
+pub struct PendingRequestRoutes(pub HashMap<u32, RequestRoute>);
+
+// Every connection to a worker starts with a frame holding the metadata of its
+// request: its length (0 when there's none) as a big endian u32, then the JSON
+pub async fn write_metadata_frame<W>(
+    writer: &mut W,
+    metadata: Option<&RequestMetadata>,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let json = metadata.map(|m| m.as_str()).unwrap_or_default();
+    writer.write_u32(json.len() as u32).await?;
+    writer.write_all(json.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn read_metadata_frame<R>(reader: &mut R) -> Result<Option<RequestMetadata>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = reader.read_u32().await? as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > MAX_REQUEST_METADATA_BYTES {
+        bail!("request metadata frame is too large ({} bytes)", len);
+    }
+    let mut json = vec![0; len];
+    reader.read_exact(&mut json).await?;
+    Ok(Some(RequestMetadata::new(String::from_utf8(json)?)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        read_metadata_frame, split_wire_headers, write_metadata_frame, RequestMetadata,
+        MAX_REQUEST_METADATA_BYTES,
+    };
+    use hyper::HeaderMap;
+
+    #[tokio::test]
+    async fn test_metadata_frame() {
+        let metadata = RequestMetadata::new(r#"{"sub":"user-1"}"#.to_string()).unwrap();
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        write_metadata_frame(&mut a, Some(&metadata)).await.unwrap();
+        write_metadata_frame(&mut a, None).await.unwrap();
+        assert_eq!(read_metadata_frame(&mut b).await.unwrap(), Some(metadata));
+        assert_eq!(read_metadata_frame(&mut b).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_metadata_is_limited() {
+        assert!(RequestMetadata::new("{not json".to_string()).is_err());
+        let large = format!("\"{}\"", "a".repeat(MAX_REQUEST_METADATA_BYTES));
+        assert!(RequestMetadata::new(large).is_err());
+    }
+
+    #[test]
+    fn test_split_wire_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "localhost".parse().unwrap());
+        headers.insert("content-length", "5".parse().unwrap());
+        headers.append("x-custom", "a".parse().unwrap());
+        headers.append("x-custom", "b".parse().unwrap());
+        headers.insert("accept", "text/plain".parse().unwrap());
+
+        let rest = split_wire_headers(&mut headers);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["host"], "localhost");
+        assert_eq!(headers["content-length"], "5");
+        assert_eq!(rest.len(), 3);
+        assert_eq!(rest.get_all("x-custom").iter().count(), 2);
+        assert!(!rest.contains_key("host"));
+    }
+}
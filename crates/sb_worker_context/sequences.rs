@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Counters of `EdgeRuntime.ids.next`, kept by the runtime so every isolate of a
+// service on the node draws from the same sequence. They live in memory and
+// start over when the node restarts.
+#[derive(Default)]
+pub struct Sequences {
+    values: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl Sequences {
+    // Adds `by` to the counter and returns its new value, the first one being `by`
+    pub fn next(&self, service: &str, name: &str, by: u64) -> u64 {
+        let mut values = self.values.lock().unwrap();
+        let value = values
+            .entry((service.to_string(), name.to_string()))
+            .or_default();
+        *value = value.saturating_add(by);
+        *value
+    }
+
+    pub fn current(&self, service: &str, name: &str) -> u64 {
+        let values = self.values.lock().unwrap();
+        values
+            .get(&(service.to_string(), name.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+pub type SharedSequences = Arc<Sequences>;
+
+// Sequences as seen by a worker, put in the op state
+pub struct WorkerSequences {
+    pub sequences: SharedSequences,
+    pub service: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::Sequences;
+
+    #[test]
+    fn test_sequences() {
+        let sequences = Sequences::default();
+        assert_eq!(sequences.current("a", "orders"), 0);
+        assert_eq!(sequences.next("a", "orders", 1), 1);
+        assert_eq!(sequences.next("a", "orders", 10), 11);
+        // per service
+        assert_eq!(sequences.next("b", "orders", 1), 1);
+        assert_eq!(sequences.current("a", "orders"), 11);
+    }
+}
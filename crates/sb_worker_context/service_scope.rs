@@ -0,0 +1,86 @@
+use anyhow::Error;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Names every service in the configs giving resources to services
+pub const ANY_SERVICE: &str = "*";
+
+// Identifies a service to the resources given to it by service (eg: signing
+// keys): the canonical path of its directory, so services with the same
+// directory name never share them
+pub fn service_scope(service_path: &Path) -> String {
+    std::fs::canonicalize(service_path)
+        .or_else(|_| std::env::current_dir().map(|cwd| cwd.join(service_path)))
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| service_path.to_string_lossy().to_string())
+}
+
+// The scope of a service named by a config file, its path being relative to the
+// file's directory
+pub fn config_service_scope(config_path: &Path, service: &str) -> String {
+    if service == ANY_SERVICE {
+        return service.to_string();
+    }
+    let base_dir = config_path.parent().unwrap_or(Path::new("."));
+    service_scope(&base_dir.join(service))
+}
+
+// Reads a config giving resources to services by name, parsed with `parse`. Its
+// services are named by the path of their directory, relative to the config
+// file's, and are keyed by their scope once read.
+pub fn load_service_config<T, S>(
+    config_path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, Error>,
+    services: impl FnOnce(&mut T) -> &mut HashMap<String, S>,
+) -> Result<T, Error> {
+    let mut config = parse(&std::fs::read_to_string(config_path)?)?;
+    let by_name = services(&mut config);
+    *by_name = std::mem::take(by_name)
+        .into_iter()
+        .map(|(service, value)| (config_service_scope(config_path, &service), value))
+        .collect();
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_service_scope, load_service_config, service_scope, ANY_SERVICE};
+    use deno_core::serde_json;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_service_scope() {
+        let dir = std::env::temp_dir().join(format!("scopes-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("a/checkout")).unwrap();
+        std::fs::create_dir_all(dir.join("b/checkout")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+
+        // services with the same name are told apart
+        let a = service_scope(&dir.join("a/./checkout"));
+        let b = service_scope(&dir.join("b/checkout"));
+        assert_eq!(a, dir.join("a/checkout").to_string_lossy());
+        assert_ne!(a, b);
+
+        // configs name services relative to their own directory
+        let config_path = dir.join("a/mail.json");
+        assert_eq!(config_service_scope(&config_path, "checkout"), a);
+        assert_eq!(config_service_scope(&config_path, "../b/checkout/"), b);
+        assert_eq!(config_service_scope(&config_path, ANY_SERVICE), ANY_SERVICE);
+
+        // services that don't exist (yet) are scoped by their absolute path
+        assert!(Path::new(&service_scope(Path::new("./missing"))).is_absolute());
+
+        std::fs::write(&config_path, r#"{ "checkout": 1, "*": 2 }"#).unwrap();
+        let config = load_service_config(
+            &config_path,
+            |json| Ok(serde_json::from_str::<HashMap<String, u32>>(json)?),
+            |config| config,
+        )
+        .unwrap();
+        assert_eq!(config[&a], 1);
+        assert_eq!(config[ANY_SERVICE], 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Resources consumed by a service within a reporting window
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceUsage {
+    pub requests: u64,
+    pub cpu_time_ms: u64,
+    // memory limit of each worker multiplied by its lifetime
+    pub memory_gb_seconds: f64,
+    pub egress_bytes: u64,
+    pub module_cache_misses: u64,
+}
+
+impl ServiceUsage {
+    pub fn merge(&mut self, other: &ServiceUsage) {
+        self.requests += other.requests;
+        self.cpu_time_ms += other.cpu_time_ms;
+        self.memory_gb_seconds += other.memory_gb_seconds;
+        self.egress_bytes += other.egress_bytes;
+        self.module_cache_misses += other.module_cache_misses;
+    }
+}
+
+// Aggregates usage per service path until it's taken by the usage reporter
+#[derive(Debug, Clone, Default)]
+pub struct UsageCollector(Arc<Mutex<HashMap<String, ServiceUsage>>>);
+
+impl UsageCollector {
+    pub fn record<F>(&self, service_path: &str, f: F)
+    where
+        F: FnOnce(&mut ServiceUsage),
+    {
+        let mut usage = self.0.lock().unwrap();
+        match usage.get_mut(service_path) {
+            Some(entry) => f(entry),
+            None => f(usage.entry(service_path.to_string()).or_default()),
+        }
+    }
+
+    pub fn take(&self) -> HashMap<String, ServiceUsage> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    // puts back usage that could not be reported
+    pub fn restore(&self, pending: HashMap<String, ServiceUsage>) {
+        let mut usage = self.0.lock().unwrap();
+        for (service_path, entry) in pending {
+            usage.entry(service_path).or_default().merge(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UsageCollector;
+
+    #[test]
+    fn test_usage_collector_take_and_restore() {
+        let collector = UsageCollector::default();
+        collector.record("./hello", |u| u.requests += 1);
+        collector.record("./hello", |u| u.egress_bytes += 10);
+
+        let taken = collector.take();
+        assert_eq!(taken["./hello"].requests, 1);
+        assert_eq!(taken["./hello"].egress_bytes, 10);
+        assert!(collector.take().is_empty());
+
+        collector.record("./hello", |u| u.requests += 1);
+        collector.restore(taken);
+        assert_eq!(collector.take()["./hello"].requests, 2);
+    }
+}
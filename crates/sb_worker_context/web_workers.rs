@@ -0,0 +1,289 @@
+use anyhow::{bail, Error};
+use deno_core::SharedArrayBufferStore;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+// Web workers (`new Worker()`) spawned by a user worker. Messages are structured
+// clones serialized with `Deno.core.serialize`.
+
+#[derive(Debug)]
+pub enum WebWorkerEvent {
+    Message(Vec<u8>),
+    // uncaught error, the worker stopped running
+    Error(String),
+    // the worker called `close()`
+    Closed,
+}
+
+// CPU and memory of a user worker and its web workers, counted against the
+// limits of the user worker. The CPU alarms of each thread and the heap limit
+// being reached are sent to the supervisor of the user worker.
+#[derive(Debug, Clone)]
+pub struct WorkerBudget {
+    memory_limit: usize,
+    // heap used by the isolates of the user worker and its web workers
+    memory_used: Arc<AtomicUsize>,
+    memory_exceeded: Arc<AtomicBool>,
+    pub cpu_alarms_tx: mpsc::UnboundedSender<()>,
+    pub memory_limit_tx: mpsc::UnboundedSender<()>,
+}
+
+// Received by the supervisor of the user worker
+#[derive(Debug)]
+pub struct WorkerBudgetAlarms {
+    pub cpu_alarms_rx: mpsc::UnboundedReceiver<()>,
+    pub memory_limit_rx: mpsc::UnboundedReceiver<()>,
+}
+
+impl WorkerBudget {
+    pub fn new(memory_limit: usize) -> (Self, WorkerBudgetAlarms) {
+        let (cpu_alarms_tx, cpu_alarms_rx) = mpsc::unbounded_channel();
+        let (memory_limit_tx, memory_limit_rx) = mpsc::unbounded_channel();
+        let budget = Self {
+            memory_limit,
+            memory_used: Arc::default(),
+            memory_exceeded: Arc::default(),
+            cpu_alarms_tx,
+            memory_limit_tx,
+        };
+        let alarms = WorkerBudgetAlarms {
+            cpu_alarms_rx,
+            memory_limit_rx,
+        };
+        (budget, alarms)
+    }
+
+    // The heap of one isolate, given back once dropped
+    pub fn memory_share(&self) -> MemoryShare {
+        MemoryShare {
+            budget: self.clone(),
+            used: 0,
+        }
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory_used.load(Ordering::Relaxed)
+    }
+}
+
+pub struct MemoryShare {
+    budget: WorkerBudget,
+    used: usize,
+}
+
+impl MemoryShare {
+    // Records the heap the isolate uses now, the supervisor is alerted (once)
+    // when all the isolates together use more than the limit
+    pub fn record(&mut self, used: usize) {
+        let budget = &self.budget;
+        let total = budget.memory_used.fetch_add(used, Ordering::Relaxed) + used;
+        budget.memory_used.fetch_sub(self.used, Ordering::Relaxed);
+        let total = total.saturating_sub(self.used);
+        self.used = used;
+        if total > budget.memory_limit && !budget.memory_exceeded.swap(true, Ordering::Relaxed) {
+            let _ = budget.memory_limit_tx.send(());
+        }
+    }
+}
+
+impl Drop for MemoryShare {
+    fn drop(&mut self) {
+        self.budget
+            .memory_used
+            .fetch_sub(self.used, Ordering::Relaxed);
+    }
+}
+
+// Ends of the channels held by the web worker
+#[derive(Clone)]
+pub struct WebWorkerPort {
+    pub name: String,
+    pub to_parent: mpsc::UnboundedSender<WebWorkerEvent>,
+    pub from_parent: Arc<Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>,
+    // SharedArrayBuffers posted between the worker and its parent
+    pub shared_array_buffers: SharedArrayBufferStore,
+    pub budget: WorkerBudget,
+}
+
+impl fmt::Debug for WebWorkerPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebWorkerPort")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+// Ends held by the user worker that spawned it
+#[derive(Debug)]
+pub struct WebWorkerHandle {
+    pub to_worker: mpsc::UnboundedSender<Vec<u8>>,
+    pub from_worker: mpsc::UnboundedReceiver<WebWorkerEvent>,
+    // the worker is terminated once this is sent or dropped
+    pub terminate_tx: oneshot::Sender<()>,
+}
+
+// Counts towards the web workers of a user worker until dropped
+#[derive(Debug)]
+pub struct WebWorkerSlot(Arc<AtomicUsize>);
+
+impl Drop for WebWorkerSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct WebWorkerSpawnOpts {
+    pub specifier: String,
+    pub port: WebWorkerPort,
+    pub terminate_rx: oneshot::Receiver<()>,
+    // held for as long as the worker runs
+    pub slot: WebWorkerSlot,
+}
+
+pub type SpawnWebWorkerFn = dyn Fn(WebWorkerSpawnOpts) -> Result<(), Error> + Send + Sync;
+
+// Put in the op state of user workers allowed to spawn web workers
+#[derive(Clone)]
+pub struct WebWorkers {
+    spawn_fn: Arc<SpawnWebWorkerFn>,
+    max_workers: usize,
+    running: Arc<AtomicUsize>,
+    shared_array_buffers: SharedArrayBufferStore,
+    budget: WorkerBudget,
+}
+
+impl WebWorkers {
+    pub fn new(
+        max_workers: usize,
+        shared_array_buffers: SharedArrayBufferStore,
+        budget: WorkerBudget,
+        spawn_fn: Arc<SpawnWebWorkerFn>,
+    ) -> Self {
+        Self {
+            spawn_fn,
+            max_workers,
+            running: Arc::default(),
+            shared_array_buffers,
+            budget,
+        }
+    }
+
+    pub fn budget(&self) -> &WorkerBudget {
+        &self.budget
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn spawn(&self, specifier: String, name: String) -> Result<WebWorkerHandle, Error> {
+        if self.running.fetch_add(1, Ordering::Relaxed) >= self.max_workers {
+            self.running.fetch_sub(1, Ordering::Relaxed);
+            bail!(
+                "a worker can run at most {} web workers at a time",
+                self.max_workers
+            );
+        }
+        let slot = WebWorkerSlot(self.running.clone());
+
+        let (to_worker, from_parent) = mpsc::unbounded_channel();
+        let (to_parent, from_worker) = mpsc::unbounded_channel();
+        let (terminate_tx, terminate_rx) = oneshot::channel();
+        (self.spawn_fn)(WebWorkerSpawnOpts {
+            specifier,
+            port: WebWorkerPort {
+                name,
+                to_parent,
+                from_parent: Arc::new(Mutex::new(from_parent)),
+                shared_array_buffers: self.shared_array_buffers.clone(),
+                budget: self.budget.clone(),
+            },
+            terminate_rx,
+            slot,
+        })?;
+
+        Ok(WebWorkerHandle {
+            to_worker,
+            from_worker,
+            terminate_tx,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WebWorkers, WorkerBudget};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_web_workers_are_bounded() {
+        let spawned = Arc::new(Mutex::new(vec![]));
+        let spawned_clone = spawned.clone();
+        let workers = WebWorkers::new(
+            2,
+            Default::default(),
+            WorkerBudget::new(1024).0,
+            Arc::new(move |opts| {
+                spawned_clone.lock().unwrap().push(opts);
+                Ok(())
+            }),
+        );
+
+        let _a = workers
+            .spawn("file:///a.ts".to_string(), "a".to_string())
+            .unwrap();
+        let _b = workers
+            .spawn("file:///b.ts".to_string(), "b".to_string())
+            .unwrap();
+        assert!(workers
+            .spawn("file:///c.ts".to_string(), "c".to_string())
+            .is_err());
+        assert_eq!(workers.running(), 2);
+
+        // a worker that stopped running frees its slot
+        spawned.lock().unwrap().remove(0);
+        assert_eq!(workers.running(), 1);
+        assert!(workers
+            .spawn("file:///c.ts".to_string(), "c".to_string())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_failed_spawn_frees_slot() {
+        let workers = WebWorkers::new(
+            1,
+            Default::default(),
+            WorkerBudget::new(1024).0,
+            Arc::new(|_| anyhow::bail!("no threads left")),
+        );
+        assert!(workers
+            .spawn("file:///a.ts".to_string(), "a".to_string())
+            .is_err());
+        assert_eq!(workers.running(), 0);
+    }
+
+    #[test]
+    fn test_worker_budget_counts_every_isolate() {
+        let (budget, mut alarms) = WorkerBudget::new(100);
+        let mut parent = budget.memory_share();
+        let mut web_worker = budget.memory_share();
+
+        parent.record(60);
+        web_worker.record(30);
+        parent.record(50);
+        assert_eq!(budget.memory_used(), 80);
+        assert!(alarms.memory_limit_rx.try_recv().is_err());
+
+        // over the limit together, though each is under it
+        web_worker.record(70);
+        assert_eq!(budget.memory_used(), 120);
+        assert!(alarms.memory_limit_rx.try_recv().is_ok());
+        web_worker.record(80);
+        assert!(alarms.memory_limit_rx.try_recv().is_err());
+
+        drop(web_worker);
+        assert_eq!(budget.memory_used(), 50);
+    }
+}
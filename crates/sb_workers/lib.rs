@@ -1,3 +1,6 @@
+mod options;
+mod web_workers;
+
 use anyhow::Error;
 use deno_core::error::{custom_error, type_error, AnyError};
 use deno_core::futures::stream::Peekable;
@@ -5,16 +8,23 @@ use deno_core::futures::{Stream, StreamExt};
 use deno_core::op2;
 use deno_core::{
     AsyncRefCell, AsyncResult, BufView, ByteString, CancelFuture, CancelHandle, CancelTryFuture,
-    JsBuffer, OpState, RcRef, Resource, ResourceId, WriteOutcome,
+    JsBuffer, OpState, RcRef, Resource, ResourceId, ToJsBuffer, WriteOutcome,
 };
+use event_worker::events::UncaughtErrorPolicy;
 use hyper::body::HttpBody;
 use hyper::header::{HeaderName, HeaderValue};
-use hyper::{Body, Request, Response};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use options::validate_create_options;
 use sb_eszip::module_loader::EszipPayloadKind;
 use sb_worker_context::essentials::{
-    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-    WorkerRuntimeOpts,
+    ConcurrencyOverflowPolicy, CreateUserWorkerResult, DeterministicMode, TimerPolicy,
+    UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerCreationRejected,
+    WorkerIsolation, WorkerReusePolicy, WorkerRuntimeOpts,
 };
+use sb_worker_context::listen::ListenPermission;
+use sb_worker_context::manifest::{Manifest, ResolvedService, SharedManifest};
+use sb_worker_context::paused_services::{service_pause, ServicePause, ServicePaused};
+use sb_worker_context::request_metadata::RequestMetadata;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cell::RefCell;
@@ -25,6 +35,10 @@ use std::rc::Rc;
 use std::task::{Context, Poll};
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
+use web_workers::{
+    op_web_worker_create, op_web_worker_host_close, op_web_worker_host_post_message,
+    op_web_worker_host_recv, op_web_worker_post_message, op_web_worker_recv,
+};
 
 deno_core::extension!(
     sb_user_workers,
@@ -32,22 +46,69 @@ deno_core::extension!(
         op_user_worker_create,
         op_user_worker_fetch_build,
         op_user_worker_fetch_send,
+        op_manifest_resolve,
+        op_manifest_get,
+        op_service_pause,
+        op_web_worker_create,
+        op_web_worker_post_message,
+        op_web_worker_recv,
+        op_web_worker_host_post_message,
+        op_web_worker_host_close,
+        op_web_worker_host_recv,
     ],
     esm_entry_point = "ext:sb_user_workers/user_workers.js",
-    esm = ["user_workers.js",]
+    esm = ["user_workers.js", "web_workers.js",]
 );
 
+// What a worker is allowed to do, overriding `netAccessDisabled`,
+// `allowRemoteModules` and `customModuleRoot` when set. With
+// `freezeRemoteOrigins`, dynamic imports can't reach origins the static module
+// graph doesn't import from.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWorkerPermissions {
+    net: Option<bool>,
+    remote_modules: Option<bool>,
+    freeze_remote_origins: Option<bool>,
+    module_root: Option<String>,
+    listen: Option<Vec<ListenPermission>>,
+}
+
+// Remote modules to fetch again instead of reading them from the module cache:
+// `true` for all of them, or their urls (and url prefixes). Always boots a new
+// worker, so the fresh modules are the ones served.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ReloadModules {
+    All(bool),
+    Some(Vec<String>),
+}
+
+impl Default for ReloadModules {
+    fn default() -> Self {
+        ReloadModules::All(false)
+    }
+}
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserWorkerCreateOptions {
     service_path: String,
     no_module_cache: bool,
+    reload_modules: ReloadModules,
     import_map_path: Option<String>,
     env_vars: Vec<(String, String)>,
     force_create: bool,
+    reuse: WorkerReusePolicy,
     allow_remote_modules: bool,
     net_access_disabled: bool,
     custom_module_root: Option<String>,
+    permissions: Option<UserWorkerPermissions>,
+    disallow_code_generation: bool,
+    deterministic: Option<DeterministicMode>,
+    timers: Option<TimerPolicy>,
+    on_uncaught_error: UncaughtErrorPolicy,
+    detect_leaks: bool,
     maybe_eszip: Option<JsBuffer>,
     maybe_entrypoint: Option<String>,
     maybe_module_code: Option<String>,
@@ -58,6 +119,16 @@ pub struct UserWorkerCreateOptions {
     cpu_time_threshold_ms: u64,
     max_cpu_bursts: u64,
     cpu_burst_interval_ms: u64,
+    hang_threshold_ms: u64,
+    cpu_weight: Option<u64>,
+    isolation: WorkerIsolation,
+    max_web_workers: usize,
+
+    body_tee_max_bytes: Option<u64>,
+    telemetry_sample_rate: Option<f64>,
+    max_concurrent_requests: Option<usize>,
+    concurrency_overflow: ConcurrencyOverflowPolicy,
+    max_workers: Option<usize>,
 }
 
 #[op2(async)]
@@ -66,6 +137,9 @@ pub async fn op_user_worker_create(
     state: Rc<RefCell<OpState>>,
     #[serde] opts: UserWorkerCreateOptions,
 ) -> Result<String, AnyError> {
+    validate_create_options(&opts)
+        .map_err(|err| custom_error("InvalidWorkerOptions", err.to_string()))?;
+
     let result_rx = {
         let op_state = state.borrow();
         let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
@@ -74,12 +148,20 @@ pub async fn op_user_worker_create(
         let UserWorkerCreateOptions {
             service_path,
             no_module_cache,
+            reload_modules,
             import_map_path,
             env_vars,
             force_create,
+            reuse,
             net_access_disabled,
             allow_remote_modules,
             custom_module_root,
+            permissions,
+            disallow_code_generation,
+            deterministic,
+            timers,
+            on_uncaught_error,
+            detect_leaks,
             maybe_eszip,
             maybe_entrypoint,
             maybe_module_code,
@@ -90,8 +172,34 @@ pub async fn op_user_worker_create(
             cpu_time_threshold_ms,
             max_cpu_bursts,
             cpu_burst_interval_ms,
+            hang_threshold_ms,
+            cpu_weight,
+            isolation,
+            max_web_workers,
+
+            body_tee_max_bytes,
+            telemetry_sample_rate,
+            max_concurrent_requests,
+            concurrency_overflow,
+            max_workers,
         } = opts;
 
+        let permissions = permissions.unwrap_or_default();
+        let net_access_disabled = permissions
+            .net
+            .map(|net| !net)
+            .unwrap_or(net_access_disabled);
+        let allow_remote_modules = permissions.remote_modules.unwrap_or(allow_remote_modules);
+        let freeze_remote_origins = permissions.freeze_remote_origins.unwrap_or(false);
+        let custom_module_root = permissions.module_root.or(custom_module_root);
+        let listen = permissions.listen.unwrap_or_default();
+        let (no_module_cache, reload_modules, force_create) = match reload_modules {
+            ReloadModules::All(false) => (no_module_cache, vec![], force_create),
+            ReloadModules::All(true) => (true, vec![], true),
+            ReloadModules::Some(urls) if urls.is_empty() => (no_module_cache, urls, force_create),
+            ReloadModules::Some(urls) => (no_module_cache, urls, true),
+        };
+
         let mut env_vars_map = HashMap::new();
         for (key, value) in env_vars {
             env_vars_map.insert(key, value);
@@ -113,14 +221,37 @@ pub async fn op_user_worker_create(
                 cpu_time_threshold_ms,
                 max_cpu_bursts,
                 cpu_burst_interval_ms,
+                hang_threshold_ms,
+                cpu_weight,
+                isolation,
+                max_web_workers,
+                web_worker: None,
                 force_create,
+                reuse,
                 net_access_disabled,
                 allow_remote_modules,
+                freeze_remote_origins,
+                reload_modules,
+                disallow_code_generation,
+                deterministic,
+                timer_policy: timers,
+                uncaught_error_policy: on_uncaught_error,
+                detect_leaks,
                 custom_module_root,
+                listen,
                 key: None,
                 pool_msg_tx: None,
                 events_msg_tx: None,
+                usage: None,
                 service_path: None,
+                body_tee_max_bytes,
+                telemetry_sample_rate,
+                max_concurrent_requests,
+                concurrency_overflow,
+                max_workers,
+                test_clock: false,
+                coverage: false,
+                module_mocks: HashMap::new(),
             }),
         };
 
@@ -139,11 +270,56 @@ pub async fn op_user_worker_create(
     // channel returns a Result<T, E>, we need to unwrap it first;
     let result = result.unwrap();
     match result {
+        // eg: the node is low on memory
+        Err(e) if e.is::<WorkerCreationRejected>() => {
+            Err(custom_error("WorkerCreationRejected", e.to_string()))
+        }
+        Err(e) if e.is::<ServicePaused>() => Err(custom_error("ServicePaused", e.to_string())),
         Err(e) => Err(custom_error("InvalidWorkerCreation", e.to_string())),
         Ok(res) => Ok(res.key.to_string()),
     }
 }
 
+fn with_manifest<F>(state: &OpState, f: F) -> Result<Option<ResolvedService>, AnyError>
+where
+    F: FnOnce(&Manifest) -> Option<ResolvedService>,
+{
+    let Some(manifest) = state.try_borrow::<SharedManifest>() else {
+        return Ok(None);
+    };
+    let manifest = manifest
+        .read()
+        .map_err(|_| type_error("deployment manifest is not readable"))?;
+
+    Ok(f(&manifest))
+}
+
+#[op2]
+#[serde]
+pub fn op_manifest_resolve(
+    state: &mut OpState,
+    #[string] path: String,
+) -> Result<Option<ResolvedService>, AnyError> {
+    with_manifest(state, |manifest| manifest.resolve(&path))
+}
+
+#[op2]
+#[serde]
+pub fn op_manifest_get(
+    state: &mut OpState,
+    #[string] name: String,
+) -> Result<Option<ResolvedService>, AnyError> {
+    with_manifest(state, |manifest| manifest.get(&name))
+}
+
+// The response requests to a paused service are answered with, `null` if it
+// isn't paused
+#[op2]
+#[serde]
+pub fn op_service_pause(#[string] service_path: String) -> Option<ServicePause> {
+    service_pause(&service_path)
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserWorkerRequest {
@@ -151,6 +327,8 @@ pub struct UserWorkerRequest {
     url: String,
     headers: Vec<(String, String)>,
     has_body: bool,
+    // JSON attached by the main worker, see `RequestMetadata`
+    metadata: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -166,10 +344,26 @@ pub struct UserWorkerResponse {
     status: u16,
     status_text: String,
     headers: Vec<(ByteString, ByteString)>,
-    body_rid: ResourceId,
+    // streamed bodies are read from this resource
+    body_rid: Option<ResourceId>,
+    // small bodies are read at once instead
+    body: Option<ToJsBuffer>,
     size: Option<u64>,
 }
 
+// responses up to this size are read at once and handed over along with their
+// head, instead of being streamed through a resource
+pub const SMALL_RESPONSE_MAX_BYTES: u64 = 8 * 1024;
+
+// responses whose body isn't passed on to JS
+fn skips_body(method: &Method, status: StatusCode) -> bool {
+    matches!(*method, Method::HEAD | Method::CONNECT)
+        || matches!(
+            status.as_u16(),
+            101 | 204 | 205 | 301 | 302 | 303 | 304 | 307 | 308
+        )
+}
+
 struct UserWorkerRequestResource(Request<Body>);
 
 impl Resource for UserWorkerRequestResource {
@@ -320,6 +514,11 @@ pub fn op_user_worker_fetch_build(
         }
     }
 
+    if let Some(metadata) = req.metadata {
+        let metadata = RequestMetadata::new(metadata).map_err(|err| type_error(err.to_string()))?;
+        request.extensions_mut().insert(metadata);
+    }
+
     let request_rid = state.resource_table.add(UserWorkerRequestResource(request));
 
     Ok(UserWorkerBuiltRequest {
@@ -351,6 +550,7 @@ pub async fn op_user_worker_fetch_send(
     let request = Rc::try_unwrap(request)
         .ok()
         .expect("multiple op_user_worker_fetch_send ongoing");
+    let method = request.0.method().clone();
     let (result_tx, result_rx) = oneshot::channel::<Result<Response<Body>, Error>>();
     let key_parsed = Uuid::try_parse(key.as_str())?;
     tx.send(UserWorkerMsgs::SendRequest(
@@ -383,6 +583,30 @@ pub async fn op_user_worker_fetch_send(
         .to_string();
 
     let size = HttpBody::size_hint(result.body()).exact();
+    if skips_body(&method, result.status()) {
+        return Ok(UserWorkerResponse {
+            status,
+            status_text,
+            headers,
+            body_rid: None,
+            body: None,
+            size,
+        });
+    }
+    if size.map_or(false, |size| size <= SMALL_RESPONSE_MAX_BYTES) {
+        let body = hyper::body::to_bytes(result.into_body())
+            .await
+            .map_err(|err| type_error(err.to_string()))?;
+        return Ok(UserWorkerResponse {
+            status,
+            status_text,
+            headers,
+            body_rid: None,
+            body: Some(body.to_vec().into()),
+            size,
+        });
+    }
+
     let stream: BytesStream = Box::pin(
         result
             .into_body()
@@ -400,7 +624,8 @@ pub async fn op_user_worker_fetch_send(
         status,
         status_text,
         headers,
-        body_rid,
+        body_rid: Some(body_rid),
+        body: None,
         size,
     };
     Ok(response)
@@ -447,3 +672,19 @@ impl Stream for MpscByteStream {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::skips_body;
+    use hyper::{Method, StatusCode};
+
+    #[test]
+    fn test_skips_body() {
+        assert!(skips_body(&Method::HEAD, StatusCode::OK));
+        assert!(skips_body(&Method::GET, StatusCode::NO_CONTENT));
+        assert!(skips_body(&Method::GET, StatusCode::NOT_MODIFIED));
+        assert!(skips_body(&Method::POST, StatusCode::FOUND));
+        assert!(!skips_body(&Method::GET, StatusCode::OK));
+        assert!(!skips_body(&Method::GET, StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::UserWorkerCreateOptions;
+use sb_worker_context::essentials::{MAX_CONCURRENT_REQUESTS, MIN_HANG_THRESHOLD_MS};
+use std::fmt;
+
+// cgroup v2 `cpu.weight` range
+const MIN_CPU_WEIGHT: u64 = 1;
+const MAX_CPU_WEIGHT: u64 = 10000;
+
+// Options of `EdgeRuntime.userWorkers.create` the main worker got wrong. Thrown
+// to JS as `InvalidWorkerOptions` errors, before anything is booted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WorkerOptionsError {
+    MissingServicePath,
+    NotPositive(&'static str),
+    OutOfRange {
+        option: &'static str,
+        min: u64,
+        max: u64,
+    },
+    TooSmall {
+        option: &'static str,
+        min: u64,
+    },
+    EmptyPath(&'static str),
+    NotARate(&'static str),
+    InvalidEnvVar(String),
+}
+
+impl fmt::Display for WorkerOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerOptionsError::MissingServicePath => write!(f, "service path must be defined"),
+            WorkerOptionsError::NotPositive(option) => {
+                write!(f, "{} must be greater than 0", option)
+            }
+            WorkerOptionsError::OutOfRange { option, min, max } => {
+                write!(f, "{} must be between {} and {}", option, min, max)
+            }
+            WorkerOptionsError::TooSmall { option, min } => {
+                write!(f, "{} must be at least {}", option, min)
+            }
+            WorkerOptionsError::EmptyPath(option) => write!(f, "{} must not be empty", option),
+            WorkerOptionsError::NotARate(option) => {
+                write!(f, "{} must be between 0 and 1", option)
+            }
+            WorkerOptionsError::InvalidEnvVar(name) => {
+                write!(f, "invalid environment variable name: {:?}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorkerOptionsError {}
+
+fn positive(option: &'static str, value: u64) -> Result<(), WorkerOptionsError> {
+    if value == 0 {
+        return Err(WorkerOptionsError::NotPositive(option));
+    }
+    Ok(())
+}
+
+fn non_empty(option: &'static str, value: Option<&str>) -> Result<(), WorkerOptionsError> {
+    if value == Some("") {
+        return Err(WorkerOptionsError::EmptyPath(option));
+    }
+    Ok(())
+}
+
+fn valid_env_var(name: &str, value: &str) -> bool {
+    !name.is_empty() && !name.contains(['=', '\0']) && !value.contains('\0')
+}
+
+pub fn validate_create_options(opts: &UserWorkerCreateOptions) -> Result<(), WorkerOptionsError> {
+    if opts.maybe_eszip.is_none() && opts.service_path.is_empty() {
+        return Err(WorkerOptionsError::MissingServicePath);
+    }
+
+    positive("memoryLimitMb", opts.memory_limit_mb)?;
+    positive("lowMemoryMultiplier", opts.low_memory_multiplier)?;
+    positive("workerTimeoutMs", opts.worker_timeout_ms)?;
+    positive("cpuTimeThresholdMs", opts.cpu_time_threshold_ms)?;
+    positive("cpuBurstIntervalMs", opts.cpu_burst_interval_ms)?;
+    if opts.hang_threshold_ms < MIN_HANG_THRESHOLD_MS {
+        return Err(WorkerOptionsError::TooSmall {
+            option: "hangThresholdMs",
+            min: MIN_HANG_THRESHOLD_MS,
+        });
+    }
+    if let Some(max) = opts.max_concurrent_requests {
+        if !(1..=MAX_CONCURRENT_REQUESTS).contains(&max) {
+            return Err(WorkerOptionsError::OutOfRange {
+                option: "maxConcurrentRequests",
+                min: 1,
+                max: MAX_CONCURRENT_REQUESTS as u64,
+            });
+        }
+    }
+    if let Some(max) = opts.max_workers {
+        positive("maxWorkers", max as u64)?;
+    }
+    if let Some(weight) = opts.cpu_weight {
+        if !(MIN_CPU_WEIGHT..=MAX_CPU_WEIGHT).contains(&weight) {
+            return Err(WorkerOptionsError::OutOfRange {
+                option: "cpuWeight",
+                min: MIN_CPU_WEIGHT,
+                max: MAX_CPU_WEIGHT,
+            });
+        }
+    }
+    if let Some(deterministic) = &opts.deterministic {
+        positive(
+            "deterministic.timerResolutionMs",
+            deterministic.timer_resolution_ms,
+        )?;
+    }
+    if let Some(timers) = &opts.timers {
+        if let Some(max) = timers.max_timers {
+            positive("timers.maxTimers", max as u64)?;
+        }
+        if let Some(ms) = timers.idle_coalesce_ms {
+            positive("timers.idleCoalesceMs", ms)?;
+        }
+    }
+    if let Some(rate) = opts.telemetry_sample_rate {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(WorkerOptionsError::NotARate("telemetrySampleRate"));
+        }
+    }
+
+    non_empty("importMapPath", opts.import_map_path.as_deref())?;
+    if let Some(permissions) = &opts.permissions {
+        non_empty("permissions.moduleRoot", permissions.module_root.as_deref())?;
+    }
+
+    if let Some((name, _)) = opts
+        .env_vars
+        .iter()
+        .find(|(name, value)| !valid_env_var(name, value))
+    {
+        return Err(WorkerOptionsError::InvalidEnvVar(name.clone()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate_create_options, WorkerOptionsError};
+    use crate::{UserWorkerCreateOptions, UserWorkerPermissions};
+    use sb_worker_context::essentials::{DeterministicMode, TimerPolicy};
+
+    fn options() -> UserWorkerCreateOptions {
+        UserWorkerCreateOptions {
+            service_path: "./examples/hello".to_string(),
+            memory_limit_mb: 512,
+            low_memory_multiplier: 5,
+            worker_timeout_ms: 5 * 60 * 1000,
+            cpu_time_threshold_ms: 50,
+            cpu_burst_interval_ms: 100,
+            max_cpu_bursts: 10,
+            hang_threshold_ms: 10 * 1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_create_options() {
+        assert_eq!(validate_create_options(&options()), Ok(()));
+
+        let missing_path = UserWorkerCreateOptions {
+            service_path: String::new(),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&missing_path),
+            Err(WorkerOptionsError::MissingServicePath)
+        );
+
+        let no_memory = UserWorkerCreateOptions {
+            memory_limit_mb: 0,
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&no_memory),
+            Err(WorkerOptionsError::NotPositive("memoryLimitMb"))
+        );
+
+        let no_workers = UserWorkerCreateOptions {
+            max_workers: Some(0),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&no_workers),
+            Err(WorkerOptionsError::NotPositive("maxWorkers"))
+        );
+
+        for threshold in [0, 10] {
+            let twitchy = UserWorkerCreateOptions {
+                hang_threshold_ms: threshold,
+                ..options()
+            };
+            assert_eq!(
+                validate_create_options(&twitchy),
+                Err(WorkerOptionsError::TooSmall {
+                    option: "hangThresholdMs",
+                    min: 100,
+                })
+            );
+        }
+
+        let heavy = UserWorkerCreateOptions {
+            cpu_weight: Some(20000),
+            ..options()
+        };
+        assert!(matches!(
+            validate_create_options(&heavy),
+            Err(WorkerOptionsError::OutOfRange {
+                option: "cpuWeight",
+                ..
+            })
+        ));
+
+        for max in [0, usize::MAX] {
+            let unbounded = UserWorkerCreateOptions {
+                max_concurrent_requests: Some(max),
+                ..options()
+            };
+            assert!(matches!(
+                validate_create_options(&unbounded),
+                Err(WorkerOptionsError::OutOfRange {
+                    option: "maxConcurrentRequests",
+                    ..
+                })
+            ));
+        }
+
+        let oversampled = UserWorkerCreateOptions {
+            telemetry_sample_rate: Some(1.5),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&oversampled),
+            Err(WorkerOptionsError::NotARate("telemetrySampleRate"))
+        );
+
+        let frozen_clock = UserWorkerCreateOptions {
+            deterministic: Some(DeterministicMode {
+                seed: 1,
+                time_origin_ms: None,
+                timer_resolution_ms: 0,
+            }),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&frozen_clock),
+            Err(WorkerOptionsError::NotPositive(
+                "deterministic.timerResolutionMs"
+            ))
+        );
+
+        let no_timers = UserWorkerCreateOptions {
+            timers: Some(TimerPolicy {
+                max_timers: Some(0),
+                ..Default::default()
+            }),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&no_timers),
+            Err(WorkerOptionsError::NotPositive("timers.maxTimers"))
+        );
+
+        let bad_env = UserWorkerCreateOptions {
+            env_vars: vec![("A=B".to_string(), "c".to_string())],
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&bad_env),
+            Err(WorkerOptionsError::InvalidEnvVar("A=B".to_string()))
+        );
+
+        let empty_root = UserWorkerCreateOptions {
+            permissions: Some(UserWorkerPermissions {
+                module_root: Some(String::new()),
+                ..Default::default()
+            }),
+            ..options()
+        };
+        assert_eq!(
+            validate_create_options(&empty_root),
+            Err(WorkerOptionsError::EmptyPath("permissions.moduleRoot"))
+        );
+    }
+}
@@ -9,21 +9,64 @@ const ops = core.ops;
 // interface WorkerOptions {
 //     servicePath: string;
 //     memoryLimitMb?: number;
+//     cpuWeight?: number;
+//     isolation?: 'thread' | 'process';
+//     maxWebWorkers?: number;
 //     workerTimeoutMs?: number;
 //     noModuleCache?: boolean;
+//     reloadModules?: boolean | Array<string>;
 //     importMapPath?: string;
 //     envVars?: Array<any>
+//     permissions?: {
+//         net?: boolean;
+//         remoteModules?: boolean;
+//         freezeRemoteOrigins?: boolean;
+//         moduleRoot?: string;
+//         listen?: Array<{ transport?: 'tcp' | 'udp'; hostname?: string; port: number }>;
+//     };
+//     disallowCodeGeneration?: boolean;
+//     deterministic?: { seed: number; timeOriginMs?: number; timerResolutionMs?: number };
+//     timers?: { minDelayMs?: number; maxTimers?: number; idleCoalesceMs?: number };
+//     onUncaughtError?: 'log-only' | 'fail-request' | 'kill-worker';
+//     detectLeaks?: boolean;
+//     reuse?: 'active' | 'replace' | 'isolated';
+//     bodyTeeMaxBytes?: number;
+//     telemetrySampleRate?: number;
+//     maxConcurrentRequests?: number;
+//     concurrencyOverflow?: 'queue' | 'spawn';
+//     maxWorkers?: number;
 // }
 
 const chunkExpression = /(?:^|\W)chunked(?:$|\W)/i;
 
-function nullBodyStatus(status) {
-	return status === 101 || status === 204 || status === 205 || status === 304;
+// tags errors with a runtime error code (see `EdgeRuntime.errors.codes`)
+function withErrorCode(err, code) {
+	if (err instanceof Error && err.code === undefined) {
+		err.code = code;
+	}
+	return err;
 }
 
-function redirectStatus(status) {
-	return status === 301 || status === 302 || status === 303 ||
-		status === 307 || status === 308;
+// Hooks the main worker registered with `EdgeRuntime.userWorkers.onResponse`,
+// run in order on every user worker response before it's returned by `fetch`
+const responseHooks = [];
+
+// A hook gets the response and `{ key, request, buffered }`. It can read the
+// status and headers of any response. Buffered responses (no body, or a body of
+// known length) can also be replaced, by returning a new `Response` from the hook.
+async function runResponseHooks(response, info) {
+	for (const hook of [...responseHooks]) {
+		try {
+			const result = await hook(response, info);
+			if (info.buffered && result instanceof Response) {
+				response = result;
+			}
+		} catch (err) {
+			// a failing hook doesn't fail the request
+			console.error('response hook failed:', err);
+		}
+	}
+	return response;
 }
 
 class UserWorker {
@@ -33,7 +76,8 @@ class UserWorker {
 
 	async fetch(req, opts = {}) {
 		const { method, url, headers, body, bodyUsed } = req;
-		const { signal } = opts;
+		// `metadata` is passed to the worker next to the request, see `EdgeRuntime.context`
+		const { signal, metadata } = opts;
 
 		signal?.throwIfAborted();
 
@@ -47,6 +91,7 @@ class UserWorker {
 			url,
 			headers: headersArray,
 			hasBody: hasReqBody,
+			metadata: metadata == null ? null : JSON.stringify(metadata),
 		};
 
 		const { requestRid, requestBodyRid } = await ops.op_user_worker_fetch_build(
@@ -61,7 +106,9 @@ class UserWorker {
 		}
 
 		const resPromise = core.opAsync('op_user_worker_fetch_send', this.key, requestRid);
-		const [, res] = await Promise.all([reqBodyPromise, resPromise]);
+		const [, res] = await Promise.all([reqBodyPromise, resPromise]).catch((err) => {
+			throw withErrorCode(err, 'WORKER_UNAVAILABLE');
+		});
 
 		const response = {
 			headers: res.headers,
@@ -70,28 +117,46 @@ class UserWorker {
 			body: null,
 		};
 
-		// TODO: add a test
-		if (nullBodyStatus(res.status) || redirectStatus(res.status)) {
-			core.close(res.bodyRid);
-		} else {
-			if (req.method === 'HEAD' || req.method === 'CONNECT') {
-				response.body = null;
-				core.close(res.bodyRid);
-			} else {
-				const bodyStream = readableStreamForRid(res.bodyRid);
-
-				signal?.addEventListener('abort', () => {
-					core.tryClose(res.bodyRid);
-				});
-				response.body = bodyStream;
-			}
+		// the runtime leaves out the bodies of HEAD requests, null body statuses
+		// and redirects, and reads small ones at once (`res.body`)
+		if (res.body !== null) {
+			response.body = res.body;
+		} else if (res.bodyRid !== null) {
+			const bodyStream = readableStreamForRid(res.bodyRid);
+
+			signal?.addEventListener('abort', () => {
+				core.tryClose(res.bodyRid);
+			});
+			response.body = bodyStream;
 		}
 
-		return new Response(response.body ? response.body : null, {
+		const userWorkerRes = new Response(response.body ? response.body : null, {
 			headers: response.headers,
 			status: response.status,
 			statusText: response.statusText,
 		});
+		if (responseHooks.length === 0) {
+			return userWorkerRes;
+		}
+		return await runResponseHooks(userWorkerRes, {
+			key: this.key,
+			request: req,
+			buffered: response.body === null || res.size !== null,
+		});
+	}
+
+	// Registers a response hook, returns a function unregistering it
+	static onResponse(hook) {
+		if (typeof hook !== 'function') {
+			throw new TypeError('response hook must be a function');
+		}
+		responseHooks.push(hook);
+		return () => {
+			const index = responseHooks.indexOf(hook);
+			if (index !== -1) {
+				responseHooks.splice(index, 1);
+			}
+		};
 	}
 
 	static async create(opts) {
@@ -102,30 +167,87 @@ class UserWorker {
 			cpuTimeThresholdMs: 50,
 			cpuBurstIntervalMs: 100,
 			maxCpuBursts: 10,
+			hangThresholdMs: 10 * 1000,
+			cpuWeight: null,
+			isolation: 'thread',
+			maxWebWorkers: 4,
 			noModuleCache: false,
+			reloadModules: false,
 			importMapPath: null,
 			envVars: [],
 			forceCreate: false,
+			reuse: 'active',
 			netAccessDisabled: false,
 			allowRemoteModules: true,
 			customModuleRoot: '',
+			permissions: null,
+			disallowCodeGeneration: false,
+			deterministic: null,
+			timers: null,
+			onUncaughtError: 'kill-worker',
+			detectLeaks: false,
 			maybeEszip: null,
 			maybeEntrypoint: null,
 			maybeModuleCode: null,
+			bodyTeeMaxBytes: null,
+			telemetrySampleRate: null,
+			maxConcurrentRequests: null,
+			concurrencyOverflow: 'queue',
+			maxWorkers: null,
 			...opts,
 		};
 
-		const { servicePath, maybeEszip } = readyOptions;
+		// options are validated by the op, invalid ones throw `InvalidWorkerOptions`
+		let pause = null;
+		const key = await core.opAsync('op_user_worker_create', readyOptions).catch((err) => {
+			if (err?.name === 'InvalidWorkerOptions') {
+				throw err;
+			}
+			if (err?.name === 'ServicePaused') {
+				pause = ops.op_service_pause(readyOptions.servicePath);
+				if (pause !== null) {
+					return null;
+				}
+				throw withErrorCode(err, 'SERVICE_PAUSED');
+			}
+			throw withErrorCode(err, err?.name === 'WorkerCreationRejected' ? 'OVERLOADED' : 'BOOT_FAILURE');
+		});
 
-		if (!maybeEszip && (!servicePath || servicePath === '')) {
-			throw new TypeError('service path must be defined');
+		if (pause !== null) {
+			return new PausedUserWorker(pause);
 		}
+		return new UserWorker(key);
+	}
+}
 
-		const key = await core.opAsync('op_user_worker_create', readyOptions);
+// Stands in for the worker of a service paused through the admin API, answering
+// every request with the pause response (no worker is booted)
+class PausedUserWorker {
+	constructor(pause) {
+		this.key = null;
+		this.paused = true;
+		this.pause = pause;
+	}
 
-		return new UserWorker(key);
+	async fetch(_req, _opts = {}) {
+		const { status, contentType, body } = this.pause;
+		return new Response(body, {
+			status,
+			headers: { 'content-type': contentType },
+		});
 	}
 }
 
+// Services described by the deployment manifest (functions.json).
+// Both lookups return `null` when no manifest is loaded or nothing matches.
+const SUPABASE_SERVICES = {
+	resolve(pathname) {
+		return ops.op_manifest_resolve(pathname);
+	},
+	get(name) {
+		return ops.op_manifest_get(name);
+	},
+};
+
 const SUPABASE_USER_WORKERS = UserWorker;
-export { SUPABASE_USER_WORKERS };
+export { SUPABASE_SERVICES, SUPABASE_USER_WORKERS };
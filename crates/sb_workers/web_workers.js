@@ -0,0 +1,132 @@
+const primordials = globalThis.__bootstrap.primordials;
+const {
+	ObjectDefineProperties,
+	String,
+	TypeError,
+} = primordials;
+import { defineEventHandler, ErrorEvent, EventTarget, MessageEvent } from 'ext:deno_web/02_event.js';
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// Web workers (`new Worker()`) of user workers. Each runs in a thread of its own,
+// with the limits and permissions of the worker that spawned it. Messages are
+// copied (transfer lists aren't supported), SharedArrayBuffers are shared.
+
+function serializeMessage(message) {
+	return core.serialize(message, { forStorage: false });
+}
+
+function deserializeMessage(data) {
+	return core.deserialize(data, { forStorage: false });
+}
+
+class Worker extends EventTarget {
+	#rid;
+	#name;
+	#terminated = false;
+
+	constructor(specifier, options = {}) {
+		super();
+		const { type = 'classic', name = '' } = options ?? {};
+		if (type !== 'module') {
+			throw new TypeError('only module workers are supported, use { type: "module" }');
+		}
+		const url = new URL(String(specifier), ops.op_main_module());
+		this.#name = String(name);
+		this.#rid = ops.op_web_worker_create(url.href, this.#name);
+		this.#poll();
+	}
+
+	get name() {
+		return this.#name;
+	}
+
+	postMessage(message) {
+		if (this.#terminated) {
+			return;
+		}
+		ops.op_web_worker_post_message(this.#rid, serializeMessage(message));
+	}
+
+	terminate() {
+		if (this.#terminated) {
+			return;
+		}
+		this.#terminated = true;
+		core.tryClose(this.#rid);
+	}
+
+	async #poll() {
+		while (!this.#terminated) {
+			let event;
+			try {
+				event = await core.opAsync('op_web_worker_recv', this.#rid);
+			} catch {
+				// terminated while waiting
+				break;
+			}
+			if (event === null || event.kind === 'closed') {
+				break;
+			}
+			if (event.kind === 'error') {
+				this.dispatchEvent(
+					new ErrorEvent('error', { message: event.message, cancelable: true }),
+				);
+				break;
+			}
+
+			let data;
+			try {
+				data = deserializeMessage(event.data);
+			} catch {
+				this.dispatchEvent(new MessageEvent('messageerror'));
+				continue;
+			}
+			this.dispatchEvent(new MessageEvent('message', { data }));
+		}
+		this.terminate();
+	}
+}
+
+defineEventHandler(Worker.prototype, 'message');
+defineEventHandler(Worker.prototype, 'messageerror');
+defineEventHandler(Worker.prototype, 'error');
+
+// Scope of a web worker: messages from the worker that spawned it are
+// dispatched on `globalThis`
+function installWebWorkerScope(name) {
+	ObjectDefineProperties(globalThis, {
+		name: { value: name, writable: true, enumerable: true, configurable: true },
+		postMessage: {
+			value: (message) => ops.op_web_worker_host_post_message(serializeMessage(message)),
+			writable: true,
+			configurable: true,
+		},
+		close: {
+			value: () => ops.op_web_worker_host_close(),
+			writable: true,
+			configurable: true,
+		},
+	});
+	defineEventHandler(globalThis, 'message');
+	defineEventHandler(globalThis, 'messageerror');
+
+	(async () => {
+		while (true) {
+			const data = await core.opAsync('op_web_worker_host_recv');
+			if (data === null) {
+				return;
+			}
+			let message;
+			try {
+				message = deserializeMessage(data);
+			} catch {
+				globalThis.dispatchEvent(new MessageEvent('messageerror'));
+				continue;
+			}
+			globalThis.dispatchEvent(new MessageEvent('message', { data: message }));
+		}
+	})();
+}
+
+export { installWebWorkerScope, Worker };
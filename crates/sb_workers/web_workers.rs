@@ -0,0 +1,150 @@
+use deno_core::error::{type_error, AnyError};
+use deno_core::op2;
+use deno_core::{
+    AsyncRefCell, CancelFuture, CancelHandle, OpState, RcRef, Resource, ResourceId, ToJsBuffer,
+};
+use sb_worker_context::web_workers::{WebWorkerEvent, WebWorkerHandle, WebWorkerPort, WebWorkers};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::{mpsc, oneshot};
+
+struct WebWorkerResource {
+    to_worker: mpsc::UnboundedSender<Vec<u8>>,
+    from_worker: AsyncRefCell<mpsc::UnboundedReceiver<WebWorkerEvent>>,
+    terminate_tx: RefCell<Option<oneshot::Sender<()>>>,
+    cancel: CancelHandle,
+}
+
+impl From<WebWorkerHandle> for WebWorkerResource {
+    fn from(handle: WebWorkerHandle) -> Self {
+        Self {
+            to_worker: handle.to_worker,
+            from_worker: AsyncRefCell::new(handle.from_worker),
+            terminate_tx: RefCell::new(Some(handle.terminate_tx)),
+            cancel: CancelHandle::default(),
+        }
+    }
+}
+
+impl Resource for WebWorkerResource {
+    fn name(&self) -> Cow<str> {
+        "webWorker".into()
+    }
+
+    // terminates the worker
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+        if let Some(tx) = self.terminate_tx.borrow_mut().take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebWorkerMessage {
+    kind: &'static str,
+    data: Option<ToJsBuffer>,
+    message: Option<String>,
+}
+
+#[op2]
+#[smi]
+pub fn op_web_worker_create(
+    state: &mut OpState,
+    #[string] specifier: String,
+    #[string] name: String,
+) -> Result<ResourceId, AnyError> {
+    let Some(workers) = state.try_borrow::<WebWorkers>() else {
+        return Err(type_error("Worker is not available in this context"));
+    };
+    let handle = workers
+        .spawn(specifier, name)
+        .map_err(|err| type_error(err.to_string()))?;
+    Ok(state.resource_table.add(WebWorkerResource::from(handle)))
+}
+
+#[op2]
+pub fn op_web_worker_post_message(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    #[buffer] data: &[u8],
+) -> Result<(), AnyError> {
+    let resource = state.resource_table.get::<WebWorkerResource>(rid)?;
+    // messages to a worker that stopped are dropped, like on the web
+    let _ = resource.to_worker.send(data.to_vec());
+    Ok(())
+}
+
+// Next event of the worker, or null once it stopped
+#[op2(async)]
+#[serde]
+pub async fn op_web_worker_recv(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<WebWorkerMessage>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<WebWorkerResource>(rid)?;
+    let mut from_worker = RcRef::map(&resource, |r| &r.from_worker).borrow_mut().await;
+    let cancel = RcRef::map(&resource, |r| &r.cancel);
+    let Some(event) = from_worker.recv().or_cancel(cancel).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(match event {
+        WebWorkerEvent::Message(data) => WebWorkerMessage {
+            kind: "message",
+            data: Some(data.into()),
+            message: None,
+        },
+        WebWorkerEvent::Error(message) => WebWorkerMessage {
+            kind: "error",
+            data: None,
+            message: Some(message),
+        },
+        WebWorkerEvent::Closed => WebWorkerMessage {
+            kind: "closed",
+            data: None,
+            message: None,
+        },
+    }))
+}
+
+fn host_port(state: &OpState) -> Result<WebWorkerPort, AnyError> {
+    state
+        .try_borrow::<WebWorkerPort>()
+        .cloned()
+        .ok_or_else(|| type_error("not running in a web worker"))
+}
+
+#[op2]
+pub fn op_web_worker_host_post_message(
+    state: &mut OpState,
+    #[buffer] data: &[u8],
+) -> Result<(), AnyError> {
+    let port = host_port(state)?;
+    let _ = port.to_parent.send(WebWorkerEvent::Message(data.to_vec()));
+    Ok(())
+}
+
+#[op2]
+pub fn op_web_worker_host_close(state: &mut OpState) -> Result<(), AnyError> {
+    let port = host_port(state)?;
+    let _ = port.to_parent.send(WebWorkerEvent::Closed);
+    Ok(())
+}
+
+// Next message of the parent, or null once it's gone
+#[op2(async)]
+#[serde]
+pub async fn op_web_worker_host_recv(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+    let port = host_port(&state.borrow())?;
+    let mut from_parent = port.from_parent.lock().await;
+    Ok(from_parent.recv().await.map(|data| data.into()))
+}
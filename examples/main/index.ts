@@ -45,6 +45,11 @@ serve(async (req: Request) => {
 		const envVars = Object.keys(envVarsObj).map((k) => [k, envVarsObj[k]]);
 		const forceCreate = false;
 		const netAccessDisabled = false;
+		// only set by trusted proxies (see `--reload-header-trusted-proxy`), eg: for previews
+		const reloadHeader = req.headers.get('x-edge-runtime-reload-modules');
+		const reloadModules = reloadHeader === null
+			? false
+			: reloadHeader === '1' || reloadHeader.split(',').map((url) => url.trim());
 
 		// load source from an eszip
 		// const maybeEszip = await Deno.readFile('./sample.eszip');
@@ -61,9 +66,13 @@ serve(async (req: Request) => {
 			envVars,
 			forceCreate,
 			netAccessDisabled,
+			reloadModules,
 			// maybeEszip,
 			// maybeEntrypoint,
 			// maybeModuleCode,
+			// when started with `--manifest`, services defined in the manifest
+			// take precedence over the defaults above
+			...EdgeRuntime.services.resolve(pathname)?.workerOptions,
 		});
 	};
 
@@ -82,11 +91,9 @@ serve(async (req: Request) => {
 			return await worker.fetch(req, { signal });
 		} catch (e) {
 			console.error(e);
-			const error = { msg: e.toString() };
-			return new Response(
-				JSON.stringify(error),
-				{ status: 500, headers: { 'Content-Type': 'application/json' } },
-			);
+			// errors raised by the runtime carry a code (eg: BOOT_FAILURE, WORKER_UNAVAILABLE)
+			// and are returned as `application/problem+json`
+			return EdgeRuntime.errors.problemResponse(e.code ?? 'INTERNAL_ERROR', e.toString());
 		}
 	};
 